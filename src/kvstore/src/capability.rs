@@ -0,0 +1,277 @@
+use crate::wait_response;
+use clap::Parser;
+use coset::{CborSerializable, CoseSign1, CoseSign1Builder};
+use many_client::client::blocking::ManyClient;
+use many_error::ManyError;
+use many_identity::{Address, Identity, Verifier};
+use many_identity_dsa::{CoseKeyIdentity, CoseKeyVerifier};
+use many_modules::kvstore;
+use many_protocol::RequestMessageBuilder;
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The request attribute carrying a capability token.
+const CAPABILITY: Attribute = Attribute::id(2000);
+
+#[derive(Debug, Parser)]
+pub struct CapabilityOpt {
+    #[clap(subcommand)]
+    subcommand: CapabilitySubCommand,
+}
+
+#[derive(Debug, Parser)]
+enum CapabilitySubCommand {
+    /// Sign a capability delegating puts under a key prefix, and print its
+    /// token. The values put with it are owned by the identity of `--pem`.
+    Create(CreateOpt),
+
+    /// Print the constraints of a token, and check its signature.
+    Inspect(TokenOpt),
+
+    /// Revoke a token created by the caller.
+    Revoke(TokenOpt),
+}
+
+#[derive(Debug, Parser)]
+struct CreateOpt {
+    /// The prefix of the keys which can be put.
+    prefix: String,
+
+    /// If the prefix is a hexadecimal string, pass this flag.
+    #[clap(long)]
+    hex_prefix: bool,
+
+    /// The maximum size of each value, in bytes.
+    #[clap(long)]
+    max_value_size: u64,
+
+    /// The number of seconds from now after which the token cannot be used.
+    #[clap(long)]
+    expires_in: u64,
+
+    /// The number of puts allowed.
+    #[clap(long)]
+    max_uses: u64,
+}
+
+#[derive(Debug, Parser)]
+struct TokenOpt {
+    /// The token, in hexadecimal.
+    token: String,
+}
+
+// This type mirrors the one of the many-kvstore server.
+#[derive(Debug, Encode, Decode)]
+#[cbor(map)]
+struct Capability {
+    #[n(0)]
+    issuer: Address,
+
+    #[n(1)]
+    prefix: ByteVec,
+
+    #[n(2)]
+    max_value_size: u64,
+
+    #[n(3)]
+    expiry: Timestamp,
+
+    #[n(4)]
+    max_uses: u64,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct RevokeCapabilityArgs {
+    #[n(0)]
+    token: ByteVec,
+}
+
+fn decode_token(token: &str) -> Result<Vec<u8>, ManyError> {
+    hex::decode(token.trim()).map_err(|e| ManyError::unknown(format!("Invalid token: {}", e)))
+}
+
+/// Decode a token, returning its capability and the identity which signed
+/// it, if the signature is valid.
+fn decode_capability(token: &[u8]) -> Result<(Capability, Option<Address>), ManyError> {
+    let envelope = CoseSign1::from_slice(token)
+        .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    let signer = CoseKeyVerifier.verify_1(&envelope).ok();
+    let payload = envelope
+        .payload
+        .as_deref()
+        .ok_or_else(|| ManyError::unknown("The token has no capability."))?;
+    let capability =
+        minicbor::decode(payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    Ok((capability, signer))
+}
+
+fn secs(timestamp: Timestamp) -> Result<u64, ManyError> {
+    Ok(timestamp
+        .as_system_time()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| ManyError::unknown(e.to_string()))?
+        .as_secs())
+}
+
+fn format_capability(
+    capability: &Capability,
+    signer: Option<Address>,
+    now: u64,
+) -> Result<String, ManyError> {
+    let signature = match signer {
+        Some(signer) if signer == capability.issuer => "valid",
+        _ => "INVALID",
+    };
+    let expiry = secs(capability.expiry)?;
+    let prefix = match std::str::from_utf8(&capability.prefix) {
+        Ok(prefix) if !prefix.chars().any(|c| c.is_control()) => format!("{:?}", prefix),
+        _ => format!("0x{}", hex::encode(capability.prefix.as_slice())),
+    };
+    Ok(format!(
+        "Issuer:         {}\n\
+         Signature:      {}\n\
+         Prefix:         {}\n\
+         Max value size: {} bytes\n\
+         Max uses:       {}\n\
+         Expiry:         {} (seconds since the epoch){}",
+        capability.issuer,
+        signature,
+        prefix,
+        capability.max_value_size,
+        capability.max_uses,
+        expiry,
+        if expiry <= now { ", EXPIRED" } else { "" }
+    ))
+}
+
+fn create(pem: Option<&Path>, opt: CreateOpt) -> Result<(), ManyError> {
+    let pem = pem.ok_or_else(|| ManyError::unknown("The issuer of a capability needs a --pem."))?;
+    let identity = CoseKeyIdentity::from_pem(
+        &std::fs::read_to_string(pem).map_err(|e| ManyError::unknown(e.to_string()))?,
+    )
+    .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+    let prefix = if opt.hex_prefix {
+        hex::decode(&opt.prefix).map_err(|e| ManyError::unknown(e.to_string()))?
+    } else {
+        opt.prefix.into_bytes()
+    };
+    let expiry = SystemTime::now() + Duration::from_secs(opt.expires_in);
+    let capability = Capability {
+        issuer: identity.address(),
+        prefix: prefix.into(),
+        max_value_size: opt.max_value_size,
+        expiry: Timestamp::from_system_time(expiry)?,
+        max_uses: opt.max_uses,
+    };
+
+    let payload =
+        minicbor::to_vec(&capability).map_err(|e| ManyError::serialization_error(e.to_string()))?;
+    let token = identity
+        .sign_1(CoseSign1Builder::new().payload(payload).build())?
+        .to_vec()
+        .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+    println!("{}", hex::encode(token));
+    Ok(())
+}
+
+/// Put a value with a capability token, which the server executes on behalf
+/// of the issuer of the token.
+pub fn put(
+    client: ManyClient<impl Identity>,
+    from: Address,
+    to: Address,
+    arguments: kvstore::PutArgs,
+    token: &str,
+) -> Result<(), ManyError> {
+    let mut message = RequestMessageBuilder::default()
+        .from(from)
+        .to(to)
+        .method("kvstore.put".to_string())
+        .data(
+            minicbor::to_vec(arguments)
+                .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+        )
+        .build()
+        .map_err(|e| ManyError::unknown(e.to_string()))?;
+    message.timestamp = Some(Timestamp::now());
+    message.nonce = Some(rand::random::<[u8; 16]>().to_vec());
+    message
+        .attributes
+        .insert(CAPABILITY.with_argument(CborAny::Bytes(decode_token(token)?)));
+    let response = client.send_message(message)?;
+    let payload = wait_response(client, response)?;
+    println!("{}", minicbor::display(&payload));
+    Ok(())
+}
+
+pub fn capability(
+    client: ManyClient<impl Identity>,
+    pem: Option<&Path>,
+    opt: CapabilityOpt,
+) -> Result<(), ManyError> {
+    match opt.subcommand {
+        CapabilitySubCommand::Create(opt) => create(pem, opt),
+        CapabilitySubCommand::Inspect(TokenOpt { token }) => {
+            let (capability, signer) = decode_capability(&decode_token(&token)?)?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| ManyError::unknown(e.to_string()))?
+                .as_secs();
+            println!("{}", format_capability(&capability, signer, now)?);
+            Ok(())
+        }
+        CapabilitySubCommand::Revoke(TokenOpt { token }) => {
+            let arguments = RevokeCapabilityArgs {
+                token: decode_token(&token)?.into(),
+            };
+            let response = client.call("kvstore.revokeCapability", arguments)?;
+            let payload = wait_response(client, response)?;
+            println!("{}", minicbor::display(&payload));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn format() {
+        let issuer =
+            Address::from_str("mqbfbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wiaaaaqnz").unwrap();
+        let mut capability = Capability {
+            issuer,
+            prefix: b"telemetry/".to_vec().into(),
+            max_value_size: 1024,
+            expiry: Timestamp::new(1_000).unwrap(),
+            max_uses: 10,
+        };
+        assert_eq!(
+            format_capability(&capability, Some(issuer), 999).unwrap(),
+            format!(
+                "Issuer:         {}\n\
+                 Signature:      valid\n\
+                 Prefix:         \"telemetry/\"\n\
+                 Max value size: 1024 bytes\n\
+                 Max uses:       10\n\
+                 Expiry:         1000 (seconds since the epoch)",
+                issuer
+            )
+        );
+
+        capability.prefix = vec![0, 1].into();
+        let output = format_capability(&capability, None, 1_000).unwrap();
+        assert!(output.contains("Signature:      INVALID\n"));
+        assert!(output.contains("Prefix:         0x0001\n"));
+        assert!(output.ends_with("(seconds since the epoch), EXPIRED"));
+    }
+}
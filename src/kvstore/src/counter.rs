@@ -0,0 +1,213 @@
+use crate::wait_response;
+use clap::Parser;
+use many_client::client::blocking::ManyClient;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::kvstore;
+use minicbor::data::{Tag, Type};
+use minicbor::encode::{Error, Write};
+use minicbor::{Decode, Decoder, Encode, Encoder};
+use num_bigint::{BigInt, Sign};
+
+#[derive(Debug, Parser)]
+pub struct CounterOpt {
+    #[clap(subcommand)]
+    subcommand: CounterSubCommand,
+}
+
+#[derive(Debug, Parser)]
+enum CounterSubCommand {
+    /// Create a counter at zero.
+    Create(CreateOpt),
+
+    /// Add a value, which can be negative, to a counter.
+    Add(AddOpt),
+
+    /// Get the value of a counter.
+    Get(KeyOpt),
+}
+
+#[derive(Debug, Parser)]
+struct KeyOpt {
+    /// The key of the counter.
+    key: String,
+
+    /// If the key is a hexadecimal string, pass this flag.
+    #[clap(long)]
+    hex_key: bool,
+}
+
+impl KeyOpt {
+    fn bytes(&self) -> Vec<u8> {
+        if self.hex_key {
+            hex::decode(&self.key).unwrap()
+        } else {
+            self.key.clone().into_bytes()
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+struct CreateOpt {
+    #[clap(flatten)]
+    key: KeyOpt,
+
+    /// Allow the counter to go below zero.
+    #[clap(long)]
+    signed: bool,
+}
+
+#[derive(Debug, Parser)]
+struct AddOpt {
+    #[clap(flatten)]
+    key: KeyOpt,
+
+    /// The value to add.
+    #[clap(allow_hyphen_values = true)]
+    delta: BigInt,
+}
+
+/// A signed integer of any size, encoded as a CBOR integer or bignum.
+struct CounterValue(BigInt);
+
+impl<C> Encode<C> for CounterValue {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>, _: &mut C) -> Result<(), Error<W::Error>> {
+        if let Ok(value) = i64::try_from(&self.0) {
+            e.i64(value)?;
+        } else if self.0.sign() == Sign::Minus {
+            let n = -&self.0 - BigInt::from(1);
+            e.tag(Tag::NegBignum)?.bytes(&n.magnitude().to_bytes_be())?;
+        } else {
+            e.tag(Tag::PosBignum)?
+                .bytes(&self.0.magnitude().to_bytes_be())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'b, C> Decode<'b, C> for CounterValue {
+    fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, minicbor::decode::Error> {
+        match d.datatype()? {
+            Type::Tag => {
+                let tag = d.tag()?;
+                let n = BigInt::from_bytes_be(Sign::Plus, d.bytes()?);
+                match tag {
+                    Tag::PosBignum => Ok(Self(n)),
+                    Tag::NegBignum => Ok(Self(-n - BigInt::from(1))),
+                    _ => Err(minicbor::decode::Error::message("Invalid tag.")),
+                }
+            }
+            Type::U8 | Type::U16 | Type::U32 | Type::U64 => Ok(Self(BigInt::from(d.u64()?))),
+            _ => Ok(Self(BigInt::from(d.i64()?))),
+        }
+    }
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct CreateArgs {
+    #[n(0)]
+    key: Vec<u8>,
+
+    #[n(1)]
+    signed: bool,
+
+    #[n(2)]
+    alternative_owner: Option<Address>,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct AddArgs {
+    #[n(0)]
+    key: Vec<u8>,
+
+    #[n(1)]
+    delta: CounterValue,
+
+    #[n(2)]
+    alternative_owner: Option<Address>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct AddReturns {
+    #[n(0)]
+    value: CounterValue,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct QueryKindArgs {
+    #[n(0)]
+    key: Vec<u8>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct QueryKindReturns {
+    #[n(0)]
+    kind: u8,
+}
+
+const KIND_COUNTER: u8 = 1;
+const KIND_SIGNED_COUNTER: u8 = 2;
+
+fn decode<'a, T: Decode<'a, ()>>(payload: &'a [u8]) -> Result<T, ManyError> {
+    if payload.is_empty() {
+        return Err(ManyError::unexpected_empty_response());
+    }
+    minicbor::decode(payload).map_err(|e| ManyError::deserialization_error(e.to_string()))
+}
+
+fn get(client: ManyClient<impl Identity>, key: Vec<u8>) -> Result<(), ManyError> {
+    let payload = client.call_("kvstore.queryKind", QueryKindArgs { key: key.clone() })?;
+    let kind = decode::<QueryKindReturns>(&payload)?.kind;
+    if kind != KIND_COUNTER && kind != KIND_SIGNED_COUNTER {
+        return Err(ManyError::unknown("The key is not a counter."));
+    }
+
+    let payload = client.call_("kvstore.get", kvstore::GetArgs { key: key.into() })?;
+    let value = decode::<kvstore::GetReturns>(&payload)?
+        .value
+        .ok_or_else(|| ManyError::unknown("The counter has no value."))?;
+    let value = if kind == KIND_SIGNED_COUNTER {
+        BigInt::from_signed_bytes_be(&value)
+    } else {
+        BigInt::from_bytes_be(Sign::Plus, &value)
+    };
+    println!("{}", value);
+    Ok(())
+}
+
+pub fn counter(
+    client: ManyClient<impl Identity>,
+    alt_owner: Option<Address>,
+    opt: CounterOpt,
+) -> Result<(), ManyError> {
+    match opt.subcommand {
+        CounterSubCommand::Create(CreateOpt { key, signed }) => {
+            let arguments = CreateArgs {
+                key: key.bytes(),
+                signed,
+                alternative_owner: alt_owner,
+            };
+            let response = client.call("kvstore.counterCreate", arguments)?;
+            let payload = wait_response(client, response)?;
+            println!("{}", minicbor::display(&payload));
+            Ok(())
+        }
+        CounterSubCommand::Add(AddOpt { key, delta }) => {
+            let arguments = AddArgs {
+                key: key.bytes(),
+                delta: CounterValue(delta),
+                alternative_owner: alt_owner,
+            };
+            let response = client.call("kvstore.counterAdd", arguments)?;
+            let payload = wait_response(client, response)?;
+            println!("{}", decode::<AddReturns>(&payload)?.value.0);
+            Ok(())
+        }
+        CounterSubCommand::Get(key) => get(client, key.bytes()),
+    }
+}
@@ -0,0 +1,206 @@
+use crate::wait_response;
+use clap::Parser;
+use many_client::client::blocking::ManyClient;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Parser)]
+pub struct LockOpt {
+    #[clap(subcommand)]
+    subcommand: LockSubCommand,
+}
+
+#[derive(Debug, Parser)]
+enum LockSubCommand {
+    /// Acquire the lock of a key, and print its token.
+    Acquire(AcquireOpt),
+
+    /// Extend the lease of a lock held.
+    Renew(RenewOpt),
+
+    /// Release a lock held.
+    Release(ReleaseOpt),
+}
+
+#[derive(Debug, Parser)]
+struct KeyOpt {
+    /// The key of the lock.
+    key: String,
+
+    /// If the key is a hexadecimal string, pass this flag.
+    #[clap(long)]
+    hex_key: bool,
+}
+
+impl KeyOpt {
+    fn bytes(&self) -> Vec<u8> {
+        if self.hex_key {
+            hex::decode(&self.key).unwrap()
+        } else {
+            self.key.clone().into_bytes()
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+struct AcquireOpt {
+    #[clap(flatten)]
+    key: KeyOpt,
+
+    /// The duration of the lease, in seconds.
+    #[clap(long, default_value = "60")]
+    lease: u64,
+}
+
+#[derive(Debug, Parser)]
+struct RenewOpt {
+    #[clap(flatten)]
+    key: KeyOpt,
+
+    /// The token returned when acquiring the lock.
+    token: u64,
+
+    /// The duration of the new lease from now, in seconds.
+    #[clap(long, default_value = "60")]
+    lease: u64,
+}
+
+#[derive(Debug, Parser)]
+struct ReleaseOpt {
+    #[clap(flatten)]
+    key: KeyOpt,
+
+    /// The token returned when acquiring the lock.
+    token: u64,
+}
+
+// These types mirror the ones of the many-kvstore server.
+#[derive(Encode)]
+#[cbor(map)]
+struct AcquireArgs {
+    #[n(0)]
+    key: ByteVec,
+
+    #[n(1)]
+    lease_secs: u64,
+
+    #[n(2)]
+    alternative_owner: Option<Address>,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct RenewArgs {
+    #[n(0)]
+    key: ByteVec,
+
+    #[n(1)]
+    token: u64,
+
+    #[n(2)]
+    lease_secs: u64,
+
+    #[n(3)]
+    alternative_owner: Option<Address>,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct ReleaseArgs {
+    #[n(0)]
+    key: ByteVec,
+
+    #[n(1)]
+    token: u64,
+
+    #[n(2)]
+    alternative_owner: Option<Address>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct LockReturns {
+    #[n(0)]
+    token: u64,
+
+    #[n(1)]
+    expires: Timestamp,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+pub(crate) struct PutWithLockArgs {
+    #[n(0)]
+    pub key: ByteVec,
+
+    #[n(1)]
+    pub value: ByteVec,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+
+    #[n(3)]
+    pub token: Option<u64>,
+
+    #[n(4)]
+    pub lock_required: Option<bool>,
+}
+
+fn print_lock(payload: &[u8]) -> Result<(), ManyError> {
+    if payload.is_empty() {
+        return Err(ManyError::unexpected_empty_response());
+    }
+    let returns: LockReturns =
+        minicbor::decode(payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    let expires = returns
+        .expires
+        .as_system_time()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| ManyError::unknown(e.to_string()))?;
+    println!("Token:   {}", returns.token);
+    println!("Expires: {} (seconds since the epoch)", expires.as_secs());
+    Ok(())
+}
+
+pub fn lock(
+    client: ManyClient<impl Identity>,
+    alt_owner: Option<Address>,
+    opt: LockOpt,
+) -> Result<(), ManyError> {
+    match opt.subcommand {
+        LockSubCommand::Acquire(AcquireOpt { key, lease }) => {
+            let arguments = AcquireArgs {
+                key: key.bytes().into(),
+                lease_secs: lease,
+                alternative_owner: alt_owner,
+            };
+            let response = client.call("kvstore.lockAcquire", arguments)?;
+            print_lock(&wait_response(client, response)?)
+        }
+        LockSubCommand::Renew(RenewOpt { key, token, lease }) => {
+            let arguments = RenewArgs {
+                key: key.bytes().into(),
+                token,
+                lease_secs: lease,
+                alternative_owner: alt_owner,
+            };
+            let response = client.call("kvstore.lockRenew", arguments)?;
+            print_lock(&wait_response(client, response)?)
+        }
+        LockSubCommand::Release(ReleaseOpt { key, token }) => {
+            let arguments = ReleaseArgs {
+                key: key.bytes().into(),
+                token,
+                alternative_owner: alt_owner,
+            };
+            let response = client.call("kvstore.lockRelease", arguments)?;
+            let payload = wait_response(client, response)?;
+            println!("{}", minicbor::display(&payload));
+            Ok(())
+        }
+    }
+}
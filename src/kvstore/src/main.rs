@@ -14,6 +14,13 @@ use std::time::Duration;
 use tracing::{debug, error, info};
 use tracing_subscriber::filter::LevelFilter;
 
+mod capability;
+mod counter;
+mod lock;
+mod quota;
+mod stat;
+mod verify;
+
 #[derive(clap::ArgEnum, Clone, Debug)]
 enum LogStrategy {
     Terminal,
@@ -68,6 +75,21 @@ enum SubCommand {
 
     /// Disable a value from the store.
     Disable(DisableOpt),
+
+    /// Create, add to and get counters.
+    Counter(counter::CounterOpt),
+
+    /// Summarize a key, or all keys under a prefix.
+    Stat(stat::StatOpt),
+
+    /// Acquire, renew and release the locks of keys.
+    Lock(lock::LockOpt),
+
+    /// Show the number of bytes of values put by an owner, and its quota.
+    Usage(quota::UsageOpt),
+
+    /// Create, inspect and revoke capabilities delegating puts.
+    Capability(capability::CapabilityOpt),
 }
 
 #[derive(Debug, Parser)]
@@ -82,6 +104,9 @@ struct GetOpt {
     /// Whether to output using hexadecimal, or regular value.
     #[clap(long)]
     hex: bool,
+
+    #[clap(flatten)]
+    verify: verify::VerifyOpt,
 }
 
 #[derive(Debug, Parser)]
@@ -110,6 +135,18 @@ struct PutOpt {
     /// Use this flag to use STDIN to get the value.
     #[clap(long, conflicts_with = "value")]
     stdin: bool,
+
+    /// The token of the lock held on the key.
+    #[clap(long)]
+    lock: Option<u64>,
+
+    /// Create the key so that it can only be put with the token of its lock.
+    #[clap(long)]
+    lock_required: bool,
+
+    /// Put on behalf of the issuer of a capability token (in hexadecimal).
+    #[clap(long, conflicts_with_all = &["lock", "lock-required"])]
+    capability: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -126,7 +163,13 @@ struct DisableOpt {
     reason: Option<String>,
 }
 
-fn get(client: ManyClient<impl Identity>, key: &[u8], hex: bool) -> Result<(), ManyError> {
+fn get(
+    client: ManyClient<impl Identity>,
+    key: &[u8],
+    hex: bool,
+    verify: verify::VerifyOpt,
+) -> Result<(), ManyError> {
+    let expected = verify.expected()?;
     let arguments = kvstore::GetArgs {
         key: key.to_vec().into(),
     };
@@ -139,6 +182,23 @@ fn get(client: ManyClient<impl Identity>, key: &[u8], hex: bool) -> Result<(), M
             .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
         let value = result.value;
 
+        // The checks are made on the whole value, and a mismatch exits with
+        // its own code.
+        let value = match value {
+            Some(value) if !expected.is_empty() => match expected.check(value.into()) {
+                Ok(value) => Some(value.into()),
+                Err(mismatch) => {
+                    error!("{}", mismatch);
+                    std::process::exit(verify::MISMATCH_EXIT_CODE);
+                }
+            },
+            None if !expected.is_empty() => {
+                error!("The key has no value to verify.");
+                std::process::exit(verify::MISMATCH_EXIT_CODE);
+            }
+            value => value,
+        };
+
         if let Some(value) = value {
             if hex {
                 println!("{}", hex::encode(value.as_slice()));
@@ -186,14 +246,26 @@ fn put(
     alt_owner: Option<Address>,
     key: &[u8],
     value: Vec<u8>,
+    lock: Option<u64>,
+    lock_required: bool,
 ) -> Result<(), ManyError> {
-    let arguments = kvstore::PutArgs {
-        key: key.to_vec().into(),
-        value: value.into(),
-        alternative_owner: alt_owner,
+    let response = if lock.is_some() || lock_required {
+        let arguments = lock::PutWithLockArgs {
+            key: key.to_vec().into(),
+            value: value.into(),
+            alternative_owner: alt_owner,
+            token: lock,
+            lock_required: lock_required.then_some(true),
+        };
+        client.call("kvstore.putWithLock", arguments)?
+    } else {
+        let arguments = kvstore::PutArgs {
+            key: key.to_vec().into(),
+            value: value.into(),
+            alternative_owner: alt_owner,
+        };
+        client.call("kvstore.put", arguments)?
     };
-
-    let response = client.call("kvstore.put", arguments)?;
     let payload = wait_response(client, response)?;
     println!("{}", minicbor::display(&payload));
     Ok(())
@@ -227,6 +299,7 @@ pub(crate) fn wait_response(
 
     let payload = data?;
     debug!("response: {}", hex::encode(&payload));
+    quota::print_warning(&attributes);
     if payload.is_empty() {
         let attr = match attributes.get::<r#async::attributes::AsyncAttribute>() {
             Ok(attr) => attr,
@@ -316,20 +389,26 @@ fn main() {
 
     debug!("{:?}", Opts::parse());
 
-    let key = pem.map_or_else(
+    let key = pem.as_ref().map_or_else(
         || Box::new(AnonymousIdentity) as Box<dyn Identity>,
         |p| Box::new(CoseKeyIdentity::from_pem(&std::fs::read_to_string(&p).unwrap()).unwrap()),
     );
 
+    let sender = key.address();
     let client = ManyClient::new(&server, server_id, key).unwrap();
     let result = match subcommand {
-        SubCommand::Get(GetOpt { key, hex_key, hex }) => {
+        SubCommand::Get(GetOpt {
+            key,
+            hex_key,
+            hex,
+            verify,
+        }) => {
             let key = if hex_key {
                 hex::decode(&key).unwrap()
             } else {
                 key.into_bytes()
             };
-            get(client, &key, hex)
+            get(client, &key, hex, verify)
         }
         SubCommand::Query(QueryOpt { key, hex_key }) => {
             let key = if hex_key {
@@ -344,6 +423,9 @@ fn main() {
             hex_key,
             value,
             stdin,
+            lock,
+            lock_required,
+            capability,
         }) => {
             let key = if hex_key {
                 hex::decode(&key).unwrap()
@@ -357,7 +439,16 @@ fn main() {
             } else {
                 value.expect("Must pass a value").into_bytes()
             };
-            put(client, alt_owner, &key, value)
+            if let Some(token) = capability {
+                let arguments = kvstore::PutArgs {
+                    key: key.into(),
+                    value: value.into(),
+                    alternative_owner: alt_owner,
+                };
+                capability::put(client, sender, server_id, arguments, &token)
+            } else {
+                put(client, alt_owner, &key, value, lock, lock_required)
+            }
         }
         SubCommand::Disable(DisableOpt {
             key,
@@ -372,6 +463,11 @@ fn main() {
             let reason = reason.map(|reason| Reason::new(123456, Some(reason), BTreeMap::new()));
             disable(client, alt_owner, &key, reason)
         }
+        SubCommand::Counter(opt) => counter::counter(client, alt_owner, opt),
+        SubCommand::Stat(opt) => stat::stat(client, opt),
+        SubCommand::Lock(opt) => lock::lock(client, alt_owner, opt),
+        SubCommand::Usage(opt) => quota::usage(client, opt),
+        SubCommand::Capability(opt) => capability::capability(client, pem.as_deref(), opt),
     };
 
     if let Err(err) = result {
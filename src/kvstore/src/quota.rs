@@ -0,0 +1,116 @@
+use clap::Parser;
+use many_client::client::blocking::ManyClient;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_types::attributes::AttributeSet;
+use many_types::cbor::CborAny;
+use minicbor::{Decode, Encode};
+
+/// The ID of the attribute the server adds to the response of puts when the
+/// owner nears its quota.
+const QUOTA_WARNING_ID: u32 = 1001;
+
+#[derive(Debug, Parser)]
+pub struct UsageOpt {
+    /// The owner to show the usage of, e.g. an account the caller has a
+    /// role in. The caller if omitted.
+    owner: Option<Address>,
+}
+
+// These types mirror the ones of the many-kvstore server.
+
+#[derive(Encode)]
+#[cbor(map)]
+struct UsageArgs {
+    #[n(0)]
+    owner: Option<Address>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct UsageReturns {
+    #[n(0)]
+    usage: u64,
+
+    #[n(1)]
+    quota: Option<u64>,
+}
+
+fn format_usage(usage: &UsageReturns) -> String {
+    match usage.quota {
+        Some(quota) if quota > 0 => format!(
+            "{} of {} bytes ({}%)",
+            usage.usage,
+            quota,
+            usage.usage.saturating_mul(100) / quota
+        ),
+        Some(quota) => format!("{} of {} bytes", usage.usage, quota),
+        None => format!("{} bytes (no quota)", usage.usage),
+    }
+}
+
+/// The quota warning in the attributes of a response, if any.
+fn format_warning(attributes: &AttributeSet) -> Option<String> {
+    let attribute = attributes.get_attribute(QUOTA_WARNING_ID)?;
+    let int = |i: usize| match attribute.arguments.get(i) {
+        Some(CborAny::Int(x)) => Some(*x),
+        _ => None,
+    };
+    Some(format!(
+        "WARNING: the owner uses {} of its {} bytes of quota ({}%). \
+         Puts over the quota will be refused.",
+        int(0)?,
+        int(1)?,
+        int(2)?
+    ))
+}
+
+/// Print the quota warning of a response on stderr.
+pub fn print_warning(attributes: &AttributeSet) {
+    if let Some(warning) = format_warning(attributes) {
+        eprintln!("\n{}\n", warning);
+    }
+}
+
+pub fn usage(client: ManyClient<impl Identity>, opt: UsageOpt) -> Result<(), ManyError> {
+    let payload = client.call_("kvstore.usage", UsageArgs { owner: opt.owner })?;
+    if payload.is_empty() {
+        return Err(ManyError::unexpected_empty_response());
+    }
+    let usage: UsageReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    println!("{}", format_usage(&usage));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use many_types::attributes::Attribute;
+
+    #[test]
+    fn usage() {
+        let usage = |usage, quota| format_usage(&UsageReturns { usage, quota });
+        assert_eq!(usage(850, Some(1000)), "850 of 1000 bytes (85%)");
+        assert_eq!(usage(0, Some(0)), "0 of 0 bytes");
+        assert_eq!(usage(12, None), "12 bytes (no quota)");
+    }
+
+    #[test]
+    fn warning() {
+        let mut attributes = AttributeSet::default();
+        assert_eq!(format_warning(&attributes), None);
+
+        attributes.insert(
+            Attribute::id(QUOTA_WARNING_ID)
+                .with_argument(CborAny::Int(850))
+                .with_argument(CborAny::Int(1000))
+                .with_argument(CborAny::Int(85)),
+        );
+        assert_eq!(
+            format_warning(&attributes).unwrap(),
+            "WARNING: the owner uses 850 of its 1000 bytes of quota (85%). \
+             Puts over the quota will be refused."
+        );
+    }
+}
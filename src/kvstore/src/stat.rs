@@ -0,0 +1,648 @@
+use clap::Parser;
+use many_client::client::blocking::ManyClient;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::events;
+use many_modules::kvstore;
+use many_types::{CborRange, SortOrder};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::ops::Bound;
+
+// Left-shift the height by this amount of bits in event IDs.
+const HEIGHT_EVENTID_SHIFT: u32 = 32;
+
+/// Number of events read at once with `--heights`.
+const EVENTS_PAGE_SIZE: u64 = 100;
+
+#[derive(Debug, Parser)]
+pub struct StatOpt {
+    /// The key, or the prefix of the keys with `--recursive`.
+    key: String,
+
+    /// If the key is a hexadecimal string, pass this flag.
+    #[clap(long)]
+    hex_key: bool,
+
+    /// Summarize every key starting with the key given.
+    #[clap(short, long)]
+    recursive: bool,
+
+    /// Number of largest keys to show.
+    #[clap(long, default_value = "10")]
+    top: usize,
+
+    /// Fetch the values of a random sample of this many keys, to estimate
+    /// how compressible they are.
+    #[clap(long)]
+    sample: Option<usize>,
+
+    /// Read the event log to find the oldest and newest heights at which the
+    /// keys were last modified. This reads every event of the store, and
+    /// remembers every key seen.
+    #[clap(long)]
+    heights: bool,
+
+    /// Number of keys listed per call.
+    #[clap(long, default_value = "1000")]
+    page_size: u64,
+
+    /// Print the summary as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct ListArgs {
+    #[n(0)]
+    prefix: ByteVec,
+
+    #[n(1)]
+    after: Option<ByteVec>,
+
+    #[n(2)]
+    count: Option<u64>,
+}
+
+#[derive(Clone, Debug, Decode)]
+#[cbor(map)]
+struct ListEntry {
+    #[n(0)]
+    key: ByteVec,
+
+    #[n(1)]
+    size: u64,
+
+    #[n(2)]
+    owner: Option<Address>,
+
+    #[n(3)]
+    disabled: bool,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct ListReturns {
+    #[n(0)]
+    entries: Vec<ListEntry>,
+
+    #[n(1)]
+    next: Option<ByteVec>,
+}
+
+/// Aggregates of a listing. Its memory is bounded by the number of largest
+/// keys kept and the number of distinct owners.
+#[derive(Debug, Default)]
+struct Stats {
+    keys: u64,
+    total_size: u64,
+    disabled: u64,
+    owners: BTreeMap<Option<Address>, u64>,
+    top: usize,
+    largest: BinaryHeap<Reverse<(u64, Vec<u8>)>>,
+}
+
+impl Stats {
+    fn new(top: usize) -> Self {
+        Self {
+            top,
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, entry: &ListEntry) {
+        self.keys += 1;
+        self.total_size += entry.size;
+        if entry.disabled {
+            self.disabled += 1;
+        }
+        *self.owners.entry(entry.owner).or_default() += 1;
+
+        if self.top > 0 {
+            self.largest
+                .push(Reverse((entry.size, entry.key.as_slice().to_vec())));
+            if self.largest.len() > self.top {
+                self.largest.pop();
+            }
+        }
+    }
+
+    fn average_size(&self) -> Option<f64> {
+        (self.keys > 0).then(|| self.total_size as f64 / self.keys as f64)
+    }
+
+    /// The largest keys, largest first.
+    fn largest(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut largest: Vec<_> = self.largest.iter().map(|Reverse(x)| x.clone()).collect();
+        largest.sort_by(|a, b| b.cmp(a));
+        largest
+    }
+
+    /// The owners, most keys first.
+    fn owners(&self) -> Vec<(Option<Address>, u64)> {
+        let mut owners: Vec<_> = self.owners.iter().map(|(o, n)| (*o, *n)).collect();
+        owners.sort_by(|a, b| b.1.cmp(&a.1));
+        owners
+    }
+}
+
+/// A uniform random sample of keys of a listing of unknown size.
+#[derive(Debug)]
+struct Reservoir {
+    size: usize,
+    seen: u64,
+    keys: Vec<Vec<u8>>,
+}
+
+impl Reservoir {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            seen: 0,
+            keys: Vec::with_capacity(size),
+        }
+    }
+
+    fn offer(&mut self, key: &[u8], rng: &mut impl Rng) {
+        self.seen += 1;
+        if self.keys.len() < self.size {
+            self.keys.push(key.to_vec());
+        } else {
+            let i = rng.gen_range(0..self.seen) as usize;
+            if i < self.size {
+                self.keys[i] = key.to_vec();
+            }
+        }
+    }
+}
+
+/// An estimate of how compressible values are, from the frequency of their
+/// bytes. Compressors exploiting repetitions can do better than this.
+#[derive(Debug)]
+struct Compressibility {
+    values: u64,
+    bytes: u64,
+    counts: [u64; 256],
+}
+
+impl Default for Compressibility {
+    fn default() -> Self {
+        Self {
+            values: 0,
+            bytes: 0,
+            counts: [0; 256],
+        }
+    }
+}
+
+impl Compressibility {
+    fn add(&mut self, value: &[u8]) {
+        self.values += 1;
+        self.bytes += value.len() as u64;
+        for b in value {
+            self.counts[*b as usize] += 1;
+        }
+    }
+
+    /// The Shannon entropy of the bytes, in bits per byte.
+    fn entropy(&self) -> Option<f64> {
+        if self.bytes == 0 {
+            return None;
+        }
+        let total = self.bytes as f64;
+        Some(
+            self.counts
+                .iter()
+                .filter(|c| **c > 0)
+                .map(|c| {
+                    let p = *c as f64 / total;
+                    -p * p.log2()
+                })
+                .sum::<f64>()
+                .max(0.0),
+        )
+    }
+
+    /// The estimated compressed size, as a fraction of the original size.
+    fn ratio(&self) -> Option<f64> {
+        self.entropy().map(|e| e / 8.0)
+    }
+}
+
+/// The oldest and newest heights at which keys were last modified, from
+/// events read latest first.
+#[derive(Debug, Default)]
+struct Heights {
+    seen: BTreeSet<Vec<u8>>,
+    oldest: Option<u64>,
+    newest: Option<u64>,
+}
+
+impl Heights {
+    fn observe(&mut self, key: &[u8], height: u64) {
+        // The first event of a key is its last modification.
+        if self.seen.insert(key.to_vec()) {
+            self.oldest = Some(self.oldest.map_or(height, |h| h.min(height)));
+            self.newest = Some(self.newest.map_or(height, |h| h.max(height)));
+        }
+    }
+}
+
+fn event_height(id: &events::EventId) -> u64 {
+    (id.as_ref()
+        .iter()
+        .fold(0u128, |acc, b| acc.wrapping_shl(8) | *b as u128)
+        >> HEIGHT_EVENTID_SHIFT) as u64
+}
+
+fn event_key(info: &events::EventInfo) -> Option<&[u8]> {
+    match info {
+        events::EventInfo::KvStorePut { key, .. } => Some(key.as_slice()),
+        events::EventInfo::KvStoreDisable { key, .. } => Some(key.as_slice()),
+        _ => None,
+    }
+}
+
+/// Show a key as text if it is printable, or as hexadecimal otherwise.
+fn display_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(s) if !s.chars().any(char::is_control) => s.to_string(),
+        _ => format!("0x{}", hex::encode(key)),
+    }
+}
+
+fn display_owner(owner: &Option<Address>) -> String {
+    owner.map_or_else(|| "(none)".to_string(), |o| o.to_string())
+}
+
+fn decode<'a, T: Decode<'a, ()>>(payload: &'a [u8]) -> Result<T, ManyError> {
+    if payload.is_empty() {
+        return Err(ManyError::unexpected_empty_response());
+    }
+    minicbor::decode(payload).map_err(|e| ManyError::deserialization_error(e.to_string()))
+}
+
+fn read_heights(
+    client: &ManyClient<impl Identity>,
+    matches: impl Fn(&[u8]) -> bool,
+) -> Result<Heights, ManyError> {
+    let mut heights = Heights::default();
+    let mut before = None;
+    loop {
+        let payload = client.call_(
+            "events.list",
+            events::ListArgs {
+                count: Some(EVENTS_PAGE_SIZE),
+                order: Some(SortOrder::Descending),
+                filter: Some(events::EventFilter {
+                    kind: Some(
+                        vec![
+                            events::EventKind::KvStorePut,
+                            events::EventKind::KvStoreDisable,
+                        ]
+                        .into(),
+                    ),
+                    id_range: Some(CborRange {
+                        start: Bound::Unbounded,
+                        end: before.map_or(Bound::Unbounded, Bound::Excluded),
+                    }),
+                    ..events::EventFilter::default()
+                }),
+            },
+        )?;
+        let list: events::ListReturns = decode(&payload)?;
+        for log in &list.events {
+            if let Some(key) = event_key(&log.content).filter(|k| matches(k)) {
+                heights.observe(key, event_height(&log.id));
+            }
+        }
+        match list.events.last() {
+            Some(last) if list.events.len() as u64 == EVENTS_PAGE_SIZE => {
+                before = Some(last.id.clone())
+            }
+            _ => return Ok(heights),
+        }
+    }
+}
+
+fn sample_values(
+    client: &ManyClient<impl Identity>,
+    keys: &[Vec<u8>],
+) -> Result<Compressibility, ManyError> {
+    let mut compressibility = Compressibility::default();
+    for key in keys {
+        let payload = client.call_(
+            "kvstore.get",
+            kvstore::GetArgs {
+                key: key.clone().into(),
+            },
+        )?;
+        if let Some(value) = decode::<kvstore::GetReturns>(&payload)?.value {
+            compressibility.add(&value);
+        }
+    }
+    Ok(compressibility)
+}
+
+fn print_human(stats: &Stats, heights: Option<&Heights>, sample: Option<&Compressibility>) {
+    println!(
+        "Keys:          {} ({} disabled)",
+        stats.keys, stats.disabled
+    );
+    println!("Total size:    {} bytes", stats.total_size);
+    if let Some(average) = stats.average_size() {
+        println!("Average size:  {:.1} bytes", average);
+    }
+    if let Some(Heights {
+        oldest: Some(oldest),
+        newest: Some(newest),
+        ..
+    }) = heights
+    {
+        println!("Last modified: between heights {} and {}", oldest, newest);
+    }
+    if let Some(sample) = sample {
+        match sample.ratio() {
+            Some(ratio) => println!(
+                "Sample:        {} values, estimated compressed size {:.1}%",
+                sample.values,
+                ratio * 100.0
+            ),
+            None => println!("Sample:        {} values, all empty", sample.values),
+        }
+    }
+
+    let largest = stats.largest();
+    if !largest.is_empty() {
+        println!("\nLargest keys:");
+        for (size, key) in largest {
+            println!("  {:>12}  {}", size, display_key(&key));
+        }
+    }
+
+    let owners = stats.owners();
+    if !owners.is_empty() {
+        println!("\nOwners:");
+        for (owner, count) in owners {
+            println!("  {:>12}  {}", count, display_owner(&owner));
+        }
+    }
+}
+
+fn to_json(
+    stats: &Stats,
+    heights: Option<&Heights>,
+    sample: Option<&Compressibility>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "keys": stats.keys,
+        "disabled": stats.disabled,
+        "total_size": stats.total_size,
+        "average_size": stats.average_size(),
+        "largest": stats.largest().iter().map(|(size, key)| serde_json::json!({
+            "key": display_key(key),
+            "size": size,
+        })).collect::<Vec<_>>(),
+        "owners": stats.owners().iter().map(|(owner, count)| serde_json::json!({
+            "owner": owner.map(|o| o.to_string()),
+            "keys": count,
+        })).collect::<Vec<_>>(),
+        "oldest_height": heights.and_then(|h| h.oldest),
+        "newest_height": heights.and_then(|h| h.newest),
+        "sample": sample.map(|s| serde_json::json!({
+            "values": s.values,
+            "bytes": s.bytes,
+            "entropy": s.entropy(),
+            "estimated_ratio": s.ratio(),
+        })),
+    })
+}
+
+pub fn stat(client: ManyClient<impl Identity>, opt: StatOpt) -> Result<(), ManyError> {
+    let key = if opt.hex_key {
+        hex::decode(&opt.key).map_err(|e| ManyError::unknown(e.to_string()))?
+    } else {
+        opt.key.clone().into_bytes()
+    };
+    let matches = |k: &[u8]| {
+        if opt.recursive {
+            k.starts_with(&key)
+        } else {
+            k == key.as_slice()
+        }
+    };
+
+    let mut stats = Stats::new(opt.top);
+    let mut reservoir = opt.sample.map(Reservoir::new);
+    let mut rng = rand::thread_rng();
+
+    // Stream the pages, so only the aggregates are kept in memory.
+    let mut after = None;
+    loop {
+        let payload = client.call_(
+            "kvstore.list",
+            ListArgs {
+                prefix: key.clone().into(),
+                after: after.take(),
+                count: Some(opt.page_size),
+            },
+        )?;
+        let page: ListReturns = decode(&payload)?;
+        for entry in page.entries.iter().filter(|e| matches(&e.key)) {
+            stats.add(entry);
+            if let Some(reservoir) = reservoir.as_mut() {
+                // Disabled values cannot be read.
+                if !entry.disabled {
+                    reservoir.offer(&entry.key, &mut rng);
+                }
+            }
+        }
+
+        match page.next {
+            // The key itself is always the first of its prefix.
+            Some(next) if opt.recursive => after = Some(next),
+            _ => break,
+        }
+    }
+
+    let heights = if opt.heights {
+        Some(read_heights(&client, matches)?)
+    } else {
+        None
+    };
+    let sample = reservoir
+        .map(|r| sample_values(&client, &r.keys))
+        .transpose()?;
+
+    if opt.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&to_json(&stats, heights.as_ref(), sample.as_ref()))
+                .map_err(|e| ManyError::unknown(e.to_string()))?
+        );
+    } else {
+        print_human(&stats, heights.as_ref(), sample.as_ref());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::str::FromStr;
+
+    fn owner(i: u32) -> Address {
+        Address::from_str("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn entry(key: &str, size: u64, owner: Option<Address>, disabled: bool) -> ListEntry {
+        ListEntry {
+            key: key.as_bytes().to_vec().into(),
+            size,
+            owner,
+            disabled,
+        }
+    }
+
+    #[test]
+    fn aggregates() {
+        let mut stats = Stats::new(2);
+        assert_eq!(stats.average_size(), None);
+
+        for e in [
+            entry("a", 10, Some(owner(1)), false),
+            entry("b", 30, Some(owner(2)), true),
+            entry("c", 20, Some(owner(1)), false),
+            entry("d", 0, None, false),
+        ] {
+            stats.add(&e);
+        }
+
+        assert_eq!(stats.keys, 4);
+        assert_eq!(stats.total_size, 60);
+        assert_eq!(stats.disabled, 1);
+        assert_eq!(stats.average_size(), Some(15.0));
+        assert_eq!(
+            stats.largest(),
+            vec![(30, b"b".to_vec()), (20, b"c".to_vec())]
+        );
+        assert_eq!(stats.owners()[0], (Some(owner(1)), 2));
+        assert_eq!(stats.owners().len(), 3);
+    }
+
+    #[test]
+    fn largest_is_bounded() {
+        let mut stats = Stats::new(3);
+        for i in 0..1000u64 {
+            stats.add(&entry(&i.to_string(), i % 500, None, false));
+        }
+        assert_eq!(stats.largest.len(), 3);
+        assert_eq!(
+            stats.largest().iter().map(|(s, _)| *s).collect::<Vec<_>>(),
+            vec![499, 499, 498]
+        );
+
+        let mut stats = Stats::new(0);
+        stats.add(&entry("a", 1, None, false));
+        assert!(stats.largest().is_empty());
+    }
+
+    #[test]
+    fn reservoir_keeps_everything_when_small() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut reservoir = Reservoir::new(10);
+        for i in 0..5u8 {
+            reservoir.offer(&[i], &mut rng);
+        }
+        assert_eq!(
+            reservoir.keys,
+            vec![vec![0], vec![1], vec![2], vec![3], vec![4]]
+        );
+    }
+
+    #[test]
+    fn reservoir_is_uniform() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = [0u32; 10];
+        for _ in 0..2000 {
+            let mut reservoir = Reservoir::new(2);
+            for i in 0..10u8 {
+                reservoir.offer(&[i], &mut rng);
+            }
+            assert_eq!(reservoir.keys.len(), 2);
+            assert_eq!(reservoir.seen, 10);
+            for key in reservoir.keys {
+                counts[key[0] as usize] += 1;
+            }
+        }
+        // Each key is expected 400 times.
+        assert!(counts.iter().all(|c| (300..500).contains(c)), "{counts:?}");
+    }
+
+    #[test]
+    fn compressibility() {
+        let mut zeros = Compressibility::default();
+        assert_eq!(zeros.ratio(), None);
+        zeros.add(&[0; 100]);
+        assert_eq!(zeros.entropy(), Some(0.0));
+
+        let mut uniform = Compressibility::default();
+        uniform.add(&(0..=255u8).collect::<Vec<_>>());
+        uniform.add(&(0..=255u8).rev().collect::<Vec<_>>());
+        assert_eq!(uniform.values, 2);
+        assert!((uniform.ratio().unwrap() - 1.0).abs() < 1e-9);
+
+        let mut text = Compressibility::default();
+        text.add(b"abababababababab");
+        assert!((text.entropy().unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn heights_use_the_last_modification() {
+        let mut heights = Heights::default();
+        // Events are read latest first.
+        heights.observe(b"a", 50);
+        heights.observe(b"b", 40);
+        heights.observe(b"a", 10);
+        heights.observe(b"c", 20);
+        heights.observe(b"b", 5);
+        assert_eq!(heights.newest, Some(50));
+        assert_eq!(heights.oldest, Some(20));
+    }
+
+    #[test]
+    fn event_heights() {
+        assert_eq!(
+            event_height(&events::EventId::from(vec![0, 0, 0, 12, 0, 0, 0, 1])),
+            12
+        );
+        assert_eq!(event_height(&events::EventId::from(vec![1])), 0);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(display_key(b"app/config"), "app/config");
+        assert_eq!(display_key(&[0, 159]), "0x009f");
+        assert_eq!(display_key(b"a\nb"), "0x610a62");
+    }
+
+    #[test]
+    fn json() {
+        let mut stats = Stats::new(1);
+        stats.add(&entry("a", 10, None, false));
+        let json = to_json(&stats, None, None);
+        assert_eq!(json["keys"], 1);
+        assert_eq!(json["largest"][0]["key"], "a");
+        assert_eq!(json["owners"][0]["owner"], serde_json::Value::Null);
+        assert_eq!(json["sample"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,222 @@
+use clap::Parser;
+use coset::{CborSerializable, CoseSign1};
+use many_error::ManyError;
+use many_identity::{Address, Identity, Verifier};
+use many_identity_dsa::{CoseKeyIdentity, CoseKeyVerifier};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+/// The exit code when a value is not the one expected, so scripts can tell
+/// it apart from the other errors, which exit with 1.
+pub const MISMATCH_EXIT_CODE: i32 = 3;
+
+#[derive(Debug, Parser)]
+pub struct VerifyOpt {
+    /// Fail unless the SHA-256 of the value is this hash, in hexadecimal.
+    /// With `--verify-signature`, this is the hash of the whole envelope.
+    #[clap(long)]
+    expect_sha256: Option<String>,
+
+    /// Expect the value to be a COSE_Sign1 envelope signed by the key of this
+    /// PEM file, and output only its payload once the signature is verified.
+    #[clap(long)]
+    verify_signature: Option<PathBuf>,
+}
+
+impl VerifyOpt {
+    /// Read the hash and the key to check the value against.
+    pub fn expected(&self) -> Result<Expected, ManyError> {
+        let sha256 = self
+            .expect_sha256
+            .as_ref()
+            .map(|hash| match hex::decode(hash.trim()) {
+                Ok(hash) if hash.len() == 32 => Ok(hash),
+                _ => Err(ManyError::unknown(format!(
+                    "Invalid SHA-256 hash: {}, expected 64 hexadecimal characters.",
+                    hash
+                ))),
+            })
+            .transpose()?;
+        let signer = self
+            .verify_signature
+            .as_ref()
+            .map(|path| {
+                let pem = std::fs::read_to_string(path).map_err(|e| {
+                    ManyError::unknown(format!("Could not read {}: {}", path.display(), e))
+                })?;
+                CoseKeyIdentity::from_pem(&pem)
+                    .map(|identity| identity.address())
+                    .map_err(|e| ManyError::unknown(e.to_string()))
+            })
+            .transpose()?;
+        Ok(Expected { sha256, signer })
+    }
+}
+
+/// A value which is not the one expected.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch(String);
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// What a value is checked against.
+#[derive(Debug, Default)]
+pub struct Expected {
+    sha256: Option<Vec<u8>>,
+    signer: Option<Address>,
+}
+
+impl Expected {
+    pub fn new(sha256: Option<Vec<u8>>, signer: Option<Address>) -> Self {
+        Self { sha256, signer }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sha256.is_none() && self.signer.is_none()
+    }
+
+    /// Check a whole value, and return what to output: the value itself, or
+    /// the payload of its envelope when a signer is expected.
+    pub fn check(&self, value: Vec<u8>) -> Result<Vec<u8>, Mismatch> {
+        if let Some(expected) = &self.sha256 {
+            let actual = Sha256::digest(&value);
+            if actual.as_slice() != expected.as_slice() {
+                return Err(Mismatch(format!(
+                    "The SHA-256 of the value is {}, expected {}.",
+                    hex::encode(actual),
+                    hex::encode(expected)
+                )));
+            }
+        }
+
+        let signer = match self.signer {
+            Some(signer) => signer,
+            None => return Ok(value),
+        };
+        let envelope = CoseSign1::from_slice(&value)
+            .map_err(|e| Mismatch(format!("The value is not a COSE_Sign1 envelope: {}", e)))?;
+        let actual = CoseKeyVerifier
+            .verify_1(&envelope)
+            .map_err(|e| Mismatch(format!("The signature of the value is invalid: {}", e)))?;
+        if actual != signer {
+            return Err(Mismatch(format!(
+                "The value is signed by {}, expected {}.",
+                actual, signer
+            )));
+        }
+        envelope
+            .payload
+            .ok_or_else(|| Mismatch("The envelope of the value has no payload.".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coset::CoseSign1Builder;
+    use many_identity_dsa::ecdsa::generate_random_ecdsa_identity;
+
+    fn signed(identity: &CoseKeyIdentity, payload: &[u8]) -> Vec<u8> {
+        identity
+            .sign_1(CoseSign1Builder::new().payload(payload.to_vec()).build())
+            .unwrap()
+            .to_vec()
+            .unwrap()
+    }
+
+    #[test]
+    fn sha256() {
+        let hash = Sha256::digest(b"artifact").to_vec();
+        let expected = Expected::new(Some(hash), None);
+        assert_eq!(
+            expected.check(b"artifact".to_vec()),
+            Ok(b"artifact".to_vec())
+        );
+
+        let mismatch = expected.check(b"artifacT".to_vec()).unwrap_err();
+        assert!(mismatch
+            .to_string()
+            .starts_with("The SHA-256 of the value is "));
+    }
+
+    #[test]
+    fn signature() {
+        let signer = generate_random_ecdsa_identity();
+        let envelope = signed(&signer, b"artifact");
+        let expected = Expected::new(None, Some(signer.address()));
+
+        // Only the payload is output.
+        assert_eq!(expected.check(envelope.clone()), Ok(b"artifact".to_vec()));
+
+        // Tampered.
+        let mut tampered = envelope.clone();
+        let at = tampered.windows(8).position(|w| w == b"artifact").unwrap();
+        tampered[at] = b'A';
+        assert!(expected
+            .check(tampered)
+            .unwrap_err()
+            .to_string()
+            .starts_with("The signature of the value is invalid"));
+
+        // Signed by another key.
+        let other = generate_random_ecdsa_identity();
+        assert_eq!(
+            expected.check(signed(&other, b"artifact")),
+            Err(Mismatch(format!(
+                "The value is signed by {}, expected {}.",
+                other.address(),
+                signer.address()
+            )))
+        );
+
+        // Not an envelope.
+        assert!(expected
+            .check(b"artifact".to_vec())
+            .unwrap_err()
+            .to_string()
+            .starts_with("The value is not a COSE_Sign1 envelope"));
+    }
+
+    #[test]
+    fn both() {
+        let signer = generate_random_ecdsa_identity();
+        let envelope = signed(&signer, b"artifact");
+
+        // The hash is the one of the envelope, checked before the signature.
+        let expected = Expected::new(
+            Some(Sha256::digest(&envelope).to_vec()),
+            Some(signer.address()),
+        );
+        assert_eq!(expected.check(envelope), Ok(b"artifact".to_vec()));
+
+        let expected = Expected::new(
+            Some(Sha256::digest(b"artifact").to_vec()),
+            Some(signer.address()),
+        );
+        assert!(expected
+            .check(signed(&signer, b"artifact"))
+            .unwrap_err()
+            .to_string()
+            .starts_with("The SHA-256"));
+    }
+
+    #[test]
+    fn options() {
+        let opt = VerifyOpt {
+            expect_sha256: Some("00".to_string()),
+            verify_signature: None,
+        };
+        assert!(opt.expected().is_err());
+
+        let opt = VerifyOpt {
+            expect_sha256: Some(hex::encode(Sha256::digest(b""))),
+            verify_signature: None,
+        };
+        assert!(!opt.expected().unwrap().is_empty());
+    }
+}
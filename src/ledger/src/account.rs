@@ -0,0 +1,1298 @@
+use crate::history::EventPages;
+use crate::output::{self, OutputFormat};
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::account::features::FeatureInfo;
+use many_modules::{account, events};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use tracing::{info, warn};
+
+#[derive(Parser)]
+pub struct CommandOpt {
+    #[clap(subcommand)]
+    /// Account subcommand to execute.
+    subcommand: SubcommandOpt,
+}
+
+#[derive(Parser)]
+enum SubcommandOpt {
+    /// Create an account, and print its address.
+    Create(CreateOpt),
+
+    /// Show the description, features and roles of an account, with the
+    /// multisig settings and, for a subresource, the account it belongs to.
+    Info(InfoOpt),
+
+    /// Give roles to identities on an account.
+    AddRoles(RolesOpt),
+
+    /// Take roles from identities on an account. Removing the last owner
+    /// asks for a confirmation first.
+    RemoveRoles(RemoveRolesOpt),
+
+    /// Show the roles of each identity on an account.
+    Roles(ListRolesOpt),
+
+    /// Let an identity claim the ownership of an account after its owners
+    /// have been inactive for some time.
+    SetRecovery(SetRecoveryOpt),
+
+    /// Claim the ownership of an account as its recovery identity. The
+    /// first call opens a claim, which owners can veto by sending any
+    /// command during the challenge window. Call it again after the
+    /// window to complete the recovery.
+    Recover(RecoverOpt),
+}
+
+#[derive(Parser)]
+struct CreateOpt {
+    /// The description of the account.
+    #[clap(long)]
+    description: Option<String>,
+
+    /// A role given to an identity on the account, as `<address>:<role>`,
+    /// e.g. `<address>:canMultisigApprove`. The creator is always an owner.
+    /// Multiple occurrences of this argument can be given.
+    #[clap(long = "role")]
+    roles: Vec<RoleArg>,
+
+    /// A feature of the account. Multisig accounts use the default
+    /// threshold, timeout and automatic execution, which can be changed
+    /// with `multisig set-defaults`. Multiple occurrences of this argument
+    /// can be given.
+    #[clap(long = "feature", arg_enum)]
+    features: Vec<FeatureArg>,
+}
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FeatureArg {
+    Ledger,
+    Multisig,
+}
+
+impl FeatureArg {
+    fn feature(self) -> account::features::Feature {
+        match self {
+            FeatureArg::Ledger => {
+                account::features::Feature::with_id(account::features::ledger::AccountLedger::ID)
+            }
+            FeatureArg::Multisig => {
+                account::features::multisig::MultisigAccountFeature::default().as_feature()
+            }
+        }
+    }
+}
+
+/// The roles which can be given to an identity on an account.
+pub const ROLES: [account::Role; 6] = [
+    account::Role::Owner,
+    account::Role::CanLedgerTransact,
+    account::Role::CanMultisigSubmit,
+    account::Role::CanMultisigApprove,
+    account::Role::CanKvStorePut,
+    account::Role::CanKvStoreDisable,
+];
+
+/// A role by its name, e.g. `canMultisigApprove`.
+pub fn parse_role(name: &str) -> Result<account::Role, String> {
+    ROLES
+        .iter()
+        .find(|role| role.to_string() == name)
+        .copied()
+        .ok_or_else(|| {
+            format!(
+                "Unknown role '{}', expected one of {}.",
+                name,
+                format_role_set(&ROLES.iter().copied().collect())
+            )
+        })
+}
+
+/// A role given to an identity, as `<address>:<role>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RoleArg {
+    address: Address,
+    role: account::Role,
+}
+
+impl FromStr for RoleArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, role) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("'{}' is not of the form <address>:<role>.", s))?;
+        Ok(Self {
+            address: crate::alias::address(address)?,
+            role: parse_role(role)?,
+        })
+    }
+}
+
+#[derive(Parser)]
+struct InfoOpt {
+    /// The account to show.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    account: Address,
+
+    /// Also show what changed on the account since a time, in RFC 3339
+    /// format (e.g. `2022-06-01` or `2022-06-01T12:00:00Z`), or after an
+    /// event, given by its hexadecimal ID.
+    #[clap(long)]
+    since: Option<Since>,
+}
+
+#[derive(Parser)]
+struct RolesOpt {
+    /// The account to change.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    account: Address,
+
+    /// The roles, as `<address>:<role>`, e.g. `<address>:canMultisigApprove`.
+    #[clap(required = true)]
+    roles: Vec<RoleArg>,
+}
+
+#[derive(Parser)]
+struct RemoveRolesOpt {
+    #[clap(flatten)]
+    roles: RolesOpt,
+
+    /// Remove the last owner of the account without asking to confirm.
+    #[clap(long)]
+    yes: bool,
+}
+
+#[derive(Parser)]
+struct ListRolesOpt {
+    /// The account to show.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    account: Address,
+}
+
+#[derive(Parser)]
+struct SetRecoveryOpt {
+    /// The account to recover.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    account: Address,
+
+    /// The identity which can claim the account.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    recovery: Address,
+
+    /// The time without a command from an owner after which the recovery
+    /// identity can claim the account.
+    #[clap(long)]
+    inactivity: humantime::Duration,
+
+    /// The time an owner has to veto a claim.
+    #[clap(long)]
+    challenge: humantime::Duration,
+}
+
+#[derive(Parser)]
+struct RecoverOpt {
+    /// The account to recover.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    account: Address,
+}
+
+// These types mirror the ones of the many-ledger server.
+
+#[derive(Encode)]
+#[cbor(map)]
+struct SetRecoveryArgs {
+    #[n(0)]
+    account: Address,
+
+    #[n(1)]
+    recovery: Address,
+
+    #[n(2)]
+    inactivity_secs: u64,
+
+    #[n(3)]
+    challenge_secs: u64,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct RecoverArgs {
+    #[n(0)]
+    account: Address,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct RecoverReturns {
+    #[n(0)]
+    recovered: bool,
+
+    #[n(1)]
+    completable: Option<Timestamp>,
+}
+
+/// The start of a range of events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Since {
+    Time(Timestamp),
+    After(events::EventId),
+}
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let time = if s.len() == 10 {
+            humantime::parse_rfc3339(&format!("{}T00:00:00Z", s))
+        } else {
+            humantime::parse_rfc3339_weak(s)
+        };
+        if let Ok(time) = time {
+            return Timestamp::from_system_time(time)
+                .map(Since::Time)
+                .map_err(|e| e.to_string());
+        }
+        hex::decode(s)
+            .map(|id| Since::After(events::EventId::from(id)))
+            .map_err(|_| format!("'{}' is neither an RFC 3339 time nor an event ID.", s))
+    }
+}
+
+/// The events which change the configuration of an account.
+const ACCOUNT_EVENT_KINDS: [events::EventKind; 7] = [
+    events::EventKind::AccountCreate,
+    events::EventKind::AccountSetDescription,
+    events::EventKind::AccountAddRoles,
+    events::EventKind::AccountRemoveRoles,
+    events::EventKind::AccountAddFeatures,
+    events::EventKind::AccountDisable,
+    events::EventKind::AccountMultisigSetDefaults,
+];
+
+type Roles = BTreeMap<Address, BTreeSet<account::Role>>;
+
+fn format_role_set(roles: &BTreeSet<account::Role>) -> String {
+    if roles.is_empty() {
+        "(none)".to_string()
+    } else {
+        roles
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn format_roles(roles: &Roles) -> String {
+    roles
+        .iter()
+        .map(|(id, roles)| format!("{} ({})", id, format_role_set(roles)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_features(features: &account::features::FeatureSet) -> String {
+    features
+        .iter()
+        .map(|f| f.id().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A line of the changelog of an account, or `None` for events which do
+/// not change it.
+fn describe(content: &events::EventInfo) -> Option<String> {
+    Some(match content {
+        events::EventInfo::AccountCreate {
+            description,
+            roles,
+            features,
+            ..
+        } => format!(
+            "Created with description {:?}, roles {} and features {}",
+            description.as_deref().unwrap_or_default(),
+            format_roles(roles),
+            format_features(features)
+        ),
+        events::EventInfo::AccountSetDescription { description, .. } => {
+            format!("Description set to {:?}", description)
+        }
+        events::EventInfo::AccountAddRoles { roles, .. } => {
+            format!("Roles added: {}", format_roles(roles))
+        }
+        events::EventInfo::AccountRemoveRoles { roles, .. } => {
+            format!("Roles removed: {}", format_roles(roles))
+        }
+        events::EventInfo::AccountAddFeatures {
+            roles, features, ..
+        } => {
+            let mut line = format!("Features added: {}", format_features(features));
+            if !roles.is_empty() {
+                line += &format!("; roles added: {}", format_roles(roles));
+            }
+            line
+        }
+        events::EventInfo::AccountDisable { .. } => "Disabled".to_string(),
+        events::EventInfo::AccountMultisigSetDefaults {
+            submitter,
+            threshold,
+            timeout_in_secs,
+            execute_automatically,
+            ..
+        } => {
+            let mut changes = vec![];
+            if let Some(threshold) = threshold {
+                changes.push(format!("threshold {}", threshold));
+            }
+            if let Some(timeout) = timeout_in_secs {
+                changes.push(format!("timeout {}s", timeout));
+            }
+            if let Some(execute) = execute_automatically {
+                changes.push(format!("execute automatically {}", execute));
+            }
+            format!(
+                "Multisig defaults set by {}: {}",
+                submitter,
+                changes.join(", ")
+            )
+        }
+        _ => return None,
+    })
+}
+
+/// Undo the change of an event on the roles of an account. Role events only
+/// list the roles which were actually added or removed once the
+/// `ChangedRoleEvents` migration of the ledger is active. Earlier events can
+/// list roles an identity already had, or never had, so the roles before
+/// them are approximate.
+fn undo(roles: &mut Roles, content: &events::EventInfo) {
+    match content {
+        events::EventInfo::AccountCreate { .. } => roles.clear(),
+        events::EventInfo::AccountAddRoles { roles: added, .. }
+        | events::EventInfo::AccountAddFeatures { roles: added, .. } => {
+            for (id, added) in added {
+                if let Some(current) = roles.get_mut(id) {
+                    current.retain(|r| !added.contains(r));
+                    if current.is_empty() {
+                        roles.remove(id);
+                    }
+                }
+            }
+        }
+        events::EventInfo::AccountRemoveRoles { roles: removed, .. } => {
+            for (id, removed) in removed {
+                roles
+                    .entry(*id)
+                    .or_default()
+                    .extend(removed.iter().copied());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The roles of an account before a list of events, in chronological
+/// order, given its current roles.
+fn roles_before(current: &Roles, events: &[events::EventLog]) -> Roles {
+    let mut roles = current.clone();
+    for log in events.iter().rev() {
+        undo(&mut roles, &log.content);
+    }
+    roles
+}
+
+/// The changelog of an account and the summary of its roles before and
+/// after the events.
+fn format_changes(current: &Roles, events: &[events::EventLog]) -> Result<String, ManyError> {
+    let mut lines = vec!["Changes:".to_string()];
+    for log in events {
+        if let Some(change) = describe(&log.content) {
+            lines.push(format!(
+                "  {} {} {}",
+                humantime::format_rfc3339_seconds(log.time.as_system_time()?),
+                hex::encode(log.id.as_ref()),
+                change
+            ));
+        }
+    }
+    if lines.len() == 1 {
+        lines.push("  (none)".to_string());
+    }
+
+    let before = roles_before(current, events);
+    let empty = BTreeSet::new();
+    lines.push("Roles:".to_string());
+    for id in before.keys().chain(current.keys()).collect::<BTreeSet<_>>() {
+        let was = before.get(id).unwrap_or(&empty);
+        let is = current.get(id).unwrap_or(&empty);
+        if was == is {
+            lines.push(format!("  {}: {} (unchanged)", id, format_role_set(is)));
+        } else {
+            lines.push(format!(
+                "  {}: {} -> {}",
+                id,
+                format_role_set(was),
+                format_role_set(is)
+            ));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Where the description, roles and features of accounts are read from.
+pub trait AccountSource {
+    fn account_info(&self, account: Address) -> Result<account::InfoReturn, ManyError>;
+}
+
+impl<I: Identity> AccountSource for ManyClient<I> {
+    fn account_info(&self, account: Address) -> Result<account::InfoReturn, ManyError> {
+        let payload = crate::transport::call_(self, "account.info", account::InfoArgs { account })?;
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+}
+
+/// The roles allowed to send tokens from an account.
+pub const SEND_ROLES: [account::Role; 2] = [account::Role::Owner, account::Role::CanLedgerTransact];
+
+/// The roles allowed to submit multisig transactions of an account.
+pub const MULTISIG_SUBMIT_ROLES: [account::Role; 2] =
+    [account::Role::Owner, account::Role::CanMultisigSubmit];
+
+/// Check that the signer of a command on behalf of an account holds one of
+/// the roles allowed to send it, e.g. to catch a personal key used instead
+/// of the one holding the role. When it does not, the caller and the roles
+/// it holds are reported, and the command is refused unless `force` is set.
+pub fn check_signer(
+    source: &impl AccountSource,
+    account: Address,
+    signer: Address,
+    allowed: &[account::Role],
+    force: bool,
+) -> Result<(), ManyError> {
+    if account == signer {
+        return Ok(());
+    }
+
+    let message = match source.account_info(account) {
+        Ok(info) => {
+            let held = info.roles.get(&signer).cloned().unwrap_or_default();
+            if allowed.iter().any(|role| held.contains(role)) {
+                return Ok(());
+            }
+            format!(
+                "The caller {} holds the roles {} on the account {}, but this needs one of {}.",
+                signer,
+                format_role_set(&held),
+                account,
+                format_role_set(&allowed.iter().copied().collect()),
+            )
+        }
+        Err(e) => format!(
+            "Could not read the roles of the account {} for the caller {}: {}",
+            account, signer, e
+        ),
+    };
+
+    if force {
+        warn!("{}", message);
+        Ok(())
+    } else {
+        Err(ManyError::unknown(format!(
+            "{} Use --force to send it anyway.",
+            message
+        )))
+    }
+}
+
+/// The roles given as arguments, by identity.
+fn role_map(args: Vec<RoleArg>) -> Roles {
+    let mut roles = Roles::new();
+    for RoleArg { address, role } in args {
+        roles.entry(address).or_default().insert(role);
+    }
+    roles
+}
+
+fn create_args(opts: CreateOpt) -> account::CreateArgs {
+    let roles = role_map(opts.roles);
+    account::CreateArgs {
+        description: opts.description,
+        roles: if roles.is_empty() { None } else { Some(roles) },
+        features: opts.features.iter().map(|f| f.feature()).collect(),
+    }
+}
+
+fn create(client: ManyClient<impl Identity>, opts: CreateOpt) -> Result<(), ManyError> {
+    let response = crate::transport::call(&client, "account.create", create_args(opts))?;
+    let payload = crate::wait_response(&client, response)?;
+    let result: account::CreateReturn =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    info!("Account created.");
+    println!("{}", result.id);
+    Ok(())
+}
+
+/// The account a subresource belongs to, and the ID of the subresource.
+fn parent(address: &Address) -> Option<(Address, u32)> {
+    let id = address.subresource_id()?;
+    let bytes = address.to_vec();
+    let hash = bytes.get(1..29)?;
+    let parent = Address::from_bytes(&[&[1u8][..], hash].concat()).ok()?;
+    if parent.with_subresource_id(id).ok()? == *address {
+        Some((parent, id))
+    } else {
+        None
+    }
+}
+
+fn feature_name(feature: &account::features::Feature) -> String {
+    let id = feature.id();
+    if id == account::features::ledger::AccountLedger::ID {
+        "ledger".to_string()
+    } else if id == account::features::multisig::MultisigAccountFeature::ID {
+        "multisig".to_string()
+    } else {
+        format!("unknown feature {}", id)
+    }
+}
+
+fn roles_json(roles: &Roles) -> serde_json::Value {
+    serde_json::Value::Object(
+        roles
+            .iter()
+            .map(|(id, roles)| {
+                (
+                    id.to_string(),
+                    roles.iter().map(|r| r.to_string()).collect(),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// The account, its features with their arguments and the roles of each
+/// identity, one per line.
+fn format_info(account: &Address, info: &account::InfoReturn) -> String {
+    let mut lines = vec![format!("Account:     {}", account)];
+    if let Some((parent, id)) = parent(account) {
+        lines.push(format!("Parent:      {}", parent));
+        lines.push(format!("Subresource: {}", id));
+    }
+    lines.push(format!(
+        "Description: {}",
+        info.description.as_deref().unwrap_or("(none)")
+    ));
+
+    if info.features.iter().next().is_none() {
+        lines.push("Features:    (none)".to_string());
+    } else {
+        lines.push("Features:".to_string());
+    }
+    for feature in info.features.iter() {
+        lines.push(format!("  {}", feature_name(feature)));
+    }
+    if let Ok(multisig) = info
+        .features
+        .get::<account::features::multisig::MultisigAccountFeature>()
+    {
+        let arg = multisig.arg;
+        let or_unset = |value: Option<String>| value.unwrap_or_else(|| "not set".to_string());
+        lines.push(format!(
+            "    Threshold:             {}",
+            or_unset(arg.threshold.map(|t| t.to_string()))
+        ));
+        lines.push(format!(
+            "    Timeout:               {}",
+            or_unset(arg.timeout_in_secs.map(|t| {
+                humantime::format_duration(std::time::Duration::from_secs(t)).to_string()
+            }))
+        ));
+        lines.push(format!(
+            "    Execute automatically: {}",
+            or_unset(
+                arg.execute_automatically
+                    .map(|e| if e { "yes" } else { "no" }.to_string())
+            )
+        ));
+    }
+
+    lines.push("Roles:".to_string());
+    lines.extend(
+        format_roles_table(&info.roles)
+            .lines()
+            .map(|l| format!("  {}", l)),
+    );
+    lines.join("\n")
+}
+
+fn info_json(account: &Address, info: &account::InfoReturn) -> serde_json::Value {
+    let multisig = info
+        .features
+        .get::<account::features::multisig::MultisigAccountFeature>()
+        .ok()
+        .map(|f| f.arg);
+    let features: Vec<_> = info
+        .features
+        .iter()
+        .map(|feature| {
+            let mut value = serde_json::json!({
+                "id": feature.id(),
+                "name": feature_name(feature),
+            });
+            match &multisig {
+                Some(arg)
+                    if feature.id() == account::features::multisig::MultisigAccountFeature::ID =>
+                {
+                    value["threshold"] = serde_json::json!(arg.threshold);
+                    value["timeout_in_secs"] = serde_json::json!(arg.timeout_in_secs);
+                    value["execute_automatically"] = serde_json::json!(arg.execute_automatically);
+                }
+                _ => {}
+            }
+            value
+        })
+        .collect();
+    let parent = parent(account);
+    serde_json::json!({
+        "account": account.to_string(),
+        "parent": parent.map(|(p, _)| p.to_string()),
+        "subresource": parent.map(|(_, id)| id),
+        "description": info.description,
+        "features": features,
+        "roles": roles_json(&info.roles),
+    })
+}
+
+fn info(
+    client: ManyClient<impl Identity>,
+    account: Address,
+    since: Option<Since>,
+) -> Result<(), ManyError> {
+    let info = client.account_info(account)?;
+
+    if output::get() == OutputFormat::Json {
+        output::print_json(&info_json(&account, &info));
+        return Ok(());
+    }
+    println!("{}", format_info(&account, &info));
+
+    if let Some(since) = since {
+        let (after, since) = match since {
+            Since::Time(time) => (None, Some(time)),
+            Since::After(id) => (Some(id), None),
+        };
+        let events =
+            EventPages::with_kinds(&client, account, ACCOUNT_EVENT_KINDS.to_vec(), after, since)
+                .collect::<Result<Vec<_>, _>>()?;
+        println!();
+        println!("{}", format_changes(&info.roles, &events)?);
+    }
+    Ok(())
+}
+
+/// Whether removing roles leaves an account which has owners without any.
+/// Nobody can change its roles, features or description after that.
+fn removes_last_owner(current: &Roles, removed: &Roles) -> bool {
+    let owners = |roles: &Roles| -> BTreeSet<Address> {
+        roles
+            .iter()
+            .filter(|(_, roles)| roles.contains(&account::Role::Owner))
+            .map(|(id, _)| *id)
+            .collect()
+    };
+    let current = owners(current);
+    let removed = owners(removed);
+    !current.is_empty() && current.is_subset(&removed)
+}
+
+/// A table of the identities of an account and their roles.
+fn format_roles_table(roles: &Roles) -> String {
+    if roles.is_empty() {
+        return "The account has no roles.".to_string();
+    }
+    let width = roles
+        .keys()
+        .map(|id| id.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(8);
+    let mut lines = vec![format!("{:width$}  ROLES", "IDENTITY", width = width)];
+    for (id, roles) in roles {
+        lines.push(format!(
+            "{:width$}  {}",
+            id.to_string(),
+            format_role_set(roles),
+            width = width
+        ));
+    }
+    lines.join("\n")
+}
+
+fn add_roles(client: ManyClient<impl Identity>, opts: RolesOpt) -> Result<(), ManyError> {
+    let arguments = account::AddRolesArgs {
+        account: opts.account,
+        roles: role_map(opts.roles),
+    };
+    let response = crate::transport::call(&client, "account.addRoles", arguments)?;
+    crate::wait_response(&client, response)?;
+
+    info!("Roles added.");
+    Ok(())
+}
+
+fn remove_roles(client: ManyClient<impl Identity>, opts: RemoveRolesOpt) -> Result<(), ManyError> {
+    let account = opts.roles.account;
+    let roles = role_map(opts.roles.roles);
+    if !opts.yes && removes_last_owner(&client.account_info(account)?.roles, &roles) {
+        let question = format!(
+            "This removes the last owner of {}, and nobody will be able to change its roles, \
+             features or description afterwards. Remove it?",
+            account
+        );
+        let stdin = std::io::stdin();
+        let confirmed = crate::split::confirm(&question, stdin.lock(), std::io::stderr())
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        if !confirmed {
+            return Err(ManyError::unknown("Cancelled, no role was removed."));
+        }
+    }
+
+    let response = crate::transport::call(
+        &client,
+        "account.removeRoles",
+        account::RemoveRolesArgs { account, roles },
+    )?;
+    crate::wait_response(&client, response)?;
+
+    info!("Roles removed.");
+    Ok(())
+}
+
+fn list_roles(client: ManyClient<impl Identity>, opts: ListRolesOpt) -> Result<(), ManyError> {
+    let roles = client.account_info(opts.account)?.roles;
+    match output::get() {
+        OutputFormat::Human => println!("{}", format_roles_table(&roles)),
+        OutputFormat::Json => output::print_json(&roles_json(&roles)),
+    }
+    Ok(())
+}
+
+fn set_recovery(client: ManyClient<impl Identity>, opts: SetRecoveryOpt) -> Result<(), ManyError> {
+    let SetRecoveryOpt {
+        account,
+        recovery,
+        inactivity,
+        challenge,
+    } = opts;
+    let arguments = SetRecoveryArgs {
+        account,
+        recovery,
+        inactivity_secs: inactivity.as_secs(),
+        challenge_secs: challenge.as_secs(),
+    };
+    let response = crate::transport::call(&client, "account.setRecovery", arguments)?;
+    crate::wait_response(&client, response)?;
+
+    info!("Recovery set.");
+    Ok(())
+}
+
+fn recover(client: ManyClient<impl Identity>, opts: RecoverOpt) -> Result<(), ManyError> {
+    let response = crate::transport::call(
+        &client,
+        "account.recover",
+        RecoverArgs {
+            account: opts.account,
+        },
+    )?;
+    let payload = crate::wait_response(&client, response)?;
+    let result: RecoverReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    match result.completable {
+        Some(completable) if !result.recovered => println!(
+            "Claim opened. Call again after {} to complete the recovery, unless an owner vetoes it.",
+            humantime::format_rfc3339_seconds(completable.as_system_time()?)
+        ),
+        _ => println!("Recovered {}.", opts.account),
+    }
+    Ok(())
+}
+
+pub fn account(client: ManyClient<impl Identity>, opts: CommandOpt) -> Result<(), ManyError> {
+    match opts.subcommand {
+        SubcommandOpt::Create(sub_opts) => create(client, sub_opts),
+        SubcommandOpt::Info(InfoOpt { account, since }) => info(client, account, since),
+        SubcommandOpt::AddRoles(sub_opts) => add_roles(client, sub_opts),
+        SubcommandOpt::RemoveRoles(sub_opts) => remove_roles(client, sub_opts),
+        SubcommandOpt::Roles(sub_opts) => list_roles(client, sub_opts),
+        SubcommandOpt::SetRecovery(sub_opts) => set_recovery(client, sub_opts),
+        SubcommandOpt::Recover(sub_opts) => recover(client, sub_opts),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use account::Role;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn roles(entries: &[(u32, &[Role])]) -> Roles {
+        entries
+            .iter()
+            .map(|(i, roles)| (address(*i), roles.iter().copied().collect()))
+            .collect()
+    }
+
+    fn log(i: u8, content: events::EventInfo) -> events::EventLog {
+        events::EventLog {
+            id: events::EventId::from(vec![i]),
+            time: Timestamp::new(1_000 + i as u64).unwrap(),
+            content,
+        }
+    }
+
+    fn add(i: u8, entries: &[(u32, &[Role])]) -> events::EventLog {
+        log(
+            i,
+            events::EventInfo::AccountAddRoles {
+                account: address(0),
+                roles: roles(entries),
+            },
+        )
+    }
+
+    fn remove(i: u8, entries: &[(u32, &[Role])]) -> events::EventLog {
+        log(
+            i,
+            events::EventInfo::AccountRemoveRoles {
+                account: address(0),
+                roles: roles(entries),
+            },
+        )
+    }
+
+    /// An account whose approver changed back and forth.
+    fn history() -> Vec<events::EventLog> {
+        vec![
+            log(
+                1,
+                events::EventInfo::AccountCreate {
+                    account: address(0),
+                    description: Some("Treasury".to_string()),
+                    roles: roles(&[(0, &[Role::Owner]), (1, &[Role::Owner])]),
+                    features: account::features::FeatureSet::default(),
+                },
+            ),
+            add(2, &[(2, &[Role::CanMultisigApprove])]),
+            remove(3, &[(2, &[Role::CanMultisigApprove])]),
+            add(4, &[(2, &[Role::CanMultisigSubmit]), (3, &[Role::Owner])]),
+            remove(5, &[(1, &[Role::Owner])]),
+            add(6, &[(2, &[Role::CanMultisigApprove])]),
+        ]
+    }
+
+    fn current() -> Roles {
+        roles(&[
+            (0, &[Role::Owner]),
+            (2, &[Role::CanMultisigApprove, Role::CanMultisigSubmit]),
+            (3, &[Role::Owner]),
+        ])
+    }
+
+    struct Accounts(BTreeMap<Address, Roles>);
+
+    impl AccountSource for Accounts {
+        fn account_info(&self, account: Address) -> Result<account::InfoReturn, ManyError> {
+            let roles = self
+                .0
+                .get(&account)
+                .cloned()
+                .ok_or_else(|| account::errors::unknown_account(account))?;
+            Ok(account::InfoReturn {
+                description: None,
+                roles,
+                features: account::features::FeatureSet::default(),
+                disabled: None,
+            })
+        }
+    }
+
+    fn accounts() -> Accounts {
+        Accounts(BTreeMap::from([(address(0), current())]))
+    }
+
+    #[test]
+    fn signer_match() {
+        let accounts = accounts();
+        assert!(check_signer(&accounts, address(0), address(3), &SEND_ROLES, false).is_ok());
+        assert!(check_signer(&accounts, address(0), address(0), &SEND_ROLES, false).is_ok());
+        assert!(check_signer(
+            &accounts,
+            address(0),
+            address(2),
+            &[Role::CanMultisigSubmit],
+            false
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn signer_mismatch() {
+        let accounts = accounts();
+        let message = check_signer(&accounts, address(0), address(2), &SEND_ROLES, false)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains(&address(2).to_string()));
+        assert!(message.contains("canMultisigApprove"));
+        assert!(message.contains("canMultisigSubmit"));
+        assert!(message.contains("--force"));
+
+        let message = check_signer(&accounts, address(0), address(9), &SEND_ROLES, false)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("(none)"));
+
+        assert!(check_signer(&accounts, address(0), address(2), &SEND_ROLES, true).is_ok());
+    }
+
+    #[test]
+    fn signer_account_not_found() {
+        let accounts = accounts();
+        let message = check_signer(&accounts, address(8), address(3), &SEND_ROLES, false)
+            .unwrap_err()
+            .to_string();
+        assert!(message.starts_with(&format!(
+            "Could not read the roles of the account {}",
+            address(8)
+        )));
+        assert!(check_signer(&accounts, address(8), address(3), &SEND_ROLES, true).is_ok());
+    }
+
+    #[test]
+    fn parse_since() {
+        assert_eq!(
+            Since::from_str("1970-01-01T00:16:40Z"),
+            Ok(Since::Time(Timestamp::new(1_000).unwrap()))
+        );
+        assert_eq!(
+            Since::from_str("1970-01-02"),
+            Ok(Since::Time(Timestamp::new(86_400).unwrap()))
+        );
+        assert_eq!(
+            Since::from_str("0000000c00000001"),
+            Ok(Since::After(events::EventId::from(vec![
+                0, 0, 0, 12, 0, 0, 0, 1
+            ])))
+        );
+        assert!(Since::from_str("last month").is_err());
+    }
+
+    #[test]
+    fn reconstruct() {
+        let events = history();
+
+        // Before each event, the roles are the ones of the scripted history.
+        let expected = [
+            roles(&[]),
+            roles(&[(0, &[Role::Owner]), (1, &[Role::Owner])]),
+            roles(&[
+                (0, &[Role::Owner]),
+                (1, &[Role::Owner]),
+                (2, &[Role::CanMultisigApprove]),
+            ]),
+            roles(&[(0, &[Role::Owner]), (1, &[Role::Owner])]),
+            roles(&[
+                (0, &[Role::Owner]),
+                (1, &[Role::Owner]),
+                (2, &[Role::CanMultisigSubmit]),
+                (3, &[Role::Owner]),
+            ]),
+            roles(&[
+                (0, &[Role::Owner]),
+                (2, &[Role::CanMultisigSubmit]),
+                (3, &[Role::Owner]),
+            ]),
+            current(),
+        ];
+        for (i, expected) in expected.iter().enumerate() {
+            assert_eq!(&roles_before(&current(), &events[i..]), expected, "{}", i);
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let events = history();
+        let output = format_changes(&current(), &events[2..]).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+
+        assert_eq!(lines[0], "Changes:");
+        assert!(lines[1].ends_with(&format!(
+            "03 Roles removed: {} (canMultisigApprove)",
+            address(2)
+        )));
+        assert_eq!(lines.len(), 1 + 4 + 1 + 4);
+        assert_eq!(lines[5], "Roles:");
+        assert!(lines[6..].contains(&format!("  {}: owner (unchanged)", address(0)).as_str()));
+        assert!(lines[6..].contains(&format!("  {}: owner -> (none)", address(1)).as_str()));
+        assert!(lines[6..].contains(&format!("  {}: (none) -> owner", address(3)).as_str()));
+    }
+
+    #[test]
+    fn no_changes() {
+        let output = format_changes(&current(), &[]).unwrap();
+        assert!(output.starts_with("Changes:\n  (none)\nRoles:\n"));
+        assert!(!output.contains("->"));
+    }
+
+    #[test]
+    fn role_arg() {
+        assert_eq!(
+            RoleArg::from_str(&format!("{}:canMultisigApprove", address(2))),
+            Ok(RoleArg {
+                address: address(2),
+                role: Role::CanMultisigApprove,
+            })
+        );
+        assert!(RoleArg::from_str(&format!("{}:canFly", address(2)))
+            .unwrap_err()
+            .starts_with("Unknown role 'canFly', expected one of "));
+        assert!(RoleArg::from_str("owner")
+            .unwrap_err()
+            .contains("<address>:<role>"));
+        assert!(RoleArg::from_str("nope:owner")
+            .unwrap_err()
+            .starts_with("Invalid address 'nope'"));
+
+        // Every role known is parsed back from its name.
+        for role in ROLES {
+            assert_eq!(parse_role(&role.to_string()), Ok(role));
+        }
+    }
+
+    #[test]
+    fn create() {
+        let opts = CreateOpt::try_parse_from([
+            "create".to_string(),
+            "--description".to_string(),
+            "Treasury".to_string(),
+            "--role".to_string(),
+            format!("{}:canMultisigSubmit", address(2)),
+            "--role".to_string(),
+            format!("{}:canMultisigApprove", address(2)),
+            "--role".to_string(),
+            format!("{}:canMultisigApprove", address(3)),
+            "--feature".to_string(),
+            "ledger".to_string(),
+            "--feature".to_string(),
+            "multisig".to_string(),
+        ])
+        .unwrap();
+        let args = create_args(opts);
+        assert_eq!(args.description.as_deref(), Some("Treasury"));
+        assert_eq!(
+            args.roles,
+            Some(roles(&[
+                (2, &[Role::CanMultisigSubmit, Role::CanMultisigApprove]),
+                (3, &[Role::CanMultisigApprove]),
+            ]))
+        );
+        assert_eq!(format_features(&args.features), "0, 1");
+
+        let args = create_args(CreateOpt::try_parse_from(["create"]).unwrap());
+        assert_eq!(args.roles, None);
+        assert_eq!(format_features(&args.features), "");
+    }
+
+    #[test]
+    fn last_owner() {
+        let current = roles(&[
+            (0, &[Role::Owner]),
+            (1, &[Role::Owner, Role::CanMultisigApprove]),
+            (2, &[Role::CanLedgerTransact]),
+        ]);
+
+        // Another owner remains.
+        assert!(!removes_last_owner(
+            &current,
+            &roles(&[(0, &[Role::Owner])])
+        ));
+        assert!(!removes_last_owner(
+            &current,
+            &roles(&[
+                (1, &[Role::CanMultisigApprove]),
+                (2, &[Role::CanLedgerTransact])
+            ])
+        ));
+
+        // Both owners at once.
+        assert!(removes_last_owner(
+            &current,
+            &roles(&[(0, &[Role::Owner]), (1, &[Role::Owner])])
+        ));
+
+        let current = roles(&[(0, &[Role::Owner])]);
+        assert!(removes_last_owner(&current, &roles(&[(0, &[Role::Owner])])));
+        assert!(!removes_last_owner(
+            &current,
+            &roles(&[(3, &[Role::Owner])])
+        ));
+
+        // An account without owners cannot lose its last one.
+        assert!(!removes_last_owner(
+            &Roles::new(),
+            &roles(&[(0, &[Role::Owner])])
+        ));
+    }
+
+    #[test]
+    fn roles_options() {
+        let opts = RemoveRolesOpt::try_parse_from([
+            "remove-roles".to_string(),
+            address(0).to_string(),
+            format!("{}:owner", address(1)),
+            format!("{}:canLedgerTransact", address(1)),
+            "--yes".to_string(),
+        ])
+        .unwrap();
+        assert!(opts.yes);
+        assert_eq!(opts.roles.account, address(0));
+        assert_eq!(
+            role_map(opts.roles.roles),
+            roles(&[(1, &[Role::Owner, Role::CanLedgerTransact])])
+        );
+
+        // At least one role.
+        assert!(
+            RolesOpt::try_parse_from(["add-roles".to_string(), address(0).to_string()]).is_err()
+        );
+    }
+
+    #[test]
+    fn roles_table() {
+        let table = format_roles_table(&roles(&[
+            (0, &[Role::Owner]),
+            (2, &[Role::CanMultisigApprove, Role::CanMultisigSubmit]),
+        ]));
+        let width = address(0).to_string().len();
+        assert_eq!(
+            table,
+            format!(
+                "{:width$}  ROLES\n{}  owner\n{}  canMultisigSubmit, canMultisigApprove",
+                "IDENTITY",
+                address(0),
+                address(2),
+                width = width
+            )
+        );
+        assert_eq!(
+            format_roles_table(&Roles::new()),
+            "The account has no roles."
+        );
+    }
+
+    #[test]
+    fn subresource_parent() {
+        let root = Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap();
+        assert_eq!(parent(&address(3)), Some((root, 3)));
+        assert_eq!(parent(&address(0)), Some((root, 0)));
+        assert_eq!(parent(&root), None);
+        assert_eq!(parent(&Address::anonymous()), None);
+    }
+
+    #[test]
+    fn info_format() {
+        let info = account::InfoReturn {
+            description: Some("Treasury".to_string()),
+            roles: roles(&[(0, &[Role::Owner]), (2, &[Role::CanMultisigApprove])]),
+            features: account::features::FeatureSet::from_iter([
+                account::features::Feature::with_id(account::features::ledger::AccountLedger::ID),
+                account::features::multisig::MultisigAccountFeature::create(
+                    Some(2),
+                    Some(86_400),
+                    Some(false),
+                )
+                .as_feature(),
+            ]),
+            disabled: None,
+        };
+        let width = address(0).to_string().len();
+        assert_eq!(
+            format_info(&address(1), &info),
+            format!(
+                "Account:     {}\n\
+                 Parent:      mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow\n\
+                 Subresource: 1\n\
+                 Description: Treasury\n\
+                 Features:\n\
+                 \x20 ledger\n\
+                 \x20 multisig\n\
+                 \x20   Threshold:             2\n\
+                 \x20   Timeout:               1day\n\
+                 \x20   Execute automatically: no\n\
+                 Roles:\n\
+                 \x20 {:width$}  ROLES\n\
+                 \x20 {}  owner\n\
+                 \x20 {}  canMultisigApprove",
+                address(1),
+                "IDENTITY",
+                address(0),
+                address(2),
+                width = width
+            )
+        );
+
+        let json = info_json(&address(1), &info);
+        assert_eq!(json["subresource"], 1);
+        assert_eq!(
+            json["parent"],
+            "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow"
+        );
+        assert_eq!(json["features"][0]["name"], "ledger");
+        assert!(json["features"][0].get("threshold").is_none());
+        assert_eq!(json["features"][1]["name"], "multisig");
+        assert_eq!(json["features"][1]["threshold"], 2);
+        assert_eq!(json["features"][1]["timeout_in_secs"], 86_400);
+        assert_eq!(json["features"][1]["execute_automatically"], false);
+        assert_eq!(
+            json["roles"][address(2).to_string()][0],
+            "canMultisigApprove"
+        );
+    }
+
+    #[test]
+    fn info_without_features() {
+        let root = Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap();
+        let info = account::InfoReturn {
+            description: None,
+            roles: Roles::new(),
+            features: account::features::FeatureSet::default(),
+            disabled: None,
+        };
+        assert_eq!(
+            format_info(&root, &info),
+            format!(
+                "Account:     {}\n\
+                 Description: (none)\n\
+                 Features:    (none)\n\
+                 Roles:\n\
+                 \x20 The account has no roles.",
+                root
+            )
+        );
+        assert_eq!(info_json(&root, &info)["parent"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,382 @@
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::Address;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// The path of the aliases file, set once by `init` before the arguments are
+/// parsed, as they can hold aliases.
+static PATH: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+/// The aliases, read from `PATH` on first use.
+static ALIASES: OnceCell<Result<Aliases, String>> = OnceCell::new();
+
+/// The warnings of the arguments parsed before logging is set up, logged by
+/// `log_warnings`. Later ones are logged right away.
+static WARNINGS: Lazy<Mutex<Option<Vec<String>>>> = Lazy::new(|| Mutex::new(Some(vec![])));
+
+/// Short names for addresses, kept in a JSON5 file, e.g.
+///
+/// ```json5
+/// {
+///     alice: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Aliases(BTreeMap<String, Address>);
+
+impl Aliases {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let entries: BTreeMap<String, String> =
+            json5::from_str(content).map_err(|e| e.to_string())?;
+        entries
+            .into_iter()
+            .map(|(name, address)| {
+                let address = Address::from_str(&address).map_err(|e| {
+                    format!("Invalid address '{}' for alias '{}': {}", address, name, e)
+                })?;
+                Ok((name, address))
+            })
+            .collect::<Result<_, String>>()
+            .map(Self)
+    }
+
+    /// Read the aliases of a file. A missing file has none.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content)
+                .map_err(|e| format!("Invalid aliases file {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Could not read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Write the aliases to a file, as JSON, which is valid JSON5.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let entries: BTreeMap<&str, String> = self
+            .0
+            .iter()
+            .map(|(name, address)| (name.as_str(), address.to_string()))
+            .collect();
+        let content = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+        std::fs::write(path, content + "\n")
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Address> {
+        self.0.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Address)> {
+        self.0.iter()
+    }
+
+    /// Add or replace an alias. Returns the address it replaced.
+    pub fn insert(&mut self, name: &str, address: Address) -> Result<Option<Address>, String> {
+        if name.is_empty() || name.chars().any(char::is_whitespace) {
+            return Err(format!(
+                "Invalid alias '{}': it cannot be empty or contain spaces.",
+                name
+            ));
+        }
+        Ok(self.0.insert(name.to_string(), address))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Address> {
+        self.0.remove(name)
+    }
+
+    /// The address of an argument, which is either an address or an alias.
+    /// An alias which is also a valid address is ignored in favor of the
+    /// address, with a warning.
+    pub fn resolve(&self, s: &str) -> Result<(Address, Option<String>), String> {
+        match (Address::from_str(s), self.get(s)) {
+            (Ok(address), None) => Ok((address, None)),
+            (Ok(address), Some(aliased)) => Ok((
+                address,
+                Some(format!(
+                    "'{}' is both an address and an alias of {}, using the address.",
+                    s, aliased
+                )),
+            )),
+            (Err(_), Some(aliased)) => Ok((aliased, None)),
+            (Err(e), None) => Err(format!(
+                "Invalid address '{}', which is not an alias either: {}",
+                s, e
+            )),
+        }
+    }
+}
+
+/// The default path of the aliases file.
+pub fn default_path(env: &dyn Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let base = match env("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env("HOME")?).join(".config"),
+    };
+    Some(base.join("many").join("aliases.json5"))
+}
+
+/// The value of the `--aliases` flag, read from the command line before it
+/// is parsed. Arguments after `--` are not flags.
+pub fn path_flag(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter().skip(1);
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy().into_owned();
+        if arg == "--" {
+            break;
+        } else if arg == "--aliases" {
+            path = args.next().map(PathBuf::from);
+        } else if let Some(value) = arg.strip_prefix("--aliases=") {
+            path = Some(PathBuf::from(value));
+        }
+    }
+    path
+}
+
+/// Set the path of the aliases file, or the default one.
+pub fn init(path: Option<PathBuf>) {
+    PATH.set(path.or_else(|| default_path(&|name| std::env::var(name).ok())))
+        .expect("The aliases file was already set.");
+}
+
+fn path() -> Result<&'static Path, String> {
+    PATH.get()
+        .and_then(Option::as_deref)
+        .ok_or_else(|| "No aliases file, use --aliases.".to_string())
+}
+
+fn aliases() -> Result<&'static Aliases, String> {
+    ALIASES
+        .get_or_init(|| match PATH.get() {
+            Some(Some(path)) => Aliases::load(path),
+            _ => Ok(Aliases::default()),
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+/// Parse an address argument, which can be an alias.
+pub fn address(s: &str) -> Result<Address, String> {
+    // An invalid aliases file does not prevent using addresses.
+    if let (Ok(address), Err(_)) = (Address::from_str(s), aliases()) {
+        return Ok(address);
+    }
+    let (address, warning) = aliases()?.resolve(s)?;
+    if let Some(warning) = warning {
+        match WARNINGS.lock().unwrap().as_mut() {
+            Some(warnings) => warnings.push(warning),
+            None => warn!("{}", warning),
+        }
+    }
+    Ok(address)
+}
+
+/// Log the warnings of the arguments, once logging is set up.
+pub fn log_warnings() {
+    for warning in WARNINGS.lock().unwrap().take().unwrap_or_default() {
+        warn!("{}", warning);
+    }
+}
+
+#[derive(Parser)]
+pub struct CommandOpt {
+    #[clap(subcommand)]
+    /// Alias subcommand to execute.
+    subcommand: SubcommandOpt,
+}
+
+#[derive(Parser)]
+enum SubcommandOpt {
+    /// Give a short name to an address, to use instead of it in arguments.
+    Add(AddOpt),
+
+    /// Remove an alias.
+    Rm(RmOpt),
+
+    /// List the aliases and their addresses.
+    List,
+}
+
+#[derive(Parser)]
+struct AddOpt {
+    /// The alias.
+    name: String,
+
+    /// The address it stands for.
+    address: Address,
+
+    /// Replace the alias if it exists.
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Parser)]
+struct RmOpt {
+    /// The alias to remove.
+    name: String,
+}
+
+pub fn alias(opts: CommandOpt) -> Result<(), ManyError> {
+    let path = path().map_err(ManyError::unknown)?;
+    let mut aliases = Aliases::load(path).map_err(ManyError::unknown)?;
+
+    match opts.subcommand {
+        SubcommandOpt::Add(AddOpt {
+            name,
+            address,
+            force,
+        }) => {
+            if let Some(existing) = aliases.get(&name).filter(|_| !force) {
+                return Err(ManyError::unknown(format!(
+                    "The alias '{}' is already {}, use --force to replace it.",
+                    name, existing
+                )));
+            }
+            if Address::from_str(&name).is_ok() {
+                warn!(
+                    "'{}' is a valid address, which is used instead of the alias.",
+                    name
+                );
+            }
+            aliases.insert(&name, address).map_err(ManyError::unknown)?;
+            aliases.save(path).map_err(ManyError::unknown)?;
+            info!("Added the alias '{}' of {}.", name, address);
+        }
+        SubcommandOpt::Rm(RmOpt { name }) => {
+            aliases
+                .remove(&name)
+                .ok_or_else(|| ManyError::unknown(format!("Unknown alias '{}'.", name)))?;
+            aliases.save(path).map_err(ManyError::unknown)?;
+            info!("Removed the alias '{}'.", name);
+        }
+        SubcommandOpt::List => {
+            if crate::output::get() == crate::output::OutputFormat::Json {
+                crate::output::print_json(&serde_json::json!(aliases
+                    .iter()
+                    .map(|(name, address)| (name.clone(), address.to_string()))
+                    .collect::<BTreeMap<_, _>>()));
+                return Ok(());
+            }
+            let width = aliases.iter().map(|(name, _)| name.len()).max();
+            for (name, address) in aliases.iter() {
+                println!("{:width$}  {}", name, address, width = width.unwrap_or(0));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn resolve() {
+        let mut aliases = Aliases::default();
+        aliases.insert("alice", address(1)).unwrap();
+        let literal = address(2).to_string();
+        aliases.insert(&literal, address(3)).unwrap();
+
+        assert_eq!(aliases.resolve("alice"), Ok((address(1), None)));
+        assert_eq!(
+            aliases.resolve(&address(4).to_string()),
+            Ok((address(4), None))
+        );
+        let (resolved, warning) = aliases.resolve(&literal).unwrap();
+        assert_eq!(resolved, address(2));
+        assert!(warning.unwrap().contains("using the address"));
+        assert!(aliases
+            .resolve("bob")
+            .unwrap_err()
+            .starts_with("Invalid address 'bob', which is not an alias"));
+
+        assert!(aliases.insert("", address(1)).is_err());
+        assert!(aliases.insert("a b", address(1)).is_err());
+        assert_eq!(aliases.insert("alice", address(5)), Ok(Some(address(1))));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("many").join("aliases.json5");
+        assert_eq!(Aliases::load(&path), Ok(Aliases::default()));
+
+        let mut aliases = Aliases::default();
+        aliases.insert("alice", address(1)).unwrap();
+        aliases.insert("bob", address(2)).unwrap();
+        aliases.save(&path).unwrap();
+        assert_eq!(Aliases::load(&path), Ok(aliases.clone()));
+
+        aliases.remove("alice");
+        aliases.save(&path).unwrap();
+        assert_eq!(Aliases::load(&path).unwrap().get("alice"), None);
+
+        let content = format!("{{\n  // Comment\n  carol: '{}',\n}}", address(3));
+        assert_eq!(
+            Aliases::parse(&content).unwrap().get("carol"),
+            Some(address(3))
+        );
+        assert!(Aliases::parse("{ carol: 'nope' }")
+            .unwrap_err()
+            .contains("'carol'"));
+    }
+
+    #[test]
+    fn flag() {
+        assert_eq!(path_flag(args(&["ledger", "balance"])), None);
+        assert_eq!(
+            path_flag(args(&["ledger", "--aliases", "a.json5", "balance"])),
+            Some(PathBuf::from("a.json5"))
+        );
+        assert_eq!(
+            path_flag(args(&["ledger", "--aliases=a.json5", "send"])),
+            Some(PathBuf::from("a.json5"))
+        );
+        assert_eq!(
+            path_flag(args(&["ledger", "balance", "--", "--aliases=a.json5"])),
+            None
+        );
+    }
+
+    #[test]
+    fn default() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert_eq!(
+            default_path(&env(&[("HOME", "/home/me")])),
+            Some(PathBuf::from("/home/me/.config/many/aliases.json5"))
+        );
+        assert_eq!(
+            default_path(&env(&[("HOME", "/home/me"), ("XDG_CONFIG_HOME", "/xdg")])),
+            Some(PathBuf::from("/xdg/many/aliases.json5"))
+        );
+        assert_eq!(default_path(&env(&[])), None);
+    }
+}
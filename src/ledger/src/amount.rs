@@ -0,0 +1,669 @@
+use many_error::ManyError;
+use num_bigint::BigUint;
+use once_cell::sync::OnceCell;
+use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The number format of the amounts typed on the command line, set once by
+/// `init`.
+static FORMAT: OnceCell<NumberFormat> = OnceCell::new();
+
+/// The languages whose locales use a comma as the decimal separator.
+const COMMA_DECIMAL_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "is",
+    "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr",
+    "uk", "vi",
+];
+
+/// The locales of these languages which use a dot as the decimal separator
+/// instead.
+const DOT_DECIMAL_LOCALES: &[&str] = &["de_CH", "de_LI", "es_MX", "es_US", "it_CH"];
+
+/// The characters grouping digits in the `CommaDecimal` format, including
+/// the no-break spaces some locales use.
+const SPACES: &[char] = &[' ', '\u{a0}', '\u{202f}'];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// A dot as the decimal separator and no digit grouping, e.g. `1500.25`.
+    /// This is the default, which refuses anything else.
+    Plain,
+
+    /// A dot as the decimal separator and commas grouping digits, e.g.
+    /// `1,500.25`.
+    DotDecimal,
+
+    /// A comma as the decimal separator and dots or spaces grouping digits,
+    /// e.g. `1.500,25` or `1 500,25`.
+    CommaDecimal,
+}
+
+impl NumberFormat {
+    /// The number format of a locale name such as `de_DE.UTF-8`. Unknown
+    /// locales, `C` and `POSIX` use a dot as the decimal separator.
+    pub fn from_locale(name: &str) -> Self {
+        let name = name.split(&['.', '@'][..]).next().unwrap_or_default();
+        let language = name.split(&['_', '-'][..]).next().unwrap_or_default();
+
+        if DOT_DECIMAL_LOCALES.contains(&name.replace('-', "_").as_str()) {
+            NumberFormat::DotDecimal
+        } else if COMMA_DECIMAL_LANGUAGES.contains(&language.to_lowercase().as_str()) {
+            NumberFormat::CommaDecimal
+        } else {
+            NumberFormat::DotDecimal
+        }
+    }
+
+    /// The number format of the locale of the environment, from `LC_ALL`,
+    /// then `LC_NUMERIC`, then `LANG`.
+    pub fn detect(env: &dyn Fn(&str) -> Option<String>) -> Self {
+        ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| env(name))
+            .find(|value| !value.is_empty())
+            .map_or(NumberFormat::DotDecimal, |value| Self::from_locale(&value))
+    }
+
+    fn separators(self) -> (char, &'static [char]) {
+        match self {
+            NumberFormat::Plain => ('.', &[]),
+            NumberFormat::DotDecimal => ('.', &[',']),
+            NumberFormat::CommaDecimal => (',', &['.', ' ', '\u{a0}', '\u{202f}']),
+        }
+    }
+}
+
+impl Display for NumberFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NumberFormat::Plain => "a dot as the decimal separator and no digit grouping",
+            NumberFormat::DotDecimal => "a dot as the decimal separator and commas grouping digits",
+            NumberFormat::CommaDecimal => {
+                "a comma as the decimal separator and dots or spaces grouping digits"
+            }
+        })
+    }
+}
+
+/// A decimal number, without its digit grouping.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decimal {
+    /// The digits before the decimal separator.
+    pub integer: String,
+
+    /// The digits after the decimal separator, if any.
+    pub fraction: String,
+}
+
+impl Decimal {
+    /// The amount in base units of a token, for amounts without decimals.
+    pub fn to_base_units(&self) -> Result<BigUint, String> {
+        if !self.fraction.is_empty() {
+            return Err(format!(
+                "Invalid amount '{}': amounts are in base units of the token and cannot have \
+                 decimals.",
+                self
+            ));
+        }
+        BigUint::from_str(&self.integer).map_err(|e| e.to_string())
+    }
+
+    /// The amount in base units of a token with `decimals` digits after the
+    /// decimal separator, e.g. `1.5` is 1500000000 base units of a token
+    /// with 9 decimals. Trailing zeros of the decimals are ignored, any
+    /// other decimal the token cannot represent is refused rather than
+    /// rounded.
+    pub fn scale(&self, decimals: u64) -> Result<BigUint, String> {
+        let fraction = self.fraction.trim_end_matches('0');
+        if fraction.len() as u64 > decimals {
+            return Err(if decimals == 0 {
+                format!(
+                    "Invalid amount '{}': the token has no decimals, amounts are whole numbers.",
+                    self
+                )
+            } else {
+                format!(
+                    "Invalid amount '{}': the token has {} decimals, the smallest amount is \
+                     0.{:0>width$}.",
+                    self,
+                    decimals,
+                    1,
+                    width = decimals as usize
+                )
+            });
+        }
+        let digits = format!(
+            "{}{:0<width$}",
+            self.integer,
+            fraction,
+            width = decimals as usize
+        );
+        BigUint::from_str(&digits).map_err(|e| e.to_string())
+    }
+}
+
+/// Prints the number with a dot as the decimal separator, without leading
+/// zeros nor trailing zeros of the decimals.
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let integer = self.integer.trim_start_matches('0');
+        let fraction = self.fraction.trim_end_matches('0');
+        f.write_str(if integer.is_empty() { "0" } else { integer })?;
+        if !fraction.is_empty() {
+            write!(f, ".{}", fraction)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Parse the digits before the decimal separator. The groups must be
+/// separated by the same character, and all have 3 digits except the first.
+fn parse_integer(s: &str, grouping: &[char]) -> Result<String, String> {
+    let separator = match s.chars().find(|c| grouping.contains(c)) {
+        Some(separator) => separator,
+        None if is_digits(s) => return Ok(s.to_string()),
+        None => {
+            return Err(match s.chars().find(|c| !c.is_ascii_digit()) {
+                Some(c) => format!("unexpected character '{}'", c),
+                None => "missing digits before the decimal separator".to_string(),
+            })
+        }
+    };
+    if let Some(c) = s.chars().find(|c| grouping.contains(c) && *c != separator) {
+        // Kinds of spaces are interchangeable, but not spaces and dots.
+        if !(SPACES.contains(&c) && SPACES.contains(&separator)) {
+            return Err(format!(
+                "digits are grouped with both '{}' and '{}'",
+                separator, c
+            ));
+        }
+    }
+
+    let groups: Vec<&str> = s.split(|c| grouping.contains(&c)).collect();
+    for (i, group) in groups.iter().enumerate() {
+        if !is_digits(group) {
+            return Err(match group.chars().find(|c| !c.is_ascii_digit()) {
+                Some(c) => format!("unexpected character '{}'", c),
+                None => "empty digit group".to_string(),
+            });
+        }
+        if i == 0 && (group.len() > 3 || group.starts_with('0')) {
+            return Err(format!(
+                "the first digit group '{}' must have 1 to 3 digits, without leading zeros",
+                group
+            ));
+        }
+        if i > 0 && group.len() != 3 {
+            return Err(format!(
+                "the digit group '{}' does not have 3 digits",
+                group
+            ));
+        }
+    }
+    Ok(groups.concat())
+}
+
+/// Parse a number strictly with the separators of a format.
+fn parse_with(s: &str, format: NumberFormat) -> Result<Decimal, String> {
+    let (decimal, grouping) = format.separators();
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty amount".to_string());
+    }
+    if s.starts_with('-') {
+        return Err("amounts cannot be negative".to_string());
+    }
+
+    let mut parts = s.split(decimal);
+    let integer = parts.next().unwrap_or_default();
+    let fraction = parts.next();
+    if parts.next().is_some() {
+        return Err(format!("more than one decimal separator '{}'", decimal));
+    }
+
+    let integer = parse_integer(integer, grouping)?;
+    let fraction = match fraction {
+        None => String::new(),
+        Some(fraction) if is_digits(fraction) => fraction.to_string(),
+        Some("") => return Err("missing digits after the decimal separator".to_string()),
+        Some(fraction) => {
+            return Err(match fraction.chars().find(|c| !c.is_ascii_digit()) {
+                Some(c) if grouping.contains(&c) => {
+                    format!("unexpected digit grouping '{}' in the decimals", c)
+                }
+                Some(c) => format!("unexpected character '{}'", c),
+                None => unreachable!(),
+            })
+        }
+    };
+    Ok(Decimal { integer, fraction })
+}
+
+fn suggestion(s: &str, readings: &[Decimal]) -> String {
+    let readings: Vec<String> = readings.iter().map(|d| d.to_string()).collect();
+    let (kind, hint) = match readings.as_slice() {
+        [reading] => ("Invalid", format!("Did you mean {}?", reading)),
+        readings => (
+            "Ambiguous",
+            format!("It could mean {}.", readings.join(" or ")),
+        ),
+    };
+    format!(
+        "{} amount '{}': amounts use {}. {} Pass --locale-numbers to use the number format of \
+         your locale instead.",
+        kind,
+        s,
+        NumberFormat::Plain,
+        hint
+    )
+}
+
+/// Parse a number typed by a user. Without a locale, only a dot decimal
+/// separator is accepted, and numbers which would read differently in
+/// another format, like `1.500`, are refused with the ways they could be
+/// read instead of guessing one.
+pub fn parse(s: &str, format: NumberFormat) -> Result<Decimal, String> {
+    if format != NumberFormat::Plain {
+        return parse_with(s, format)
+            .map_err(|e| format!("Invalid amount '{}' with {}: {}.", s, format, e));
+    }
+
+    let mut readings: Vec<Decimal> = Vec::new();
+    let plain = parse_with(s, NumberFormat::Plain);
+    if let Ok(plain) = &plain {
+        readings.push(plain.clone());
+    }
+    for format in [NumberFormat::DotDecimal, NumberFormat::CommaDecimal] {
+        if let Ok(reading) = parse_with(s, format) {
+            if !readings
+                .iter()
+                .any(|r| r.to_string() == reading.to_string())
+            {
+                readings.push(reading);
+            }
+        }
+    }
+
+    match plain {
+        Ok(plain) if readings.len() == 1 => Ok(plain),
+        Err(e) if readings.is_empty() => Err(format!("Invalid amount '{}': {}.", s, e)),
+        _ => Err(suggestion(s, &readings)),
+    }
+}
+
+/// Format an amount in base units of a token with `decimals` digits after
+/// the decimal separator, keeping all of them, and grouping the digits
+/// before it by 3, e.g. `12,345.678900000` for a token with 9 decimals.
+pub fn format_decimal(amount: &impl Display, decimals: u64, format: NumberFormat) -> String {
+    let (decimal, grouping) = match format {
+        NumberFormat::CommaDecimal => (',', '.'),
+        NumberFormat::Plain | NumberFormat::DotDecimal => ('.', ','),
+    };
+    let decimals = decimals as usize;
+    let digits = format!("{:0>width$}", amount.to_string(), width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+
+    let mut result = String::new();
+    for (i, c) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            result.push(grouping);
+        }
+        result.push(c);
+    }
+    if !fraction.is_empty() {
+        result.push(decimal);
+        result.push_str(fraction);
+    }
+    result
+}
+
+/// Format an amount for a human, in the number format of the amounts typed
+/// on the command line.
+pub fn format_units(amount: &impl Display, decimals: u64) -> String {
+    format_decimal(
+        amount,
+        decimals,
+        FORMAT.get().copied().unwrap_or(NumberFormat::Plain),
+    )
+}
+
+/// Set the number format of the amounts typed on the command line.
+pub fn init(format: NumberFormat) {
+    FORMAT
+        .set(format)
+        .expect("The number format was already set.");
+}
+
+/// An amount typed on the command line. It is parsed when used, once the
+/// number format of the `--locale-numbers` flag is known.
+#[derive(Clone, Debug)]
+pub struct AmountArg(String);
+
+impl FromStr for AmountArg {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl AmountArg {
+    fn parse(&self) -> Result<Decimal, ManyError> {
+        let format = FORMAT.get().copied().unwrap_or(NumberFormat::Plain);
+        parse(&self.0, format).map_err(ManyError::unknown)
+    }
+
+    /// The amount in base units of a token.
+    pub fn base_units(&self) -> Result<BigUint, ManyError> {
+        self.parse()?.to_base_units().map_err(ManyError::unknown)
+    }
+
+    /// The amount in base units of a token. Amounts with decimals, and all
+    /// amounts when `decimal` is set, are in whole tokens, and scaled with
+    /// the number of decimals of the token. These are only read when
+    /// needed.
+    pub fn scaled_units(
+        &self,
+        decimal: bool,
+        decimals: impl FnOnce() -> Result<u64, ManyError>,
+    ) -> Result<BigUint, ManyError> {
+        let amount = self.parse()?;
+        if decimal || !amount.fraction.is_empty() {
+            amount.scale(decimals()?).map_err(ManyError::unknown)
+        } else {
+            amount.to_base_units().map_err(ManyError::unknown)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(s: &str, format: NumberFormat) -> String {
+        parse(s, format).unwrap().to_string()
+    }
+
+    fn err(s: &str, format: NumberFormat) -> String {
+        parse(s, format).unwrap_err()
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(ok("1500", NumberFormat::Plain), "1500");
+        assert_eq!(ok(" 1500.25 ", NumberFormat::Plain), "1500.25");
+        assert_eq!(ok("1.5", NumberFormat::Plain), "1.5");
+        assert_eq!(ok("0.125", NumberFormat::Plain), "0.125");
+        assert_eq!(ok("1234.567", NumberFormat::Plain), "1234.567");
+        assert_eq!(ok("007", NumberFormat::Plain), "7");
+    }
+
+    #[test]
+    fn plain_ambiguous() {
+        // Dot grouping in a comma decimal locale.
+        assert_eq!(
+            err("1.500", NumberFormat::Plain),
+            "Ambiguous amount '1.500': amounts use a dot as the decimal separator and no digit \
+             grouping. It could mean 1.5 or 1500. Pass --locale-numbers to use the number format \
+             of your locale instead."
+        );
+        assert!(err("123.456", NumberFormat::Plain).contains("It could mean 123.456 or 123456."));
+
+        // A comma decimal separator, or comma grouping.
+        assert!(err("1,500", NumberFormat::Plain).contains("It could mean 1500 or 1.5."));
+        assert!(err("0,500", NumberFormat::Plain).contains("Did you mean 0.5?"));
+    }
+
+    #[test]
+    fn plain_suggestions() {
+        assert_eq!(
+            err("1,5", NumberFormat::Plain),
+            "Invalid amount '1,5': amounts use a dot as the decimal separator and no digit \
+             grouping. Did you mean 1.5? Pass --locale-numbers to use the number format of your \
+             locale instead."
+        );
+        assert!(err("1 500,25", NumberFormat::Plain).contains("Did you mean 1500.25?"));
+        assert!(err("1.500,25", NumberFormat::Plain).contains("Did you mean 1500.25?"));
+        assert!(err("1,500.25", NumberFormat::Plain).contains("Did you mean 1500.25?"));
+        assert!(err("1,500,000", NumberFormat::Plain).contains("Did you mean 1500000?"));
+        assert!(err("1.500.000", NumberFormat::Plain).contains("Did you mean 1500000?"));
+        assert!(err("1\u{a0}500", NumberFormat::Plain).contains("Did you mean 1500?"));
+    }
+
+    #[test]
+    fn plain_invalid() {
+        assert_eq!(
+            err("1,5,0", NumberFormat::Plain),
+            "Invalid amount '1,5,0': unexpected character ','."
+        );
+        assert_eq!(
+            err("", NumberFormat::Plain),
+            "Invalid amount '': empty amount."
+        );
+        assert_eq!(
+            err("-1", NumberFormat::Plain),
+            "Invalid amount '-1': amounts cannot be negative."
+        );
+        assert_eq!(
+            err("1.", NumberFormat::Plain),
+            "Invalid amount '1.': missing digits after the decimal separator."
+        );
+        assert_eq!(
+            err(".5", NumberFormat::Plain),
+            "Invalid amount '.5': missing digits before the decimal separator."
+        );
+        assert_eq!(
+            err("1e3", NumberFormat::Plain),
+            "Invalid amount '1e3': unexpected character 'e'."
+        );
+        assert!(err("1 5", NumberFormat::Plain).starts_with("Invalid amount '1 5': unexpected"));
+    }
+
+    #[test]
+    fn comma_decimal() {
+        let f = NumberFormat::CommaDecimal;
+        assert_eq!(ok("1,5", f), "1.5");
+        assert_eq!(ok("1,500", f), "1.5");
+        assert_eq!(ok("1.500", f), "1500");
+        assert_eq!(ok("1 500,25", f), "1500.25");
+        assert_eq!(ok("1\u{a0}500\u{202f}000,25", f), "1500000.25");
+        assert_eq!(ok("1.500.000", f), "1500000");
+        assert_eq!(ok("1500,25", f), "1500.25");
+
+        assert_eq!(
+            err("1.5", f),
+            "Invalid amount '1.5' with a comma as the decimal separator and dots or spaces \
+             grouping digits: the digit group '5' does not have 3 digits."
+        );
+        assert!(err("1,500.25", f).contains("unexpected digit grouping '.' in the decimals"));
+        assert!(err("1.500 000", f).contains("digits are grouped with both '.' and ' '"));
+        assert!(err("1,5,0", f).contains("more than one decimal separator ','"));
+        assert!(err("1500.000", f).contains("the first digit group '1500'"));
+        assert!(err("0.500", f).contains("without leading zeros"));
+        assert!(err("1..500", f).contains("empty digit group"));
+    }
+
+    #[test]
+    fn dot_decimal() {
+        let f = NumberFormat::DotDecimal;
+        assert_eq!(ok("1,500", f), "1500");
+        assert_eq!(ok("1.500", f), "1.5");
+        assert_eq!(ok("1,500.25", f), "1500.25");
+        assert_eq!(ok("1,500,000", f), "1500000");
+
+        assert!(err("1,5", f).contains("the digit group '5' does not have 3 digits"));
+        assert!(err("1 500,25", f).contains("unexpected character ' '"));
+        assert!(err("1.500,25", f).contains("unexpected digit grouping ',' in the decimals"));
+    }
+
+    #[test]
+    fn base_units() {
+        let units = |s| parse(s, NumberFormat::Plain).unwrap().to_base_units();
+        assert_eq!(units("1500"), Ok(BigUint::from(1500u32)));
+        assert_eq!(
+            units("1.5"),
+            Err(
+                "Invalid amount '1.5': amounts are in base units of the token and cannot have \
+                 decimals."
+                    .to_string()
+            )
+        );
+        assert!(units("1.0").is_err());
+        assert_eq!(
+            parse("1.500", NumberFormat::CommaDecimal)
+                .unwrap()
+                .to_base_units(),
+            Ok(BigUint::from(1500u32))
+        );
+    }
+
+    #[test]
+    fn scale() {
+        let scaled = |s, decimals| parse(s, NumberFormat::Plain).unwrap().scale(decimals);
+        let units = |n: u64| Ok(BigUint::from(n));
+        assert_eq!(scaled("1.5", 9), units(1_500_000_000));
+        assert_eq!(scaled("1", 9), units(1_000_000_000));
+        assert_eq!(scaled("0.000000001", 9), units(1));
+        assert_eq!(scaled("0.000000001000", 9), units(1));
+        assert_eq!(scaled("0.100000000", 9), units(100_000_000));
+        assert_eq!(scaled("1.0", 0), units(1));
+        assert_eq!(scaled("0", 6), units(0));
+        assert_eq!(scaled("0.0", 6), units(0));
+        assert_eq!(scaled("007.25", 2), units(725));
+        assert_eq!(
+            scaled("123456789012345678901234567890.123456789", 9),
+            Ok(BigUint::from_str("123456789012345678901234567890123456789").unwrap())
+        );
+
+        // Decimals the token cannot represent are refused, not rounded.
+        assert_eq!(
+            scaled("0.000000001", 8),
+            Err(
+                "Invalid amount '0.000000001': the token has 8 decimals, the smallest amount \
+                 is 0.00000001."
+                    .to_string()
+            )
+        );
+        assert!(scaled("0.0000000015", 9).is_err());
+        assert!(scaled("1.999999999999", 9).is_err());
+        assert_eq!(
+            scaled("1.5", 0),
+            Err(
+                "Invalid amount '1.5': the token has no decimals, amounts are whole numbers."
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn scaled_units() {
+        let no_decimals = || -> Result<u64, ManyError> { panic!("Should not be read") };
+        let nine = || Ok(9);
+        let arg = |s: &str| AmountArg::from_str(s).unwrap();
+
+        // Integers are in base units, unless forced.
+        assert_eq!(
+            arg("1000").scaled_units(false, no_decimals),
+            Ok(BigUint::from(1000u32))
+        );
+        assert_eq!(
+            arg("1000").scaled_units(true, nine),
+            Ok(BigUint::from(1_000_000_000_000u64))
+        );
+        assert_eq!(
+            arg("1.5").scaled_units(false, nine),
+            Ok(BigUint::from(1_500_000_000u64))
+        );
+
+        // The precision is needed, but not available.
+        let unavailable = || Err(ManyError::unknown("Not published."));
+        assert!(arg("1.5").scaled_units(false, unavailable).is_err());
+    }
+
+    #[test]
+    fn formatting() {
+        let format =
+            |n: u64, decimals| format_decimal(&BigUint::from(n), decimals, NumberFormat::Plain);
+        assert_eq!(format(12_345_678_900_000, 9), "12,345.678900000");
+        assert_eq!(format(1, 9), "0.000000001");
+        assert_eq!(format(0, 9), "0.000000000");
+        assert_eq!(format(1_000_000_000, 9), "1.000000000");
+        assert_eq!(format(999, 0), "999");
+        assert_eq!(format(1_000, 0), "1,000");
+        assert_eq!(format(1_234_567, 0), "1,234,567");
+        assert_eq!(format(123_456_789, 3), "123,456.789");
+        assert_eq!(
+            format_decimal(
+                &BigUint::from(12_345_678_900_000u64),
+                9,
+                NumberFormat::CommaDecimal
+            ),
+            "12.345,678900000"
+        );
+
+        // What is printed is read back as the same amount.
+        for format in [NumberFormat::DotDecimal, NumberFormat::CommaDecimal] {
+            for n in [0u64, 1, 999, 1_000, 12_345_678_900_000, u64::MAX] {
+                let printed = format_decimal(&BigUint::from(n), 9, format);
+                assert_eq!(
+                    parse(&printed, format).unwrap().scale(9),
+                    Ok(BigUint::from(n)),
+                    "{}",
+                    printed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locales() {
+        assert_eq!(
+            NumberFormat::from_locale("de_DE.UTF-8"),
+            NumberFormat::CommaDecimal
+        );
+        assert_eq!(
+            NumberFormat::from_locale("fr_FR"),
+            NumberFormat::CommaDecimal
+        );
+        assert_eq!(
+            NumberFormat::from_locale("pt-BR"),
+            NumberFormat::CommaDecimal
+        );
+        assert_eq!(
+            NumberFormat::from_locale("de_CH.UTF-8"),
+            NumberFormat::DotDecimal
+        );
+        assert_eq!(
+            NumberFormat::from_locale("en_US.UTF-8"),
+            NumberFormat::DotDecimal
+        );
+        assert_eq!(NumberFormat::from_locale("C"), NumberFormat::DotDecimal);
+        assert_eq!(NumberFormat::from_locale("POSIX"), NumberFormat::DotDecimal);
+
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert_eq!(
+            NumberFormat::detect(&env(&[("LANG", "en_US.UTF-8"), ("LC_NUMERIC", "fr_FR")])),
+            NumberFormat::CommaDecimal
+        );
+        assert_eq!(
+            NumberFormat::detect(&env(&[("LC_ALL", ""), ("LANG", "de_DE")])),
+            NumberFormat::CommaDecimal
+        );
+        assert_eq!(
+            NumberFormat::detect(&env(&[("LC_ALL", "en_GB"), ("LANG", "de_DE")])),
+            NumberFormat::DotDecimal
+        );
+        assert_eq!(NumberFormat::detect(&env(&[])), NumberFormat::DotDecimal);
+    }
+}
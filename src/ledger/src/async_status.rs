@@ -0,0 +1,120 @@
+use crate::output;
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::Identity;
+use many_modules::r#async::StatusReturn;
+use minicbor::bytes::ByteVec;
+use serde_json::json;
+
+#[derive(Parser)]
+pub struct AsyncStatusOpt {
+    /// The async token, in hexadecimal, as logged by a command which timed
+    /// out waiting for its result.
+    #[clap(parse(try_from_str = parse_token))]
+    token: ByteVec,
+
+    /// Wait for the result if it is still pending, as commands do, instead
+    /// of only reporting it.
+    #[clap(long)]
+    wait: bool,
+}
+
+fn parse_token(s: &str) -> Result<ByteVec, String> {
+    let token = hex::decode(s.trim()).map_err(|e| format!("Invalid async token: {}.", e))?;
+    if token.is_empty() {
+        return Err("The async token cannot be empty.".to_string());
+    }
+    Ok(token.into())
+}
+
+/// How a status is reported to humans.
+fn describe(status: &StatusReturn) -> &'static str {
+    match status {
+        StatusReturn::Unknown => {
+            "The server does not know the token, the command may not be in a block yet."
+        }
+        StatusReturn::Queued => "The command is queued.",
+        StatusReturn::Processing => "The command is being processed.",
+        StatusReturn::Done { .. } => "The command is done.",
+        StatusReturn::Expired => {
+            "The async token expired, the server no longer has the result of the command."
+        }
+    }
+}
+
+fn status_name(status: &StatusReturn) -> &'static str {
+    match status {
+        StatusReturn::Unknown => "unknown",
+        StatusReturn::Queued => "queued",
+        StatusReturn::Processing => "processing",
+        StatusReturn::Done { .. } => "done",
+        StatusReturn::Expired => "expired",
+    }
+}
+
+fn print_payload(token: &[u8], payload: &[u8]) {
+    match output::get() {
+        output::OutputFormat::Human => println!("{}", minicbor::display(payload)),
+        output::OutputFormat::Json => {
+            output::print_json(&output::response_json(Some(token), payload))
+        }
+    }
+}
+
+fn expired() -> ManyError {
+    ManyError::unknown(describe(&StatusReturn::Expired))
+}
+
+/// Read, or wait for, the result of a command from its async token.
+pub fn async_status(
+    client: ManyClient<impl Identity>,
+    opts: AsyncStatusOpt,
+) -> Result<(), ManyError> {
+    let AsyncStatusOpt { token, wait } = opts;
+
+    if wait {
+        let payload = crate::wait_token(&client, &token)?.ok_or_else(expired)?;
+        print_payload(&token, &payload);
+        return Ok(());
+    }
+
+    match crate::async_status(&client, &token)? {
+        StatusReturn::Done { response } => {
+            let payload = crate::wait_response(&client, *response)?;
+            print_payload(&token, &payload);
+        }
+        StatusReturn::Expired => return Err(expired()),
+        status => match output::get() {
+            output::OutputFormat::Human => println!("{}", describe(&status)),
+            output::OutputFormat::Json => output::print_json(&json!({
+                "token": hex::encode(token.as_slice()),
+                "status": status_name(&status),
+            })),
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token() {
+        assert_eq!(parse_token("0a1B"), Ok(vec![0x0a, 0x1b].into()));
+        assert_eq!(parse_token(" 0a1b\n"), Ok(vec![0x0a, 0x1b].into()));
+        assert!(parse_token("").is_err());
+        assert!(parse_token("0a1").is_err());
+        assert!(parse_token("zz").is_err());
+    }
+
+    #[test]
+    fn statuses() {
+        assert_eq!(status_name(&StatusReturn::Unknown), "unknown");
+        assert_eq!(status_name(&StatusReturn::Queued), "queued");
+        assert_eq!(status_name(&StatusReturn::Processing), "processing");
+        assert_eq!(status_name(&StatusReturn::Expired), "expired");
+        assert_eq!(describe(&StatusReturn::Queued), "The command is queued.");
+    }
+}
@@ -0,0 +1,461 @@
+use crate::transport::ManyClient;
+use clap::Parser;
+use coset::{CborSerializable, CoseKey, CoseSign1};
+use many_error::ManyError;
+use many_identity::{Address, AnonymousIdentity, Identity};
+use many_modules::{base, events, kvstore};
+use many_protocol::{
+    encode_cose_sign1_from_request, RequestMessage, RequestMessageBuilder, ResponseMessage,
+};
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use once_cell::sync::OnceCell;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The request attribute carrying an attachment, as its CBOR encoding.
+const ATTACHMENT: Attribute = Attribute::id(2000);
+
+/// The prefix of the keys of uploaded attachments, followed by the hash of
+/// their value in hexadecimal.
+const KEY_PREFIX: &str = "attachments/";
+
+// These types mirror the ones of the many-ledger server.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct Attachment {
+    #[n(0)]
+    pub server: Address,
+
+    #[n(1)]
+    pub key: ByteVec,
+
+    #[n(2)]
+    pub hash: ByteVec,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct AttachmentArgs {
+    #[n(0)]
+    event: Option<events::EventId>,
+
+    #[n(1)]
+    token: Option<ByteVec>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct AttachmentReturns {
+    #[n(0)]
+    attachment: Option<Attachment>,
+}
+
+impl Attachment {
+    fn key_str(&self) -> String {
+        String::from_utf8(self.key.to_vec()).unwrap_or_else(|_| hex::encode(self.key.as_slice()))
+    }
+}
+
+/// An identity shared by the clients of the ledger and of the key-value
+/// store.
+#[derive(Clone)]
+pub struct SharedIdentity(Arc<Mutex<Box<dyn Identity>>>);
+
+impl SharedIdentity {
+    pub fn new(identity: Box<dyn Identity>) -> Self {
+        Self(Arc::new(Mutex::new(identity)))
+    }
+}
+
+impl Identity for SharedIdentity {
+    fn address(&self) -> Address {
+        self.0.lock().expect("Identity mutex poisoned").address()
+    }
+
+    fn public_key(&self) -> Option<CoseKey> {
+        self.0.lock().expect("Identity mutex poisoned").public_key()
+    }
+
+    fn sign_1(&self, envelope: CoseSign1) -> Result<CoseSign1, ManyError> {
+        self.0
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .sign_1(envelope)
+    }
+}
+
+/// The caller and the ledger, to upload attachments and send or sign the
+/// commands referencing them.
+struct Context {
+    identity: SharedIdentity,
+    server_id: Address,
+}
+
+static CONTEXT: OnceCell<Context> = OnceCell::new();
+
+/// Set the identity which uploads attachments, and the ledger they are sent
+/// to.
+pub fn init(identity: SharedIdentity, server_id: Address) {
+    if CONTEXT
+        .set(Context {
+            identity,
+            server_id,
+        })
+        .is_err()
+    {
+        panic!("Attachments are already initialized.");
+    }
+}
+
+fn context() -> Result<&'static Context, ManyError> {
+    CONTEXT
+        .get()
+        .ok_or_else(|| ManyError::unknown("Attachments are not initialized."))
+}
+
+#[derive(Default, Parser)]
+pub struct AttachOpt {
+    /// A file to upload to a key-value store first, and reference in the
+    /// transaction. The ledger only records its key and hash.
+    #[clap(long, requires = "kvstore")]
+    attach: Option<PathBuf>,
+
+    /// The URL of the key-value store to upload the attachment to.
+    #[clap(long)]
+    kvstore: Option<String>,
+}
+
+impl AttachOpt {
+    /// Upload the file to attach, if any.
+    pub fn upload(&self) -> Result<Option<Attachment>, ManyError> {
+        let (path, url) = match (&self.attach, &self.kvstore) {
+            (Some(path), Some(url)) => (path, url),
+            _ => return Ok(None),
+        };
+        let value = std::fs::read(path)
+            .map_err(|e| ManyError::unknown(format!("Could not read {}: {}", path.display(), e)))?;
+        let client = ManyClient::new(url, Address::anonymous(), context()?.identity.clone())
+            .map_err(ManyError::unknown)?;
+        upload(&client, value).map(Some)
+    }
+}
+
+/// What attachments need from a key-value store.
+pub trait KvStore {
+    fn address(&self) -> Result<Address, ManyError>;
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), ManyError>;
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, ManyError>;
+}
+
+impl<I: Identity> KvStore for ManyClient<I> {
+    fn address(&self) -> Result<Address, ManyError> {
+        let status: base::Status = minicbor::decode(&crate::transport::call_(self, "status", ())?)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        status
+            .identity
+            .ok_or_else(|| ManyError::unknown("The key-value store has no identity."))
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), ManyError> {
+        let arguments = kvstore::PutArgs {
+            key: key.into(),
+            value: value.into(),
+            alternative_owner: None,
+        };
+        let response = crate::transport::call(self, "kvstore.put", arguments)?;
+        crate::wait_response(self, response).map(|_| ())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, ManyError> {
+        let arguments = kvstore::GetArgs {
+            key: key.to_vec().into(),
+        };
+        let returns: kvstore::GetReturns =
+            minicbor::decode(&crate::transport::call_(self, "kvstore.get", arguments)?)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        Ok(returns.value.map(|v| v.to_vec()))
+    }
+}
+
+fn sha256(value: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, value)
+        .as_ref()
+        .to_vec()
+}
+
+/// Put a value in a key-value store, under the hash of its value, and return
+/// its reference.
+pub fn upload(store: &impl KvStore, value: Vec<u8>) -> Result<Attachment, ManyError> {
+    let hash = sha256(&value);
+    let key = format!("{}{}", KEY_PREFIX, hex::encode(&hash)).into_bytes();
+    let server = store.address()?;
+    store.put(key.clone(), value)?;
+    Ok(Attachment {
+        server,
+        key: key.into(),
+        hash: hash.into(),
+    })
+}
+
+/// Get the value of an attachment from a key-value store, and check it
+/// against its hash.
+pub fn fetch(store: &impl KvStore, attachment: &Attachment) -> Result<Vec<u8>, ManyError> {
+    let value = store.get(&attachment.key)?.ok_or_else(|| {
+        ManyError::unknown(format!(
+            "The attachment {} is not in the key-value store.",
+            attachment.key_str()
+        ))
+    })?;
+    if sha256(&value) != attachment.hash.as_slice() {
+        return Err(ManyError::unknown(format!(
+            "The attachment {} does not match its hash, it was modified.",
+            attachment.key_str()
+        )));
+    }
+    Ok(value)
+}
+
+/// Fetch an attachment from the key-value store at a URL, and describe
+/// whether it matches its hash.
+pub fn verify(url: &str, attachment: &Attachment) -> String {
+    let result = ManyClient::new(url, attachment.server, AnonymousIdentity)
+        .map_err(ManyError::unknown)
+        .and_then(|client| fetch(&client, attachment));
+    match result {
+        Ok(value) => format!(
+            "attachment {}: {} bytes, hash verified",
+            attachment.key_str(),
+            value.len()
+        ),
+        Err(e) => format!("attachment {}: {}", attachment.key_str(), e),
+    }
+}
+
+/// A request of the caller to the ledger, with a fresh timestamp and nonce
+/// and an attachment if any.
+pub(crate) fn request<A: Encode<()>>(
+    from: Address,
+    to: Address,
+    method: &str,
+    arguments: A,
+    attachment: Option<&Attachment>,
+) -> Result<RequestMessage, ManyError> {
+    let mut message = RequestMessageBuilder::default()
+        .from(from)
+        .to(to)
+        .method(method.to_string())
+        .data(
+            minicbor::to_vec(arguments)
+                .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+        )
+        .build()
+        .map_err(|e| ManyError::unknown(e.to_string()))?;
+    message.timestamp = Some(Timestamp::now());
+    message.nonce = Some(rand::random::<[u8; 16]>().to_vec());
+    if let Some(attachment) = attachment {
+        message.attributes.insert(
+            ATTACHMENT.with_argument(CborAny::Bytes(
+                minicbor::to_vec(attachment)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            )),
+        );
+    }
+    Ok(message)
+}
+
+/// Call a command of the ledger, with an attachment if any.
+pub fn call<A: Encode<()>>(
+    client: &ManyClient<impl Identity>,
+    method: &str,
+    arguments: A,
+    attachment: Option<&Attachment>,
+) -> Result<ResponseMessage, ManyError> {
+    match attachment {
+        None => crate::transport::call(client, method, arguments),
+        Some(attachment) => {
+            let context = context()?;
+            crate::transport::send_message(
+                client,
+                request(
+                    context.identity.address(),
+                    context.server_id,
+                    method,
+                    arguments,
+                    Some(attachment),
+                )?,
+            )
+        }
+    }
+}
+
+/// The envelope of a command of the ledger signed by the caller, as it
+/// would be sent, with an attachment if any. Nothing is sent.
+pub fn signed_request<A: Encode<()>>(
+    method: &str,
+    arguments: A,
+    attachment: Option<&Attachment>,
+) -> Result<Vec<u8>, ManyError> {
+    let context = context()?;
+    let message = request(
+        context.identity.address(),
+        context.server_id,
+        method,
+        arguments,
+        attachment,
+    )?;
+    sign(message, context)
+}
+
+/// The envelope of a command of the ledger signed by the caller, with the
+/// given timestamp and nonce instead of fresh ones, so the same arguments
+/// always give the same message. Nothing is sent.
+pub fn signed_request_at<A: Encode<()>>(
+    method: &str,
+    arguments: A,
+    timestamp: Timestamp,
+    nonce: Vec<u8>,
+) -> Result<Vec<u8>, ManyError> {
+    let context = context()?;
+    let mut message = request(
+        context.identity.address(),
+        context.server_id,
+        method,
+        arguments,
+        None,
+    )?;
+    message.timestamp = Some(timestamp);
+    message.nonce = Some(nonce);
+    sign(message, context)
+}
+
+fn sign(message: RequestMessage, context: &Context) -> Result<Vec<u8>, ManyError> {
+    encode_cose_sign1_from_request(message, &context.identity)?
+        .to_vec()
+        .map_err(|e| ManyError::serialization_error(e.to_string()))
+}
+
+fn query(
+    client: &ManyClient<impl Identity>,
+    arguments: AttachmentArgs,
+) -> Result<Option<Attachment>, ManyError> {
+    let returns: AttachmentReturns = minicbor::decode(&crate::transport::call_(
+        client,
+        "ledger.attachment",
+        arguments,
+    )?)
+    .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    Ok(returns.attachment)
+}
+
+/// The attachment of an event, if any.
+pub fn of_event(
+    client: &ManyClient<impl Identity>,
+    event: events::EventId,
+) -> Result<Option<Attachment>, ManyError> {
+    query(
+        client,
+        AttachmentArgs {
+            event: Some(event),
+            token: None,
+        },
+    )
+}
+
+/// The attachment of a multisig transaction, if any.
+pub fn of_multisig(
+    client: &ManyClient<impl Identity>,
+    token: ByteVec,
+) -> Result<Option<Attachment>, ManyError> {
+    query(
+        client,
+        AttachmentArgs {
+            event: None,
+            token: Some(token),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    fn address(s: &str) -> Address {
+        Address::from_str(s).unwrap()
+    }
+
+    #[derive(Default)]
+    struct FakeKvStore {
+        values: RefCell<BTreeMap<Vec<u8>, Vec<u8>>>,
+    }
+
+    impl KvStore for FakeKvStore {
+        fn address(&self) -> Result<Address, ManyError> {
+            Ok(address(
+                "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+            ))
+        }
+
+        fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), ManyError> {
+            self.values.borrow_mut().insert(key, value);
+            Ok(())
+        }
+
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, ManyError> {
+            Ok(self.values.borrow().get(key).cloned())
+        }
+    }
+
+    #[test]
+    fn upload_then_reference() {
+        let store = FakeKvStore::default();
+        let attachment = upload(&store, b"Invoice 42".to_vec()).unwrap();
+        assert_eq!(attachment.server, store.address().unwrap());
+        assert_eq!(attachment.hash.as_slice(), sha256(b"Invoice 42"));
+        assert_eq!(
+            attachment.key_str(),
+            format!("attachments/{}", hex::encode(sha256(b"Invoice 42")))
+        );
+        assert_eq!(store.get(&attachment.key).unwrap().unwrap(), b"Invoice 42");
+
+        // The transfer carries the reference in an attribute.
+        let message = request(
+            Address::anonymous(),
+            Address::anonymous(),
+            "ledger.send",
+            (),
+            &attachment,
+        )
+        .unwrap();
+        let attribute = message.attributes.get_attribute(ATTACHMENT.id).unwrap();
+        match attribute.arguments.as_slice() {
+            [CborAny::Bytes(bytes)] => {
+                assert_eq!(minicbor::decode::<Attachment>(bytes).unwrap(), attachment)
+            }
+            other => panic!("Unexpected attribute arguments: {:?}", other),
+        }
+
+        assert_eq!(fetch(&store, &attachment).unwrap(), b"Invoice 42");
+    }
+
+    #[test]
+    fn hash_mismatch() {
+        let store = FakeKvStore::default();
+        let attachment = upload(&store, b"Invoice 42".to_vec()).unwrap();
+        store
+            .put(attachment.key.to_vec(), b"Invoice 43".to_vec())
+            .unwrap();
+        let err = fetch(&store, &attachment).unwrap_err();
+        assert!(err.to_string().contains("does not match its hash"));
+
+        store.values.borrow_mut().clear();
+        let err = fetch(&store, &attachment).unwrap_err();
+        assert!(err.to_string().contains("is not in the key-value store"));
+    }
+}
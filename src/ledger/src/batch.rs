@@ -0,0 +1,439 @@
+use crate::progress::{Event, Progress};
+use crate::transport::ManyClient;
+use crate::{resolve_symbol, wait_response};
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::{base, ledger};
+use many_types::ledger::{Symbol, TokenAmount};
+use minicbor::{Decode, Encode};
+use num_bigint::BigUint;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// Maximum number of operations in a `ledger.batch` message, as enforced by
+/// the server.
+const MAXIMUM_BATCH_COUNT: usize = 500;
+
+/// The error of the transfers which were not sent because another one
+/// failed.
+const SKIPPED: &str = "Skipped.";
+
+// These types mirror the ones of the many-ledger server.
+
+#[derive(Encode)]
+enum BatchOperation {
+    #[n(0)]
+    Send(#[n(0)] ledger::SendArgs),
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct BatchArgs {
+    #[n(0)]
+    operations: Vec<BatchOperation>,
+
+    #[n(1)]
+    atomic: bool,
+}
+
+#[derive(Debug, PartialEq, Decode)]
+#[cbor(index_only)]
+enum BatchItemStatus {
+    #[n(0)]
+    Executed,
+    #[n(1)]
+    Failed,
+    #[n(2)]
+    Skipped,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct BatchItemResult {
+    #[n(0)]
+    status: BatchItemStatus,
+
+    #[n(1)]
+    error: Option<ManyError>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct BatchReturns {
+    #[n(0)]
+    results: Vec<BatchItemResult>,
+}
+
+#[derive(Parser)]
+pub struct SendBatchOpt {
+    /// A file with a `<address>,<amount>,<symbol>` line per transfer. Empty
+    /// lines and lines starting with `#` are ignored. Symbols can be local
+    /// names.
+    file: PathBuf,
+
+    /// The from identity, if different than the one provided by the
+    /// PEM argument.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    account: Option<Address>,
+
+    /// Send nothing unless every transfer succeeds. This needs a server
+    /// supporting `ledger.batch`, and at most 500 transfers.
+    #[clap(long)]
+    atomic: bool,
+
+    /// Stop at the first transfer which fails, and skip the following ones.
+    /// Transfers are then sent one by one, even if the server supports
+    /// `ledger.batch`.
+    #[clap(long, conflicts_with = "atomic")]
+    stop_on_error: bool,
+}
+
+/// A line of the transfers file.
+#[derive(Debug, PartialEq, Eq)]
+struct Row {
+    line: usize,
+    to: Address,
+    amount: BigUint,
+    symbol: String,
+}
+
+fn parse_rows(content: &str) -> Result<Vec<Row>, String> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, content)| {
+            let fields: Vec<&str> = content.split(',').map(str::trim).collect();
+            let (to, amount, symbol) = match fields[..] {
+                [to, amount, symbol] => (to, amount, symbol),
+                _ => {
+                    return Err(format!(
+                        "Line {}: expected <address>,<amount>,<symbol>.",
+                        line
+                    ))
+                }
+            };
+            Ok(Row {
+                line,
+                to: Address::from_str(to)
+                    .map_err(|e| format!("Line {}: invalid address '{}': {}", line, to, e))?,
+                amount: BigUint::from_str(amount)
+                    .map_err(|e| format!("Line {}: invalid amount '{}': {}", line, amount, e))?,
+                symbol: symbol.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Whether the server advertises an endpoint.
+fn supports(client: &ManyClient<impl Identity>, endpoint: &str) -> Result<bool, ManyError> {
+    let endpoints: base::Endpoints =
+        minicbor::decode(&crate::transport::call_(client, "endpoints", ())?)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    Ok(endpoints.0.contains(endpoint))
+}
+
+fn send_batch_message(
+    client: &ManyClient<impl Identity>,
+    sends: &[ledger::SendArgs],
+    atomic: bool,
+) -> Result<Vec<Result<(), String>>, ManyError> {
+    let response = crate::transport::call(
+        client,
+        "ledger.batch",
+        BatchArgs {
+            operations: sends.iter().cloned().map(BatchOperation::Send).collect(),
+            atomic,
+        },
+    )?;
+    let payload = wait_response(client, response)?;
+    if payload.is_empty() {
+        return Err(ManyError::unexpected_empty_response());
+    }
+    let returns: BatchReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    Ok(returns
+        .results
+        .into_iter()
+        .map(|result| match (result.status, result.error) {
+            (BatchItemStatus::Executed, _) => Ok(()),
+            (BatchItemStatus::Skipped, _) => Err(SKIPPED.to_string()),
+            (BatchItemStatus::Failed, e) => {
+                Err(e.map_or_else(|| "Failed.".to_string(), |e| e.to_string()))
+            }
+        })
+        .collect())
+}
+
+fn send_one_by_one(
+    client: &ManyClient<impl Identity>,
+    sends: &[ledger::SendArgs],
+    stop_on_error: bool,
+) -> Vec<Result<(), String>> {
+    let mut failed = false;
+    sends
+        .iter()
+        .map(|send| {
+            if failed && stop_on_error {
+                return Err(SKIPPED.to_string());
+            }
+            let result = crate::transport::call(client, "ledger.send", send.clone())
+                .and_then(|response| wait_response(client, response))
+                .map(|_| ())
+                .map_err(|e| e.to_string());
+            failed |= result.is_err();
+            result
+        })
+        .collect()
+}
+
+/// Print the result of each transfer and a summary, and return the number
+/// which were not sent.
+fn report(
+    rows: &[Row],
+    results: Vec<Result<(), String>>,
+    out: &mut impl Write,
+    progress: &Progress,
+) -> std::io::Result<usize> {
+    let mut failed = 0;
+    let mut skipped = 0;
+    for (index, (row, result)) in rows.iter().zip(results).enumerate() {
+        match &result {
+            Ok(()) => writeln!(out, "line {}: sent {} to {}", row.line, row.amount, row.to)?,
+            Err(e) if e == SKIPPED => {
+                skipped += 1;
+                writeln!(out, "line {}: skipped", row.line)?;
+            }
+            Err(e) => {
+                failed += 1;
+                writeln!(out, "line {}: failed: {}", row.line, e)?;
+            }
+        }
+        progress.emit(Event::BatchItem {
+            line: row.line,
+            index,
+            total: rows.len(),
+            ok: result.is_ok(),
+            error: result.err(),
+        });
+    }
+    writeln!(
+        out,
+        "{} sent, {} failed, {} skipped.",
+        rows.len() - failed - skipped,
+        failed,
+        skipped
+    )?;
+    Ok(failed + skipped)
+}
+
+pub fn send_batch(
+    client: ManyClient<impl Identity>,
+    client_address: Address,
+    opts: SendBatchOpt,
+) -> Result<(), ManyError> {
+    let SendBatchOpt {
+        file,
+        account,
+        atomic,
+        stop_on_error,
+    } = opts;
+    let from = account.unwrap_or(client_address);
+    if from.is_anonymous() {
+        return Err(ManyError::invalid_identity());
+    }
+
+    let content = std::fs::read_to_string(&file).map_err(|e| ManyError::unknown(e.to_string()))?;
+    let rows = parse_rows(&content).map_err(ManyError::unknown)?;
+    if rows.is_empty() {
+        return Err(ManyError::unknown("No transfers to send."));
+    }
+
+    let mut symbols: BTreeMap<String, Symbol> = BTreeMap::new();
+    let mut sends = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let symbol = match symbols.get(&row.symbol) {
+            Some(symbol) => *symbol,
+            None => {
+                let symbol = resolve_symbol(&client, row.symbol.clone())?;
+                symbols.insert(row.symbol.clone(), symbol);
+                symbol
+            }
+        };
+        sends.push(ledger::SendArgs {
+            from: Some(from),
+            to: row.to,
+            symbol,
+            amount: TokenAmount::from(row.amount.clone()),
+        });
+    }
+
+    let batched = supports(&client, "ledger.batch")?;
+    if atomic && (!batched || sends.len() > MAXIMUM_BATCH_COUNT) {
+        return Err(ManyError::unknown(if batched {
+            format!(
+                "An atomic batch can have at most {} transfers.",
+                MAXIMUM_BATCH_COUNT
+            )
+        } else {
+            "The server does not support atomic batches.".to_string()
+        }));
+    }
+
+    let results = if stop_on_error {
+        send_one_by_one(&client, &sends, true)
+    } else if batched {
+        info!("Sending {} transfers with ledger.batch", sends.len());
+        let mut results = Vec::with_capacity(sends.len());
+        for chunk in sends.chunks(MAXIMUM_BATCH_COUNT) {
+            results.extend(send_batch_message(&client, chunk, atomic)?);
+        }
+        results
+    } else {
+        warn!("The server does not support ledger.batch, sending transfers one by one");
+        send_one_by_one(&client, &sends, false)
+    };
+
+    let failed = report(
+        &rows,
+        results,
+        &mut std::io::stdout(),
+        crate::progress::get(),
+    )
+    .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+    if failed > 0 {
+        Err(ManyError::unknown(format!(
+            "{} of {} transfers were not sent.",
+            failed,
+            rows.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::testing::*;
+
+    const ADDRESS: &str = "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp";
+
+    #[test]
+    fn rows() {
+        let content = format!("# payouts\n\n{ADDRESS},100,MFX\n  {ADDRESS} , 2 , {ADDRESS}\n");
+        assert_eq!(
+            parse_rows(&content).unwrap(),
+            vec![
+                Row {
+                    line: 3,
+                    to: Address::from_str(ADDRESS).unwrap(),
+                    amount: BigUint::from(100u32),
+                    symbol: "MFX".to_string(),
+                },
+                Row {
+                    line: 4,
+                    to: Address::from_str(ADDRESS).unwrap(),
+                    amount: BigUint::from(2u32),
+                    symbol: ADDRESS.to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_rows() {
+        assert!(parse_rows(&format!("{ADDRESS},100"))
+            .unwrap_err()
+            .starts_with("Line 1:"));
+        assert!(parse_rows(&format!("{ADDRESS},100,MFX,extra")).is_err());
+        assert!(parse_rows("nope,100,MFX").is_err());
+        assert!(parse_rows(&format!("\n{ADDRESS},-1,MFX"))
+            .unwrap_err()
+            .starts_with("Line 2:"));
+    }
+
+    #[test]
+    fn operations_encoding() {
+        // A send is encoded as an array of the variant index and the arguments.
+        let bytes = minicbor::to_vec(BatchOperation::Send(ledger::SendArgs {
+            from: None,
+            to: Address::from_str(ADDRESS).unwrap(),
+            symbol: Address::from_str(ADDRESS).unwrap(),
+            amount: TokenAmount::from(1u64),
+        }))
+        .unwrap();
+        assert_eq!(bytes[..2], [0x82, 0x00]);
+    }
+
+    #[test]
+    fn report_items() {
+        let rows = parse_rows(&format!(
+            "{ADDRESS},1,MFX\n{ADDRESS},2,MFX\n{ADDRESS},3,MFX\n"
+        ))
+        .unwrap();
+        let events = Buffer::default();
+        let mut out = Vec::new();
+        let failed = report(
+            &rows,
+            vec![
+                Ok(()),
+                Err("Insufficient funds.".to_string()),
+                Err(SKIPPED.to_string()),
+            ],
+            &mut out,
+            &Progress::json(events.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(failed, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "line 1: sent 1 to {ADDRESS}\n\
+                 line 2: failed: Insufficient funds.\n\
+                 line 3: skipped\n\
+                 1 sent, 1 failed, 1 skipped.\n"
+            )
+        );
+        assert_eq!(
+            events.lines(),
+            vec![
+                serde_json::json!({
+                    "seq": 0,
+                    "type": "batch_item",
+                    "line": 1,
+                    "index": 0,
+                    "total": 3,
+                    "ok": true,
+                    "error": null,
+                }),
+                serde_json::json!({
+                    "seq": 1,
+                    "type": "batch_item",
+                    "line": 2,
+                    "index": 1,
+                    "total": 3,
+                    "ok": false,
+                    "error": "Insufficient funds.",
+                }),
+                serde_json::json!({
+                    "seq": 2,
+                    "type": "batch_item",
+                    "line": 3,
+                    "index": 2,
+                    "total": 3,
+                    "ok": false,
+                    "error": SKIPPED,
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,736 @@
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::{ManyError, ManyErrorCode};
+use many_identity::{Address, Identity};
+use many_modules::ledger;
+use many_types::ledger::Symbol;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use once_cell::sync::OnceCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Default bound of the total size of the cached responses, for all servers.
+pub const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A response of a server, and when it was fetched.
+#[derive(Encode, Decode)]
+#[cbor(map)]
+struct CachedResponse {
+    #[n(0)]
+    method: String,
+
+    /// Seconds since the UNIX epoch.
+    #[n(1)]
+    fetched_at: u64,
+
+    #[n(2)]
+    payload: ByteVec,
+}
+
+/// The local names of the symbols of a server, and when they were fetched.
+#[derive(Encode, Decode)]
+#[cbor(map)]
+struct CachedSymbols {
+    /// Seconds since the UNIX epoch.
+    #[n(0)]
+    fetched_at: u64,
+
+    #[n(1)]
+    local_names: BTreeMap<Symbol, String>,
+
+    /// The digest of the names on the server when they were fetched, if it
+    /// provides one.
+    #[n(2)]
+    digest: Option<ByteVec>,
+}
+
+// Mirrors the arguments and returns of `tokens.namesDigest` of the
+// many-ledger server.
+#[derive(Encode)]
+#[cbor(map)]
+struct NamesDigestArgs {}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct NamesDigestReturns {
+    #[n(0)]
+    digest: ByteVec,
+}
+
+/// The responses of read commands, by server ID, so they can be shown again
+/// without connectivity. Responses are kept in one file each, keyed by the
+/// caller, method and arguments, and the least recently used ones are
+/// removed when the cache grows over its size.
+pub struct Cache {
+    dir: PathBuf,
+    server_id: Address,
+    caller: Address,
+    max_size: u64,
+}
+
+/// The default directory of the cache.
+pub fn default_dir(env: &dyn Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let base = match env("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env("HOME")?).join(".cache"),
+    };
+    Some(base.join("many-ledger"))
+}
+
+/// How to label a response fetched at `fetched_at`.
+pub fn label(method: &str, fetched_at: SystemTime, now: SystemTime) -> String {
+    let age = now.duration_since(fetched_at).unwrap_or_default().as_secs();
+    format!(
+        "Offline: cached {} response from {} ({} ago).",
+        method,
+        humantime::format_rfc3339_seconds(fetched_at),
+        humantime::format_duration(Duration::from_secs(age))
+    )
+}
+
+/// Write a file at once, so a reader never sees part of it.
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path).map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        e
+    })
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, server_id: Address, caller: Address) -> Self {
+        Self {
+            dir,
+            server_id,
+            caller,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    fn path(&self, method: &str, arguments: &[u8]) -> PathBuf {
+        // Responses may depend on the caller, e.g. balances without an account.
+        let key = [
+            self.caller.to_string().as_bytes(),
+            &[0],
+            method.as_bytes(),
+            &[0],
+            arguments,
+        ]
+        .concat();
+        let hash = ring::digest::digest(&ring::digest::SHA256, &key);
+        self.dir
+            .join(self.server_id.to_string())
+            .join(format!("{}.cbor", hex::encode(hash.as_ref())))
+    }
+
+    /// Cache the response of a call, then remove the least recently used
+    /// responses if the cache is too large.
+    pub fn store(
+        &self,
+        method: &str,
+        arguments: &[u8],
+        payload: &[u8],
+        fetched_at: SystemTime,
+    ) -> Result<(), String> {
+        let path = self.path(method, arguments);
+        let content = minicbor::to_vec(CachedResponse {
+            method: method.to_string(),
+            fetched_at: fetched_at
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?
+                .as_secs(),
+            payload: payload.to_vec().into(),
+        })
+        .map_err(|e| e.to_string())?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {}", parent.display(), e))?;
+        }
+        write_atomic(&path, &content)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+        self.evict()
+    }
+
+    /// The cached response of a call and when it was fetched, if any.
+    pub fn load(
+        &self,
+        method: &str,
+        arguments: &[u8],
+    ) -> Result<Option<(Vec<u8>, SystemTime)>, String> {
+        let path = self.path(method, arguments);
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        let cached: CachedResponse = minicbor::decode(&content)
+            .map_err(|e| format!("Invalid cache file {}: {}", path.display(), e))?;
+        if cached.method != method {
+            return Ok(None);
+        }
+
+        // The modification time orders the responses by last use.
+        if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Ok(Some((
+            cached.payload.to_vec(),
+            UNIX_EPOCH + Duration::from_secs(cached.fetched_at),
+        )))
+    }
+
+    /// Remove the least recently used responses, of any server, until the
+    /// cache fits in its size.
+    fn evict(&self) -> Result<(), String> {
+        let mut files = vec![];
+        let servers = std::fs::read_dir(&self.dir)
+            .map_err(|e| format!("Could not read {}: {}", self.dir.display(), e))?;
+        for server in servers.flatten() {
+            let entries = match std::fs::read_dir(server.path()) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().map_or(true, |e| e != "cbor") {
+                    continue;
+                }
+                if let Ok(metadata) = entry.metadata() {
+                    let used = metadata.modified().unwrap_or(UNIX_EPOCH);
+                    files.push((used, path, metadata.len()));
+                }
+            }
+        }
+
+        let mut size: u64 = files.iter().map(|(_, _, len)| len).sum();
+        files.sort();
+        for (_, path, len) in files {
+            if size <= self.max_size {
+                break;
+            }
+            debug!("Evicting {} from the cache", path.display());
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("Could not remove {}: {}", path.display(), e))?;
+            size -= len;
+        }
+        Ok(())
+    }
+
+    /// Remove the cached responses and symbols of all servers.
+    pub fn clear(&self) -> Result<(), String> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("Could not remove {}: {}", self.dir.display(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The local names of the symbols of a server, kept between invocations so
+/// that commands resolving symbols do not call `ledger.info` every time,
+/// and the limits of its transfers, from `governance.params`.
+/// Unlike the responses, they are keyed by the URL of the server as well as
+/// its identity, since servers with the anonymous identity cannot be told
+/// apart otherwise.
+pub struct SymbolCache {
+    path: PathBuf,
+    ttl: Duration,
+}
+
+impl SymbolCache {
+    pub fn new(dir: &Path, server: &str, server_id: Address, ttl: Duration) -> Self {
+        let key = [server.as_bytes(), &[0], server_id.to_string().as_bytes()].concat();
+        let hash = ring::digest::digest(&ring::digest::SHA256, &key);
+        Self {
+            path: dir
+                .join("symbols")
+                .join(format!("{}.cbor", hex::encode(hash.as_ref()))),
+            ttl,
+        }
+    }
+
+    fn limits_path(&self) -> PathBuf {
+        self.path.with_extension("limits.cbor")
+    }
+
+    /// Whether something fetched at `fetched_at` (seconds since the UNIX
+    /// epoch) is still fresh.
+    fn is_fresh(&self, fetched_at: u64, now: SystemTime) -> bool {
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(fetched_at);
+        // A time in the future means the clock changed, so it is stale too.
+        matches!(now.duration_since(fetched_at), Ok(age) if age < self.ttl)
+    }
+
+    /// The cached local names and the digest of the names on the server, if
+    /// they were fetched less than the TTL ago.
+    #[allow(clippy::type_complexity)]
+    pub fn load(
+        &self,
+        now: SystemTime,
+    ) -> Result<Option<(BTreeMap<Symbol, String>, Option<Vec<u8>>)>, String> {
+        let content = match std::fs::read(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Could not read {}: {}", self.path.display(), e)),
+        };
+        let cached: CachedSymbols = minicbor::decode(&content)
+            .map_err(|e| format!("Invalid cache file {}: {}", self.path.display(), e))?;
+        Ok(if self.is_fresh(cached.fetched_at, now) {
+            Some((cached.local_names, cached.digest.map(|d| d.to_vec())))
+        } else {
+            None
+        })
+    }
+
+    /// The cached response of `governance.params`, if it was fetched less
+    /// than the TTL ago.
+    pub fn load_limits(&self, now: SystemTime) -> Result<Option<Vec<u8>>, String> {
+        let path = self.limits_path();
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+        };
+        let cached: CachedResponse = minicbor::decode(&content)
+            .map_err(|e| format!("Invalid cache file {}: {}", path.display(), e))?;
+        Ok(if self.is_fresh(cached.fetched_at, now) {
+            Some(cached.payload.to_vec())
+        } else {
+            None
+        })
+    }
+
+    pub fn store_limits(&self, payload: &[u8], fetched_at: SystemTime) -> Result<(), String> {
+        let path = self.limits_path();
+        let content = minicbor::to_vec(CachedResponse {
+            method: "governance.params".to_string(),
+            fetched_at: fetched_at
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?
+                .as_secs(),
+            payload: payload.to_vec().into(),
+        })
+        .map_err(|e| e.to_string())?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {}", parent.display(), e))?;
+        }
+        write_atomic(&path, &content)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    pub fn store(
+        &self,
+        local_names: &BTreeMap<Symbol, String>,
+        digest: Option<&[u8]>,
+        fetched_at: SystemTime,
+    ) -> Result<(), String> {
+        let content = minicbor::to_vec(CachedSymbols {
+            fetched_at: fetched_at
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| e.to_string())?
+                .as_secs(),
+            local_names: local_names.clone(),
+            digest: digest.map(|d| d.to_vec().into()),
+        })
+        .map_err(|e| e.to_string())?;
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {}", parent.display(), e))?;
+        }
+        write_atomic(&self.path, &content)
+            .map_err(|e| format!("Could not write {}: {}", self.path.display(), e))
+    }
+}
+
+static CACHE: OnceCell<Cache> = OnceCell::new();
+static SYMBOLS: OnceCell<SymbolCache> = OnceCell::new();
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Set the cache of all subcommands. Can only be called once. Without a
+/// cache, responses are not cached and offline reads fail.
+pub fn init(cache: Cache) {
+    if CACHE.set(cache).is_err() {
+        panic!("The cache was already set.");
+    }
+}
+
+/// Set the symbol cache. Without it, symbols are fetched every time.
+pub fn init_symbols(cache: SymbolCache) {
+    if SYMBOLS.set(cache).is_err() {
+        panic!("The symbol cache was already set.");
+    }
+}
+
+/// Serve the read commands from the cache instead of the server.
+pub fn set_offline() {
+    OFFLINE.store(true, Ordering::Relaxed);
+}
+
+/// Call a read method. Online, its response is cached; offline, the
+/// response cached by the last successful call with the same arguments is
+/// returned, and labeled with its age.
+pub fn call_<A: Encode<()>>(
+    client: &ManyClient<impl Identity>,
+    method: &str,
+    arguments: A,
+) -> Result<Vec<u8>, ManyError> {
+    let bytes =
+        minicbor::to_vec(&arguments).map_err(|e| ManyError::serialization_error(e.to_string()))?;
+    let cache = CACHE.get();
+
+    if OFFLINE.load(Ordering::Relaxed) {
+        let cache = cache.ok_or_else(|| {
+            ManyError::unknown("There is no cache directory, set $XDG_CACHE_HOME or $HOME.")
+        })?;
+        return match cache.load(method, &bytes).map_err(ManyError::unknown)? {
+            Some((payload, fetched_at)) => {
+                warn!("{}", label(method, fetched_at, SystemTime::now()));
+                Ok(payload)
+            }
+            None => Err(ManyError::unknown(format!(
+                "Nothing cached for {} on server {} with these arguments. Run the command \
+                 online once to cache it.",
+                method, cache.server_id
+            ))),
+        };
+    }
+
+    let response = crate::transport::call(client, method, arguments)?;
+    let payload = crate::wait_response(client, response)?;
+    if let Some(cache) = cache {
+        if let Err(e) = cache.store(method, &bytes, &payload, SystemTime::now()) {
+            warn!("Could not cache the response: {}", e);
+        }
+    }
+    Ok(payload)
+}
+
+/// The digest of the local names of the symbols on the server, if it
+/// provides one. Offline, or if it cannot be read, there is none.
+fn names_digest(client: &ManyClient<impl Identity>) -> Option<Vec<u8>> {
+    if OFFLINE.load(Ordering::Relaxed) {
+        return None;
+    }
+    match crate::transport::call_(client, "tokens.namesDigest", NamesDigestArgs {}) {
+        Ok(payload) => match minicbor::decode::<NamesDigestReturns>(&payload) {
+            Ok(returns) => Some(returns.digest.to_vec()),
+            Err(e) => {
+                warn!("Invalid digest of the symbols: {}", e);
+                None
+            }
+        },
+        Err(e) if e.code() == ManyErrorCode::InvalidMethodName => {
+            debug!("The server does not provide a digest of the symbols.");
+            None
+        }
+        Err(e) => {
+            warn!("Could not check whether the symbols changed: {}", e);
+            None
+        }
+    }
+}
+
+/// The local names of the symbols of the ledger, fetched from the server,
+/// and cached for the next invocations.
+pub fn fetch_local_names(
+    client: &ManyClient<impl Identity>,
+) -> Result<BTreeMap<Symbol, String>, ManyError> {
+    let info: ledger::InfoReturns = minicbor::decode(&call_(client, "ledger.info", ())?)
+        .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    if let Some(symbols) = SYMBOLS.get() {
+        let digest = names_digest(client);
+        if let Err(e) = symbols.store(&info.local_names, digest.as_deref(), SystemTime::now()) {
+            warn!("Could not cache the symbols: {}", e);
+        }
+    }
+    Ok(info.local_names)
+}
+
+/// The local names of the symbols of the ledger, from the symbol cache if
+/// they are fresh enough and did not change on the server since, otherwise
+/// fetched from the server. A symbol missing from them may have been added
+/// since they were cached, so it should be looked up again with
+/// `fetch_local_names` before failing.
+pub fn local_names(
+    client: &ManyClient<impl Identity>,
+) -> Result<BTreeMap<Symbol, String>, ManyError> {
+    current_local_names(
+        SYMBOLS.get(),
+        SystemTime::now(),
+        || names_digest(client),
+        || fetch_local_names(client),
+    )
+}
+
+/// The cached local names, unless they are stale or the digest of the names
+/// on the server differs from the one cached with them. Without a digest on
+/// the server, e.g. offline, only the TTL is checked.
+fn current_local_names(
+    symbols: Option<&SymbolCache>,
+    now: SystemTime,
+    server_digest: impl FnOnce() -> Option<Vec<u8>>,
+    fetch: impl FnOnce() -> Result<BTreeMap<Symbol, String>, ManyError>,
+) -> Result<BTreeMap<Symbol, String>, ManyError> {
+    let cached =
+        symbols.and_then(|symbols| symbols.load(now).map_err(|e| warn!("{}", e)).ok().flatten());
+    match cached {
+        Some((local_names, digest)) => match server_digest() {
+            Some(server_digest) if digest.as_ref() != Some(&server_digest) => {
+                info!("The symbols changed on the server since they were cached.");
+                fetch()
+            }
+            _ => {
+                debug!("Using the cached symbols");
+                Ok(local_names)
+            }
+        },
+        None => fetch(),
+    }
+}
+
+/// The response of `governance.params`, with the limits of transfers, from
+/// the symbol cache if it is fresh enough, otherwise fetched and cached for
+/// the next invocations.
+pub fn limits(fetch: impl FnOnce() -> Result<Vec<u8>, ManyError>) -> Result<Vec<u8>, ManyError> {
+    let symbols = SYMBOLS.get();
+    let cached = symbols.and_then(|symbols| {
+        symbols
+            .load_limits(SystemTime::now())
+            .map_err(|e| warn!("{}", e))
+            .ok()
+            .flatten()
+    });
+    if let Some(payload) = cached {
+        debug!("Using the cached limits");
+        return Ok(payload);
+    }
+
+    let payload = fetch()?;
+    if let Some(symbols) = symbols {
+        if let Err(e) = symbols.store_limits(&payload, SystemTime::now()) {
+            warn!("Could not cache the limits: {}", e);
+        }
+    }
+    Ok(payload)
+}
+
+#[derive(Parser)]
+pub struct CommandOpt {
+    #[clap(subcommand)]
+    /// Cache subcommand to execute.
+    subcommand: SubcommandOpt,
+}
+
+#[derive(Parser)]
+enum SubcommandOpt {
+    /// Remove the cached responses and symbols of all servers.
+    Clear,
+}
+
+pub fn cache(opts: CommandOpt) -> Result<(), ManyError> {
+    let cache = CACHE.get().ok_or_else(|| {
+        ManyError::unknown("There is no cache directory, set $XDG_CACHE_HOME or $HOME.")
+    })?;
+    match opts.subcommand {
+        SubcommandOpt::Clear => {
+            cache.clear().map_err(ManyError::unknown)?;
+            info!("Cleared the cache.");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn identity(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn cache(dir: &Path) -> Cache {
+        Cache::new(dir.to_path_buf(), identity(100), identity(1))
+    }
+
+    #[test]
+    fn populated() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path());
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_655_000_000);
+
+        assert_eq!(cache.load("ledger.balance", b"a").unwrap(), None);
+        cache
+            .store("ledger.balance", b"a", b"payload", fetched_at)
+            .unwrap();
+        assert_eq!(
+            cache.load("ledger.balance", b"a").unwrap(),
+            Some((b"payload".to_vec(), fetched_at))
+        );
+
+        // Other arguments, methods, callers and servers are cached separately.
+        assert_eq!(cache.load("ledger.balance", b"b").unwrap(), None);
+        assert_eq!(cache.load("ledger.info", b"a").unwrap(), None);
+        let other = Cache::new(dir.path().to_path_buf(), identity(100), identity(2));
+        assert_eq!(other.load("ledger.balance", b"a").unwrap(), None);
+        let other = Cache::new(dir.path().to_path_buf(), identity(101), identity(1));
+        assert_eq!(other.load("ledger.balance", b"a").unwrap(), None);
+
+        // Newer responses replace older ones, leaving no temporary file.
+        cache
+            .store("ledger.balance", b"a", b"newer", fetched_at)
+            .unwrap();
+        assert_eq!(
+            cache.load("ledger.balance", b"a").unwrap().unwrap().0,
+            b"newer".to_vec()
+        );
+        let server_dir = dir.path().join(identity(100).to_string());
+        assert_eq!(std::fs::read_dir(server_dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn staleness() {
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_655_000_000);
+        assert_eq!(
+            label(
+                "ledger.balance",
+                fetched_at,
+                fetched_at + Duration::from_secs(3 * 3600 + 125)
+            ),
+            "Offline: cached ledger.balance response from 2022-06-12T02:13:20Z (3h 2m 5s ago)."
+        );
+        assert_eq!(
+            label("ledger.info", fetched_at, fetched_at),
+            "Offline: cached ledger.info response from 2022-06-12T02:13:20Z (0s ago)."
+        );
+    }
+
+    #[test]
+    fn symbols() {
+        let dir = tempfile::tempdir().unwrap();
+        let ttl = Duration::from_secs(3600);
+        let symbols = SymbolCache::new(dir.path(), "http://a:8000/", identity(100), ttl);
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_655_000_000);
+        let local_names = BTreeMap::from([(identity(1000), "MFX".to_string())]);
+
+        assert_eq!(symbols.load(fetched_at), Ok(None));
+        symbols.store(&local_names, None, fetched_at).unwrap();
+        assert_eq!(
+            symbols.load(fetched_at),
+            Ok(Some((local_names.clone(), None)))
+        );
+        assert_eq!(
+            symbols.load(fetched_at + ttl - Duration::from_secs(1)),
+            Ok(Some((local_names.clone(), None)))
+        );
+
+        // Stale, or fetched in the future.
+        assert_eq!(symbols.load(fetched_at + ttl), Ok(None));
+        assert_eq!(symbols.load(fetched_at - Duration::from_secs(1)), Ok(None));
+
+        // The limits are kept next to the names, with the same TTL.
+        assert_eq!(symbols.load_limits(fetched_at), Ok(None));
+        symbols.store_limits(b"params", fetched_at).unwrap();
+        assert_eq!(
+            symbols.load_limits(fetched_at + ttl - Duration::from_secs(1)),
+            Ok(Some(b"params".to_vec()))
+        );
+        assert_eq!(symbols.load_limits(fetched_at + ttl), Ok(None));
+        assert_eq!(
+            symbols.load(fetched_at),
+            Ok(Some((local_names.clone(), None)))
+        );
+
+        // Other URLs and server identities are cached separately.
+        let other = SymbolCache::new(dir.path(), "http://b:8000/", identity(100), ttl);
+        assert_eq!(other.load(fetched_at), Ok(None));
+        let other = SymbolCache::new(dir.path(), "http://a:8000/", identity(101), ttl);
+        assert_eq!(other.load(fetched_at), Ok(None));
+
+        let cache = cache(dir.path());
+        cache.clear().unwrap();
+        assert_eq!(symbols.load(fetched_at), Ok(None));
+        assert_eq!(symbols.load_limits(fetched_at), Ok(None));
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn renamed_symbols() {
+        let dir = tempfile::tempdir().unwrap();
+        let ttl = Duration::from_secs(3600);
+        let symbols = SymbolCache::new(dir.path(), "http://a:8000/", identity(100), ttl);
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_655_000_000);
+        let now = fetched_at + Duration::from_secs(60);
+        let mfx = BTreeMap::from([(identity(1000), "MFX".to_string())]);
+        let mfy = BTreeMap::from([(identity(1000), "MFY".to_string())]);
+        symbols.store(&mfx, Some(b"mfx"), fetched_at).unwrap();
+
+        let current = |server_digest: Option<&[u8]>, fetched: &BTreeMap<Symbol, String>| {
+            current_local_names(
+                Some(&symbols),
+                now,
+                || server_digest.map(|d| d.to_vec()),
+                || Ok(fetched.clone()),
+            )
+            .unwrap()
+        };
+
+        // Unchanged, or no digest to compare with, e.g. offline.
+        assert_eq!(current(Some(b"mfx"), &mfy), mfx);
+        assert_eq!(current(None, &mfy), mfx);
+
+        // Renamed on the server within the TTL.
+        assert_eq!(current(Some(b"mfy"), &mfy), mfy);
+
+        // Cached before the server provided a digest.
+        symbols.store(&mfx, None, fetched_at).unwrap();
+        assert_eq!(current(Some(b"mfx"), &mfy), mfy);
+    }
+
+    #[test]
+    fn eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = vec![0; 1000];
+        let now = SystemTime::now();
+        let unbounded = cache(dir.path());
+
+        // Three responses, used one second apart.
+        for (i, key) in [b"a", b"b", b"c"].iter().enumerate() {
+            unbounded
+                .store("ledger.balance", *key, &payload, now)
+                .unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(unbounded.path("ledger.balance", *key))
+                .unwrap()
+                .set_modified(now - Duration::from_secs(10 - i as u64))
+                .unwrap();
+        }
+        let size = std::fs::metadata(unbounded.path("ledger.balance", b"a"))
+            .unwrap()
+            .len();
+        let cache = Cache {
+            max_size: size * 3,
+            ..cache(dir.path())
+        };
+
+        // Using the oldest one makes the second the least recently used.
+        assert!(cache.load("ledger.balance", b"a").unwrap().is_some());
+        cache.store("ledger.balance", b"d", &payload, now).unwrap();
+
+        assert_eq!(cache.load("ledger.balance", b"b").unwrap(), None);
+        for key in [b"a", b"c", b"d"] {
+            assert!(cache.load("ledger.balance", key).unwrap().is_some());
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use many_identity::Address;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::debug;
+
+pub const ENV_SERVER: &str = "MANY_LEDGER_SERVER";
+pub const ENV_SERVER_ID: &str = "MANY_LEDGER_SERVER_ID";
+pub const ENV_PEM: &str = "MANY_LEDGER_PEM";
+
+pub const DEFAULT_SERVER: &str = "http://localhost:8000";
+
+/// The options which can have defaults. There is no `Debug` implementation,
+/// so values cannot end up in logs.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub server: Option<String>,
+    pub server_id: Option<String>,
+    pub pem: Option<PathBuf>,
+}
+
+/// The configuration file, e.g.
+///
+/// ```toml
+/// server = "http://localhost:8000"
+/// pem = "~/keys/me.pem"
+///
+/// [profile.staging]
+/// server = "https://staging.example.com/api"
+/// server_id = "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp"
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    server: Option<String>,
+    server_id: Option<String>,
+    pem: Option<PathBuf>,
+
+    #[serde(default)]
+    profile: BTreeMap<String, Profile>,
+}
+
+impl ConfigFile {
+    fn parse(content: &str, path: &Path) -> Result<Self, String> {
+        toml::from_str(content)
+            .map_err(|e| format!("Invalid configuration file {}: {}", path.display(), e))
+    }
+
+    /// The values of a profile, falling back to the top level ones.
+    fn profile(self, name: Option<&str>) -> Result<Profile, String> {
+        let Self {
+            server,
+            server_id,
+            pem,
+            mut profile,
+        } = self;
+        let top = Profile {
+            server,
+            server_id,
+            pem,
+        };
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(top),
+        };
+
+        let selected = profile
+            .remove(name)
+            .ok_or_else(|| format!("Unknown profile '{}'.", name))?;
+        Ok(Profile {
+            server: selected.server.or(top.server),
+            server_id: selected.server_id.or(top.server_id),
+            pem: selected.pem.or(top.pem),
+        })
+    }
+}
+
+/// The options after applying the defaults.
+pub struct Resolved {
+    pub server: String,
+    pub server_id: Address,
+    pub pem: Option<PathBuf>,
+}
+
+/// The default path of the configuration file.
+pub fn default_path(env: &dyn Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let base = match env("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env("HOME")?).join(".config"),
+    };
+    Some(base.join("many-ledger").join("config.toml"))
+}
+
+/// Replace a leading `~` by the home directory, as a shell would.
+fn expand_home(path: PathBuf, env: &dyn Fn(&str) -> Option<String>) -> PathBuf {
+    match (path.strip_prefix("~"), env("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path,
+    }
+}
+
+/// Read the configuration file. A missing file is only an error if its path
+/// was given explicitly or a profile is selected.
+fn read_config(
+    path: Option<PathBuf>,
+    profile: Option<&str>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Profile, String> {
+    let (path, explicit) = match path {
+        Some(path) => (path, true),
+        None => match default_path(env) {
+            Some(path) => (path, false),
+            None => return ConfigFile::default().profile(profile),
+        },
+    };
+
+    let config = match std::fs::read_to_string(&path) {
+        Ok(content) => ConfigFile::parse(&content, &path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => ConfigFile::default(),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+    };
+    debug!("Read the configuration file {}", path.display());
+    config.profile(profile)
+}
+
+/// The first value set and where it comes from. Only the source is logged,
+/// as values like the server URL can hold credentials.
+fn pick<T>(name: &str, values: [Option<T>; 3]) -> Option<(&'static str, T)> {
+    let (source, value) = ["flag", "environment", "configuration file"]
+        .into_iter()
+        .zip(values)
+        .find_map(|(source, value)| Some((source, value?)))?;
+    debug!("Using the {} from the {}", name, source);
+    Some((source, value))
+}
+
+/// Apply the defaults to the options given as flags. Each option is taken
+/// from, in order of precedence, its flag, its environment variable, the
+/// selected profile of the configuration file, the top level of the file,
+/// and the built-in default.
+pub fn resolve(
+    flags: Profile,
+    profile: Option<&str>,
+    config_path: Option<PathBuf>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Resolved, String> {
+    let var = |name: &str| env(name).filter(|v| !v.is_empty());
+    let file = read_config(config_path, profile, env)?;
+
+    let server = pick("server", [flags.server, var(ENV_SERVER), file.server])
+        .map_or_else(|| DEFAULT_SERVER.to_string(), |(_, server)| server);
+
+    let server_id = match pick(
+        "server ID",
+        [flags.server_id, var(ENV_SERVER_ID), file.server_id],
+    ) {
+        Some((source, id)) => Address::from_str(&id)
+            .map_err(|e| format!("Invalid server ID from the {}: {}", source, e))?,
+        None => Address::anonymous(),
+    };
+
+    let pem = pick(
+        "PEM file",
+        [
+            flags.pem,
+            var(ENV_PEM).map(PathBuf::from),
+            file.pem.map(|p| expand_home(p, env)),
+        ],
+    )
+    .map(|(_, pem)| pem);
+
+    Ok(Resolved {
+        server,
+        server_id,
+        pem,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_ID: &str = "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp";
+
+    const CONFIG: &str = r#"
+        server = "http://file:8000"
+        pem = "~/keys/me.pem"
+
+        [profile.staging]
+        server = "https://staging:8000"
+        server_id = "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp"
+
+        [profile.other]
+        pem = "/other.pem"
+    "#;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone())
+    }
+
+    fn config_file(content: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        (dir, path)
+    }
+
+    fn flags(server: Option<&str>) -> Profile {
+        Profile {
+            server: server.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn built_in_defaults() {
+        let resolved = resolve(Profile::default(), None, None, &env(&[])).unwrap();
+        assert_eq!(resolved.server, DEFAULT_SERVER);
+        assert!(resolved.server_id.is_anonymous());
+        assert_eq!(resolved.pem, None);
+    }
+
+    #[test]
+    fn precedence() {
+        let (_dir, path) = config_file(CONFIG);
+        let vars = env(&[(ENV_SERVER, "http://env:8000"), ("HOME", "/home/me")]);
+
+        let resolved = resolve(
+            flags(Some("http://flag:8000")),
+            None,
+            Some(path.clone()),
+            &vars,
+        );
+        assert_eq!(resolved.unwrap().server, "http://flag:8000");
+
+        let resolved = resolve(flags(None), None, Some(path.clone()), &vars).unwrap();
+        assert_eq!(resolved.server, "http://env:8000");
+        assert_eq!(resolved.pem, Some(PathBuf::from("/home/me/keys/me.pem")));
+
+        let resolved = resolve(flags(None), None, Some(path), &env(&[])).unwrap();
+        assert_eq!(resolved.server, "http://file:8000");
+
+        // An empty variable is unset.
+        let resolved = resolve(flags(None), None, None, &env(&[(ENV_SERVER, "")])).unwrap();
+        assert_eq!(resolved.server, DEFAULT_SERVER);
+    }
+
+    #[test]
+    fn profiles() {
+        let (_dir, path) = config_file(CONFIG);
+        let vars = env(&[("HOME", "/home/me")]);
+
+        let resolved = resolve(flags(None), Some("staging"), Some(path.clone()), &vars).unwrap();
+        assert_eq!(resolved.server, "https://staging:8000");
+        assert_eq!(resolved.server_id, Address::from_str(SERVER_ID).unwrap());
+        // Values missing from the profile are taken from the top level.
+        assert_eq!(resolved.pem, Some(PathBuf::from("/home/me/keys/me.pem")));
+
+        let resolved = resolve(flags(None), Some("other"), Some(path.clone()), &vars).unwrap();
+        assert_eq!(resolved.server, "http://file:8000");
+        assert_eq!(resolved.pem, Some(PathBuf::from("/other.pem")));
+
+        let err = resolve(flags(None), Some("prod"), Some(path), &vars).err();
+        assert_eq!(err.as_deref(), Some("Unknown profile 'prod'."));
+        assert!(resolve(flags(None), Some("staging"), None, &env(&[])).is_err());
+    }
+
+    #[test]
+    fn default_path_from_env() {
+        assert_eq!(
+            default_path(&env(&[("XDG_CONFIG_HOME", "/cfg"), ("HOME", "/home/me")])),
+            Some(PathBuf::from("/cfg/many-ledger/config.toml"))
+        );
+        assert_eq!(
+            default_path(&env(&[("HOME", "/home/me")])),
+            Some(PathBuf::from("/home/me/.config/many-ledger/config.toml"))
+        );
+        assert_eq!(default_path(&env(&[])), None);
+    }
+
+    #[test]
+    fn missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let vars = env(&[("HOME", dir.path().to_str().unwrap())]);
+
+        // The default file is optional, but not one given explicitly.
+        assert!(resolve(flags(None), None, None, &vars).is_ok());
+        assert!(resolve(flags(None), None, Some(dir.path().join("nope.toml")), &vars).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let (_dir, path) = config_file("server = http://no-quotes\n");
+        let err = resolve(flags(None), None, Some(path.clone()), &env(&[]))
+            .err()
+            .unwrap();
+        assert!(err.starts_with("Invalid configuration file"), "{}", err);
+        assert!(err.contains(&path.display().to_string()));
+        assert!(err.contains("line 1"), "{}", err);
+
+        let (_dir, path) = config_file("sever = \"http://typo\"\n");
+        let err = resolve(flags(None), None, Some(path), &env(&[]))
+            .err()
+            .unwrap();
+        assert!(err.contains("sever"), "{}", err);
+
+        let (_dir, path) = config_file("server_id = \"nope\"\n");
+        let err = resolve(flags(None), None, Some(path), &env(&[]))
+            .err()
+            .unwrap();
+        assert!(
+            err.starts_with("Invalid server ID from the configuration file"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use crate::amount::AmountArg;
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeMap;
+use tracing::info;
+
+// These types mirror the ones of the many-ledger server.
+
+#[derive(Debug, Decode)]
+#[cbor(index_only)]
+enum EscrowState {
+    #[n(0)]
+    Pending,
+    #[n(1)]
+    Released,
+    #[n(2)]
+    Refunded,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct EscrowInfo {
+    #[n(0)]
+    payer: Address,
+
+    #[n(1)]
+    payee: Address,
+
+    #[n(2)]
+    arbiter: Option<Address>,
+
+    #[n(3)]
+    symbol: Symbol,
+
+    #[n(4)]
+    amount: TokenAmount,
+
+    #[n(5)]
+    timeout: Timestamp,
+
+    #[n(6)]
+    state: EscrowState,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct EscrowCreateArgs {
+    #[n(0)]
+    from: Option<Address>,
+
+    #[n(1)]
+    payee: Address,
+
+    #[n(2)]
+    symbol: Symbol,
+
+    #[n(3)]
+    amount: TokenAmount,
+
+    #[n(4)]
+    timeout_in_secs: u64,
+
+    #[n(5)]
+    arbiter: Option<Address>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct EscrowCreateReturns {
+    #[n(0)]
+    escrow: Address,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct EscrowArgs {
+    #[n(0)]
+    escrow: Address,
+}
+
+/// The `ledger.balance` return value. Servers with escrow support also
+/// return the amounts locked in pending escrows.
+#[derive(Decode)]
+#[cbor(map)]
+pub(crate) struct BalanceReturns {
+    #[n(0)]
+    pub balances: BTreeMap<Symbol, TokenAmount>,
+
+    #[n(1)]
+    pub escrowed: Option<BTreeMap<Symbol, TokenAmount>>,
+}
+
+#[derive(Parser)]
+pub struct CommandOpt {
+    #[clap(subcommand)]
+    /// Escrow subcommand to execute.
+    subcommand: SubcommandOpt,
+}
+
+#[derive(Parser)]
+enum SubcommandOpt {
+    /// Lock tokens in a new escrow for a payee.
+    Create(CreateOpt),
+
+    /// Release the tokens of an escrow to its payee.
+    Release(EscrowOpt),
+
+    /// Refund the tokens of an escrow to its payer.
+    Refund(EscrowOpt),
+
+    /// Show the information of an escrow.
+    Info(EscrowOpt),
+}
+
+#[derive(Parser)]
+struct CreateOpt {
+    /// The payer account, if different than the one provided by the
+    /// PEM argument.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    account: Option<Address>,
+
+    /// An arbiter that can release or refund the escrow at any time.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    arbiter: Option<Address>,
+
+    /// The time after which the escrow is refunded to the payer.
+    #[clap(long)]
+    timeout: humantime::Duration,
+
+    /// The payee identity.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    identity: Address,
+
+    /// The amount of tokens.
+    amount: AmountArg,
+
+    /// The symbol to use. This can either be an identity or
+    /// a local name for a symbol.
+    symbol: String,
+}
+
+#[derive(Parser)]
+struct EscrowOpt {
+    /// The escrow address, obtained when creating the escrow.
+    escrow: Address,
+}
+
+fn create(client: ManyClient<impl Identity>, opts: CreateOpt) -> Result<(), ManyError> {
+    let CreateOpt {
+        account,
+        arbiter,
+        timeout,
+        identity,
+        amount,
+        symbol,
+    } = opts;
+    let amount = TokenAmount::from(amount.base_units()?);
+    let symbol = crate::resolve_symbol(&client, symbol)?;
+
+    let arguments = EscrowCreateArgs {
+        from: account,
+        payee: identity,
+        symbol,
+        amount,
+        timeout_in_secs: timeout.as_secs(),
+        arbiter,
+    };
+    let response = crate::transport::call(&client, "ledger.escrowCreate", arguments)?;
+
+    let payload = crate::wait_response(&client, response)?;
+    let result: EscrowCreateReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    info!("Escrow: {}", result.escrow);
+    println!("{}", result.escrow);
+    Ok(())
+}
+
+fn release(client: ManyClient<impl Identity>, opts: EscrowOpt) -> Result<(), ManyError> {
+    let arguments = EscrowArgs {
+        escrow: opts.escrow,
+    };
+    let response = crate::transport::call(&client, "ledger.escrowRelease", arguments)?;
+    crate::wait_response(&client, response)?;
+
+    info!("Released.");
+    Ok(())
+}
+
+fn refund(client: ManyClient<impl Identity>, opts: EscrowOpt) -> Result<(), ManyError> {
+    let arguments = EscrowArgs {
+        escrow: opts.escrow,
+    };
+    let response = crate::transport::call(&client, "ledger.escrowRefund", arguments)?;
+    crate::wait_response(&client, response)?;
+
+    info!("Refunded.");
+    Ok(())
+}
+
+fn info(client: ManyClient<impl Identity>, opts: EscrowOpt) -> Result<(), ManyError> {
+    let arguments = EscrowArgs {
+        escrow: opts.escrow,
+    };
+    let payload = crate::transport::call_(&client, "ledger.escrowInfo", arguments)?;
+    let EscrowInfo {
+        payer,
+        payee,
+        arbiter,
+        symbol,
+        amount,
+        timeout,
+        state,
+    } = minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    println!("State:   {:?}", state);
+    println!("Payer:   {}", payer);
+    println!("Payee:   {}", payee);
+    if let Some(arbiter) = arbiter {
+        println!("Arbiter: {}", arbiter);
+    }
+    println!("Amount:  {} {}", amount, symbol);
+    println!(
+        "Timeout: {}",
+        humantime::format_rfc3339_seconds(timeout.as_system_time()?)
+    );
+    Ok(())
+}
+
+pub fn escrow(client: ManyClient<impl Identity>, opts: CommandOpt) -> Result<(), ManyError> {
+    match opts.subcommand {
+        SubcommandOpt::Create(sub_opts) => create(client, sub_opts),
+        SubcommandOpt::Release(sub_opts) => release(client, sub_opts),
+        SubcommandOpt::Refund(sub_opts) => refund(client, sub_opts),
+        SubcommandOpt::Info(sub_opts) => info(client, sub_opts),
+    }
+}
@@ -0,0 +1,256 @@
+use crate::history::{self, Listed};
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::Identity;
+use many_modules::events;
+use many_types::ledger::Symbol;
+use many_types::CborRange;
+use minicbor::Decode;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::Bound;
+
+// Mirrors the `events.info` return value of the MANY specification. Only
+// the total is read.
+#[derive(Decode)]
+#[cbor(map)]
+struct InfoReturns {
+    #[n(0)]
+    total: u64,
+}
+
+#[derive(Parser)]
+pub struct CommandOpt {
+    #[clap(subcommand)]
+    /// Event subcommand to execute.
+    subcommand: SubcommandOpt,
+}
+
+#[derive(Parser)]
+enum SubcommandOpt {
+    /// Show an event with all its fields.
+    Get(GetOpt),
+
+    /// Show the number of events of the server.
+    Count,
+}
+
+#[derive(Parser)]
+struct GetOpt {
+    /// The event ID, in hexadecimal as printed by `history`.
+    #[clap(parse(try_from_str = parse_event_id))]
+    id: events::EventId,
+}
+
+fn parse_event_id(s: &str) -> Result<events::EventId, String> {
+    let s = s.trim();
+    history::parse_event_id(s.strip_prefix("0x").unwrap_or(s))
+        .map_err(|e| format!("Invalid event ID '{}': {}", s, e))
+}
+
+/// The name of the type of an event, e.g. `AccountMultisigApprove`.
+fn type_name(content: &events::EventInfo) -> String {
+    format!("{:?}", content)
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Print an event, with a line per field. Events of types this client does
+/// not know are shown as CBOR.
+fn describe(
+    out: &mut impl Write,
+    listed: &Listed,
+    local_names: &BTreeMap<Symbol, String>,
+) -> std::io::Result<()> {
+    let id = listed
+        .id()
+        .map_or_else(|| "?".to_string(), |id| hex::encode(id.as_ref()));
+    writeln!(out, "Event {}", id)?;
+    let log = match listed {
+        Listed::Event(log) => log,
+        Listed::Unknown(cbor) => {
+            writeln!(out, "  Type:   unknown")?;
+            return writeln!(out, "  CBOR:   {}", minicbor::display(cbor));
+        }
+    };
+
+    match log.time.as_system_time() {
+        Ok(time) => writeln!(out, "  Time:   {}", history::rfc3339(time))?,
+        Err(_) => writeln!(out, "  Time:   {:?}", log.time)?,
+    }
+    writeln!(out, "  Type:   {}", type_name(&log.content))?;
+    match &log.content {
+        events::EventInfo::Send {
+            from,
+            to,
+            symbol,
+            amount,
+        } => {
+            writeln!(out, "  From:   {}", from)?;
+            writeln!(out, "  To:     {}", to)?;
+            writeln!(
+                out,
+                "  Amount: {} {}",
+                amount,
+                history::symbol_name(local_names, symbol)
+            )?;
+        }
+        content => {
+            // The fields of the other types, without the type name and the
+            // braces around them.
+            let debug = format!("{:#?}", content);
+            let lines: Vec<&str> = debug.lines().collect();
+            if lines.len() > 2 {
+                for line in lines[1..lines.len() - 1].iter().copied() {
+                    let line = line.strip_prefix("    ").unwrap_or(line);
+                    writeln!(out, "  {}", line.trim_end_matches(','))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The event with this ID, if the server has it.
+fn get_event(client: &ManyClient<impl Identity>, id: events::EventId) -> Result<Listed, ManyError> {
+    let filter = events::EventFilter {
+        id_range: Some(CborRange {
+            start: Bound::Included(id.clone()),
+            end: Bound::Included(id.clone()),
+        }),
+        ..events::EventFilter::default()
+    };
+    let payload = crate::cache::call_(
+        client,
+        "events.list",
+        events::ListArgs {
+            count: Some(1),
+            order: None,
+            filter: Some(filter),
+        },
+    )?;
+    history::decode_list(&payload)?
+        .into_iter()
+        .find(|listed| listed.id().as_ref() == Some(&id))
+        .ok_or_else(|| ManyError::unknown(format!("No event {}.", hex::encode(id.as_ref()))))
+}
+
+fn get(client: ManyClient<impl Identity>, opts: GetOpt) -> Result<(), ManyError> {
+    let listed = get_event(&client, opts.id)?;
+    let local_names = crate::cache::local_names(&client)?;
+    describe(&mut std::io::stdout(), &listed, &local_names)
+        .map_err(|e| ManyError::unknown(e.to_string()))
+}
+
+fn count(client: ManyClient<impl Identity>) -> Result<(), ManyError> {
+    let payload = crate::transport::call_(&client, "events.info", ())?;
+    let info: InfoReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    println!("{}", info.total);
+    Ok(())
+}
+
+pub fn event(client: ManyClient<impl Identity>, opts: CommandOpt) -> Result<(), ManyError> {
+    match opts.subcommand {
+        SubcommandOpt::Get(sub_opts) => get(client, sub_opts),
+        SubcommandOpt::Count => count(client),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use many_identity::Address;
+    use many_types::ledger::TokenAmount;
+    use many_types::Timestamp;
+    use std::str::FromStr;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn describe_to_string(listed: &Listed, local_names: &BTreeMap<Symbol, String>) -> String {
+        let mut out = Vec::new();
+        describe(&mut out, listed, local_names).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn ids() {
+        let id = events::EventId::from(vec![0, 0, 0, 12, 0, 0, 0, 1]);
+        assert_eq!(parse_event_id("0000000c00000001"), Ok(id.clone()));
+        assert_eq!(parse_event_id("0x0000000c00000001"), Ok(id));
+        assert!(parse_event_id("event 12")
+            .unwrap_err()
+            .starts_with("Invalid event ID 'event 12'"));
+    }
+
+    #[test]
+    fn send() {
+        let listed = Listed::Event(events::EventLog {
+            id: events::EventId::from(vec![0, 0, 0, 12, 0, 0, 0, 1]),
+            time: Timestamp::new(1_655_000_000).unwrap(),
+            content: events::EventInfo::Send {
+                from: address(1),
+                to: address(2),
+                symbol: address(3),
+                amount: TokenAmount::from(1_500u64),
+            },
+        });
+        let local_names = BTreeMap::from([(address(3), "MFX".to_string())]);
+        assert_eq!(
+            describe_to_string(&listed, &local_names),
+            format!(
+                "Event 0000000c00000001\n  \
+                 Time:   2022-06-12T02:13:20Z\n  \
+                 Type:   Send\n  \
+                 From:   {}\n  \
+                 To:     {}\n  \
+                 Amount: 1500 MFX\n",
+                address(1),
+                address(2)
+            )
+        );
+    }
+
+    #[test]
+    fn other_types() {
+        let listed = Listed::Event(events::EventLog {
+            id: events::EventId::from(vec![0, 0, 0, 14]),
+            time: Timestamp::new(1_655_000_000).unwrap(),
+            content: events::EventInfo::AccountMultisigApprove {
+                account: address(1),
+                token: vec![1, 2].into(),
+                approver: address(2),
+            },
+        });
+        let description = describe_to_string(&listed, &BTreeMap::new());
+        let lines: Vec<&str> = description.lines().collect();
+        assert_eq!(lines[2], "  Type:   AccountMultisigApprove");
+        assert!(lines[3].starts_with("  account: "));
+        assert!(lines.iter().any(|line| line.starts_with("  token: ")));
+        assert!(lines.iter().any(|line| line.starts_with("  approver: ")));
+    }
+
+    #[test]
+    fn unknown_type() {
+        let mut e = minicbor::Encoder::new(Vec::new());
+        e.map(3).unwrap();
+        e.u8(0).unwrap().bytes(&[0, 0, 0, 13]).unwrap();
+        e.u8(1).unwrap().u64(1_655_000_000).unwrap();
+        e.u8(2).unwrap().map(1).unwrap().u16(9999).unwrap();
+        e.str("new").unwrap();
+        let listed = Listed::Unknown(e.into_writer());
+
+        let description = describe_to_string(&listed, &BTreeMap::new());
+        assert!(
+            description.starts_with("Event 0000000d\n  Type:   unknown\n  CBOR:   {0: h'0000000d'")
+        );
+    }
+}
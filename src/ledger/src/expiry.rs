@@ -0,0 +1,359 @@
+use crate::transport::ManyClient;
+use many_error::{define_attribute_many_error, ManyError};
+use many_identity::Identity;
+use many_modules::base;
+use many_types::attributes::Attribute;
+use minicbor::{Decode, Decoder, Encode};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// The error of the ledger rejecting a message outside of the window of its
+// method, to tell it from the others.
+define_attribute_many_error!(
+    attribute 2 => {
+        45: fn message_stale(method, timestamp, now, not_before, not_after)
+            => "The timestamp {timestamp} of the message is outside of the window of '{method}', from {not_before} to {not_after} around the server time {now} (seconds since the epoch). Sign it again.",
+    }
+);
+
+/// The attribute of the status of the ledger answering
+/// `ledger.replayProtection`.
+const REPLAY_PROTECTION: Attribute = Attribute::id(2002);
+
+/// The governance parameter of the default expiry window, in seconds.
+const EXPIRY_PARAM: &str = "tx.expiryInSecs";
+
+/// The governance parameter of the expiry windows of specific methods.
+const EXPIRY_OVERRIDES_PARAM: &str = "tx.expiryOverrides";
+
+#[derive(Encode)]
+#[cbor(map)]
+struct ParamsArgs {}
+
+/// The values of the expiry parameters, as the ledger encodes them. Other
+/// parameters are skipped.
+#[derive(Encode, Decode)]
+enum ParamValue {
+    #[n(1)]
+    Integer(#[n(0)] u64),
+    #[n(4)]
+    IntegerMap(#[n(0)] BTreeMap<String, u64>),
+}
+
+#[derive(Encode, Decode)]
+#[cbor(map)]
+struct ParamInfo {
+    #[n(1)]
+    value: ParamValue,
+}
+
+/// The expiry parameters of the ledger.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExpiryParams {
+    pub default: u64,
+    pub overrides: BTreeMap<String, u64>,
+}
+
+impl ExpiryParams {
+    /// The window of a method in seconds, or `None` if the ledger executes
+    /// its messages whatever their timestamp.
+    pub fn window(&self, method: &str) -> Option<u64> {
+        let window = self.overrides.get(method).copied().unwrap_or(self.default);
+        (window != 0).then_some(window)
+    }
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct ReplayProtectionArgs {}
+
+/// The windows of the replay protection of the ledger, as
+/// `ledger.replayProtection` returns them. The other fields are skipped.
+#[derive(Decode)]
+#[cbor(map)]
+struct ReplayProtection {
+    #[n(1)]
+    window: u64,
+    #[n(2)]
+    overrides: BTreeMap<String, u64>,
+}
+
+fn decode_replay_protection(payload: &[u8]) -> Result<ExpiryParams, minicbor::decode::Error> {
+    let replay: ReplayProtection = minicbor::decode(payload)?;
+    Ok(ExpiryParams {
+        default: replay.window,
+        overrides: replay.overrides,
+    })
+}
+
+/// The expiry parameters of the ledger, if its status tells it answers
+/// `ledger.replayProtection`.
+pub fn advertised(
+    client: &ManyClient<impl Identity>,
+    status: &base::Status,
+) -> Option<ExpiryParams> {
+    if !status
+        .attributes
+        .iter()
+        .any(|a| a.id == REPLAY_PROTECTION.id)
+    {
+        return None;
+    }
+    let payload =
+        match crate::transport::call_(client, "ledger.replayProtection", ReplayProtectionArgs {}) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Could not read the replay protection of the ledger: {}", e);
+                return None;
+            }
+        };
+    match decode_replay_protection(&payload) {
+        Ok(params) => Some(params),
+        Err(e) => {
+            warn!(
+                "Could not decode the replay protection of the ledger: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// A hint to fix a message the ledger rejected for its timestamp, from the
+/// server time and the window in the error. The clock of this machine is
+/// blamed if it is itself outside of the window, the message otherwise.
+pub fn stale_hint(e: &ManyError, now: SystemTime) -> Option<String> {
+    if e.code() != message_stale("", "", "", "", "").code() {
+        return None;
+    }
+    let arg = |name| e.argument(name)?.parse::<i128>().ok();
+    let (timestamp, server_now, not_after) = (arg("timestamp")?, arg("now")?, arg("not_after")?);
+    let window = not_after - server_now;
+    let local = now.duration_since(UNIX_EPOCH).ok()?.as_secs() as i128;
+
+    let skew = local - server_now;
+    let direction = |d: i128| if d > 0 { "ahead of" } else { "behind" };
+    Some(if skew.abs() > window {
+        format!(
+            "The clock of this machine is {}s {} the server, which accepts {}s. \
+             Synchronize it, e.g. with NTP, and sign the message again.",
+            skew.abs(),
+            direction(skew),
+            window
+        )
+    } else {
+        let offset = timestamp - server_now;
+        format!(
+            "The message was signed {}s {} the server time, which accepts {}s. \
+             Check the clock of the machine which signed it, and sign it again.",
+            offset.abs(),
+            direction(offset),
+            window
+        )
+    })
+}
+
+/// Read the expiry parameters from the response of `governance.params`.
+fn decode_params(payload: &[u8]) -> Result<ExpiryParams, minicbor::decode::Error> {
+    let mut params = ExpiryParams::default();
+    let mut d = Decoder::new(payload);
+    let len = d.map()?.unwrap_or_default();
+    for _ in 0..len {
+        if d.u32()? != 0 {
+            d.skip()?;
+            continue;
+        }
+        let count = d.map()?.unwrap_or_default();
+        for _ in 0..count {
+            match (d.str()?, d.probe().decode::<ParamInfo>()) {
+                (
+                    EXPIRY_PARAM,
+                    Ok(ParamInfo {
+                        value: ParamValue::Integer(n),
+                    }),
+                ) => params.default = n,
+                (
+                    EXPIRY_OVERRIDES_PARAM,
+                    Ok(ParamInfo {
+                        value: ParamValue::IntegerMap(map),
+                    }),
+                ) => params.overrides = map,
+                _ => {}
+            }
+            d.skip()?;
+        }
+    }
+    Ok(params)
+}
+
+/// The expiry parameters of the ledger. Through the cache, so envelopes can
+/// be signed offline with the parameters read last.
+pub fn params(client: &ManyClient<impl Identity>) -> Result<ExpiryParams, ManyError> {
+    let payload = crate::cache::call_(client, "governance.params", ParamsArgs {})?;
+    decode_params(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))
+}
+
+/// Until when the ledger executes a message signed at `signed_at`.
+pub fn expires_at(signed_at: SystemTime, window: Option<u64>) -> Option<SystemTime> {
+    window.map(|w| signed_at + Duration::from_secs(w))
+}
+
+/// Sign a command without sending it, and print its envelope with the time
+/// until which the ledger executes it, per the window of the method. The
+/// envelope is stamped if its transfer was not checked against the server
+/// limits.
+pub fn print_signed<A: Encode<()>>(
+    client: &ManyClient<impl Identity>,
+    method: &str,
+    arguments: A,
+    unchecked: bool,
+) -> Result<(), ManyError> {
+    let window = match params(client) {
+        Ok(params) => params.window(method),
+        Err(e) => {
+            warn!("Could not read the expiry window of {}: {}", method, e);
+            None
+        }
+    };
+    let mut envelope = crate::attachment::signed_request(method, arguments, None)?;
+    if unchecked {
+        envelope = crate::limits::stamp_unchecked(&envelope)?;
+    }
+    crate::output::print_envelope(&envelope, expires_at(SystemTime::now(), window));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minicbor::Encoder;
+
+    /// A response of `governance.params` with the expiry parameters, if
+    /// any, among parameters of other types.
+    fn response(default: Option<u64>, overrides: Option<BTreeMap<String, u64>>) -> Vec<u8> {
+        let mut e = Encoder::new(vec![]);
+        let count = 2 + default.is_some() as u64 + overrides.is_some() as u64;
+        e.map(1).unwrap().u32(0).unwrap().map(count).unwrap();
+
+        // `args.strict`, a boolean, and `governance`, an address.
+        e.str("args.strict").unwrap();
+        e.map(3).unwrap();
+        e.u32(0).unwrap().u32(0).unwrap();
+        e.u32(1).unwrap();
+        e.array(2)
+            .unwrap()
+            .u32(0)
+            .unwrap()
+            .array(1)
+            .unwrap()
+            .bool(true)
+            .unwrap();
+        e.u32(2).unwrap().bool(false).unwrap();
+        e.str("governance").unwrap();
+        e.map(3).unwrap();
+        e.u32(0).unwrap().u32(3).unwrap();
+        e.u32(1).unwrap();
+        e.array(2).unwrap().u32(3).unwrap().array(1).unwrap();
+        e.bytes(&[0]).unwrap();
+        e.u32(2).unwrap().bool(true).unwrap();
+
+        if let Some(n) = default {
+            e.str(EXPIRY_PARAM).unwrap();
+            e.encode(ParamInfo {
+                value: ParamValue::Integer(n),
+            })
+            .unwrap();
+        }
+        if let Some(map) = overrides {
+            e.str(EXPIRY_OVERRIDES_PARAM).unwrap();
+            e.encode(ParamInfo {
+                value: ParamValue::IntegerMap(map),
+            })
+            .unwrap();
+        }
+        e.into_writer()
+    }
+
+    #[test]
+    fn windows() {
+        let overrides = BTreeMap::from([
+            ("ledger.send".to_string(), 300),
+            ("account.multisigApprove".to_string(), 86_400),
+            ("account.multisigExecute".to_string(), 0),
+        ]);
+        let params = decode_params(&response(Some(600), Some(overrides.clone()))).unwrap();
+        assert_eq!(
+            params,
+            ExpiryParams {
+                default: 600,
+                overrides: overrides.clone(),
+            }
+        );
+        assert_eq!(params.window("ledger.send"), Some(300));
+        assert_eq!(params.window("account.multisigApprove"), Some(86_400));
+        assert_eq!(params.window("account.multisigExecute"), None);
+        assert_eq!(params.window("account.create"), Some(600));
+
+        // Ledgers without the parameters accept any timestamp.
+        let params = decode_params(&response(None, None)).unwrap();
+        assert_eq!(params, ExpiryParams::default());
+        assert_eq!(params.window("ledger.send"), None);
+        let params = decode_params(&response(None, Some(overrides))).unwrap();
+        assert_eq!(params.window("ledger.send"), Some(300));
+        assert_eq!(params.window("account.create"), None);
+    }
+
+    #[test]
+    fn replay_protection() {
+        // `clock`, `window`, `overrides`, `nonce_required` and
+        // `nonce_retention`.
+        let mut e = Encoder::new(vec![]);
+        e.map(5).unwrap();
+        e.u32(0).unwrap().u32(0).unwrap();
+        e.u32(1).unwrap().u64(300).unwrap();
+        e.u32(2)
+            .unwrap()
+            .encode(BTreeMap::from([("ledger.send".to_string(), 60u64)]))
+            .unwrap();
+        e.u32(3).unwrap().bool(false).unwrap();
+        e.u32(4).unwrap().u64(0).unwrap();
+        let params = decode_replay_protection(&e.into_writer()).unwrap();
+        assert_eq!(params.window("ledger.send"), Some(60));
+        assert_eq!(params.window("account.create"), Some(300));
+
+        assert!(decode_replay_protection(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn hint() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let stale =
+            |timestamp| message_stale("ledger.send", timestamp, 1_000_000, 999_700, 1_000_300);
+
+        // This machine is behind the server.
+        let hint = stale_hint(&stale(999_000), at(999_000)).unwrap();
+        assert!(hint.starts_with(
+            "The clock of this machine is 1000s behind the server, which accepts 300s."
+        ));
+
+        // This machine is on time, the message was signed long ago, or by a
+        // machine ahead of the server.
+        let hint = stale_hint(&stale(999_000), at(1_000_005)).unwrap();
+        assert!(hint.starts_with("The message was signed 1000s behind the server time"));
+        let hint = stale_hint(&stale(1_000_400), at(1_000_300)).unwrap();
+        assert!(hint.starts_with("The message was signed 400s ahead of the server time"));
+
+        assert_eq!(stale_hint(&ManyError::unknown("Other."), at(0)), None);
+    }
+
+    #[test]
+    fn expiry() {
+        let signed_at = UNIX_EPOCH + Duration::from_secs(1_655_000_000);
+        assert_eq!(
+            expires_at(signed_at, Some(300)),
+            Some(UNIX_EPOCH + Duration::from_secs(1_655_000_300))
+        );
+        assert_eq!(expires_at(signed_at, None), None);
+    }
+}
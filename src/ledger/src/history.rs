@@ -0,0 +1,970 @@
+use crate::attachment;
+use crate::progress::{self, ExportWriter};
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::{events, ledger};
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::{CborRange, SortOrder, Timestamp};
+use minicbor::data::Type;
+use minicbor::Decoder;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::SystemTime;
+use tracing::info;
+
+/// Number of events requested per `events.list` call.
+const PAGE_SIZE: u64 = 100;
+
+/// Event IDs are the block height shifted by this many bits, plus the index
+/// of the event in the block.
+const HEIGHT_EVENTID_SHIFT: u32 = 32;
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Csv,
+    Ofx,
+}
+
+#[derive(Parser)]
+pub struct HistoryOpt {
+    /// The account to show the history of. If omitted it will use the
+    /// identity of the caller.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    identity: Option<Address>,
+
+    /// Write the history in a format for accounting software instead.
+    #[clap(long, arg_enum)]
+    export: Option<ExportFormat>,
+
+    /// The number of decimals of the symbols, used to convert exported
+    /// amounts. The ledger does not publish decimals, so amounts are in
+    /// base units by default.
+    #[clap(long, default_value_t = 0)]
+    decimals: usize,
+
+    /// Fetch the attachments of the transfers from the key-value store at
+    /// this URL, and check them against their hash.
+    #[clap(long, conflicts_with = "export")]
+    kvstore: Option<String>,
+
+    /// Show at most this many events. The ID of the next event is logged,
+    /// to continue with `--from-id`.
+    #[clap(long, conflicts_with = "export")]
+    count: Option<u64>,
+
+    /// Start from this event ID, in hexadecimal.
+    #[clap(long, conflicts_with = "export", parse(try_from_str = parse_event_id))]
+    from_id: Option<events::EventId>,
+
+    /// Show the history cached by the last successful call with the same
+    /// options, without connecting to the server.
+    #[clap(long, conflicts_with = "kvstore")]
+    offline: bool,
+
+    /// The symbols to show the history of. This can either be an identity or
+    /// a local name for a symbol. All symbols are shown if omitted.
+    #[clap(last = true)]
+    symbols: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EntryKind {
+    Send,
+    Receive,
+}
+
+impl EntryKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Send => "send",
+            EntryKind::Receive => "receive",
+        }
+    }
+}
+
+/// A single token movement of the account.
+struct Entry {
+    id: events::EventId,
+    time: SystemTime,
+    kind: EntryKind,
+    counterparty: Address,
+    symbol: String,
+    amount: TokenAmount,
+    memo: Option<String>,
+}
+
+impl Entry {
+    fn from_event(
+        account: &Address,
+        log: events::EventLog,
+        local_names: &BTreeMap<Symbol, String>,
+    ) -> Result<Option<Self>, ManyError> {
+        let (kind, counterparty, symbol, amount) = match log.content {
+            events::EventInfo::Send {
+                from,
+                to,
+                symbol,
+                amount,
+            } => {
+                if &from == account {
+                    (EntryKind::Send, to, symbol, amount)
+                } else if &to == account {
+                    (EntryKind::Receive, from, symbol, amount)
+                } else {
+                    return Ok(None);
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            id: log.id,
+            time: log.time.as_system_time()?,
+            kind,
+            counterparty,
+            symbol: symbol_name(local_names, &symbol),
+            amount,
+            memo: None,
+        }))
+    }
+
+    fn height(&self) -> u64 {
+        let id = self
+            .id
+            .as_ref()
+            .iter()
+            .fold(0u128, |acc, b| acc.wrapping_shl(8) | *b as u128);
+        (id >> HEIGHT_EVENTID_SHIFT) as u64
+    }
+
+    fn signed_amount(&self, decimals: usize) -> String {
+        format_amount(&self.amount, decimals, self.kind == EntryKind::Send)
+    }
+}
+
+pub(crate) fn parse_event_id(s: &str) -> Result<events::EventId, String> {
+    hex::decode(s)
+        .map(events::EventId::from)
+        .map_err(|e| e.to_string())
+}
+
+/// An event listed by `events.list`, or its CBOR encoding if this client
+/// cannot decode it, e.g. an event type added to the server after it.
+pub(crate) enum Listed {
+    Event(events::EventLog),
+    Unknown(Vec<u8>),
+}
+
+impl Listed {
+    pub(crate) fn id(&self) -> Option<events::EventId> {
+        match self {
+            Listed::Event(log) => Some(log.id.clone()),
+            Listed::Unknown(cbor) => unknown_event_id(cbor),
+        }
+    }
+}
+
+/// The ID of an event which cannot be decoded, from the first field of its
+/// map.
+fn unknown_event_id(cbor: &[u8]) -> Option<events::EventId> {
+    let mut d = Decoder::new(cbor);
+    d.map().ok()?;
+    if d.u8().ok()? != 0 {
+        return None;
+    }
+    Some(events::EventId::from(d.bytes().ok()?.to_vec()))
+}
+
+/// Decode the events of an `events.list` response one by one, keeping the
+/// ones of unknown types as CBOR instead of failing the whole list.
+pub(crate) fn decode_list(payload: &[u8]) -> Result<Vec<Listed>, ManyError> {
+    let error = |e: minicbor::decode::Error| ManyError::deserialization_error(e.to_string());
+    let mut d = Decoder::new(payload);
+    let len = d.map().map_err(error)?;
+    let mut listed = vec![];
+    for _ in 0..len.ok_or_else(|| ManyError::deserialization_error("Indefinite map."))? {
+        d.skip().map_err(error)?;
+        if d.datatype().map_err(error)? != Type::Array {
+            d.skip().map_err(error)?;
+            continue;
+        }
+        let count = d
+            .array()
+            .map_err(error)?
+            .ok_or_else(|| ManyError::deserialization_error("Indefinite array."))?;
+        for _ in 0..count {
+            let start = d.position();
+            d.skip().map_err(error)?;
+            let cbor = &payload[start..d.position()];
+            listed.push(match minicbor::decode(cbor) {
+                Ok(log) => Listed::Event(log),
+                Err(_) => Listed::Unknown(cbor.to_vec()),
+            });
+        }
+    }
+    Ok(listed)
+}
+
+/// One line describing an event of the account: its ID, time, type, and
+/// what moved. Events this client does not know are shown as CBOR.
+fn format_event(
+    account: &Address,
+    listed: &Listed,
+    local_names: &BTreeMap<Symbol, String>,
+    decimals: usize,
+) -> Result<String, ManyError> {
+    let log = match listed {
+        Listed::Event(log) => log,
+        Listed::Unknown(cbor) => {
+            let id = listed
+                .id()
+                .map_or_else(|| "?".to_string(), |id| hex::encode(id.as_ref()));
+            return Ok(format!("{} unknown {}", id, minicbor::display(cbor)));
+        }
+    };
+    if let Some(entry) = Entry::from_event(account, log.clone(), local_names)? {
+        return Ok(format!(
+            "{} {} {:>8} {:>24} {} {}",
+            hex::encode(entry.id.as_ref()),
+            rfc3339(entry.time),
+            entry.kind.as_str(),
+            entry.signed_amount(decimals),
+            entry.symbol,
+            entry.counterparty,
+        ));
+    }
+
+    let details = match &log.content {
+        events::EventInfo::AccountMultisigSubmit {
+            account,
+            token: Some(t),
+            memo,
+            ..
+        } => match memo {
+            Some(memo) => format!(
+                "submit {} on {}: {:?}",
+                hex::encode(t.as_slice()),
+                account,
+                memo.iter_str().cloned().collect::<Vec<_>>().join(" ")
+            ),
+            None => format!("submit {} on {}", hex::encode(t.as_slice()), account),
+        },
+        events::EventInfo::AccountMultisigApprove {
+            account,
+            token: t,
+            approver,
+        } => format!(
+            "approve {} on {} by {}",
+            hex::encode(t.as_slice()),
+            account,
+            approver
+        ),
+        events::EventInfo::AccountMultisigRevoke {
+            account,
+            token: t,
+            revoker,
+        } => format!(
+            "revoke {} on {} by {}",
+            hex::encode(t.as_slice()),
+            account,
+            revoker
+        ),
+        events::EventInfo::AccountMultisigExecute {
+            account, token: t, ..
+        } => format!("execute {} on {}", hex::encode(t.as_slice()), account),
+        events::EventInfo::AccountMultisigWithdraw {
+            account,
+            token: t,
+            withdrawer,
+        } => format!(
+            "withdraw {} on {} by {}",
+            hex::encode(t.as_slice()),
+            account,
+            withdrawer
+        ),
+        content => {
+            let cbor = minicbor::to_vec(content)
+                .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+            return Ok(format!(
+                "{} {} {:>8} {}",
+                hex::encode(log.id.as_ref()),
+                rfc3339(log.time.as_system_time()?),
+                "other",
+                minicbor::display(&cbor)
+            ));
+        }
+    };
+    Ok(format!(
+        "{} {} {:>8} {}",
+        hex::encode(log.id.as_ref()),
+        rfc3339(log.time.as_system_time()?),
+        "multisig",
+        details
+    ))
+}
+
+/// A page of the events of an account, of every type, from an event.
+fn list_page(
+    client: &ManyClient<impl Identity>,
+    account: Address,
+    symbols: &[Symbol],
+    from: Bound<events::EventId>,
+    count: u64,
+) -> Result<Vec<Listed>, ManyError> {
+    let filter = events::EventFilter {
+        account: Some(vec![account].into()),
+        symbol: if symbols.is_empty() {
+            None
+        } else {
+            Some(symbols.to_vec().into())
+        },
+        id_range: Some(CborRange {
+            start: from,
+            end: Bound::Unbounded,
+        }),
+        ..events::EventFilter::default()
+    };
+    decode_list(&crate::cache::call_(
+        client,
+        "events.list",
+        events::ListArgs {
+            count: Some(count),
+            order: Some(SortOrder::Ascending),
+            filter: Some(filter),
+        },
+    )?)
+}
+
+pub(crate) fn symbol_name(local_names: &BTreeMap<Symbol, String>, symbol: &Symbol) -> String {
+    local_names
+        .get(symbol)
+        .cloned()
+        .unwrap_or_else(|| symbol.to_string())
+}
+
+/// Format a base unit amount as a decimal number.
+fn format_amount(amount: &TokenAmount, decimals: usize, negative: bool) -> String {
+    let digits = format!("{:0>width$}", amount.to_string(), width = decimals + 1);
+    let (int, frac) = digits.split_at(digits.len() - decimals);
+    let sign = if negative { "-" } else { "" };
+    if frac.is_empty() {
+        format!("{}{}", sign, int)
+    } else {
+        format!("{}{}.{}", sign, int, frac)
+    }
+}
+
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// OFX dates are `YYYYMMDDHHMMSS`, in UTC.
+fn ofx_date(time: SystemTime) -> String {
+    rfc3339(time).chars().filter(char::is_ascii_digit).collect()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn xml_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+struct CsvWriter<W: Write> {
+    out: W,
+    decimals: usize,
+}
+
+impl<W: Write> CsvWriter<W> {
+    fn new(out: W, decimals: usize) -> std::io::Result<Self> {
+        let mut w = Self { out, decimals };
+        writeln!(
+            w.out,
+            "date,event id,type,counterparty,symbol,amount,memo,block height"
+        )?;
+        Ok(w)
+    }
+
+    fn entry(&mut self, entry: &Entry) -> std::io::Result<()> {
+        writeln!(
+            self.out,
+            "{},{},{},{},{},{},{},{}",
+            rfc3339(entry.time),
+            hex::encode(entry.id.as_ref()),
+            entry.kind.as_str(),
+            entry.counterparty,
+            csv_field(&entry.symbol),
+            entry.signed_amount(self.decimals),
+            csv_field(entry.memo.as_deref().unwrap_or_default()),
+            entry.height(),
+        )
+    }
+}
+
+/// Writes an OFX 2.2 document with one bank statement per symbol.
+struct OfxWriter<W: Write> {
+    out: W,
+    account: Address,
+    decimals: usize,
+}
+
+impl<W: Write> OfxWriter<W> {
+    fn new(
+        mut out: W,
+        account: Address,
+        decimals: usize,
+        now: SystemTime,
+    ) -> std::io::Result<Self> {
+        writeln!(
+            out,
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#
+        )?;
+        writeln!(
+            out,
+            r#"<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>"#
+        )?;
+        writeln!(out, "<OFX>")?;
+        writeln!(out, "<SIGNONMSGSRSV1><SONRS>")?;
+        writeln!(
+            out,
+            "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>"
+        )?;
+        writeln!(out, "<DTSERVER>{}</DTSERVER>", ofx_date(now))?;
+        writeln!(out, "<LANGUAGE>ENG</LANGUAGE>")?;
+        writeln!(out, "</SONRS></SIGNONMSGSRSV1>")?;
+        writeln!(out, "<BANKMSGSRSV1>")?;
+        Ok(Self {
+            out,
+            account,
+            decimals,
+        })
+    }
+
+    fn begin_statement(
+        &mut self,
+        symbol: &str,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> std::io::Result<()> {
+        let out = &mut self.out;
+        writeln!(out, "<STMTTRNRS>")?;
+        writeln!(out, "<TRNUID>0</TRNUID>")?;
+        writeln!(
+            out,
+            "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>"
+        )?;
+        writeln!(out, "<STMTRS>")?;
+        // Tokens are not an ISO 4217 currency.
+        writeln!(out, "<CURDEF>XXX</CURDEF>")?;
+        writeln!(
+            out,
+            "<BANKACCTFROM><BANKID>{}</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>",
+            xml_text(symbol),
+            self.account
+        )?;
+        writeln!(out, "<BANKTRANLIST>")?;
+        writeln!(out, "<DTSTART>{}</DTSTART>", ofx_date(start))?;
+        writeln!(out, "<DTEND>{}</DTEND>", ofx_date(end))
+    }
+
+    fn entry(&mut self, entry: &Entry) -> std::io::Result<()> {
+        let out = &mut self.out;
+        let trntype = match entry.kind {
+            EntryKind::Send => "DEBIT",
+            EntryKind::Receive => "CREDIT",
+        };
+        writeln!(out, "<STMTTRN>")?;
+        writeln!(out, "<TRNTYPE>{}</TRNTYPE>", trntype)?;
+        writeln!(out, "<DTPOSTED>{}</DTPOSTED>", ofx_date(entry.time))?;
+        writeln!(
+            out,
+            "<TRNAMT>{}</TRNAMT>",
+            entry.signed_amount(self.decimals)
+        )?;
+        writeln!(out, "<FITID>{}</FITID>", hex::encode(entry.id.as_ref()))?;
+        let memo = match &entry.memo {
+            Some(memo) => format!("{}: {}", entry.counterparty, memo),
+            None => entry.counterparty.to_string(),
+        };
+        writeln!(out, "<MEMO>{}</MEMO>", xml_text(&memo))?;
+        writeln!(out, "</STMTTRN>")
+    }
+
+    fn end_statement(&mut self, balance: &TokenAmount, as_of: SystemTime) -> std::io::Result<()> {
+        let out = &mut self.out;
+        writeln!(out, "</BANKTRANLIST>")?;
+        writeln!(
+            out,
+            "<LEDGERBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></LEDGERBAL>",
+            format_amount(balance, self.decimals, false),
+            ofx_date(as_of)
+        )?;
+        writeln!(out, "</STMTRS>")?;
+        writeln!(out, "</STMTTRNRS>")
+    }
+
+    fn finish(mut self) -> std::io::Result<()> {
+        writeln!(self.out, "</BANKMSGSRSV1>")?;
+        writeln!(self.out, "</OFX>")?;
+        self.out.flush()
+    }
+}
+
+/// Iterates over the events of an account, one `events.list` page at a
+/// time, so exports never hold more than a page in memory.
+pub(crate) struct EventPages<'a, I: Identity> {
+    client: &'a ManyClient<I>,
+    account: Address,
+    kinds: Vec<events::EventKind>,
+    symbols: Vec<Symbol>,
+    since: Option<Timestamp>,
+    last: Option<events::EventId>,
+    page: std::vec::IntoIter<events::EventLog>,
+    done: bool,
+}
+
+impl<'a, I: Identity> EventPages<'a, I> {
+    fn new(client: &'a ManyClient<I>, account: Address, symbols: Vec<Symbol>) -> Self {
+        Self {
+            client,
+            account,
+            kinds: vec![events::EventKind::Send],
+            symbols,
+            since: None,
+            last: None,
+            page: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    /// The events of the given kinds about an account, after an event or
+    /// from a time.
+    pub(crate) fn with_kinds(
+        client: &'a ManyClient<I>,
+        account: Address,
+        kinds: Vec<events::EventKind>,
+        after: Option<events::EventId>,
+        since: Option<Timestamp>,
+    ) -> Self {
+        Self {
+            client,
+            account,
+            kinds,
+            symbols: vec![],
+            since,
+            last: after,
+            page: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    fn fetch(&self, order: SortOrder, count: u64) -> Result<Vec<events::EventLog>, ManyError> {
+        let start = self.last.clone().map_or(Bound::Unbounded, Bound::Excluded);
+        let filter = events::EventFilter {
+            account: Some(vec![self.account].into()),
+            kind: Some(self.kinds.clone().into()),
+            symbol: if self.symbols.is_empty() {
+                None
+            } else {
+                Some(self.symbols.clone().into())
+            },
+            id_range: Some(CborRange {
+                start,
+                end: Bound::Unbounded,
+            }),
+            date_range: self.since.map(|since| CborRange {
+                start: Bound::Included(since),
+                end: Bound::Unbounded,
+            }),
+            ..events::EventFilter::default()
+        };
+        let payload = crate::cache::call_(
+            self.client,
+            "events.list",
+            events::ListArgs {
+                count: Some(count),
+                order: Some(order),
+                filter: Some(filter),
+            },
+        )?;
+        let list: events::ListReturns = minicbor::decode(&payload)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        Ok(list.events)
+    }
+
+    /// The time of the first and last events, if any.
+    fn time_range(&self) -> Result<Option<(SystemTime, SystemTime)>, ManyError> {
+        let first = self.fetch(SortOrder::Ascending, 1)?;
+        let last = self.fetch(SortOrder::Descending, 1)?;
+        match (first.first(), last.first()) {
+            (Some(first), Some(last)) => Ok(Some((
+                first.time.as_system_time()?,
+                last.time.as_system_time()?,
+            ))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a, I: Identity> Iterator for EventPages<'a, I> {
+    type Item = Result<events::EventLog, ManyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(log) = self.page.next() {
+                self.last = Some(log.id.clone());
+                return Some(Ok(log));
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.fetch(SortOrder::Ascending, PAGE_SIZE) {
+                Ok(page) => {
+                    self.done = (page.len() as u64) < PAGE_SIZE;
+                    self.page = page.into_iter();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// The token transfers of an account, in chronological order.
+fn entries<'a, I: Identity>(
+    client: &'a ManyClient<I>,
+    account: Address,
+    symbols: Vec<Symbol>,
+    local_names: &'a BTreeMap<Symbol, String>,
+) -> impl Iterator<Item = Result<Entry, ManyError>> + 'a {
+    EventPages::new(client, account, symbols).filter_map(move |log| {
+        log.and_then(|log| Entry::from_event(&account, log, local_names))
+            .transpose()
+    })
+}
+
+fn io_error(e: std::io::Error) -> ManyError {
+    ManyError::unknown(e.to_string())
+}
+
+pub fn history(
+    client: ManyClient<impl Identity>,
+    caller: Address,
+    opts: HistoryOpt,
+) -> Result<(), ManyError> {
+    let HistoryOpt {
+        identity,
+        export,
+        decimals,
+        kvstore,
+        count,
+        from_id,
+        offline,
+        symbols,
+    } = opts;
+    let account = identity.unwrap_or(caller);
+    if offline {
+        crate::cache::set_offline();
+    }
+
+    let info: ledger::InfoReturns =
+        minicbor::decode(&crate::cache::call_(&client, "ledger.info", ())?)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    let symbols = symbols
+        .iter()
+        .map(|x| {
+            Address::from_str(x).or_else(|_| {
+                info.local_names
+                    .iter()
+                    .find(|(_, y)| y == &x)
+                    .map(|(s, _)| *s)
+                    .ok_or_else(|| ManyError::unknown(format!("Could not resolve symbol '{}'", x)))
+            })
+        })
+        .collect::<Result<Vec<Symbol>, _>>()?;
+
+    let stdout = std::io::stdout();
+    let mut out = ExportWriter::new(stdout.lock(), progress::get());
+    match export {
+        None => {
+            let mut from = from_id.map_or(Bound::Unbounded, Bound::Included);
+            let mut remaining = count.unwrap_or(u64::MAX);
+            while remaining > 0 {
+                // One more event than shown, to know whether there are more.
+                let page_size = remaining.min(PAGE_SIZE);
+                let page = list_page(&client, account, &symbols, from, page_size + 1)?;
+                let more = page.len() as u64 > page_size;
+                for listed in page.iter().take(page_size as usize) {
+                    println!(
+                        "{}",
+                        format_event(&account, listed, &info.local_names, decimals)?
+                    );
+                    if let (Some(url), Listed::Event(log)) = (&kvstore, listed) {
+                        if matches!(log.content, events::EventInfo::Send { .. }) {
+                            if let Some(attachment) = attachment::of_event(&client, log.id.clone())?
+                            {
+                                println!("    {}", attachment::verify(url, &attachment));
+                            }
+                        }
+                    }
+                }
+                remaining -= page.len().min(page_size as usize) as u64;
+                if !more {
+                    break;
+                }
+                let next = page
+                    .last()
+                    .and_then(Listed::id)
+                    .ok_or_else(|| ManyError::unknown("An event has no ID, cannot continue."))?;
+                if remaining == 0 {
+                    info!(
+                        "More events follow, continue with --from-id {}",
+                        hex::encode(next.as_ref())
+                    );
+                }
+                from = Bound::Included(next);
+            }
+        }
+        Some(ExportFormat::Csv) => {
+            let mut writer = CsvWriter::new(&mut out, decimals).map_err(io_error)?;
+            for entry in entries(&client, account, symbols, &info.local_names) {
+                writer.entry(&entry?).map_err(io_error)?;
+            }
+        }
+        Some(ExportFormat::Ofx) => {
+            let symbols = if symbols.is_empty() {
+                info.symbols.iter().copied().collect()
+            } else {
+                symbols
+            };
+            let balances: ledger::BalanceReturns = minicbor::decode(&crate::cache::call_(
+                &client,
+                "ledger.balance",
+                ledger::BalanceArgs {
+                    account: Some(account),
+                    symbols: Some(symbols.clone().into()),
+                },
+            )?)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+            let now = SystemTime::now();
+            let mut writer = OfxWriter::new(&mut out, account, decimals, now).map_err(io_error)?;
+            for symbol in symbols {
+                let (start, end) = EventPages::new(&client, account, vec![symbol])
+                    .time_range()?
+                    .unwrap_or((now, now));
+                writer
+                    .begin_statement(&symbol_name(&info.local_names, &symbol), start, end)
+                    .map_err(io_error)?;
+                for entry in entries(&client, account, vec![symbol], &info.local_names) {
+                    writer.entry(&entry?).map_err(io_error)?;
+                }
+                writer
+                    .end_statement(
+                        &balances.balances.get(&symbol).cloned().unwrap_or_default(),
+                        now,
+                    )
+                    .map_err(io_error)?;
+            }
+            writer.finish().map_err(io_error)?;
+        }
+    }
+    if export.is_some() {
+        out.finish().map_err(io_error)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn address(s: &str) -> Address {
+        Address::from_str(s).unwrap()
+    }
+
+    fn entries() -> Vec<Entry> {
+        let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        vec![
+            Entry {
+                id: events::EventId::from(vec![0, 0, 0, 12, 0, 0, 0, 1]),
+                time: time(1_655_000_000),
+                kind: EntryKind::Receive,
+                counterparty: address("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow"),
+                symbol: "MFX".to_string(),
+                amount: TokenAmount::from(1_500_000_000u64),
+                memo: None,
+            },
+            Entry {
+                id: events::EventId::from(vec![0, 0, 0, 15, 0, 0, 0, 2]),
+                time: time(1_655_003_600),
+                kind: EntryKind::Send,
+                counterparty: address("mqdukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iaaaaq25"),
+                symbol: "MFX".to_string(),
+                amount: TokenAmount::from(250_000_000u64),
+                memo: Some("Invoice 42, \"rent\" & <fees>".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn amounts() {
+        let amount = TokenAmount::from(1_500u64);
+        assert_eq!(format_amount(&amount, 0, false), "1500");
+        assert_eq!(format_amount(&amount, 3, true), "-1.500");
+        assert_eq!(format_amount(&amount, 6, false), "0.001500");
+    }
+
+    fn send_event() -> events::EventLog {
+        events::EventLog {
+            id: events::EventId::from(vec![0, 0, 0, 12, 0, 0, 0, 1]),
+            time: Timestamp::new(1_655_000_000).unwrap(),
+            content: events::EventInfo::Send {
+                from: address("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow"),
+                to: address("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp"),
+                symbol: address("mqdukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iaaaaq25"),
+                amount: TokenAmount::from(1_500u64),
+            },
+        }
+    }
+
+    #[test]
+    fn unknown_events() {
+        // An events.list response with a send, and an event of an unknown type.
+        let mut e = minicbor::Encoder::new(Vec::new());
+        e.map(2).unwrap().u8(0).unwrap().u64(2).unwrap();
+        e.u8(1)
+            .unwrap()
+            .array(2)
+            .unwrap()
+            .encode(send_event())
+            .unwrap();
+        e.map(3).unwrap();
+        e.u8(0).unwrap().bytes(&[0, 0, 0, 13]).unwrap();
+        e.u8(1).unwrap().u64(1_655_000_000).unwrap();
+        e.u8(2).unwrap().map(1).unwrap().u16(9999).unwrap();
+        e.str("new").unwrap();
+        let payload = e.into_writer();
+
+        let listed = decode_list(&payload).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(matches!(&listed[0], Listed::Event(log) if log.id == send_event().id));
+        assert!(matches!(&listed[1], Listed::Unknown(_)));
+        assert_eq!(
+            listed[1].id(),
+            Some(events::EventId::from(vec![0, 0, 0, 13]))
+        );
+
+        let account = address("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp");
+        let local_names = BTreeMap::new();
+        assert_eq!(
+            format_event(&account, &listed[0], &local_names, 3).unwrap(),
+            format!(
+                "0000000c00000001 2022-06-12T02:13:20Z  receive                    1.500 {} {}",
+                "mqdukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iaaaaq25",
+                "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+            )
+        );
+        assert!(format_event(&account, &listed[1], &local_names, 3)
+            .unwrap()
+            .starts_with("0000000d unknown {0: h'0000000d'"));
+    }
+
+    #[test]
+    fn submit_memo() {
+        let account = address("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp");
+        let submit = |memo: Option<&str>| {
+            Listed::Event(events::EventLog {
+                id: events::EventId::from(vec![0, 0, 0, 14]),
+                time: Timestamp::new(1_655_000_000).unwrap(),
+                content: events::EventInfo::AccountMultisigSubmit {
+                    submitter: account,
+                    account,
+                    memo: memo.map(|m| m.to_string().try_into().unwrap()),
+                    transaction: Box::new(events::AccountMultisigTransaction::Send(
+                        ledger::SendArgs {
+                            from: Some(account),
+                            to: address("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow"),
+                            symbol: address(
+                                "mqdukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iaaaaq25",
+                            ),
+                            amount: TokenAmount::from(1_500u64),
+                        },
+                    )),
+                    token: Some(vec![1, 2].into()),
+                    threshold: 2,
+                    timeout: Timestamp::new(1_655_086_400).unwrap(),
+                    execute_automatically: false,
+                    data: None,
+                },
+            })
+        };
+
+        let local_names = BTreeMap::new();
+        let line = format_event(&account, &submit(Some("Rent")), &local_names, 3).unwrap();
+        assert!(line.ends_with(&format!("submit 0102 on {}: \"Rent\"", account)));
+        let line = format_event(&account, &submit(None), &local_names, 3).unwrap();
+        assert!(line.ends_with(&format!("submit 0102 on {}", account)));
+    }
+
+    #[test]
+    fn golden_csv() {
+        let mut out = Vec::new();
+        let mut writer = CsvWriter::new(&mut out, 9).unwrap();
+        for entry in entries() {
+            writer.entry(&entry).unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            include_str!("../tests/golden/history.csv")
+        );
+    }
+
+    #[test]
+    fn golden_ofx() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_655_100_000);
+        let entries = entries();
+        let mut out = Vec::new();
+        let mut writer = OfxWriter::new(
+            &mut out,
+            address("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp"),
+            9,
+            now,
+        )
+        .unwrap();
+        writer
+            .begin_statement("MFX", entries[0].time, entries[1].time)
+            .unwrap();
+        for entry in &entries {
+            writer.entry(entry).unwrap();
+        }
+        writer
+            .end_statement(&TokenAmount::from(1_250_000_000u64), now)
+            .unwrap();
+        writer.begin_statement("ABC", now, now).unwrap();
+        writer.end_statement(&TokenAmount::default(), now).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            include_str!("../tests/golden/history.ofx")
+        );
+    }
+}
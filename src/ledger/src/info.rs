@@ -0,0 +1,210 @@
+use crate::output::{self, OutputFormat};
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::Identity;
+use many_modules::{events, ledger};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use serde_json::json;
+use std::time::UNIX_EPOCH;
+
+#[derive(Parser)]
+pub struct InfoOpt {
+    /// Show the statistics of the chain instead of its symbols.
+    #[clap(long)]
+    stats: bool,
+}
+
+// These types mirror the ones of the many-ledger server.
+
+#[derive(Encode)]
+#[cbor(map)]
+struct StatsArgs {}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct LastEvent {
+    #[n(0)]
+    id: events::EventId,
+
+    #[n(1)]
+    time: Timestamp,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct StatsReturns {
+    #[n(0)]
+    transactions: u64,
+
+    #[n(1)]
+    events: u64,
+
+    #[n(2)]
+    nonzero_addresses: u64,
+
+    #[n(3)]
+    accounts: u64,
+
+    #[n(4)]
+    symbols: u64,
+
+    #[n(5)]
+    pending_multisig: u64,
+
+    #[n(6)]
+    last_event: Option<LastEvent>,
+}
+
+fn format_stats(stats: &StatsReturns) -> Result<String, ManyError> {
+    let mut lines = vec![
+        format!("Transactions:       {}", stats.transactions),
+        format!("Events:             {}", stats.events),
+        format!("Nonzero addresses:  {}", stats.nonzero_addresses),
+        format!("Accounts:           {}", stats.accounts),
+        format!("Symbols:            {}", stats.symbols),
+        format!("Pending multisig:   {}", stats.pending_multisig),
+    ];
+    if let Some(LastEvent { id, time }) = &stats.last_event {
+        let secs = time
+            .as_system_time()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .as_secs();
+        lines.push(format!("Last event:         {}", hex::encode(id.as_ref())));
+        lines.push(format!(
+            "Last event time:    {} (seconds since the epoch)",
+            secs
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// The state hash of the ledger, and a table of its symbols with their local
+/// names.
+fn format_info(info: &ledger::InfoReturns) -> String {
+    let names: Vec<&str> = info
+        .symbols
+        .iter()
+        .map(|symbol| info.local_names.get(symbol).map_or("-", String::as_str))
+        .collect();
+    let width = names
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+
+    let mut lines = vec![
+        format!("Hash:     {}", hex::encode(info.hash.as_slice())),
+        format!("Symbols:  {}", info.symbols.len()),
+    ];
+    if !info.symbols.is_empty() {
+        lines.push(String::new());
+        lines.push(format!("{:width$}  SYMBOL", "NAME", width = width));
+        for (symbol, name) in info.symbols.iter().zip(names) {
+            lines.push(format!("{:width$}  {}", name, symbol, width = width));
+        }
+    }
+    lines.join("\n")
+}
+
+pub fn info(client: ManyClient<impl Identity>, opts: InfoOpt) -> Result<(), ManyError> {
+    if opts.stats {
+        let payload = crate::transport::call_(&client, "ledger.stats", StatsArgs {})?;
+        if payload.is_empty() {
+            return Err(ManyError::unexpected_empty_response());
+        }
+        let stats: StatsReturns = minicbor::decode(&payload)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        println!("{}", format_stats(&stats)?);
+    } else {
+        let info: ledger::InfoReturns =
+            minicbor::decode(&crate::transport::call_(&client, "ledger.info", ())?)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        match output::get() {
+            OutputFormat::Human => println!("{}", format_info(&info)),
+            OutputFormat::Json => output::print_json(&json!({
+                "hash": hex::encode(info.hash.as_slice()),
+                "symbols": info
+                    .symbols
+                    .iter()
+                    .map(|symbol| json!({
+                        "symbol": symbol.to_string(),
+                        "name": info.local_names.get(symbol),
+                    }))
+                    .collect::<Vec<_>>(),
+            })),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    fn stats(last_event: Option<LastEvent>) -> StatsReturns {
+        StatsReturns {
+            transactions: 12,
+            events: 10,
+            nonzero_addresses: 4,
+            accounts: 2,
+            symbols: 1,
+            pending_multisig: 0,
+            last_event,
+        }
+    }
+
+    #[test]
+    fn symbols_table() {
+        let symbol = |i: u32| {
+            many_identity::Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+                .unwrap()
+                .with_subresource_id(i)
+                .unwrap()
+        };
+        let info = ledger::InfoReturns {
+            symbols: vec![symbol(1), symbol(2)],
+            hash: vec![0xab, 0xcd].into(),
+            local_names: BTreeMap::from([(symbol(1), "MFX".to_string())]),
+        };
+        assert_eq!(
+            format_info(&info),
+            format!(
+                "Hash:     abcd\nSymbols:  2\n\nNAME  SYMBOL\nMFX   {}\n-     {}",
+                symbol(1),
+                symbol(2)
+            )
+        );
+
+        let empty = ledger::InfoReturns {
+            symbols: vec![],
+            hash: vec![].into(),
+            local_names: BTreeMap::new(),
+        };
+        assert_eq!(format_info(&empty), "Hash:     \nSymbols:  0");
+    }
+
+    #[test]
+    fn without_events() {
+        let output = format_stats(&stats(None)).unwrap();
+        assert!(output.starts_with("Transactions:       12\n"));
+        assert!(output.ends_with("Pending multisig:   0"));
+        assert!(!output.contains("Last event"));
+    }
+
+    #[test]
+    fn last_event() {
+        let output = format_stats(&stats(Some(LastEvent {
+            id: events::EventId::from(vec![0, 0, 0, 9]),
+            time: Timestamp::new(1_000).unwrap(),
+        })))
+        .unwrap();
+        assert!(output.contains("Last event:         00000009\n"));
+        assert!(output.ends_with("Last event time:    1000 (seconds since the epoch)"));
+    }
+}
@@ -0,0 +1,519 @@
+use crate::transport::ManyClient;
+use coset::cbor::value::Value;
+use coset::{CborSerializable, CoseSign1, Label};
+use many_error::{ManyError, ManyErrorCode};
+use many_identity::{Address, Identity};
+use many_modules::account::features::multisig::Memo;
+use many_modules::ledger;
+use many_types::ledger::{Symbol, TokenAmount};
+use minicbor::{Decode, Decoder, Encode};
+use tracing::{debug, warn};
+
+/// The limits a server declares for transfers of a symbol.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransferLimits {
+    /// The smallest amount that can be transferred, if any.
+    pub minimum: Option<TokenAmount>,
+
+    /// The fee charged to the sender on top of the amount.
+    pub fee: TokenAmount,
+
+    /// Whether transfers of the symbol are paused.
+    pub paused: bool,
+}
+
+/// Why a transfer would be rejected by the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LimitError {
+    Paused,
+    ZeroAmount,
+    BelowMinimum {
+        amount: TokenAmount,
+        minimum: TokenAmount,
+    },
+    InsufficientFunds {
+        total: TokenAmount,
+        balance: TokenAmount,
+    },
+}
+
+impl std::fmt::Display for LimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitError::Paused => write!(f, "Transfers of this symbol are paused."),
+            LimitError::ZeroAmount => write!(f, "The amount cannot be zero."),
+            LimitError::BelowMinimum { amount, minimum } => write!(
+                f,
+                "The amount {} is below the minimum transfer of {}.",
+                amount, minimum
+            ),
+            LimitError::InsufficientFunds { total, balance } => write!(
+                f,
+                "The total cost of {} (amount and fee) exceeds the balance of {}.",
+                total, balance
+            ),
+        }
+    }
+}
+
+/// Returns what transferring `amount` costs the sender, or why the
+/// server would reject the transfer.
+pub fn total_cost(
+    amount: &TokenAmount,
+    limits: &TransferLimits,
+) -> Result<TokenAmount, LimitError> {
+    if limits.paused {
+        return Err(LimitError::Paused);
+    }
+    if amount.is_zero() {
+        return Err(LimitError::ZeroAmount);
+    }
+    if let Some(minimum) = &limits.minimum {
+        if amount < minimum {
+            return Err(LimitError::BelowMinimum {
+                amount: amount.clone(),
+                minimum: minimum.clone(),
+            });
+        }
+    }
+    Ok(amount.clone() + limits.fee.clone())
+}
+
+/// Validate a transfer against the limits and the balance of the sender,
+/// returning its total cost.
+pub fn validate(
+    amount: &TokenAmount,
+    balance: &TokenAmount,
+    limits: &TransferLimits,
+) -> Result<TokenAmount, LimitError> {
+    let total = total_cost(amount, limits)?;
+    if &total > balance {
+        return Err(LimitError::InsufficientFunds {
+            total,
+            balance: balance.clone(),
+        });
+    }
+    Ok(total)
+}
+
+/// Reject a transfer to its source, which most ledgers reject too, unless
+/// it is explicitly allowed.
+pub fn check_self_transfer(from: &Address, to: &Address, allowed: bool) -> Result<(), ManyError> {
+    if from == to && !allowed {
+        return Err(ManyError::unknown(
+            "The destination is the source. Use --self-transfer if the ledger \
+             records them as no-op events.",
+        ));
+    }
+    Ok(())
+}
+
+/// Check the memo of a transfer before signing it, so a memo which is too
+/// long is reported here rather than by the server. Empty memos are
+/// omitted.
+pub fn check_memo(memo: Option<String>) -> Result<Option<Memo>, ManyError> {
+    match memo {
+        Some(memo) if !memo.is_empty() => Memo::try_from(memo)
+            .map(Some)
+            .map_err(|e| ManyError::unknown(format!("Invalid memo: {:?}", e))),
+        _ => Ok(None),
+    }
+}
+
+/// The governance parameter of the fee charged on sends.
+const SEND_FEE_PARAM: &str = "send.fee";
+
+/// The governance parameter of the address receiving the fees.
+const SEND_FEE_COLLECTOR_PARAM: &str = "send.feeCollector";
+
+#[derive(Encode)]
+#[cbor(map)]
+struct ParamsArgs {}
+
+/// The values of the fee parameters, as the ledger encodes them. Other
+/// parameters are skipped.
+#[derive(Encode, Decode)]
+enum ParamValue {
+    #[n(2)]
+    Amount(#[n(0)] TokenAmount),
+    #[n(3)]
+    Address(#[n(0)] Address),
+}
+
+#[derive(Encode, Decode)]
+#[cbor(map)]
+struct ParamInfo {
+    #[n(1)]
+    value: ParamValue,
+}
+
+/// Read the fee a send from `from` costs from the response of
+/// `governance.params`. The collector does not pay it.
+fn decode_fee(payload: &[u8], from: &Address) -> Result<TokenAmount, minicbor::decode::Error> {
+    let mut fee = TokenAmount::zero();
+    let mut collector = None;
+    let mut d = Decoder::new(payload);
+    let len = d.map()?.unwrap_or_default();
+    for _ in 0..len {
+        if d.u32()? != 0 {
+            d.skip()?;
+            continue;
+        }
+        let count = d.map()?.unwrap_or_default();
+        for _ in 0..count {
+            match (d.str()?, d.probe().decode::<ParamInfo>()) {
+                (
+                    SEND_FEE_PARAM,
+                    Ok(ParamInfo {
+                        value: ParamValue::Amount(amount),
+                    }),
+                ) => fee = amount,
+                (
+                    SEND_FEE_COLLECTOR_PARAM,
+                    Ok(ParamInfo {
+                        value: ParamValue::Address(address),
+                    }),
+                ) => collector = Some(address),
+                _ => {}
+            }
+            d.skip()?;
+        }
+    }
+    if collector.as_ref() == Some(from) {
+        fee = TokenAmount::zero();
+    }
+    Ok(fee)
+}
+
+/// Fetch the limits of a symbol and the balance of the sender. The limits
+/// are read through the symbol cache, the balance always from the server.
+///
+/// The many-ledger server only declares a fee, as governance parameters.
+/// It has no minimum transfer nor paused symbols, so these limits are never
+/// set here, and only checked by `validate` for callers which have them.
+pub fn fetch(
+    client: &ManyClient<impl Identity>,
+    from: Address,
+    symbol: Symbol,
+) -> Result<(TokenAmount, TransferLimits), ManyError> {
+    let payload = crate::transport::call_(
+        client,
+        "ledger.balance",
+        ledger::BalanceArgs {
+            account: Some(from),
+            symbols: Some(vec![symbol].into()),
+        },
+    )?;
+    let balance: ledger::BalanceReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    // Ledgers without governance do not charge any fee.
+    let params = crate::cache::limits(|| {
+        crate::transport::call_(client, "governance.params", ParamsArgs {})
+    });
+    let fee = match params {
+        Ok(payload) => decode_fee(&payload, &from)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?,
+        Err(e) if e.code() == ManyErrorCode::InvalidMethodName => {
+            debug!("The server does not support governance parameters.");
+            TokenAmount::zero()
+        }
+        Err(e) => return Err(e),
+    };
+    Ok((
+        balance.balances.get(&symbol).cloned().unwrap_or_default(),
+        TransferLimits {
+            fee,
+            ..Default::default()
+        },
+    ))
+}
+
+/// Check a transfer before signing it. The transfer is rejected if it would
+/// fail, unless `warn_only` is set, e.g. when it is executed later and the
+/// balance can change in the meantime. Offline, nothing is checked, and the
+/// envelope should be stamped with `stamp_unchecked`.
+pub fn check(
+    client: &ManyClient<impl Identity>,
+    from: Address,
+    symbol: Symbol,
+    amount: &TokenAmount,
+    offline: bool,
+    warn_only: bool,
+) -> Result<(), ManyError> {
+    if offline {
+        warn!("Offline, the transfer was not checked against the server limits.");
+        return Ok(());
+    }
+
+    let (balance, limits) = fetch(client, from, symbol)?;
+    match validate(amount, &balance, &limits) {
+        Ok(_) => Ok(()),
+        Err(e) if warn_only => {
+            warn!("{}", e);
+            Ok(())
+        }
+        Err(e) => Err(ManyError::unknown(e.to_string())),
+    }
+}
+
+/// The label of the unprotected header of envelopes whose transfer was not
+/// checked against the limits of the server before signing.
+pub const UNCHECKED_LIMITS_LABEL: &str = "many-ledger.uncheckedLimits";
+
+const UNCHECKED_LIMITS_WARNING: &str =
+    "Signed offline, the transfer was not checked against the server limits.";
+
+/// Record in the metadata of an envelope that its transfer was not checked,
+/// so whoever submits it knows. The header is unprotected, as adding it
+/// must not invalidate the signature.
+pub fn stamp_unchecked(envelope: &[u8]) -> Result<Vec<u8>, ManyError> {
+    let mut envelope = CoseSign1::from_slice(envelope)
+        .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    let label = Label::Text(UNCHECKED_LIMITS_LABEL.to_string());
+    envelope.unprotected.rest.retain(|(l, _)| l != &label);
+    envelope
+        .unprotected
+        .rest
+        .push((label, Value::Text(UNCHECKED_LIMITS_WARNING.to_string())));
+    envelope
+        .to_vec()
+        .map_err(|e| ManyError::serialization_error(e.to_string()))
+}
+
+/// The warning stamped by `stamp_unchecked` on an envelope, if any.
+pub fn unchecked_warning(envelope: &CoseSign1) -> Option<String> {
+    let label = Label::Text(UNCHECKED_LIMITS_LABEL.to_string());
+    envelope
+        .unprotected
+        .rest
+        .iter()
+        .find_map(|(l, value)| match value {
+            Value::Text(warning) if l == &label => Some(warning.clone()),
+            _ => None,
+        })
+}
+
+// Mirrors the arguments of `ledger.checkReceive` of the many-ledger server.
+#[derive(Encode)]
+#[cbor(map)]
+struct CheckReceiveArgs {
+    #[n(0)]
+    from: Option<Address>,
+
+    #[n(1)]
+    to: Address,
+
+    #[n(2)]
+    symbol: Symbol,
+
+    #[n(3)]
+    amount: TokenAmount,
+}
+
+/// Warn if the recipient of a transfer would reject it, because of its
+/// receive policy. The transfer is still sent, as the policy can change
+/// before it executes. Offline, nothing is checked.
+pub fn check_receive(
+    client: &ManyClient<impl Identity>,
+    from: Address,
+    to: Address,
+    symbol: Symbol,
+    amount: &TokenAmount,
+    offline: bool,
+) {
+    if offline {
+        return;
+    }
+    let arguments = CheckReceiveArgs {
+        from: Some(from),
+        to,
+        symbol,
+        amount: amount.clone(),
+    };
+    match crate::transport::call_(client, "ledger.checkReceive", arguments) {
+        Ok(_) => {}
+        Err(e) if e.code() == ManyErrorCode::InvalidMethodName => {
+            debug!("The server does not support receive policies.");
+        }
+        Err(e) => warn!("The recipient would likely reject this transfer: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(minimum: Option<u64>, fee: u64, paused: bool) -> TransferLimits {
+        TransferLimits {
+            minimum: minimum.map(TokenAmount::from),
+            fee: TokenAmount::from(fee),
+            paused,
+        }
+    }
+
+    fn validate_(amount: u64, balance: u64, limits: &TransferLimits) -> Result<u64, LimitError> {
+        validate(
+            &TokenAmount::from(amount),
+            &TokenAmount::from(balance),
+            limits,
+        )
+        .map(|total| total.to_string().parse().unwrap())
+    }
+
+    #[test]
+    fn no_limits() {
+        let limits = TransferLimits::default();
+        assert_eq!(validate_(10, 10, &limits), Ok(10));
+        assert_eq!(validate_(0, 10, &limits), Err(LimitError::ZeroAmount));
+        assert_eq!(
+            validate_(11, 10, &limits),
+            Err(LimitError::InsufficientFunds {
+                total: 11u64.into(),
+                balance: 10u64.into(),
+            })
+        );
+    }
+
+    #[test]
+    fn fee() {
+        let limits = limits(None, 3, false);
+        assert_eq!(validate_(7, 10, &limits), Ok(10));
+        assert_eq!(
+            validate_(8, 10, &limits),
+            Err(LimitError::InsufficientFunds {
+                total: 11u64.into(),
+                balance: 10u64.into(),
+            })
+        );
+    }
+
+    #[test]
+    fn minimum() {
+        let limits = limits(Some(5), 0, false);
+        assert_eq!(validate_(5, 10, &limits), Ok(5));
+        assert_eq!(
+            validate_(4, 10, &limits),
+            Err(LimitError::BelowMinimum {
+                amount: 4u64.into(),
+                minimum: 5u64.into(),
+            })
+        );
+    }
+
+    #[test]
+    fn paused() {
+        let limits = limits(None, 0, true);
+        assert_eq!(validate_(1, 10, &limits), Err(LimitError::Paused));
+    }
+
+    #[test]
+    fn self_transfer() {
+        let a: Address = "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp"
+            .parse()
+            .unwrap();
+        let b = a.with_subresource_id(1).unwrap();
+        assert!(check_self_transfer(&a, &b, false).is_ok());
+        assert!(check_self_transfer(&a, &a, false).is_err());
+        assert!(check_self_transfer(&a, &a, true).is_ok());
+    }
+
+    #[test]
+    fn memo() {
+        assert!(check_memo(None).unwrap().is_none());
+        assert!(check_memo(Some(String::new())).unwrap().is_none());
+        assert!(check_memo(Some("Invoice 42".to_string()))
+            .unwrap()
+            .is_some());
+        assert!(check_memo(Some("x".repeat(1_000_000))).is_err());
+    }
+
+    /// A response of `governance.params` with the fee parameters, if any,
+    /// after `args.strict`, a boolean.
+    fn params(fee: Option<u64>, collector: Option<Address>) -> Vec<u8> {
+        let mut e = minicbor::Encoder::new(vec![]);
+        let count = 1 + fee.is_some() as u64 + collector.is_some() as u64;
+        e.map(1).unwrap().u32(0).unwrap().map(count).unwrap();
+        e.str("args.strict").unwrap();
+        e.map(2).unwrap();
+        e.u32(0).unwrap().u32(0).unwrap();
+        e.u32(1).unwrap();
+        e.array(2)
+            .unwrap()
+            .u32(0)
+            .unwrap()
+            .array(1)
+            .unwrap()
+            .bool(true)
+            .unwrap();
+        if let Some(fee) = fee {
+            e.str(SEND_FEE_PARAM).unwrap();
+            e.encode(ParamInfo {
+                value: ParamValue::Amount(fee.into()),
+            })
+            .unwrap();
+        }
+        if let Some(collector) = collector {
+            e.str(SEND_FEE_COLLECTOR_PARAM).unwrap();
+            e.encode(ParamInfo {
+                value: ParamValue::Address(collector),
+            })
+            .unwrap();
+        }
+        e.into_writer()
+    }
+
+    #[test]
+    fn governance_fee() {
+        let a: Address = "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp"
+            .parse()
+            .unwrap();
+        let b = a.with_subresource_id(1).unwrap();
+        assert_eq!(decode_fee(&params(None, None), &a).unwrap(), 0u64.into());
+        assert_eq!(
+            decode_fee(&params(Some(5), Some(b)), &a).unwrap(),
+            5u64.into()
+        );
+        assert_eq!(
+            decode_fee(&params(Some(5), Some(b)), &b).unwrap(),
+            0u64.into()
+        );
+    }
+
+    #[test]
+    fn unchecked_stamp() {
+        let envelope = coset::CoseSign1Builder::new()
+            .payload(vec![1, 2, 3])
+            .signature(vec![4, 5, 6])
+            .build();
+        assert_eq!(unchecked_warning(&envelope), None);
+
+        let stamped = stamp_unchecked(&envelope.clone().to_vec().unwrap()).unwrap();
+        let stamped = stamp_unchecked(&stamped).unwrap();
+        let stamped = CoseSign1::from_slice(&stamped).unwrap();
+        assert_eq!(
+            unchecked_warning(&stamped).as_deref(),
+            Some(UNCHECKED_LIMITS_WARNING)
+        );
+        assert_eq!(stamped.unprotected.rest.len(), 1);
+        assert_eq!(stamped.protected, envelope.protected);
+        assert_eq!(stamped.payload, envelope.payload);
+        assert_eq!(stamped.signature, envelope.signature);
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            validate_(8, 10, &limits(None, 3, false))
+                .unwrap_err()
+                .to_string(),
+            "The total cost of 11 (amount and fee) exceeds the balance of 10."
+        );
+        assert_eq!(
+            validate_(4, 10, &limits(Some(5), 0, false))
+                .unwrap_err()
+                .to_string(),
+            "The amount 4 is below the minimum transfer of 5."
+        );
+    }
+}
@@ -1,26 +1,59 @@
-use clap::{ArgGroup, Parser};
-use many_client::client::blocking::ManyClient;
+use crate::transport::ManyClient;
+use clap::Parser;
 use many_error::ManyError;
 use many_identity::{Address, AnonymousIdentity, Identity};
-use many_identity_dsa::CoseKeyIdentity;
-use many_identity_hsm::{Hsm, HsmIdentity, HsmMechanismType, HsmSessionType, HsmUserType};
+use many_identity_hsm::{Hsm, HsmIdentity, HsmSessionType, HsmUserType};
+use many_modules::account::features::multisig::Memo;
 use many_modules::r#async::{StatusArgs, StatusReturn};
 use many_modules::{ledger, r#async};
 use many_protocol::ResponseMessage;
+use many_types::cbor::CborAny;
 use many_types::ledger::{Symbol, TokenAmount};
 use minicbor::data::Tag;
 use minicbor::encode::{Error, Write};
-use minicbor::{Decoder, Encoder};
+use minicbor::{Decode, Decoder, Encode, Encoder};
 use num_bigint::BigUint;
+use once_cell::sync::OnceCell;
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
+use std::io::IsTerminal;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::Duration;
-use tracing::{debug, error, info, trace};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, trace, warn};
 use tracing_subscriber::filter::LevelFilter;
 
+mod account;
+mod alias;
+mod amount;
+mod async_status;
+mod attachment;
+mod batch;
+mod cache;
+mod config;
+mod doctor;
+mod escrow;
+mod event;
+mod expiry;
+mod history;
+mod hsm;
+mod identity;
+mod info;
+mod keystore;
+mod limits;
 mod multisig;
+mod output;
+mod progress;
+mod send_many;
+mod sign;
+mod split;
+mod submit;
+mod submitted;
+mod subresources;
+mod swap;
+mod template;
+mod transport;
+mod watch;
 
 #[derive(clap::ArgEnum, Clone, Debug)]
 enum LogStrategy {
@@ -55,29 +88,53 @@ impl<'b> minicbor::Decode<'b, ()> for Amount {
 }
 
 #[derive(Parser)]
-#[clap(
-    group(
-        ArgGroup::new("hsm")
-        .multiple(true)
-        .args(&["module", "slot", "keyid"])
-        .requires_all(&["module", "slot", "keyid"])
-    )
-)]
 struct Opts {
-    /// Many server URL to connect to.
-    #[clap(default_value = "http://localhost:8000")]
-    server: String,
+    /// Many server URL to connect to. Defaults to `MANY_LEDGER_SERVER`, then
+    /// the configuration file, then http://localhost:8000.
+    server: Option<String>,
 
     /// The identity of the server (an identity string), or anonymous if you don't know it.
-    #[clap(default_value_t)]
+    /// Defaults to `MANY_LEDGER_SERVER_ID`, then the configuration file.
     #[clap(long)]
-    server_id: Address,
+    server_id: Option<String>,
 
-    /// A PEM file for the identity. If not specified, anonymous will be used.
+    /// A PEM file for the identity. Defaults to `MANY_LEDGER_PEM`, then the
+    /// configuration file. If none is specified, anonymous will be used.
     #[clap(long)]
     pem: Option<PathBuf>,
 
-    /// HSM PKCS#11 module path
+    /// Read the passphrase of an encrypted PEM file from this environment
+    /// variable instead of prompting for it.
+    #[clap(long)]
+    pem_password_env: Option<String>,
+
+    /// A keystore of named PEM files: a directory of `<name>.pem` files, or
+    /// a JSON5 file mapping names to PEM files. Its `default` key is the
+    /// identity, unless `--pem` or `--key-name` is given.
+    #[clap(long)]
+    keystore: Option<PathBuf>,
+
+    /// The key of the keystore to use as the identity.
+    #[clap(long)]
+    key_name: Option<String>,
+
+    /// The configuration file with the defaults of the server and identity
+    /// options. Defaults to `$XDG_CONFIG_HOME/many-ledger/config.toml`.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// A profile of the configuration file to use, overriding its top level
+    /// values.
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// The file of the aliases which can be used instead of addresses in
+    /// arguments. Defaults to `$XDG_CONFIG_HOME/many/aliases.json5`.
+    #[clap(long)]
+    aliases: Option<PathBuf>,
+
+    /// HSM PKCS#11 module path. With `--slot` and `--keyid`, the key of the
+    /// HSM is the identity; alone, it is the HSM of the `hsm` commands.
     #[clap(long, conflicts_with("pem"))]
     module: Option<PathBuf>,
 
@@ -89,6 +146,21 @@ struct Opts {
     #[clap(long, conflicts_with("pem"))]
     keyid: Option<String>,
 
+    /// Read the HSM user PIN from this environment variable instead of
+    /// prompting for it.
+    #[clap(long, conflicts_with("hsm-pin-file"))]
+    hsm_pin_env: Option<String>,
+
+    /// The signature mechanism of the HSM key.
+    #[clap(long, arg_enum, default_value_t = hsm::Mechanism::Ecdsa)]
+    hsm_mechanism: hsm::Mechanism,
+
+    /// Read the HSM user PIN from this file instead of prompting for it. A
+    /// trailing newline is ignored. The file must not be readable by
+    /// everyone.
+    #[clap(long)]
+    hsm_pin_file: Option<PathBuf>,
+
     /// Increase output logging verbosity to DEBUG level.
     #[clap(short, long, parse(from_occurrences))]
     verbose: i8,
@@ -101,27 +173,175 @@ struct Opts {
     #[clap(long, arg_enum, default_value_t = LogStrategy::Terminal)]
     logmode: LogStrategy,
 
+    /// Report the progress of long operations as JSON lines on stderr,
+    /// instead of a spinner, e.g. for graphical wrappers. Each line has a
+    /// `type` field and a sequence number `seq`.
+    #[clap(long)]
+    progress_json: bool,
+
+    /// Parse the amounts with the number format of the locale of the
+    /// environment (`LC_ALL`, `LC_NUMERIC`, then `LANG`), e.g. `1.500,25` in
+    /// a German locale. Otherwise amounts use a dot as the decimal separator
+    /// and no digit grouping, and anything else is refused.
+    #[clap(long)]
+    locale_numbers: bool,
+
+    /// The format of the results of `balance` and `send` on stdout. With
+    /// `json`, logs stay on stderr so the output can be piped to `jq`.
+    #[clap(long, arg_enum, default_value_t = output::OutputFormat::Human)]
+    output: output::OutputFormat,
+
+    /// How long to wait for the result of an async command, in seconds.
+    /// Its token is printed on timeout, to check it later.
+    #[clap(long, default_value_t = 60)]
+    async_timeout: u64,
+
+    /// How often to check the status of an async command, in milliseconds.
+    #[clap(long, default_value_t = 1000)]
+    async_poll_interval: u64,
+
+    /// How long a call of the server can take, in seconds. A query which
+    /// times out is sent again per --retries, a command fails. Unlimited by
+    /// default.
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    /// How many times to send a query again after a network error, waiting
+    /// longer before each attempt. Commands, e.g. `send`, are never sent
+    /// again, as the server may have executed them.
+    #[clap(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Always fetch the symbols of the ledger and their local names from
+    /// the server, instead of using the ones cached by previous commands.
+    #[clap(long)]
+    no_cache: bool,
+
+    /// How long the symbols of the ledger and their local names are cached
+    /// between commands, e.g. `1h` or `10m`. A symbol missing from the
+    /// cache is always looked up on the server.
+    #[clap(long, default_value = "1h")]
+    symbols_ttl: humantime::Duration,
+
     #[clap(subcommand)]
     subcommand: SubCommand,
 }
 
 #[derive(Parser)]
 enum SubCommand {
+    /// Show the state hash and the symbols of the ledger with their local
+    /// names, or the statistics of the chain. No identity is needed.
+    Info(info::InfoOpt),
+
     /// Read the balance of an account.
     Balance(BalanceOpt),
 
     /// Send tokens to an account.
     Send(TargetCommandOpt),
 
+    /// Sign a transfer without any network access, and write its envelope
+    /// to a file, e.g. on an air-gapped machine.
+    Sign(sign::SignOpt),
+
+    /// Send a transfer signed elsewhere, e.g. by `sign`, without signing
+    /// anything. No identity is needed.
+    Submit(submit::SubmitOpt),
+
+    /// Send tokens to several accounts, listed in a file. The transfers are
+    /// sent in `ledger.batch` messages if the server supports it.
+    SendBatch(batch::SendBatchOpt),
+
+    /// Send tokens of one symbol to several accounts given with `--to`, one
+    /// transfer after the other. All the transfers are checked, and their
+    /// total confirmed, before the first one is sent.
+    SendMany(send_many::SendManyOpt),
+
+    /// Show the events of an account, or export its token transfers.
+    History(history::HistoryOpt),
+
+    /// Show a single event with all its fields, or count the events.
+    Event(event::CommandOpt),
+
+    /// List the requests of the caller broadcast by the server, and where
+    /// they were executed. This needs a many-abci server with a request
+    /// index.
+    Submitted(submitted::SubmittedOpt),
+
     /// Perform a multisig operation.
     Multisig(multisig::CommandOpt),
+
+    /// Perform an escrow operation.
+    Escrow(escrow::CommandOpt),
+
+    /// Show an account, and what changed on it over time.
+    Account(account::CommandOpt),
+
+    /// Perform a swap operation.
+    Swap(swap::CommandOpt),
+
+    /// Send the full balance of a symbol of a range of subresources of an
+    /// account to a destination.
+    Sweep(subresources::SweepOpt),
+
+    /// Check the configuration of the client and the server, and print how
+    /// to fix what is wrong.
+    Doctor(doctor::DoctorOpt),
+
+    /// Find the keys of an HSM.
+    Hsm(hsm::CommandOpt),
+
+    /// Manage the keys of the keystore.
+    Key(keystore::CommandOpt),
+
+    /// Save transfers sent repeatedly as templates, and send them with new
+    /// amounts.
+    Template(template::CommandOpt),
+
+    /// Manage the aliases of addresses.
+    Alias(alias::CommandOpt),
+
+    /// Manage the cache of the responses and symbols of the servers.
+    Cache(cache::CommandOpt),
+
+    /// Read the result of a command from its async token, e.g. after the
+    /// command timed out waiting for it.
+    AsyncStatus(async_status::AsyncStatusOpt),
 }
 
 #[derive(Parser)]
 struct BalanceOpt {
-    /// The identity to check. This can be a Pem file (which will be used to calculate a public
-    /// identity) or an identity string. If omitted it will use the identity of the caller.
-    identity: Option<String>,
+    /// The identities to check. Each can be a Pem file (which will be used to calculate a public
+    /// identity), an identity string or an alias. If omitted it will use the identity of the
+    /// caller. With several identities, their balances are grouped by identity, followed by the
+    /// total of each symbol.
+    identity: Vec<String>,
+
+    /// Read the balances of a range of subresources of the identity instead,
+    /// e.g. `0..999`. Only nonzero balances are printed.
+    #[clap(long)]
+    subresource_range: Option<subresources::SubresourceRange>,
+
+    #[clap(flatten)]
+    limits: subresources::RangeLimitsOpt,
+
+    /// Show the balances cached by the last successful call, without
+    /// connecting to the server.
+    #[clap(long, conflicts_with = "subresource-range")]
+    offline: bool,
+
+    /// Print the amounts in base units, as returned by the ledger, instead
+    /// of with the decimals of their token.
+    #[clap(long)]
+    raw: bool,
+
+    /// Read the balances again every `--interval` until interrupted with
+    /// Ctrl-C, highlighting the ones which changed since the previous read.
+    #[clap(long, conflicts_with_all = &["offline", "subresource-range"])]
+    watch: bool,
+
+    /// The number of seconds between two reads of `--watch`.
+    #[clap(long, default_value = "5", parse(try_from_str = watch::parse_interval))]
+    interval: Duration,
 
     /// The symbol to check the balance of. This can either be an identity or
     /// a local name for a symbol. If it doesn't parse to an identity an
@@ -134,19 +354,58 @@ struct BalanceOpt {
 pub(crate) struct TargetCommandOpt {
     /// The from identity, if different than the one provided by the
     /// PEM argument.
-    #[clap(long)]
+    #[clap(long, parse(try_from_str = alias::address))]
     account: Option<Address>,
 
-    /// The account or target identity.
+    /// The account or target identity, or its alias.
+    #[clap(parse(try_from_str = alias::address))]
     identity: Address,
 
-    /// The amount of tokens.
-    amount: BigUint,
+    /// The amount of tokens, in base units. An amount with decimals, e.g.
+    /// `1.5`, is in whole tokens instead, and converted to base units with
+    /// the number of decimals of the symbol.
+    amount: amount::AmountArg,
+
+    /// Read an amount without decimals in whole tokens too.
+    #[clap(long)]
+    decimal: bool,
 
     /// The symbol to use.  This can either be an identity or
     /// a local name for a symbol. If it doesn't parse to an identity an
     /// additional call will be made to retrieve local names.
     symbol: String,
+
+    /// Do not check the transfer against the balance and the limits of the
+    /// server before signing it.
+    #[clap(long)]
+    offline: bool,
+
+    /// Allow sending to the source account, for ledgers which record these
+    /// transfers as events without moving funds.
+    #[clap(long)]
+    self_transfer: bool,
+
+    /// Send even if the signing identity holds none of the roles needed on
+    /// the `--account` account, or if its roles cannot be read.
+    #[clap(long)]
+    force: bool,
+
+    /// A memo for the recipient. Empty memos are omitted.
+    #[clap(long)]
+    memo: Option<String>,
+
+    /// Print the signed request, in hexadecimal and in CBOR diagnostic
+    /// notation, instead of sending it. Nothing is checked against the
+    /// server, and symbols which are not addresses are resolved from the
+    /// cache.
+    #[clap(long, conflicts_with = "attach")]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    attach: attachment::AttachOpt,
+
+    #[clap(flatten)]
+    split: split::SplitOpt,
 }
 
 pub fn resolve_symbol(
@@ -154,77 +413,414 @@ pub fn resolve_symbol(
     symbol: String,
 ) -> Result<Address, ManyError> {
     if let Ok(symbol) = Address::from_str(&symbol) {
-        Ok(symbol)
-    } else {
-        // Get info.
-        let info: ledger::InfoReturns =
-            minicbor::decode(&client.call_("ledger.info", ())?).unwrap();
-        info.local_names
+        return Ok(symbol);
+    }
+    let find = |local_names: BTreeMap<Symbol, String>| {
+        local_names
             .into_iter()
             .find(|(_, y)| y == &symbol)
             .map(|(x, _)| x)
-            .ok_or_else(|| ManyError::unknown(format!("Could not resolve symbol '{}'", &symbol)))
+    };
+    match find(cache::local_names(client)?) {
+        Some(symbol) => Ok(symbol),
+        // The symbol may be newer than the cache.
+        None => find(cache::fetch_local_names(client)?)
+            .ok_or_else(|| ManyError::unknown(format!("Could not resolve symbol '{}'", &symbol))),
     }
 }
 
+// Mirror `tokens.info` of the MANY specification, which the pinned
+// many-modules does not have yet. Only the number of decimals is read.
+#[derive(Encode)]
+#[cbor(map)]
+struct TokenInfoArgs {
+    #[n(0)]
+    symbol: Symbol,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct TokenInfoReturns {
+    #[n(0)]
+    info: TokenInfo,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct TokenInfo {
+    #[n(1)]
+    summary: TokenInfoSummary,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct TokenInfoSummary {
+    #[n(2)]
+    decimals: u64,
+}
+
+/// The number of decimals of a symbol, from `tokens.info`.
+fn token_decimals(client: &ManyClient<impl Identity>, symbol: Symbol) -> Result<u64, ManyError> {
+    cache::call_(client, "tokens.info", TokenInfoArgs { symbol })
+        .and_then(|payload| {
+            minicbor::decode::<TokenInfoReturns>(&payload)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+        })
+        .map(|returns| returns.info.summary.decimals)
+        .map_err(|e| {
+            ManyError::unknown(format!(
+                "Could not read the number of decimals of {} to convert the amount to base \
+                 units: {} Pass the amount in base units instead.",
+                symbol, e
+            ))
+        })
+}
+
+/// The amount of a transfer in base units, scaled with the number of
+/// decimals of its symbol when it is in whole tokens.
+pub(crate) fn amount_units(
+    client: &ManyClient<impl Identity>,
+    amount: &amount::AmountArg,
+    symbol: &str,
+    decimal: bool,
+) -> Result<BigUint, ManyError> {
+    amount.scaled_units(decimal, || {
+        token_decimals(client, resolve_symbol(client, symbol.to_string())?)
+    })
+}
+
+/// The sum of the amounts of each symbol.
+fn sum_amounts<'a>(
+    amounts: impl IntoIterator<Item = &'a BTreeMap<Symbol, TokenAmount>>,
+) -> BTreeMap<Symbol, TokenAmount> {
+    let mut totals: BTreeMap<Symbol, TokenAmount> = BTreeMap::new();
+    for (symbol, amount) in amounts.into_iter().flatten() {
+        *totals.entry(*symbol).or_insert_with(TokenAmount::zero) += amount.clone();
+    }
+    totals
+}
+
 fn balance(
     client: ManyClient<impl Identity>,
-    account: Option<Address>,
+    accounts: Vec<Address>,
     symbols: Vec<String>,
+    raw: bool,
+    interval: Option<Duration>,
 ) -> Result<(), ManyError> {
-    // Get info.
-    let info: ledger::InfoReturns = minicbor::decode(&client.call_("ledger.info", ())?).unwrap();
-    let local_names: BTreeMap<String, Symbol> = info
-        .local_names
+    let mut names = cache::local_names(&client)?;
+    // The symbols missing from the cache may be newer than it.
+    if symbols
         .iter()
-        .map(|(x, y)| (y.clone(), *x))
-        .collect();
+        .any(|x| Address::from_str(x).is_err() && !names.values().any(|name| name == x))
+    {
+        names = cache::fetch_local_names(&client)?;
+    }
+    let local_names: BTreeMap<String, Symbol> =
+        names.iter().map(|(x, y)| (y.clone(), *x)).collect();
 
-    let argument = ledger::BalanceArgs {
-        account,
-        symbols: if symbols.is_empty() {
-            None
-        } else {
-            Some(
-                symbols
-                    .iter()
-                    .map(|x| {
-                        if let Ok(i) = Address::from_str(x) {
-                            Ok(i)
-                        } else if let Some(i) = local_names.get(x.as_str()) {
-                            Ok(*i)
-                        } else {
-                            Err(ManyError::unknown(format!(
-                                "Could not resolve symbol '{}'",
-                                x
-                            )))
-                        }
-                    })
-                    .collect::<Result<Vec<_>, _>>()?
-                    .into(),
-            )
-        },
+    let symbols: Option<Vec<Symbol>> = if symbols.is_empty() {
+        None
+    } else {
+        Some(
+            symbols
+                .iter()
+                .map(|x| {
+                    if let Ok(i) = Address::from_str(x) {
+                        Ok(i)
+                    } else if let Some(i) = local_names.get(x.as_str()) {
+                        Ok(*i)
+                    } else {
+                        Err(ManyError::unknown(format!(
+                            "Could not resolve symbol '{}'",
+                            x
+                        )))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+    let fetch = |account: Option<Address>| -> Result<escrow::BalanceReturns, ManyError> {
+        let argument = ledger::BalanceArgs {
+            account,
+            symbols: symbols.clone().map(Into::into),
+        };
+        let payload = cache::call_(&client, "ledger.balance", argument)?;
+        if payload.is_empty() {
+            return Err(ManyError::unexpected_empty_response());
+        }
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))
     };
-    let payload = client.call_("ledger.balance", argument)?;
 
-    if payload.is_empty() {
-        Err(ManyError::unexpected_empty_response())
-    } else {
-        let balance: ledger::BalanceReturns = minicbor::decode(&payload).unwrap();
-        for (symbol, amount) in balance.balances {
-            if let Some(symbol_name) = info.local_names.get(&symbol) {
-                println!("{:>12} {} ({})", amount, symbol_name, symbol);
+    // The decimals of each symbol, read once. Amounts of a symbol whose
+    // decimals are unavailable are printed in base units.
+    let mut decimals = BTreeMap::new();
+    let mut format = |symbol: Symbol, amount: &TokenAmount| {
+        let decimals = *decimals.entry(symbol).or_insert_with(|| {
+            if raw {
+                None
             } else {
-                println!("{:>12} {}", amount, symbol);
+                token_decimals(&client, symbol).ok()
+            }
+        });
+        match decimals {
+            Some(decimals) => amount::format_units(amount, decimals),
+            None => amount.to_string(),
+        }
+    };
+    let name = |symbol: &Symbol| match names.get(symbol) {
+        Some(symbol_name) => format!("{} ({})", symbol_name, symbol),
+        None => symbol.to_string(),
+    };
+    let mut lines_of = |balances: &BTreeMap<Symbol, TokenAmount>,
+                        escrowed: &BTreeMap<Symbol, TokenAmount>| {
+        let mut lines = vec![];
+        for (symbol, amount) in balances {
+            lines.push((format(*symbol, amount), name(symbol)));
+        }
+        for (symbol, amount) in escrowed {
+            lines.push((
+                format(*symbol, amount),
+                format!("{} escrowed", name(symbol)),
+            ));
+        }
+        lines
+    };
+
+    // The symbols and their decimals are read once, only the balances are
+    // read again when watching.
+    let watching = interval.is_some();
+    let mut changes = watch::Changes::new(watching && std::io::stdout().is_terminal());
+    let mut poll = || -> Result<(), ManyError> {
+        // One call per identity, or one for the caller.
+        let balances = match accounts.len() {
+            0 => vec![(None, fetch(None)?)],
+            _ => accounts
+                .iter()
+                .map(|account| Ok((Some(*account), fetch(Some(*account))?)))
+                .collect::<Result<Vec<_>, ManyError>>()?,
+        };
+        let grouped = balances.len() > 1;
+        let totals = grouped.then(|| {
+            (
+                sum_amounts(balances.iter().map(|(_, b)| &b.balances)),
+                sum_amounts(balances.iter().filter_map(|(_, b)| b.escrowed.as_ref())),
+            )
+        });
+
+        if output::get() == output::OutputFormat::Json {
+            let json = |balance: &escrow::BalanceReturns| {
+                output::balance_json(
+                    &balance.balances,
+                    balance.escrowed.as_ref().unwrap_or(&BTreeMap::new()),
+                    &names,
+                )
+            };
+            match totals {
+                Some((balances_total, escrowed_total)) => output::print_json(&serde_json::json!({
+                    "accounts": balances
+                        .iter()
+                        .filter_map(|(account, balance)| {
+                            account.map(|account| (account.to_string(), json(balance)))
+                        })
+                        .collect::<serde_json::Map<_, _>>(),
+                    "total": output::balance_json(&balances_total, &escrowed_total, &names),
+                })),
+                None => output::print_json(&json(&balances[0].1)),
             }
+            return Ok(());
+        }
+
+        // The title of each group, and its lines.
+        let mut groups = vec![];
+        for (account, balance) in &balances {
+            let lines = lines_of(
+                &balance.balances,
+                balance.escrowed.as_ref().unwrap_or(&BTreeMap::new()),
+            );
+            groups.push((
+                account
+                    .filter(|_| grouped)
+                    .map(|account| account.to_string()),
+                lines,
+            ));
+        }
+        if let Some((balances_total, escrowed_total)) = &totals {
+            groups.push((
+                Some("Total".to_string()),
+                lines_of(balances_total, escrowed_total),
+            ));
         }
 
+        // The amounts are aligned across all the groups.
+        let width = groups
+            .iter()
+            .flat_map(|(_, lines)| lines.iter().map(|(amount, _)| amount.len()))
+            .max()
+            .unwrap_or(0)
+            .max(12);
+        if watching {
+            println!(
+                "Balances at {}:",
+                humantime::format_rfc3339_seconds(SystemTime::now())
+            );
+        }
+        for (title, lines) in groups {
+            if let Some(title) = &title {
+                println!("{}", title);
+            }
+            for (amount, name) in lines {
+                let line = format!("{:>width$} {}", amount, name, width = width);
+                let key = format!("{} {}", title.as_deref().unwrap_or_default(), name);
+                println!("{}", changes.line(key, &amount, line));
+            }
+        }
+        if watching {
+            println!();
+        }
+        changes.next();
         Ok(())
+    };
+
+    match interval {
+        Some(interval) => watch::watch(interval, poll),
+        None => poll(),
     }
 }
 
+/// The ID of the attribute many-abci adds to the response of an executed
+/// transaction, with its block height as first argument.
+const TX_LOCATION_ATTRIBUTE_ID: u32 = 1000;
+
+fn executed_at_height(response: &ResponseMessage) -> Option<i64> {
+    let attribute = response
+        .attributes
+        .get_attribute(TX_LOCATION_ATTRIBUTE_ID)?;
+    match attribute.arguments.first() {
+        Some(CborAny::Int(height)) => Some(*height),
+        _ => None,
+    }
+}
+
+/// How long `wait_response` waits for async results.
+struct AsyncWait {
+    timeout: Duration,
+    poll_interval: Duration,
+}
+
+impl Default for AsyncWait {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+static ASYNC_WAIT: OnceCell<AsyncWait> = OnceCell::new();
+
+/// Set how long all subcommands wait for async results. Can only be called
+/// once.
+fn init_async_wait(wait: AsyncWait) {
+    if ASYNC_WAIT.set(wait).is_err() {
+        panic!("The async wait was already set.");
+    }
+}
+
+/// The status of an async token.
+pub(crate) fn async_status(
+    client: &ManyClient<impl Identity>,
+    token: &[u8],
+) -> Result<StatusReturn, ManyError> {
+    let response = crate::transport::call(
+        client,
+        "async.status",
+        StatusArgs {
+            token: token.to_vec().into(),
+        },
+    )?;
+    minicbor::decode(&response.data?).map_err(|e| ManyError::deserialization_error(e.to_string()))
+}
+
+/// Wait for the result of an async token, polling its status until it is
+/// done or the async wait times out. Returns `None` if the token expired
+/// before its result could be read.
+pub(crate) fn wait_token(
+    client: &ManyClient<impl Identity>,
+    token: &[u8],
+) -> Result<Option<Vec<u8>>, ManyError> {
+    let token_hex = hex::encode(token);
+    let progress = progress::get();
+    let spinner = progress.spinner("Waiting for async response");
+    let start = Instant::now();
+    let done = |outcome| {
+        if let Some(spinner) = &spinner {
+            spinner.finish();
+        }
+        progress.emit(progress::Event::AsyncDone {
+            token: token_hex.clone(),
+            outcome,
+            elapsed_ms: progress::elapsed_ms(start.elapsed()),
+        });
+    };
+
+    let wait = ASYNC_WAIT.get_or_init(AsyncWait::default);
+    let deadline = start + wait.timeout;
+    let mut attempt = 0;
+    let mut known = false;
+    loop {
+        match async_status(client, token)? {
+            StatusReturn::Done { response } => {
+                done("done");
+                if let Some(height) = executed_at_height(&response) {
+                    info!("Executed at height {}", height);
+                }
+                return wait_response(client, *response).map(Some);
+            }
+            StatusReturn::Expired => {
+                done("expired");
+                return Ok(None);
+            }
+            // Blockchain servers only know the token once its
+            // transaction is in a block.
+            StatusReturn::Unknown => {
+                debug!("The server does not know the async token yet.");
+            }
+            StatusReturn::Queued | StatusReturn::Processing => {
+                known = true;
+            }
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        progress.emit(progress::Event::AsyncWait {
+            token: token_hex.clone(),
+            attempt,
+            elapsed_ms: progress::elapsed_ms(start.elapsed()),
+        });
+        attempt += 1;
+        std::thread::sleep(wait.poll_interval.min(deadline - now));
+    }
+    done("timeout");
+    let waited = humantime::format_duration(Duration::from_secs(start.elapsed().as_secs()));
+    Err(ManyError::unknown(if known {
+        format!(
+            "Timed out after {} waiting for the async result. It is still pending, \
+             check it later with `ledger async-status --wait {}`.",
+            waited, token_hex
+        )
+    } else {
+        format!(
+            "Timed out after {} waiting for the async result. The server does not know \
+             the token {}, the command may not be in a block yet.",
+            waited, token_hex
+        )
+    }))
+}
+
 pub(crate) fn wait_response(
-    client: ManyClient<impl Identity>,
+    client: &ManyClient<impl Identity>,
     response: ResponseMessage,
 ) -> Result<Vec<u8>, ManyError> {
     let ResponseMessage {
@@ -243,82 +839,164 @@ pub(crate) fn wait_response(
         };
         info!("Async token: {}", hex::encode(&attr.token));
 
-        let progress =
-            indicatif::ProgressBar::new_spinner().with_message("Waiting for async response");
-        progress.enable_steady_tick(100);
-
-        // TODO: improve on this by using duration and thread and watchdog.
-        // Wait for the server for ~60 seconds by pinging it every second.
-        for _ in 0..60 {
-            let response = client.call(
-                "async.status",
-                StatusArgs {
-                    token: attr.token.clone(),
-                },
-            )?;
-            let status: StatusReturn = minicbor::decode(&response.data?)
-                .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
-            match status {
-                StatusReturn::Done { response } => {
-                    progress.finish();
-                    return wait_response(client, *response);
-                }
-                StatusReturn::Expired => {
-                    progress.finish();
-                    info!("Async token expired before we could check it.");
-                    return Ok(Vec::new());
-                }
-                _ => {
-                    std::thread::sleep(Duration::from_secs(1));
-                }
+        match wait_token(client, &attr.token)? {
+            Some(payload) => Ok(payload),
+            None => {
+                info!("Async token expired before we could check it.");
+                Ok(Vec::new())
             }
         }
-        Err(ManyError::unknown(
-            "Transport timed out waiting for async result.",
-        ))
     } else {
         Ok(payload)
     }
 }
 
+// Mirrors `ledger::SendArgs` of the MANY specification, which has an
+// optional memo that the pinned many-modules does not have yet.
+#[derive(Clone, Debug, Encode)]
+#[cbor(map)]
+struct SendArgs {
+    #[n(0)]
+    from: Option<Address>,
+
+    #[n(1)]
+    to: Address,
+
+    #[n(2)]
+    amount: TokenAmount,
+
+    #[n(3)]
+    symbol: Symbol,
+
+    #[n(4)]
+    memo: Option<Memo>,
+}
+
+/// Check a transfer and build its arguments, without sending it.
+#[allow(clippy::too_many_arguments)]
+fn send_args(
+    client: &ManyClient<impl Identity>,
+    signer: Address,
+    from: Address,
+    to: Address,
+    amount: BigUint,
+    symbol: String,
+    offline: bool,
+    self_transfer: bool,
+    force: bool,
+    memo: Option<String>,
+) -> Result<SendArgs, ManyError> {
+    let symbol = resolve_symbol(client, symbol)?;
+
+    if from.is_anonymous() {
+        return Err(ManyError::invalid_identity());
+    }
+    limits::check_self_transfer(&from, &to, self_transfer)?;
+    if !offline {
+        account::check_signer(client, from, signer, &account::SEND_ROLES, force)?;
+    }
+    let amount = TokenAmount::from(amount);
+    limits::check(client, from, symbol, &amount, offline, false)?;
+    limits::check_receive(client, from, to, symbol, &amount, offline);
+    let memo = limits::check_memo(memo)?;
+
+    Ok(SendArgs {
+        from: Some(from),
+        to,
+        amount,
+        symbol,
+        memo,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 fn send(
     client: ManyClient<impl Identity>,
+    signer: Address,
     from: Address,
     to: Address,
     amount: BigUint,
     symbol: String,
+    offline: bool,
+    self_transfer: bool,
+    force: bool,
+    memo: Option<String>,
+    dry_run: bool,
+    attach: attachment::AttachOpt,
+    split: split::SplitOpt,
 ) -> Result<(), ManyError> {
-    let symbol = resolve_symbol(&client, symbol)?;
+    let arguments = send_args(
+        &client,
+        signer,
+        from,
+        to,
+        amount.clone(),
+        symbol,
+        offline || dry_run,
+        self_transfer,
+        force,
+        memo,
+    )?;
+    if dry_run {
+        return expiry::print_signed(&client, "ledger.send", arguments, offline || dry_run);
+    }
+    if split.is_set() {
+        return split::send_split(&client, amount, arguments, split);
+    }
 
-    if from.is_anonymous() {
-        Err(ManyError::invalid_identity())
-    } else {
-        let arguments = ledger::SendArgs {
-            from: Some(from),
-            to,
-            symbol,
-            amount: TokenAmount::from(amount),
-        };
-        let response = client.call("ledger.send", arguments)?;
-        let payload = wait_response(client, response)?;
-        println!("{}", minicbor::display(&payload));
-        Ok(())
+    let attachment = attach.upload()?;
+    let response = attachment::call(&client, "ledger.send", arguments, attachment.as_ref())?;
+    let token = response
+        .attributes
+        .get::<r#async::attributes::AsyncAttribute>()
+        .ok()
+        .map(|attr| attr.token.to_vec());
+    let payload = wait_response(&client, response)?;
+    match output::get() {
+        output::OutputFormat::Human => println!("{}", minicbor::display(&payload)),
+        output::OutputFormat::Json => {
+            output::print_json(&output::response_json(token.as_deref(), &payload))
+        }
     }
+    Ok(())
 }
 
 fn main() {
     let Opts {
         pem,
+        pem_password_env,
+        keystore,
+        key_name,
         module,
         slot,
         keyid,
+        hsm_pin_env,
+        hsm_pin_file,
+        hsm_mechanism,
         server,
         server_id,
+        config,
+        profile,
+        aliases: _,
         subcommand,
         verbose,
         quiet,
         logmode,
-    } = Opts::parse();
+        progress_json,
+        locale_numbers,
+        output,
+        async_timeout,
+        async_poll_interval,
+        timeout,
+        retries,
+        no_cache,
+        symbols_ttl,
+    } = {
+        // Arguments can be aliases, so the aliases file is needed to parse
+        // them.
+        alias::init(alias::path_flag(std::env::args_os()));
+        Opts::parse()
+    };
 
     let verbose_level = 2 + verbose - quiet;
     let log_level = match verbose_level {
@@ -346,13 +1024,106 @@ fn main() {
             subscriber.init();
         }
     };
+    alias::log_warnings();
+    progress::init(if progress_json {
+        progress::Progress::json(std::io::stderr())
+    } else {
+        progress::Progress::interactive()
+    });
+    output::init(output);
+    init_async_wait(AsyncWait {
+        timeout: Duration::from_secs(async_timeout),
+        poll_interval: Duration::from_millis(async_poll_interval),
+    });
+    transport::init(transport::Transport {
+        timeout: timeout.map(Duration::from_secs),
+        retries,
+    });
+    amount::init(if locale_numbers {
+        let format = amount::NumberFormat::detect(&|name| std::env::var(name).ok());
+        debug!("Parsing amounts with {}", format);
+        format
+    } else {
+        amount::NumberFormat::Plain
+    });
+
+    // A key of the keystore is given as a flag, so it takes precedence over
+    // the defaults, but not over `--pem`.
+    let pem = match pem {
+        Some(pem) => Some(pem),
+        None => keystore::select(keystore.as_deref(), key_name.as_deref()).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        }),
+    };
+
+    let config::Resolved {
+        server,
+        server_id,
+        pem,
+    } = config::resolve(
+        config::Profile {
+            server,
+            server_id,
+            pem,
+        },
+        profile.as_deref(),
+        config,
+        &|name| std::env::var(name).ok(),
+    )
+    .unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+    // An HSM key takes precedence over a default PEM file.
+    let pem = if module.is_some() { None } else { pem };
+
+    let pin_source = hsm::PinSource::new(hsm_pin_env, hsm_pin_file);
+    identity::init(pem_password_env);
+
+    // The doctor checks the identity flags itself, instead of failing on the
+    // first one that is wrong.
+    let subcommand = match subcommand {
+        SubCommand::Doctor(opts) => {
+            let ok = doctor::doctor(server, server_id, pem, module, slot, keyid, opts);
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+        SubCommand::Hsm(opts) => {
+            if let Err(e) = hsm::hsm(module, &pin_source, opts) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            std::process::exit(0);
+        }
+        SubCommand::Key(opts) => {
+            if let Err(e) = keystore::key(keystore, opts) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            std::process::exit(0);
+        }
+        subcommand => subcommand,
+    };
+    let hsm_flags = [module.is_some(), slot.is_some(), keyid.is_some()];
+    if hsm_flags.contains(&true) && hsm_flags.contains(&false) {
+        error!("The HSM flags --module, --slot and --keyid must be used together.");
+        std::process::exit(1);
+    }
 
     let key: Box<dyn Identity> = if let (Some(module), Some(slot), Some(keyid)) =
         (module, slot, keyid)
     {
         trace!("Getting user PIN");
-        let pin = rpassword::prompt_password("Please enter the HSM user PIN: ")
-            .expect("I/O error when reading HSM PIN");
+        let pin = hsm::read_pin(
+            &pin_source,
+            &|name| std::env::var(name).ok(),
+            std::io::stdin().is_terminal(),
+            || rpassword::prompt_password("Please enter the HSM user PIN: "),
+        )
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
         let keyid = hex::decode(keyid).expect("Failed to decode keyid to hex");
 
         {
@@ -366,45 +1137,147 @@ fn main() {
         }
 
         trace!("Creating CoseKeyIdentity");
-        // Only ECDSA is supported at the moment. It should be easy to add support for new EC mechanisms
-        Box::new(
-            HsmIdentity::new(HsmMechanismType::ECDSA)
-                .expect("Unable to create CoseKeyIdentity from HSM"),
-        )
+        let mechanism = hsm_mechanism.hsm_type().unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        Box::new(HsmIdentity::new(mechanism).expect("Unable to create CoseKeyIdentity from HSM"))
     } else {
         pem.map_or_else(
             || Box::new(AnonymousIdentity) as Box<dyn Identity>,
-            |p| Box::new(CoseKeyIdentity::from_pem(&std::fs::read_to_string(&p).unwrap()).unwrap()),
+            |p| {
+                Box::new(identity::read_pem(&p).unwrap_or_else(|e| {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }))
+            },
         )
     };
 
     let client_address = key.address();
+    let key = attachment::SharedIdentity::new(key);
+    attachment::init(key.clone(), server_id);
+    if let Some(dir) = cache::default_dir(&|name| std::env::var(name).ok()) {
+        if !no_cache {
+            cache::init_symbols(cache::SymbolCache::new(
+                &dir,
+                &server,
+                server_id,
+                symbols_ttl.into(),
+            ));
+        }
+        cache::init(cache::Cache::new(dir, server_id, client_address));
+    }
     let client = ManyClient::new(&server, server_id, key).unwrap();
+    // Queries on many addresses are made concurrently, on connections of
+    // their own.
+    let connect =
+        || ManyClient::new(&server, server_id, AnonymousIdentity).map_err(ManyError::unknown);
     let result = match subcommand {
-        SubCommand::Balance(BalanceOpt { identity, symbols }) => {
-            let identity = identity.map(|identity| {
-                Address::from_str(&identity)
-                    .or_else(|_| {
-                        let bytes = std::fs::read_to_string(PathBuf::from(identity))?;
-
-                        Ok(CoseKeyIdentity::from_pem(&bytes).unwrap().address())
-                    })
-                    .map_err(|_: std::io::Error| ())
-                    .expect("Unable to decode identity command-line argument")
-            });
-
-            balance(client, identity, symbols)
+        SubCommand::Balance(BalanceOpt {
+            identity,
+            subresource_range,
+            limits,
+            offline,
+            raw,
+            watch,
+            interval,
+            symbols,
+        }) => {
+            if offline {
+                cache::set_offline();
+            }
+            let identities: Vec<Address> = identity
+                .into_iter()
+                .map(|identity| {
+                    alias::address(&identity)
+                        .or_else(|e| {
+                            let path = PathBuf::from(identity);
+                            if !path.exists() {
+                                return Err(e);
+                            }
+                            crate::identity::read_pem(&path).map(|id| id.address())
+                        })
+                        .unwrap_or_else(|e| {
+                            error!("{}", e);
+                            std::process::exit(1);
+                        })
+                })
+                .collect();
+
+            match subresource_range {
+                Some(_) if identities.len() > 1 => Err(ManyError::unknown(
+                    "--subresource-range reads the balances of a single identity.",
+                )),
+                Some(range) => subresources::balance(
+                    client,
+                    connect,
+                    identities.first().copied().unwrap_or(client_address),
+                    symbols,
+                    range,
+                    limits,
+                ),
+                None => balance(client, identities, symbols, raw, watch.then_some(interval)),
+            }
         }
         SubCommand::Send(TargetCommandOpt {
             account,
             identity,
             amount,
+            decimal,
             symbol,
+            offline,
+            self_transfer,
+            force,
+            memo,
+            dry_run,
+            attach,
+            split,
         }) => {
+            // Before resolving the amount, which can read the decimals of the
+            // symbol from the server.
+            if dry_run {
+                cache::set_offline();
+            }
             let from = account.unwrap_or(client_address);
-            send(client, from, identity, amount, symbol)
+            amount_units(&client, &amount, &symbol, decimal).and_then(|amount| {
+                send(
+                    client,
+                    client_address,
+                    from,
+                    identity,
+                    amount,
+                    symbol,
+                    offline,
+                    self_transfer,
+                    force,
+                    memo,
+                    dry_run,
+                    attach,
+                    split,
+                )
+            })
+        }
+        SubCommand::Sign(opts) => sign::sign(client_address, opts),
+        SubCommand::Submit(opts) => submit::submit(client, opts),
+        SubCommand::Info(opts) => info::info(client, opts),
+        SubCommand::SendBatch(opts) => batch::send_batch(client, client_address, opts),
+        SubCommand::SendMany(opts) => send_many::send_many(client, client_address, opts),
+        SubCommand::History(opts) => history::history(client, client_address, opts),
+        SubCommand::Event(opts) => event::event(client, opts),
+        SubCommand::Submitted(opts) => submitted::submitted(client, client_address, opts),
+        SubCommand::Multisig(opts) => {
+            multisig::multisig(client, client_address, &server, server_id, opts)
         }
-        SubCommand::Multisig(opts) => multisig::multisig(client, opts),
+        SubCommand::Escrow(opts) => escrow::escrow(client, opts),
+        SubCommand::Account(opts) => account::account(client, opts),
+        SubCommand::Swap(opts) => swap::swap(client, opts),
+        SubCommand::Sweep(opts) => subresources::sweep(client, connect, client_address, opts),
+        SubCommand::Template(opts) => template::template(client, client_address, server_id, opts),
+        SubCommand::AsyncStatus(opts) => async_status::async_status(client, opts),
+        SubCommand::Alias(opts) => alias::alias(opts),
+        SubCommand::Cache(opts) => cache::cache(opts),
+        SubCommand::Doctor(_) | SubCommand::Hsm(_) | SubCommand::Key(_) => unreachable!(),
     };
 
     if let Err(err) = result {
@@ -415,6 +1288,9 @@ fn main() {
                 .collect::<Vec<&str>>()
                 .join("\n|  ")
         );
+        if let Some(hint) = expiry::stale_hint(&err, SystemTime::now()) {
+            warn!("{}", hint);
+        }
         std::process::exit(1);
     }
 }
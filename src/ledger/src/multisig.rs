@@ -1,14 +1,23 @@
+use crate::transport::ManyClient;
 use crate::TargetCommandOpt;
 use clap::Parser;
-use many_client::client::blocking::ManyClient;
+use inbox::InboxServer;
 use many_error::ManyError;
-use many_identity::{Address, Identity};
+use many_identity::{Address, AnonymousIdentity, Identity};
 use many_modules::account::features::multisig;
 use many_modules::{events, ledger};
 use many_protocol::ResponseMessage;
-use many_types::ledger::TokenAmount;
+use many_types::ledger::{Symbol, TokenAmount};
 use minicbor::bytes::ByteVec;
-use tracing::info;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{info, warn};
+
+mod inbox;
+mod status;
+mod webauthn;
 
 #[derive(Parser)]
 pub struct CommandOpt {
@@ -20,6 +29,7 @@ pub struct CommandOpt {
 #[derive(Parser)]
 struct SetDefaultsOpt {
     /// The account to set defaults of.
+    #[clap(parse(try_from_str = crate::alias::address))]
     target_account: Address,
 
     #[clap(flatten)]
@@ -31,6 +41,7 @@ enum SubcommandOpt {
     /// Submit a new transaction to be approved.
     Submit {
         /// The account to use as the source of the multisig command.
+        #[clap(parse(try_from_str = crate::alias::address))]
         account: Address,
 
         #[clap(flatten)]
@@ -41,19 +52,39 @@ enum SubcommandOpt {
     },
 
     /// Approve a transaction.
-    Approve(TransactionOpt),
+    Approve(ApproveOpt),
+
+    /// Submit an approval signed by the web approval page, as prepared by
+    /// `approve --prepare-webauthn`. The envelope is sent unmodified.
+    SubmitApproval(webauthn::SubmitApprovalOpt),
+
+    /// Approve the transactions of a file of tokens, one in hexadecimal per
+    /// line.
+    ApproveBatch(ApproveBatchOpt),
 
     /// Revoke approval of a transaction.
     Revoke(TransactionOpt),
 
+    /// Withdraw a transaction you submitted, so it cannot be approved or
+    /// executed anymore.
+    Withdraw(TransactionOpt),
+
     /// Execute a transaction.
     Execute(TransactionOpt),
 
     /// Show the information of a multisig transaction.
-    Info(TransactionOpt),
+    Info(InfoOpt),
 
     /// Set new defaults for the multisig account.
     SetDefaults(SetDefaultsOpt),
+
+    /// Walk through the transactions awaiting your approval, across all
+    /// accounts.
+    Inbox(inbox::InboxOpt),
+
+    /// Show the approvals, expiry and state of the transactions of a file of
+    /// tokens in a single table.
+    Status(status::StatusOpt),
 }
 
 #[derive(Parser)]
@@ -77,13 +108,57 @@ struct TransactionOpt {
 }
 
 #[derive(Parser)]
-struct MultisigArgOpt {
+struct ApproveOpt {
+    #[clap(flatten)]
+    transaction: TransactionOpt,
+
+    /// Do not approve, but print the approval for the web approval page to
+    /// have it signed by a WebAuthn credential, as JSON.
+    #[clap(long)]
+    prepare_webauthn: bool,
+
+    /// The address of the WebAuthn identity approving, when preparing an
+    /// approval. Defaults to the caller.
+    #[clap(long, requires = "prepare-webauthn", parse(try_from_str = crate::alias::address))]
+    approver: Option<Address>,
+}
+
+#[derive(Parser)]
+struct ApproveBatchOpt {
+    /// The file of tokens. Blank lines and lines starting with `#` are
+    /// skipped.
+    file: PathBuf,
+
+    /// Stop at the first approval which fails, instead of trying the
+    /// others.
+    #[clap(long)]
+    stop_on_error: bool,
+}
+
+#[derive(Parser)]
+struct InfoOpt {
+    #[clap(flatten)]
+    transaction: TransactionOpt,
+
+    /// Fetch the attachment of the transaction from the key-value store at
+    /// this URL, and check it against its hash.
+    #[clap(long)]
+    kvstore: Option<String>,
+
+    /// Show the transaction cached by the last successful call, without
+    /// connecting to the server.
+    #[clap(long, conflicts_with = "kvstore")]
+    offline: bool,
+}
+
+#[derive(Default, Parser)]
+pub(crate) struct MultisigArgOpt {
     /// The number of approvals needed to execute a transaction.
     #[clap(long)]
     threshold: Option<u64>,
 
-    /// The timeout of a transaction.
-    #[clap(long)]
+    /// The timeout of a transaction, e.g. `1d`, `12h` or `30m`.
+    #[clap(long, visible_alias = "expire-in")]
     timeout: Option<humantime::Duration>,
 
     /// Whether to execute a transaction automatically when the threshold of
@@ -92,8 +167,38 @@ struct MultisigArgOpt {
     execute_automatically: Option<bool>,
 }
 
-fn submit_send(
+impl MultisigArgOpt {
+    /// Refuse a threshold of 0 or a timeout which is already over, before
+    /// signing anything.
+    fn validate(&self) -> Result<(), ManyError> {
+        if self.threshold == Some(0) {
+            return Err(ManyError::unknown(
+                "The threshold must be at least 1 approval.",
+            ));
+        }
+        if self.timeout.map_or(false, |d| d.as_secs() == 0) {
+            return Err(ManyError::unknown(
+                "The timeout must be at least 1s, the transaction would expire immediately.",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Print the token of a submitted transaction, to share with the approvers.
+fn print_token(token: &ByteVec) {
+    let token = hex::encode(token.as_slice());
+    match crate::output::get() {
+        crate::output::OutputFormat::Human => println!("Transaction token: {}", token),
+        crate::output::OutputFormat::Json => {
+            crate::output::print_json(&serde_json::json!({ "token": token }))
+        }
+    }
+}
+
+pub(crate) fn submit_send(
     client: ManyClient<impl Identity>,
+    caller: Address,
     account: Address,
     multisig_arg: MultisigArgOpt,
     opts: TargetCommandOpt,
@@ -102,39 +207,87 @@ fn submit_send(
         account: from,
         identity,
         amount,
+        decimal,
         symbol,
+        offline,
+        self_transfer,
+        force,
+        memo,
+        dry_run,
+        attach,
+        split,
     } = opts;
+    if split.is_set() {
+        return Err(ManyError::unknown(
+            "--split is not supported for multisig transactions, submit each part instead.",
+        ));
+    }
+    multisig_arg.validate()?;
     let MultisigArgOpt {
         threshold,
         timeout,
         execute_automatically,
     } = multisig_arg;
+    if dry_run {
+        crate::cache::set_offline();
+    }
+    let offline = offline || dry_run;
+    let amount = TokenAmount::from(crate::amount_units(&client, &amount, &symbol, decimal)?);
     let symbol = crate::resolve_symbol(&client, symbol)?;
+    let from = from.unwrap_or(account);
+    crate::limits::check_self_transfer(&from, &identity, self_transfer)?;
+    if !offline {
+        crate::account::check_signer(
+            &client,
+            account,
+            caller,
+            &crate::account::MULTISIG_SUBMIT_ROLES,
+            force,
+        )?;
+    }
+    // The balance can change before the transaction is executed, so only
+    // warn about it.
+    crate::limits::check(&client, from, symbol, &amount, offline, true)?;
+    crate::limits::check_receive(&client, from, identity, symbol, &amount, offline);
+    let memo = crate::limits::check_memo(memo)?;
+
     let transaction = events::AccountMultisigTransaction::Send(ledger::SendArgs {
-        from: from.or(Some(account)),
+        from: Some(from),
         to: identity,
         symbol,
-        amount: TokenAmount::from(amount),
+        amount,
     });
     let arguments = multisig::SubmitTransactionArgs {
         account,
-        memo: None,
+        memo,
         transaction: Box::new(transaction),
         threshold,
         timeout_in_secs: timeout.map(|d| d.as_secs()),
         execute_automatically,
         data: None,
     };
-    let response = client.call("account.multisigSubmitTransaction", arguments)?;
+    if dry_run {
+        return crate::expiry::print_signed(
+            &client,
+            "account.multisigSubmitTransaction",
+            arguments,
+            offline,
+        );
+    }
 
-    let payload = crate::wait_response(client, response)?;
+    let attachment = attach.upload()?;
+    let response = crate::attachment::call(
+        &client,
+        "account.multisigSubmitTransaction",
+        arguments,
+        attachment.as_ref(),
+    )?;
+
+    let payload = crate::wait_response(&client, response)?;
     let result: multisig::SubmitTransactionReturn =
         minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
 
-    info!(
-        "Transaction Token: {}",
-        hex::encode(result.token.as_slice())
-    );
+    print_token(&result.token);
     Ok(())
 }
 
@@ -145,6 +298,8 @@ fn submit_set_defaults(
     target: Address,
     opts: MultisigArgOpt,
 ) -> Result<(), ManyError> {
+    multisig_arg.validate()?;
+    opts.validate()?;
     let MultisigArgOpt {
         threshold,
         timeout,
@@ -167,27 +322,25 @@ fn submit_set_defaults(
         execute_automatically,
         data: None,
     };
-    let response = client.call("account.multisigSubmitTransaction", arguments)?;
+    let response = crate::transport::call(&client, "account.multisigSubmitTransaction", arguments)?;
 
-    let payload = crate::wait_response(client, response)?;
+    let payload = crate::wait_response(&client, response)?;
     let result: multisig::SubmitTransactionReturn =
         minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
 
-    info!(
-        "Transaction Token: {}",
-        hex::encode(result.token.as_slice())
-    );
+    print_token(&result.token);
     Ok(())
 }
 
 fn submit(
     client: ManyClient<impl Identity>,
+    caller: Address,
     account: Address,
     multisig_arg: MultisigArgOpt,
     opts: SubmitOpt,
 ) -> Result<(), ManyError> {
     match opts {
-        SubmitOpt::Send(target) => submit_send(client, account, multisig_arg, target),
+        SubmitOpt::Send(target) => submit_send(client, caller, account, multisig_arg, target),
         SubmitOpt::SetDefaults(SetDefaultsOpt {
             target_account,
             opts,
@@ -195,54 +348,456 @@ fn submit(
     }
 }
 
-fn approve(client: ManyClient<impl Identity>, opts: TransactionOpt) -> Result<(), ManyError> {
-    let arguments = multisig::ApproveArgs { token: opts.token };
-    let response = client.call("account.multisigApprove", arguments)?;
+fn approve(
+    client: ManyClient<impl Identity>,
+    caller: Address,
+    server: &str,
+    server_id: Address,
+    opts: ApproveOpt,
+) -> Result<(), ManyError> {
+    let ApproveOpt {
+        transaction: TransactionOpt { token },
+        prepare_webauthn,
+        approver,
+    } = opts;
+    if prepare_webauthn {
+        return webauthn::prepare(
+            &client,
+            server,
+            server_id,
+            approver.unwrap_or(caller),
+            token,
+        );
+    }
+
+    approve_token(&client, token)?;
+    info!("Approved.");
+
+    Ok(())
+}
+
+fn approve_token(client: &ManyClient<impl Identity>, token: ByteVec) -> Result<(), ManyError> {
+    let arguments = multisig::ApproveArgs { token };
+    let response = crate::transport::call(client, "account.multisigApprove", arguments)?;
 
     let payload = crate::wait_response(client, response)?;
     let _result: multisig::ApproveReturn =
         minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    Ok(())
+}
 
-    info!("Approved.");
+/// The tokens of a file, one in hexadecimal per line. Blank lines and lines
+/// starting with `#` are skipped.
+fn parse_tokens(content: &str) -> Result<Vec<ByteVec>, String> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            parse_token(line).map_err(|e| format!("Invalid token on line {}: {}", number, e))
+        })
+        .collect()
+}
+
+/// Approve each token in turn, and return the result of each approval
+/// attempted. Stops at the first failure if `stop_on_error` is set.
+fn approve_all(
+    tokens: Vec<ByteVec>,
+    stop_on_error: bool,
+    mut approve: impl FnMut(ByteVec) -> Result<(), ManyError>,
+) -> Vec<(ByteVec, Result<(), ManyError>)> {
+    let mut results = Vec::new();
+    for token in tokens {
+        let result = approve(token.clone());
+        let failed = result.is_err();
+        results.push((token, result));
+        if failed && stop_on_error {
+            break;
+        }
+    }
+    results
+}
+
+fn approve_batch(
+    client: ManyClient<impl Identity>,
+    opts: ApproveBatchOpt,
+) -> Result<(), ManyError> {
+    let ApproveBatchOpt {
+        file,
+        stop_on_error,
+    } = opts;
+    let content = std::fs::read_to_string(&file).map_err(|e| ManyError::unknown(e.to_string()))?;
+    let tokens = parse_tokens(&content).map_err(ManyError::unknown)?;
+    let count = tokens.len();
+
+    let results = approve_all(tokens, stop_on_error, |token| {
+        let result = approve_token(&client, token.clone());
+        if crate::output::get() == crate::output::OutputFormat::Human {
+            match &result {
+                Ok(()) => println!("{}: approved", hex::encode(token.as_slice())),
+                Err(e) => println!("{}: failed: {}", hex::encode(token.as_slice()), e),
+            }
+        }
+        result
+    });
+    if crate::output::get() == crate::output::OutputFormat::Json {
+        crate::output::print_json(&serde_json::Value::Array(
+            results
+                .iter()
+                .map(|(token, result)| {
+                    serde_json::json!({
+                        "token": hex::encode(token.as_slice()),
+                        "approved": result.is_ok(),
+                        "error": result.as_ref().err().map(ToString::to_string),
+                    })
+                })
+                .collect(),
+        ));
+    }
 
+    let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+    if failed > 0 {
+        return Err(ManyError::unknown(format!(
+            "{} of {} approvals failed{}.",
+            failed,
+            count,
+            if results.len() < count {
+                format!(", {} not attempted", count - results.len())
+            } else {
+                String::new()
+            }
+        )));
+    }
+    info!("Approved {} transactions.", count);
     Ok(())
 }
 
 fn revoke(client: ManyClient<impl Identity>, opts: TransactionOpt) -> Result<(), ManyError> {
-    let arguments = multisig::RevokeArgs { token: opts.token };
-    let response = client.call("account.multisigRevoke", arguments)?;
+    let arguments = multisig::RevokeArgs {
+        token: opts.token.clone(),
+    };
+    let response = crate::transport::call(&client, "account.multisigRevoke", arguments)?;
 
-    let payload = crate::wait_response(client, response)?;
+    let payload = crate::wait_response(&client, response)?;
     let _result: multisig::RevokeReturn =
         minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
 
     info!("Revoked.");
+    print_info(&client, &opts.token)
+}
 
-    Ok(())
+/// Explain the error of a withdrawal refused because the caller did not
+/// submit the transaction. Other errors are returned as they are.
+fn withdraw_error(e: ManyError, caller: &Address, submitter: Option<Address>) -> ManyError {
+    if e.code() != multisig::errors::cannot_execute_transaction().code() {
+        return e;
+    }
+    let submitter = submitter.map_or_else(|| "someone else".to_string(), |s| s.to_string());
+    ManyError::unknown(format!(
+        "Only the submitter of a transaction can withdraw it, and {} submitted this one, not {}. \
+        The server said: {}",
+        submitter, caller, e
+    ))
 }
 
-fn execute(client: ManyClient<impl Identity>, opts: TransactionOpt) -> Result<(), ManyError> {
-    let arguments = multisig::ExecuteArgs { token: opts.token };
-    let response = client.call("account.multisigExecute", arguments)?;
+fn withdraw(
+    client: ManyClient<impl Identity>,
+    caller: Address,
+    opts: TransactionOpt,
+) -> Result<(), ManyError> {
+    let arguments = multisig::WithdrawArgs {
+        token: opts.token.clone(),
+    };
+    let payload = crate::transport::call(&client, "account.multisigWithdraw", arguments)
+        .and_then(|response| crate::wait_response(&client, response))
+        .map_err(|e| {
+            let submitter = client
+                .multisig_info(&opts.token)
+                .ok()
+                .map(|info| info.submitter);
+            withdraw_error(e, &caller, submitter)
+        })?;
+    let _result: multisig::WithdrawReturn =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
 
-    let payload = crate::wait_response(client, response)?;
+    info!("Withdrawn.");
+    print_info(&client, &opts.token)
+}
+
+/// The server refuses to execute a transaction with the same error whether
+/// it lacks approvals or the caller is not allowed to, so tell which one it
+/// is from the transaction.
+fn execute_error(e: ManyError, info: Option<&multisig::InfoReturn>) -> ManyError {
+    let info = match info {
+        Some(info) if e.code() == multisig::errors::cannot_execute_transaction().code() => info,
+        _ => return e,
+    };
+    let approved = info.approvers.values().filter(|a| a.approved).count() as u64;
+    if approved < info.threshold {
+        ManyError::unknown(format!(
+            "This transaction has {} of the {} approvals it needs, {} more are needed to execute \
+            it. The server said: {}",
+            approved,
+            info.threshold,
+            info.threshold - approved,
+            e
+        ))
+    } else {
+        ManyError::unknown(format!(
+            "This transaction has the {} approvals it needs, but only its submitter {} or an \
+            owner of the account can execute it. The server said: {}",
+            info.threshold, info.submitter, e
+        ))
+    }
+}
+
+/// Describe the result of an executed transaction, from the payload of its
+/// response.
+fn describe_result(
+    transaction: &events::AccountMultisigTransaction,
+    data: &[u8],
+    local_names: &BTreeMap<Symbol, String>,
+) -> String {
+    match transaction {
+        events::AccountMultisigTransaction::Send(ledger::SendArgs {
+            from,
+            to,
+            symbol,
+            amount,
+        }) => format!(
+            "Sent {} {} from {} to {}.",
+            amount,
+            symbol_name(local_names, symbol),
+            from.map_or_else(|| "the account".to_string(), |f| f.to_string()),
+            to
+        ),
+        events::AccountMultisigTransaction::AccountCreate(_) => {
+            match minicbor::decode::<many_modules::account::CreateReturn>(data) {
+                Ok(created) => format!("Created the account {}.", created.id),
+                Err(_) => minicbor::display(data).to_string(),
+            }
+        }
+        _ => minicbor::display(data).to_string(),
+    }
+}
+
+/// The event of a send just executed, if it is the last send of its source.
+fn send_event(
+    client: &ManyClient<impl Identity>,
+    send: &ledger::SendArgs,
+) -> Result<Option<events::EventId>, ManyError> {
+    let from = match send.from {
+        Some(from) => from,
+        None => return Ok(None),
+    };
+    let filter = events::EventFilter {
+        account: Some(vec![from].into()),
+        kind: Some(vec![events::EventKind::Send].into()),
+        ..events::EventFilter::default()
+    };
+    let payload = crate::transport::call_(
+        client,
+        "events.list",
+        events::ListArgs {
+            count: Some(1),
+            order: Some(many_types::SortOrder::Descending),
+            filter: Some(filter),
+        },
+    )?;
+    let list: events::ListReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    Ok(list.events.into_iter().find_map(|log| match log.content {
+        events::EventInfo::Send {
+            from: f,
+            to,
+            symbol,
+            amount,
+        } if f == from && to == send.to && symbol == send.symbol && amount == send.amount => {
+            Some(log.id)
+        }
+        _ => None,
+    }))
+}
+
+fn execute(client: ManyClient<impl Identity>, opts: TransactionOpt) -> Result<(), ManyError> {
+    let info = client.multisig_info(&opts.token).ok();
+    let arguments = multisig::ExecuteArgs {
+        token: opts.token.clone(),
+    };
+    let payload = crate::transport::call(&client, "account.multisigExecute", arguments)
+        .and_then(|response| crate::wait_response(&client, response))
+        .map_err(|e| execute_error(e, info.as_ref()))?;
     let result: ResponseMessage =
         minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    let data = result.data?;
 
-    info!("Executed:");
-    println!("{}", minicbor::display(&result.data?));
+    info!("Executed.");
+    match info {
+        Some(info) => {
+            let local_names = client.local_names().unwrap_or_default();
+            println!(
+                "{}",
+                describe_result(&info.transaction, &data, &local_names)
+            );
+            if let events::AccountMultisigTransaction::Send(send) = &info.transaction {
+                match send_event(&client, send) {
+                    Ok(Some(id)) => println!("Event: {}", hex::encode(id.as_ref())),
+                    Ok(None) => {}
+                    Err(e) => warn!("Could not find the event of the send: {}", e),
+                }
+            }
+        }
+        None => println!("{}", minicbor::display(&data)),
+    }
     Ok(())
 }
 
-fn info(client: ManyClient<impl Identity>, opts: TransactionOpt) -> Result<(), ManyError> {
-    let arguments = multisig::InfoArgs { token: opts.token };
-    let response = client.call("account.multisigInfo", arguments)?;
+/// Print the information of a transaction.
+fn print_info(client: &ManyClient<impl Identity>, token: &ByteVec) -> Result<(), ManyError> {
+    let arguments = multisig::InfoArgs {
+        token: token.clone(),
+    };
+    let payload = crate::cache::call_(client, "account.multisigInfo", arguments)?;
+    match minicbor::decode::<multisig::InfoReturn>(&payload) {
+        Ok(result) => {
+            let local_names = crate::cache::call_(client, "ledger.info", ())
+                .ok()
+                .and_then(|info| minicbor::decode::<ledger::InfoReturns>(&info).ok())
+                .map(|info| info.local_names)
+                .unwrap_or_default();
+            let mut out = std::io::stdout();
+            describe(&mut out, token, &result, &local_names)
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+        }
+        // A transaction of a type this client does not know.
+        Err(e) => {
+            warn!("Could not decode the transaction: {}", e);
+            println!("{}", minicbor::display(&payload));
+        }
+    }
+    Ok(())
+}
 
-    let payload = crate::wait_response(client, response)?;
-    let result: multisig::InfoReturn =
-        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+fn info(client: ManyClient<impl Identity>, opts: InfoOpt) -> Result<(), ManyError> {
+    let InfoOpt {
+        transaction: TransactionOpt { token },
+        kvstore,
+        offline,
+    } = opts;
+    if offline {
+        crate::cache::set_offline();
+    }
+    print_info(&client, &token)?;
+    if let Some(url) = kvstore {
+        match crate::attachment::of_multisig(&client, token)? {
+            Some(attachment) => println!("{}", crate::attachment::verify(&url, &attachment)),
+            None => println!("No attachment."),
+        }
+    }
+    Ok(())
+}
 
-    println!("{:#?}", result);
+fn symbol_name(local_names: &BTreeMap<Symbol, String>, symbol: &Symbol) -> String {
+    local_names
+        .get(symbol)
+        .cloned()
+        .unwrap_or_else(|| symbol.to_string())
+}
+
+/// Print a multisig transaction for a human to review before approving it.
+fn describe(
+    out: &mut impl Write,
+    token: &ByteVec,
+    info: &multisig::InfoReturn,
+    local_names: &BTreeMap<Symbol, String>,
+) -> std::io::Result<()> {
+    writeln!(out, "Transaction {}", hex::encode(token.as_slice()))?;
+    writeln!(out, "  State:     {:?}", info.state)?;
+    writeln!(out, "  Submitter: {}", info.submitter)?;
+    if let Some(memo) = &info.memo {
+        writeln!(out, "  Memo:      {:?}", memo)?;
+    }
+    match &info.transaction {
+        events::AccountMultisigTransaction::Send(ledger::SendArgs {
+            from,
+            to,
+            symbol,
+            amount,
+        }) => {
+            writeln!(out, "  Send:")?;
+            match from {
+                Some(from) => writeln!(out, "    From:    {}", from)?,
+                None => writeln!(out, "    From:    the account")?,
+            }
+            writeln!(out, "    To:      {}", to)?;
+            writeln!(out, "    Symbol:  {}", symbol_name(local_names, symbol))?;
+            writeln!(out, "    Amount:  {}", amount)?;
+        }
+        events::AccountMultisigTransaction::AccountMultisigSetDefaults(
+            multisig::SetDefaultsArgs {
+                account,
+                threshold,
+                timeout_in_secs,
+                execute_automatically,
+            },
+        ) => {
+            writeln!(out, "  Set defaults of {}:", account)?;
+            if let Some(threshold) = threshold {
+                writeln!(out, "    Threshold: {}", threshold)?;
+            }
+            if let Some(timeout) = timeout_in_secs {
+                writeln!(
+                    out,
+                    "    Timeout:   {}",
+                    humantime::format_duration(Duration::from_secs(*timeout))
+                )?;
+            }
+            if let Some(execute_automatically) = execute_automatically {
+                writeln!(out, "    Execute automatically: {}", execute_automatically)?;
+            }
+        }
+        other => match minicbor::to_vec(other) {
+            Ok(bytes) => writeln!(out, "  Transaction: {}", minicbor::display(&bytes))?,
+            Err(_) => writeln!(out, "  Transaction: {:?}", other)?,
+        },
+    }
+    writeln!(
+        out,
+        "  Approvals: {}/{}",
+        info.approvers.values().filter(|a| a.approved).count(),
+        info.threshold
+    )?;
+    match info.timeout.as_system_time() {
+        Ok(timeout) => writeln!(
+            out,
+            "  Expires:   {}",
+            humantime::format_rfc3339_seconds(timeout)
+        )?,
+        Err(_) => writeln!(out, "  Expires:   {:?}", info.timeout)?,
+    }
+    writeln!(
+        out,
+        "  Execute automatically: {}",
+        if info.execute_automatically {
+            "yes"
+        } else {
+            "no"
+        }
+    )?;
+    writeln!(out, "  Approvers:")?;
+    for (approver, approver_info) in &info.approvers {
+        writeln!(
+            out,
+            "    {} {}",
+            approver,
+            if approver_info.approved {
+                "approved"
+            } else {
+                "not approved"
+            }
+        )?;
+    }
     Ok(())
 }
 
@@ -251,36 +806,386 @@ fn set_defaults(
     account: Address,
     opts: MultisigArgOpt,
 ) -> Result<(), ManyError> {
+    if opts.threshold.is_none() && opts.timeout.is_none() && opts.execute_automatically.is_none() {
+        return Err(ManyError::unknown(
+            "Nothing to set, pass one of --threshold, --timeout or --execute-automatically.",
+        ));
+    }
+    opts.validate()?;
+
     let arguments = multisig::SetDefaultsArgs {
         account,
         threshold: opts.threshold,
         timeout_in_secs: opts.timeout.map(|d| d.as_secs()),
         execute_automatically: opts.execute_automatically,
     };
-    let response = client.call("account.multisigSetDefaults", arguments)?;
+    let response = crate::transport::call(&client, "account.multisigSetDefaults", arguments)?;
 
-    let payload = crate::wait_response(client, response)?;
+    let payload = crate::wait_response(&client, response)?;
     let _result: multisig::SetDefaultsReturn =
         minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
 
     info!("Defaults set.");
+    let info = client.account_info(&account)?;
+    let feature = info
+        .features
+        .get::<multisig::MultisigAccountFeature>()
+        .map_err(|_| {
+            ManyError::unknown(format!("The account {} has no multisig feature.", account))
+        })?;
+    println!("{}", format_defaults(&account, &feature.arg));
     Ok(())
 }
 
-pub fn multisig(client: ManyClient<impl Identity>, opts: CommandOpt) -> Result<(), ManyError> {
+/// The multisig settings of an account, with the server defaults for those
+/// it does not set.
+fn format_defaults(account: &Address, arg: &multisig::MultisigAccountFeatureArg) -> String {
+    let or_default = |value: Option<String>| value.unwrap_or_else(|| "server default".to_string());
+    format!(
+        "Multisig defaults of {}\n\
+         \x20 Threshold:             {}\n\
+         \x20 Timeout:               {}\n\
+         \x20 Execute automatically: {}",
+        account,
+        or_default(arg.threshold.map(|t| t.to_string())),
+        or_default(
+            arg.timeout_in_secs
+                .map(|t| humantime::format_duration(Duration::from_secs(t)).to_string())
+        ),
+        or_default(
+            arg.execute_automatically
+                .map(|e| if e { "yes" } else { "no" }.to_string())
+        ),
+    )
+}
+
+pub fn multisig(
+    client: ManyClient<impl Identity>,
+    caller: Address,
+    server: &str,
+    server_id: Address,
+    opts: CommandOpt,
+) -> Result<(), ManyError> {
     match opts.subcommand {
         SubcommandOpt::Submit {
             account,
             multisig_arg,
             subcommand,
-        } => submit(client, account, multisig_arg, subcommand),
-        SubcommandOpt::Approve(sub_opts) => approve(client, sub_opts),
+        } => submit(client, caller, account, multisig_arg, subcommand),
+        SubcommandOpt::Approve(sub_opts) => approve(client, caller, server, server_id, sub_opts),
+        SubcommandOpt::ApproveBatch(sub_opts) => approve_batch(client, sub_opts),
+        SubcommandOpt::SubmitApproval(sub_opts) => webauthn::submit_approval(client, sub_opts),
         SubcommandOpt::Revoke(sub_opts) => revoke(client, sub_opts),
+        SubcommandOpt::Withdraw(sub_opts) => withdraw(client, caller, sub_opts),
         SubcommandOpt::Execute(sub_opts) => execute(client, sub_opts),
         SubcommandOpt::Info(sub_opts) => info(client, sub_opts),
         SubcommandOpt::SetDefaults(SetDefaultsOpt {
             target_account,
             opts,
         }) => set_defaults(client, target_account, opts),
+        SubcommandOpt::Inbox(opts) => inbox::inbox(client, caller, opts),
+        SubcommandOpt::Status(opts) => {
+            // Transactions are queried concurrently, on connections of their
+            // own.
+            let connect = || {
+                ManyClient::new(server, server_id, AnonymousIdentity).map_err(ManyError::unknown)
+            };
+            status::status(client, connect, opts)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use many_types::Timestamp;
+    use std::str::FromStr;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn info(transaction: events::AccountMultisigTransaction) -> multisig::InfoReturn {
+        multisig::InfoReturn {
+            memo: None,
+            transaction,
+            submitter: address(1),
+            approvers: BTreeMap::from([
+                (address(1), multisig::ApproverInfo { approved: true }),
+                (address(2), multisig::ApproverInfo { approved: false }),
+            ]),
+            threshold: 2,
+            execute_automatically: true,
+            timeout: Timestamp::new(1_655_000_300).unwrap(),
+            data: None,
+            state: multisig::MultisigTransactionState::Pending,
+        }
+    }
+
+    fn described(info: &multisig::InfoReturn, local_names: &BTreeMap<Symbol, String>) -> String {
+        let mut out = Vec::new();
+        describe(&mut out, &ByteVec::from(vec![1, 2]), info, local_names).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn send() {
+        let send = events::AccountMultisigTransaction::Send(ledger::SendArgs {
+            from: Some(address(3)),
+            to: address(4),
+            symbol: address(1000),
+            amount: TokenAmount::from(1_000u64),
+        });
+        let local_names = BTreeMap::from([(address(1000), "MFX".to_string())]);
+        assert_eq!(
+            described(&info(send), &local_names),
+            format!(
+                "Transaction 0102\n\
+                 \x20 State:     Pending\n\
+                 \x20 Submitter: {submitter}\n\
+                 \x20 Send:\n\
+                 \x20   From:    {from}\n\
+                 \x20   To:      {to}\n\
+                 \x20   Symbol:  MFX\n\
+                 \x20   Amount:  1000\n\
+                 \x20 Approvals: 1/2\n\
+                 \x20 Expires:   2022-06-12T02:18:20Z\n\
+                 \x20 Execute automatically: yes\n\
+                 \x20 Approvers:\n\
+                 \x20   {submitter} approved\n\
+                 \x20   {other} not approved\n",
+                submitter = address(1),
+                from = address(3),
+                to = address(4),
+                other = address(2),
+            )
+        );
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            parse_tokens("# Approvals of the day\n0102\n\n  # Refunds\n  0a0b  \n"),
+            Ok(vec![ByteVec::from(vec![1, 2]), ByteVec::from(vec![10, 11])])
+        );
+        assert_eq!(parse_tokens(""), Ok(vec![]));
+        assert!(parse_tokens("0102\nzz")
+            .unwrap_err()
+            .starts_with("Invalid token on line 2"));
+    }
+
+    #[test]
+    fn batch() {
+        let tokens = || {
+            vec![
+                ByteVec::from(vec![1]),
+                ByteVec::from(vec![2]),
+                ByteVec::from(vec![3]),
+            ]
+        };
+        let mut attempted = Vec::new();
+        let approve = |attempted: &mut Vec<ByteVec>, token: ByteVec| {
+            attempted.push(token.clone());
+            if token.as_slice() == [2] {
+                Err(ManyError::unknown("no"))
+            } else {
+                Ok(())
+            }
+        };
+
+        // A failure does not abort the batch.
+        let results = approve_all(tokens(), false, |t| approve(&mut attempted, t));
+        assert_eq!(attempted, tokens());
+        assert_eq!(
+            results.iter().map(|(_, r)| r.is_ok()).collect::<Vec<_>>(),
+            vec![true, false, true]
+        );
+
+        attempted.clear();
+        let results = approve_all(tokens(), true, |t| approve(&mut attempted, t));
+        assert_eq!(attempted, tokens()[..2].to_vec());
+        assert_eq!(results.len(), 2);
+        assert!(results[1].1.is_err());
+    }
+
+    #[test]
+    fn other_transactions() {
+        let set_defaults = events::AccountMultisigTransaction::AccountMultisigSetDefaults(
+            multisig::SetDefaultsArgs {
+                account: address(3),
+                threshold: Some(3),
+                timeout_in_secs: Some(3600),
+                execute_automatically: None,
+            },
+        );
+        let out = described(&info(set_defaults), &BTreeMap::new());
+        assert!(out.contains(&format!("  Set defaults of {}:\n", address(3))));
+        assert!(out.contains("    Threshold: 3\n"));
+        assert!(out.contains("    Timeout:   1h\n"));
+
+        // Other transactions are shown as CBOR.
+        let disable = events::AccountMultisigTransaction::AccountDisable(
+            many_modules::account::DisableArgs {
+                account: address(3),
+            },
+        );
+        let bytes = minicbor::to_vec(&disable).unwrap();
+        let out = described(&info(disable), &BTreeMap::new());
+        assert!(out.contains(&format!("  Transaction: {}\n", minicbor::display(&bytes))));
+    }
+
+    #[test]
+    fn withdraw_refused() {
+        let refused = multisig::errors::cannot_execute_transaction();
+        let message = withdraw_error(refused.clone(), &address(2), Some(address(1))).to_string();
+        assert!(message.contains(&format!(
+            "{} submitted this one, not {}",
+            address(1),
+            address(2)
+        )));
+        assert!(message.contains(&refused.to_string()));
+
+        // Other errors are left as they are.
+        let expired = multisig::errors::transaction_expired_or_withdrawn();
+        assert_eq!(
+            withdraw_error(expired.clone(), &address(2), Some(address(1))),
+            expired
+        );
+    }
+
+    #[test]
+    fn set_defaults_opts() {
+        let parse = |args: &[&str]| {
+            SetDefaultsOpt::try_parse_from(
+                ["set-defaults", &address(3).to_string()].iter().chain(args),
+            )
+            .map(|opt| opt.opts)
+        };
+        for (timeout, secs) in [("1d", 86_400), ("12h", 43_200), ("30m", 1_800)] {
+            let opts = parse(&["--timeout", timeout]).unwrap();
+            assert_eq!(opts.timeout.map(|d| d.as_secs()), Some(secs));
+        }
+        let opts = parse(&["--threshold", "2", "--execute-automatically", "false"]).unwrap();
+        assert_eq!(opts.threshold, Some(2));
+        assert_eq!(opts.execute_automatically, Some(false));
+
+        assert!(parse(&["--timeout", "soon"]).is_err());
+        assert!(parse(&["--execute-automatically", "maybe"]).is_err());
+    }
+
+    #[test]
+    fn defaults() {
+        let arg = multisig::MultisigAccountFeatureArg {
+            threshold: Some(2),
+            timeout_in_secs: Some(129_600),
+            execute_automatically: None,
+        };
+        assert_eq!(
+            format_defaults(&address(3), &arg),
+            format!(
+                "Multisig defaults of {}\n\
+                 \x20 Threshold:             2\n\
+                 \x20 Timeout:               1day 12h\n\
+                 \x20 Execute automatically: server default",
+                address(3)
+            )
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let parse =
+            |args: &[&str]| MultisigArgOpt::try_parse_from(["submit"].iter().chain(args)).unwrap();
+        let opts = parse(&[
+            "--threshold",
+            "3",
+            "--expire-in",
+            "2h",
+            "--execute-automatically",
+            "true",
+        ]);
+        assert_eq!(opts.threshold, Some(3));
+        assert_eq!(opts.timeout.map(|d| d.as_secs()), Some(7_200));
+        assert_eq!(opts.execute_automatically, Some(true));
+        assert!(opts.validate().is_ok());
+        assert!(parse(&[]).validate().is_ok());
+
+        let e = parse(&["--threshold", "0"]).validate().unwrap_err();
+        assert!(e.to_string().contains("at least 1 approval"));
+        let e = parse(&["--expire-in", "0s"]).validate().unwrap_err();
+        assert!(e.to_string().contains("expire immediately"));
+    }
+
+    #[test]
+    fn execute_refused() {
+        let refused = multisig::errors::cannot_execute_transaction();
+        let send = || {
+            events::AccountMultisigTransaction::Send(ledger::SendArgs {
+                from: Some(address(3)),
+                to: address(4),
+                symbol: address(1000),
+                amount: TokenAmount::from(1_000u64),
+            })
+        };
+
+        // 1 of 2 approvals.
+        let mut pending = info(send());
+        let message = execute_error(refused.clone(), Some(&pending)).to_string();
+        assert!(message.contains("has 1 of the 2 approvals it needs, 1 more are needed"));
+        assert!(message.contains(&refused.to_string()));
+
+        pending.approvers.get_mut(&address(2)).unwrap().approved = true;
+        let message = execute_error(refused.clone(), Some(&pending)).to_string();
+        assert!(message.contains(&format!(
+            "has the 2 approvals it needs, but only its submitter {}",
+            address(1)
+        )));
+
+        // Other errors, or without the transaction, are left as they are.
+        assert_eq!(execute_error(refused.clone(), None), refused);
+        let expired = multisig::errors::transaction_expired_or_withdrawn();
+        assert_eq!(execute_error(expired.clone(), Some(&pending)), expired);
+    }
+
+    #[test]
+    fn executed() {
+        let local_names = BTreeMap::from([(address(1000), "MFX".to_string())]);
+        let send = events::AccountMultisigTransaction::Send(ledger::SendArgs {
+            from: Some(address(3)),
+            to: address(4),
+            symbol: address(1000),
+            amount: TokenAmount::from(1_000u64),
+        });
+        let empty = minicbor::to_vec(many_modules::EmptyReturn).unwrap();
+        assert_eq!(
+            describe_result(&send, &empty, &local_names),
+            format!("Sent 1000 MFX from {} to {}.", address(3), address(4))
+        );
+
+        let create =
+            events::AccountMultisigTransaction::AccountCreate(many_modules::account::CreateArgs {
+                description: None,
+                roles: None,
+                features: Default::default(),
+            });
+        let created =
+            minicbor::to_vec(many_modules::account::CreateReturn { id: address(5) }).unwrap();
+        assert_eq!(
+            describe_result(&create, &created, &local_names),
+            format!("Created the account {}.", address(5))
+        );
+
+        let disable = events::AccountMultisigTransaction::AccountDisable(
+            many_modules::account::DisableArgs {
+                account: address(3),
+            },
+        );
+        assert_eq!(
+            describe_result(&disable, &empty, &local_names),
+            minicbor::display(&empty).to_string()
+        );
     }
 }
@@ -0,0 +1,792 @@
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::account::features::multisig;
+use many_modules::{account, events, ledger};
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::{CborRange, SortOrder};
+use minicbor::bytes::ByteVec;
+use num_bigint::BigUint;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::ops::Bound;
+use std::str::FromStr;
+
+/// Number of events requested per `events.list` call.
+const PAGE_SIZE: u64 = 100;
+
+#[derive(Parser)]
+pub struct InboxOpt {
+    /// Only list the transactions of this account.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    account: Option<Address>,
+
+    /// Approve every transaction matching a filter without prompting, e.g.
+    /// `symbol=MFX,max-amount=1000`. Filters are comma separated `key=value`
+    /// pairs, with keys `account`, `symbol` and `max-amount`. All given
+    /// filters must match; transactions other than sends never match a
+    /// `symbol` or `max-amount` filter.
+    #[clap(long)]
+    approve_all_matching: Option<InboxFilter>,
+}
+
+/// A filter for approving transactions in bulk. At least one key must be
+/// given, so an empty filter can never approve everything by mistake.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InboxFilter {
+    account: Option<Address>,
+
+    /// A symbol address or local name.
+    symbol: Option<String>,
+
+    max_amount: Option<TokenAmount>,
+}
+
+impl FromStr for InboxFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = InboxFilter::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid filter '{}', expected key=value.", pair))?;
+            match key.trim() {
+                "account" => {
+                    filter.account =
+                        Some(Address::from_str(value.trim()).map_err(|e| e.to_string())?)
+                }
+                "symbol" => filter.symbol = Some(value.trim().to_string()),
+                "max-amount" => {
+                    let amount = BigUint::from_str(value.trim()).map_err(|e| e.to_string())?;
+                    filter.max_amount = Some(TokenAmount::from(amount))
+                }
+                key => return Err(format!("Unknown filter key '{}'.", key)),
+            }
+        }
+
+        if filter == InboxFilter::default() {
+            Err("The filter cannot be empty.".to_string())
+        } else {
+            Ok(filter)
+        }
+    }
+}
+
+impl InboxFilter {
+    fn matches(&self, tx: &Pending, local_names: &BTreeMap<Symbol, String>) -> bool {
+        if matches!(self.account, Some(account) if account != tx.account) {
+            return false;
+        }
+        if self.symbol.is_none() && self.max_amount.is_none() {
+            return true;
+        }
+
+        match &tx.info.transaction {
+            events::AccountMultisigTransaction::Send(ledger::SendArgs {
+                symbol, amount, ..
+            }) => {
+                let symbol_matches = self.symbol.as_ref().map_or(true, |s| {
+                    &symbol.to_string() == s || local_names.get(symbol) == Some(s)
+                });
+                let amount_matches = self.max_amount.as_ref().map_or(true, |max| amount <= max);
+                symbol_matches && amount_matches
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The server calls made by the inbox.
+pub(super) trait InboxServer {
+    /// A page of `AccountMultisigSubmit` events, after the given event.
+    fn submissions(
+        &self,
+        account: Option<Address>,
+        after: Option<events::EventId>,
+        count: u64,
+    ) -> Result<Vec<events::EventLog>, ManyError>;
+
+    fn account_info(&self, account: &Address) -> Result<account::InfoReturn, ManyError>;
+
+    fn multisig_info(&self, token: &ByteVec) -> Result<multisig::InfoReturn, ManyError>;
+
+    fn local_names(&self) -> Result<BTreeMap<Symbol, String>, ManyError>;
+
+    fn approve(&self, token: &ByteVec) -> Result<(), ManyError>;
+
+    fn withdraw(&self, token: &ByteVec) -> Result<(), ManyError>;
+}
+
+impl<I: Identity> InboxServer for ManyClient<I> {
+    fn submissions(
+        &self,
+        account: Option<Address>,
+        after: Option<events::EventId>,
+        count: u64,
+    ) -> Result<Vec<events::EventLog>, ManyError> {
+        let filter = events::EventFilter {
+            account: account.map(|a| vec![a].into()),
+            kind: Some(vec![events::EventKind::AccountMultisigSubmit].into()),
+            id_range: Some(CborRange {
+                start: after.map_or(Bound::Unbounded, Bound::Excluded),
+                end: Bound::Unbounded,
+            }),
+            ..events::EventFilter::default()
+        };
+        let payload = crate::transport::call_(
+            self,
+            "events.list",
+            events::ListArgs {
+                count: Some(count),
+                order: Some(SortOrder::Ascending),
+                filter: Some(filter),
+            },
+        )?;
+        let list: events::ListReturns = minicbor::decode(&payload)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        Ok(list.events)
+    }
+
+    fn account_info(&self, account: &Address) -> Result<account::InfoReturn, ManyError> {
+        let payload = crate::transport::call_(
+            self,
+            "account.info",
+            account::InfoArgs { account: *account },
+        )?;
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    fn multisig_info(&self, token: &ByteVec) -> Result<multisig::InfoReturn, ManyError> {
+        let payload = crate::transport::call_(
+            self,
+            "account.multisigInfo",
+            multisig::InfoArgs {
+                token: token.clone(),
+            },
+        )?;
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    fn local_names(&self) -> Result<BTreeMap<Symbol, String>, ManyError> {
+        let info: ledger::InfoReturns =
+            minicbor::decode(&crate::transport::call_(self, "ledger.info", ())?)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        Ok(info.local_names)
+    }
+
+    fn approve(&self, token: &ByteVec) -> Result<(), ManyError> {
+        let response = crate::transport::call(
+            self,
+            "account.multisigApprove",
+            multisig::ApproveArgs {
+                token: token.clone(),
+            },
+        )?;
+        crate::wait_response(self, response).map(|_| ())
+    }
+
+    fn withdraw(&self, token: &ByteVec) -> Result<(), ManyError> {
+        let response = crate::transport::call(
+            self,
+            "account.multisigWithdraw",
+            multisig::WithdrawArgs {
+                token: token.clone(),
+            },
+        )?;
+        crate::wait_response(self, response).map(|_| ())
+    }
+}
+
+/// A pending transaction the caller can act on.
+#[derive(Debug)]
+pub(super) struct Pending {
+    pub token: ByteVec,
+    pub account: Address,
+    pub info: multisig::InfoReturn,
+}
+
+impl Pending {
+    fn can_approve(&self, caller: &Address) -> bool {
+        !self
+            .info
+            .approvers
+            .get(caller)
+            .map_or(false, |a| a.approved)
+    }
+
+    fn can_withdraw(&self, caller: &Address) -> bool {
+        &self.info.submitter == caller
+    }
+
+    fn approvals(&self) -> usize {
+        self.info.approvers.values().filter(|a| a.approved).count()
+    }
+}
+
+fn can_sign(info: &account::InfoReturn, caller: &Address) -> bool {
+    info.roles.get(caller).map_or(false, |roles| {
+        roles.contains(&account::Role::Owner)
+            || roles.contains(&account::Role::CanMultisigApprove)
+            || roles.contains(&account::Role::CanMultisigSubmit)
+    })
+}
+
+/// The pending transactions the caller has yet to approve or has submitted,
+/// in submission order. The ledger has no index of the accounts of an
+/// identity, so this walks the submission events and checks the roles of
+/// each account once.
+fn pending(
+    server: &impl InboxServer,
+    caller: &Address,
+    account: Option<Address>,
+) -> Result<Vec<Pending>, ManyError> {
+    let mut signer_of = BTreeMap::new();
+    let mut result = Vec::new();
+    let mut after = None;
+
+    loop {
+        let page = server.submissions(account, after.clone(), PAGE_SIZE)?;
+        let done = (page.len() as u64) < PAGE_SIZE;
+
+        for log in page {
+            after = Some(log.id.clone());
+            let (account, token) = match log.content {
+                events::EventInfo::AccountMultisigSubmit {
+                    account,
+                    token: Some(token),
+                    ..
+                } => (account, token),
+                _ => continue,
+            };
+
+            let is_signer = match signer_of.get(&account) {
+                Some(is_signer) => *is_signer,
+                None => {
+                    let is_signer = can_sign(&server.account_info(&account)?, caller);
+                    signer_of.insert(account, is_signer);
+                    is_signer
+                }
+            };
+            if !is_signer {
+                continue;
+            }
+
+            let info = server.multisig_info(&token)?;
+            if info.state != multisig::MultisigTransactionState::Pending {
+                continue;
+            }
+            let tx = Pending {
+                token,
+                account,
+                info,
+            };
+            if tx.can_approve(caller) || tx.can_withdraw(caller) {
+                result.push(tx);
+            }
+        }
+
+        if done {
+            return Ok(result);
+        }
+    }
+}
+
+pub(super) fn describe(
+    out: &mut impl Write,
+    tx: &Pending,
+    local_names: &BTreeMap<Symbol, String>,
+) -> std::io::Result<()> {
+    writeln!(out, "Transaction {}", hex::encode(tx.token.as_slice()))?;
+    writeln!(out, "  Account:   {}", tx.account)?;
+    writeln!(out, "  Submitter: {}", tx.info.submitter)?;
+    if let Some(memo) = &tx.info.memo {
+        writeln!(out, "  Memo:      {:?}", memo)?;
+    }
+    match &tx.info.transaction {
+        events::AccountMultisigTransaction::Send(ledger::SendArgs {
+            from,
+            to,
+            symbol,
+            amount,
+        }) => {
+            let symbol = local_names
+                .get(symbol)
+                .cloned()
+                .unwrap_or_else(|| symbol.to_string());
+            let from = from.unwrap_or(tx.account);
+            writeln!(
+                out,
+                "  Send:      {} {} from {} to {}",
+                amount, symbol, from, to
+            )?;
+        }
+        other => writeln!(out, "  Transaction: {:?}", other)?,
+    }
+    writeln!(out, "  Approvals: {}/{}", tx.approvals(), tx.info.threshold)?;
+    if let Ok(timeout) = tx.info.timeout.as_system_time() {
+        writeln!(
+            out,
+            "  Timeout:   {}",
+            humantime::format_rfc3339_seconds(timeout)
+        )?;
+    }
+    Ok(())
+}
+
+/// The account a transaction was submitted to, from its submission event.
+pub(super) fn account_of(
+    server: &impl InboxServer,
+    token: &ByteVec,
+) -> Result<Option<Address>, ManyError> {
+    let mut after = None;
+    loop {
+        let page = server.submissions(None, after.clone(), PAGE_SIZE)?;
+        let done = (page.len() as u64) < PAGE_SIZE;
+
+        for log in page {
+            after = Some(log.id.clone());
+            if let events::EventInfo::AccountMultisigSubmit {
+                account,
+                token: Some(t),
+                ..
+            } = log.content
+            {
+                if &t == token {
+                    return Ok(Some(account));
+                }
+            }
+        }
+
+        if done {
+            return Ok(None);
+        }
+    }
+}
+
+/// Approve the pending transactions matching the filter. Returns the tokens
+/// of the approved transactions.
+fn approve_matching(
+    server: &impl InboxServer,
+    caller: &Address,
+    account: Option<Address>,
+    filter: &InboxFilter,
+) -> Result<Vec<ByteVec>, ManyError> {
+    let local_names = server.local_names()?;
+    let mut approved = Vec::new();
+    for tx in pending(server, caller, account.or(filter.account))? {
+        if tx.can_approve(caller) && filter.matches(&tx, &local_names) {
+            server.approve(&tx.token)?;
+            approved.push(tx.token);
+        }
+    }
+    Ok(approved)
+}
+
+fn io_error(e: std::io::Error) -> ManyError {
+    ManyError::unknown(e.to_string())
+}
+
+/// Walk through the pending transactions, prompting for an action on each.
+fn interactive(
+    server: &impl InboxServer,
+    caller: &Address,
+    account: Option<Address>,
+    mut input: impl BufRead,
+    mut out: impl Write,
+) -> Result<(), ManyError> {
+    let local_names = server.local_names()?;
+    let pending = pending(server, caller, account)?;
+    if pending.is_empty() {
+        writeln!(out, "No transactions awaiting your approval.").map_err(io_error)?;
+        return Ok(());
+    }
+
+    for (i, tx) in pending.iter().enumerate() {
+        writeln!(out, "[{}/{}]", i + 1, pending.len()).map_err(io_error)?;
+        describe(&mut out, tx, &local_names).map_err(io_error)?;
+
+        let mut choices = vec![];
+        if tx.can_approve(caller) {
+            choices.push("[a]pprove");
+        }
+        choices.push("[s]kip");
+        if tx.can_withdraw(caller) {
+            choices.push("[w]ithdraw");
+        }
+        choices.push("[q]uit");
+
+        loop {
+            write!(out, "{}? ", choices.join(", ")).map_err(io_error)?;
+            out.flush().map_err(io_error)?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line).map_err(io_error)? == 0 {
+                return Ok(());
+            }
+            match line.trim() {
+                "a" if tx.can_approve(caller) => {
+                    server.approve(&tx.token)?;
+                    writeln!(out, "Approved.").map_err(io_error)?;
+                }
+                "w" if tx.can_withdraw(caller) => {
+                    server.withdraw(&tx.token)?;
+                    writeln!(out, "Withdrawn.").map_err(io_error)?;
+                }
+                "s" => {}
+                "q" => return Ok(()),
+                _ => continue,
+            }
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn inbox(
+    client: ManyClient<impl Identity>,
+    caller: Address,
+    opts: InboxOpt,
+) -> Result<(), ManyError> {
+    match opts.approve_all_matching {
+        Some(filter) => {
+            for token in approve_matching(&client, &caller, opts.account, &filter)? {
+                println!("Approved {}", hex::encode(token.as_slice()));
+            }
+            Ok(())
+        }
+        None => {
+            let stdin = std::io::stdin();
+            interactive(
+                &client,
+                &caller,
+                opts.account,
+                stdin.lock(),
+                std::io::stdout(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use many_modules::events::AccountMultisigTransaction;
+    use many_types::Timestamp;
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+
+    fn address(s: &str) -> Address {
+        Address::from_str(s).unwrap()
+    }
+
+    fn caller() -> Address {
+        address("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+    }
+
+    fn other() -> Address {
+        address("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp")
+    }
+
+    fn symbol() -> Symbol {
+        caller().with_subresource_id(1000).unwrap()
+    }
+
+    fn account(id: u32) -> Address {
+        other().with_subresource_id(id).unwrap()
+    }
+
+    fn send(amount: u64) -> AccountMultisigTransaction {
+        AccountMultisigTransaction::Send(ledger::SendArgs {
+            from: None,
+            to: other(),
+            symbol: symbol(),
+            amount: TokenAmount::from(amount),
+        })
+    }
+
+    /// A server with accounts and transactions, which records approvals.
+    #[derive(Default)]
+    struct MockServer {
+        roles: BTreeMap<Address, BTreeMap<Address, BTreeSet<account::Role>>>,
+        transactions: Vec<(Address, multisig::InfoReturn)>,
+        approved: RefCell<Vec<ByteVec>>,
+    }
+
+    impl MockServer {
+        fn with_account(mut self, account: Address, role: account::Role) -> Self {
+            self.roles.insert(
+                account,
+                BTreeMap::from([(caller(), BTreeSet::from([role]))]),
+            );
+            self
+        }
+
+        fn with_transaction(
+            mut self,
+            account: Address,
+            submitter: Address,
+            transaction: AccountMultisigTransaction,
+            state: multisig::MultisigTransactionState,
+        ) -> Self {
+            self.transactions.push((
+                account,
+                multisig::InfoReturn {
+                    memo: None,
+                    transaction,
+                    submitter,
+                    approvers: BTreeMap::from([(
+                        submitter,
+                        multisig::ApproverInfo { approved: true },
+                    )]),
+                    threshold: 2,
+                    execute_automatically: false,
+                    timeout: Timestamp::new(0).unwrap(),
+                    data: None,
+                    state,
+                },
+            ));
+            self
+        }
+
+        fn token(i: usize) -> ByteVec {
+            ByteVec::from(vec![i as u8])
+        }
+    }
+
+    impl InboxServer for MockServer {
+        fn submissions(
+            &self,
+            account: Option<Address>,
+            after: Option<events::EventId>,
+            count: u64,
+        ) -> Result<Vec<events::EventLog>, ManyError> {
+            let start = after.map_or(0, |id| id.as_ref()[0] as usize + 1);
+            Ok(self
+                .transactions
+                .iter()
+                .enumerate()
+                .skip(start)
+                .filter(|(_, (a, _))| account.map_or(true, |account| &account == a))
+                .take(count as usize)
+                .map(|(i, (a, info))| events::EventLog {
+                    id: events::EventId::from(vec![i as u8]),
+                    time: Timestamp::new(0).unwrap(),
+                    content: events::EventInfo::AccountMultisigSubmit {
+                        submitter: info.submitter,
+                        account: *a,
+                        memo: None,
+                        transaction: Box::new(info.transaction.clone()),
+                        token: Some(Self::token(i)),
+                        threshold: info.threshold,
+                        timeout: info.timeout,
+                        execute_automatically: false,
+                        data: None,
+                    },
+                })
+                .collect())
+        }
+
+        fn account_info(&self, account: &Address) -> Result<account::InfoReturn, ManyError> {
+            Ok(account::InfoReturn {
+                description: None,
+                roles: self.roles.get(account).cloned().unwrap_or_default(),
+                features: account::features::FeatureSet::default(),
+                disabled: None,
+            })
+        }
+
+        fn multisig_info(&self, token: &ByteVec) -> Result<multisig::InfoReturn, ManyError> {
+            Ok(self.transactions[token.as_slice()[0] as usize].1.clone())
+        }
+
+        fn local_names(&self) -> Result<BTreeMap<Symbol, String>, ManyError> {
+            Ok(BTreeMap::from([(symbol(), "MFX".to_string())]))
+        }
+
+        fn approve(&self, token: &ByteVec) -> Result<(), ManyError> {
+            self.approved.borrow_mut().push(token.clone());
+            Ok(())
+        }
+
+        fn withdraw(&self, _token: &ByteVec) -> Result<(), ManyError> {
+            Ok(())
+        }
+    }
+
+    fn server() -> MockServer {
+        use multisig::MultisigTransactionState as State;
+        MockServer::default()
+            .with_account(account(0), account::Role::CanMultisigApprove)
+            .with_account(account(1), account::Role::Owner)
+            .with_account(account(2), account::Role::CanLedgerTransact)
+            .with_transaction(account(0), other(), send(100), State::Pending)
+            .with_transaction(account(0), other(), send(100), State::ExecutedManually)
+            .with_transaction(account(1), other(), send(5000), State::Pending)
+            .with_transaction(account(2), other(), send(1), State::Pending)
+            .with_transaction(account(3), other(), send(1), State::Pending)
+            .with_transaction(account(1), caller(), send(1), State::Pending)
+    }
+
+    fn tokens(pending: &[Pending]) -> Vec<ByteVec> {
+        pending.iter().map(|tx| tx.token.clone()).collect()
+    }
+
+    #[test]
+    fn enumerate() {
+        let server = server();
+        let pending = pending(&server, &caller(), None).unwrap();
+
+        // Not pending, no signing role, unknown account.
+        assert_eq!(
+            tokens(&pending),
+            vec![
+                MockServer::token(0),
+                MockServer::token(2),
+                MockServer::token(5)
+            ]
+        );
+        assert!(pending[0].can_approve(&caller()));
+        assert!(!pending[0].can_withdraw(&caller()));
+        assert!(!pending[2].can_approve(&caller()));
+        assert!(pending[2].can_withdraw(&caller()));
+
+        let pending = super::pending(&server, &caller(), Some(account(1))).unwrap();
+        assert_eq!(
+            tokens(&pending),
+            vec![MockServer::token(2), MockServer::token(5)]
+        );
+    }
+
+    #[test]
+    fn enumerate_pages() {
+        let mut server = MockServer::default().with_account(account(0), account::Role::Owner);
+        for _ in 0..PAGE_SIZE + 5 {
+            server = server.with_transaction(
+                account(0),
+                other(),
+                send(1),
+                multisig::MultisigTransactionState::Pending,
+            );
+        }
+        assert_eq!(
+            pending(&server, &caller(), None).unwrap().len() as u64,
+            PAGE_SIZE + 5
+        );
+    }
+
+    #[test]
+    fn account_of_token() {
+        let server = server();
+        assert_eq!(
+            account_of(&server, &MockServer::token(2)).unwrap(),
+            Some(account(1))
+        );
+        assert_eq!(
+            account_of(&server, &MockServer::token(4)).unwrap(),
+            Some(account(3))
+        );
+        assert_eq!(account_of(&server, &MockServer::token(42)).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_filter() {
+        assert_eq!(
+            "symbol=MFX, max-amount=1000".parse(),
+            Ok(InboxFilter {
+                account: None,
+                symbol: Some("MFX".to_string()),
+                max_amount: Some(TokenAmount::from(1000u64)),
+            })
+        );
+        assert_eq!(
+            format!("account={}", account(1)).parse(),
+            Ok(InboxFilter {
+                account: Some(account(1)),
+                ..InboxFilter::default()
+            })
+        );
+        assert!("".parse::<InboxFilter>().is_err());
+        assert!("symbol".parse::<InboxFilter>().is_err());
+        assert!("foo=bar".parse::<InboxFilter>().is_err());
+        assert!("max-amount=-1".parse::<InboxFilter>().is_err());
+    }
+
+    #[test]
+    fn filter() {
+        let local_names = server().local_names().unwrap();
+        let tx = |account, transaction| Pending {
+            token: MockServer::token(0),
+            account,
+            info: multisig::InfoReturn {
+                memo: None,
+                transaction,
+                submitter: other(),
+                approvers: BTreeMap::new(),
+                threshold: 2,
+                execute_automatically: false,
+                timeout: Timestamp::new(0).unwrap(),
+                data: None,
+                state: multisig::MultisigTransactionState::Pending,
+            },
+        };
+        let set_defaults =
+            AccountMultisigTransaction::AccountMultisigSetDefaults(multisig::SetDefaultsArgs {
+                account: account(0),
+                threshold: None,
+                timeout_in_secs: None,
+                execute_automatically: None,
+            });
+        let filter = |s: &str| s.parse::<InboxFilter>().unwrap();
+
+        assert!(filter("max-amount=100").matches(&tx(account(0), send(100)), &local_names));
+        assert!(!filter("max-amount=99").matches(&tx(account(0), send(100)), &local_names));
+        assert!(filter("symbol=MFX").matches(&tx(account(0), send(100)), &local_names));
+        assert!(filter(&format!("symbol={}", symbol()))
+            .matches(&tx(account(0), send(100)), &local_names));
+        assert!(!filter("symbol=ABC").matches(&tx(account(0), send(100)), &local_names));
+
+        let by_account = filter(&format!("account={}", account(0)));
+        assert!(by_account.matches(&tx(account(0), set_defaults.clone()), &local_names));
+        assert!(!by_account.matches(&tx(account(1), send(100)), &local_names));
+        assert!(!filter("symbol=MFX").matches(&tx(account(0), set_defaults), &local_names));
+    }
+
+    #[test]
+    fn approve_all_matching() {
+        let server = server();
+        let approved = approve_matching(
+            &server,
+            &caller(),
+            None,
+            &"max-amount=1000".parse().unwrap(),
+        )
+        .unwrap();
+
+        // Transactions the caller submitted are already approved.
+        assert_eq!(approved, vec![MockServer::token(0)]);
+        assert_eq!(*server.approved.borrow(), approved);
+    }
+
+    #[test]
+    fn prompt() {
+        let server = server();
+        let mut out = Vec::new();
+        interactive(
+            &server,
+            &caller(),
+            None,
+            "x\na\ns\nq\n".as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(*server.approved.borrow(), vec![MockServer::token(0)]);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Send:      100 MFX from"));
+        assert!(out.contains("[3/3]"));
+        assert!(out.contains("[s]kip, [w]ithdraw, [q]uit? "));
+    }
+}
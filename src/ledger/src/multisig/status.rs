@@ -0,0 +1,793 @@
+use super::inbox::InboxServer;
+use crate::output::{self, OutputFormat};
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::account::features::multisig::{self, MultisigTransactionState};
+use many_modules::{events, ledger};
+use many_types::ledger::Symbol;
+use minicbor::bytes::ByteVec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Default number of `account.multisigInfo` queries in flight at once.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Number of events requested per `events.list` call.
+const PAGE_SIZE: u64 = 100;
+
+#[derive(Parser)]
+pub struct StatusOpt {
+    /// The file of tokens, one in hexadecimal per line. Blank lines and
+    /// lines starting with `#` are skipped.
+    #[clap(long)]
+    tokens_file: PathBuf,
+
+    /// Maximum number of queries sent to the server at once.
+    #[clap(long, default_value_t = DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+
+    /// Refresh the table at this interval, e.g. `30s` or `5m`, until no
+    /// transaction is pending anymore.
+    #[clap(long)]
+    watch: Option<humantime::Duration>,
+
+    /// Exit with an error unless every transaction was executed, e.g. to
+    /// gate a release in CI. Transactions which could not be queried count
+    /// as not executed.
+    #[clap(long)]
+    fail_if_pending: bool,
+}
+
+/// The server calls made to follow transactions. Queries are made
+/// concurrently, on a connection per worker.
+pub(super) trait StatusServer {
+    fn multisig_info(&self, token: &ByteVec) -> Result<multisig::InfoReturn, ManyError>;
+
+    /// A page of `AccountMultisigSubmit` events, after the given event.
+    fn submissions(
+        &self,
+        after: Option<events::EventId>,
+        count: u64,
+    ) -> Result<Vec<events::EventLog>, ManyError>;
+
+    fn local_names(&self) -> Result<BTreeMap<Symbol, String>, ManyError>;
+}
+
+impl<I: Identity> StatusServer for ManyClient<I> {
+    fn multisig_info(&self, token: &ByteVec) -> Result<multisig::InfoReturn, ManyError> {
+        InboxServer::multisig_info(self, token)
+    }
+
+    fn submissions(
+        &self,
+        after: Option<events::EventId>,
+        count: u64,
+    ) -> Result<Vec<events::EventLog>, ManyError> {
+        InboxServer::submissions(self, None, after, count)
+    }
+
+    fn local_names(&self) -> Result<BTreeMap<Symbol, String>, ManyError> {
+        InboxServer::local_names(self)
+    }
+}
+
+/// The accounts the transactions were submitted to, from their submission
+/// events. The events are walked once, until every token is found.
+fn accounts_of(
+    server: &impl StatusServer,
+    tokens: &[ByteVec],
+) -> Result<BTreeMap<ByteVec, Address>, ManyError> {
+    let wanted: BTreeSet<&ByteVec> = tokens.iter().collect();
+    let mut accounts = BTreeMap::new();
+    let mut after = None;
+    loop {
+        let page = server.submissions(after.clone(), PAGE_SIZE)?;
+        let done = (page.len() as u64) < PAGE_SIZE;
+
+        for log in page {
+            after = Some(log.id.clone());
+            if let events::EventInfo::AccountMultisigSubmit {
+                account,
+                token: Some(token),
+                ..
+            } = log.content
+            {
+                if wanted.contains(&token) {
+                    accounts.insert(token, account);
+                }
+            }
+        }
+
+        if done || accounts.len() == wanted.len() {
+            return Ok(accounts);
+        }
+    }
+}
+
+/// Query the information of every transaction with at most `concurrency`
+/// queries in flight. Results are in the order of the tokens; a failed
+/// query does not stop the others.
+fn query_all<S: StatusServer>(
+    connect: impl Fn() -> Result<S, ManyError> + Sync,
+    tokens: &[ByteVec],
+    concurrency: usize,
+) -> Result<Vec<Result<multisig::InfoReturn, ManyError>>, ManyError> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..tokens.len()).map(|_| None).collect::<Vec<_>>());
+    let error = Mutex::new(None);
+
+    std::thread::scope(|s| {
+        for _ in 0..concurrency.clamp(1, tokens.len().max(1)) {
+            s.spawn(|| {
+                let server = match connect() {
+                    Ok(server) => server,
+                    Err(e) => {
+                        error.lock().unwrap().get_or_insert(e);
+                        return;
+                    }
+                };
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= tokens.len() {
+                        return;
+                    }
+                    let result = server.multisig_info(&tokens[i]);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    // Only when no worker could connect are some tokens left unqueried.
+    let results: Option<Vec<_>> = results.into_inner().unwrap().into_iter().collect();
+    match (results, error.into_inner().unwrap()) {
+        (Some(results), _) => Ok(results),
+        (None, Some(e)) => Err(e),
+        (None, None) => Err(ManyError::unknown("Some transactions were not queried.")),
+    }
+}
+
+/// The status of a transaction, as shown in a row of the table.
+#[derive(Debug)]
+struct Row {
+    token: ByteVec,
+    account: Option<Address>,
+    info: Result<multisig::InfoReturn, String>,
+}
+
+impl Row {
+    fn state(&self) -> Option<&MultisigTransactionState> {
+        self.info.as_ref().ok().map(|info| &info.state)
+    }
+
+    fn is_executed(&self) -> bool {
+        matches!(
+            self.state(),
+            Some(
+                MultisigTransactionState::ExecutedAutomatically
+                    | MultisigTransactionState::ExecutedManually
+            )
+        )
+    }
+
+    fn is_pending(&self) -> bool {
+        self.state() == Some(&MultisigTransactionState::Pending)
+    }
+}
+
+/// The counts of a refresh of the table.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    total: usize,
+    executed: usize,
+    pending: usize,
+    failed: usize,
+}
+
+impl Summary {
+    fn of(rows: &[Row]) -> Self {
+        Self {
+            total: rows.len(),
+            executed: rows.iter().filter(|r| r.is_executed()).count(),
+            pending: rows.iter().filter(|r| r.is_pending()).count(),
+            failed: rows.iter().filter(|r| r.info.is_err()).count(),
+        }
+    }
+
+    /// Whether a refresh could still change something.
+    fn is_final(&self) -> bool {
+        self.pending == 0 && self.failed == 0
+    }
+}
+
+fn abbreviate(token: &ByteVec) -> String {
+    let token = hex::encode(token.as_slice());
+    if token.len() > 16 {
+        format!("{}..{}", &token[..8], &token[token.len() - 6..])
+    } else {
+        token
+    }
+}
+
+fn state_name(state: &MultisigTransactionState) -> &'static str {
+    match state {
+        MultisigTransactionState::Pending => "pending",
+        MultisigTransactionState::ExecutedAutomatically => "executed",
+        MultisigTransactionState::ExecutedManually => "executed",
+        MultisigTransactionState::Withdrawn => "withdrawn",
+        MultisigTransactionState::Expired => "expired",
+    }
+}
+
+fn summarize(
+    transaction: &events::AccountMultisigTransaction,
+    local_names: &BTreeMap<Symbol, String>,
+) -> String {
+    match transaction {
+        events::AccountMultisigTransaction::Send(ledger::SendArgs {
+            to, symbol, amount, ..
+        }) => format!(
+            "send {} {} to {}",
+            amount,
+            super::symbol_name(local_names, symbol),
+            to
+        ),
+        events::AccountMultisigTransaction::AccountMultisigSetDefaults(_) => {
+            "set multisig defaults".to_string()
+        }
+        other => format!("{:?}", other)
+            .split(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// The time left before a pending transaction expires.
+fn countdown(info: &multisig::InfoReturn, now: SystemTime) -> String {
+    if info.state != MultisigTransactionState::Pending {
+        return "-".to_string();
+    }
+    match info.timeout.as_system_time() {
+        Ok(timeout) => match timeout.duration_since(now) {
+            Ok(left) => format!(
+                "in {}",
+                humantime::format_duration(Duration::from_secs(left.as_secs()))
+            ),
+            Err(_) => "expired".to_string(),
+        },
+        Err(_) => "?".to_string(),
+    }
+}
+
+/// The account of a transaction, from its submission event, or from the
+/// sender of a send when the event was not found.
+fn account_of(row: &Row) -> Option<Address> {
+    row.account.or_else(|| match &row.info {
+        Ok(multisig::InfoReturn {
+            transaction: events::AccountMultisigTransaction::Send(args),
+            ..
+        }) => args.from,
+        _ => None,
+    })
+}
+
+fn format_table(rows: &[Row], local_names: &BTreeMap<Symbol, String>, now: SystemTime) -> String {
+    let cells: Vec<[String; 6]> = rows
+        .iter()
+        .map(|row| {
+            let token = abbreviate(&row.token);
+            let account = account_of(row).map_or_else(|| "?".to_string(), |a| a.to_string());
+            match &row.info {
+                Ok(info) => [
+                    token,
+                    account,
+                    summarize(&info.transaction, local_names),
+                    format!(
+                        "{}/{}",
+                        info.approvers.values().filter(|a| a.approved).count(),
+                        info.threshold
+                    ),
+                    countdown(info, now),
+                    state_name(&info.state).to_string(),
+                ],
+                Err(e) => [
+                    token,
+                    account,
+                    "-".to_string(),
+                    "-".to_string(),
+                    "-".to_string(),
+                    format!("error: {}", e),
+                ],
+            }
+        })
+        .collect();
+
+    let header = [
+        "TOKEN",
+        "ACCOUNT",
+        "TRANSACTION",
+        "APPROVALS",
+        "EXPIRES",
+        "STATE",
+    ];
+    let mut widths = header.map(str::len);
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: &[&str]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let summary = Summary::of(rows);
+    let mut lines = vec![line(&header)];
+    lines.extend(
+        cells
+            .iter()
+            .map(|row| line(&row.iter().map(String::as_str).collect::<Vec<_>>())),
+    );
+    lines.push(format!(
+        "{} transactions: {} executed, {} pending, {} other, {} failed to query.",
+        summary.total,
+        summary.executed,
+        summary.pending,
+        summary.total - summary.executed - summary.pending - summary.failed,
+        summary.failed
+    ));
+    lines.join("\n")
+}
+
+fn rows_json(rows: &[Row], now: SystemTime) -> serde_json::Value {
+    serde_json::Value::Array(
+        rows.iter()
+            .map(|row| {
+                let mut value = serde_json::json!({
+                    "token": hex::encode(row.token.as_slice()),
+                    "account": account_of(row).map(|a| a.to_string()),
+                });
+                match &row.info {
+                    Ok(info) => {
+                        value["state"] = state_name(&info.state).into();
+                        value["approvals"] = info
+                            .approvers
+                            .values()
+                            .filter(|a| a.approved)
+                            .count()
+                            .into();
+                        value["threshold"] = info.threshold.into();
+                        value["expires_in_secs"] = info
+                            .timeout
+                            .as_system_time()
+                            .ok()
+                            .filter(|_| info.state == MultisigTransactionState::Pending)
+                            .and_then(|timeout| timeout.duration_since(now).ok())
+                            .map(|left| left.as_secs())
+                            .into();
+                    }
+                    Err(e) => value["error"] = e.clone().into(),
+                }
+                value
+            })
+            .collect(),
+    )
+}
+
+/// Refresh the table until nothing can change anymore, or once without an
+/// interval. Returns the summary of the last refresh.
+fn watch(
+    mut refresh: impl FnMut(&mut dyn Write) -> Result<Summary, ManyError>,
+    interval: Option<Duration>,
+    mut sleep: impl FnMut(Duration),
+    out: &mut impl Write,
+) -> Result<Summary, ManyError> {
+    loop {
+        let summary = refresh(out)?;
+        match interval {
+            Some(interval) if !summary.is_final() => sleep(interval),
+            _ => return Ok(summary),
+        }
+    }
+}
+
+pub fn status<S: StatusServer>(
+    client: ManyClient<impl Identity>,
+    connect: impl Fn() -> Result<S, ManyError> + Sync,
+    opts: StatusOpt,
+) -> Result<(), ManyError> {
+    let content = std::fs::read_to_string(&opts.tokens_file)
+        .map_err(|e| ManyError::unknown(e.to_string()))?;
+    let tokens = super::parse_tokens(&content).map_err(ManyError::unknown)?;
+
+    // Neither the accounts nor the local names change between refreshes.
+    let accounts = accounts_of(&client, &tokens).unwrap_or_else(|e| {
+        warn!("Could not read the accounts of the transactions: {}", e);
+        BTreeMap::new()
+    });
+    let local_names = StatusServer::local_names(&client).unwrap_or_default();
+
+    let refresh = |out: &mut dyn Write| {
+        let rows: Vec<Row> = query_all(&connect, &tokens, opts.concurrency)?
+            .into_iter()
+            .zip(&tokens)
+            .map(|(info, token)| Row {
+                token: token.clone(),
+                account: accounts.get(token).copied(),
+                info: info.map_err(|e| e.to_string()),
+            })
+            .collect();
+        let now = SystemTime::now();
+        match output::get() {
+            OutputFormat::Human => {
+                writeln!(
+                    out,
+                    "Updated {}",
+                    humantime::format_rfc3339_seconds(SystemTime::now())
+                )
+                .and_then(|_| writeln!(out, "{}", format_table(&rows, &local_names, now)))
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            }
+            OutputFormat::Json => output::print_json(&rows_json(&rows, now)),
+        }
+        Ok(Summary::of(&rows))
+    };
+
+    let summary = watch(
+        refresh,
+        opts.watch.map(Into::into),
+        std::thread::sleep,
+        &mut std::io::stdout(),
+    )?;
+    if opts.fail_if_pending && summary.executed < summary.total {
+        return Err(ManyError::unknown(format!(
+            "{} of {} transactions are not executed.",
+            summary.total - summary.executed,
+            summary.total
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use many_modules::events::AccountMultisigTransaction;
+    use many_types::ledger::TokenAmount;
+    use many_types::Timestamp;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn token(i: u8) -> ByteVec {
+        ByteVec::from(vec![i])
+    }
+
+    fn info(approved: usize, state: MultisigTransactionState) -> multisig::InfoReturn {
+        multisig::InfoReturn {
+            memo: None,
+            transaction: AccountMultisigTransaction::Send(ledger::SendArgs {
+                from: None,
+                to: address(9),
+                symbol: address(1000),
+                amount: TokenAmount::from(100u64),
+            }),
+            submitter: address(1),
+            approvers: (1..=3)
+                .map(|i| {
+                    (
+                        address(i),
+                        multisig::ApproverInfo {
+                            approved: i as usize <= approved,
+                        },
+                    )
+                })
+                .collect(),
+            threshold: 2,
+            execute_automatically: false,
+            timeout: Timestamp::new(10_000).unwrap(),
+            data: None,
+            state,
+        }
+    }
+
+    /// A server answering with a script of states per token, one per round
+    /// of queries, and failing for tokens without one.
+    #[derive(Clone, Default)]
+    struct ScriptedServer {
+        script: BTreeMap<ByteVec, Vec<multisig::InfoReturn>>,
+        accounts: Vec<(ByteVec, Address)>,
+        queries: Arc<Mutex<BTreeMap<ByteVec, usize>>>,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl StatusServer for ScriptedServer {
+        fn multisig_info(&self, token: &ByteVec) -> Result<multisig::InfoReturn, ManyError> {
+            let n = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(n, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(5));
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let round = {
+                let mut queries = self.queries.lock().unwrap();
+                let count = queries.entry(token.clone()).or_default();
+                *count += 1;
+                *count - 1
+            };
+            let script = self
+                .script
+                .get(token)
+                .ok_or_else(|| ManyError::unknown("Transaction not found."))?;
+            Ok(script[round.min(script.len() - 1)].clone())
+        }
+
+        fn submissions(
+            &self,
+            after: Option<events::EventId>,
+            count: u64,
+        ) -> Result<Vec<events::EventLog>, ManyError> {
+            let start = after.map_or(0, |id| id.as_ref()[0] as usize + 1);
+            Ok(self
+                .accounts
+                .iter()
+                .enumerate()
+                .skip(start)
+                .take(count as usize)
+                .map(|(i, (token, account))| events::EventLog {
+                    id: events::EventId::from(vec![i as u8]),
+                    time: Timestamp::new(1).unwrap(),
+                    content: events::EventInfo::AccountMultisigSubmit {
+                        submitter: address(1),
+                        account: *account,
+                        memo: None,
+                        transaction: Box::new(
+                            info(0, MultisigTransactionState::Pending).transaction,
+                        ),
+                        token: Some(token.clone()),
+                        threshold: 2,
+                        timeout: Timestamp::new(10_000).unwrap(),
+                        execute_automatically: false,
+                        data: None,
+                    },
+                })
+                .collect())
+        }
+
+        fn local_names(&self) -> Result<BTreeMap<Symbol, String>, ManyError> {
+            Ok(BTreeMap::from([(address(1000), "MFX".to_string())]))
+        }
+    }
+
+    fn rows(server: &ScriptedServer, tokens: &[ByteVec]) -> Vec<Row> {
+        let accounts = accounts_of(server, tokens).unwrap();
+        query_all(|| Ok(server.clone()), tokens, 2)
+            .unwrap()
+            .into_iter()
+            .zip(tokens)
+            .map(|(info, token)| Row {
+                token: token.clone(),
+                account: accounts.get(token).copied(),
+                info: info.map_err(|e| e.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aggregate() {
+        let server = ScriptedServer {
+            script: BTreeMap::from([
+                (token(1), vec![info(1, MultisigTransactionState::Pending)]),
+                (
+                    token(2),
+                    vec![info(2, MultisigTransactionState::ExecutedAutomatically)],
+                ),
+                (token(3), vec![info(0, MultisigTransactionState::Withdrawn)]),
+            ]),
+            accounts: vec![(token(1), address(5)), (token(2), address(6))],
+            ..Default::default()
+        };
+        let tokens = vec![token(1), token(2), token(3), token(4)];
+        let rows = rows(&server, &tokens);
+        let now = Timestamp::new(10_000 - 3_660)
+            .unwrap()
+            .as_system_time()
+            .unwrap();
+        let table = format_table(&rows, &server.local_names().unwrap(), now);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("TOKEN  ACCOUNT"));
+        assert!(lines[0].contains("TRANSACTION"));
+        assert!(lines[0].ends_with("STATE"));
+        assert!(lines[1].starts_with(&format!("01     {}", address(5))));
+        assert!(lines[1].contains(&format!("send 100 MFX to {}", address(9))));
+        assert!(lines[1].contains("1/2"));
+        assert!(lines[1].contains("in 1h 1m"));
+        assert!(lines[1].ends_with("pending"));
+        assert!(lines[2].contains(&address(6).to_string()));
+        assert!(lines[2].contains("2/2"));
+        assert!(lines[2].ends_with("executed"));
+        // Not found in the events, nor the sender of the send.
+        assert!(lines[3].starts_with("03     ?"));
+        assert!(lines[3].ends_with("withdrawn"));
+        // A failed query is shown inline.
+        assert!(lines[4].starts_with("04     ?"));
+        assert!(lines[4].contains("error: "));
+        assert!(lines[4].ends_with("Transaction not found."));
+        assert_eq!(
+            lines[5],
+            "4 transactions: 1 executed, 1 pending, 1 other, 1 failed to query."
+        );
+
+        assert_eq!(
+            Summary::of(&rows),
+            Summary {
+                total: 4,
+                executed: 1,
+                pending: 1,
+                failed: 1,
+            }
+        );
+
+        let json = rows_json(&rows, now);
+        assert_eq!(json[0]["state"], "pending");
+        assert_eq!(json[0]["expires_in_secs"], 3_660);
+        assert_eq!(json[1]["expires_in_secs"], serde_json::Value::Null);
+        assert!(json[3]["error"]
+            .as_str()
+            .unwrap()
+            .contains("Transaction not found."));
+        assert!(json[3].get("state").is_none());
+    }
+
+    #[test]
+    fn expired_countdown() {
+        let now = Timestamp::new(20_000).unwrap().as_system_time().unwrap();
+        let pending = info(0, MultisigTransactionState::Pending);
+        assert_eq!(countdown(&pending, now), "expired");
+        let executed = info(2, MultisigTransactionState::ExecutedManually);
+        assert_eq!(countdown(&executed, now), "-");
+    }
+
+    #[test]
+    fn abbreviated() {
+        assert_eq!(abbreviate(&token(1)), "01");
+        assert_eq!(
+            abbreviate(&ByteVec::from((0..16).collect::<Vec<u8>>())),
+            "00010203..0d0e0f"
+        );
+    }
+
+    #[test]
+    fn bounded_concurrency() {
+        let tokens: Vec<ByteVec> = (0..20).map(token).collect();
+        let server = ScriptedServer {
+            script: tokens
+                .iter()
+                .map(|t| (t.clone(), vec![info(0, MultisigTransactionState::Pending)]))
+                .collect(),
+            ..Default::default()
+        };
+        let results = query_all(|| Ok(server.clone()), &tokens, 3).unwrap();
+        assert_eq!(results.len(), 20);
+        assert!(results.iter().all(Result::is_ok));
+        assert!(server.max_in_flight.load(Ordering::SeqCst) <= 3);
+
+        // Without a connection, nothing is queried.
+        assert!(
+            query_all::<ScriptedServer>(|| Err(ManyError::unknown("Down")), &tokens, 3).is_err()
+        );
+    }
+
+    #[test]
+    fn watch_until_final() {
+        let server = ScriptedServer {
+            script: BTreeMap::from([
+                (
+                    token(1),
+                    vec![
+                        info(0, MultisigTransactionState::Pending),
+                        info(1, MultisigTransactionState::Pending),
+                        info(2, MultisigTransactionState::ExecutedAutomatically),
+                    ],
+                ),
+                (
+                    token(2),
+                    vec![info(2, MultisigTransactionState::ExecutedManually)],
+                ),
+            ]),
+            ..Default::default()
+        };
+        let tokens = vec![token(1), token(2)];
+        let mut sleeps = vec![];
+        let mut out = Vec::new();
+        let summary = watch(
+            |out| {
+                let rows = rows(&server, &tokens);
+                writeln!(
+                    out,
+                    "{}",
+                    format_table(&rows, &BTreeMap::new(), SystemTime::now())
+                )
+                .unwrap();
+                Ok(Summary::of(&rows))
+            },
+            Some(Duration::from_secs(30)),
+            |d| sleeps.push(d),
+            &mut out,
+        )
+        .unwrap();
+
+        // Refreshed until the pending transaction was executed.
+        assert_eq!(sleeps, vec![Duration::from_secs(30); 2]);
+        assert_eq!(summary.executed, 2);
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("TOKEN").count(), 3);
+        assert!(out.contains("0/2"));
+        assert!(out.contains("1/2"));
+
+        // Without an interval, the table is printed once.
+        let mut out = Vec::new();
+        let mut refreshes = 0;
+        let summary = watch(
+            |_| {
+                refreshes += 1;
+                Ok(Summary {
+                    total: 1,
+                    pending: 1,
+                    ..Default::default()
+                })
+            },
+            None,
+            |_| panic!("Should not sleep"),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(refreshes, 1);
+        assert_eq!(summary.pending, 1);
+    }
+
+    #[test]
+    fn watch_through_failures() {
+        // A transaction failing to be queried is retried at the next refresh.
+        let mut refreshes = 0;
+        let summary = watch(
+            |_| {
+                refreshes += 1;
+                Ok(Summary {
+                    total: 1,
+                    executed: (refreshes > 1) as usize,
+                    failed: (refreshes == 1) as usize,
+                    ..Default::default()
+                })
+            },
+            Some(Duration::from_secs(1)),
+            |_| {},
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(refreshes, 2);
+        assert!(summary.is_final());
+    }
+}
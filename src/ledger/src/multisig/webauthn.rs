@@ -0,0 +1,323 @@
+use super::inbox::{self, InboxServer, Pending};
+use crate::transport::ManyClient;
+use clap::Parser;
+use coset::{CborSerializable, CoseSign1};
+use many_error::ManyError;
+use many_identity::verifiers::AnonymousVerifier;
+use many_identity::{Address, Identity};
+use many_identity_dsa::CoseKeyVerifier;
+use many_modules::account::features::multisig;
+use many_protocol::{decode_response_from_cose_sign1, RequestMessage, ResponseMessage};
+use minicbor::bytes::ByteVec;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::info;
+
+/// The only method the web approval page signs.
+const APPROVE_METHOD: &str = "account.multisigApprove";
+
+/// The version of the prepared approvals, for the web approval page.
+const PREPARED_VERSION: u64 = 1;
+
+#[derive(Parser)]
+pub struct SubmitApprovalOpt {
+    /// A file with the envelope signed by the web approval page, in
+    /// hexadecimal or in binary.
+    file: PathBuf,
+}
+
+/// Sends envelopes signed by another client.
+pub(super) trait EnvelopeServer {
+    fn forward(&self, envelope: CoseSign1) -> Result<ResponseMessage, ManyError>;
+}
+
+impl<I: Identity> EnvelopeServer for ManyClient<I> {
+    fn forward(&self, envelope: CoseSign1) -> Result<ResponseMessage, ManyError> {
+        let response = crate::transport::send_envelope(self, envelope)?;
+        decode_response_from_cose_sign1(&response, None, &(AnonymousVerifier, CoseKeyVerifier))
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+}
+
+/// Everything the web approval page needs to have an approval signed by a
+/// WebAuthn credential: the request to sign, where to send it, and what it
+/// approves.
+#[allow(clippy::too_many_arguments)]
+fn prepared_json(
+    server: &str,
+    server_id: Address,
+    account: Address,
+    approver: Address,
+    token: &ByteVec,
+    payload: &[u8],
+    summary: &str,
+    expires_at: Option<SystemTime>,
+) -> Value {
+    json!({
+        "version": PREPARED_VERSION,
+        "server": server,
+        "server_id": server_id.to_string(),
+        "method": APPROVE_METHOD,
+        "account": account.to_string(),
+        "approver": approver.to_string(),
+        "token": hex::encode(token.as_slice()),
+        "payload": hex::encode(payload),
+        "summary": summary,
+        "expires_at": expires_at.map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+    })
+}
+
+/// Prepare the approval of a transaction by a WebAuthn identity, and print
+/// it for the web approval page. Nothing is signed or sent.
+pub(super) fn prepare(
+    client: &ManyClient<impl Identity>,
+    server: &str,
+    server_id: Address,
+    approver: Address,
+    token: ByteVec,
+) -> Result<(), ManyError> {
+    if approver.is_anonymous() {
+        return Err(ManyError::unknown(
+            "The approver cannot be anonymous, pass the address of their WebAuthn identity \
+            with --approver.",
+        ));
+    }
+
+    let info = client.multisig_info(&token)?;
+    if info.state != multisig::MultisigTransactionState::Pending {
+        return Err(ManyError::unknown(format!(
+            "The transaction is not pending, it is {:?}.",
+            info.state
+        )));
+    }
+    let account = inbox::account_of(client, &token)?.ok_or_else(|| {
+        ManyError::unknown("No submission of this transaction was found in the events.")
+    })?;
+
+    let mut summary = Vec::new();
+    inbox::describe(
+        &mut summary,
+        &Pending {
+            token: token.clone(),
+            account,
+            info,
+        },
+        &client.local_names()?,
+    )
+    .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+    let request = crate::attachment::request(
+        approver,
+        server_id,
+        APPROVE_METHOD,
+        multisig::ApproveArgs {
+            token: token.clone(),
+        },
+        None,
+    )?;
+    let payload = request
+        .to_bytes()
+        .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+    let expires_at = crate::expiry::params(client).ok().and_then(|params| {
+        crate::expiry::expires_at(SystemTime::now(), params.window(APPROVE_METHOD))
+    });
+
+    crate::output::print_json(&prepared_json(
+        server,
+        server_id,
+        account,
+        approver,
+        &token,
+        &payload,
+        String::from_utf8_lossy(&summary).trim_end(),
+        expires_at,
+    ));
+    Ok(())
+}
+
+/// The envelope in a file, in hexadecimal or in binary.
+fn read_envelope(content: &[u8]) -> Vec<u8> {
+    std::str::from_utf8(content)
+        .ok()
+        .and_then(|s| hex::decode(s.trim()).ok())
+        .unwrap_or_else(|| content.to_vec())
+}
+
+/// Check that an envelope is an approval which can be sent as it is, and
+/// return it with the token it approves.
+fn check_envelope(bytes: &[u8]) -> Result<(CoseSign1, ByteVec), ManyError> {
+    let envelope = CoseSign1::from_slice(bytes)
+        .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    // The client sends the envelope re-encoded, so it must encode to the
+    // exact bytes which were signed.
+    let encoded = envelope
+        .clone()
+        .to_vec()
+        .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+    if encoded != bytes {
+        return Err(ManyError::unknown(
+            "The envelope is not canonically encoded, it cannot be forwarded unmodified.",
+        ));
+    }
+
+    let payload = envelope
+        .payload
+        .as_ref()
+        .ok_or_else(|| ManyError::unknown("The envelope has no payload."))?;
+    let request = RequestMessage::from_bytes(payload)
+        .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    if request.method != APPROVE_METHOD {
+        return Err(ManyError::unknown(format!(
+            "The envelope calls '{}', only approvals can be submitted.",
+            request.method
+        )));
+    }
+    let args: multisig::ApproveArgs = minicbor::decode(&request.data)
+        .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    Ok((envelope, args.token))
+}
+
+/// Send an approval signed elsewhere, unmodified.
+fn forward(
+    server: &impl EnvelopeServer,
+    content: &[u8],
+) -> Result<(ByteVec, ResponseMessage), ManyError> {
+    let (envelope, token) = check_envelope(&read_envelope(content))?;
+    Ok((token, server.forward(envelope)?))
+}
+
+/// Submit an approval signed by the web approval page, and report its
+/// result.
+pub(super) fn submit_approval(
+    client: ManyClient<impl Identity>,
+    opts: SubmitApprovalOpt,
+) -> Result<(), ManyError> {
+    let content = std::fs::read(&opts.file).map_err(|e| ManyError::unknown(e.to_string()))?;
+    let (token, response) = forward(&client, &content)?;
+
+    let payload = crate::wait_response(&client, response)?;
+    let _result: multisig::ApproveReturn =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    info!("Approved {}.", hex::encode(token.as_slice()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coset::{iana, CoseSign1Builder, HeaderBuilder};
+    use std::cell::RefCell;
+    use std::str::FromStr;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn token() -> ByteVec {
+        ByteVec::from(vec![1, 2, 3])
+    }
+
+    fn request(method: &str) -> RequestMessage {
+        crate::attachment::request(
+            address(1),
+            address(0),
+            method,
+            multisig::ApproveArgs { token: token() },
+            None,
+        )
+        .unwrap()
+    }
+
+    /// An envelope as the web approval page signs it. The server checks
+    /// the signature, not the client.
+    fn fixture(request: &RequestMessage) -> Vec<u8> {
+        CoseSign1Builder::new()
+            .protected(
+                HeaderBuilder::new()
+                    .algorithm(iana::Algorithm::ES256)
+                    .key_id(b"webauthn-credential".to_vec())
+                    .build(),
+            )
+            .payload(request.to_bytes().unwrap())
+            .signature(vec![0x5a; 64])
+            .build()
+            .to_vec()
+            .unwrap()
+    }
+
+    /// A server recording the envelopes it receives.
+    #[derive(Default)]
+    struct MockServer {
+        received: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl EnvelopeServer for MockServer {
+        fn forward(&self, envelope: CoseSign1) -> Result<ResponseMessage, ManyError> {
+            let bytes = envelope.clone().to_vec().unwrap();
+            self.received.borrow_mut().push(bytes);
+            let request = RequestMessage::from_bytes(&envelope.payload.unwrap()).unwrap();
+            Ok(ResponseMessage::from_request(
+                &request,
+                &address(0),
+                Ok(vec![0xa0]),
+            ))
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let request = request(APPROVE_METHOD);
+        let envelope = fixture(&request);
+
+        for content in [hex::encode(&envelope).into_bytes(), envelope.clone()] {
+            let server = MockServer::default();
+            let (approved, response) = forward(&server, &content).unwrap();
+            assert_eq!(approved, token());
+            assert_eq!(response.data, Ok(vec![0xa0]));
+            assert_eq!(server.received.into_inner(), vec![envelope.clone()]);
+        }
+
+        // The payload is the request signed, as prepared.
+        let prepared = prepared_json(
+            "http://localhost:8000",
+            address(0),
+            address(2),
+            address(1),
+            &token(),
+            &request.to_bytes().unwrap(),
+            "Transaction 010203",
+            None,
+        );
+        assert_eq!(prepared["token"], json!("010203"));
+        assert_eq!(prepared["method"], json!(APPROVE_METHOD));
+        assert_eq!(prepared["account"], json!(address(2).to_string()));
+        assert_eq!(prepared["approver"], json!(address(1).to_string()));
+        assert_eq!(prepared["server"], json!("http://localhost:8000"));
+        assert_eq!(
+            CoseSign1::from_slice(&envelope).unwrap().payload,
+            hex::decode(prepared["payload"].as_str().unwrap()).ok()
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let server = MockServer::default();
+
+        // Only approvals.
+        let envelope = fixture(&request("ledger.send"));
+        assert!(forward(&server, &envelope).is_err());
+
+        // Trailing bytes would not be sent.
+        let mut envelope = fixture(&request(APPROVE_METHOD));
+        envelope.push(0);
+        assert!(forward(&server, &envelope).is_err());
+
+        assert!(forward(&server, b"not an envelope").is_err());
+        assert!(server.received.into_inner().is_empty());
+    }
+}
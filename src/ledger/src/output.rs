@@ -0,0 +1,171 @@
+use many_types::ledger::{Symbol, TokenAmount};
+use once_cell::sync::OnceCell;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+/// How the results of commands are printed on stdout.
+#[derive(clap::ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+
+    /// A single JSON document. Amounts are strings, so they do not lose
+    /// precision in parsers reading numbers as floats.
+    Json,
+}
+
+static FORMAT: OnceCell<OutputFormat> = OnceCell::new();
+
+/// Set how the results of all subcommands are printed. Can only be called
+/// once.
+pub fn init(format: OutputFormat) {
+    FORMAT
+        .set(format)
+        .expect("The output format was already set.");
+}
+
+/// How the results are printed, for humans if not initialized.
+pub fn get() -> OutputFormat {
+    FORMAT.get().copied().unwrap_or(OutputFormat::Human)
+}
+
+/// Print a JSON document on stdout. Logs go to stderr, so stdout can be
+/// piped to a JSON parser.
+pub fn print_json(value: &Value) {
+    println!("{}", value);
+}
+
+fn amounts_json(
+    amounts: &BTreeMap<Symbol, TokenAmount>,
+    local_names: &BTreeMap<Symbol, String>,
+) -> Value {
+    Value::Object(
+        amounts
+            .iter()
+            .map(|(symbol, amount)| {
+                (
+                    symbol.to_string(),
+                    json!({
+                        "name": local_names.get(symbol),
+                        "amount": amount.to_string(),
+                    }),
+                )
+            })
+            .collect::<Map<_, _>>(),
+    )
+}
+
+/// The balances of an account, by symbol address, with the local name of
+/// the symbol when it is known.
+pub fn balance_json(
+    balances: &BTreeMap<Symbol, TokenAmount>,
+    escrowed: &BTreeMap<Symbol, TokenAmount>,
+    local_names: &BTreeMap<Symbol, String>,
+) -> Value {
+    json!({
+        "balances": amounts_json(balances, local_names),
+        "escrowed": amounts_json(escrowed, local_names),
+    })
+}
+
+/// The result of a command: its async token if it was executed
+/// asynchronously, and the CBOR payload of its response, in hexadecimal.
+pub fn response_json(token: Option<&[u8]>, payload: &[u8]) -> Value {
+    json!({
+        "token": token.map(hex::encode),
+        "payload": hex::encode(payload),
+    })
+}
+
+/// A signed request which was not sent, in hexadecimal and in CBOR
+/// diagnostic notation, and until when the ledger executes it if it expires.
+pub fn envelope_json(envelope: &[u8], expires_at: Option<SystemTime>) -> Value {
+    json!({
+        "envelope": hex::encode(envelope),
+        "diagnostic": minicbor::display(envelope).to_string(),
+        "expires_at": expires_at.map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+    })
+}
+
+/// Print a signed request which was not sent. For humans, the hexadecimal
+/// envelope is the only line on stdout, so it can be saved and submitted
+/// later, and its diagnostic notation and expiry go to stderr.
+pub fn print_envelope(envelope: &[u8], expires_at: Option<SystemTime>) {
+    match get() {
+        OutputFormat::Human => {
+            println!("{}", hex::encode(envelope));
+            eprintln!("{}", minicbor::display(envelope));
+            if let Some(t) = expires_at {
+                eprintln!(
+                    "Submit before {}, the ledger rejects it after.",
+                    humantime::format_rfc3339_seconds(t)
+                );
+            }
+        }
+        OutputFormat::Json => print_json(&envelope_json(envelope, expires_at)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use many_identity::Address;
+    use std::str::FromStr;
+
+    fn symbol(i: u32) -> Symbol {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    #[test]
+    fn balance() {
+        let huge = TokenAmount::from(num_bigint::BigUint::from(10u8).pow(30));
+        let balances = BTreeMap::from([(symbol(1), huge), (symbol(2), TokenAmount::from(5u64))]);
+        let escrowed = BTreeMap::from([(symbol(1), TokenAmount::from(7u64))]);
+        let local_names = BTreeMap::from([(symbol(1), "MFX".to_string())]);
+
+        let value = balance_json(&balances, &escrowed, &local_names);
+        assert_eq!(
+            value,
+            json!({
+                "balances": {
+                    symbol(1).to_string(): {
+                        "name": "MFX",
+                        "amount": "1000000000000000000000000000000",
+                    },
+                    symbol(2).to_string(): { "name": null, "amount": "5" },
+                },
+                "escrowed": {
+                    symbol(1).to_string(): { "name": "MFX", "amount": "7" },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn response() {
+        assert_eq!(
+            response_json(Some(&[1, 2]), &[0xa0]),
+            json!({ "token": "0102", "payload": "a0" })
+        );
+        assert_eq!(
+            response_json(None, &[]),
+            json!({ "token": null, "payload": "" })
+        );
+    }
+
+    #[test]
+    fn envelope() {
+        assert_eq!(
+            envelope_json(&[0x82, 0x01, 0x61, 0x61], None),
+            json!({ "envelope": "82016161", "diagnostic": "[1, \"a\"]", "expires_at": null })
+        );
+        let expires_at = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_655_000_300);
+        assert_eq!(
+            envelope_json(&[0x01], Some(expires_at))["expires_at"],
+            json!("2022-06-12T02:18:20Z")
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Exports report their size every time this many more bytes are written.
+pub const EXPORT_REPORT_BYTES: u64 = 64 * 1024;
+
+/// A progress event. The `type` field of each variant and the names of
+/// their fields are stable, so wrappers can rely on them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A check of the status of an async token which is not done yet.
+    AsyncWait {
+        token: String,
+        attempt: u32,
+        elapsed_ms: u64,
+    },
+
+    /// The end of the wait for an async token, with an outcome of `done`,
+    /// `expired` or `timeout`.
+    AsyncDone {
+        token: String,
+        outcome: &'static str,
+        elapsed_ms: u64,
+    },
+
+    /// The result of a transfer of a batch file.
+    BatchItem {
+        line: usize,
+        index: usize,
+        total: usize,
+        ok: bool,
+        error: Option<String>,
+    },
+
+    /// The end of a batch of sends of a sweep.
+    SweepBatch {
+        batch: usize,
+        sent: usize,
+        total: usize,
+        swept: String,
+    },
+
+    /// The number of bytes of an export written so far.
+    ExportBytes { bytes: u64, done: bool },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    seq: u64,
+
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Reports the progress of long operations, either interactively with a
+/// spinner on the terminal, or as JSON lines with sequence numbers.
+pub struct Progress {
+    sink: Option<Mutex<Box<dyn Write + Send>>>,
+    sequence: AtomicU64,
+}
+
+impl Progress {
+    pub fn interactive() -> Self {
+        Self {
+            sink: None,
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    pub fn json(sink: impl Write + Send + 'static) -> Self {
+        Self {
+            sink: Some(Mutex::new(Box::new(sink))),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Write an event, if events are reported. Errors writing them are
+    /// ignored, as they should not fail the operation.
+    pub fn emit(&self, event: Event) {
+        if let Some(sink) = &self.sink {
+            let mut sink = sink.lock().unwrap();
+            let line = Line {
+                seq: self.sequence.fetch_add(1, Ordering::SeqCst),
+                event: &event,
+            };
+            if let Ok(json) = serde_json::to_string(&line) {
+                let _ = writeln!(sink, "{}", json);
+                let _ = sink.flush();
+            }
+        }
+    }
+
+    /// A spinner, unless events are reported instead.
+    pub fn spinner(&self, message: &'static str) -> Option<indicatif::ProgressBar> {
+        if self.sink.is_some() {
+            return None;
+        }
+        let spinner = indicatif::ProgressBar::new_spinner().with_message(message);
+        spinner.enable_steady_tick(100);
+        Some(spinner)
+    }
+}
+
+static PROGRESS: OnceCell<Progress> = OnceCell::new();
+
+/// Set how progress is reported by all subcommands. Can only be called once.
+pub fn init(progress: Progress) {
+    if PROGRESS.set(progress).is_err() {
+        panic!("Progress reporting is already initialized.");
+    }
+}
+
+/// How progress is reported, interactively if not initialized.
+pub fn get() -> &'static Progress {
+    PROGRESS.get_or_init(Progress::interactive)
+}
+
+pub fn elapsed_ms(elapsed: Duration) -> u64 {
+    elapsed.as_millis() as u64
+}
+
+/// A writer reporting the number of bytes written through it.
+pub struct ExportWriter<'a, W: Write> {
+    inner: W,
+    progress: &'a Progress,
+    written: u64,
+    reported: u64,
+}
+
+impl<'a, W: Write> ExportWriter<'a, W> {
+    pub fn new(inner: W, progress: &'a Progress) -> Self {
+        Self {
+            inner,
+            progress,
+            written: 0,
+            reported: 0,
+        }
+    }
+
+    /// Flush the writer and report the final size.
+    pub fn finish(mut self) -> std::io::Result<u64> {
+        self.inner.flush()?;
+        self.progress.emit(Event::ExportBytes {
+            bytes: self.written,
+            done: true,
+        });
+        Ok(self.written)
+    }
+}
+
+impl<'a, W: Write> Write for ExportWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        if self.written - self.reported >= EXPORT_REPORT_BYTES {
+            self.reported = self.written;
+            self.progress.emit(Event::ExportBytes {
+                bytes: self.written,
+                done: false,
+            });
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A sink tests can read the events back from.
+    #[derive(Clone, Default)]
+    pub struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        pub fn lines(&self) -> Vec<serde_json::Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect()
+        }
+    }
+
+    pub fn types(lines: &[serde_json::Value]) -> Vec<&str> {
+        lines.iter().map(|l| l["type"].as_str().unwrap()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::*;
+    use super::*;
+
+    #[test]
+    fn async_wait() {
+        let buffer = Buffer::default();
+        let progress = Progress::json(buffer.clone());
+        assert!(progress.spinner("Waiting").is_none());
+
+        for attempt in 0..2 {
+            progress.emit(Event::AsyncWait {
+                token: "0102".to_string(),
+                attempt,
+                elapsed_ms: attempt as u64 * 1000,
+            });
+        }
+        progress.emit(Event::AsyncDone {
+            token: "0102".to_string(),
+            outcome: "done",
+            elapsed_ms: 2000,
+        });
+
+        let lines = buffer.lines();
+        assert_eq!(types(&lines), ["async_wait", "async_wait", "async_done"]);
+        assert_eq!(
+            lines[1],
+            serde_json::json!({
+                "seq": 1,
+                "type": "async_wait",
+                "token": "0102",
+                "attempt": 1,
+                "elapsed_ms": 1000,
+            })
+        );
+        assert_eq!(lines[2]["outcome"], "done");
+    }
+
+    #[test]
+    fn sequence_is_monotonic() {
+        let buffer = Buffer::default();
+        let progress = Progress::json(buffer.clone());
+        for (i, ok) in [true, false, true].into_iter().enumerate() {
+            progress.emit(Event::BatchItem {
+                line: i + 2,
+                index: i,
+                total: 3,
+                ok,
+                error: (!ok).then(|| "Insufficient funds.".to_string()),
+            });
+        }
+        progress.emit(Event::SweepBatch {
+            batch: 1,
+            sent: 3,
+            total: 3,
+            swept: "30".to_string(),
+        });
+
+        let lines = buffer.lines();
+        let seqs: Vec<u64> = lines.iter().map(|l| l["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, [0, 1, 2, 3]);
+        assert_eq!(
+            types(&lines),
+            ["batch_item", "batch_item", "batch_item", "sweep_batch"]
+        );
+        assert_eq!(lines[1]["error"], "Insufficient funds.");
+        assert!(lines[0]["error"].is_null());
+    }
+
+    #[test]
+    fn export_bytes() {
+        let buffer = Buffer::default();
+        let progress = Progress::json(buffer.clone());
+        let mut writer = ExportWriter::new(Vec::new(), &progress);
+        let chunk = vec![0u8; 40 * 1024];
+        for _ in 0..4 {
+            writer.write_all(&chunk).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 160 * 1024);
+
+        let bytes: Vec<(u64, bool)> = buffer
+            .lines()
+            .iter()
+            .map(|l| (l["bytes"].as_u64().unwrap(), l["done"].as_bool().unwrap()))
+            .collect();
+        assert_eq!(
+            bytes,
+            [(80 * 1024, false), (160 * 1024, false), (160 * 1024, true)]
+        );
+    }
+
+    #[test]
+    fn interactive_is_silent() {
+        let progress = Progress::interactive();
+        progress.emit(Event::ExportBytes {
+            bytes: 1,
+            done: true,
+        });
+        assert_eq!(progress.sequence.load(Ordering::SeqCst), 0);
+    }
+}
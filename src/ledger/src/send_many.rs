@@ -0,0 +1,412 @@
+use crate::limits::{self, LimitError, TransferLimits};
+use crate::output::{self, OutputFormat};
+use crate::transport::ManyClient;
+use crate::{account, alias, amount, resolve_symbol, wait_response, SendArgs};
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::r#async;
+use many_types::ledger::{Symbol, TokenAmount};
+use num_bigint::BigUint;
+use std::str::FromStr;
+use tracing::info;
+
+#[derive(Parser)]
+pub struct SendManyOpt {
+    /// The from identity, if different than the one provided by the
+    /// PEM argument.
+    #[clap(long, parse(try_from_str = alias::address))]
+    account: Option<Address>,
+
+    /// A recipient and the amount to send it, as `<address>:<amount>`. The
+    /// address can be an alias. Repeat for each recipient.
+    #[clap(long = "to", required = true)]
+    recipients: Vec<Recipient>,
+
+    /// The symbol of all the transfers, resolved once. This can either be
+    /// an identity or a local name for a symbol.
+    #[clap(long)]
+    symbol: String,
+
+    /// Read the amounts without decimals in whole tokens too.
+    #[clap(long)]
+    decimal: bool,
+
+    /// A memo for every recipient. Empty memos are omitted.
+    #[clap(long)]
+    memo: Option<String>,
+
+    /// Do not check the transfers against the balance and the limits of the
+    /// server before signing them.
+    #[clap(long)]
+    offline: bool,
+
+    /// Allow sending to the source account, for ledgers which record these
+    /// transfers as events without moving funds.
+    #[clap(long)]
+    self_transfer: bool,
+
+    /// Send even if the signing identity holds none of the roles needed on
+    /// the `--account` account, or if its roles cannot be read.
+    #[clap(long)]
+    force: bool,
+
+    /// Send the remaining transfers when one fails, instead of stopping at
+    /// the first failure.
+    #[clap(long)]
+    continue_on_error: bool,
+
+    /// Send without asking to confirm the transfers.
+    #[clap(long)]
+    yes: bool,
+}
+
+/// A `--to` argument.
+#[derive(Clone, Debug)]
+pub struct Recipient {
+    to: Address,
+    amount: amount::AmountArg,
+}
+
+impl FromStr for Recipient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (to, amount) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("Expected <address>:<amount>, got '{}'.", s))?;
+        if amount.trim().is_empty() {
+            return Err(format!("No amount for {}.", to));
+        }
+        Ok(Self {
+            to: alias::address(to.trim())?,
+            amount: amount::AmountArg::from_str(amount).unwrap(),
+        })
+    }
+}
+
+/// A transfer checked before sending any of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Transfer {
+    to: Address,
+    amount: BigUint,
+}
+
+/// Check every transfer against the limits of the symbol, and their total
+/// cost against the balance. All the invalid transfers are reported at once.
+fn check_all(
+    transfers: &[Transfer],
+    balance: Option<&TokenAmount>,
+    limits: &TransferLimits,
+) -> Result<(), String> {
+    let mut errors = Vec::new();
+    let mut total = TokenAmount::zero();
+    for (i, transfer) in transfers.iter().enumerate() {
+        match limits::total_cost(&TokenAmount::from(transfer.amount.clone()), limits) {
+            Ok(cost) => total = total + cost,
+            Err(e) => errors.push(format!("Transfer {} to {}: {}", i + 1, transfer.to, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    match balance {
+        Some(balance) if &total > balance => Err(LimitError::InsufficientFunds {
+            total,
+            balance: balance.clone(),
+        }
+        .to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Describe the transfers about to be sent, with their total.
+fn plan(from: &Address, symbol: &Symbol, transfers: &[Transfer]) -> String {
+    let total: BigUint = transfers.iter().map(|t| &t.amount).sum();
+    let mut plan = format!(
+        "Sending {} {} from {} to {} recipients:\n",
+        total,
+        symbol,
+        from,
+        transfers.len()
+    );
+    for (i, transfer) in transfers.iter().enumerate() {
+        plan.push_str(&format!(
+            "  {}. {} {}\n",
+            i + 1,
+            transfer.to,
+            transfer.amount
+        ));
+    }
+    plan
+}
+
+/// The result of each transfer attempted, in order. The ones after a
+/// failure are missing unless `--continue-on-error` is set.
+type Results = Vec<Result<Option<Vec<u8>>, ManyError>>;
+
+/// A table of the transfers and what happened to each, and the total sent.
+fn summary(symbol: &Symbol, transfers: &[Transfer], results: &Results) -> String {
+    let mut summary = String::new();
+    let mut sent = BigUint::default();
+    let mut count = 0;
+    for (i, transfer) in transfers.iter().enumerate() {
+        let status = match results.get(i) {
+            Some(Ok(_)) => {
+                sent += &transfer.amount;
+                count += 1;
+                "sent".to_string()
+            }
+            Some(Err(e)) => format!("failed: {}", e),
+            None => "not attempted".to_string(),
+        };
+        summary.push_str(&format!(
+            "  {}. {} {} {}\n",
+            i + 1,
+            transfer.to,
+            transfer.amount,
+            status
+        ));
+    }
+    let total: BigUint = transfers.iter().map(|t| &t.amount).sum();
+    summary.push_str(&format!(
+        "Sent {} of {} {} to {} of {} recipients.",
+        sent,
+        total,
+        symbol,
+        count,
+        transfers.len()
+    ));
+    summary
+}
+
+/// Send tokens of one symbol to several recipients, one transfer after the
+/// other. Every transfer is checked before the first one is sent.
+pub fn send_many(
+    client: ManyClient<impl Identity>,
+    signer: Address,
+    opts: SendManyOpt,
+) -> Result<(), ManyError> {
+    let from = opts.account.unwrap_or(signer);
+    if from.is_anonymous() {
+        return Err(ManyError::invalid_identity());
+    }
+    let symbol = resolve_symbol(&client, opts.symbol.clone())?;
+    let memo = limits::check_memo(opts.memo.clone())?;
+
+    // The decimals are only read if an amount is in whole tokens.
+    let decimals = once_cell::unsync::OnceCell::new();
+    let mut transfers = Vec::with_capacity(opts.recipients.len());
+    for (i, recipient) in opts.recipients.iter().enumerate() {
+        let amount = recipient
+            .amount
+            .scaled_units(opts.decimal, || {
+                decimals
+                    .get_or_try_init(|| crate::token_decimals(&client, symbol))
+                    .copied()
+            })
+            .and_then(|amount| {
+                limits::check_self_transfer(&from, &recipient.to, opts.self_transfer)
+                    .map(|_| amount)
+            })
+            .map_err(|e| {
+                ManyError::unknown(format!("Transfer {} to {}: {}", i + 1, recipient.to, e))
+            })?;
+        transfers.push(Transfer {
+            to: recipient.to,
+            amount,
+        });
+    }
+
+    if opts.offline {
+        check_all(&transfers, None, &TransferLimits::default())
+    } else {
+        account::check_signer(&client, from, signer, &account::SEND_ROLES, opts.force)?;
+        let (balance, limits) = limits::fetch(&client, from, symbol)?;
+        check_all(&transfers, Some(&balance), &limits)
+    }
+    .map_err(ManyError::unknown)?;
+    for transfer in &transfers {
+        let amount = TokenAmount::from(transfer.amount.clone());
+        limits::check_receive(&client, from, transfer.to, symbol, &amount, opts.offline);
+    }
+
+    let plan = plan(&from, &symbol, &transfers);
+    if opts.yes {
+        info!("{}", plan.trim_end());
+    } else {
+        let stdin = std::io::stdin();
+        let question = format!("{}Send these transfers?", plan);
+        let confirmed = crate::split::confirm(&question, stdin.lock(), std::io::stderr())
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        if !confirmed {
+            return Err(ManyError::unknown("Cancelled, nothing was sent."));
+        }
+    }
+
+    let mut results = Results::with_capacity(transfers.len());
+    for transfer in &transfers {
+        let arguments = SendArgs {
+            from: Some(from),
+            to: transfer.to,
+            amount: TokenAmount::from(transfer.amount.clone()),
+            symbol,
+            memo: memo.clone(),
+        };
+        let result =
+            crate::transport::call(&client, "ledger.send", arguments).and_then(|response| {
+                let token = response
+                    .attributes
+                    .get::<r#async::attributes::AsyncAttribute>()
+                    .ok()
+                    .map(|attr| attr.token.to_vec());
+                wait_response(&client, response).map(|_| token)
+            });
+        let failed = result.is_err();
+        results.push(result);
+        if failed && !opts.continue_on_error {
+            break;
+        }
+    }
+
+    match output::get() {
+        OutputFormat::Human => println!("{}", summary(&symbol, &transfers, &results)),
+        OutputFormat::Json => output::print_json(&serde_json::Value::Array(
+            transfers
+                .iter()
+                .enumerate()
+                .map(|(i, transfer)| {
+                    let result = results.get(i);
+                    serde_json::json!({
+                        "to": transfer.to.to_string(),
+                        "amount": transfer.amount.to_string(),
+                        "sent": matches!(result, Some(Ok(_))),
+                        "token": result
+                            .and_then(|r| r.as_ref().ok())
+                            .and_then(Option::as_ref)
+                            .map(hex::encode),
+                        "error": result
+                            .and_then(|r| r.as_ref().err())
+                            .map(ToString::to_string),
+                    })
+                })
+                .collect(),
+        )),
+    }
+
+    let failed = transfers.len() - results.iter().filter(|r| r.is_ok()).count();
+    if failed > 0 {
+        Err(ManyError::unknown(format!(
+            "{} of {} transfers were not sent.",
+            failed,
+            transfers.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn transfers(amounts: &[u64]) -> Vec<Transfer> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| Transfer {
+                to: address(i as u32 + 2),
+                amount: BigUint::from(*amount),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recipients() {
+        let recipient = Recipient::from_str(&format!("{}:100", address(2))).unwrap();
+        assert_eq!(recipient.to, address(2));
+        assert_eq!(
+            recipient.amount.base_units().unwrap(),
+            BigUint::from(100u32)
+        );
+
+        assert!(Recipient::from_str(&address(2).to_string())
+            .unwrap_err()
+            .contains("Expected <address>:<amount>"));
+        assert!(Recipient::from_str(&format!("{}:", address(2)))
+            .unwrap_err()
+            .contains("No amount"));
+        assert!(Recipient::from_str("not-an-address:100").is_err());
+    }
+
+    #[test]
+    fn checks() {
+        let balance = TokenAmount::from(600u64);
+        let limits = TransferLimits::default();
+        assert!(check_all(&transfers(&[100, 200, 300]), Some(&balance), &limits).is_ok());
+        assert!(check_all(&transfers(&[100, 200, 300]), None, &limits).is_ok());
+
+        // The total is checked, not each transfer.
+        assert_eq!(
+            check_all(&transfers(&[100, 200, 301]), Some(&balance), &limits).unwrap_err(),
+            LimitError::InsufficientFunds {
+                total: TokenAmount::from(601u64),
+                balance: balance.clone(),
+            }
+            .to_string()
+        );
+
+        // With the fee of every transfer.
+        let fee = TransferLimits {
+            fee: TokenAmount::from(1u64),
+            ..TransferLimits::default()
+        };
+        assert!(check_all(&transfers(&[100, 200, 298]), Some(&balance), &fee).is_err());
+
+        // Every invalid transfer is reported.
+        assert_eq!(
+            check_all(&transfers(&[100, 0, 300, 0]), None, &limits).unwrap_err(),
+            format!(
+                "Transfer 2 to {}: The amount cannot be zero.\n\
+                 Transfer 4 to {}: The amount cannot be zero.",
+                address(3),
+                address(5)
+            )
+        );
+    }
+
+    #[test]
+    fn plan_and_summary() {
+        let transfers = transfers(&[100, 200, 300]);
+        assert_eq!(
+            plan(&address(1), &address(9), &transfers),
+            format!(
+                "Sending 600 {} from {} to 3 recipients:\n  1. {} 100\n  2. {} 200\n  3. {} 300\n",
+                address(9),
+                address(1),
+                address(2),
+                address(3),
+                address(4)
+            )
+        );
+
+        let results: Results = vec![Ok(None), Err(ManyError::unknown("Insufficient funds."))];
+        let summary = summary(&address(9), &transfers, &results);
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], format!("  1. {} 100 sent", address(2)));
+        assert!(lines[1].starts_with(&format!("  2. {} 200 failed: ", address(3))));
+        assert!(lines[1].contains("Insufficient funds."));
+        assert_eq!(lines[2], format!("  3. {} 300 not attempted", address(4)));
+        assert_eq!(
+            lines[3],
+            format!("Sent 100 of 600 {} to 1 of 3 recipients.", address(9))
+        );
+    }
+}
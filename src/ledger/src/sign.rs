@@ -0,0 +1,220 @@
+use crate::{alias, amount, limits, output, SendArgs};
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::ledger::TokenAmount;
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::info;
+
+#[derive(Parser)]
+pub struct SignOpt {
+    /// The from identity, if different than the one provided by the
+    /// PEM argument.
+    #[clap(long, parse(try_from_str = alias::address))]
+    account: Option<Address>,
+
+    /// The account or target identity, or its alias.
+    #[clap(parse(try_from_str = alias::address))]
+    identity: Address,
+
+    /// The amount of tokens, in base units. An amount with decimals, e.g.
+    /// `1.5`, is in whole tokens instead, and needs `--decimals`.
+    amount: amount::AmountArg,
+
+    /// Read an amount without decimals in whole tokens too.
+    #[clap(long)]
+    decimal: bool,
+
+    /// The number of decimals of the symbol, to convert an amount in whole
+    /// tokens to base units.
+    #[clap(long)]
+    decimals: Option<u64>,
+
+    /// The address of the symbol. Local names are not resolved, as nothing
+    /// is read from the server.
+    #[clap(parse(try_from_str = parse_symbol))]
+    symbol: Address,
+
+    /// Allow sending to the source account, for ledgers which record these
+    /// transfers as events without moving funds.
+    #[clap(long)]
+    self_transfer: bool,
+
+    /// A memo for the recipient. Empty memos are omitted.
+    #[clap(long)]
+    memo: Option<String>,
+
+    /// The file to write the signed envelope to, in CBOR.
+    #[clap(long)]
+    out: PathBuf,
+
+    /// The timestamp of the message, as an RFC 3339 time or seconds since
+    /// the epoch. Defaults to now.
+    #[clap(long, parse(try_from_str = parse_timestamp))]
+    timestamp: Option<Timestamp>,
+
+    /// The nonce of the message, in hexadecimal. Defaults to 16 random
+    /// bytes.
+    #[clap(long, parse(try_from_str = parse_nonce))]
+    nonce: Option<ByteVec>,
+}
+
+fn parse_symbol(s: &str) -> Result<Address, String> {
+    Address::from_str(s).map_err(|_| {
+        format!(
+            "'{}' is not an address. Symbols are not resolved when signing offline, use the \
+             address of the symbol.",
+            s
+        )
+    })
+}
+
+fn parse_timestamp(s: &str) -> Result<Timestamp, String> {
+    if let Ok(secs) = s.parse::<u64>() {
+        return Timestamp::new(secs).map_err(|e| e.to_string());
+    }
+    let time = humantime::parse_rfc3339_weak(s).map_err(|_| {
+        format!(
+            "'{}' is neither an RFC 3339 time nor a number of seconds.",
+            s
+        )
+    })?;
+    Timestamp::from_system_time(time).map_err(|e| e.to_string())
+}
+
+fn parse_nonce(s: &str) -> Result<ByteVec, String> {
+    let nonce = hex::decode(s).map_err(|e| format!("Invalid nonce: {}.", e))?;
+    if nonce.is_empty() {
+        return Err("The nonce cannot be empty.".to_string());
+    }
+    Ok(nonce.into())
+}
+
+/// The arguments of the transfer, checked without the server.
+fn send_args(signer: Address, opts: &SignOpt) -> Result<SendArgs, ManyError> {
+    let from = opts.account.unwrap_or(signer);
+    if from.is_anonymous() {
+        return Err(ManyError::invalid_identity());
+    }
+    limits::check_self_transfer(&from, &opts.identity, opts.self_transfer)?;
+    let amount = opts.amount.scaled_units(opts.decimal, || {
+        opts.decimals.ok_or_else(|| {
+            ManyError::unknown(
+                "The amount is in whole tokens, pass the decimals of the symbol with \
+                 --decimals, or the amount in base units.",
+            )
+        })
+    })?;
+
+    Ok(SendArgs {
+        from: Some(from),
+        to: opts.identity,
+        amount: TokenAmount::from(amount),
+        symbol: opts.symbol,
+        memo: limits::check_memo(opts.memo.clone())?,
+    })
+}
+
+/// Sign a transfer without any network access, and write its envelope to
+/// a file, to be submitted from another machine. Nothing was checked against
+/// the server limits, so the envelope is stamped with it.
+pub fn sign(signer: Address, opts: SignOpt) -> Result<(), ManyError> {
+    let arguments = send_args(signer, &opts)?;
+    let timestamp = opts.timestamp.unwrap_or_else(Timestamp::now);
+    let nonce = opts.nonce.as_ref().map_or_else(
+        || rand::random::<[u8; 16]>().to_vec(),
+        |nonce| nonce.to_vec(),
+    );
+
+    let envelope =
+        crate::attachment::signed_request_at("ledger.send", arguments, timestamp, nonce)?;
+    let envelope = limits::stamp_unchecked(&envelope)?;
+    std::fs::write(&opts.out, &envelope).map_err(|e| {
+        ManyError::unknown(format!("Could not write {}: {}", opts.out.display(), e))
+    })?;
+
+    match output::get() {
+        output::OutputFormat::Human => eprintln!("{}", minicbor::display(&envelope)),
+        output::OutputFormat::Json => output::print_json(&output::envelope_json(&envelope, None)),
+    }
+    info!("Wrote the signed envelope to {}.", opts.out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn opts(args: &[&str]) -> Result<SignOpt, clap::Error> {
+        SignOpt::try_parse_from(
+            ["sign", "--out", "envelope.cbor"]
+                .iter()
+                .copied()
+                .chain(args.iter().copied()),
+        )
+    }
+
+    #[test]
+    fn arguments() {
+        let to = address(2).to_string();
+        let symbol = address(3).to_string();
+
+        let args = send_args(address(1), &opts(&[&to, "1000", &symbol]).unwrap()).unwrap();
+        assert_eq!(args.from, Some(address(1)));
+        assert_eq!(args.to, address(2));
+        assert_eq!(args.amount, TokenAmount::from(1000u64));
+        assert_eq!(args.symbol, address(3));
+
+        // Whole tokens need the decimals, which are not read from the server.
+        let whole = opts(&[&to, "1.5", &symbol]).unwrap();
+        assert!(send_args(address(1), &whole)
+            .unwrap_err()
+            .to_string()
+            .contains("--decimals"));
+        let whole = opts(&["--decimals", "9", &to, "1.5", &symbol]).unwrap();
+        assert_eq!(
+            send_args(address(1), &whole).unwrap().amount,
+            TokenAmount::from(1_500_000_000u64)
+        );
+
+        let to_self = opts(&[&address(1).to_string(), "1000", &symbol]).unwrap();
+        assert!(send_args(address(1), &to_self).is_err());
+        assert!(send_args(
+            Address::anonymous(),
+            &opts(&[&to, "1000", &symbol]).unwrap()
+        )
+        .is_err());
+
+        assert!(opts(&[&to, "1000", "MFX"])
+            .unwrap_err()
+            .to_string()
+            .contains("Symbols are not resolved"));
+    }
+
+    #[test]
+    fn timestamp_and_nonce() {
+        assert_eq!(
+            parse_timestamp("86400"),
+            Ok(Timestamp::new(86_400).unwrap())
+        );
+        assert_eq!(
+            parse_timestamp("1970-01-02T00:00:00Z"),
+            Ok(Timestamp::new(86_400).unwrap())
+        );
+        assert!(parse_timestamp("tomorrow").is_err());
+
+        assert_eq!(parse_nonce("00ff"), Ok(ByteVec::from(vec![0, 255])));
+        assert!(parse_nonce("").is_err());
+        assert!(parse_nonce("xyz").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use crate::output::{self, OutputFormat};
+use crate::transport::ManyClient;
+use crate::{wait_response, SendArgs};
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::Identity;
+use many_modules::r#async;
+use many_types::ledger::TokenAmount;
+use num_bigint::BigUint;
+use std::io::{BufRead, Write};
+use tracing::info;
+
+#[derive(Default, Parser)]
+pub(crate) struct SplitOpt {
+    /// Send the amount in this many transfers, one after the other. Each
+    /// transfer is the amount divided by this, and the remainder of the
+    /// division is added to the last one.
+    #[clap(long, conflicts_with_all = &["dry-run", "attach"])]
+    split: Option<u64>,
+
+    /// With `--split`, send the remaining transfers when one fails, instead
+    /// of stopping at the first failure.
+    #[clap(long, requires = "split")]
+    continue_on_error: bool,
+
+    /// With `--split`, send without asking to confirm the plan.
+    #[clap(long, requires = "split")]
+    yes: bool,
+}
+
+impl SplitOpt {
+    pub fn is_set(&self) -> bool {
+        self.split.is_some()
+    }
+}
+
+/// Divide a total into `parts` amounts, all equal to the total divided by
+/// `parts`, except the last one which also holds the remainder of the
+/// division. Every part is at least 1, so the total must be at least the
+/// number of parts.
+pub(crate) fn split(total: &BigUint, parts: u64) -> Result<Vec<BigUint>, String> {
+    if parts == 0 {
+        return Err("Cannot split a transfer in 0 parts.".to_string());
+    }
+    let count = BigUint::from(parts);
+    if total < &count {
+        return Err(format!(
+            "Cannot split {} in {} transfers of at least 1.",
+            total, parts
+        ));
+    }
+
+    let base = total / &count;
+    let remainder = total % &count;
+    let mut amounts = vec![base.clone(); parts as usize];
+    if let Some(last) = amounts.last_mut() {
+        *last = base + remainder;
+    }
+    Ok(amounts)
+}
+
+/// Describe the transfers about to be sent.
+fn plan(total: &BigUint, amounts: &[BigUint], arguments: &SendArgs) -> String {
+    let mut plan = format!(
+        "Sending {} {} to {} in {} transfers:\n",
+        total,
+        arguments.symbol,
+        arguments.to,
+        amounts.len()
+    );
+    for (i, amount) in amounts.iter().enumerate() {
+        plan.push_str(&format!("  {}. {}\n", i + 1, amount));
+    }
+    plan
+}
+
+/// Ask a question and read the answer. Anything but "y" or "yes" declines.
+pub(crate) fn confirm(
+    question: &str,
+    mut input: impl BufRead,
+    mut out: impl Write,
+) -> std::io::Result<bool> {
+    write!(out, "{} [y/N] ", question)?;
+    out.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// The result of each transfer attempted.
+type Results = Vec<(BigUint, Result<Option<Vec<u8>>, ManyError>)>;
+
+/// Send each amount in turn. Stops at the first failure unless
+/// `continue_on_error` is set.
+fn send_all(
+    amounts: &[BigUint],
+    continue_on_error: bool,
+    mut send: impl FnMut(&BigUint) -> Result<Option<Vec<u8>>, ManyError>,
+) -> Results {
+    let mut results = Vec::new();
+    for amount in amounts {
+        let result = send(amount);
+        let failed = result.is_err();
+        results.push((amount.clone(), result));
+        if failed && !continue_on_error {
+            break;
+        }
+    }
+    results
+}
+
+/// Check that the transfers which succeeded add up to the total, and report
+/// the shortfall otherwise.
+fn reconcile(total: &BigUint, count: usize, results: &Results) -> Result<BigUint, ManyError> {
+    let sent: BigUint = results
+        .iter()
+        .filter(|(_, r)| r.is_ok())
+        .map(|(amount, _)| amount)
+        .sum();
+    if &sent == total {
+        return Ok(sent);
+    }
+
+    let failed: Vec<String> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, r))| r.is_err())
+        .map(|(i, _)| (i + 1).to_string())
+        .collect();
+    let not_attempted = count - results.len();
+    Err(ManyError::unknown(format!(
+        "Sent {} of {}, short of {}: transfer(s) {} failed{}.",
+        sent,
+        total,
+        total - &sent,
+        failed.join(", "),
+        if not_attempted > 0 {
+            format!(", {} not attempted", not_attempted)
+        } else {
+            String::new()
+        }
+    )))
+}
+
+/// Send a transfer as several smaller ones, once the plan is confirmed.
+/// The arguments were checked against the server for the whole amount.
+pub(crate) fn send_split(
+    client: &ManyClient<impl Identity>,
+    total: BigUint,
+    arguments: SendArgs,
+    opts: SplitOpt,
+) -> Result<(), ManyError> {
+    let parts = opts.split.unwrap_or(1);
+    let amounts = split(&total, parts).map_err(ManyError::unknown)?;
+
+    let plan = plan(&total, &amounts, &arguments);
+    if opts.yes {
+        info!("{}", plan.trim_end());
+    } else {
+        let stdin = std::io::stdin();
+        let question = format!("{}Send these transfers?", plan);
+        let confirmed = confirm(&question, stdin.lock(), std::io::stderr())
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        if !confirmed {
+            return Err(ManyError::unknown("Cancelled, nothing was sent."));
+        }
+    }
+
+    let mut part = 0;
+    let results = send_all(&amounts, opts.continue_on_error, |amount| {
+        part += 1;
+        let response = crate::transport::call(
+            client,
+            "ledger.send",
+            SendArgs {
+                amount: TokenAmount::from(amount.clone()),
+                ..arguments.clone()
+            },
+        )?;
+        let token = response
+            .attributes
+            .get::<r#async::attributes::AsyncAttribute>()
+            .ok()
+            .map(|attr| attr.token.to_vec());
+        let result = wait_response(client, response).map(|_| token);
+        if output::get() == OutputFormat::Human {
+            match &result {
+                Ok(_) => println!("{}/{}: sent {}", part, parts, amount),
+                Err(e) => println!("{}/{}: failed to send {}: {}", part, parts, amount, e),
+            }
+        }
+        result
+    });
+    if output::get() == OutputFormat::Json {
+        output::print_json(&serde_json::Value::Array(
+            results
+                .iter()
+                .map(|(amount, result)| {
+                    serde_json::json!({
+                        "amount": amount.to_string(),
+                        "sent": result.is_ok(),
+                        "token": result.as_ref().ok().and_then(Option::as_ref).map(hex::encode),
+                        "error": result.as_ref().err().map(ToString::to_string),
+                    })
+                })
+                .collect(),
+        ));
+    }
+
+    let sent = reconcile(&total, amounts.len(), &results)?;
+    info!("Sent {} in {} transfers.", sent, amounts.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(n: u64) -> BigUint {
+        BigUint::from(n)
+    }
+
+    #[test]
+    fn exhaustive() {
+        for total in 0..=200u64 {
+            for parts in 0..=50u64 {
+                let amounts = match split(&big(total), parts) {
+                    Ok(amounts) => amounts,
+                    Err(_) => {
+                        assert!(parts == 0 || total < parts, "{} in {}", total, parts);
+                        continue;
+                    }
+                };
+                assert!(parts > 0 && total >= parts);
+                assert_eq!(amounts.len() as u64, parts);
+                assert_eq!(amounts.iter().sum::<BigUint>(), big(total));
+
+                // All parts are the quotient, the last one with the
+                // remainder.
+                let (last, rest) = amounts.split_last().unwrap();
+                assert!(rest.iter().all(|a| a == &big(total / parts)));
+                assert_eq!(last, &big(total / parts + total % parts));
+                assert!(amounts.iter().all(|a| a >= &big(1)));
+            }
+        }
+    }
+
+    #[test]
+    fn large() {
+        let total = BigUint::from(u128::MAX) * 3u32 + 2u32;
+        let amounts = split(&total, 3).unwrap();
+        assert_eq!(amounts[0], BigUint::from(u128::MAX));
+        assert_eq!(amounts[1], BigUint::from(u128::MAX));
+        assert_eq!(amounts[2], BigUint::from(u128::MAX) + 2u32);
+        assert_eq!(amounts.iter().sum::<BigUint>(), total);
+    }
+
+    #[test]
+    fn confirmation() {
+        for (answer, expected) in [
+            ("y\n", true),
+            ("YES\n", true),
+            ("n\n", false),
+            ("\n", false),
+            ("", false),
+        ] {
+            let mut out = Vec::new();
+            assert_eq!(
+                confirm("Plan\nSend these transfers?", answer.as_bytes(), &mut out).unwrap(),
+                expected
+            );
+            assert_eq!(out, b"Plan\nSend these transfers? [y/N] ");
+        }
+    }
+
+    #[test]
+    fn partial_failure() {
+        // 2, 2, 2 and 4.
+        let amounts = split(&big(10), 4).unwrap();
+
+        // The last part fails, with the remainder.
+        let results = send_all(&amounts, false, |amount| {
+            if amount == &big(4) {
+                Err(ManyError::unknown("Insufficient funds."))
+            } else {
+                Ok(None)
+            }
+        });
+        assert_eq!(results.len(), 4);
+        let e = reconcile(&big(10), 4, &results).unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("Sent 6 of 10, short of 4: transfer(s) 4 failed."));
+
+        // Stops at the first failure, or carries on.
+        let mut calls = 0;
+        let results = send_all(&amounts, false, |_| {
+            calls += 1;
+            if calls == 2 {
+                Err(ManyError::unknown("Timeout."))
+            } else {
+                Ok(None)
+            }
+        });
+        assert_eq!(results.len(), 2);
+        let e = reconcile(&big(10), 4, &results).unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("Sent 2 of 10, short of 8: transfer(s) 2 failed, 2 not attempted."));
+
+        let mut calls = 0;
+        let results = send_all(&amounts, true, |_| {
+            calls += 1;
+            if calls == 2 {
+                Err(ManyError::unknown("Timeout."))
+            } else {
+                Ok(None)
+            }
+        });
+        assert_eq!(results.len(), 4);
+        let e = reconcile(&big(10), 4, &results).unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("Sent 8 of 10, short of 2: transfer(s) 2 failed."));
+
+        let results = send_all(&amounts, false, |_| Ok(None));
+        assert_eq!(reconcile(&big(10), 4, &results).unwrap(), big(10));
+    }
+}
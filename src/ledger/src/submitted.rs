@@ -0,0 +1,167 @@
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_types::{CborRange, Timestamp};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// These types mirror the ones of the many-abci server.
+
+#[derive(Encode)]
+#[cbor(map)]
+struct FindRequestArgs {
+    #[n(0)]
+    sender: Address,
+
+    #[n(1)]
+    nonce_range: Option<CborRange<ByteVec>>,
+
+    #[n(2)]
+    time_range: Option<CborRange<Timestamp>>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct TxLocation {
+    #[n(0)]
+    height: u64,
+
+    #[n(1)]
+    index: u64,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct FoundRequest {
+    #[n(0)]
+    nonce: Option<ByteVec>,
+
+    #[n(1)]
+    timestamp: Timestamp,
+
+    #[n(2)]
+    hash: ByteVec,
+
+    #[n(3)]
+    location: Option<TxLocation>,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct FindRequestReturns {
+    #[n(0)]
+    requests: Vec<FoundRequest>,
+}
+
+/// A point in time, either a duration ago (e.g. `2h`) or an RFC 3339 date
+/// (e.g. `2022-06-01T00:00:00Z`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Since(Duration);
+
+impl Since {
+    fn parse(s: &str, now: SystemTime) -> Result<Self, String> {
+        let time = match humantime::parse_duration(s) {
+            Ok(ago) => now
+                .checked_sub(ago)
+                .ok_or_else(|| format!("'{}' is too far in the past.", s))?,
+            Err(_) => humantime::parse_rfc3339_weak(s)
+                .map_err(|_| format!("'{}' is neither a duration nor a date.", s))?,
+        };
+        time.duration_since(UNIX_EPOCH)
+            .map(Self)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, SystemTime::now())
+    }
+}
+
+#[derive(Parser)]
+pub struct SubmittedOpt {
+    /// Only list the requests made after this time, either a duration ago
+    /// (e.g. `2h`) or a date.
+    #[clap(long, default_value = "1day")]
+    since: Since,
+}
+
+/// List the requests of the caller broadcast by the server, which must be
+/// a many-abci node with a request index.
+pub fn submitted(
+    client: ManyClient<impl Identity>,
+    client_address: Address,
+    opts: SubmittedOpt,
+) -> Result<(), ManyError> {
+    if client_address.is_anonymous() {
+        return Err(ManyError::invalid_identity());
+    }
+    let since =
+        Timestamp::new(opts.since.0.as_secs()).map_err(|e| ManyError::unknown(e.to_string()))?;
+
+    let payload = crate::transport::call_(
+        &client,
+        "abci.findRequest",
+        FindRequestArgs {
+            sender: client_address,
+            nonce_range: None,
+            time_range: Some(CborRange {
+                start: Bound::Included(since),
+                end: Bound::Unbounded,
+            }),
+        },
+    )?;
+    let returns: FindRequestReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    if returns.requests.is_empty() {
+        println!("No requests found.");
+    }
+    for request in returns.requests {
+        let time = request
+            .timestamp
+            .as_system_time()
+            .map(|t| humantime::format_rfc3339_seconds(t).to_string())
+            .unwrap_or_default();
+        let location = match request.location {
+            Some(TxLocation { height, index }) => format!("height {} index {}", height, index),
+            None => "pending".to_string(),
+        };
+        println!(
+            "{} {} nonce {} {}",
+            time,
+            hex::encode(request.hash.as_slice()),
+            request
+                .nonce
+                .map_or_else(|| "-".to_string(), |n| hex::encode(n.as_slice())),
+            location
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_655_100_000);
+        assert_eq!(
+            Since::parse("2h", now).unwrap(),
+            Since(Duration::from_secs(1_655_100_000 - 7_200))
+        );
+        assert_eq!(
+            Since::parse("2022-06-13T00:00:00Z", now).unwrap(),
+            Since(Duration::from_secs(1_655_078_400))
+        );
+        assert!(Since::parse("yesterday", now).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+use crate::progress::{self, Event, Progress};
+use crate::resolve_symbol;
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_modules::ledger;
+use many_types::ledger::{Symbol, TokenAmount};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Default maximum number of subresources in a range.
+const DEFAULT_MAX_SUBRESOURCES: u32 = 10_000;
+
+/// Default number of balance queries in flight at once.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Default number of sends between two progress reports of a sweep.
+const DEFAULT_BATCH_SIZE: usize = 50;
+
+/// An inclusive range of subresource IDs, given as `<start>..<end>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubresourceRange {
+    start: u32,
+    end: u32,
+}
+
+impl FromStr for SubresourceRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("Invalid range '{}', expected <start>..<end>.", s))?;
+        let start = u32::from_str(start.trim()).map_err(|e| e.to_string())?;
+        let end = u32::from_str(end.trim()).map_err(|e| e.to_string())?;
+        if start > end {
+            return Err(format!("Invalid range '{}', start is after end.", s));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl SubresourceRange {
+    fn len(&self) -> u64 {
+        (self.end - self.start) as u64 + 1
+    }
+}
+
+#[derive(Parser)]
+pub struct RangeLimitsOpt {
+    /// Maximum number of subresources in a range.
+    #[clap(long, default_value_t = DEFAULT_MAX_SUBRESOURCES)]
+    max_subresources: u32,
+
+    /// Maximum number of balance queries sent to the server at once.
+    #[clap(long, default_value_t = DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+}
+
+#[derive(Parser)]
+pub struct SweepOpt {
+    /// The account whose subresources are swept, if different than the one
+    /// provided by the PEM argument.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    account: Option<Address>,
+
+    /// The subresources to sweep, e.g. `0..999`.
+    #[clap(long)]
+    subresource_range: SubresourceRange,
+
+    /// Number of sends between two progress reports.
+    #[clap(long, default_value_t = DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+
+    #[clap(flatten)]
+    limits: RangeLimitsOpt,
+
+    /// The destination of the funds.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    destination: Address,
+
+    /// The symbol to sweep. This can either be an identity or a local name
+    /// for a symbol.
+    symbol: String,
+}
+
+/// The server calls made on subresources. Queries are made concurrently,
+/// on a connection per worker.
+pub(crate) trait SubresourceServer {
+    fn balance(
+        &self,
+        account: Address,
+        symbols: Option<Vec<Symbol>>,
+    ) -> Result<BTreeMap<Symbol, TokenAmount>, ManyError>;
+
+    fn send(&self, args: ledger::SendArgs) -> Result<(), ManyError>;
+}
+
+impl<I: Identity> SubresourceServer for ManyClient<I> {
+    fn balance(
+        &self,
+        account: Address,
+        symbols: Option<Vec<Symbol>>,
+    ) -> Result<BTreeMap<Symbol, TokenAmount>, ManyError> {
+        let payload = crate::transport::call_(
+            self,
+            "ledger.balance",
+            ledger::BalanceArgs {
+                account: Some(account),
+                symbols: symbols.map(Into::into),
+            },
+        )?;
+        let returns: ledger::BalanceReturns = minicbor::decode(&payload)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        Ok(returns.balances)
+    }
+
+    fn send(&self, args: ledger::SendArgs) -> Result<(), ManyError> {
+        let response = crate::transport::call(self, "ledger.send", args)?;
+        crate::wait_response(self, response).map(|_| ())
+    }
+}
+
+/// The addresses of a range of subresources of an account, derived the same
+/// way the server derives them.
+fn derive(account: &Address, range: SubresourceRange, max: u32) -> Result<Vec<Address>, ManyError> {
+    if range.len() > max as u64 {
+        return Err(ManyError::unknown(format!(
+            "The range has {} subresources, more than the maximum of {}.",
+            range.len(),
+            max
+        )));
+    }
+    (range.start..=range.end)
+        .map(|id| account.with_subresource_id(id))
+        .collect()
+}
+
+/// Query the balances of addresses with at most `concurrency` queries in
+/// flight. Results are in the order of the addresses. Stops at the first
+/// error.
+fn balances<S: SubresourceServer>(
+    connect: impl Fn() -> Result<S, ManyError> + Sync,
+    addresses: &[Address],
+    symbols: Option<Vec<Symbol>>,
+    concurrency: usize,
+) -> Result<Vec<BTreeMap<Symbol, TokenAmount>>, ManyError> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![BTreeMap::new(); addresses.len()]);
+    let error = Mutex::new(None);
+
+    std::thread::scope(|s| {
+        for _ in 0..concurrency.clamp(1, addresses.len().max(1)) {
+            s.spawn(|| {
+                let server = match connect() {
+                    Ok(server) => server,
+                    Err(e) => {
+                        *error.lock().unwrap() = Some(e);
+                        return;
+                    }
+                };
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= addresses.len() || error.lock().unwrap().is_some() {
+                        return;
+                    }
+                    match server.balance(addresses[i], symbols.clone()) {
+                        Ok(balances) => results.lock().unwrap()[i] = balances,
+                        Err(e) => {
+                            error.lock().unwrap().get_or_insert(e);
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    match error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(results.into_inner().unwrap()),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SweepSummary {
+    swept: usize,
+    total: TokenAmount,
+    failed: Vec<(Address, TokenAmount, String)>,
+}
+
+/// Send each nonzero balance to the destination, reporting progress after
+/// every batch. A failed send does not stop the sweep.
+fn sweep_balances(
+    server: &impl SubresourceServer,
+    balances: Vec<(Address, TokenAmount)>,
+    destination: Address,
+    symbol: Symbol,
+    batch_size: usize,
+    out: &mut impl Write,
+    progress: &Progress,
+) -> std::io::Result<SweepSummary> {
+    let mut summary = SweepSummary {
+        swept: 0,
+        total: TokenAmount::zero(),
+        failed: vec![],
+    };
+    let balances: Vec<_> = balances
+        .into_iter()
+        .filter(|(from, amount)| !amount.is_zero() && from != &destination)
+        .collect();
+
+    for (i, batch) in balances.chunks(batch_size.max(1)).enumerate() {
+        for (from, amount) in batch {
+            let result = server.send(ledger::SendArgs {
+                from: Some(*from),
+                to: destination,
+                symbol,
+                amount: amount.clone(),
+            });
+            match result {
+                Ok(()) => {
+                    summary.swept += 1;
+                    summary.total += amount.clone();
+                }
+                Err(e) => summary.failed.push((*from, amount.clone(), e.to_string())),
+            }
+        }
+        let sent = summary.swept + summary.failed.len();
+        writeln!(
+            out,
+            "Batch {}: {} of {} sent, {} swept so far.",
+            i + 1,
+            sent,
+            balances.len(),
+            summary.total
+        )?;
+        progress.emit(Event::SweepBatch {
+            batch: i + 1,
+            sent,
+            total: balances.len(),
+            swept: summary.total.to_string(),
+        });
+    }
+    Ok(summary)
+}
+
+fn local_names(client: &ManyClient<impl Identity>) -> Result<BTreeMap<Symbol, String>, ManyError> {
+    let info: ledger::InfoReturns =
+        minicbor::decode(&crate::transport::call_(client, "ledger.info", ())?)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    Ok(info.local_names)
+}
+
+/// Print the nonzero balances of a range of subresources, and their totals.
+pub fn balance<S: SubresourceServer>(
+    client: ManyClient<impl Identity>,
+    connect: impl Fn() -> Result<S, ManyError> + Sync,
+    account: Address,
+    symbols: Vec<String>,
+    range: SubresourceRange,
+    limits: RangeLimitsOpt,
+) -> Result<(), ManyError> {
+    let local_names = local_names(&client)?;
+    let symbols = if symbols.is_empty() {
+        None
+    } else {
+        Some(
+            symbols
+                .into_iter()
+                .map(|s| resolve_symbol(&client, s))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+
+    let addresses = derive(&account, range, limits.max_subresources)?;
+    let balances = balances(connect, &addresses, symbols, limits.concurrency)?;
+
+    let mut totals: BTreeMap<Symbol, TokenAmount> = BTreeMap::new();
+    for (address, balances) in addresses.iter().zip(balances) {
+        for (symbol, amount) in balances.into_iter().filter(|(_, a)| !a.is_zero()) {
+            let name = local_names.get(&symbol).cloned().unwrap_or_default();
+            println!("{} {:>12} {}", address, amount, name);
+            *totals.entry(symbol).or_insert_with(TokenAmount::zero) += amount;
+        }
+    }
+    for (symbol, amount) in totals {
+        match local_names.get(&symbol) {
+            Some(name) => println!("Total {:>12} {} ({})", amount, name, symbol),
+            None => println!("Total {:>12} {}", amount, symbol),
+        }
+    }
+    Ok(())
+}
+
+/// Send the full balance of a symbol of each subresource in a range to a
+/// destination.
+pub fn sweep<S: SubresourceServer>(
+    client: ManyClient<impl Identity>,
+    connect: impl Fn() -> Result<S, ManyError> + Sync,
+    caller: Address,
+    opts: SweepOpt,
+) -> Result<(), ManyError> {
+    let SweepOpt {
+        account,
+        subresource_range,
+        batch_size,
+        limits,
+        destination,
+        symbol,
+    } = opts;
+    let account = account.unwrap_or(caller);
+    let symbol = resolve_symbol(&client, symbol)?;
+
+    let addresses = derive(&account, subresource_range, limits.max_subresources)?;
+    let balances = balances(connect, &addresses, Some(vec![symbol]), limits.concurrency)?;
+    let balances = addresses
+        .into_iter()
+        .zip(balances)
+        .map(|(address, mut b)| (address, b.remove(&symbol).unwrap_or_else(TokenAmount::zero)))
+        .collect();
+
+    let mut out = std::io::stdout();
+    let summary = sweep_balances(
+        &client,
+        balances,
+        destination,
+        symbol,
+        batch_size,
+        &mut out,
+        progress::get(),
+    )
+    .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+    println!(
+        "Swept {} {} from {} subresources to {}.",
+        summary.total, symbol, summary.swept, destination
+    );
+    if !summary.failed.is_empty() {
+        println!("{} sends failed:", summary.failed.len());
+        for (from, amount, error) in &summary.failed {
+            println!("  {} ({}): {}", from, amount, error);
+        }
+        return Err(ManyError::unknown(format!(
+            "{} sends failed.",
+            summary.failed.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::testing::*;
+    use std::sync::Arc;
+
+    fn account() -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap()
+    }
+
+    fn destination() -> Address {
+        Address::from_str("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp").unwrap()
+    }
+
+    fn symbol() -> Symbol {
+        account().with_subresource_id(1_000_000).unwrap()
+    }
+
+    /// A server with balances of a single symbol, which records sends.
+    #[derive(Clone, Default)]
+    struct MockServer {
+        balances: BTreeMap<Address, TokenAmount>,
+        failing: Vec<Address>,
+        sent: Arc<Mutex<Vec<ledger::SendArgs>>>,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl SubresourceServer for MockServer {
+        fn balance(
+            &self,
+            account: Address,
+            _symbols: Option<Vec<Symbol>>,
+        ) -> Result<BTreeMap<Symbol, TokenAmount>, ManyError> {
+            let n = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(n, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            Ok(self
+                .balances
+                .get(&account)
+                .map(|b| BTreeMap::from([(symbol(), b.clone())]))
+                .unwrap_or_default())
+        }
+
+        fn send(&self, args: ledger::SendArgs) -> Result<(), ManyError> {
+            if self.failing.contains(&args.from.unwrap()) {
+                return Err(ManyError::unknown("Nope"));
+            }
+            self.sent.lock().unwrap().push(args);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(
+            SubresourceRange::from_str("3..7"),
+            Ok(SubresourceRange { start: 3, end: 7 })
+        );
+        assert_eq!(SubresourceRange::from_str("3..7").unwrap().len(), 5);
+        assert_eq!(SubresourceRange::from_str("4..4").unwrap().len(), 1);
+        assert!(SubresourceRange::from_str("7..3").is_err());
+        assert!(SubresourceRange::from_str("7").is_err());
+        assert!(SubresourceRange::from_str("a..3").is_err());
+    }
+
+    #[test]
+    fn derivation() {
+        let range = SubresourceRange::from_str("0..9").unwrap();
+        let addresses = derive(&account(), range, 10).unwrap();
+        assert_eq!(addresses.len(), 10);
+        for (id, address) in addresses.iter().enumerate() {
+            assert_eq!(address.subresource_id(), Some(id as u32));
+            assert_eq!(address, &account().with_subresource_id(id as u32).unwrap());
+        }
+
+        // The range is capped.
+        assert!(derive(&account(), range, 9).is_err());
+        // Subresource IDs are 31 bits.
+        let range = SubresourceRange::from_str("2147483647..2147483648").unwrap();
+        assert!(derive(&account(), range, 10).is_err());
+    }
+
+    #[test]
+    fn bounded_concurrency() {
+        let addresses = derive(&account(), SubresourceRange { start: 0, end: 39 }, 100).unwrap();
+        let server = MockServer {
+            balances: BTreeMap::from([(addresses[7], TokenAmount::from(7u64))]),
+            ..Default::default()
+        };
+
+        let balances = balances(|| Ok(server.clone()), &addresses, None, 3).unwrap();
+        assert_eq!(balances.len(), 40);
+        assert_eq!(balances[7].get(&symbol()), Some(&TokenAmount::from(7u64)));
+        assert!(balances[8].is_empty());
+        assert!(server.max_in_flight.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn sweep_summary() {
+        let addresses = derive(&account(), SubresourceRange { start: 0, end: 4 }, 100).unwrap();
+        let server = MockServer {
+            failing: vec![addresses[3]],
+            ..Default::default()
+        };
+        let balances = vec![
+            (addresses[0], TokenAmount::from(10u64)),
+            (addresses[1], TokenAmount::zero()),
+            (addresses[2], TokenAmount::from(25u64)),
+            (addresses[3], TokenAmount::from(100u64)),
+            (addresses[4], TokenAmount::from(5u64)),
+            (destination(), TokenAmount::from(1000u64)),
+        ];
+
+        let mut out = Vec::new();
+        let events = Buffer::default();
+        let summary = sweep_balances(
+            &server,
+            balances,
+            destination(),
+            symbol(),
+            2,
+            &mut out,
+            &Progress::json(events.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(summary.swept, 3);
+        assert_eq!(summary.total, TokenAmount::from(40u64));
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, addresses[3]);
+        assert_eq!(summary.failed[0].1, TokenAmount::from(100u64));
+
+        let sent = server.sent.lock().unwrap();
+        assert_eq!(
+            sent.iter().map(|s| s.from.unwrap()).collect::<Vec<_>>(),
+            vec![addresses[0], addresses[2], addresses[4]]
+        );
+        assert!(sent.iter().all(|s| s.to == destination()));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Batch 1: 2 of 4 sent, 35 swept so far.\n\
+             Batch 2: 4 of 4 sent, 40 swept so far.\n"
+        );
+
+        let events = events.lines();
+        assert_eq!(types(&events), ["sweep_batch", "sweep_batch"]);
+        assert_eq!(
+            events[1],
+            serde_json::json!({
+                "seq": 1,
+                "type": "sweep_batch",
+                "batch": 2,
+                "sent": 4,
+                "total": 4,
+                "swept": "40",
+            })
+        );
+    }
+}
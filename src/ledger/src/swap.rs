@@ -0,0 +1,269 @@
+use crate::amount::AmountArg;
+use crate::transport::ManyClient;
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use tracing::info;
+
+// These types mirror the ones of the many-ledger server.
+
+#[derive(Debug, Decode)]
+#[cbor(index_only)]
+enum SwapState {
+    #[n(0)]
+    Pending,
+    #[n(1)]
+    Accepted,
+    #[n(2)]
+    Cancelled,
+    #[n(3)]
+    Expired,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct SwapInfo {
+    #[n(0)]
+    maker: Address,
+
+    #[n(1)]
+    counterparty: Option<Address>,
+
+    #[n(2)]
+    symbol: Symbol,
+
+    #[n(3)]
+    amount: TokenAmount,
+
+    #[n(4)]
+    want_symbol: Symbol,
+
+    #[n(5)]
+    want_amount: TokenAmount,
+
+    #[n(6)]
+    expiry: Timestamp,
+
+    #[n(7)]
+    state: SwapState,
+
+    #[n(8)]
+    taker: Option<Address>,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct SwapCreateArgs {
+    #[n(0)]
+    from: Option<Address>,
+
+    #[n(1)]
+    symbol: Symbol,
+
+    #[n(2)]
+    amount: TokenAmount,
+
+    #[n(3)]
+    want_symbol: Symbol,
+
+    #[n(4)]
+    want_amount: TokenAmount,
+
+    #[n(5)]
+    counterparty: Option<Address>,
+
+    #[n(6)]
+    expires_in_secs: u64,
+}
+
+#[derive(Decode)]
+#[cbor(map)]
+struct SwapCreateReturns {
+    #[n(0)]
+    swap: Address,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct SwapAcceptArgs {
+    #[n(0)]
+    swap: Address,
+
+    #[n(1)]
+    from: Option<Address>,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+struct SwapArgs {
+    #[n(0)]
+    swap: Address,
+}
+
+#[derive(Parser)]
+pub struct CommandOpt {
+    #[clap(subcommand)]
+    /// Swap subcommand to execute.
+    subcommand: SubcommandOpt,
+}
+
+#[derive(Parser)]
+enum SubcommandOpt {
+    /// Lock tokens in a new swap, in exchange for tokens of another symbol.
+    Create(CreateOpt),
+
+    /// Accept a swap, exchanging the wanted tokens for the locked ones.
+    Accept(AcceptOpt),
+
+    /// Cancel a pending swap, refunding its tokens to its maker.
+    Cancel(SwapOpt),
+
+    /// Show the information of a swap.
+    Info(SwapOpt),
+}
+
+#[derive(Parser)]
+struct CreateOpt {
+    /// The maker account, if different than the one provided by the
+    /// PEM argument.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    account: Option<Address>,
+
+    /// The only identity that can accept the swap.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    counterparty: Option<Address>,
+
+    /// The time after which the swap is refunded to the maker.
+    #[clap(long)]
+    expiry: humantime::Duration,
+
+    /// The amount of tokens to lock.
+    amount: AmountArg,
+
+    /// The symbol of the tokens to lock. This can either be an identity or
+    /// a local name for a symbol.
+    symbol: String,
+
+    /// The amount of tokens wanted in exchange.
+    want_amount: AmountArg,
+
+    /// The symbol of the tokens wanted in exchange.
+    want_symbol: String,
+}
+
+#[derive(Parser)]
+struct AcceptOpt {
+    /// The taker account, if different than the one provided by the
+    /// PEM argument.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    account: Option<Address>,
+
+    /// The swap address, obtained when creating the swap.
+    swap: Address,
+}
+
+#[derive(Parser)]
+struct SwapOpt {
+    /// The swap address, obtained when creating the swap.
+    swap: Address,
+}
+
+fn create(client: ManyClient<impl Identity>, opts: CreateOpt) -> Result<(), ManyError> {
+    let CreateOpt {
+        account,
+        counterparty,
+        expiry,
+        amount,
+        symbol,
+        want_amount,
+        want_symbol,
+    } = opts;
+    let amount = amount.base_units()?;
+    let want_amount = want_amount.base_units()?;
+    let symbol = crate::resolve_symbol(&client, symbol)?;
+    let want_symbol = crate::resolve_symbol(&client, want_symbol)?;
+
+    let arguments = SwapCreateArgs {
+        from: account,
+        symbol,
+        amount: TokenAmount::from(amount),
+        want_symbol,
+        want_amount: TokenAmount::from(want_amount),
+        counterparty,
+        expires_in_secs: expiry.as_secs(),
+    };
+    let response = crate::transport::call(&client, "ledger.swapCreate", arguments)?;
+
+    let payload = crate::wait_response(&client, response)?;
+    let result: SwapCreateReturns =
+        minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    info!("Swap: {}", result.swap);
+    println!("{}", result.swap);
+    Ok(())
+}
+
+fn accept(client: ManyClient<impl Identity>, opts: AcceptOpt) -> Result<(), ManyError> {
+    let arguments = SwapAcceptArgs {
+        swap: opts.swap,
+        from: opts.account,
+    };
+    let response = crate::transport::call(&client, "ledger.swapAccept", arguments)?;
+    crate::wait_response(&client, response)?;
+
+    info!("Accepted.");
+    Ok(())
+}
+
+fn cancel(client: ManyClient<impl Identity>, opts: SwapOpt) -> Result<(), ManyError> {
+    let arguments = SwapArgs { swap: opts.swap };
+    let response = crate::transport::call(&client, "ledger.swapCancel", arguments)?;
+    crate::wait_response(&client, response)?;
+
+    info!("Cancelled.");
+    Ok(())
+}
+
+fn info(client: ManyClient<impl Identity>, opts: SwapOpt) -> Result<(), ManyError> {
+    let arguments = SwapArgs { swap: opts.swap };
+    let payload = crate::transport::call_(&client, "ledger.swapInfo", arguments)?;
+    let SwapInfo {
+        maker,
+        counterparty,
+        symbol,
+        amount,
+        want_symbol,
+        want_amount,
+        expiry,
+        state,
+        taker,
+    } = minicbor::decode(&payload).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    println!("State:        {:?}", state);
+    println!("Maker:        {}", maker);
+    if let Some(counterparty) = counterparty {
+        println!("Counterparty: {}", counterparty);
+    }
+    if let Some(taker) = taker {
+        println!("Taker:        {}", taker);
+    }
+    println!("Locked:       {} {}", amount, symbol);
+    println!("Wanted:       {} {}", want_amount, want_symbol);
+    println!(
+        "Expiry:       {}",
+        humantime::format_rfc3339_seconds(expiry.as_system_time()?)
+    );
+    Ok(())
+}
+
+pub fn swap(client: ManyClient<impl Identity>, opts: CommandOpt) -> Result<(), ManyError> {
+    match opts.subcommand {
+        SubcommandOpt::Create(sub_opts) => create(client, sub_opts),
+        SubcommandOpt::Accept(sub_opts) => accept(client, sub_opts),
+        SubcommandOpt::Cancel(sub_opts) => cancel(client, sub_opts),
+        SubcommandOpt::Info(sub_opts) => info(client, sub_opts),
+    }
+}
@@ -0,0 +1,628 @@
+use crate::amount::AmountArg;
+use crate::transport::ManyClient;
+use crate::{attachment, config, TargetCommandOpt};
+use clap::Parser;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// The version of the templates written by this client. Templates of newer
+/// versions are refused, as they can hold fields this client would ignore.
+pub const TEMPLATE_VERSION: u32 = 1;
+
+/// The amount of templates saved without `--amount`, filled by the
+/// `--amount` of `template run`.
+const DEFAULT_AMOUNT: &str = "{amount}";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TemplateKind {
+    Send,
+    MultisigSubmit,
+}
+
+/// A prepared transfer, whose amount and memo can have placeholders like
+/// `{amount}` or `{month}`. Literal braces are written `{{` and `}}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub version: u32,
+    pub kind: TemplateKind,
+
+    /// The ledger the template was saved for. Addresses are kept as
+    /// strings, like in the configuration file.
+    pub server_id: String,
+
+    /// The multisig account submitting the transfer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig_account: Option<String>,
+
+    /// The source account, if not the caller or the multisig account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+
+    pub to: String,
+    pub symbol: String,
+    pub amount: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+impl Template {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let Version { version } = toml::from_str(content).map_err(|e| e.to_string())?;
+        if version > TEMPLATE_VERSION {
+            return Err(format!(
+                "The template has version {}, this client supports up to version {}.",
+                version, TEMPLATE_VERSION
+            ));
+        }
+        let template: Self = toml::from_str(content).map_err(|e| e.to_string())?;
+        template.addresses()?;
+        Ok(template)
+    }
+
+    /// The multisig account, the source and the destination of the
+    /// transfer.
+    pub fn addresses(&self) -> Result<(Option<Address>, Option<Address>, Address), String> {
+        let parse = |field: &str, s: &str| {
+            Address::from_str(s).map_err(|e| format!("Invalid {} '{}': {}", field, s, e))
+        };
+        Ok((
+            self.multisig_account
+                .as_deref()
+                .map(|s| parse("multisig_account", s))
+                .transpose()?,
+            self.from.as_deref().map(|s| parse("from", s)).transpose()?,
+            parse("to", &self.to)?,
+        ))
+    }
+
+    /// The placeholders of the amount and the memo.
+    pub fn placeholders(&self) -> Result<BTreeSet<String>, String> {
+        let mut names = placeholders(&self.amount)?;
+        if let Some(memo) = &self.memo {
+            names.extend(placeholders(memo)?);
+        }
+        Ok(names)
+    }
+}
+
+/// A template whose placeholders were filled.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Filled {
+    pub amount: String,
+    pub memo: Option<String>,
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a pattern into its text and its placeholders.
+fn parse_pattern(pattern: &str) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut rest = pattern;
+    while let Some(i) = rest.find(&['{', '}'][..]) {
+        let (text, tail) = rest.split_at(i);
+        parts.push(Part::Text(text));
+        if tail.starts_with("{{") {
+            parts.push(Part::Text("{"));
+            rest = &tail[2..];
+        } else if tail.starts_with("}}") {
+            parts.push(Part::Text("}"));
+            rest = &tail[2..];
+        } else if tail.starts_with('}') {
+            return Err(format!("Unmatched '}}' in '{}'.", pattern));
+        } else {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in '{}'.", pattern))?;
+            let name = &tail[1..end];
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("Invalid placeholder '{{{}}}'.", name));
+            }
+            parts.push(Part::Placeholder(name));
+            rest = &tail[end + 1..];
+        }
+    }
+    parts.push(Part::Text(rest));
+    Ok(parts)
+}
+
+pub fn placeholders(pattern: &str) -> Result<BTreeSet<String>, String> {
+    Ok(parse_pattern(pattern)?
+        .into_iter()
+        .filter_map(|part| match part {
+            Part::Placeholder(name) => Some(name.to_string()),
+            Part::Text(_) => None,
+        })
+        .collect())
+}
+
+/// Replace the placeholders of a pattern by their values. Every placeholder
+/// needs a value.
+pub fn fill(pattern: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    parse_pattern(pattern)?
+        .into_iter()
+        .map(|part| match part {
+            Part::Text(text) => Ok(text),
+            Part::Placeholder(name) => vars
+                .get(name)
+                .map(String::as_str)
+                .ok_or_else(|| format!("No value for the placeholder '{{{}}}'.", name)),
+        })
+        .collect()
+}
+
+/// Fill the amount and the memo of a template. Variables which are not
+/// placeholders of the template are an error, as they are likely typos.
+pub fn fill_template(
+    template: &Template,
+    vars: &BTreeMap<String, String>,
+) -> Result<Filled, String> {
+    let names = template.placeholders()?;
+    let unused: Vec<&str> = vars
+        .keys()
+        .filter(|name| !names.contains(*name))
+        .map(String::as_str)
+        .collect();
+    if !unused.is_empty() {
+        return Err(format!(
+            "The template has no placeholder {}.",
+            unused.join(", ")
+        ));
+    }
+    Ok(Filled {
+        amount: fill(&template.amount, vars)?,
+        memo: template
+            .memo
+            .as_deref()
+            .map(|memo| fill(memo, vars))
+            .transpose()?,
+    })
+}
+
+/// The directory of the templates, next to the default configuration file.
+pub fn default_dir(env: &dyn Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    Some(config::default_path(env)?.parent()?.join("templates"))
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid template name '{}': only letters, digits, '-' and '_' are allowed.",
+            name
+        ))
+    }
+}
+
+fn template_path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    check_name(name)?;
+    Ok(dir.join(format!("{}.toml", name)))
+}
+
+pub fn save(dir: &Path, name: &str, template: &Template, force: bool) -> Result<(), String> {
+    let path = template_path(dir, name)?;
+    if path.exists() && !force {
+        return Err(format!(
+            "The template '{}' already exists, use --force to replace it.",
+            name
+        ));
+    }
+    let content = toml::to_string(template).map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    std::fs::write(&path, content).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+pub fn load(dir: &Path, name: &str) -> Result<Template, String> {
+    let path = template_path(dir, name)?;
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read the template '{}': {}", name, e))?;
+    Template::parse(&content).map_err(|e| format!("Invalid template {}: {}", path.display(), e))
+}
+
+/// The names of the saved templates, in order.
+pub fn list(dir: &Path) -> Result<Vec<String>, String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("Could not read {}: {}", dir.display(), e)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid variable '{}', expected key=value.", s))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+#[derive(Parser)]
+pub struct CommandOpt {
+    /// The directory of the templates. Defaults to the `templates` directory
+    /// next to the default configuration file.
+    #[clap(long)]
+    dir: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    subcommand: SubcommandOpt,
+}
+
+#[derive(Parser)]
+enum SubcommandOpt {
+    /// Save a transfer as a template.
+    Save(SaveOpt),
+
+    /// List the saved templates.
+    List,
+
+    /// Fill the placeholders of a template and send its transfer.
+    Run(RunOpt),
+}
+
+#[derive(Parser)]
+struct SaveOpt {
+    /// The name of the template.
+    name: String,
+
+    /// Replace the template if it exists.
+    #[clap(long)]
+    force: bool,
+
+    #[clap(subcommand)]
+    kind: SaveKindOpt,
+}
+
+#[derive(Parser)]
+struct TransferOpt {
+    /// The source account, if different than the caller, or the multisig
+    /// account for a submission.
+    #[clap(long, parse(try_from_str = crate::alias::address))]
+    from: Option<Address>,
+
+    /// The destination.
+    #[clap(parse(try_from_str = crate::alias::address))]
+    to: Address,
+
+    /// The symbol, as an address or a local name.
+    symbol: String,
+
+    /// The amount, which can have placeholders. Defaults to `{amount}`,
+    /// given with `template run --amount`.
+    #[clap(long, default_value = DEFAULT_AMOUNT)]
+    amount: String,
+
+    /// The memo of the transfer, which can have placeholders.
+    #[clap(long)]
+    memo: Option<String>,
+}
+
+#[derive(Parser)]
+enum SaveKindOpt {
+    /// A transfer sent directly.
+    Send(TransferOpt),
+
+    /// A transfer submitted to a multisig account.
+    MultisigSubmit {
+        /// The multisig account submitting the transfer.
+        #[clap(parse(try_from_str = crate::alias::address))]
+        account: Address,
+
+        #[clap(flatten)]
+        transfer: TransferOpt,
+    },
+}
+
+#[derive(Parser)]
+struct RunOpt {
+    /// The name of the template.
+    name: String,
+
+    /// The value of the `{amount}` placeholder.
+    #[clap(long)]
+    amount: Option<String>,
+
+    /// The value of a placeholder, as `key=value`. Can be given multiple
+    /// times.
+    #[clap(long = "var", parse(try_from_str = parse_var))]
+    vars: Vec<(String, String)>,
+
+    /// Print the transfer instead of sending it. Only the symbol is resolved
+    /// with the server.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+fn template_of(server_id: Address, kind: SaveKindOpt) -> Result<Template, String> {
+    let (kind, multisig_account, transfer) = match kind {
+        SaveKindOpt::Send(transfer) => (TemplateKind::Send, None, transfer),
+        SaveKindOpt::MultisigSubmit { account, transfer } => {
+            (TemplateKind::MultisigSubmit, Some(account), transfer)
+        }
+    };
+    let template = Template {
+        version: TEMPLATE_VERSION,
+        kind,
+        server_id: server_id.to_string(),
+        multisig_account: multisig_account.map(|a| a.to_string()),
+        from: transfer.from.map(|a| a.to_string()),
+        to: transfer.to.to_string(),
+        symbol: transfer.symbol,
+        amount: transfer.amount,
+        memo: transfer.memo,
+    };
+    // Refuse malformed patterns now rather than when running the template.
+    template.placeholders()?;
+    Ok(template)
+}
+
+fn describe(name: &str, template: &Template) -> String {
+    let kind = match template.kind {
+        TemplateKind::Send => "send".to_string(),
+        TemplateKind::MultisigSubmit => format!(
+            "multisig-submit {}",
+            template.multisig_account.as_deref().unwrap_or_default()
+        ),
+    };
+    format!(
+        "{}: {} {} {} to {}",
+        name, kind, template.amount, template.symbol, template.to
+    )
+}
+
+fn run(
+    client: ManyClient<impl Identity>,
+    caller: Address,
+    server_id: Address,
+    dir: &Path,
+    opts: RunOpt,
+) -> Result<(), ManyError> {
+    let RunOpt {
+        name,
+        amount,
+        vars,
+        dry_run,
+    } = opts;
+    let template = load(dir, &name).map_err(ManyError::unknown)?;
+    let (multisig_account, from, to) = template.addresses().map_err(ManyError::unknown)?;
+    if Address::from_str(&template.server_id).ok() != Some(server_id) {
+        warn!(
+            "The template '{}' was saved for the server {}, not {}.",
+            name, template.server_id, server_id
+        );
+    }
+
+    let mut vars: BTreeMap<String, String> = vars.into_iter().collect();
+    if let Some(amount) = amount {
+        vars.insert("amount".to_string(), amount);
+    }
+    let filled = fill_template(&template, &vars).map_err(ManyError::unknown)?;
+    let amount = AmountArg::from_str(&filled.amount).unwrap();
+    let base_units = amount.base_units()?;
+
+    if dry_run {
+        let symbol = crate::resolve_symbol(&client, template.symbol.clone())?;
+        println!("{}", describe(&name, &template));
+        if let Some(account) = multisig_account {
+            println!("Multisig:  {}", account);
+        }
+        println!("From:      {}", from.or(multisig_account).unwrap_or(caller));
+        println!("To:        {}", to);
+        println!("Amount:    {} {} ({})", base_units, template.symbol, symbol);
+        if let Some(memo) = &filled.memo {
+            println!("Memo:      {}", memo);
+        }
+        return Ok(());
+    }
+
+    let target = TargetCommandOpt {
+        account: from,
+        identity: to,
+        amount,
+        decimal: false,
+        symbol: template.symbol,
+        offline: false,
+        self_transfer: false,
+        force: false,
+        memo: filled.memo,
+        dry_run: false,
+        attach: attachment::AttachOpt::default(),
+        split: crate::split::SplitOpt::default(),
+    };
+    match (template.kind, multisig_account) {
+        (TemplateKind::Send, _) => crate::send(
+            client,
+            caller,
+            target.account.unwrap_or(caller),
+            target.identity,
+            base_units,
+            target.symbol,
+            target.offline,
+            target.self_transfer,
+            target.force,
+            target.memo,
+            target.dry_run,
+            target.attach,
+            target.split,
+        ),
+        (TemplateKind::MultisigSubmit, Some(account)) => {
+            crate::multisig::submit_send(client, caller, account, Default::default(), target)
+        }
+        (TemplateKind::MultisigSubmit, None) => {
+            Err(ManyError::unknown("The template has no multisig account."))
+        }
+    }
+}
+
+pub fn template(
+    client: ManyClient<impl Identity>,
+    caller: Address,
+    server_id: Address,
+    opts: CommandOpt,
+) -> Result<(), ManyError> {
+    let dir = opts
+        .dir
+        .or_else(|| default_dir(&|name| std::env::var(name).ok()))
+        .ok_or_else(|| ManyError::unknown("No directory for the templates, use --dir."))?;
+
+    match opts.subcommand {
+        SubcommandOpt::Save(SaveOpt { name, force, kind }) => {
+            let template = template_of(server_id, kind).map_err(ManyError::unknown)?;
+            save(&dir, &name, &template, force).map_err(ManyError::unknown)?;
+            info!("Saved the template '{}'.", name);
+            Ok(())
+        }
+        SubcommandOpt::List => {
+            for name in list(&dir).map_err(ManyError::unknown)? {
+                match load(&dir, &name) {
+                    Ok(template) => println!("{}", describe(&name, &template)),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            Ok(())
+        }
+        SubcommandOpt::Run(opts) => run(client, caller, server_id, &dir, opts),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(i: u32) -> Address {
+        Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow")
+            .unwrap()
+            .with_subresource_id(i)
+            .unwrap()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn template() -> Template {
+        Template {
+            version: TEMPLATE_VERSION,
+            kind: TemplateKind::MultisigSubmit,
+            server_id: address(0).to_string(),
+            multisig_account: Some(address(1).to_string()),
+            from: None,
+            to: address(2).to_string(),
+            symbol: "MFX".to_string(),
+            amount: DEFAULT_AMOUNT.to_string(),
+            memo: Some("Rent {month} {{flat {unit}}}".to_string()),
+        }
+    }
+
+    #[test]
+    fn substitution() {
+        let values = vars(&[("amount", "1000"), ("month", "2022-09"), ("unit", "4B")]);
+        assert_eq!(fill("{amount}", &values).unwrap(), "1000");
+        assert_eq!(fill("no placeholder", &values).unwrap(), "no placeholder");
+        assert_eq!(fill("{month}-{month}", &values).unwrap(), "2022-09-2022-09");
+        assert_eq!(fill("{{amount}}", &values).unwrap(), "{amount}");
+
+        assert!(fill("{year}", &values).unwrap_err().contains("{year}"));
+        assert!(fill("{amount", &values).is_err());
+        assert!(fill("amount}", &values).is_err());
+        assert!(fill("{}", &values).is_err());
+        assert!(fill("{a b}", &values).is_err());
+
+        assert_eq!(
+            fill_template(&template(), &values).unwrap(),
+            Filled {
+                amount: "1000".to_string(),
+                memo: Some("Rent 2022-09 {flat 4B}".to_string()),
+            }
+        );
+        assert_eq!(
+            template().placeholders().unwrap(),
+            BTreeSet::from([
+                "amount".to_string(),
+                "month".to_string(),
+                "unit".to_string()
+            ])
+        );
+
+        // A variable which is not a placeholder is likely a typo.
+        let err = fill_template(
+            &template(),
+            &vars(&[
+                ("amount", "1000"),
+                ("month", "2022-09"),
+                ("unit", "4B"),
+                ("mnth", "x"),
+            ]),
+        )
+        .unwrap_err();
+        assert!(err.contains("mnth"), "{}", err);
+    }
+
+    #[test]
+    fn versions() {
+        let content = toml::to_string(&template()).unwrap();
+        assert!(content.starts_with("version = 1\n"), "{}", content);
+        assert_eq!(Template::parse(&content).unwrap(), template());
+
+        let newer = content.replace("version = 1", "version = 2");
+        let err = Template::parse(&newer).unwrap_err();
+        assert!(err.contains("version 2"), "{}", err);
+
+        let unknown = format!("{}fee = \"1\"\n", content);
+        assert!(Template::parse(&unknown).is_err());
+
+        let unversioned = content.replace("version = 1\n", "");
+        assert!(Template::parse(&unversioned).is_err());
+
+        let invalid = content.replace(&address(2).to_string(), "nope");
+        assert!(Template::parse(&invalid).unwrap_err().contains("'nope'"));
+    }
+
+    #[test]
+    fn save_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join("templates");
+        assert_eq!(list(&dir).unwrap(), Vec::<String>::new());
+
+        save(&dir, "rent", &template(), false).unwrap();
+        save(&dir, "a-salary_2", &template(), false).unwrap();
+        assert!(save(&dir, "rent", &template(), false).is_err());
+        save(&dir, "rent", &template(), true).unwrap();
+        assert!(save(&dir, "../rent", &template(), false).is_err());
+
+        assert_eq!(list(&dir).unwrap(), vec!["a-salary_2", "rent"]);
+        assert_eq!(load(&dir, "rent").unwrap(), template());
+        assert!(load(&dir, "missing").is_err());
+    }
+}
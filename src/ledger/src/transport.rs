@@ -0,0 +1,331 @@
+use coset::{CborSerializable, CoseSign1};
+use many_error::ManyError;
+use many_identity::verifiers::AnonymousVerifier;
+use many_identity::{Address, Identity};
+use many_identity_dsa::CoseKeyVerifier;
+use many_protocol::{
+    decode_response_from_cose_sign1, encode_cose_sign1_from_request, RequestMessage,
+    ResponseMessage,
+};
+use minicbor::Encode;
+use once_cell::sync::OnceCell;
+use reqwest::blocking::Client;
+use reqwest::{IntoUrl, Url};
+use std::time::Duration;
+use tracing::debug;
+
+/// The methods which only read, so they can be sent again after a failure.
+/// Commands are never retried, as the first attempt may have been executed.
+const QUERIES: &[&str] = &[
+    "status",
+    "endpoints",
+    "async.status",
+    "account.info",
+    "account.multisigInfo",
+    "events.list",
+    "governance.params",
+    "kvstore.get",
+    "ledger.attachment",
+    "ledger.balance",
+    "ledger.checkReceive",
+    "ledger.escrowInfo",
+    "ledger.info",
+    "ledger.replayProtection",
+    "ledger.stats",
+    "ledger.swapInfo",
+    "tokens.info",
+];
+
+/// The delay before the first retry, doubled for each of the next ones.
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The longest delay between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How the calls of the server are made.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Transport {
+    /// How long a call can take, if limited.
+    pub timeout: Option<Duration>,
+
+    /// How many times a query is sent again after a transport error.
+    pub retries: u32,
+}
+
+static TRANSPORT: OnceCell<Transport> = OnceCell::new();
+
+/// Set the timeout and the retries of all calls. Can only be called once.
+pub fn init(transport: Transport) {
+    if TRANSPORT.set(transport).is_err() {
+        panic!("The transport was already set.");
+    }
+}
+
+fn transport() -> Transport {
+    TRANSPORT.get().copied().unwrap_or_default()
+}
+
+fn is_query(method: &str) -> bool {
+    QUERIES.contains(&method)
+}
+
+/// Whether the call failed before reaching the server, or without an answer
+/// from it. Errors returned by the server are not retried.
+fn is_transport_error(e: &ManyError) -> bool {
+    e.code() == ManyError::unexpected_transport_error("").code()
+}
+
+/// The delay before a retry, from 1.
+fn backoff(attempt: u32) -> Duration {
+    FIRST_BACKOFF
+        .checked_mul(1 << attempt.saturating_sub(1).min(16))
+        .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+}
+
+fn timeout_message(what: &str, timeout: Duration) -> String {
+    format!(
+        "Timed out waiting for the server to answer {}, after the {} of --timeout.",
+        what,
+        humantime::format_duration(timeout)
+    )
+}
+
+/// A transport error for a failed HTTP request, so queries are retried
+/// after it, including when it timed out.
+fn http_error(what: &str, timeout: Option<Duration>, e: reqwest::Error) -> ManyError {
+    match timeout {
+        Some(timeout) if e.is_timeout() => {
+            ManyError::unexpected_transport_error(timeout_message(what, timeout))
+        }
+        _ => ManyError::unexpected_transport_error(e.to_string()),
+    }
+}
+
+/// A client of a MANY server, which signs its messages with an identity.
+/// Its requests time out after the timeout of the transport, if any.
+#[derive(Clone)]
+pub struct ManyClient<I: Identity> {
+    http: Client,
+    timeout: Option<Duration>,
+    url: Url,
+    to: Address,
+    identity: I,
+}
+
+impl<I: Identity> ManyClient<I> {
+    /// A client of the server at `url`, whose responses are signed by `to`
+    /// unless it is anonymous.
+    pub fn new<S: IntoUrl>(url: S, to: Address, identity: I) -> Result<Self, String> {
+        Self::with_timeout(url, to, identity, transport().timeout)
+    }
+
+    fn with_timeout<S: IntoUrl>(
+        url: S,
+        to: Address,
+        identity: I,
+        timeout: Option<Duration>,
+    ) -> Result<Self, String> {
+        let mut builder = Client::builder();
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(Self {
+            http: builder.build().map_err(|e| e.to_string())?,
+            timeout,
+            url: url.into_url().map_err(|e| e.to_string())?,
+            to,
+            identity,
+        })
+    }
+
+    /// Send an envelope as is, and return the envelope of the response.
+    fn send_envelope(&self, what: &str, envelope: CoseSign1) -> Result<CoseSign1, ManyError> {
+        let bytes = envelope
+            .to_vec()
+            .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+        let body = self
+            .http
+            .post(self.url.clone())
+            .body(bytes)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.bytes())
+            .map_err(|e| http_error(what, self.timeout, e))?;
+        CoseSign1::from_slice(&body).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    /// Sign a message, send it and return its verified response.
+    fn send_message(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let method = message.method.clone();
+        let envelope = encode_cose_sign1_from_request(message, &self.identity)
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let response = self.send_envelope(&method, envelope)?;
+        let to = (!self.to.is_anonymous()).then_some(self.to);
+        decode_response_from_cose_sign1(&response, to, &(AnonymousVerifier, CoseKeyVerifier))
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    fn call<A: Encode<()>>(
+        &self,
+        method: &str,
+        arguments: A,
+    ) -> Result<ResponseMessage, ManyError> {
+        let message =
+            crate::attachment::request(self.identity.address(), self.to, method, arguments, None)?;
+        self.send_message(message)
+    }
+}
+
+/// Make a call, sending it again after transport errors if it is a query,
+/// waiting longer before each attempt.
+fn with_retries<T>(
+    method: &str,
+    retries: u32,
+    mut call: impl FnMut() -> Result<T, ManyError>,
+) -> Result<T, ManyError> {
+    let retries = if is_query(method) { retries } else { 0 };
+    let mut attempt = 0;
+    loop {
+        match call() {
+            Err(e) if attempt < retries && is_transport_error(&e) => {
+                attempt += 1;
+                let delay = backoff(attempt);
+                debug!(
+                    "{} failed: {}. Retrying in {}, attempt {} of {}.",
+                    method,
+                    e,
+                    humantime::format_duration(delay),
+                    attempt,
+                    retries
+                );
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Call a method of the server.
+pub fn call<A: Encode<()>>(
+    client: &ManyClient<impl Identity>,
+    method: &str,
+    arguments: A,
+) -> Result<ResponseMessage, ManyError> {
+    with_retries(method, transport().retries, || {
+        client.call(method, &arguments)
+    })
+}
+
+/// Call a method of the server, and return the payload of its response.
+pub fn call_<A: Encode<()>>(
+    client: &ManyClient<impl Identity>,
+    method: &str,
+    arguments: A,
+) -> Result<Vec<u8>, ManyError> {
+    with_retries(method, transport().retries, || {
+        client.call(method, &arguments)?.data
+    })
+}
+
+/// Send a message built by the caller. It is not retried.
+pub fn send_message(
+    client: &ManyClient<impl Identity>,
+    message: RequestMessage,
+) -> Result<ResponseMessage, ManyError> {
+    client.send_message(message)
+}
+
+/// Send an envelope signed elsewhere. It is not retried.
+pub fn send_envelope(
+    client: &ManyClient<impl Identity>,
+    envelope: CoseSign1,
+) -> Result<CoseSign1, ManyError> {
+    client.send_envelope("the envelope", envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn backoffs() {
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(2), Duration::from_secs(1));
+        assert_eq!(backoff(4), Duration::from_secs(4));
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn queries() {
+        assert!(is_query("ledger.balance"));
+        assert!(is_query("async.status"));
+        assert!(!is_query("ledger.send"));
+        assert!(!is_query("account.multisigApprove"));
+    }
+
+    #[test]
+    fn retries() {
+        let transport_error = || ManyError::unexpected_transport_error("connection reset");
+        let attempts = &Cell::new(0);
+        let flaky = |failures: u32| {
+            attempts.set(0);
+            move || {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() <= failures {
+                    Err(transport_error())
+                } else {
+                    Ok(attempts.get())
+                }
+            }
+        };
+
+        // No retries by default.
+        assert!(with_retries("ledger.info", 0, flaky(1)).is_err());
+        assert_eq!(attempts.get(), 1);
+
+        assert_eq!(with_retries("ledger.info", 2, flaky(1)).unwrap(), 2);
+        assert!(with_retries("ledger.info", 1, flaky(2)).is_err());
+        assert_eq!(attempts.get(), 2);
+
+        // Commands are never retried.
+        assert!(with_retries("ledger.send", 3, flaky(1)).is_err());
+        assert_eq!(attempts.get(), 1);
+
+        // Errors of the server are not retried.
+        let server_error = || -> Result<(), ManyError> {
+            attempts.set(attempts.get() + 1);
+            Err(ManyError::unknown("unknown symbol"))
+        };
+        attempts.set(0);
+        assert!(with_retries("ledger.info", 3, server_error).is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn timeout() {
+        // A server which accepts connections but never answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let client = ManyClient::with_timeout(
+            url,
+            Address::anonymous(),
+            many_identity::AnonymousIdentity,
+            Some(Duration::from_millis(200)),
+        )
+        .unwrap();
+
+        let e = call_(&client, "ledger.info", ()).unwrap_err();
+        assert!(is_transport_error(&e));
+        assert!(e.to_string().contains("Timed out"), "{}", e);
+        drop(listener);
+    }
+
+    #[test]
+    fn message() {
+        assert_eq!(
+            timeout_message("ledger.info", Duration::from_secs(30)),
+            "Timed out waiting for the server to answer ledger.info, after the 30s of --timeout."
+        );
+    }
+}
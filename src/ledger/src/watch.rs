@@ -0,0 +1,142 @@
+use many_error::ManyError;
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Parse the `--interval` of a watch, in seconds.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    match s.parse::<u64>() {
+        Ok(0) => Err("The interval must be at least 1 second.".to_string()),
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(e) => Err(format!("Invalid interval '{}': {}", s, e)),
+    }
+}
+
+/// The values printed by the previous poll, to highlight the ones which
+/// changed since.
+#[derive(Default)]
+pub struct Changes {
+    previous: Option<BTreeMap<String, String>>,
+    current: BTreeMap<String, String>,
+    terminal: bool,
+}
+
+impl Changes {
+    /// Changed lines are printed in bold if `terminal` is set, and always
+    /// followed by their previous value.
+    pub fn new(terminal: bool) -> Self {
+        Self {
+            terminal,
+            ..Self::default()
+        }
+    }
+
+    /// The line of a value, highlighted if the value changed since the
+    /// previous poll, or was not there. Nothing is highlighted on the first
+    /// poll.
+    pub fn line(&mut self, key: String, value: &str, line: String) -> String {
+        let change = match self.previous.as_ref().map(|previous| previous.get(&key)) {
+            Some(Some(previous)) if previous != value => Some(format!("(was {})", previous)),
+            Some(None) => Some("(new)".to_string()),
+            _ => None,
+        };
+        self.current.insert(key, value.to_string());
+        match change {
+            Some(change) if self.terminal => format!("\x1b[1m{}\x1b[0m {}", line, change),
+            Some(change) => format!("{} {}", line, change),
+            None => line,
+        }
+    }
+
+    /// End a poll. Its values are the previous ones of the next poll.
+    pub fn next(&mut self) {
+        self.previous = Some(std::mem::take(&mut self.current));
+    }
+}
+
+/// Poll every `interval` until interrupted with Ctrl-C, which is not an
+/// error. Only the first poll has to succeed, the errors of the next ones
+/// are logged and the watch goes on.
+pub fn watch(
+    interval: Duration,
+    mut poll: impl FnMut() -> Result<(), ManyError>,
+) -> Result<(), ManyError> {
+    let (sender, interrupted) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = sender.send(());
+    })
+    .map_err(|e| ManyError::unknown(format!("Could not handle Ctrl-C: {}", e)))?;
+
+    poll()?;
+    watch_until(interval, &interrupted, poll);
+    Ok(())
+}
+
+fn watch_until(
+    interval: Duration,
+    interrupted: &mpsc::Receiver<()>,
+    mut poll: impl FnMut() -> Result<(), ManyError>,
+) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) = interrupted.recv_timeout(interval) {
+        if let Err(e) = poll() {
+            warn!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval() {
+        assert_eq!(parse_interval("5"), Ok(Duration::from_secs(5)));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("-1").is_err());
+        assert!(parse_interval("1s").is_err());
+    }
+
+    #[test]
+    fn changes() {
+        let mut changes = Changes::new(false);
+        assert_eq!(changes.line("a".to_string(), "1", "1 A".to_string()), "1 A");
+        changes.next();
+
+        assert_eq!(changes.line("a".to_string(), "1", "1 A".to_string()), "1 A");
+        assert_eq!(
+            changes.line("b".to_string(), "7", "7 B".to_string()),
+            "7 B (new)"
+        );
+        changes.next();
+
+        assert_eq!(
+            changes.line("a".to_string(), "2", "2 A".to_string()),
+            "2 A (was 1)"
+        );
+        assert_eq!(changes.line("b".to_string(), "7", "7 B".to_string()), "7 B");
+
+        let mut changes = Changes::new(true);
+        changes.line("a".to_string(), "1", "1 A".to_string());
+        changes.next();
+        assert_eq!(
+            changes.line("a".to_string(), "2", "2 A".to_string()),
+            "\x1b[1m2 A\x1b[0m (was 1)"
+        );
+    }
+
+    #[test]
+    fn interrupt() {
+        let (sender, interrupted) = mpsc::channel();
+        let mut polls = 0;
+        watch_until(Duration::from_millis(1), &interrupted, || {
+            polls += 1;
+            if polls == 3 {
+                sender.send(()).unwrap();
+            }
+            // Errors do not stop the watch.
+            Err(ManyError::unknown("Connection refused."))
+        });
+        assert_eq!(polls, 3);
+    }
+}
@@ -1,10 +1,22 @@
+use crate::backend::{Backend, SystemResolver};
+use crate::block_digest::{BlockDigests, DigestEntry};
+use crate::local_attribute::split_local_attributes;
+use crate::metrics::BlockMetrics;
+use crate::tx_location::TxLocations;
+use crate::validator::{
+    ParamsArgs, UpdateValidatorArgs, ValidatorParams, ValidatorUpdates, UPDATE_VALIDATOR_METHOD,
+};
 use coset::{CborSerializable, CoseSign1};
 use many_client::client::blocking::{block_on, ManyClient};
-use many_error::ManyError;
+use many_error::{ManyError, ManyErrorCode};
+use many_identity::verifiers::AnonymousVerifier;
 use many_identity::{Address, AnonymousIdentity};
+use many_identity_dsa::CoseKeyVerifier;
 use many_modules::abci_backend::{AbciBlock, AbciCommitInfo, AbciInfo};
-use many_protocol::ResponseMessage;
-use reqwest::{IntoUrl, Url};
+use many_modules::EmptyReturn;
+use many_protocol::{decode_request_from_cose_sign1, RequestMessage, ResponseMessage};
+use reqwest::IntoUrl;
+use std::sync::Arc;
 use tendermint_abci::Application;
 use tendermint_proto::abci::*;
 use tracing::debug;
@@ -16,8 +28,11 @@ lazy_static::lazy_static!(
 #[derive(Debug, Clone)]
 pub struct AbciApp {
     app_name: String,
-    many_client: ManyClient<AnonymousIdentity>,
-    many_url: Url,
+    backend: Arc<Backend>,
+    metrics: Arc<BlockMetrics>,
+    tx_locations: Arc<TxLocations>,
+    validator_updates: Arc<ValidatorUpdates>,
+    block_digests: Arc<BlockDigests>,
 }
 
 impl AbciApp {
@@ -38,13 +53,55 @@ impl AbciApp {
         let many_client = ManyClient::new(many_url.clone(), server_id, AnonymousIdentity)?;
         let status = many_client.status().map_err(|x| x.to_string())?;
         let app_name = status.name;
+        let backend = Backend::new(many_url, Arc::new(SystemResolver))?;
 
         Ok(Self {
             app_name,
-            many_url,
-            many_client,
+            backend: Arc::new(backend),
+            metrics: Arc::new(BlockMetrics::default()),
+            tx_locations: Arc::new(TxLocations::default()),
+            validator_updates: Arc::new(ValidatorUpdates::default()),
+            block_digests: Arc::new(BlockDigests::default()),
         })
     }
+
+    /// Use the given block metrics, e.g. to also export them to Prometheus.
+    pub fn with_metrics(self, metrics: Arc<BlockMetrics>) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Use the given backend connection, e.g. to configure how it reconnects
+    /// or the identity its responses must be signed by.
+    pub fn with_backend(self, backend: Arc<Backend>) -> Self {
+        Self { backend, ..self }
+    }
+
+    /// Record the location of delivered transactions in the given cache,
+    /// e.g. to share it with the async module.
+    pub fn with_tx_locations(self, tx_locations: Arc<TxLocations>) -> Self {
+        Self {
+            tx_locations,
+            ..self
+        }
+    }
+
+    /// Execute the validator updates requested through MANY, e.g. to share
+    /// them with the module listing the pending ones.
+    pub fn with_validator_updates(self, validator_updates: Arc<ValidatorUpdates>) -> Self {
+        Self {
+            validator_updates,
+            ..self
+        }
+    }
+
+    /// Record the digests of the blocks executed in the given store, e.g. to
+    /// share it with the module returning them.
+    pub fn with_block_digests(self, block_digests: Arc<BlockDigests>) -> Self {
+        Self {
+            block_digests,
+            ..self
+        }
+    }
 }
 
 impl Application for AbciApp {
@@ -54,19 +111,19 @@ impl Application for AbciApp {
             request.version, request.block_version, request.p2p_version
         );
 
-        let AbciInfo { height, hash } =
-            match self.many_client.call_("abci.info", ()).and_then(|payload| {
+        let AbciInfo { height, hash } = match block_on(self.backend.call_("abci.info", ()))
+            .and_then(|payload| {
                 minicbor::decode(&payload)
                     .map_err(|e| ManyError::deserialization_error(e.to_string()))
             }) {
-                Ok(x) => x,
-                Err(err) => {
-                    return ResponseInfo {
-                        data: format!("An error occurred during call to abci.info:\n{}", err),
-                        ..Default::default()
-                    }
+            Ok(x) => x,
+            Err(err) => {
+                return ResponseInfo {
+                    data: format!("An error occurred during call to abci.info:\n{}", err),
+                    ..Default::default()
                 }
-            };
+            }
+        };
 
         ResponseInfo {
             data: format!("many-abci-bridge({})", self.app_name),
@@ -90,11 +147,17 @@ impl Application for AbciApp {
                 }
             }
         };
-        let value = match block_on(many_client::client::send_envelope(
-            self.many_url.clone(),
-            cose,
-        )) {
-            Ok(cose_sign) => cose_sign,
+        let value = match block_on(self.backend.send_envelope(cose)) {
+            Ok(cose_sign) => match self.backend.verify(&cose_sign) {
+                Ok(()) => cose_sign,
+                Err(err) => {
+                    return ResponseQuery {
+                        code: 4,
+                        log: err,
+                        ..Default::default()
+                    }
+                }
+            },
 
             Err(err) => {
                 return ResponseQuery {
@@ -120,16 +183,68 @@ impl Application for AbciApp {
     }
 
     fn begin_block(&self, request: RequestBeginBlock) -> ResponseBeginBlock {
+        let height = request.header.as_ref().map_or(0, |x| x.height as u64);
         let time = request
             .header
             .and_then(|x| x.time.map(|x| x.seconds as u64));
+        let validators = request
+            .last_commit_info
+            .map(|info| info.votes)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|vote| vote.validator)
+            .map(|v| (v.address.to_vec(), v.power.max(0) as u64));
+        self.validator_updates.begin_block(height, validators);
+        self.block_digests.begin_block(height);
+        self.tx_locations.begin_block(
+            height,
+            time.and_then(|t| many_types::Timestamp::new(t).ok()),
+        );
 
         let block = AbciBlock { time };
-        let _ = self.many_client.call_("abci.beginBlock", block);
+        self.metrics.begin_block();
+        let _ = block_on(self.backend.call_("abci.beginBlock", block));
         ResponseBeginBlock { events: vec![] }
     }
 
     fn deliver_tx(&self, request: RequestDeliverTx) -> ResponseDeliverTx {
+        let size = request.tx.len();
+        self.tx_locations.deliver_tx(&request.tx);
+        let entry = DigestEntry::new(&request.tx, 0);
+        let (response, elapsed) = BlockMetrics::time(|| self.deliver_tx_inner(request));
+        self.metrics.deliver_tx(size, elapsed);
+        self.block_digests.deliver_tx(DigestEntry {
+            code: response.code,
+            ..entry
+        });
+        response
+    }
+
+    fn end_block(&self, _request: RequestEndBlock) -> ResponseEndBlock {
+        let _ = block_on(self.backend.call_("abci.endBlock", ()));
+        let (validator_updates, mut events) = self.validator_updates.end_block();
+        events.extend(self.block_digests.end_block());
+        events.extend(self.metrics.end_block());
+        ResponseEndBlock {
+            validator_updates,
+            events,
+            ..Default::default()
+        }
+    }
+
+    fn flush(&self) -> ResponseFlush {
+        Default::default()
+    }
+
+    fn commit(&self) -> ResponseCommit {
+        let (response, elapsed) = BlockMetrics::time(|| self.commit_inner());
+        self.metrics.commit(elapsed);
+        response
+    }
+}
+
+impl AbciApp {
+    fn deliver_tx_inner(&self, request: RequestDeliverTx) -> ResponseDeliverTx {
         let cose = match CoseSign1::from_slice(&request.tx) {
             Ok(x) => x,
             Err(err) => {
@@ -140,34 +255,19 @@ impl Application for AbciApp {
                 }
             }
         };
-        match block_on(many_client::client::send_envelope(
-            self.many_url.clone(),
-            cose,
-        )) {
-            Ok(cose_sign) => {
-                let payload = cose_sign.payload.unwrap_or_default();
-                let mut response = ResponseMessage::from_bytes(&payload).unwrap_or_default();
-
-                // Consensus will sign the result, so the `from` field is unnecessary.
-                response.from = Address::anonymous();
-                // The version is ignored and removed.
-                response.version = None;
-                // The timestamp MIGHT differ between two nodes so we just force it to be 0.
-                response.timestamp = Some(*EPOCH);
-
-                if let Ok(data) = response.to_bytes() {
-                    ResponseDeliverTx {
-                        code: 0,
-                        data: data.into(),
-                        ..Default::default()
-                    }
-                } else {
-                    ResponseDeliverTx {
-                        code: 3,
-                        ..Default::default()
-                    }
+        if Self::is_validator_update(&cose) {
+            return Self::deliver_response(self.update_validator(&cose));
+        }
+        match block_on(self.backend.send_envelope(cose)) {
+            Ok(cose_sign) => match self.backend.verify(&cose_sign) {
+                Ok(()) => {
+                    let payload = cose_sign.payload.unwrap_or_default();
+                    Self::deliver_response(
+                        ResponseMessage::from_bytes(&payload).unwrap_or_default(),
+                    )
                 }
-            }
+                Err(err) => Self::unverified_response(err),
+            },
             Err(err) => ResponseDeliverTx {
                 code: 1,
                 data: vec![].into(),
@@ -177,17 +277,94 @@ impl Application for AbciApp {
         }
     }
 
-    fn end_block(&self, _request: RequestEndBlock) -> ResponseEndBlock {
-        let _ = self.many_client.call_("abci.endBlock", ());
-        Default::default()
+    fn deliver_response(response: ResponseMessage) -> ResponseDeliverTx {
+        // Local attributes may differ between nodes, so they are moved to
+        // the events, which are not hashed.
+        let (mut response, events) = split_local_attributes(response);
+        // Consensus will sign the result, so the `from` field is unnecessary.
+        response.from = Address::anonymous();
+        // The version is ignored and removed.
+        response.version = None;
+        // The timestamp MIGHT differ between two nodes so we just force it to be 0.
+        response.timestamp = Some(*EPOCH);
+
+        if let Ok(data) = response.to_bytes() {
+            ResponseDeliverTx {
+                code: 0,
+                data: data.into(),
+                events,
+                ..Default::default()
+            }
+        } else {
+            ResponseDeliverTx {
+                code: 3,
+                ..Default::default()
+            }
+        }
     }
 
-    fn flush(&self) -> ResponseFlush {
-        Default::default()
+    /// The result of a transaction whose response is not signed by the
+    /// backend. Only the code and data of a result are part of consensus, so
+    /// they do not depend on the reason, which is only logged.
+    pub fn unverified_response(reason: String) -> ResponseDeliverTx {
+        ResponseDeliverTx {
+            code: 4,
+            data: vec![].into(),
+            log: reason,
+            ..Default::default()
+        }
     }
 
-    fn commit(&self) -> ResponseCommit {
-        self.many_client.call_("abci.commit", ()).map_or_else(
+    /// Whether a transaction is a validator update, which is executed here
+    /// instead of by the backend. The signature is not verified yet.
+    fn is_validator_update(cose: &CoseSign1) -> bool {
+        cose.payload
+            .as_ref()
+            .and_then(|payload| RequestMessage::from_bytes(payload).ok())
+            .map_or(false, |message| message.method == UPDATE_VALIDATOR_METHOD)
+    }
+
+    fn update_validator(&self, cose: &CoseSign1) -> ResponseMessage {
+        let message =
+            match decode_request_from_cose_sign1(cose, &(AnonymousVerifier, CoseKeyVerifier)) {
+                Ok(message) => message,
+                Err(err) => return ResponseMessage::error(Address::anonymous(), None, err),
+            };
+        let data = minicbor::decode::<UpdateValidatorArgs>(&message.data)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))
+            .and_then(|args| {
+                let params = self.validator_params()?;
+                self.validator_updates
+                    .request(&params, &message.from(), args)
+            })
+            .and_then(|_| {
+                minicbor::to_vec(EmptyReturn)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))
+            });
+        ResponseMessage::from_request(&message, &Address::anonymous(), data)
+    }
+
+    /// The validator parameters, from the consensus state of the backend. A
+    /// backend without governance parameters refuses every update.
+    fn validator_params(&self) -> Result<ValidatorParams, ManyError> {
+        let payload =
+            block_on(self.backend.call_("governance.params", ParamsArgs {})).map_err(|e| {
+                if e.code() == ManyErrorCode::InvalidMethodName {
+                    ManyError::unknown(
+                        "The backend has no governance parameters, so it has no validator admins."
+                            .to_string(),
+                    )
+                } else {
+                    e
+                }
+            })?;
+        ValidatorParams::decode(&payload)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    fn commit_inner(&self) -> ResponseCommit {
+        let result = block_on(self.backend.call_("abci.commit", ()));
+        result.map_or_else(
             |err| ResponseCommit {
                 data: err.to_string().into_bytes().into(),
                 retain_height: 0,
@@ -0,0 +1,387 @@
+use coset::{CborSerializable, CoseSign1};
+use many_error::ManyError;
+use many_identity::verifiers::AnonymousVerifier;
+use many_identity::{Address, AnonymousIdentity};
+use many_identity_dsa::CoseKeyVerifier;
+use many_protocol::{
+    decode_response_from_cose_sign1, encode_cose_sign1_from_request, RequestMessageBuilder,
+    ResponseMessage,
+};
+use minicbor::Encode;
+use prometheus::{IntCounter, Registry};
+use reqwest::{IntoUrl, Url};
+use std::fmt::{Debug, Formatter};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// How long a connection attempt gets before the next address is tried in
+/// parallel, as recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How long a single connection attempt can take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of consecutive failed requests after which the connection to the
+/// backend is re-established.
+pub const RECONNECT_AFTER_FAILURES: u32 = 3;
+
+/// Default minimum time between two reconnections.
+pub const DEFAULT_RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Resolves a host name to socket addresses.
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>>;
+}
+
+/// The resolver of the operating system.
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+/// Order addresses so that the two families alternate, starting with the
+/// family of the first address (RFC 8305, section 4).
+pub fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_v6 = addrs.first().map_or(true, SocketAddr::is_ipv6);
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_v6);
+
+    let mut result = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return result,
+            (a, b) => {
+                result.extend(a);
+                result.extend(b);
+            }
+        }
+    }
+}
+
+/// Connect to the addresses in order, starting the next attempt when the
+/// previous one failed or did not succeed within `delay`, and return the
+/// first address that accepted a connection.
+pub fn connect_first(
+    addrs: &[SocketAddr],
+    delay: Duration,
+    timeout: Duration,
+) -> std::io::Result<SocketAddr> {
+    let (tx, rx) = mpsc::channel();
+    let mut pending = 0;
+    let mut last_error = None;
+
+    for addr in addrs.iter().copied() {
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            let result = TcpStream::connect_timeout(&addr, timeout).map(|_| ());
+            let _ = tx.send((addr, result));
+        });
+        pending += 1;
+
+        if let Ok((addr, result)) = rx.recv_timeout(delay) {
+            match result {
+                Ok(()) => return Ok(addr),
+                Err(e) => {
+                    pending -= 1;
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+
+    while pending > 0 {
+        match rx.recv() {
+            Ok((addr, Ok(()))) => return Ok(addr),
+            Ok((_, Err(e))) => {
+                pending -= 1;
+                last_error = Some(e);
+            }
+            Err(_) => break,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "No address to connect to.")
+    }))
+}
+
+/// Decode a response of the backend, verifying that it is signed by the
+/// expected identity. Unsigned responses are refused.
+pub fn verify_response(
+    envelope: &CoseSign1,
+    expected: &Address,
+) -> Result<ResponseMessage, String> {
+    let response = decode_response_from_cose_sign1(envelope, None, &CoseKeyVerifier)
+        .map_err(|e| e.to_string())?;
+    if &response.from != expected {
+        return Err(format!(
+            "The response is signed by {}, expected {}.",
+            response.from, expected
+        ));
+    }
+    Ok(response)
+}
+
+/// Counters of the connections to the backend.
+#[derive(Clone)]
+pub struct BackendMetrics {
+    reconnects: IntCounter,
+    connection_changes: IntCounter,
+}
+
+impl BackendMetrics {
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let reconnects = IntCounter::new(
+            "many_abci_backend_reconnects_total",
+            "Number of times the connection to the backend was re-established.",
+        )?;
+        let connection_changes = IntCounter::new(
+            "many_abci_backend_connection_changes_total",
+            "Number of times the backend was reconnected at a different address.",
+        )?;
+        registry.register(Box::new(reconnects.clone()))?;
+        registry.register(Box::new(connection_changes.clone()))?;
+        Ok(Self {
+            reconnects,
+            connection_changes,
+        })
+    }
+}
+
+struct Connection {
+    client: reqwest::Client,
+    addr: Option<SocketAddr>,
+    failures: u32,
+    last_attempt: Instant,
+}
+
+/// The HTTP connection to the backend MANY application. The host name of the
+/// URL is resolved when connecting, and again when reconnecting after
+/// consecutive failures, so the backend can move to another address. The
+/// URL keeps its host name, so TLS still verifies it.
+pub struct Backend {
+    url: Url,
+    resolver: Arc<dyn Resolver>,
+    reconnect_interval: Duration,
+    connection: Mutex<Connection>,
+    metrics: Option<BackendMetrics>,
+    identity: Option<Address>,
+    #[cfg(feature = "testing-hooks")]
+    faults: Option<Arc<crate::faults::Faults>>,
+}
+
+impl Debug for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Backend").field("url", &self.url).finish()
+    }
+}
+
+impl Backend {
+    pub fn new<U: IntoUrl>(url: U, resolver: Arc<dyn Resolver>) -> Result<Self, String> {
+        let backend = Self {
+            url: url.into_url().map_err(|e| e.to_string())?,
+            resolver,
+            reconnect_interval: DEFAULT_RECONNECT_INTERVAL,
+            connection: Mutex::new(Connection {
+                client: reqwest::Client::new(),
+                addr: None,
+                failures: 0,
+                last_attempt: Instant::now(),
+            }),
+            metrics: None,
+            identity: None,
+            #[cfg(feature = "testing-hooks")]
+            faults: None,
+        };
+        backend.connect()?;
+        Ok(backend)
+    }
+
+    pub fn with_reconnect_interval(self, reconnect_interval: Duration) -> Self {
+        Self {
+            reconnect_interval,
+            ..self
+        }
+    }
+
+    pub fn with_metrics(self, metrics: &BackendMetrics) -> Self {
+        Self {
+            metrics: Some(metrics.clone()),
+            ..self
+        }
+    }
+
+    /// Refuse the responses of the backend which are not signed by this
+    /// identity.
+    pub fn with_identity(self, identity: Address) -> Self {
+        Self {
+            identity: Some(identity),
+            ..self
+        }
+    }
+
+    /// Inject faults in the requests to the backend.
+    #[cfg(feature = "testing-hooks")]
+    pub fn with_faults(self, faults: Arc<crate::faults::Faults>) -> Self {
+        Self {
+            faults: Some(faults),
+            ..self
+        }
+    }
+
+    /// The address the backend is currently reached at.
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.connection.lock().unwrap().addr
+    }
+
+    fn connect(&self) -> Result<SocketAddr, String> {
+        let host = self.url.host_str().ok_or("The backend URL has no host.")?;
+        let port = self
+            .url
+            .port_or_known_default()
+            .ok_or("The backend URL has no port.")?;
+
+        let addrs = self
+            .resolver
+            .resolve(host, port)
+            .map_err(|e| format!("Could not resolve {}: {}", host, e))?;
+        let addr = connect_first(
+            &interleave(addrs),
+            CONNECTION_ATTEMPT_DELAY,
+            CONNECT_TIMEOUT,
+        )
+        .map_err(|e| format!("Could not connect to {}: {}", host, e))?;
+        let client = reqwest::Client::builder()
+            .resolve(host, addr)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let mut connection = self.connection.lock().unwrap();
+        match connection.addr {
+            Some(previous) if previous != addr => {
+                info!("Backend moved from {} to {}", previous, addr);
+                if let Some(metrics) = &self.metrics {
+                    metrics.connection_changes.inc();
+                }
+            }
+            Some(_) => {}
+            None => debug!("Backend connected at {}", addr),
+        }
+        connection.client = client;
+        connection.addr = Some(addr);
+        connection.failures = 0;
+        Ok(addr)
+    }
+
+    /// Re-resolve the backend and connect to it again.
+    pub fn reconnect(&self) -> Result<SocketAddr, String> {
+        self.connection.lock().unwrap().last_attempt = Instant::now();
+        if let Some(metrics) = &self.metrics {
+            metrics.reconnects.inc();
+        }
+        self.connect()
+    }
+
+    /// Record the outcome of a request to the backend. After enough
+    /// consecutive failures, and at most once per reconnect interval, the
+    /// connection is re-established.
+    pub fn record(&self, success: bool) {
+        let due = {
+            let mut connection = self.connection.lock().unwrap();
+            if success {
+                connection.failures = 0;
+                return;
+            }
+            connection.failures += 1;
+            connection.failures >= RECONNECT_AFTER_FAILURES
+                && connection.last_attempt.elapsed() >= self.reconnect_interval
+        };
+
+        if due {
+            if let Err(e) = self.reconnect() {
+                warn!("Could not reconnect to the backend: {}", e);
+            }
+        }
+    }
+
+    /// Send an envelope to the backend and return its response.
+    pub async fn send_envelope(&self, envelope: CoseSign1) -> Result<CoseSign1, ManyError> {
+        #[cfg(feature = "testing-hooks")]
+        if let Some(faults) = &self.faults {
+            return self.send_with_faults(faults, envelope).await;
+        }
+        self.post(envelope).await
+    }
+
+    pub(crate) async fn post(&self, envelope: CoseSign1) -> Result<CoseSign1, ManyError> {
+        let bytes = envelope
+            .to_vec()
+            .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+        let client = self.connection.lock().unwrap().client.clone();
+
+        let result = async {
+            client
+                .post(self.url.clone())
+                .body(bytes)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        }
+        .await;
+        self.record(result.is_ok());
+
+        let body = result.map_err(|e| ManyError::unknown(e.to_string()))?;
+        CoseSign1::from_slice(&body).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    /// Verify that a response is signed by the identity of the backend, if
+    /// one is expected.
+    pub fn verify(&self, envelope: &CoseSign1) -> Result<(), String> {
+        match &self.identity {
+            Some(identity) => verify_response(envelope, identity)
+                .map(|_| ())
+                .map_err(|e| {
+                    error!("REJECTED a response of the backend: {}", e);
+                    e
+                }),
+            None => Ok(()),
+        }
+    }
+
+    /// Call a method of the backend anonymously, and return the data of its
+    /// verified response.
+    pub async fn call_<A: Encode<()>>(
+        &self,
+        method: &str,
+        argument: A,
+    ) -> Result<Vec<u8>, ManyError> {
+        let message = RequestMessageBuilder::default()
+            .from(Address::anonymous())
+            .method(method.to_string())
+            .data(
+                minicbor::to_vec(argument)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            )
+            .build()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let envelope = encode_cose_sign1_from_request(message, &AnonymousIdentity)
+            .map_err(ManyError::unexpected_transport_error)?;
+
+        let response = self.send_envelope(envelope).await?;
+        self.verify(&response)
+            .map_err(ManyError::unexpected_transport_error)?;
+        decode_response_from_cose_sign1(&response, None, &(AnonymousVerifier, CoseKeyVerifier))
+            .map_err(ManyError::unexpected_transport_error)?
+            .data
+    }
+}
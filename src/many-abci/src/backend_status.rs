@@ -0,0 +1,231 @@
+use async_trait::async_trait;
+use many_error::ManyError;
+use many_modules::abci_backend::EndpointInfo;
+use many_modules::base;
+use prometheus::{IntCounter, IntGauge, Registry};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// The status of the backend and the endpoints it declares, which the
+/// frontend serves and routes messages with.
+pub struct BackendInfo {
+    pub status: base::Status,
+    pub endpoints: BTreeMap<String, EndpointInfo>,
+    hash: Vec<u8>,
+}
+
+impl BackendInfo {
+    pub fn new(
+        status: base::Status,
+        endpoints: BTreeMap<String, EndpointInfo>,
+    ) -> Result<Self, ManyError> {
+        use sha2::Digest;
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(
+            minicbor::to_vec(&status).map_err(|e| ManyError::serialization_error(e.to_string()))?,
+        );
+        hasher.update(
+            minicbor::to_vec(&endpoints)
+                .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+        );
+        Ok(Self {
+            status,
+            endpoints,
+            hash: hasher.finalize().to_vec(),
+        })
+    }
+}
+
+/// The differences between two backend infos.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatusDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+
+    /// Endpoints which became commands or queries.
+    pub changed: Vec<String>,
+
+    /// Whether the status itself (name, version, attributes...) changed.
+    pub status: bool,
+}
+
+impl StatusDiff {
+    pub fn new(old: &BackendInfo, new: &BackendInfo) -> Self {
+        let added = new
+            .endpoints
+            .keys()
+            .filter(|name| !old.endpoints.contains_key(*name))
+            .cloned()
+            .collect();
+        let removed = old
+            .endpoints
+            .keys()
+            .filter(|name| !new.endpoints.contains_key(*name))
+            .cloned()
+            .collect();
+        let changed = new
+            .endpoints
+            .iter()
+            .filter(|(name, info)| {
+                matches!(old.endpoints.get(*name), Some(old) if old.is_command != info.is_command)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        let status = minicbor::to_vec(&old.status).ok() != minicbor::to_vec(&new.status).ok();
+
+        Self {
+            added,
+            removed,
+            changed,
+            status,
+        }
+    }
+}
+
+/// Gauges and counters of the status refreshes.
+#[derive(Clone)]
+pub struct StatusMetrics {
+    stale: IntGauge,
+    updates: IntCounter,
+}
+
+impl StatusMetrics {
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let stale = IntGauge::new(
+            "many_abci_backend_status_stale",
+            "1 if the last refresh of the backend status failed, and the last known one is served.",
+        )?;
+        let updates = IntCounter::new(
+            "many_abci_backend_status_updates_total",
+            "Number of times the backend status changed while running.",
+        )?;
+        registry.register(Box::new(stale.clone()))?;
+        registry.register(Box::new(updates.clone()))?;
+        Ok(Self { stale, updates })
+    }
+}
+
+/// The backend info currently served, shared between the frontend and the
+/// refresh task. It is replaced as a whole, so a message is always routed
+/// with the endpoints of the status being served.
+pub struct SharedStatus {
+    current: RwLock<Arc<BackendInfo>>,
+    stale: AtomicBool,
+    metrics: Option<StatusMetrics>,
+}
+
+impl SharedStatus {
+    pub fn new(info: BackendInfo) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(info)),
+            stale: AtomicBool::new(false),
+            metrics: None,
+        }
+    }
+
+    pub fn with_metrics(self, metrics: &StatusMetrics) -> Self {
+        Self {
+            metrics: Some(metrics.clone()),
+            ..self
+        }
+    }
+
+    pub fn get(&self) -> Arc<BackendInfo> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// The endpoint a message for `method` is routed to, if any.
+    pub fn route(&self, method: &str) -> Option<EndpointInfo> {
+        self.get().endpoints.get(method).cloned()
+    }
+
+    /// Whether the last refresh failed, so the info served can be outdated.
+    pub fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    fn set_stale(&self, stale: bool) {
+        self.stale.store(stale, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.stale.set(stale as i64);
+        }
+    }
+
+    /// Replace the info served, returning what changed, or `None` if
+    /// nothing did.
+    fn update(&self, info: BackendInfo) -> Option<StatusDiff> {
+        let mut current = self.current.write().unwrap();
+        if current.hash == info.hash {
+            return None;
+        }
+        let diff = StatusDiff::new(&current, &info);
+        *current = Arc::new(info);
+        if let Some(metrics) = &self.metrics {
+            metrics.updates.inc();
+        }
+        Some(diff)
+    }
+}
+
+/// Where the backend info is fetched from.
+#[async_trait]
+pub trait StatusSource: Send + Sync {
+    async fn fetch(&self) -> Result<BackendInfo, ManyError>;
+}
+
+#[async_trait]
+impl<S: StatusSource + ?Sized> StatusSource for Arc<S> {
+    async fn fetch(&self) -> Result<BackendInfo, ManyError> {
+        self.as_ref().fetch().await
+    }
+}
+
+/// The result of a refresh.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    Unchanged,
+    Updated(StatusDiff),
+    Failed(String),
+}
+
+/// Fetch the backend info and replace the one served if it changed. On
+/// failure, the last known info is kept and marked as stale.
+pub async fn refresh(source: &dyn StatusSource, shared: &SharedStatus) -> RefreshOutcome {
+    match source.fetch().await {
+        Ok(info) => {
+            if shared.is_stale() {
+                info!("The backend status can be refreshed again");
+            }
+            shared.set_stale(false);
+            shared
+                .update(info)
+                .map_or(RefreshOutcome::Unchanged, RefreshOutcome::Updated)
+        }
+        Err(e) => {
+            shared.set_stale(true);
+            RefreshOutcome::Failed(e.to_string())
+        }
+    }
+}
+
+/// Refresh the backend info every `interval`, forever.
+pub async fn run(source: impl StatusSource, shared: Arc<SharedStatus>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick completes immediately, and the info was just fetched.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        match refresh(&source, &shared).await {
+            RefreshOutcome::Unchanged => debug!("The backend status did not change"),
+            RefreshOutcome::Updated(diff) => info!(
+                "The backend status changed: endpoints added {:?}, removed {:?}, changed {:?}, status changed: {}",
+                diff.added, diff.removed, diff.changed, diff.status
+            ),
+            RefreshOutcome::Failed(e) => warn!("Could not refresh the backend status: {}", e),
+        }
+    }
+}
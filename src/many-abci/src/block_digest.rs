@@ -0,0 +1,306 @@
+use crate::replay::RecordedBlock;
+use crate::tx_location::tx_hash;
+use coset::{CborSerializable, CoseSign1};
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use sha2::Digest;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use tendermint_proto::abci::{Event, EventAttribute};
+
+/// Default number of blocks whose digest is kept in memory. Older ones are
+/// recomputed from Tendermint.
+pub const DEFAULT_BLOCK_DIGESTS: usize = 10_000;
+
+/// The type of the tendermint event attached to the block in EndBlock with
+/// the digest of its MANY messages.
+pub const BLOCK_DIGEST_EVENT: &str = "many_block_digest";
+
+/// A MANY message of a block, as covered by the digest of the block.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct DigestEntry {
+    /// The Tendermint hash of the transaction.
+    #[n(0)]
+    pub hash: ByteVec,
+
+    /// The `from` field of the request, which is anonymous for transactions
+    /// which are not MANY requests. Signatures are not verified.
+    #[n(1)]
+    pub sender: Address,
+
+    /// The method of the request, empty for transactions which are not MANY
+    /// requests.
+    #[n(2)]
+    pub method: String,
+
+    /// The DeliverTx result code.
+    #[n(3)]
+    pub code: u32,
+}
+
+impl DigestEntry {
+    pub fn new(tx: &[u8], code: u32) -> Self {
+        let message = CoseSign1::from_slice(tx)
+            .ok()
+            .and_then(|cose| cose.payload)
+            .and_then(|payload| RequestMessage::from_bytes(&payload).ok());
+        let (sender, method) = match message {
+            Some(message) => (message.from(), message.method),
+            None => (Address::anonymous(), String::new()),
+        };
+        Self {
+            hash: tx_hash(tx).into(),
+            sender,
+            method,
+            code,
+        }
+    }
+}
+
+/// The digest of the MANY messages of a block, in order, with their result
+/// codes. Auditors can check it against the messages the bridge executed
+/// without running a node.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct BlockDigest {
+    #[n(0)]
+    pub height: u64,
+
+    #[n(1)]
+    pub digest: ByteVec,
+
+    #[n(2)]
+    pub entries: Vec<DigestEntry>,
+}
+
+/// The SHA-256 of the height and of the CBOR array of the entries.
+fn digest_of(height: u64, entries: &[DigestEntry]) -> Vec<u8> {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(height.to_be_bytes());
+    hasher.update(minicbor::to_vec(entries).expect("Could not encode the digest entries"));
+    hasher.finalize().to_vec()
+}
+
+fn attribute(key: &str, value: String) -> EventAttribute {
+    EventAttribute {
+        key: key.to_string().into_bytes().into(),
+        value: value.into_bytes().into(),
+        index: true,
+    }
+}
+
+impl BlockDigest {
+    pub fn new(height: u64, entries: Vec<DigestEntry>) -> Self {
+        Self {
+            height,
+            digest: digest_of(height, &entries).into(),
+            entries,
+        }
+    }
+
+    /// Recompute the digest of a block from the transactions and results
+    /// recorded by Tendermint, e.g. read with `replay::recorded_block`.
+    pub fn from_recorded(block: &RecordedBlock) -> Self {
+        Self::new(
+            block.height,
+            block
+                .txs
+                .iter()
+                .map(|tx| DigestEntry::new(&tx.tx, tx.code))
+                .collect(),
+        )
+    }
+
+    /// Whether the digest is the one of the height and the entries.
+    pub fn is_consistent(&self) -> bool {
+        self.digest.as_slice() == digest_of(self.height, &self.entries).as_slice()
+    }
+
+    /// The event attached to the block in EndBlock.
+    pub fn as_event(&self) -> Event {
+        Event {
+            r#type: BLOCK_DIGEST_EVENT.to_string(),
+            attributes: vec![
+                attribute("height", self.height.to_string()),
+                attribute("digest", hex::encode(self.digest.as_slice())),
+                attribute("messages", self.entries.len().to_string()),
+            ],
+        }
+    }
+}
+
+/// Check a digest, e.g. returned by `blockchain.blockDigest` or read from the
+/// events of a block, against the block recorded by Tendermint. Returns why
+/// they differ, naming the first entry which does.
+pub fn verify(digest: &BlockDigest, block: &RecordedBlock) -> Result<(), String> {
+    if !digest.is_consistent() {
+        return Err(format!(
+            "The digest of block {} does not match its entries.",
+            digest.height
+        ));
+    }
+    let expected = BlockDigest::from_recorded(block);
+    if expected.digest == digest.digest {
+        return Ok(());
+    }
+    if expected.height != digest.height {
+        return Err(format!(
+            "The digest is for block {}, not {}.",
+            digest.height, expected.height
+        ));
+    }
+    match expected
+        .entries
+        .iter()
+        .zip(&digest.entries)
+        .position(|(a, b)| a != b)
+    {
+        Some(index) => Err(format!(
+            "Message {} of block {} differs: recorded {:?}, digested {:?}.",
+            index, block.height, expected.entries[index], digest.entries[index]
+        )),
+        None => Err(format!(
+            "Block {} has {} messages, but {} were digested.",
+            block.height,
+            expected.entries.len(),
+            digest.entries.len()
+        )),
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    height: u64,
+    entries: Vec<DigestEntry>,
+    digests: BTreeMap<u64, BlockDigest>,
+}
+
+/// The digests of the last blocks executed, recorded as they are. This is
+/// local to the node, and not part of consensus.
+pub struct BlockDigests {
+    inner: Mutex<Inner>,
+    capacity: usize,
+}
+
+impl Default for BlockDigests {
+    fn default() -> Self {
+        Self::new(DEFAULT_BLOCK_DIGESTS)
+    }
+}
+
+impl BlockDigests {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity,
+        }
+    }
+
+    pub fn begin_block(&self, height: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.height = height;
+        inner.entries.clear();
+    }
+
+    /// Record the next transaction of the current block.
+    pub fn deliver_tx(&self, entry: DigestEntry) {
+        self.inner.lock().unwrap().entries.push(entry);
+    }
+
+    /// Compute the digest of the current block, and return the events to
+    /// attach to it.
+    pub fn end_block(&self) -> Vec<Event> {
+        let mut inner = self.inner.lock().unwrap();
+        let entries = std::mem::take(&mut inner.entries);
+        let digest = BlockDigest::new(inner.height, entries);
+        let event = digest.as_event();
+        inner.digests.insert(digest.height, digest);
+        while inner.digests.len() > self.capacity {
+            inner.digests.pop_first();
+        }
+        vec![event]
+    }
+
+    pub fn get(&self, height: u64) -> Option<BlockDigest> {
+        self.inner.lock().unwrap().digests.get(&height).cloned()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct BlockDigestArgs {
+    #[n(0)]
+    pub height: u64,
+}
+
+pub trait BlockDigestModuleBackend: Send {
+    fn block_digest(&self, args: BlockDigestArgs) -> Result<BlockDigest, ManyError>;
+}
+
+const ENDPOINTS: &[&str] = &["blockchain.blockDigest"];
+
+/// A module returning the digest of the MANY messages of a block.
+pub struct BlockDigestModule<T: BlockDigestModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: BlockDigestModuleBackend> BlockDigestModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "BlockDigestModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: BlockDigestModuleBackend> Debug for BlockDigestModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BlockDigestModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: BlockDigestModuleBackend> ManyModule for BlockDigestModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "blockchain.blockDigest" => minicbor::decode::<BlockDigestArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "blockchain.blockDigest" => minicbor::decode(&message.data)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+                .and_then(|args| backend.block_digest(args))
+                .and_then(|returns| {
+                    minicbor::to_vec(returns)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))
+                }),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
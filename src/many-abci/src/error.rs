@@ -0,0 +1,8 @@
+use many_error::define_attribute_many_error;
+
+define_attribute_many_error!(
+    attribute 1001 => {
+        1: pub fn mempool_congested(depth, retry_after)
+            => "The mempool is congested with {depth} transactions, retry in {retry_after} seconds.",
+    }
+);
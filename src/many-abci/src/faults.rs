@@ -0,0 +1,236 @@
+use crate::backend::Backend;
+use async_trait::async_trait;
+use coset::CoseSign1;
+use many_error::{ManyError, ManyErrorCode};
+use many_protocol::RequestMessage;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tendermint_rpc::response_error::{Code, ResponseError};
+use tendermint_rpc::{Client, SimpleRequest};
+use tracing::warn;
+
+/// The method of the faults matching every call.
+pub const ANY_METHOD: &str = "*";
+
+/// The code of the JSON-RPC errors injected without a code.
+const DEFAULT_RPC_ERROR_CODE: i32 = -32603;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Make the call, then lose its response, as if the connection closed
+    /// after the request was sent.
+    Drop,
+
+    /// Make the call after a delay.
+    Delay(Duration),
+
+    /// Fail without making the call, with the given error code if any.
+    Error(Option<i64>),
+}
+
+/// A fault injected in the next `count` calls of an RPC method of
+/// Tendermint, or of a MANY method of the backend.
+///
+/// Its text format is `<method> <action> [<count>]`, where the action is
+/// `drop`, `delay:<duration>`, `error` or `error:<code>`, and the count
+/// defaults to 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fault {
+    pub method: String,
+    pub action: FaultAction,
+    pub count: u32,
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let method = words.next().ok_or("Missing method.")?.to_string();
+        let action = match words.next().ok_or("Missing action.")? {
+            "drop" => FaultAction::Drop,
+            "error" => FaultAction::Error(None),
+            action => match action.split_once(':') {
+                Some(("delay", delay)) => {
+                    FaultAction::Delay(humantime::parse_duration(delay).map_err(|e| e.to_string())?)
+                }
+                Some(("error", code)) => {
+                    FaultAction::Error(Some(code.parse().map_err(|_| "Invalid error code.")?))
+                }
+                _ => return Err(format!("Unknown action: {}.", action)),
+            },
+        };
+        let count = match words.next() {
+            Some(count) => count.parse().map_err(|_| "Invalid count.")?,
+            None => 1,
+        };
+        if words.next().is_some() {
+            return Err("Too many words.".to_string());
+        }
+        Ok(Self {
+            method,
+            action,
+            count,
+        })
+    }
+}
+
+/// Parse the faults of a control file, one per line. Empty lines and lines
+/// starting with `#` are ignored.
+pub fn parse(content: &str) -> Result<Vec<Fault>, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Fault::from_str(line).map_err(|e| format!("{:?}: {}", line, e)))
+        .collect()
+}
+
+/// The faults to inject in the next calls.
+///
+/// Faults are added by the tests, or read from a control file which is
+/// removed once read, so a harness can inject faults in a running bridge.
+/// The file should be written atomically, e.g. by renaming it.
+#[derive(Debug, Default)]
+pub struct Faults {
+    faults: Mutex<Vec<Fault>>,
+    control_file: Option<PathBuf>,
+}
+
+impl Faults {
+    pub fn with_control_file(self, control_file: PathBuf) -> Self {
+        Self {
+            control_file: Some(control_file),
+            ..self
+        }
+    }
+
+    pub fn add(&self, fault: Fault) {
+        self.faults.lock().unwrap().push(fault);
+    }
+
+    /// Add the faults of the control file, if it exists.
+    fn load(&self) -> Result<(), String> {
+        let path = match &self.control_file {
+            Some(path) if path.exists() => path,
+            _ => return Ok(()),
+        };
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        std::fs::remove_file(path).map_err(|e| e.to_string())?;
+        self.faults.lock().unwrap().extend(parse(&content)?);
+        Ok(())
+    }
+
+    /// The action to take for the next call of a method, if a fault
+    /// matches it. The first fault matching is used.
+    pub fn next(&self, method: &str) -> Option<FaultAction> {
+        if let Err(e) = self.load() {
+            warn!("Could not read the faults to inject: {}", e);
+        }
+
+        let mut faults = self.faults.lock().unwrap();
+        let i = faults
+            .iter()
+            .position(|f| f.method == method || f.method == ANY_METHOD)?;
+        let action = faults[i].action.clone();
+        faults[i].count = faults[i].count.saturating_sub(1);
+        if faults[i].count == 0 {
+            faults.remove(i);
+        }
+        warn!("Injecting {:?} in a call of {}", action, method);
+        Some(action)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faults.lock().unwrap().is_empty()
+    }
+}
+
+/// A Tendermint RPC client injecting faults in its calls.
+#[derive(Clone)]
+pub struct FaultyClient<C: Client> {
+    inner: C,
+    faults: Arc<Faults>,
+}
+
+impl<C: Client> FaultyClient<C> {
+    pub fn new(inner: C, faults: Arc<Faults>) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl<C: Client + Send + Sync> Client for FaultyClient<C> {
+    async fn perform<R>(&self, request: R) -> Result<R::Response, tendermint_rpc::Error>
+    where
+        R: SimpleRequest,
+    {
+        let method = request.method().to_string();
+        match self.faults.next(&method) {
+            None => self.inner.perform(request).await,
+            Some(FaultAction::Drop) => {
+                let _ = self.inner.perform(request).await;
+                Err(tendermint_rpc::Error::client_internal(format!(
+                    "Dropped the response of {}.",
+                    method
+                )))
+            }
+            Some(FaultAction::Delay(delay)) => {
+                smol::Timer::after(delay).await;
+                self.inner.perform(request).await
+            }
+            Some(FaultAction::Error(code)) => {
+                let code = code.map_or(DEFAULT_RPC_ERROR_CODE, |code| code as i32);
+                Err(tendermint_rpc::Error::response(ResponseError::new(
+                    Code::from(code),
+                    Some(format!("Injected in {}.", method)),
+                )))
+            }
+        }
+    }
+}
+
+impl Backend {
+    /// Send an envelope to the backend, injecting the fault of its method.
+    /// Injected errors count as failed requests.
+    pub(crate) async fn send_with_faults(
+        &self,
+        faults: &Faults,
+        envelope: CoseSign1,
+    ) -> Result<CoseSign1, ManyError> {
+        let method = envelope
+            .payload
+            .as_ref()
+            .and_then(|payload| RequestMessage::from_bytes(payload).ok())
+            .map(|message| message.method)
+            .unwrap_or_default();
+
+        match faults.next(&method) {
+            None => self.post(envelope).await,
+            Some(FaultAction::Drop) => {
+                let _ = self.post(envelope).await;
+                Err(ManyError::unknown(format!(
+                    "Dropped the response of {}.",
+                    method
+                )))
+            }
+            Some(FaultAction::Delay(delay)) => {
+                smol::Timer::after(delay).await;
+                self.post(envelope).await
+            }
+            Some(FaultAction::Error(code)) => {
+                self.record(false);
+                let message = format!("Injected in {}.", method);
+                Err(match code {
+                    Some(code) => {
+                        ManyError::new(ManyErrorCode::from(code), Some(message), BTreeMap::new())
+                    }
+                    None => ManyError::unknown(message),
+                })
+            }
+        }
+    }
+}
@@ -1,3 +1,19 @@
 pub mod abci_app;
+pub mod backend;
+pub mod backend_status;
+pub mod block_digest;
+pub mod error;
+#[cfg(feature = "testing-hooks")]
+pub mod faults;
+pub mod listener;
+pub mod local_attribute;
 pub mod many_app;
+pub mod mempool;
+pub mod metrics;
 pub mod module;
+pub mod read_pool;
+pub mod reindex;
+pub mod replay;
+pub mod request_index;
+pub mod tx_location;
+pub mod validator;
@@ -0,0 +1,207 @@
+use coset::{CborSerializable, CoseSign1};
+use many_server::transport::LowLevelManyRequestHandler;
+use prometheus::{IntCounter, IntCounterVec, Opts, Registry};
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Maximum size of a request body.
+const READ_BUFFER_LEN: u64 = 1024 * 1024 * 10;
+
+/// How often listeners check for their termination signal.
+const TERM_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An address to serve MANY on. Unix domain sockets are given as
+/// `unix:/path/to.sock`, anything else is a TCP address and port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("Missing unix socket path.".to_string()),
+            Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+            None => Ok(ListenAddr::Tcp(s.to_string())),
+        }
+    }
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => f.write_str(addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Request counters of all listeners, labelled by listen address.
+#[derive(Clone)]
+pub struct ListenerMetrics {
+    requests: IntCounterVec,
+}
+
+impl ListenerMetrics {
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "many_abci_listener_requests_total",
+                "Number of MANY requests received, per listener.",
+            ),
+            &["listener"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        Ok(Self { requests })
+    }
+}
+
+/// Removes a unix socket file when dropped.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Could not remove socket {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// A MANY HTTP listener. Several listeners can serve the same MANY server;
+/// each has its own termination signal.
+pub struct Listener<E: LowLevelManyRequestHandler> {
+    addr: ListenAddr,
+    server: tiny_http::Server,
+    executor: E,
+    term_signal: Arc<AtomicBool>,
+    requests: Option<IntCounter>,
+
+    // Declared last so the socket is closed before its file is removed.
+    _socket_file: Option<SocketFile>,
+}
+
+impl<E: LowLevelManyRequestHandler> Listener<E> {
+    /// Bind to an address. Unix sockets are created with the given
+    /// permissions, if any. A socket file left by a previous run is
+    /// replaced, but any other file at that path is an error.
+    pub fn bind(addr: ListenAddr, executor: E, mode: Option<u32>) -> Result<Self, String> {
+        let (server, socket_file) = match &addr {
+            ListenAddr::Tcp(a) => (
+                tiny_http::Server::http(a.as_str()).map_err(|e| e.to_string())?,
+                None,
+            ),
+            ListenAddr::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(format!("{} exists and is not a socket.", path.display()));
+                    }
+                    std::fs::remove_file(path).map_err(|e| e.to_string())?;
+                }
+
+                let server = tiny_http::Server::http_unix(path).map_err(|e| e.to_string())?;
+                let socket_file = SocketFile(path.clone());
+                if let Some(mode) = mode {
+                    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                        .map_err(|e| e.to_string())?;
+                }
+                (server, Some(socket_file))
+            }
+        };
+
+        Ok(Self {
+            addr,
+            server,
+            executor,
+            term_signal: Arc::new(AtomicBool::new(false)),
+            requests: None,
+            _socket_file: socket_file,
+        })
+    }
+
+    pub fn with_metrics(mut self, metrics: &ListenerMetrics) -> Self {
+        let label = self.addr.to_string();
+        self.requests = Some(metrics.requests.with_label_values(&[label.as_str()]));
+        self
+    }
+
+    pub fn addr(&self) -> &ListenAddr {
+        &self.addr
+    }
+
+    /// The bound TCP address, e.g. when binding to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    pub fn term_signal(&self) -> Arc<AtomicBool> {
+        self.term_signal.clone()
+    }
+
+    /// Serve requests until the termination signal is set. The socket file
+    /// of unix listeners is removed when this returns.
+    pub async fn serve(self) -> Result<(), String> {
+        info!("Starting MANY server on {}", self.addr);
+        while !self.term_signal.load(Ordering::Relaxed) {
+            match self.server.recv_timeout(TERM_CHECK_INTERVAL) {
+                Ok(Some(request)) => self.handle(request).await,
+                Ok(None) => {}
+                Err(e) => return Err(format!("{}: {}", self.addr, e)),
+            }
+        }
+        info!("Stopped MANY server on {}", self.addr);
+        Ok(())
+    }
+
+    async fn handle(&self, mut request: tiny_http::Request) {
+        if let Some(requests) = &self.requests {
+            requests.inc();
+        }
+
+        let mut body = Vec::new();
+        if let Err(e) = request
+            .as_reader()
+            .take(READ_BUFFER_LEN)
+            .read_to_end(&mut body)
+        {
+            debug!("Could not read request: {}", e);
+            let _ = request.respond(tiny_http::Response::empty(400));
+            return;
+        }
+
+        let envelope = match CoseSign1::from_slice(&body) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                debug!("Invalid envelope: {}", e);
+                let _ = request.respond(tiny_http::Response::empty(400));
+                return;
+            }
+        };
+
+        let response = self
+            .executor
+            .execute(envelope)
+            .await
+            .and_then(|r| r.to_vec().map_err(|e| e.to_string()));
+
+        // Ignore errors on return.
+        let _ = match response {
+            Ok(bytes) => request.respond(tiny_http::Response::from_data(bytes)),
+            Err(e) => {
+                debug!("Could not execute request: {}", e);
+                request.respond(tiny_http::Response::empty(500))
+            }
+        };
+    }
+}
@@ -0,0 +1,65 @@
+use many_protocol::ResponseMessage;
+use many_types::attributes::Attribute;
+use std::ops::RangeInclusive;
+use tendermint_proto::abci::{Event, EventAttribute};
+
+/// Attributes with an ID in this range are annotations of the node which
+/// answered, like the location of a transaction or a warning depending on
+/// its configuration. They are not part of consensus.
+pub const LOCAL_ATTRIBUTES: RangeInclusive<u32> = 1000..=1999;
+
+/// The type of the tendermint event carrying a local attribute of the
+/// response of a transaction, as hexadecimal CBOR. Tendermint does not hash
+/// events in the results of a block, unlike the data of the transaction.
+pub const LOCAL_ATTRIBUTE_EVENT: &str = "many_local_attribute";
+
+const LOCAL_ATTRIBUTE_KEY: &str = "attribute";
+
+pub fn is_local(attribute: &Attribute) -> bool {
+    LOCAL_ATTRIBUTES.contains(&attribute.id)
+}
+
+/// Remove the local attributes of the response of a transaction, returning
+/// them as events.
+pub fn split_local_attributes(mut response: ResponseMessage) -> (ResponseMessage, Vec<Event>) {
+    let (local, attributes): (Vec<Attribute>, Vec<Attribute>) =
+        response.attributes.iter().cloned().partition(is_local);
+    response.attributes = attributes.into_iter().collect();
+
+    let events = local
+        .iter()
+        .filter_map(|attribute| minicbor::to_vec(attribute).ok())
+        .map(|cbor| Event {
+            r#type: LOCAL_ATTRIBUTE_EVENT.to_string(),
+            attributes: vec![EventAttribute {
+                key: LOCAL_ATTRIBUTE_KEY.to_string().into_bytes().into(),
+                value: hex::encode(cbor).into_bytes().into(),
+                index: false,
+            }],
+        })
+        .collect();
+    (response, events)
+}
+
+/// Add the local attributes found in the events of a transaction back to its
+/// response, given the type and the key/value pairs of each event.
+pub fn with_local_attributes<'a>(
+    mut response: ResponseMessage,
+    events: impl IntoIterator<Item = (&'a str, Vec<(&'a str, &'a str)>)>,
+) -> ResponseMessage {
+    for (kind, attributes) in events {
+        if kind != LOCAL_ATTRIBUTE_EVENT {
+            continue;
+        }
+        for (key, value) in attributes {
+            let attribute = hex::decode(value)
+                .ok()
+                .and_then(|cbor| minicbor::decode::<Attribute>(&cbor).ok())
+                .filter(is_local);
+            if let (LOCAL_ATTRIBUTE_KEY, Some(attribute)) = (key, attribute) {
+                response = response.with_attribute(attribute);
+            }
+        }
+    }
+    response
+}
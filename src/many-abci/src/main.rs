@@ -6,9 +6,9 @@ use many_identity_dsa::{CoseKeyIdentity, CoseKeyVerifier};
 use many_identity_webauthn::WebAuthnVerifier;
 use many_modules::{base, blockchain, r#async};
 use many_protocol::ManyUrl;
-use many_server::transport::http::HttpServer;
 use many_server::ManyServer;
 use std::collections::BTreeSet;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use tendermint_abci::ServerBuilder;
@@ -17,12 +17,40 @@ use tracing::{debug, error, info, trace};
 use tracing_subscriber::filter::LevelFilter;
 
 mod abci_app;
+mod backend;
+mod backend_status;
+mod block_digest;
+mod error;
+#[cfg(feature = "testing-hooks")]
+mod faults;
+mod listener;
+mod local_attribute;
 mod many_app;
+mod mempool;
+mod metrics;
 mod module;
+mod read_pool;
+mod reindex;
+mod replay;
+mod request_index;
+mod tx_location;
+mod validator;
 
 use abci_app::AbciApp;
-use many_app::AbciModuleMany;
+use backend::{Backend, BackendMetrics, SystemResolver};
+use backend_status::{SharedStatus, StatusMetrics, StatusSource};
+use block_digest::{BlockDigestModule, BlockDigests};
+use listener::{ListenAddr, Listener, ListenerMetrics};
+use many_app::{AbciModuleMany, AbciStatusSource};
+use mempool::{MempoolGate, MempoolLimits, MempoolMetrics, TendermintMempool};
+use metrics::BlockMetrics;
 use module::AbciBlockchainModuleImpl;
+use read_pool::ReadPool;
+use reindex::{ReindexCacheModule, Reindexer, MAXIMUM_REINDEX_BLOCKS};
+use replay::{HeightRange, TendermintSource};
+use request_index::{FindRequestModule, RequestIndex, DEFAULT_REQUEST_INDEX_CAPACITY};
+use tx_location::{TxLocationModule, TxLocations};
+use validator::{ValidatorUpdates, ValidatorUpdatesModule};
 
 #[derive(clap::ArgEnum, Clone, Debug)]
 enum LogStrategy {
@@ -33,24 +61,44 @@ enum LogStrategy {
 #[derive(Debug, Parser)]
 struct Opts {
     /// Address and port to bind the ABCI server to.
-    #[clap(long)]
-    abci: String,
+    #[clap(long, required_unless_present_any = &["replay", "reindex_cache"])]
+    abci: Option<String>,
 
     /// URL for the tendermint server. Tendermint must already be running.
     #[clap(long)]
     tendermint: String,
 
     /// URL (including scheme) that has the MANY application running.
+    #[clap(long, required_unless_present_any = &["replay", "reindex_cache"])]
+    many_app: Option<String>,
+
+    /// URL (including scheme) of a read replica of the MANY application,
+    /// e.g. a stateless copy of it running against a read-only snapshot.
+    /// Queries are load-balanced on the read replicas in round-robin, while
+    /// commands and the calls of Tendermint go to `--many-app`. Multiple
+    /// occurrences of this argument can be given.
     #[clap(long)]
-    many_app: String,
+    many_app_read: Vec<String>,
 
-    /// Address and port to bind the MANY server to.
-    #[clap(long)]
-    many: String,
+    /// Time between two health checks of the read replicas. A replica
+    /// failing a query or a health check is taken out of rotation until it
+    /// passes one again.
+    #[clap(long, default_value = "10s")]
+    read_probe_interval: humantime::Duration,
+
+    /// Address and port to bind the MANY server to, or `unix:<path>` for a
+    /// unix domain socket. Multiple occurrences of this argument can be given.
+    #[clap(long, required_unless_present_any = &["replay", "reindex_cache"])]
+    many: Vec<ListenAddr>,
+
+    /// Permissions of the unix domain sockets of the MANY server, in octal
+    /// (e.g. 660). Uses the process umask if not specified.
+    #[clap(long, parse(try_from_str = parse_socket_mode))]
+    many_socket_mode: Option<u32>,
 
     /// A pem file for the MANY frontend.
-    #[clap(long)]
-    many_pem: PathBuf,
+    #[clap(long, required_unless_present_any = &["replay", "reindex_cache"])]
+    many_pem: Option<PathBuf>,
 
     /// The default server read buffer size, in bytes, for each incoming client connection.
     #[clap(short, long, default_value = "1048576")]
@@ -66,7 +114,7 @@ struct Opts {
 
     /// Application absolute URLs allowed to communicate with this server. Any
     /// application will be able to communicate with this server if left empty.
-    /// Multiple occurences of this argument can be given.
+    /// Multiple occurrences of this argument can be given.
     #[clap(long)]
     allow_origin: Option<Vec<ManyUrl>>,
 
@@ -79,6 +127,182 @@ struct Opts {
     /// Any addresses will be able to execute queries, e.g., balance, get, ...
     #[clap(long)]
     allow_addrs: Option<PathBuf>,
+
+    /// Address and port to serve Prometheus metrics on, at `/metrics`.
+    /// Disabled if not specified.
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Minimum time between two reconnections to the MANY application, when
+    /// requests to it keep failing. Its host name is resolved again on each
+    /// reconnection.
+    #[clap(long, default_value = "10s")]
+    backend_reconnect_interval: humantime::Duration,
+
+    /// Time between two refreshes of the status and endpoints of the MANY
+    /// application, so changes to it are served without restarting. When a
+    /// refresh fails, the last known status is kept and reported as stale.
+    #[clap(long, default_value = "60s")]
+    status_refresh_interval: humantime::Duration,
+
+    /// Replay the transactions of an inclusive range of blocks, given as
+    /// `<from>..<to>`, against the backend at `--replay-target`, then exit.
+    /// The results are compared with the ones recorded by tendermint, and
+    /// the first divergence is reported.
+    #[clap(long, requires = "replay_target")]
+    replay: Option<HeightRange>,
+
+    /// URL of the MANY backend to replay transactions against. It must be a
+    /// fresh instance at the state of the block before the replayed range.
+    #[clap(long)]
+    replay_target: Option<String>,
+
+    /// Path of a file where the commands broadcast by this node are recorded
+    /// by sender, so clients can find them with `abci.findRequest`. Disabled
+    /// if not specified.
+    #[clap(long)]
+    request_index: Option<PathBuf>,
+
+    /// Maximum number of commands kept in the request index.
+    #[clap(long, default_value_t = DEFAULT_REQUEST_INDEX_CAPACITY)]
+    request_index_capacity: usize,
+
+    /// Maximum age of the commands kept in the request index.
+    #[clap(long, default_value = "30days")]
+    request_index_max_age: humantime::Duration,
+
+    /// Rebuild the request index at `--request-index` from the blocks kept by
+    /// tendermint, then exit. The bridge should be stopped meanwhile. The
+    /// progress is saved next to the request index, and an interrupted
+    /// reindexing resumes where it stopped.
+    #[clap(long, requires = "request_index")]
+    reindex_cache: bool,
+
+    /// The height to start reindexing from, when no reindexing was started
+    /// before. Blocks pruned by tendermint are skipped.
+    #[clap(long, default_value_t = 1)]
+    reindex_from_height: u64,
+
+    /// Path to a JSON file containing an array of MANY addresses allowed to
+    /// reindex the request index and the transaction locations of this node
+    /// from the chain history with `abci.reindexCache`, while it runs.
+    /// Disabled if not specified.
+    #[clap(long)]
+    reindex_admins: Option<PathBuf>,
+
+    /// The identity of the MANY application. When specified, its responses
+    /// which are not signed by this identity are refused, e.g. when it runs
+    /// on another host, and the bridge does not start if the application
+    /// has another identity.
+    #[clap(long)]
+    backend_id: Option<Address>,
+
+    /// Number of transactions in the mempool of Tendermint from which new
+    /// commands are refused with a "mempool congested" error, instead of
+    /// being broadcast. Queries are always served. Disabled if not
+    /// specified.
+    #[clap(long)]
+    mempool_high_water: Option<u64>,
+
+    /// Number of transactions in the mempool under which commands are
+    /// accepted again, once refused. Defaults to half of
+    /// `--mempool-high-water`.
+    #[clap(long, requires = "mempool_high_water")]
+    mempool_low_water: Option<u64>,
+
+    /// Time during which the number of transactions in the mempool is
+    /// reused, before reading it again from Tendermint.
+    #[clap(long, default_value = "1s")]
+    mempool_check_interval: humantime::Duration,
+
+    /// The delay after which the clients refused are told to retry.
+    #[clap(long, default_value = "10s")]
+    mempool_retry_after: humantime::Duration,
+
+    /// A control file of faults to inject in the calls to Tendermint and to
+    /// the backend, read whenever it exists and removed once read. Each line
+    /// has the format `<method> <drop|delay:<duration>|error[:<code>]> [<count>]`.
+    /// This requires the feature "testing-hooks" to be enabled.
+    #[cfg(feature = "testing-hooks")]
+    #[clap(long)]
+    faults_only_for_testing: Option<PathBuf>,
+}
+
+fn parse_socket_mode(s: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(s, 8)
+}
+
+/// Replay a range of blocks and report the first divergence. Returns the
+/// exit code of the process.
+async fn run_replay(tendermint: &str, target: &str, range: HeightRange) -> i32 {
+    let source = TendermintSource::new(tendermint_rpc::HttpClient::new(tendermint).unwrap());
+    let target = target.to_string();
+    let report = tokio::task::spawn_blocking(move || {
+        let app = AbciApp::create(target, Address::anonymous())?;
+        replay::replay(&source, &app, range)
+    })
+    .await
+    .unwrap();
+
+    match report {
+        Ok(report) => {
+            println!(
+                "Replayed {} blocks and {} transactions.",
+                report.blocks, report.txs
+            );
+            match report.divergence {
+                Some(divergence) => {
+                    println!("{}", divergence);
+                    2
+                }
+                None => {
+                    println!("No divergence.");
+                    0
+                }
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            1
+        }
+    }
+}
+
+/// Rebuild a request index from the chain history. Returns the exit code of
+/// the process.
+async fn run_reindex(
+    tendermint: &str,
+    index: RequestIndex,
+    progress_path: PathBuf,
+    from_height: u64,
+) -> i32 {
+    let source = TendermintSource::new(tendermint_rpc::HttpClient::new(tendermint).unwrap());
+    let result = tokio::task::spawn_blocking(move || {
+        let mut reindexer = Reindexer::new(source)
+            .with_request_index(Arc::new(index))
+            .with_progress_file(progress_path)?;
+        if !reindexer.started() {
+            reindexer.restart(from_height);
+        }
+        reindexer.run(MAXIMUM_REINDEX_BLOCKS)?;
+        Ok::<_, String>(reindexer.progress().clone())
+    })
+    .await
+    .unwrap();
+
+    match result {
+        Ok(progress) => {
+            println!(
+                "Reindexed {} transactions, skipped {} pruned blocks.",
+                progress.txs, progress.pruned
+            );
+            0
+        }
+        Err(e) => {
+            error!("{}", e);
+            1
+        }
+    }
 }
 
 #[tokio::main]
@@ -87,7 +311,10 @@ async fn main() {
         abci,
         tendermint,
         many_app,
+        many_app_read,
+        read_probe_interval,
         many,
+        many_socket_mode,
         many_pem,
         abci_read_buf_size,
         verbose,
@@ -95,6 +322,23 @@ async fn main() {
         allow_origin,
         logmode,
         allow_addrs,
+        metrics_addr,
+        backend_reconnect_interval,
+        status_refresh_interval,
+        replay,
+        replay_target,
+        request_index,
+        request_index_capacity,
+        request_index_max_age,
+        reindex_cache,
+        reindex_from_height,
+        reindex_admins,
+        backend_id,
+        mempool_high_water,
+        mempool_low_water,
+        mempool_check_interval,
+        mempool_retry_after,
+        ..
     } = Opts::parse();
 
     let verbose_level = 2 + verbose - quiet;
@@ -130,6 +374,35 @@ async fn main() {
         git_sha = env!("VERGEN_GIT_SHA")
     );
 
+    if let (Some(range), Some(target)) = (replay, replay_target) {
+        std::process::exit(run_replay(&tendermint, &target, range).await);
+    }
+    let reindex_progress = request_index
+        .as_ref()
+        .map(|path| path.with_extension("reindex"));
+    let request_index = request_index.map(|path| {
+        RequestIndex::open(
+            &path,
+            request_index_capacity,
+            Some(request_index_max_age.into()),
+        )
+        .unwrap_or_else(|e| panic!("Could not open {}: {}", path.display(), e))
+    });
+    if reindex_cache {
+        std::process::exit(
+            run_reindex(
+                &tendermint,
+                request_index.unwrap(),
+                reindex_progress.unwrap(),
+                reindex_from_height,
+            )
+            .await,
+        );
+    }
+    let abci = abci.unwrap();
+    let many_app = many_app.unwrap();
+    let many_pem = many_pem.unwrap();
+
     // Try to get the status of the backend MANY app.
     let many_client = ManyClient::new(&many_app, Address::anonymous(), AnonymousIdentity).unwrap();
 
@@ -158,8 +431,95 @@ async fn main() {
         std::thread::sleep(std::time::Duration::from_secs(1));
     };
 
+    match backend_id {
+        Some(backend_id) if status.identity != backend_id => {
+            error!(
+                "The backend app has the identity {}, expected {}... Terminating.",
+                status.identity, backend_id
+            );
+            std::process::exit(1);
+        }
+        Some(backend_id) => info!("Verifying the responses of the backend app {}", backend_id),
+        None => {}
+    }
+
+    let (block_metrics, listener_metrics, backend_metrics, status_metrics, mempool_metrics) =
+        match metrics_addr {
+            Some(addr) => {
+                let registry = prometheus::Registry::new();
+                let block_metrics = BlockMetrics::with_registry(&registry).unwrap();
+                let listener_metrics = ListenerMetrics::new(&registry).unwrap();
+                let backend_metrics = BackendMetrics::new(&registry).unwrap();
+                let status_metrics = StatusMetrics::new(&registry).unwrap();
+                let mempool_metrics = MempoolMetrics::new(&registry).unwrap();
+                metrics::serve(addr, registry).expect("Could not start metrics server");
+                info!("Serving metrics on {}", addr);
+                (
+                    block_metrics,
+                    Some(listener_metrics),
+                    Some(backend_metrics),
+                    Some(status_metrics),
+                    Some(mempool_metrics),
+                )
+            }
+            None => (BlockMetrics::default(), None, None, None, None),
+        };
+
+    // The locations of the transactions delivered, shared with the async
+    // module.
+    let tx_locations = Arc::new(TxLocations::default());
+    let app_tx_locations = tx_locations.clone();
+
+    // The digests of the blocks executed, shared with the block digest
+    // module.
+    let block_digests = Arc::new(BlockDigests::default());
+    let app_block_digests = block_digests.clone();
+
+    // The validator updates, shared with the module listing the pending
+    // ones.
+    let validator_updates = Arc::new(ValidatorUpdates::default());
+    let app_validator_updates = validator_updates.clone();
+
+    #[cfg(feature = "testing-hooks")]
+    let faults = {
+        let Opts {
+            faults_only_for_testing,
+            ..
+        } = Opts::parse();
+        let faults = faults::Faults::default();
+        Arc::new(match faults_only_for_testing {
+            Some(path) => {
+                tracing::warn!("Injecting the faults of {}", path.display());
+                faults.with_control_file(path)
+            }
+            None => faults,
+        })
+    };
+    #[cfg(feature = "testing-hooks")]
+    let backend_faults = faults.clone();
+
     let abci_app = tokio::task::spawn_blocking(move || {
-        AbciApp::create(many_app, Address::anonymous()).unwrap()
+        let backend = Backend::new(many_app.as_str(), Arc::new(SystemResolver))
+            .unwrap()
+            .with_reconnect_interval(backend_reconnect_interval.into());
+        let backend = match &backend_metrics {
+            Some(metrics) => backend.with_metrics(metrics),
+            None => backend,
+        };
+        let backend = match backend_id {
+            Some(backend_id) => backend.with_identity(backend_id),
+            None => backend,
+        };
+        #[cfg(feature = "testing-hooks")]
+        let backend = backend.with_faults(backend_faults);
+
+        AbciApp::create(many_app, Address::anonymous())
+            .unwrap()
+            .with_metrics(Arc::new(block_metrics))
+            .with_backend(Arc::new(backend))
+            .with_tx_locations(app_tx_locations)
+            .with_validator_updates(app_validator_updates)
+            .with_block_digests(app_block_digests)
     })
     .await
     .unwrap();
@@ -186,6 +546,9 @@ async fn main() {
         std::thread::sleep(std::time::Duration::from_secs(1));
     }
 
+    #[cfg(feature = "testing-hooks")]
+    let abci_client = faults::FaultyClient::new(abci_client, faults);
+
     let key = CoseKeyIdentity::from_pem(&std::fs::read_to_string(&many_pem).unwrap()).unwrap();
     info!(many_address = key.address().to_string().as_str());
     let server = ManyServer::new(
@@ -200,32 +563,166 @@ async fn main() {
     );
     let allowed_addrs: Option<BTreeSet<Address>> =
         allow_addrs.map(|path| json5::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap());
-    let backend = AbciModuleMany::new(abci_client.clone(), status, key, allowed_addrs).await;
-    let blockchain_impl = Arc::new(Mutex::new(AbciBlockchainModuleImpl::new(abci_client)));
+    let request_index = request_index.map(Arc::new);
+    let status_source = AbciStatusSource::new(many_client, abci_client.clone(), key.clone());
+    let status_source = match backend_id {
+        Some(backend_id) => status_source.with_backend_id(backend_id),
+        None => status_source,
+    };
+    let backend_info = status_source
+        .fetch()
+        .await
+        .unwrap_or_else(|e| panic!("Could not get the endpoints of the backend: {}", e));
+    let shared_status = SharedStatus::new(backend_info);
+    let shared_status = Arc::new(match &status_metrics {
+        Some(metrics) => shared_status.with_metrics(metrics),
+        None => shared_status,
+    });
+    tokio::spawn(backend_status::run(
+        status_source,
+        shared_status.clone(),
+        status_refresh_interval.into(),
+    ));
+    let backend = AbciModuleMany::new(abci_client.clone(), shared_status, key, allowed_addrs);
+    let backend = match backend_id {
+        Some(backend_id) => backend.with_backend_id(backend_id),
+        None => backend,
+    };
+    let backend = match mempool_high_water {
+        Some(high_water) => {
+            let limits = MempoolLimits {
+                high_water,
+                low_water: mempool_low_water.unwrap_or(high_water / 2),
+                check_interval: mempool_check_interval.into(),
+                retry_after: mempool_retry_after.into(),
+            };
+            if limits.low_water >= limits.high_water {
+                error!(
+                    "The mempool low water mark ({}) must be below the high water mark ({})... Terminating.",
+                    limits.low_water, limits.high_water
+                );
+                std::process::exit(1);
+            }
+            let source = TendermintMempool::new(tendermint.as_str())
+                .unwrap_or_else(|e| panic!("Invalid Tendermint URL {}: {}", tendermint, e));
+            let gate = MempoolGate::new(source, limits);
+            let gate = match &mempool_metrics {
+                Some(metrics) => gate.with_metrics(metrics),
+                None => gate,
+            };
+            info!(
+                "Refusing commands from {} transactions in the mempool, until {} or less",
+                limits.high_water, limits.low_water
+            );
+            backend.with_mempool_gate(Arc::new(gate))
+        }
+        None => backend,
+    };
+    let backend = if many_app_read.is_empty() {
+        backend
+    } else {
+        let read_pool = tokio::task::spawn_blocking(move || {
+            let replicas = many_app_read
+                .iter()
+                .map(|url| {
+                    let replica = Backend::new(url.as_str(), Arc::new(SystemResolver))
+                        .unwrap_or_else(|e| panic!("Could not connect to {}: {}", url, e))
+                        .with_reconnect_interval(backend_reconnect_interval.into());
+                    match backend_id {
+                        Some(backend_id) => replica.with_identity(backend_id),
+                        None => replica,
+                    }
+                })
+                .collect();
+            Arc::new(ReadPool::new(replicas))
+        })
+        .await
+        .unwrap();
+        info!("Sending the queries to {} read replicas", read_pool.len());
+        tokio::spawn(read_pool::run(
+            read_pool.clone(),
+            read_probe_interval.into(),
+        ));
+        backend.with_read_pool(read_pool)
+    };
+    let reindexer = reindex_admins.map(|path| {
+        let admins: BTreeSet<Address> =
+            json5::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let source =
+            TendermintSource::new(tendermint_rpc::HttpClient::new(tendermint.as_str()).unwrap());
+        let reindexer = Reindexer::new(source)
+            .with_tx_locations(tx_locations.clone())
+            .with_admins(admins);
+        let reindexer = match (&request_index, reindex_progress) {
+            (Some(index), Some(progress)) => reindexer
+                .with_request_index(index.clone())
+                .with_progress_file(&progress)
+                .unwrap_or_else(|e| panic!("Could not read {}: {}", progress.display(), e)),
+            _ => reindexer,
+        };
+        Arc::new(Mutex::new(reindexer))
+    });
+    let mut blockchain_impl = AbciBlockchainModuleImpl::new(abci_client)
+        .with_tx_locations(tx_locations)
+        .with_validator_updates(validator_updates)
+        .with_block_digests(block_digests);
+    let backend = match &request_index {
+        Some(index) => {
+            blockchain_impl = blockchain_impl.with_request_index(index.clone());
+            backend.with_request_index(index.clone())
+        }
+        None => backend,
+    };
+    let blockchain_impl = Arc::new(Mutex::new(blockchain_impl));
 
     {
         let mut s = server.lock().unwrap();
         s.add_module(base::BaseModule::new(server.clone()));
         s.add_module(blockchain::BlockchainModule::new(blockchain_impl.clone()));
+        s.add_module(TxLocationModule::new(blockchain_impl.clone()));
+        s.add_module(ValidatorUpdatesModule::new(blockchain_impl.clone()));
+        s.add_module(BlockDigestModule::new(blockchain_impl.clone()));
+        if request_index.is_some() {
+            s.add_module(FindRequestModule::new(blockchain_impl.clone()));
+        }
+        if let Some(reindexer) = reindexer {
+            s.add_module(ReindexCacheModule::new(reindexer));
+        }
         s.add_module(r#async::AsyncModule::new(blockchain_impl));
         s.set_fallback_module(backend);
     }
 
-    let mut many_server = HttpServer::new(server);
-
-    signal_hook::flag::register(signal_hook::consts::SIGTERM, many_server.term_signal())
-        .expect("Could not register signal handler");
-    signal_hook::flag::register(signal_hook::consts::SIGHUP, many_server.term_signal())
-        .expect("Could not register signal handler");
-    signal_hook::flag::register(signal_hook::consts::SIGINT, many_server.term_signal())
-        .expect("Could not register signal handler");
+    let listeners: Vec<_> = many
+        .into_iter()
+        .map(|addr| {
+            let listener = Listener::bind(addr.clone(), server.clone(), many_socket_mode)
+                .unwrap_or_else(|e| panic!("Could not bind to {}: {}", addr, e));
+            match &listener_metrics {
+                Some(metrics) => listener.with_metrics(metrics),
+                None => listener,
+            }
+        })
+        .collect();
+
+    for listener in &listeners {
+        for signal in [
+            signal_hook::consts::SIGTERM,
+            signal_hook::consts::SIGHUP,
+            signal_hook::consts::SIGINT,
+        ] {
+            signal_hook::flag::register(signal, listener.term_signal())
+                .expect("Could not register signal handler");
+        }
+    }
 
-    info!("Starting MANY server on addr {}", many.clone());
-    match many_server.bind(many).await {
-        Ok(_) => {}
-        Err(error) => {
+    // Each listener stops on its own; an error on one does not stop the others.
+    let handles: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| tokio::spawn(listener.serve()))
+        .collect();
+    for handle in handles {
+        if let Err(error) = handle.await.unwrap() {
             error!("{}", error);
-            panic!("Error happened in many: {:?}", error);
         }
     }
 
@@ -1,8 +1,15 @@
+use crate::backend::verify_response;
+use crate::backend_status::{BackendInfo, SharedStatus, StatusSource};
+use crate::mempool::MempoolGate;
+use crate::read_pool::ReadPool;
+use crate::request_index::{RequestIndex, RequestRecord};
+use crate::validator::UPDATE_VALIDATOR_METHOD;
 use async_trait::async_trait;
 use coset::{CborSerializable, CoseSign1};
+use many_client::ManyClient;
 use many_error::ManyError;
 use many_identity::verifiers::AnonymousVerifier;
-use many_identity::{Address, Identity};
+use many_identity::{Address, AnonymousIdentity, Identity};
 use many_identity_dsa::{CoseKeyIdentity, CoseKeyVerifier};
 use many_modules::abci_backend::{AbciInit, EndpointInfo, ABCI_MODULE_ATTRIBUTE};
 use many_modules::base;
@@ -14,57 +21,188 @@ use many_protocol::{
 use many_server::transport::LowLevelManyRequestHandler;
 use many_types::attributes::Attribute;
 use many_types::cbor::CborAny;
+use many_types::Timestamp;
 use std::collections::{BTreeMap, BTreeSet};
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
 use tendermint_rpc::Client;
+use tracing::{error, warn};
 
-pub struct AbciModuleMany<C: Client> {
-    client: C,
-    backend_status: base::Status,
+/// Fetches the status of the backend from its MANY server, and its
+/// endpoints with `abci.init` through Tendermint.
+pub struct AbciStatusSource<C: Client> {
+    many_client: ManyClient<AnonymousIdentity>,
+    abci_client: C,
     identity: CoseKeyIdentity,
-    backend_endpoints: BTreeMap<String, EndpointInfo>,
-    allow_addrs: Option<BTreeSet<Address>>,
+    backend_id: Option<Address>,
 }
 
-impl<C: Client + Sync> AbciModuleMany<C> {
-    pub async fn new(
-        client: C,
-        backend_status: base::Status,
+impl<C: Client> AbciStatusSource<C> {
+    pub fn new(
+        many_client: ManyClient<AnonymousIdentity>,
+        abci_client: C,
         identity: CoseKeyIdentity,
-        allow_addrs: Option<BTreeSet<Address>>,
     ) -> Self {
+        Self {
+            many_client,
+            abci_client,
+            identity,
+            backend_id: None,
+        }
+    }
+
+    /// Refuse the status and endpoints of a backend with another identity.
+    pub fn with_backend_id(self, backend_id: Address) -> Self {
+        Self {
+            backend_id: Some(backend_id),
+            ..self
+        }
+    }
+
+    async fn endpoints(&self) -> Result<BTreeMap<String, EndpointInfo>, ManyError> {
         let init_message = RequestMessageBuilder::default()
-            .from(identity.address())
+            .from(self.identity.address())
             .method("abci.init".to_string())
             .build()
-            .unwrap();
-        let data = encode_cose_sign1_from_request(init_message, &identity)
-            .unwrap()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = encode_cose_sign1_from_request(init_message, &self.identity)
+            .map_err(ManyError::unexpected_transport_error)?
             .to_vec()
-            .unwrap();
+            .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+
+        let response = self
+            .abci_client
+            .abci_query(None, data, None, false)
+            .await
+            .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))?;
+        let response = CoseSign1::from_slice(&response.value)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        let response = match &self.backend_id {
+            Some(backend_id) => verify_response(&response, backend_id),
+            None => decode_response_from_cose_sign1(
+                &response,
+                None,
+                &(AnonymousVerifier, CoseKeyVerifier),
+            ),
+        }
+        .map_err(ManyError::unexpected_transport_error)?;
+        let init_message: AbciInit = minicbor::decode(&response.data?)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+        Ok(init_message.endpoints)
+    }
+}
+
+#[async_trait]
+impl<C: Client + Send + Sync> StatusSource for AbciStatusSource<C> {
+    async fn fetch(&self) -> Result<BackendInfo, ManyError> {
+        let status = self
+            .many_client
+            .status()
+            .await
+            .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))?;
+        if let Some(backend_id) = &self.backend_id {
+            if &status.identity != backend_id {
+                return Err(ManyError::unexpected_transport_error(format!(
+                    "The backend has the identity {}, expected {}.",
+                    status.identity, backend_id
+                )));
+            }
+        }
+        BackendInfo::new(status, self.endpoints().await?)
+    }
+}
 
-        let response = client.abci_query(None, data, None, false).await.unwrap();
-        let response = CoseSign1::from_slice(&response.value).unwrap();
-        let response =
-            decode_response_from_cose_sign1(&response, None, &(AnonymousVerifier, CoseKeyVerifier))
-                .unwrap();
-        let init_message: AbciInit = minicbor::decode(&response.data.unwrap()).unwrap();
+pub struct AbciModuleMany<C: Client> {
+    client: C,
+    backend: Arc<SharedStatus>,
+    identity: CoseKeyIdentity,
+    allow_addrs: Option<BTreeSet<Address>>,
+    request_index: Option<Arc<RequestIndex>>,
+    backend_id: Option<Address>,
+    read_pool: Option<Arc<ReadPool>>,
+    mempool_gate: Option<Arc<MempoolGate>>,
+}
 
+impl<C: Client + Sync> AbciModuleMany<C> {
+    /// The status and endpoints of the backend are read from `backend` for
+    /// every message, so they follow its refreshes.
+    pub fn new(
+        client: C,
+        backend: Arc<SharedStatus>,
+        identity: CoseKeyIdentity,
+        allow_addrs: Option<BTreeSet<Address>>,
+    ) -> Self {
         Self {
             client,
-            backend_status,
+            backend,
             identity,
-            backend_endpoints: init_message.endpoints,
             allow_addrs,
+            request_index: None,
+            backend_id: None,
+            read_pool: None,
+            mempool_gate: None,
+        }
+    }
+
+    /// Refuse the responses to queries which are not signed by the backend.
+    pub fn with_backend_id(self, backend_id: Address) -> Self {
+        Self {
+            backend_id: Some(backend_id),
+            ..self
+        }
+    }
+
+    /// Record the commands broadcast, so their senders can find them.
+    pub fn with_request_index(self, request_index: Arc<RequestIndex>) -> Self {
+        Self {
+            request_index: Some(request_index),
+            ..self
+        }
+    }
+
+    /// Send the queries to the replicas of `read_pool` instead of going
+    /// through Tendermint to the primary backend, which is only queried when
+    /// no replica is available.
+    pub fn with_read_pool(self, read_pool: Arc<ReadPool>) -> Self {
+        Self {
+            read_pool: Some(read_pool),
+            ..self
+        }
+    }
+
+    /// Refuse commands while the mempool of Tendermint is congested. Queries
+    /// are always served.
+    pub fn with_mempool_gate(self, mempool_gate: Arc<MempoolGate>) -> Self {
+        Self {
+            mempool_gate: Some(mempool_gate),
+            ..self
+        }
+    }
+
+    /// The endpoint of a method, which is either one of the backend or a
+    /// validator update. The sender of a validator update is only checked
+    /// when it is executed, against the admins in the state of the backend.
+    fn route(&self, method: &str) -> Option<EndpointInfo> {
+        if method == UPDATE_VALIDATOR_METHOD {
+            Some(EndpointInfo { is_command: true })
+        } else {
+            self.backend.route(method)
         }
     }
 
     async fn execute_message(&self, envelope: CoseSign1) -> Result<CoseSign1, ManyError> {
         let message =
             decode_request_from_cose_sign1(&envelope, &(AnonymousVerifier, CoseKeyVerifier))?;
-        if let Some(info) = self.backend_endpoints.get(&message.method) {
+        if let Some(info) = self.route(&message.method) {
             let is_command = info.is_command;
+            if let (false, Some(pool)) = (is_command, &self.read_pool) {
+                match pool.send_envelope(envelope.clone()).await {
+                    Ok(envelope) => return Ok(envelope),
+                    Err(e) => warn!("Querying the primary backend instead: {}", e),
+                }
+            }
+
             let data = envelope
                 .to_vec()
                 .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))?;
@@ -76,6 +214,9 @@ impl<C: Client + Sync> AbciModuleMany<C> {
                 {
                     return Err(ManyError::invalid_from_identity());
                 }
+                if let Some(gate) = &self.mempool_gate {
+                    gate.check().await?;
+                }
 
                 let response = self
                     .client
@@ -83,6 +224,20 @@ impl<C: Client + Sync> AbciModuleMany<C> {
                     .await
                     .map_err(ManyError::unexpected_transport_error)?;
 
+                if let Some(index) = &self.request_index {
+                    let now = Timestamp::now();
+                    let record = RequestRecord {
+                        sender: message.from(),
+                        nonce: message.nonce.clone().map(Into::into),
+                        timestamp: message.timestamp.unwrap_or(now),
+                        hash: response.hash.as_bytes().to_vec().into(),
+                        broadcast_at: now,
+                    };
+                    if let Err(e) = index.record(record) {
+                        warn!("Could not record request: {}", e);
+                    }
+                }
+
                 // A command will always return an empty payload with an ASYNC attribute.
                 let response =
                     ResponseMessage::from_request(&message, &self.identity.address(), Ok(vec![]))
@@ -99,8 +254,15 @@ impl<C: Client + Sync> AbciModuleMany<C> {
                     .await
                     .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))?;
 
-                CoseSign1::from_slice(&response.value)
-                    .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))
+                let envelope = CoseSign1::from_slice(&response.value)
+                    .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))?;
+                if let Some(backend_id) = &self.backend_id {
+                    verify_response(&envelope, backend_id).map_err(|e| {
+                        error!("REJECTED a response of the backend: {}", e);
+                        ManyError::unexpected_transport_error(e)
+                    })?;
+                }
+                Ok(envelope)
             }
         } else {
             Err(ManyError::invalid_method_name(message.method))
@@ -132,13 +294,14 @@ impl<C: Client + Sync + Send> LowLevelManyRequestHandler for AbciModuleMany<C> {
 impl<C: Client + Sync + Send> base::BaseModuleBackend for AbciModuleMany<C> {
     fn endpoints(&self) -> Result<base::Endpoints, ManyError> {
         Ok(base::Endpoints(BTreeSet::from_iter(
-            self.backend_endpoints.keys().cloned(),
+            self.backend.get().endpoints.keys().cloned(),
         )))
     }
 
     fn status(&self) -> Result<base::Status, ManyError> {
-        let attributes: BTreeSet<Attribute> = self
-            .backend_status
+        let backend = self.backend.get();
+        let attributes: BTreeSet<Attribute> = backend
+            .status
             .attributes
             .iter()
             .filter(|x| x.id != ABCI_MODULE_ATTRIBUTE.id)
@@ -148,7 +311,7 @@ impl<C: Client + Sync + Send> base::BaseModuleBackend for AbciModuleMany<C> {
         let mut builder = base::StatusBuilder::default();
 
         builder
-            .name(format!("AbciModule({})", self.backend_status.name))
+            .name(format!("AbciModule({})", backend.status.name))
             .version(1)
             .identity(self.identity.address())
             .attributes(attributes.into_iter().collect())
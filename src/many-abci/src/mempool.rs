@@ -0,0 +1,215 @@
+use crate::error;
+use async_trait::async_trait;
+use many_error::ManyError;
+use prometheus::{IntCounter, IntGauge, Registry};
+use reqwest::{IntoUrl, Url};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Where the number of transactions waiting in the mempool is read from.
+#[async_trait]
+pub trait MempoolSource: Send + Sync {
+    async fn depth(&self) -> Result<u64, ManyError>;
+}
+
+#[async_trait]
+impl<S: MempoolSource + ?Sized> MempoolSource for Arc<S> {
+    async fn depth(&self) -> Result<u64, ManyError> {
+        self.as_ref().depth().await
+    }
+}
+
+/// Reads the depth of the mempool from the `/num_unconfirmed_txs` endpoint
+/// of the Tendermint RPC.
+pub struct TendermintMempool {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl TendermintMempool {
+    pub fn new<U: IntoUrl>(tendermint: U) -> Result<Self, String> {
+        let url = tendermint
+            .into_url()
+            .map_err(|e| e.to_string())?
+            .join("num_unconfirmed_txs")
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url,
+        })
+    }
+}
+
+#[async_trait]
+impl MempoolSource for TendermintMempool {
+    async fn depth(&self) -> Result<u64, ManyError> {
+        let body = self
+            .client
+            .get(self.url.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))?
+            .bytes()
+            .await
+            .map_err(|e| ManyError::unexpected_transport_error(e.to_string()))?;
+        let body: serde_json::Value = serde_json::from_slice(&body)
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+        // Tendermint encodes its integers as strings.
+        body["result"]["total"]
+            .as_str()
+            .and_then(|total| total.parse().ok())
+            .ok_or_else(|| {
+                ManyError::deserialization_error(format!("No number of transactions in {}.", body))
+            })
+    }
+}
+
+/// The thresholds of the gate, in number of transactions in the mempool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MempoolLimits {
+    /// The depth from which commands are refused.
+    pub high_water: u64,
+
+    /// The depth under which commands are accepted again, once refused.
+    pub low_water: u64,
+
+    /// How long the depth read is used before reading it again.
+    pub check_interval: Duration,
+
+    /// The delay suggested to the clients refused.
+    pub retry_after: Duration,
+}
+
+/// Gauges and counters of the gate.
+#[derive(Clone)]
+pub struct MempoolMetrics {
+    depth: IntGauge,
+    closed: IntGauge,
+    rejections: IntCounter,
+}
+
+impl MempoolMetrics {
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let depth = IntGauge::new(
+            "many_abci_mempool_depth",
+            "Number of transactions in the mempool, as last read.",
+        )?;
+        let closed = IntGauge::new(
+            "many_abci_mempool_gate_closed",
+            "1 if commands are refused because the mempool is congested.",
+        )?;
+        let rejections = IntCounter::new(
+            "many_abci_mempool_rejections_total",
+            "Number of commands refused because the mempool was congested.",
+        )?;
+        registry.register(Box::new(depth.clone()))?;
+        registry.register(Box::new(closed.clone()))?;
+        registry.register(Box::new(rejections.clone()))?;
+        Ok(Self {
+            depth,
+            closed,
+            rejections,
+        })
+    }
+}
+
+struct GateState {
+    depth: u64,
+    read_at: Option<Instant>,
+    closed: bool,
+}
+
+/// Refuses commands while the mempool is congested, instead of letting
+/// their broadcast fail. The gate closes when the depth reaches the high
+/// water mark, and only opens again under the low water mark, so it does
+/// not flap around a single threshold. When the depth cannot be read, the
+/// gate stays as it was.
+pub struct MempoolGate {
+    source: Box<dyn MempoolSource>,
+    limits: MempoolLimits,
+    state: Mutex<GateState>,
+    metrics: Option<MempoolMetrics>,
+}
+
+impl MempoolGate {
+    pub fn new(source: impl MempoolSource + 'static, limits: MempoolLimits) -> Self {
+        Self {
+            source: Box::new(source),
+            limits,
+            state: Mutex::new(GateState {
+                depth: 0,
+                read_at: None,
+                closed: false,
+            }),
+            metrics: None,
+        }
+    }
+
+    pub fn with_metrics(self, metrics: &MempoolMetrics) -> Self {
+        Self {
+            metrics: Some(metrics.clone()),
+            ..self
+        }
+    }
+
+    /// Whether commands are currently refused.
+    pub async fn is_closed(&self) -> bool {
+        self.state.lock().await.closed
+    }
+
+    /// Check that a command can be broadcast, reading the depth of the
+    /// mempool again if the last one read is too old. The lock is held
+    /// while reading, so the commands arriving meanwhile wait for it instead
+    /// of all reading the depth.
+    pub async fn check(&self) -> Result<(), ManyError> {
+        let mut state = self.state.lock().await;
+        let fresh = state
+            .read_at
+            .map_or(false, |at| at.elapsed() < self.limits.check_interval);
+        if !fresh {
+            state.read_at = Some(Instant::now());
+            match self.source.depth().await {
+                Ok(depth) => self.update(&mut state, depth),
+                Err(e) => warn!("Could not read the depth of the mempool: {}", e),
+            }
+        }
+
+        if state.closed {
+            if let Some(metrics) = &self.metrics {
+                metrics.rejections.inc();
+            }
+            Err(error::mempool_congested(
+                state.depth,
+                self.limits.retry_after.as_secs(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn update(&self, state: &mut GateState, depth: u64) {
+        state.depth = depth;
+        if !state.closed && depth >= self.limits.high_water {
+            warn!(
+                "The mempool has {} transactions, refusing commands until it has {} or less",
+                depth, self.limits.low_water
+            );
+            state.closed = true;
+        } else if state.closed && depth <= self.limits.low_water {
+            info!(
+                "The mempool has {} transactions, accepting commands again",
+                depth
+            );
+            state.closed = false;
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.depth.set(depth as i64);
+            metrics.closed.set(state.closed as i64);
+        }
+    }
+}
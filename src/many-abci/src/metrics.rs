@@ -0,0 +1,174 @@
+use prometheus::{Encoder, Histogram, HistogramOpts, Registry, TextEncoder};
+use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tendermint_proto::abci::{Event, EventAttribute};
+use tracing::warn;
+
+/// The type of the tendermint event attached to every block in EndBlock.
+pub const BLOCK_METRICS_EVENT: &str = "many_block_metrics";
+
+/// Statistics of the block being executed.
+#[derive(Clone, Debug, Default)]
+struct BlockStats {
+    deliver_tx_time: Duration,
+    messages: u64,
+    bytes: u64,
+}
+
+/// Prometheus histograms, only created when metrics are enabled.
+struct Histograms {
+    deliver_tx_seconds: Histogram,
+    messages: Histogram,
+    bytes: Histogram,
+    commit_seconds: Histogram,
+}
+
+impl Histograms {
+    fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let histogram = |name: &str, help: &str, buckets: Vec<f64>| {
+            let h = Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets))?;
+            registry.register(Box::new(h.clone()))?;
+            Ok::<_, prometheus::Error>(h)
+        };
+
+        Ok(Self {
+            deliver_tx_seconds: histogram(
+                "many_abci_block_deliver_tx_seconds",
+                "Time spent in DeliverTx, per block.",
+                prometheus::exponential_buckets(0.001, 2.0, 14)?,
+            )?,
+            messages: histogram(
+                "many_abci_block_messages",
+                "Number of MANY messages, per block.",
+                prometheus::exponential_buckets(1.0, 2.0, 14)?,
+            )?,
+            bytes: histogram(
+                "many_abci_block_bytes",
+                "Number of transaction bytes, per block.",
+                prometheus::exponential_buckets(256.0, 4.0, 10)?,
+            )?,
+            commit_seconds: histogram(
+                "many_abci_block_commit_seconds",
+                "Time spent in Commit, per block.",
+                prometheus::exponential_buckets(0.001, 2.0, 14)?,
+            )?,
+        })
+    }
+}
+
+/// Per-block execution timing and size metrics. Shared between the ABCI
+/// connections, which all hold a clone of the application.
+#[derive(Default)]
+pub struct BlockMetrics {
+    current: Mutex<BlockStats>,
+    last_commit_time: Mutex<Option<Duration>>,
+    histograms: Option<Histograms>,
+}
+
+impl Debug for BlockMetrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BlockMetrics")
+    }
+}
+
+fn attribute(key: &str, value: String) -> EventAttribute {
+    EventAttribute {
+        key: key.to_string().into_bytes().into(),
+        value: value.into_bytes().into(),
+        index: true,
+    }
+}
+
+impl BlockMetrics {
+    /// Create metrics that also update Prometheus histograms in the registry.
+    pub fn with_registry(registry: &Registry) -> Result<Self, prometheus::Error> {
+        Ok(Self {
+            histograms: Some(Histograms::new(registry)?),
+            ..Default::default()
+        })
+    }
+
+    pub fn begin_block(&self) {
+        *self.current.lock().unwrap() = BlockStats::default();
+    }
+
+    pub fn deliver_tx(&self, bytes: usize, elapsed: Duration) {
+        let mut current = self.current.lock().unwrap();
+        current.deliver_tx_time += elapsed;
+        current.messages += 1;
+        current.bytes += bytes as u64;
+    }
+
+    /// Record the statistics of the block and return the events to attach
+    /// to it. Commit happens after EndBlock, so the commit time reported is
+    /// the one of the previous block.
+    pub fn end_block(&self) -> Vec<Event> {
+        let stats = std::mem::take(&mut *self.current.lock().unwrap());
+        let last_commit_time = *self.last_commit_time.lock().unwrap();
+
+        if let Some(h) = &self.histograms {
+            h.deliver_tx_seconds
+                .observe(stats.deliver_tx_time.as_secs_f64());
+            h.messages.observe(stats.messages as f64);
+            h.bytes.observe(stats.bytes as f64);
+        }
+
+        let mut attributes = vec![
+            attribute(
+                "deliver_tx_us",
+                stats.deliver_tx_time.as_micros().to_string(),
+            ),
+            attribute("messages", stats.messages.to_string()),
+            attribute("bytes", stats.bytes.to_string()),
+        ];
+        if let Some(t) = last_commit_time {
+            attributes.push(attribute("previous_commit_us", t.as_micros().to_string()));
+        }
+
+        vec![Event {
+            r#type: BLOCK_METRICS_EVENT.to_string(),
+            attributes,
+        }]
+    }
+
+    pub fn commit(&self, elapsed: Duration) {
+        if let Some(h) = &self.histograms {
+            h.commit_seconds.observe(elapsed.as_secs_f64());
+        }
+        *self.last_commit_time.lock().unwrap() = Some(elapsed);
+    }
+
+    /// Time a closure, using the monotonic clock.
+    pub fn time<R>(f: impl FnOnce() -> R) -> (R, Duration) {
+        let start = Instant::now();
+        let r = f();
+        (r, start.elapsed())
+    }
+}
+
+/// Serve the registry in the Prometheus text format on `/metrics`, on a
+/// background thread.
+pub fn serve(addr: SocketAddr, registry: Registry) -> Result<std::thread::JoinHandle<()>, String> {
+    let server = tiny_http::Server::http(addr).map_err(|e| e.to_string())?;
+    Ok(std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let mut buffer = Vec::new();
+                let encoder = TextEncoder::new();
+                if let Err(e) = encoder.encode(&registry.gather(), &mut buffer) {
+                    warn!("Could not encode metrics: {}", e);
+                }
+                tiny_http::Response::from_data(buffer).with_header(
+                    tiny_http::Header::from_bytes("Content-Type", encoder.format_type()).unwrap(),
+                )
+            } else {
+                tiny_http::Response::from_data(Vec::new()).with_status_code(404)
+            };
+
+            // Ignore errors on return.
+            let _ = request.respond(response);
+        }
+    }))
+}
@@ -1,3 +1,15 @@
+use crate::block_digest::{BlockDigest, BlockDigestArgs, BlockDigestModuleBackend, BlockDigests};
+use crate::local_attribute::with_local_attributes;
+use crate::replay::recorded_block;
+use crate::request_index::{
+    FindRequestArgs, FindRequestModuleBackend, FindRequestReturns, FoundRequest, RequestIndex,
+};
+use crate::tx_location::{
+    with_location, TxLocation, TxLocationArgs, TxLocationModuleBackend, TxLocations,
+};
+use crate::validator::{
+    PendingValidatorUpdatesReturns, ValidatorUpdates, ValidatorUpdatesModuleBackend,
+};
 use many_client::client::blocking::block_on;
 use many_error::ManyError;
 use many_identity::Address;
@@ -9,9 +21,14 @@ use many_types::blockchain::{
     TransactionIdentifier,
 };
 use many_types::Timestamp;
+use std::sync::Arc;
 use tendermint::Time;
 use tendermint_rpc::Client;
 
+fn timestamp(time: Time) -> Option<Timestamp> {
+    Timestamp::new(time.duration_since(Time::unix_epoch()).ok()?.as_secs()).ok()
+}
+
 fn _many_block_from_tendermint_block(block: tendermint::Block) -> Block {
     let height = block.header.height.value();
     let txs_count = block.data.len() as u64;
@@ -41,15 +58,7 @@ fn _many_block_from_tendermint_block(block: tendermint::Block) -> Block {
             BlockIdentifier::new(block.header.last_block_id.unwrap().hash.into(), height - 1)
         },
         app_hash: Some(block.header.app_hash.value()),
-        timestamp: Timestamp::new(
-            block
-                .header
-                .time
-                .duration_since(Time::unix_epoch())
-                .unwrap()
-                .as_secs(),
-        )
-        .unwrap(),
+        timestamp: timestamp(block.header.time).unwrap(),
         txs_count,
         txs,
     }
@@ -57,11 +66,74 @@ fn _many_block_from_tendermint_block(block: tendermint::Block) -> Block {
 
 pub struct AbciBlockchainModuleImpl<C: Client> {
     client: C,
+    locations: Arc<TxLocations>,
+    request_index: Option<Arc<RequestIndex>>,
+    validator_updates: Arc<ValidatorUpdates>,
+    block_digests: Arc<BlockDigests>,
 }
 
 impl<C: Client> AbciBlockchainModuleImpl<C> {
     pub fn new(client: C) -> Self {
-        Self { client }
+        Self {
+            client,
+            locations: Arc::new(TxLocations::default()),
+            request_index: None,
+            validator_updates: Arc::new(ValidatorUpdates::default()),
+            block_digests: Arc::new(BlockDigests::default()),
+        }
+    }
+
+    /// Use the transaction locations recorded by the ABCI application.
+    pub fn with_tx_locations(self, locations: Arc<TxLocations>) -> Self {
+        Self { locations, ..self }
+    }
+
+    /// Use the requests recorded by the MANY frontend.
+    pub fn with_request_index(self, request_index: Arc<RequestIndex>) -> Self {
+        Self {
+            request_index: Some(request_index),
+            ..self
+        }
+    }
+
+    /// Use the validator updates executed by the ABCI application.
+    pub fn with_validator_updates(self, validator_updates: Arc<ValidatorUpdates>) -> Self {
+        Self {
+            validator_updates,
+            ..self
+        }
+    }
+
+    /// Use the block digests recorded by the ABCI application.
+    pub fn with_block_digests(self, block_digests: Arc<BlockDigests>) -> Self {
+        Self {
+            block_digests,
+            ..self
+        }
+    }
+}
+
+impl<C: Client + Send + Sync> AbciBlockchainModuleImpl<C> {
+    /// The location of a transaction executed at `height`, from the locations
+    /// recorded by this node, or else from Tendermint.
+    async fn location(
+        &self,
+        hash: &[u8],
+        height: tendermint::block::Height,
+        index: u32,
+    ) -> TxLocation {
+        if let Some(location) = self.locations.get(hash) {
+            return location;
+        }
+        let time = match self.client.block(height).await {
+            Ok(block) => timestamp(block.block.header.time),
+            Err(_) => None,
+        };
+        TxLocation {
+            height: height.value(),
+            index: index as u64,
+            time,
+        }
     }
 }
 
@@ -84,11 +156,23 @@ impl<C: Client + Send + Sync> r#async::AsyncModuleBackend for AbciBlockchainModu
                 {
                     Ok(tx) => {
                         tracing::warn!("result: {}", hex::encode(tx.tx_result.data.value()));
+                        let response = ResponseMessage::from_bytes(tx.tx_result.data.value())
+                            .map_err(abci_frontend::abci_transport_error)?;
+                        let response = with_local_attributes(
+                            response,
+                            tx.tx_result.events.iter().map(|e| {
+                                (
+                                    e.type_str.as_str(),
+                                    e.attributes
+                                        .iter()
+                                        .map(|t| (t.key.as_ref(), t.value.as_ref()))
+                                        .collect(),
+                                )
+                            }),
+                        );
+                        let location = self.location(&hash, tx.height, tx.index).await;
                         Ok(StatusReturn::Done {
-                            response: Box::new(
-                                ResponseMessage::from_bytes(tx.tx_result.data.value())
-                                    .map_err(abci_frontend::abci_transport_error)?,
-                            ),
+                            response: Box::new(with_location(response, &location)),
                         })
                     }
 
@@ -186,3 +270,86 @@ impl<C: Client + Send + Sync> blockchain::BlockchainModuleBackend for AbciBlockc
         }
     }
 }
+
+impl<C: Client + Send + Sync> TxLocationModuleBackend for AbciBlockchainModuleImpl<C> {
+    fn tx_location(&self, args: TxLocationArgs) -> Result<TxLocation, ManyError> {
+        let hash = TryInto::<[u8; 32]>::try_into(args.token.as_slice())
+            .map_err(|_| ManyError::unknown("Invalid transaction hash .".to_string()))?;
+
+        block_on(async {
+            let tx = self
+                .client
+                .tx(tendermint_rpc::abci::transaction::Hash::new(hash), false)
+                .await
+                .map_err(|e| {
+                    tracing::error!("abci transport: {}", e.to_string());
+                    abci_frontend::abci_transport_error(e.to_string())
+                })?;
+            Ok(self.location(&hash, tx.height, tx.index).await)
+        })
+    }
+}
+
+impl<C: Client + Send + Sync> FindRequestModuleBackend for AbciBlockchainModuleImpl<C> {
+    fn find_request(&self, args: FindRequestArgs) -> Result<FindRequestReturns, ManyError> {
+        let index = self
+            .request_index
+            .as_ref()
+            .ok_or_else(|| ManyError::unknown("The request index is not enabled on this node."))?;
+        let records = index.find(
+            &args.sender,
+            &args.nonce_range.unwrap_or_default(),
+            &args.time_range.unwrap_or_default(),
+        );
+
+        let requests = block_on(async {
+            let mut requests = Vec::with_capacity(records.len());
+            for record in records {
+                // Transactions not found were not executed (yet).
+                let location = match TryInto::<[u8; 32]>::try_into(record.hash.as_slice()) {
+                    Ok(hash) => match self
+                        .client
+                        .tx(tendermint_rpc::abci::transaction::Hash::new(hash), false)
+                        .await
+                    {
+                        Ok(tx) => Some(self.location(&hash, tx.height, tx.index).await),
+                        Err(_) => None,
+                    },
+                    Err(_) => None,
+                };
+                requests.push(FoundRequest {
+                    nonce: record.nonce,
+                    timestamp: record.timestamp,
+                    hash: record.hash,
+                    location,
+                });
+            }
+            requests
+        });
+        Ok(FindRequestReturns { requests })
+    }
+}
+
+impl<C: Client + Send + Sync> ValidatorUpdatesModuleBackend for AbciBlockchainModuleImpl<C> {
+    fn pending_validator_updates(&self) -> Result<PendingValidatorUpdatesReturns, ManyError> {
+        Ok(self.validator_updates.pending())
+    }
+}
+
+impl<C: Client + Send + Sync> BlockDigestModuleBackend for AbciBlockchainModuleImpl<C> {
+    /// The digest recorded when the block was executed, or else the one
+    /// recomputed from Tendermint, which is the same.
+    fn block_digest(&self, args: BlockDigestArgs) -> Result<BlockDigest, ManyError> {
+        if let Some(digest) = self.block_digests.get(args.height) {
+            return Ok(digest);
+        }
+        if args.height == 0 {
+            return Err(blockchain::unknown_block());
+        }
+        let block = block_on(recorded_block(&self.client, args.height)).map_err(|e| {
+            tracing::error!("abci transport: {}", e);
+            abci_frontend::abci_transport_error(e)
+        })?;
+        Ok(BlockDigest::from_recorded(&block))
+    }
+}
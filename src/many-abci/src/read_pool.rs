@@ -0,0 +1,137 @@
+use crate::backend::Backend;
+use coset::CoseSign1;
+use many_error::ManyError;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+struct Replica {
+    backend: Backend,
+    healthy: AtomicBool,
+}
+
+impl Replica {
+    fn set_healthy(&self, healthy: bool) -> bool {
+        self.healthy.swap(healthy, Ordering::Relaxed)
+    }
+}
+
+/// Read replicas of the MANY application, e.g. stateless copies of it
+/// running against read-only snapshots, which queries are load-balanced on
+/// in round-robin. Commands, and everything the consensus depends on, are
+/// never sent to them.
+///
+/// A replica failing a request or a health check is taken out of the
+/// rotation until a health check succeeds again.
+pub struct ReadPool {
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+}
+
+impl Debug for ReadPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.replicas.iter().map(|r| &r.backend))
+            .finish()
+    }
+}
+
+impl ReadPool {
+    pub fn new(backends: Vec<Backend>) -> Self {
+        Self {
+            replicas: backends
+                .into_iter()
+                .map(|backend| Replica {
+                    backend,
+                    healthy: AtomicBool::new(true),
+                })
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.replicas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// Whether each replica is in the rotation, in the order they were given.
+    pub fn healthy(&self) -> Vec<bool> {
+        self.replicas
+            .iter()
+            .map(|r| r.healthy.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Send a query to the next healthy replica, failing over to the
+    /// following ones. The response is verified like the responses of the
+    /// primary. An error is returned when no replica could answer, so the
+    /// query can be sent to the primary instead.
+    pub async fn send_envelope(&self, envelope: CoseSign1) -> Result<CoseSign1, ManyError> {
+        let len = self.replicas.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for i in 0..len {
+            let replica = &self.replicas[(start + i) % len];
+            if !replica.healthy.load(Ordering::Relaxed) {
+                continue;
+            }
+            let result = replica
+                .backend
+                .send_envelope(envelope.clone())
+                .await
+                .and_then(|response| {
+                    replica
+                        .backend
+                        .verify(&response)
+                        .map(|_| response)
+                        .map_err(ManyError::unexpected_transport_error)
+                });
+            match result {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    warn!(
+                        "Read replica {:?} failed, taking it out of rotation: {}",
+                        replica.backend, e
+                    );
+                    replica.set_healthy(false);
+                }
+            }
+        }
+        Err(ManyError::unexpected_transport_error(
+            "No read replica is available.",
+        ))
+    }
+
+    /// Check the health of every replica with a `status` call, taking the
+    /// failing ones out of the rotation and putting back the ones which
+    /// recovered.
+    pub async fn probe(&self) {
+        for replica in &self.replicas {
+            let healthy = replica.backend.call_("status", ()).await.is_ok();
+            let was_healthy = replica.set_healthy(healthy);
+            match (was_healthy, healthy) {
+                (false, true) => info!("Read replica {:?} is back in rotation", replica.backend),
+                (true, false) => warn!(
+                    "Read replica {:?} failed its health check, taking it out of rotation",
+                    replica.backend
+                ),
+                _ => debug!("Read replica {:?} healthy: {}", replica.backend, healthy),
+            }
+        }
+    }
+}
+
+/// Check the health of the replicas every `interval`, forever.
+pub async fn run(pool: Arc<ReadPool>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        pool.probe().await;
+    }
+}
@@ -0,0 +1,349 @@
+use crate::replay::{AvailableBlocks, BlockSource, RecordedBlock};
+use crate::request_index::{RequestIndex, RequestRecord};
+use crate::tx_location::{tx_hash, TxLocation, TxLocations};
+use coset::{CborSerializable, CoseSign1};
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Default number of blocks reindexed by a single `abci.reindexCache` call.
+pub const DEFAULT_REINDEX_BLOCKS: u64 = 100;
+
+/// Maximum number of blocks reindexed by a single `abci.reindexCache` call,
+/// so a call does not hold the caches for too long.
+pub const MAXIMUM_REINDEX_BLOCKS: u64 = 1000;
+
+/// How far the reindexing of the chain history went.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReindexProgress {
+    /// The next block to reindex.
+    #[n(0)]
+    pub next_height: u64,
+
+    /// The number of transactions reindexed.
+    #[n(1)]
+    pub txs: u64,
+
+    /// The number of blocks skipped because Tendermint pruned them.
+    #[n(2)]
+    pub pruned: u64,
+}
+
+/// The request recorded for a transaction, if it is a MANY request.
+fn request_record(tx: &[u8], hash: &[u8], time: Option<Timestamp>) -> Option<RequestRecord> {
+    let envelope = CoseSign1::from_slice(tx).ok()?;
+    let message = RequestMessage::from_bytes(envelope.payload.as_ref()?).ok()?;
+    let broadcast_at = time.or(message.timestamp)?;
+    Some(RequestRecord {
+        sender: message.from(),
+        nonce: message.nonce.clone().map(Into::into),
+        timestamp: message.timestamp.unwrap_or(broadcast_at),
+        hash: hash.to_vec().into(),
+        broadcast_at,
+    })
+}
+
+/// Backfills the caches of this node from the chain history, e.g. after
+/// enabling the request index on an existing chain: the location of each
+/// transaction, and the request index by sender and nonce. Only the caches
+/// are written, never the state of the application.
+///
+/// The progress is kept next to the request index, if it is kept in a file,
+/// so the reindexing resumes where it stopped after a restart.
+pub struct Reindexer<S: BlockSource + AvailableBlocks> {
+    source: S,
+    locations: Option<Arc<TxLocations>>,
+    request_index: Option<Arc<RequestIndex>>,
+    progress_path: Option<PathBuf>,
+    progress: ReindexProgress,
+    admins: BTreeSet<Address>,
+}
+
+impl<S: BlockSource + AvailableBlocks> Reindexer<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            locations: None,
+            request_index: None,
+            progress_path: None,
+            progress: ReindexProgress::default(),
+            admins: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_tx_locations(self, locations: Arc<TxLocations>) -> Self {
+        Self {
+            locations: Some(locations),
+            ..self
+        }
+    }
+
+    pub fn with_request_index(self, request_index: Arc<RequestIndex>) -> Self {
+        Self {
+            request_index: Some(request_index),
+            ..self
+        }
+    }
+
+    /// Save the progress at `path`, and resume from the progress saved there
+    /// if any.
+    pub fn with_progress_file(self, path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let progress = match std::fs::read(&path) {
+            Ok(bytes) => minicbor::decode(&bytes).map_err(|e| e.to_string())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.progress,
+            Err(e) => return Err(e.to_string()),
+        };
+        Ok(Self {
+            progress_path: Some(path),
+            progress,
+            ..self
+        })
+    }
+
+    /// The addresses allowed to call `abci.reindexCache`.
+    pub fn with_admins(self, admins: BTreeSet<Address>) -> Self {
+        Self { admins, ..self }
+    }
+
+    pub fn progress(&self) -> &ReindexProgress {
+        &self.progress
+    }
+
+    /// Whether the reindexing started, or was restored from a file.
+    pub fn started(&self) -> bool {
+        self.progress.next_height > 0
+    }
+
+    /// Restart the reindexing from a height.
+    pub fn restart(&mut self, from_height: u64) {
+        self.progress = ReindexProgress {
+            next_height: from_height.max(1),
+            ..Default::default()
+        };
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.progress_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let tmp = path.with_extension("tmp");
+        let bytes = minicbor::to_vec(&self.progress).map_err(|e| e.to_string())?;
+        std::fs::write(&tmp, bytes).map_err(|e| e.to_string())?;
+        std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+    }
+
+    fn index(&self, block: RecordedBlock, records: &mut Vec<RequestRecord>) {
+        let time = block.time.and_then(|secs| Timestamp::new(secs).ok());
+        for (index, tx) in block.txs.iter().enumerate() {
+            let hash = tx_hash(&tx.tx);
+            if let Some(locations) = &self.locations {
+                locations.insert(
+                    hash.clone(),
+                    TxLocation {
+                        height: block.height,
+                        index: index as u64,
+                        time,
+                    },
+                );
+            }
+            records.extend(request_record(&tx.tx, &hash, time));
+        }
+    }
+
+    /// Reindex up to `max_blocks` blocks from where the reindexing stopped,
+    /// and return the latest height. Blocks pruned by Tendermint are skipped
+    /// with a warning. A block which cannot be read stops the step, after
+    /// saving the progress of the blocks before it.
+    pub fn step(&mut self, max_blocks: u64) -> Result<u64, String> {
+        if !self.started() {
+            self.restart(1);
+        }
+        let available = self.source.available_blocks()?;
+        if self.progress.next_height < available.from {
+            warn!(
+                "Blocks {} to {} were pruned, skipping them",
+                self.progress.next_height,
+                available.from - 1
+            );
+            self.progress.pruned += available.from - self.progress.next_height;
+            self.progress.next_height = available.from;
+        }
+
+        let end = available
+            .to
+            .min(self.progress.next_height.saturating_add(max_blocks) - 1);
+        let mut records = vec![];
+        let mut result = Ok(available.to);
+        while self.progress.next_height <= end {
+            match self.source.block(self.progress.next_height) {
+                Ok(block) => {
+                    self.progress.txs += block.txs.len() as u64;
+                    self.index(block, &mut records);
+                    self.progress.next_height += 1;
+                }
+                Err(e) => {
+                    result = Err(format!(
+                        "Could not read block {}: {}",
+                        self.progress.next_height, e
+                    ));
+                    break;
+                }
+            }
+        }
+
+        if let Some(index) = &self.request_index {
+            index.backfill(records)?;
+        }
+        self.save()?;
+        result
+    }
+
+    /// Reindex every block up to the latest one, reporting the progress.
+    pub fn run(&mut self, blocks_per_step: u64) -> Result<(), String> {
+        loop {
+            let latest = self.step(blocks_per_step)?;
+            info!(
+                "Reindexed up to block {} of {} ({} transactions, {} pruned blocks)",
+                self.progress.next_height - 1,
+                latest,
+                self.progress.txs,
+                self.progress.pruned
+            );
+            if self.progress.next_height > latest {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReindexCacheArgs {
+    /// Restart the reindexing from this height, instead of resuming it.
+    #[n(0)]
+    pub from_height: Option<u64>,
+
+    #[n(1)]
+    pub max_blocks: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReindexCacheReturns {
+    #[n(0)]
+    pub progress: ReindexProgress,
+
+    #[n(1)]
+    pub latest_height: u64,
+
+    /// Whether every block up to the latest one was reindexed.
+    #[n(2)]
+    pub done: bool,
+}
+
+pub trait ReindexCacheModuleBackend: Send {
+    fn reindex_cache(
+        &mut self,
+        sender: &Address,
+        args: ReindexCacheArgs,
+    ) -> Result<ReindexCacheReturns, ManyError>;
+}
+
+impl<S: BlockSource + AvailableBlocks + Send> ReindexCacheModuleBackend for Reindexer<S> {
+    fn reindex_cache(
+        &mut self,
+        sender: &Address,
+        args: ReindexCacheArgs,
+    ) -> Result<ReindexCacheReturns, ManyError> {
+        if !self.admins.contains(sender) {
+            return Err(ManyError::invalid_from_identity());
+        }
+        if let Some(from_height) = args.from_height {
+            self.restart(from_height);
+        }
+        let max_blocks = args
+            .max_blocks
+            .unwrap_or(DEFAULT_REINDEX_BLOCKS)
+            .clamp(1, MAXIMUM_REINDEX_BLOCKS);
+
+        let latest_height = self.step(max_blocks).map_err(ManyError::unknown)?;
+        Ok(ReindexCacheReturns {
+            progress: self.progress.clone(),
+            latest_height,
+            done: self.progress.next_height > latest_height,
+        })
+    }
+}
+
+/// A module reindexing the caches of this node from the chain history, a
+/// bounded number of blocks per call.
+pub struct ReindexCacheModule<T: ReindexCacheModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: ReindexCacheModuleBackend> ReindexCacheModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "ReindexCacheModule".to_string(),
+                attribute: None,
+                endpoints: vec!["abci.reindexCache".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: ReindexCacheModuleBackend> Debug for ReindexCacheModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReindexCacheModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ReindexCacheModuleBackend> ManyModule for ReindexCacheModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "abci.reindexCache" => minicbor::decode::<ReindexCacheArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "abci.reindexCache" => minicbor::decode(&message.data)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+                .and_then(|args| backend.reindex_cache(&from, args))
+                .and_then(|returns| {
+                    minicbor::to_vec(returns)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))
+                }),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,253 @@
+use many_client::client::blocking::block_on;
+use many_protocol::ResponseMessage;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use tendermint::Time;
+use tendermint_abci::Application;
+use tendermint_proto::abci::{
+    RequestBeginBlock, RequestDeliverTx, RequestEndBlock, ResponseDeliverTx,
+};
+use tendermint_rpc::Client;
+use tracing::{debug, info};
+
+/// An inclusive range of block heights, given as `<from>..<to>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeightRange {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl FromStr for HeightRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once("..")
+            .ok_or_else(|| format!("Invalid height range '{}', expected <from>..<to>.", s))?;
+        let from = from.parse().map_err(|e| format!("Invalid height: {}", e))?;
+        let to = to.parse().map_err(|e| format!("Invalid height: {}", e))?;
+        if from == 0 || from > to {
+            return Err(format!("Invalid height range '{}'.", s));
+        }
+        Ok(Self { from, to })
+    }
+}
+
+/// The result of a transaction, as recorded by tendermint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecordedTx {
+    pub tx: Vec<u8>,
+    pub code: u32,
+    pub data: Vec<u8>,
+    pub log: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecordedBlock {
+    pub height: u64,
+
+    /// Seconds since the UNIX epoch, as sent to `abci.beginBlock`.
+    pub time: Option<u64>,
+
+    pub txs: Vec<RecordedTx>,
+}
+
+/// Where the blocks to replay are read from.
+pub trait BlockSource {
+    fn block(&self, height: u64) -> Result<RecordedBlock, String>;
+}
+
+/// The blocks a source still has. Tendermint nodes may prune the blocks
+/// older than their retention.
+pub trait AvailableBlocks {
+    fn available_blocks(&self) -> Result<HeightRange, String>;
+}
+
+/// Reads blocks and their DeliverTx results from the tendermint RPC.
+pub struct TendermintSource<C: Client> {
+    client: C,
+}
+
+impl<C: Client> TendermintSource<C> {
+    pub fn new(client: C) -> Self {
+        Self { client }
+    }
+}
+
+/// Read a block and its DeliverTx results from the tendermint RPC.
+pub async fn recorded_block<C: Client + Sync>(
+    client: &C,
+    height: u64,
+) -> Result<RecordedBlock, String> {
+    let (block, results) = async {
+        let block = client.block(height as u32).await?;
+        let results = client.block_results(height as u32).await?;
+        Ok::<_, tendermint_rpc::Error>((block.block, results))
+    }
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let results = results.txs_results.unwrap_or_default();
+    if results.len() != block.data.len() {
+        return Err(format!(
+            "Block {} has {} transactions but {} results.",
+            height,
+            block.data.len(),
+            results.len()
+        ));
+    }
+
+    let time = block
+        .header
+        .time
+        .duration_since(Time::unix_epoch())
+        .ok()
+        .map(|d| d.as_secs());
+    let txs = block
+        .data
+        .into_iter()
+        .zip(results)
+        .map(|(tx, result)| RecordedTx {
+            tx,
+            code: result.code.value(),
+            data: result.data.value().clone(),
+            log: result.log.to_string(),
+        })
+        .collect();
+
+    Ok(RecordedBlock { height, time, txs })
+}
+
+impl<C: Client + Sync> BlockSource for TendermintSource<C> {
+    fn block(&self, height: u64) -> Result<RecordedBlock, String> {
+        block_on(recorded_block(&self.client, height))
+    }
+}
+
+impl<C: Client + Sync> AvailableBlocks for TendermintSource<C> {
+    fn available_blocks(&self) -> Result<HeightRange, String> {
+        let status = block_on(self.client.status()).map_err(|e| e.to_string())?;
+        Ok(HeightRange {
+            from: status.sync_info.earliest_block_height.value().max(1),
+            to: status.sync_info.latest_block_height.value(),
+        })
+    }
+}
+
+/// The first transaction whose result differs from the recorded one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub height: u64,
+    pub index: usize,
+    pub expected: RecordedTx,
+    pub actual: ResponseDeliverTx,
+}
+
+fn dump_response(f: &mut Formatter<'_>, data: &[u8]) -> std::fmt::Result {
+    writeln!(f, "    data: {}", hex::encode(data))?;
+    match ResponseMessage::from_bytes(data) {
+        Ok(response) => writeln!(f, "    decoded: {:?}", response),
+        Err(_) if data.is_empty() => Ok(()),
+        Err(e) => writeln!(f, "    decoded: <invalid response: {}>", e),
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Divergence at height {}, transaction {}.",
+            self.height, self.index
+        )?;
+        writeln!(f, "  envelope: {}", hex::encode(&self.expected.tx))?;
+        writeln!(
+            f,
+            "  expected: code {}, log {:?}",
+            self.expected.code, self.expected.log
+        )?;
+        dump_response(f, &self.expected.data)?;
+        writeln!(
+            f,
+            "  actual: code {}, log {:?}",
+            self.actual.code, self.actual.log
+        )?;
+        dump_response(f, &self.actual.data)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub blocks: u64,
+    pub txs: u64,
+    pub divergence: Option<Divergence>,
+}
+
+/// Replay the transactions of a range of blocks against an application, and
+/// compare their results with the recorded ones. The application must be at
+/// the state of the block before the range. Blocks go through the same
+/// begin block, deliver and commit calls as under consensus, so the results
+/// of the application are normalized the same way they were when recorded.
+///
+/// Stops at the first divergence, after committing the block it was found
+/// in.
+pub fn replay(
+    source: &impl BlockSource,
+    app: &impl Application,
+    range: HeightRange,
+) -> Result<ReplayReport, String> {
+    let mut report = ReplayReport::default();
+
+    for height in range.from..=range.to {
+        let block = source.block(height)?;
+        debug!("Replaying block {} ({} txs)", height, block.txs.len());
+
+        app.begin_block(RequestBeginBlock {
+            header: Some(tendermint_proto::types::Header {
+                height: height as i64,
+                time: block
+                    .time
+                    .map(|seconds| tendermint_proto::google::protobuf::Timestamp {
+                        seconds: seconds as i64,
+                        nanos: 0,
+                    }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        for (index, expected) in block.txs.into_iter().enumerate() {
+            let actual = app.deliver_tx(RequestDeliverTx {
+                tx: expected.tx.clone().into(),
+            });
+            report.txs += 1;
+
+            if report.divergence.is_none()
+                && (actual.code != expected.code
+                    || actual.data.as_ref() != expected.data.as_slice())
+            {
+                report.divergence = Some(Divergence {
+                    height,
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        app.end_block(RequestEndBlock {
+            height: height as i64,
+        });
+        app.commit();
+        report.blocks += 1;
+
+        if report.divergence.is_some() {
+            break;
+        }
+    }
+
+    info!(
+        "Replayed {} blocks and {} transactions",
+        report.blocks, report.txs
+    );
+    Ok(report)
+}
@@ -0,0 +1,389 @@
+use crate::tx_location::TxLocation;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::{CborRange, Timestamp};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::warn;
+
+/// Default number of requests kept in the index.
+pub const DEFAULT_REQUEST_INDEX_CAPACITY: usize = 100_000;
+
+/// Maximum number of requests returned by a single `abci.findRequest` call.
+pub const MAXIMUM_FOUND_REQUESTS: usize = 1000;
+
+/// A command this node broadcast to Tendermint.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct RequestRecord {
+    #[n(0)]
+    pub sender: Address,
+
+    #[n(1)]
+    pub nonce: Option<ByteVec>,
+
+    /// The timestamp of the request, or the time it was broadcast if it had
+    /// none.
+    #[n(2)]
+    pub timestamp: Timestamp,
+
+    /// The Tendermint hash of the transaction, which is also its async token.
+    #[n(3)]
+    pub hash: ByteVec,
+
+    #[n(4)]
+    pub broadcast_at: Timestamp,
+}
+
+struct Inner {
+    records: VecDeque<RequestRecord>,
+    file: Option<File>,
+
+    /// Number of records in the file, including evicted ones.
+    written: usize,
+}
+
+/// A bounded index of the commands broadcast by this node, by sender. It is
+/// kept in an append-only file, compacted when it has too many evicted
+/// records, so clients can find their requests after the node restarts.
+pub struct RequestIndex {
+    path: Option<PathBuf>,
+    capacity: usize,
+    max_age: Option<Duration>,
+    inner: Mutex<Inner>,
+}
+
+impl Debug for RequestIndex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RequestIndex")
+    }
+}
+
+fn read_records(path: &Path) -> Result<Vec<RequestRecord>, String> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut decoder = minicbor::Decoder::new(&bytes);
+    let mut records = vec![];
+    while decoder.position() < bytes.len() {
+        match decoder.decode::<RequestRecord>() {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                // A record cut short by a crash. The file is rewritten
+                // without it on open.
+                warn!(
+                    "Ignoring the end of the request index {}: {}",
+                    path.display(),
+                    e
+                );
+                break;
+            }
+        }
+    }
+    Ok(records)
+}
+
+impl RequestIndex {
+    /// An index which is lost when the node stops.
+    pub fn in_memory(capacity: usize, max_age: Option<Duration>) -> Self {
+        Self {
+            path: None,
+            capacity,
+            max_age,
+            inner: Mutex::new(Inner {
+                records: VecDeque::new(),
+                file: None,
+                written: 0,
+            }),
+        }
+    }
+
+    /// Load the index kept at `path`, creating it if needed.
+    pub fn open(
+        path: impl AsRef<Path>,
+        capacity: usize,
+        max_age: Option<Duration>,
+    ) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut index = Self::in_memory(capacity, max_age);
+
+        // The clock may have changed since the records were written, so all
+        // of them are checked.
+        let now = Timestamp::now();
+        let oldest = index.oldest(now);
+        let inner = index.inner.get_mut().unwrap();
+        inner.records = read_records(&path)?.into();
+        inner
+            .records
+            .retain(|r| oldest.map_or(true, |oldest| r.broadcast_at >= oldest));
+        index.path = Some(path);
+        index.evict(now);
+        index.compact()?;
+        Ok(index)
+    }
+
+    /// The time before which records are too old, if they have a maximum age.
+    fn oldest(&self, now: Timestamp) -> Option<Timestamp> {
+        let max_age = self.max_age?;
+        let now = now.as_system_time().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Timestamp::new(now.checked_sub(max_age)?.as_secs()).ok()
+    }
+
+    /// Drop the records over capacity or older than the maximum age. Records
+    /// are in broadcast order, so only the first ones are checked.
+    fn evict(&self, now: Timestamp) {
+        let oldest = self.oldest(now);
+        let mut inner = self.inner.lock().unwrap();
+        while inner.records.len() > self.capacity
+            || matches!((inner.records.front(), oldest), (Some(r), Some(oldest)) if r.broadcast_at < oldest)
+        {
+            inner.records.pop_front();
+        }
+    }
+
+    /// Rewrite the file with the records kept.
+    fn compact(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut inner = self.inner.lock().unwrap();
+
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp).map_err(|e| e.to_string())?;
+            for record in &inner.records {
+                let bytes = minicbor::to_vec(record).map_err(|e| e.to_string())?;
+                file.write_all(&bytes).map_err(|e| e.to_string())?;
+            }
+            file.sync_all().map_err(|e| e.to_string())?;
+        }
+        std::fs::rename(&tmp, path).map_err(|e| e.to_string())?;
+
+        inner.file = Some(
+            OpenOptions::new()
+                .append(true)
+                .open(path)
+                .map_err(|e| e.to_string())?,
+        );
+        inner.written = inner.records.len();
+        Ok(())
+    }
+
+    /// Add a request broadcast by this node.
+    pub fn record(&self, record: RequestRecord) -> Result<(), String> {
+        let now = record.broadcast_at;
+        let compact = {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(file) = inner.file.as_mut() {
+                let bytes = minicbor::to_vec(&record).map_err(|e| e.to_string())?;
+                file.write_all(&bytes).map_err(|e| e.to_string())?;
+            }
+            inner.records.push_back(record);
+            inner.written += 1;
+            inner.written > self.capacity * 2
+        };
+
+        self.evict(now);
+        if compact {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Add requests found in the chain history, skipping the ones already
+    /// indexed, and return how many were added. They are kept in broadcast
+    /// order, within the same capacity and maximum age as the others.
+    pub fn backfill(&self, records: Vec<RequestRecord>) -> Result<usize, String> {
+        let added = {
+            let mut inner = self.inner.lock().unwrap();
+            let mut known: BTreeSet<Vec<u8>> =
+                inner.records.iter().map(|r| r.hash.to_vec()).collect();
+            let mut added = 0;
+            for record in records {
+                if known.insert(record.hash.to_vec()) {
+                    inner.records.push_back(record);
+                    added += 1;
+                }
+            }
+            inner
+                .records
+                .make_contiguous()
+                .sort_by_key(|r| r.broadcast_at);
+            added
+        };
+
+        self.evict(Timestamp::now());
+        self.compact()?;
+        Ok(added)
+    }
+
+    /// The requests of a sender whose nonce and timestamp are in the ranges,
+    /// oldest first.
+    pub fn find(
+        &self,
+        sender: &Address,
+        nonce_range: &CborRange<ByteVec>,
+        time_range: &CborRange<Timestamp>,
+    ) -> Vec<RequestRecord> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .records
+            .iter()
+            .filter(|r| &r.sender == sender)
+            .filter(|r| match &r.nonce {
+                Some(nonce) => contains(nonce_range, nonce),
+                None => is_unbounded(nonce_range),
+            })
+            .filter(|r| contains(time_range, &r.timestamp))
+            .take(MAXIMUM_FOUND_REQUESTS)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn is_unbounded<T>(range: &CborRange<T>) -> bool {
+    matches!(
+        (&range.start, &range.end),
+        (Bound::Unbounded, Bound::Unbounded)
+    )
+}
+
+fn contains<T: Ord>(range: &CborRange<T>, value: &T) -> bool {
+    let after_start = match &range.start {
+        Bound::Included(start) => value >= start,
+        Bound::Excluded(start) => value > start,
+        Bound::Unbounded => true,
+    };
+    let before_end = match &range.end {
+        Bound::Included(end) => value <= end,
+        Bound::Excluded(end) => value < end,
+        Bound::Unbounded => true,
+    };
+    after_start && before_end
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct FindRequestArgs {
+    #[n(0)]
+    pub sender: Address,
+
+    /// Only requests with a nonce in this range. Requests without a nonce
+    /// only match if the range is unbounded.
+    #[n(1)]
+    pub nonce_range: Option<CborRange<ByteVec>>,
+
+    #[n(2)]
+    pub time_range: Option<CborRange<Timestamp>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct FoundRequest {
+    #[n(0)]
+    pub nonce: Option<ByteVec>,
+
+    #[n(1)]
+    pub timestamp: Timestamp,
+
+    #[n(2)]
+    pub hash: ByteVec,
+
+    /// Where the transaction was executed, if it was.
+    #[n(3)]
+    pub location: Option<TxLocation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct FindRequestReturns {
+    #[n(0)]
+    pub requests: Vec<FoundRequest>,
+}
+
+pub trait FindRequestModuleBackend: Send {
+    fn find_request(&self, args: FindRequestArgs) -> Result<FindRequestReturns, ManyError>;
+}
+
+/// A module finding the commands a sender submitted through this node.
+pub struct FindRequestModule<T: FindRequestModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: FindRequestModuleBackend> FindRequestModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "FindRequestModule".to_string(),
+                attribute: None,
+                endpoints: vec!["abci.findRequest".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: FindRequestModuleBackend> Debug for FindRequestModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FindRequestModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: FindRequestModuleBackend> ManyModule for FindRequestModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "abci.findRequest" => minicbor::decode::<FindRequestArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "abci.findRequest" => minicbor::decode(&message.data)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+                .and_then(|args| backend.find_request(args))
+                .and_then(|returns| {
+                    minicbor::to_vec(returns)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))
+                }),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
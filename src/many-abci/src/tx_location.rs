@@ -0,0 +1,235 @@
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// Default number of transactions whose location is kept in memory. Older
+/// ones are looked up from Tendermint.
+pub const DEFAULT_TX_LOCATIONS: usize = 100_000;
+
+/// The attribute added to the response of an executed transaction, with its
+/// block height, index in the block and block time as arguments. The ID is
+/// local to many-abci.
+pub const TX_LOCATION: Attribute = Attribute::id(1000);
+
+/// Where a transaction was executed.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct TxLocation {
+    #[n(0)]
+    pub height: u64,
+
+    #[n(1)]
+    pub index: u64,
+
+    #[n(2)]
+    pub time: Option<Timestamp>,
+}
+
+impl TxLocation {
+    pub fn as_attribute(&self) -> Attribute {
+        let mut attribute = TX_LOCATION
+            .with_argument(CborAny::Int(self.height as i64))
+            .with_argument(CborAny::Int(self.index as i64));
+        let secs = self
+            .time
+            .and_then(|t| t.as_system_time().ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+        if let Some(secs) = secs {
+            attribute = attribute.with_argument(CborAny::Int(secs.as_secs() as i64));
+        }
+        attribute
+    }
+
+    pub fn from_attribute(attribute: &Attribute) -> Option<Self> {
+        let int = |i: usize| match attribute.arguments.get(i) {
+            Some(CborAny::Int(x)) => u64::try_from(*x).ok(),
+            _ => None,
+        };
+        Some(Self {
+            height: int(0)?,
+            index: int(1)?,
+            time: int(2).and_then(|secs| Timestamp::new(secs).ok()),
+        })
+    }
+}
+
+/// Add the location of a transaction to its response.
+pub fn with_location(response: ResponseMessage, location: &TxLocation) -> ResponseMessage {
+    response.with_attribute(location.as_attribute())
+}
+
+/// The hash of a transaction, as used by Tendermint and as the async token
+/// of commands.
+pub fn tx_hash(tx: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    sha2::Sha256::digest(tx).to_vec()
+}
+
+#[derive(Default)]
+struct Inner {
+    height: u64,
+    time: Option<Timestamp>,
+    index: u64,
+    locations: BTreeMap<Vec<u8>, TxLocation>,
+    order: VecDeque<Vec<u8>>,
+}
+
+/// The locations of the last transactions delivered, recorded as blocks are
+/// executed.
+pub struct TxLocations {
+    inner: Mutex<Inner>,
+    capacity: usize,
+}
+
+impl Default for TxLocations {
+    fn default() -> Self {
+        Self::new(DEFAULT_TX_LOCATIONS)
+    }
+}
+
+impl TxLocations {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity,
+        }
+    }
+
+    pub fn begin_block(&self, height: u64, time: Option<Timestamp>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.height = height;
+        inner.time = time;
+        inner.index = 0;
+    }
+
+    /// Record the next transaction of the current block.
+    pub fn deliver_tx(&self, tx: &[u8]) -> TxLocation {
+        let mut inner = self.inner.lock().unwrap();
+        let location = TxLocation {
+            height: inner.height,
+            index: inner.index,
+            time: inner.time,
+        };
+        inner.index += 1;
+
+        let hash = tx_hash(tx);
+        if inner
+            .locations
+            .insert(hash.clone(), location.clone())
+            .is_none()
+        {
+            inner.order.push_back(hash);
+        }
+        self.evict(&mut inner);
+        location
+    }
+
+    /// Record the location of a transaction delivered before this node
+    /// started, e.g. when reindexing the chain history. A location already
+    /// recorded is kept.
+    pub fn insert(&self, hash: Vec<u8>, location: TxLocation) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.locations.contains_key(&hash) {
+            return;
+        }
+        inner.locations.insert(hash.clone(), location);
+        inner.order.push_back(hash);
+        self.evict(&mut inner);
+    }
+
+    fn evict(&self, inner: &mut Inner) {
+        while inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.locations.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, hash: &[u8]) -> Option<TxLocation> {
+        self.inner.lock().unwrap().locations.get(hash).cloned()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct TxLocationArgs {
+    /// The async token or hash of the transaction.
+    #[n(0)]
+    pub token: ByteVec,
+}
+
+pub trait TxLocationModuleBackend: Send {
+    fn tx_location(&self, args: TxLocationArgs) -> Result<TxLocation, ManyError>;
+}
+
+const ENDPOINTS: &[&str] = &["blockchain.txLocation"];
+
+/// A module returning where a transaction was executed.
+pub struct TxLocationModule<T: TxLocationModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: TxLocationModuleBackend> TxLocationModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "TxLocationModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: TxLocationModuleBackend> Debug for TxLocationModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TxLocationModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: TxLocationModuleBackend> ManyModule for TxLocationModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "blockchain.txLocation" => minicbor::decode::<TxLocationArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "blockchain.txLocation" => minicbor::decode(&message.data)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+                .and_then(|args| backend.tx_location(args))
+                .and_then(|returns| {
+                    minicbor::to_vec(returns)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))
+                }),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
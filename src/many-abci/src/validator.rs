@@ -0,0 +1,399 @@
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Decoder, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use tendermint_proto::abci::{Event, EventAttribute, ValidatorUpdate};
+use tendermint_proto::crypto::{public_key, PublicKey};
+
+/// The command to update a validator. It is broadcast like the commands of
+/// the backend, but executed by many-abci itself.
+pub const UPDATE_VALIDATOR_METHOD: &str = "blockchain.updateValidator";
+
+/// The type of the tendermint event attached to the block in EndBlock for
+/// every validator update it returns.
+pub const VALIDATOR_UPDATE_EVENT: &str = "many_validator_update";
+
+/// Default minimum share of the total voting power, in percent, which must
+/// remain after the validator updates of a block.
+pub const DEFAULT_QUORUM_MARGIN: u64 = 67;
+
+/// The governance parameter of the addresses allowed to update validators.
+const ADMINS_PARAM: &str = "validators.admins";
+
+/// The governance parameter of the quorum margin.
+const QUORUM_MARGIN_PARAM: &str = "validators.quorumMargin";
+
+/// Validator updates returned in EndBlock at height H are used by Tendermint
+/// from height H + 2.
+const VALIDATOR_UPDATE_DELAY: u64 = 2;
+
+const ED25519_PUBLIC_KEY_LENGTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct UpdateValidatorArgs {
+    /// The Ed25519 consensus key of the validator.
+    #[n(0)]
+    pub public_key: ByteVec,
+
+    /// The new voting power of the validator, or 0 to remove it.
+    #[n(1)]
+    pub power: u64,
+
+    /// The key the validator rotates from, which is removed in the same
+    /// block the new key is added.
+    #[n(2)]
+    pub replaces: Option<ByteVec>,
+}
+
+#[derive(Encode)]
+#[cbor(map)]
+pub struct ParamsArgs {}
+
+/// The values of the validator parameters, as the ledger encodes them. Other
+/// parameters are skipped.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum ParamValue {
+    #[n(1)]
+    Integer(#[n(0)] u64),
+    #[n(5)]
+    AddressSet(#[n(0)] BTreeSet<Address>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ParamInfo {
+    #[n(1)]
+    pub value: ParamValue,
+}
+
+/// Who can update the validators, and the quorum margin they must keep.
+/// They are governance parameters of the backend, so they are part of its
+/// consensus state and every node checks the same ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatorParams {
+    pub admins: BTreeSet<Address>,
+    pub quorum_margin: u64,
+}
+
+impl Default for ValidatorParams {
+    /// Without admins, every update is refused.
+    fn default() -> Self {
+        Self {
+            admins: BTreeSet::new(),
+            quorum_margin: DEFAULT_QUORUM_MARGIN,
+        }
+    }
+}
+
+impl ValidatorParams {
+    /// Read the validator parameters from the response of
+    /// `governance.params`. Those missing keep their default.
+    pub fn decode(payload: &[u8]) -> Result<Self, minicbor::decode::Error> {
+        let mut params = Self::default();
+        let mut d = Decoder::new(payload);
+        let len = d.map()?.unwrap_or_default();
+        for _ in 0..len {
+            if d.u32()? != 0 {
+                d.skip()?;
+                continue;
+            }
+            let count = d.map()?.unwrap_or_default();
+            for _ in 0..count {
+                match (d.str()?, d.probe().decode::<ParamInfo>()) {
+                    (
+                        ADMINS_PARAM,
+                        Ok(ParamInfo {
+                            value: ParamValue::AddressSet(admins),
+                        }),
+                    ) => params.admins = admins,
+                    (
+                        QUORUM_MARGIN_PARAM,
+                        Ok(ParamInfo {
+                            value: ParamValue::Integer(n),
+                        }),
+                    ) => params.quorum_margin = n,
+                    _ => {}
+                }
+                d.skip()?;
+            }
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PendingValidatorUpdate {
+    #[n(0)]
+    pub public_key: ByteVec,
+
+    #[n(1)]
+    pub power: u64,
+
+    /// The height Tendermint uses the update from, once it was returned at
+    /// the end of a block. Updates without one are buffered until the end
+    /// of the current block.
+    #[n(2)]
+    pub effective_height: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PendingValidatorUpdatesReturns {
+    #[n(0)]
+    pub updates: Vec<PendingValidatorUpdate>,
+}
+
+/// The Tendermint address of an Ed25519 validator key.
+fn validator_address(public_key: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    sha2::Sha256::digest(public_key)[..20].to_vec()
+}
+
+fn check_key(public_key: &[u8]) -> Result<(), ManyError> {
+    if public_key.len() == ED25519_PUBLIC_KEY_LENGTH {
+        Ok(())
+    } else {
+        Err(ManyError::unknown(format!(
+            "Invalid validator key; expected a {}-byte Ed25519 key.",
+            ED25519_PUBLIC_KEY_LENGTH
+        )))
+    }
+}
+
+fn attribute(key: &str, value: String) -> EventAttribute {
+    EventAttribute {
+        key: key.to_string().into_bytes().into(),
+        value: value.into_bytes().into(),
+        index: true,
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    height: u64,
+
+    /// The voting power of the validators of the last block, by address.
+    validators: BTreeMap<Vec<u8>, u64>,
+
+    /// The updates requested in the current block, by key.
+    buffered: BTreeMap<Vec<u8>, u64>,
+
+    /// The updates returned to Tendermint which are not used yet.
+    scheduled: Vec<PendingValidatorUpdate>,
+}
+
+impl Inner {
+    /// The power of a validator once the buffered updates are applied.
+    fn power(&self, public_key: &[u8]) -> u64 {
+        self.buffered.get(public_key).copied().unwrap_or_else(|| {
+            self.validators
+                .get(&validator_address(public_key))
+                .copied()
+                .unwrap_or_default()
+        })
+    }
+}
+
+/// The validator updates requested through MANY. They are executed as
+/// transactions by every node, against the validator parameters of the
+/// backend, so every node returns the same updates at the end of the block.
+#[derive(Default)]
+pub struct ValidatorUpdates {
+    inner: Mutex<Inner>,
+}
+
+impl Debug for ValidatorUpdates {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ValidatorUpdates")
+    }
+}
+
+impl ValidatorUpdates {
+    /// Start a block, given the address and voting power of the validators
+    /// of the last block, as found in its commit info.
+    pub fn begin_block(&self, height: u64, validators: impl IntoIterator<Item = (Vec<u8>, u64)>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.height = height;
+        inner.validators = validators.into_iter().collect();
+        inner.buffered.clear();
+        inner
+            .scheduled
+            .retain(|u| u.effective_height.map_or(false, |h| h > height));
+    }
+
+    /// Buffer the update requested by `sender` until the end of the block.
+    pub fn request(
+        &self,
+        params: &ValidatorParams,
+        sender: &Address,
+        args: UpdateValidatorArgs,
+    ) -> Result<(), ManyError> {
+        if !params.admins.contains(sender) {
+            return Err(ManyError::invalid_from_identity());
+        }
+        check_key(&args.public_key)?;
+
+        let mut inner = self.inner.lock().unwrap();
+        let mut updates = vec![(args.public_key.to_vec(), args.power)];
+        if let Some(old_key) = args.replaces {
+            check_key(&old_key)?;
+            if old_key == args.public_key {
+                return Err(ManyError::unknown(
+                    "A validator key cannot replace itself.".to_string(),
+                ));
+            }
+            if inner.power(&old_key) == 0 {
+                return Err(ManyError::unknown(format!(
+                    "Unknown validator key {}.",
+                    hex::encode(old_key.as_slice())
+                )));
+            }
+            updates.push((old_key.to_vec(), 0));
+        }
+
+        // The margin is checked against the validators of the last block, so
+        // the updates of one block cannot remove more power than it allows.
+        // There are none before the first commit.
+        let before: u64 = inner.validators.values().sum();
+        let mut buffered = inner.buffered.clone();
+        buffered.extend(updates.iter().cloned());
+        let after = total_power(&inner.validators, &buffered);
+        if before > 0 && after * 100 < before * params.quorum_margin {
+            return Err(ManyError::unknown(format!(
+                "The update would reduce the total voting power from {} to {}, below {}%.",
+                before, after, params.quorum_margin
+            )));
+        }
+
+        inner.buffered = buffered;
+        Ok(())
+    }
+
+    /// End the block, returning the validator updates buffered and the
+    /// events recording them.
+    pub fn end_block(&self) -> (Vec<ValidatorUpdate>, Vec<Event>) {
+        let mut inner = self.inner.lock().unwrap();
+        let effective_height = inner.height + VALIDATOR_UPDATE_DELAY;
+        let buffered = std::mem::take(&mut inner.buffered);
+
+        let mut updates = vec![];
+        let mut events = vec![];
+        for (public_key, power) in buffered {
+            updates.push(ValidatorUpdate {
+                pub_key: Some(PublicKey {
+                    sum: Some(public_key::Sum::Ed25519(public_key.clone().into())),
+                }),
+                power: power as i64,
+            });
+            events.push(Event {
+                r#type: VALIDATOR_UPDATE_EVENT.to_string(),
+                attributes: vec![
+                    attribute("public_key", hex::encode(&public_key)),
+                    attribute("power", power.to_string()),
+                    attribute("effective_height", effective_height.to_string()),
+                ],
+            });
+            inner.scheduled.push(PendingValidatorUpdate {
+                public_key: public_key.into(),
+                power,
+                effective_height: Some(effective_height),
+            });
+        }
+        (updates, events)
+    }
+
+    /// The updates buffered in the current block, then the ones returned to
+    /// Tendermint which are not used yet.
+    pub fn pending(&self) -> PendingValidatorUpdatesReturns {
+        let inner = self.inner.lock().unwrap();
+        let buffered = inner
+            .buffered
+            .iter()
+            .map(|(public_key, power)| PendingValidatorUpdate {
+                public_key: public_key.clone().into(),
+                power: *power,
+                effective_height: None,
+            });
+        PendingValidatorUpdatesReturns {
+            updates: buffered.chain(inner.scheduled.iter().cloned()).collect(),
+        }
+    }
+}
+
+/// The total voting power of the validators once `updates` are applied.
+fn total_power(validators: &BTreeMap<Vec<u8>, u64>, updates: &BTreeMap<Vec<u8>, u64>) -> u64 {
+    let mut validators = validators.clone();
+    for (public_key, power) in updates {
+        validators.insert(validator_address(public_key), *power);
+    }
+    validators.values().sum()
+}
+
+pub trait ValidatorUpdatesModuleBackend: Send {
+    fn pending_validator_updates(&self) -> Result<PendingValidatorUpdatesReturns, ManyError>;
+}
+
+/// A module listing the validator updates which did not take effect yet.
+pub struct ValidatorUpdatesModule<T: ValidatorUpdatesModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: ValidatorUpdatesModuleBackend> ValidatorUpdatesModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "ValidatorUpdatesModule".to_string(),
+                attribute: None,
+                endpoints: vec!["blockchain.pendingValidatorUpdates".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: ValidatorUpdatesModuleBackend> Debug for ValidatorUpdatesModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ValidatorUpdatesModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ValidatorUpdatesModuleBackend> ManyModule for ValidatorUpdatesModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "blockchain.pendingValidatorUpdates" => Ok(()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "blockchain.pendingValidatorUpdates" => {
+                backend.pending_validator_updates().and_then(|returns| {
+                    minicbor::to_vec(returns)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))
+                })
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
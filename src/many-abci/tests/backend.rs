@@ -0,0 +1,232 @@
+use many_abci::abci_app::AbciApp;
+use many_abci::backend::{
+    connect_first, interleave, verify_response, Backend, BackendMetrics, Resolver, SystemResolver,
+    RECONNECT_AFTER_FAILURES,
+};
+use many_abci::listener::{ListenAddr, Listener};
+use many_identity::verifiers::AnonymousVerifier;
+use many_identity::{Address, Identity};
+use many_identity_dsa::ed25519::generate_random_ed25519_identity;
+use many_identity_dsa::CoseKeyVerifier;
+use many_protocol::{encode_cose_sign1_from_response, ResponseMessage};
+use many_server::ManyServer;
+use prometheus::Registry;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A resolver returning whatever addresses the test sets.
+#[derive(Default)]
+struct StubResolver {
+    addrs: Mutex<Vec<SocketAddr>>,
+    calls: Mutex<Vec<(String, u16)>>,
+}
+
+impl StubResolver {
+    fn set(&self, addrs: Vec<SocketAddr>) {
+        *self.addrs.lock().unwrap() = addrs;
+    }
+}
+
+impl Resolver for StubResolver {
+    fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        self.calls.lock().unwrap().push((host.to_string(), port));
+        Ok(self.addrs.lock().unwrap().clone())
+    }
+}
+
+fn listen() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+/// An address nothing listens on.
+fn closed() -> SocketAddr {
+    listen().1
+}
+
+fn counter(registry: &Registry, name: &str) -> u64 {
+    registry
+        .gather()
+        .iter()
+        .find(|f| f.get_name() == name)
+        .map_or(0, |f| f.get_metric()[0].get_counter().get_value() as u64)
+}
+
+#[test]
+fn interleave_families() {
+    let v4 = |n: u8| SocketAddr::from(([10, 0, 0, n], 80));
+    let v6 = |n: u16| SocketAddr::from(([0xfd00, 0, 0, 0, 0, 0, 0, n], 80));
+
+    assert_eq!(
+        interleave(vec![v6(1), v6(2), v6(3), v4(1), v4(2)]),
+        vec![v6(1), v4(1), v6(2), v4(2), v6(3)]
+    );
+    assert_eq!(
+        interleave(vec![v4(1), v4(2), v6(1)]),
+        vec![v4(1), v6(1), v4(2)]
+    );
+    assert_eq!(interleave(vec![]), vec![]);
+}
+
+#[test]
+fn connect_skips_unreachable() {
+    let (_listener, addr) = listen();
+    let found = connect_first(
+        &[closed(), addr],
+        Duration::from_millis(250),
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    assert_eq!(found, addr);
+
+    assert!(connect_first(
+        &[closed()],
+        Duration::from_millis(250),
+        Duration::from_secs(1)
+    )
+    .is_err());
+    assert!(connect_first(&[], Duration::from_millis(250), Duration::from_secs(1)).is_err());
+}
+
+#[test]
+fn reconnect_resolves_again() {
+    let (_a, addr_a) = listen();
+    let (_b, addr_b) = listen();
+    let resolver = Arc::new(StubResolver::default());
+    resolver.set(vec![addr_a]);
+
+    let registry = Registry::new();
+    let metrics = BackendMetrics::new(&registry).unwrap();
+    let backend = Backend::new("http://backend.example:8000", resolver.clone())
+        .unwrap()
+        .with_metrics(&metrics);
+    assert_eq!(backend.address(), Some(addr_a));
+
+    // The backend moved.
+    resolver.set(vec![closed(), addr_b]);
+    assert_eq!(backend.reconnect(), Ok(addr_b));
+    assert_eq!(backend.address(), Some(addr_b));
+    assert_eq!(
+        resolver.calls.lock().unwrap().as_slice(),
+        [
+            ("backend.example".to_string(), 8000),
+            ("backend.example".to_string(), 8000)
+        ]
+    );
+    assert_eq!(counter(&registry, "many_abci_backend_reconnects_total"), 1);
+    assert_eq!(
+        counter(&registry, "many_abci_backend_connection_changes_total"),
+        1
+    );
+
+    // Reconnecting to the same address is not a change.
+    assert_eq!(backend.reconnect(), Ok(addr_b));
+    assert_eq!(counter(&registry, "many_abci_backend_reconnects_total"), 2);
+    assert_eq!(
+        counter(&registry, "many_abci_backend_connection_changes_total"),
+        1
+    );
+
+    // A failed reconnection keeps the current connection.
+    resolver.set(vec![closed()]);
+    assert!(backend.reconnect().is_err());
+    assert_eq!(backend.address(), Some(addr_b));
+}
+
+#[test]
+fn reconnect_after_failures() {
+    let (_a, addr_a) = listen();
+    let (_b, addr_b) = listen();
+    let resolver = Arc::new(StubResolver::default());
+    resolver.set(vec![addr_a]);
+    let backend = Backend::new("http://backend.example:8000", resolver.clone())
+        .unwrap()
+        .with_reconnect_interval(Duration::ZERO);
+    resolver.set(vec![addr_b]);
+
+    // A success resets the count of consecutive failures.
+    for _ in 1..RECONNECT_AFTER_FAILURES {
+        backend.record(false);
+    }
+    backend.record(true);
+    for _ in 1..RECONNECT_AFTER_FAILURES {
+        backend.record(false);
+    }
+    assert_eq!(backend.address(), Some(addr_a));
+
+    backend.record(false);
+    assert_eq!(backend.address(), Some(addr_b));
+}
+
+#[test]
+fn reconnect_interval() {
+    let (_a, addr_a) = listen();
+    let (_b, addr_b) = listen();
+    let resolver = Arc::new(StubResolver::default());
+    resolver.set(vec![addr_a]);
+    let backend = Backend::new("http://backend.example:8000", resolver.clone())
+        .unwrap()
+        .with_reconnect_interval(Duration::from_secs(3600));
+    resolver.set(vec![addr_b]);
+
+    for _ in 0..RECONNECT_AFTER_FAILURES * 2 {
+        backend.record(false);
+    }
+    assert_eq!(backend.address(), Some(addr_a));
+}
+
+#[test]
+fn verify_signer() {
+    let backend = generate_random_ed25519_identity();
+    let other = generate_random_ed25519_identity();
+    let response = |from: Address| ResponseMessage {
+        from,
+        data: Ok(vec![]),
+        ..Default::default()
+    };
+
+    let envelope = encode_cose_sign1_from_response(response(backend.address()), &backend).unwrap();
+    assert!(verify_response(&envelope, &backend.address()).is_ok());
+
+    // Signed by another key.
+    let envelope = encode_cose_sign1_from_response(response(other.address()), &other).unwrap();
+    assert!(verify_response(&envelope, &backend.address()).is_err());
+
+    // Signed by another key, claiming to be the backend.
+    let envelope = encode_cose_sign1_from_response(response(backend.address()), &other).unwrap();
+    assert!(verify_response(&envelope, &backend.address()).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backend_with_wrong_key() {
+    let expected = generate_random_ed25519_identity().address();
+    let server = ManyServer::simple(
+        "test",
+        generate_random_ed25519_identity(),
+        (AnonymousVerifier, CoseKeyVerifier),
+        None,
+    );
+    let listener =
+        Listener::bind(ListenAddr::Tcp("127.0.0.1:0".to_string()), server, None).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(listener.serve());
+
+    let backend = Backend::new(url.as_str(), Arc::new(SystemResolver)).unwrap();
+    assert!(backend.call_("status", ()).await.is_ok());
+
+    let backend = backend.with_identity(expected);
+    assert!(backend.call_("status", ()).await.is_err());
+}
+
+/// Every node rejecting a response delivers the same result, whatever the
+/// response was.
+#[test]
+fn unverified_response_deterministic() {
+    let a = AbciApp::unverified_response("The response is signed by a.".to_string());
+    let b = AbciApp::unverified_response("The response is signed by b.".to_string());
+    assert_ne!(a.code, 0);
+    assert_eq!(a.code, b.code);
+    assert_eq!(a.data, b.data);
+}
@@ -0,0 +1,152 @@
+use async_trait::async_trait;
+use many_abci::backend_status::{
+    refresh, run, BackendInfo, RefreshOutcome, SharedStatus, StatusDiff, StatusMetrics,
+    StatusSource,
+};
+use many_error::ManyError;
+use many_modules::abci_backend::EndpointInfo;
+use many_modules::base;
+use prometheus::Registry;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A backend whose endpoints the test changes, or makes unreachable.
+#[derive(Default)]
+struct StubSource {
+    endpoints: Mutex<Option<Vec<(&'static str, bool)>>>,
+}
+
+impl StubSource {
+    fn new(endpoints: &[(&'static str, bool)]) -> Arc<Self> {
+        let source = Arc::new(Self::default());
+        source.set(Some(endpoints));
+        source
+    }
+
+    fn set(&self, endpoints: Option<&[(&'static str, bool)]>) {
+        *self.endpoints.lock().unwrap() = endpoints.map(<[_]>::to_vec);
+    }
+}
+
+#[async_trait]
+impl StatusSource for StubSource {
+    async fn fetch(&self) -> Result<BackendInfo, ManyError> {
+        let endpoints = self
+            .endpoints
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| ManyError::unknown("Backend unreachable."))?;
+        let status = base::StatusBuilder::default()
+            .name("many-ledger".to_string())
+            .version(1)
+            .attributes(vec![])
+            .build()
+            .unwrap();
+        BackendInfo::new(
+            status,
+            endpoints
+                .into_iter()
+                .map(|(name, is_command)| (name.to_string(), EndpointInfo { is_command }))
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+}
+
+async fn shared(source: &Arc<StubSource>) -> Arc<SharedStatus> {
+    Arc::new(SharedStatus::new(source.fetch().await.unwrap()))
+}
+
+fn gauge(registry: &Registry, name: &str) -> i64 {
+    registry
+        .gather()
+        .iter()
+        .find(|f| f.get_name() == name)
+        .map_or(0, |f| f.get_metric()[0].get_gauge().get_value() as i64)
+}
+
+#[tokio::test]
+async fn diff_and_short_circuit() {
+    let source = StubSource::new(&[("ledger.info", false), ("ledger.send", true)]);
+    let shared = shared(&source).await;
+
+    assert_eq!(refresh(&source, &shared).await, RefreshOutcome::Unchanged);
+    let before = shared.get();
+
+    source.set(Some(&[("ledger.send", false), ("ledger.batch", true)]));
+    assert_eq!(
+        refresh(&source, &shared).await,
+        RefreshOutcome::Updated(StatusDiff {
+            added: vec!["ledger.batch".to_string()],
+            removed: vec!["ledger.info".to_string()],
+            changed: vec!["ledger.send".to_string()],
+            status: false,
+        })
+    );
+    assert_eq!(refresh(&source, &shared).await, RefreshOutcome::Unchanged);
+
+    // Infos handed out before are not modified.
+    assert!(before.endpoints.contains_key("ledger.info"));
+    assert!(!shared.get().endpoints.contains_key("ledger.info"));
+}
+
+#[tokio::test]
+async fn failures_keep_last_known() {
+    let source = StubSource::new(&[("ledger.info", false)]);
+    let registry = Registry::new();
+    let metrics = StatusMetrics::new(&registry).unwrap();
+    let shared = SharedStatus::new(source.fetch().await.unwrap()).with_metrics(&metrics);
+
+    source.set(None);
+    assert!(matches!(
+        refresh(&source, &shared).await,
+        RefreshOutcome::Failed(_)
+    ));
+    assert!(shared.is_stale());
+    assert_eq!(gauge(&registry, "many_abci_backend_status_stale"), 1);
+    assert!(shared.route("ledger.info").is_some());
+
+    source.set(Some(&[("ledger.info", false)]));
+    assert_eq!(refresh(&source, &shared).await, RefreshOutcome::Unchanged);
+    assert!(!shared.is_stale());
+    assert_eq!(gauge(&registry, "many_abci_backend_status_stale"), 0);
+}
+
+#[tokio::test]
+async fn routing_follows_backend() {
+    let source = StubSource::new(&[("ledger.info", false)]);
+    let shared = shared(&source).await;
+    let task = tokio::spawn(run(
+        source.clone(),
+        shared.clone(),
+        Duration::from_millis(10),
+    ));
+    assert!(shared.route("kvstore.get").is_none());
+
+    // The backend is upgraded in place.
+    source.set(Some(&[("ledger.info", false), ("kvstore.get", false)]));
+    let mut routed = false;
+    for _ in 0..200 {
+        if shared.route("kvstore.get").is_some() {
+            routed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(routed, "The new endpoint was not routed");
+    assert_eq!(
+        shared.route("ledger.info").map(|e| e.is_command),
+        Some(false)
+    );
+
+    source.set(Some(&[("kvstore.get", false)]));
+    for _ in 0..200 {
+        if shared.route("ledger.info").is_none() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(shared.route("ledger.info").is_none());
+    task.abort();
+}
@@ -0,0 +1,204 @@
+use coset::CborSerializable;
+use many_abci::block_digest::{
+    verify, BlockDigest, BlockDigestArgs, BlockDigestModule, BlockDigestModuleBackend,
+    BlockDigests, DigestEntry, BLOCK_DIGEST_EVENT,
+};
+use many_abci::replay::{RecordedBlock, RecordedTx};
+use many_abci::tx_location::tx_hash;
+use many_error::ManyError;
+use many_identity::{Address, Identity};
+use many_identity_dsa::ed25519::generate_random_ed25519_identity;
+use many_modules::ManyModule;
+use many_protocol::{encode_cose_sign1_from_request, RequestMessageBuilder};
+use std::sync::{Arc, Mutex};
+use tendermint_proto::abci::Event;
+
+fn request(sender: &impl Identity, method: &str) -> Vec<u8> {
+    let message = RequestMessageBuilder::default()
+        .from(sender.address())
+        .method(method.to_string())
+        .data(vec![])
+        .build()
+        .unwrap();
+    encode_cose_sign1_from_request(message, sender)
+        .unwrap()
+        .to_vec()
+        .unwrap()
+}
+
+/// Deliver a scripted chain of blocks of transactions and their result
+/// codes, as the ABCI application does, returning the events of each block.
+fn deliver(digests: &BlockDigests, blocks: &[Vec<(Vec<u8>, u32)>]) -> Vec<Vec<Event>> {
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, txs)| {
+            digests.begin_block(i as u64 + 1);
+            for (tx, code) in txs {
+                digests.deliver_tx(DigestEntry::new(tx, *code));
+            }
+            digests.end_block()
+        })
+        .collect()
+}
+
+fn recorded(height: u64, txs: &[(Vec<u8>, u32)]) -> RecordedBlock {
+    RecordedBlock {
+        height,
+        time: None,
+        txs: txs
+            .iter()
+            .map(|(tx, code)| RecordedTx {
+                tx: tx.clone(),
+                code: *code,
+                ..Default::default()
+            })
+            .collect(),
+    }
+}
+
+fn event_attribute(event: &Event, key: &str) -> String {
+    event
+        .attributes
+        .iter()
+        .find(|a| a.key.to_vec() == key.as_bytes())
+        .map(|a| String::from_utf8(a.value.to_vec()).unwrap())
+        .unwrap()
+}
+
+fn blocks() -> Vec<Vec<(Vec<u8>, u32)>> {
+    let alice = generate_random_ed25519_identity();
+    let bob = generate_random_ed25519_identity();
+    vec![
+        vec![
+            (request(&alice, "ledger.send"), 0),
+            (request(&bob, "kvstore.put"), 0),
+            (b"not a request".to_vec(), 2),
+        ],
+        vec![],
+        vec![
+            (request(&bob, "ledger.send"), 1),
+            (request(&alice, "account.create"), 0),
+        ],
+    ]
+}
+
+#[test]
+fn scripted_blocks() {
+    let blocks = blocks();
+    let digests = BlockDigests::default();
+    let events = deliver(&digests, &blocks);
+
+    for (i, txs) in blocks.iter().enumerate() {
+        let height = i as u64 + 1;
+        let digest = digests.get(height).unwrap();
+        assert!(digest.is_consistent());
+        assert_eq!(digest.entries.len(), txs.len());
+        for ((tx, code), entry) in txs.iter().zip(&digest.entries) {
+            assert_eq!(entry.hash.as_slice(), tx_hash(tx).as_slice());
+            assert_eq!(entry.code, *code);
+        }
+
+        // The event carries the same digest.
+        assert_eq!(events[i].len(), 1);
+        assert_eq!(events[i][0].r#type, BLOCK_DIGEST_EVENT);
+        assert_eq!(
+            event_attribute(&events[i][0], "digest"),
+            hex::encode(digest.digest.as_slice())
+        );
+        assert_eq!(
+            event_attribute(&events[i][0], "messages"),
+            txs.len().to_string()
+        );
+
+        // Third parties recompute it from the block recorded by Tendermint.
+        assert_eq!(BlockDigest::from_recorded(&recorded(height, txs)), digest);
+        assert_eq!(verify(&digest, &recorded(height, txs)), Ok(()));
+    }
+
+    let first = digests.get(1).unwrap();
+    assert_eq!(first.entries[1].method, "kvstore.put");
+    assert_eq!(first.entries[2].method, "");
+    assert_eq!(first.entries[2].sender, Address::anonymous());
+
+    // Empty blocks at different heights have different digests.
+    assert_ne!(
+        BlockDigest::new(2, vec![]).digest,
+        BlockDigest::new(4, vec![]).digest
+    );
+    assert_eq!(digests.get(4), None);
+}
+
+#[test]
+fn tampered() {
+    let blocks = blocks();
+    let digests = BlockDigests::default();
+    deliver(&digests, &blocks);
+    let digest = digests.get(3).unwrap();
+    let block = recorded(3, &blocks[2]);
+
+    // An entry changed without the digest.
+    let mut changed = digest.clone();
+    changed.entries[0].code = 0;
+    assert!(!changed.is_consistent());
+    assert!(verify(&changed, &block).is_err());
+
+    // An entry changed with the digest recomputed.
+    let changed = BlockDigest::new(3, changed.entries);
+    let err = verify(&changed, &block).unwrap_err();
+    assert!(err.starts_with("Message 0 of block 3 differs"), "{}", err);
+
+    // Entries reordered.
+    let mut entries = digest.entries.clone();
+    entries.swap(0, 1);
+    assert!(verify(&BlockDigest::new(3, entries), &block).is_err());
+
+    // A block whose recorded result differs from the digested one.
+    let mut block = block;
+    block.txs[1].code = 5;
+    let err = verify(&digest, &block).unwrap_err();
+    assert!(err.starts_with("Message 1 of block 3 differs"), "{}", err);
+
+    // A message missing from the digest.
+    let mut entries = digest.entries;
+    entries.pop();
+    let err = verify(&BlockDigest::new(3, entries), &recorded(3, &blocks[2])).unwrap_err();
+    assert_eq!(err, "Block 3 has 2 messages, but 1 were digested.");
+}
+
+#[test]
+fn bounded() {
+    let digests = BlockDigests::new(2);
+    deliver(&digests, &blocks());
+
+    assert_eq!(digests.get(1), None);
+    assert!(digests.get(2).is_some());
+    assert!(digests.get(3).is_some());
+}
+
+struct Recorded(Arc<BlockDigests>);
+
+impl BlockDigestModuleBackend for Recorded {
+    fn block_digest(&self, args: BlockDigestArgs) -> Result<BlockDigest, ManyError> {
+        self.0
+            .get(args.height)
+            .ok_or_else(|| ManyError::unknown("Unknown block."))
+    }
+}
+
+#[tokio::test]
+async fn block_digest_endpoint() {
+    let digests = Arc::new(BlockDigests::default());
+    deliver(&digests, &blocks());
+    let module = BlockDigestModule::new(Arc::new(Mutex::new(Recorded(digests.clone()))));
+
+    let request = RequestMessageBuilder::default()
+        .from(Address::anonymous())
+        .method("blockchain.blockDigest".to_string())
+        .data(minicbor::to_vec(BlockDigestArgs { height: 1 }).unwrap())
+        .build()
+        .unwrap();
+    let response = module.execute(request).await.unwrap();
+    let returned: BlockDigest = minicbor::decode(&response.data.unwrap()).unwrap();
+    assert_eq!(returned, digests.get(1).unwrap());
+}
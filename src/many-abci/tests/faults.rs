@@ -0,0 +1,285 @@
+#![cfg(feature = "testing-hooks")]
+
+use many_abci::backend::{Backend, BackendMetrics, SystemResolver, RECONNECT_AFTER_FAILURES};
+use many_abci::backend_status::{BackendInfo, SharedStatus};
+use many_abci::faults::{parse, Fault, FaultAction, Faults, FaultyClient};
+use many_abci::listener::{ListenAddr, Listener};
+use many_abci::many_app::AbciModuleMany;
+use many_abci::module::AbciBlockchainModuleImpl;
+use many_abci::request_index::RequestIndex;
+use many_abci::tx_location::{tx_hash, TxLocationArgs, TxLocationModuleBackend, TxLocations};
+use many_error::ManyErrorCode;
+use many_identity::verifiers::AnonymousVerifier;
+use many_identity::Identity;
+use many_identity_dsa::ed25519::generate_random_ed25519_identity;
+use many_identity_dsa::CoseKeyVerifier;
+use many_modules::abci_backend::EndpointInfo;
+use many_modules::base;
+use many_protocol::{
+    decode_response_from_cose_sign1, encode_cose_sign1_from_request, RequestMessageBuilder,
+};
+use many_server::transport::LowLevelManyRequestHandler;
+use many_server::ManyServer;
+use many_types::Timestamp;
+use prometheus::Registry;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tendermint_rpc::{Method, MockClient, MockRequestMethodMatcher};
+
+fn fault(s: &str) -> Fault {
+    Fault::from_str(s).unwrap()
+}
+
+fn counter(registry: &Registry, name: &str) -> u64 {
+    registry
+        .gather()
+        .iter()
+        .find(|f| f.get_name() == name)
+        .map_or(0, |f| f.get_metric()[0].get_counter().get_value() as u64)
+}
+
+/// A Tendermint node answering `broadcast_tx_sync` and `tx` with canned
+/// responses, and whose calls go through `faults`.
+fn tendermint(faults: &Arc<Faults>) -> FaultyClient<MockClient<MockRequestMethodMatcher>> {
+    let hash = hex::encode_upper([1; 32]);
+    let matcher = MockRequestMethodMatcher::default()
+        .map(
+            Method::BroadcastTxSync,
+            Ok(format!(
+                r#"{{"jsonrpc":"2.0","id":"","result":{{"code":0,"data":"","log":"","hash":"{}"}}}}"#,
+                hash
+            )),
+        )
+        .map(
+            Method::Tx,
+            Ok(format!(
+                r#"{{"jsonrpc":"2.0","id":"","result":{{"hash":"{}","height":"5","index":2,"tx_result":{{"code":0,"data":"","log":"","info":"","gas_wanted":"0","gas_used":"0","events":[],"codespace":""}},"tx":""}}}}"#,
+                hash
+            )),
+        );
+    let (client, driver) = MockClient::new(matcher);
+    tokio::spawn(driver.run());
+    FaultyClient::new(client, faults.clone())
+}
+
+fn shared_status() -> Arc<SharedStatus> {
+    let status = base::StatusBuilder::default()
+        .name("many-ledger".to_string())
+        .version(1)
+        .attributes(vec![])
+        .build()
+        .unwrap();
+    let endpoints =
+        BTreeMap::from([("ledger.send".to_string(), EndpointInfo { is_command: true })]);
+    Arc::new(SharedStatus::new(
+        BackendInfo::new(status, endpoints).unwrap(),
+    ))
+}
+
+async fn backend_server() -> String {
+    let server = ManyServer::simple(
+        "test",
+        generate_random_ed25519_identity(),
+        (AnonymousVerifier, CoseKeyVerifier),
+        None,
+    );
+    let listener =
+        Listener::bind(ListenAddr::Tcp("127.0.0.1:0".to_string()), server, None).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(listener.serve());
+    url
+}
+
+#[test]
+fn control_file_format() {
+    assert_eq!(
+        parse("# Lose a broadcast.\nbroadcast_tx_sync drop\n\n* delay:250ms 3\nstatus error:-32000 2\n")
+            .unwrap(),
+        vec![
+            Fault {
+                method: "broadcast_tx_sync".to_string(),
+                action: FaultAction::Drop,
+                count: 1,
+            },
+            Fault {
+                method: "*".to_string(),
+                action: FaultAction::Delay(Duration::from_millis(250)),
+                count: 3,
+            },
+            Fault {
+                method: "status".to_string(),
+                action: FaultAction::Error(Some(-32000)),
+                count: 2,
+            },
+        ]
+    );
+
+    for invalid in [
+        "status",
+        "status crash",
+        "status delay:soon",
+        "status error:x",
+        "status drop twice",
+        "status drop 1 2",
+    ] {
+        assert!(parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn next_calls_only() {
+    let faults = Faults::default();
+    faults.add(fault("status error 2"));
+    faults.add(fault("* drop"));
+
+    assert_eq!(faults.next("status"), Some(FaultAction::Error(None)));
+    assert_eq!(faults.next("block"), Some(FaultAction::Drop));
+    assert_eq!(faults.next("block"), None);
+    assert_eq!(faults.next("status"), Some(FaultAction::Error(None)));
+    assert_eq!(faults.next("status"), None);
+    assert!(faults.is_empty());
+}
+
+#[test]
+fn control_file_read_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("faults");
+    let faults = Faults::default().with_control_file(path.clone());
+    assert_eq!(faults.next("tx"), None);
+
+    std::fs::write(&path, "tx error:7\n").unwrap();
+    assert_eq!(faults.next("tx"), Some(FaultAction::Error(Some(7))));
+    assert!(!path.exists());
+    assert_eq!(faults.next("tx"), None);
+
+    // An invalid file is ignored.
+    std::fs::write(&path, "tx explode\n").unwrap();
+    assert_eq!(faults.next("tx"), None);
+    assert!(!path.exists());
+}
+
+/// Errors of the backend count as failures, and the bridge reconnects after
+/// enough of them.
+#[tokio::test(flavor = "multi_thread")]
+async fn backend_reconnects_after_errors() {
+    let url = backend_server().await;
+    let registry = Registry::new();
+    let faults = Arc::new(Faults::default());
+    let backend = Backend::new(url.as_str(), Arc::new(SystemResolver))
+        .unwrap()
+        .with_reconnect_interval(Duration::ZERO)
+        .with_metrics(&BackendMetrics::new(&registry).unwrap())
+        .with_faults(faults.clone());
+
+    faults.add(Fault {
+        method: "status".to_string(),
+        action: FaultAction::Error(Some(7)),
+        count: RECONNECT_AFTER_FAILURES,
+    });
+    for _ in 0..RECONNECT_AFTER_FAILURES {
+        let err = backend.call_("status", ()).await.unwrap_err();
+        assert_eq!(err.code(), ManyErrorCode::from(7));
+    }
+    assert_eq!(counter(&registry, "many_abci_backend_reconnects_total"), 1);
+    assert!(backend.call_("status", ()).await.is_ok());
+}
+
+/// A lost response is not a failure of the connection.
+#[tokio::test(flavor = "multi_thread")]
+async fn backend_dropped_responses() {
+    let url = backend_server().await;
+    let registry = Registry::new();
+    let faults = Arc::new(Faults::default());
+    let backend = Backend::new(url.as_str(), Arc::new(SystemResolver))
+        .unwrap()
+        .with_reconnect_interval(Duration::ZERO)
+        .with_metrics(&BackendMetrics::new(&registry).unwrap())
+        .with_faults(faults.clone());
+
+    faults.add(Fault {
+        method: "status".to_string(),
+        action: FaultAction::Drop,
+        count: RECONNECT_AFTER_FAILURES,
+    });
+    for _ in 0..RECONNECT_AFTER_FAILURES {
+        assert!(backend.call_("status", ()).await.is_err());
+    }
+    assert_eq!(counter(&registry, "many_abci_backend_reconnects_total"), 0);
+    assert!(backend.call_("status", ()).await.is_ok());
+}
+
+/// A command whose broadcast response was lost is not indexed, and its
+/// retry is indexed once, even when slow.
+#[tokio::test(flavor = "multi_thread")]
+async fn broadcast_retry_indexed_once() {
+    let faults = Arc::new(Faults::default());
+    let index = Arc::new(RequestIndex::in_memory(100, None));
+    let bridge = AbciModuleMany::new(
+        tendermint(&faults),
+        shared_status(),
+        generate_random_ed25519_identity(),
+        None,
+    )
+    .with_request_index(index.clone());
+
+    let sender = generate_random_ed25519_identity();
+    let mut message = RequestMessageBuilder::default()
+        .from(sender.address())
+        .method("ledger.send".to_string())
+        .data(vec![])
+        .build()
+        .unwrap();
+    message.timestamp = Some(Timestamp::now());
+    message.nonce = Some(vec![1, 2, 3]);
+    let envelope = encode_cose_sign1_from_request(message, &sender).unwrap();
+    let send = || async {
+        let response = bridge.execute(envelope.clone()).await.unwrap();
+        decode_response_from_cose_sign1(&response, None, &CoseKeyVerifier)
+            .unwrap()
+            .data
+    };
+
+    faults.add(fault("broadcast_tx_sync drop"));
+    assert!(send().await.is_err());
+    assert!(index.is_empty());
+
+    faults.add(fault("broadcast_tx_sync delay:200ms"));
+    let start = Instant::now();
+    assert!(send().await.is_ok());
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(index.len(), 1);
+}
+
+/// The location of a transaction delivered by this node comes from its
+/// cache, without asking Tendermint for the block.
+#[tokio::test(flavor = "multi_thread")]
+async fn location_from_cache() {
+    let faults = Arc::new(Faults::default());
+    let locations = Arc::new(TxLocations::default());
+    let time = Timestamp::new(1_000).unwrap();
+    let module =
+        AbciBlockchainModuleImpl::new(tendermint(&faults)).with_tx_locations(locations.clone());
+    let args = TxLocationArgs {
+        token: vec![1; 32].into(),
+    };
+
+    faults.add(fault("block error"));
+    let location = module.tx_location(args).unwrap();
+    assert_eq!((location.height, location.index), (5, 2));
+    assert_eq!(location.time, None);
+    assert!(faults.is_empty());
+
+    // Once delivered by this node, the location of a transaction is cached.
+    let tx = b"tx";
+    locations.begin_block(5, Some(time));
+    locations.deliver_tx(tx);
+    let args = TxLocationArgs {
+        token: tx_hash(tx).into(),
+    };
+    faults.add(fault("block error"));
+    let location = module.tx_location(args).unwrap();
+    assert_eq!(location.time, Some(time));
+    assert!(!faults.is_empty());
+}
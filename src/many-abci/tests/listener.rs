@@ -0,0 +1,160 @@
+use coset::{CborSerializable, CoseSign1};
+use many_abci::listener::{ListenAddr, Listener, ListenerMetrics};
+use many_identity::verifiers::AnonymousVerifier;
+use many_identity::{Address, AnonymousIdentity};
+use many_identity_dsa::ed25519::generate_random_ed25519_identity;
+use many_identity_dsa::CoseKeyVerifier;
+use many_protocol::{encode_cose_sign1_from_request, RequestMessageBuilder};
+use many_server::ManyServer;
+use prometheus::Registry;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+fn status_request() -> Vec<u8> {
+    let message = RequestMessageBuilder::default()
+        .from(Address::anonymous())
+        .method("status".to_string())
+        .build()
+        .unwrap();
+    encode_cose_sign1_from_request(message, &AnonymousIdentity)
+        .unwrap()
+        .to_vec()
+        .unwrap()
+}
+
+/// Send a raw HTTP request over a stream and return the status code and body.
+fn post(mut stream: impl Read + Write) -> (u16, Vec<u8>) {
+    let body = status_request();
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(&body).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .expect("Invalid HTTP response");
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+fn requests(registry: &Registry, label: &str) -> u64 {
+    registry
+        .gather()
+        .into_iter()
+        .find(|f| f.get_name() == "many_abci_listener_requests_total")
+        .expect("Counter not registered")
+        .get_metric()
+        .iter()
+        .find(|m| m.get_label()[0].get_value() == label)
+        .map(|m| m.get_counter().get_value() as u64)
+        .unwrap_or(0)
+}
+
+#[test]
+fn listen_addr() {
+    assert_eq!(
+        "127.0.0.1:8000".parse(),
+        Ok(ListenAddr::Tcp("127.0.0.1:8000".to_string()))
+    );
+    assert_eq!(
+        "unix:/tmp/many.sock".parse(),
+        Ok(ListenAddr::Unix(PathBuf::from("/tmp/many.sock")))
+    );
+    assert!("unix:".parse::<ListenAddr>().is_err());
+    assert_eq!(
+        ListenAddr::Unix(PathBuf::from("/tmp/many.sock")).to_string(),
+        "unix:/tmp/many.sock"
+    );
+}
+
+#[test]
+fn not_a_socket() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let server = ManyServer::simple(
+        "test",
+        generate_random_ed25519_identity(),
+        (AnonymousVerifier, CoseKeyVerifier),
+        None,
+    );
+    assert!(Listener::bind(ListenAddr::Unix(file.path().to_path_buf()), server, None).is_err());
+    assert!(file.path().exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tcp_and_unix() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("many.sock");
+    let server = ManyServer::simple(
+        "test",
+        generate_random_ed25519_identity(),
+        (AnonymousVerifier, CoseKeyVerifier),
+        None,
+    );
+    let registry = Registry::new();
+    let metrics = ListenerMetrics::new(&registry).unwrap();
+
+    let tcp = Listener::bind(
+        ListenAddr::Tcp("127.0.0.1:0".to_string()),
+        server.clone(),
+        None,
+    )
+    .unwrap()
+    .with_metrics(&metrics);
+    let unix = Listener::bind(ListenAddr::Unix(socket.clone()), server, Some(0o600))
+        .unwrap()
+        .with_metrics(&metrics);
+
+    let tcp_addr = tcp.local_addr().unwrap();
+    let tcp_label = tcp.addr().to_string();
+    let unix_label = unix.addr().to_string();
+    assert_eq!(
+        std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    let (tcp_term, unix_term) = (tcp.term_signal(), unix.term_signal());
+    let tcp = tokio::spawn(tcp.serve());
+    let unix = tokio::spawn(unix.serve());
+
+    let s = socket.clone();
+    let responses = tokio::task::spawn_blocking(move || {
+        [
+            post(TcpStream::connect(tcp_addr).unwrap()),
+            post(UnixStream::connect(s).unwrap()),
+        ]
+    })
+    .await
+    .unwrap();
+    for (status, body) in responses {
+        assert_eq!(status, 200);
+        assert!(CoseSign1::from_slice(&body).is_ok());
+    }
+    assert_eq!(requests(&registry, &tcp_label), 1);
+    assert_eq!(requests(&registry, &unix_label), 1);
+
+    // Stopping one listener leaves the other serving.
+    unix_term.store(true, Ordering::Relaxed);
+    unix.await.unwrap().unwrap();
+    assert!(!socket.exists());
+
+    let (status, _) =
+        tokio::task::spawn_blocking(move || post(TcpStream::connect(tcp_addr).unwrap()))
+            .await
+            .unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(requests(&registry, &tcp_label), 2);
+
+    tcp_term.store(true, Ordering::Relaxed);
+    tcp.await.unwrap().unwrap();
+}
@@ -0,0 +1,99 @@
+use many_abci::local_attribute::{
+    split_local_attributes, with_local_attributes, LOCAL_ATTRIBUTE_EVENT,
+};
+use many_protocol::ResponseMessage;
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use tendermint_proto::abci::Event;
+
+fn warning(percent: i64) -> Attribute {
+    Attribute::id(1001)
+        .with_argument(CborAny::Int(850))
+        .with_argument(CborAny::Int(1000))
+        .with_argument(CborAny::Int(percent))
+}
+
+fn response() -> ResponseMessage {
+    ResponseMessage {
+        data: Ok(vec![0xa0]),
+        ..Default::default()
+    }
+    .with_attribute(Attribute::id(1))
+}
+
+/// The events as the RPC of Tendermint returns them.
+fn strings(events: &[Event]) -> Vec<(String, Vec<(String, String)>)> {
+    events
+        .iter()
+        .map(|e| {
+            (
+                e.r#type.clone(),
+                e.attributes
+                    .iter()
+                    .map(|a| {
+                        (
+                            String::from_utf8(a.key.to_vec()).unwrap(),
+                            String::from_utf8(a.value.to_vec()).unwrap(),
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+fn restore(response: ResponseMessage, events: &[Event]) -> ResponseMessage {
+    let events = strings(events);
+    with_local_attributes(
+        response,
+        events.iter().map(|(kind, attributes)| {
+            (
+                kind.as_str(),
+                attributes
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect(),
+            )
+        }),
+    )
+}
+
+/// Nodes with different warnings deliver the same data, which is what
+/// consensus hashes.
+#[test]
+fn data_without_local_attributes() {
+    let (plain, events) = split_local_attributes(response());
+    assert!(events.is_empty());
+
+    let (a, a_events) = split_local_attributes(response().with_attribute(warning(85)));
+    let (b, b_events) = split_local_attributes(response().with_attribute(warning(90)));
+    assert_eq!(a.to_bytes().unwrap(), plain.to_bytes().unwrap());
+    assert_eq!(b.to_bytes().unwrap(), plain.to_bytes().unwrap());
+    assert_ne!(a_events, b_events);
+    assert_eq!(a_events[0].r#type, LOCAL_ATTRIBUTE_EVENT);
+
+    // Other attributes stay in the data.
+    assert!(a.attributes.get_attribute(1).is_some());
+}
+
+/// The response served from the events of the transaction is the one the
+/// backend returned.
+#[test]
+fn roundtrip() {
+    let original = response().with_attribute(warning(85));
+    let (delivered, events) = split_local_attributes(original.clone());
+    let delivered = ResponseMessage::from_bytes(&delivered.to_bytes().unwrap()).unwrap();
+
+    let restored = restore(delivered, &events);
+    assert_eq!(restored.attributes.get_attribute(1001), Some(&warning(85)));
+    assert!(restored.attributes.get_attribute(1).is_some());
+    assert_eq!(restored.data, original.data);
+}
+
+#[test]
+fn other_events_ignored() {
+    let (delivered, mut events) = split_local_attributes(response().with_attribute(warning(85)));
+    events[0].r#type = "other".to_string();
+    let restored = restore(delivered, &events);
+    assert!(restored.attributes.get_attribute(1001).is_none());
+}
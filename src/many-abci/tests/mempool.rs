@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use many_abci::error;
+use many_abci::mempool::{MempoolGate, MempoolLimits, MempoolMetrics, MempoolSource};
+use many_error::ManyError;
+use prometheus::Registry;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A mempool whose depth the test changes, or makes unreadable.
+#[derive(Default)]
+struct StubMempool {
+    depth: Mutex<Option<u64>>,
+    reads: AtomicU64,
+}
+
+impl StubMempool {
+    fn set(&self, depth: Option<u64>) {
+        *self.depth.lock().unwrap() = depth;
+    }
+}
+
+#[async_trait]
+impl MempoolSource for StubMempool {
+    async fn depth(&self) -> Result<u64, ManyError> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.depth
+            .lock()
+            .unwrap()
+            .ok_or_else(|| ManyError::unexpected_transport_error("Tendermint unreachable."))
+    }
+}
+
+fn limits(check_interval: Duration) -> MempoolLimits {
+    MempoolLimits {
+        high_water: 100,
+        low_water: 50,
+        check_interval,
+        retry_after: Duration::from_secs(10),
+    }
+}
+
+fn metric(registry: &Registry, name: &str) -> i64 {
+    registry
+        .gather()
+        .iter()
+        .find(|f| f.get_name() == name)
+        .map_or(0, |f| {
+            let metric = &f.get_metric()[0];
+            if metric.has_counter() {
+                metric.get_counter().get_value() as i64
+            } else {
+                metric.get_gauge().get_value() as i64
+            }
+        })
+}
+
+#[tokio::test]
+async fn hysteresis() {
+    let mempool = Arc::new(StubMempool::default());
+    let registry = Registry::new();
+    let metrics = MempoolMetrics::new(&registry).unwrap();
+    let gate = MempoolGate::new(mempool.clone(), limits(Duration::ZERO)).with_metrics(&metrics);
+
+    // (depth, accepted) in order, going up through the high water mark and
+    // back down through the low water mark.
+    let steps = [
+        (0, true),
+        (99, true),
+        (100, false),
+        (150, false),
+        (75, false),
+        (51, false),
+        (50, true),
+        (75, true),
+        (99, true),
+        (100, false),
+    ];
+    let mut rejections = 0;
+    for (depth, accepted) in steps {
+        mempool.set(Some(depth));
+        let result = gate.check().await;
+        assert_eq!(result.is_ok(), accepted, "At a depth of {}", depth);
+        assert_eq!(gate.is_closed().await, !accepted);
+        assert_eq!(
+            metric(&registry, "many_abci_mempool_gate_closed"),
+            !accepted as i64
+        );
+        assert_eq!(metric(&registry, "many_abci_mempool_depth"), depth as i64);
+
+        if let Err(e) = result {
+            rejections += 1;
+            assert_eq!(e.code(), error::mempool_congested(0, 0).code());
+            assert!(e
+                .to_string()
+                .contains(&format!("with {} transactions", depth)));
+            assert!(e.to_string().contains("retry in 10 seconds"));
+        }
+    }
+    assert_eq!(
+        metric(&registry, "many_abci_mempool_rejections_total"),
+        rejections
+    );
+}
+
+#[tokio::test]
+async fn unreadable() {
+    let mempool = Arc::new(StubMempool::default());
+    let gate = MempoolGate::new(mempool.clone(), limits(Duration::ZERO));
+
+    // The gate stays open, and stays closed, when the depth cannot be read.
+    assert!(gate.check().await.is_ok());
+
+    mempool.set(Some(200));
+    assert!(gate.check().await.is_err());
+    mempool.set(None);
+    let e = gate.check().await.unwrap_err();
+    assert!(e.to_string().contains("with 200 transactions"));
+
+    mempool.set(Some(0));
+    assert!(gate.check().await.is_ok());
+}
+
+#[tokio::test]
+async fn cached() {
+    let mempool = Arc::new(StubMempool::default());
+    mempool.set(Some(0));
+    let gate = MempoolGate::new(mempool.clone(), limits(Duration::from_millis(200)));
+
+    for _ in 0..10 {
+        assert!(gate.check().await.is_ok());
+    }
+    assert_eq!(mempool.reads.load(Ordering::Relaxed), 1);
+
+    // The congestion is only seen once the depth is read again.
+    mempool.set(Some(100));
+    assert!(gate.check().await.is_ok());
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(gate.check().await.is_err());
+    assert_eq!(mempool.reads.load(Ordering::Relaxed), 2);
+}
@@ -0,0 +1,82 @@
+use many_abci::metrics::{BlockMetrics, BLOCK_METRICS_EVENT};
+use prometheus::Registry;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tendermint_proto::abci::Event;
+
+fn attributes(events: &[Event]) -> BTreeMap<String, String> {
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].r#type, BLOCK_METRICS_EVENT);
+    events[0]
+        .attributes
+        .iter()
+        .map(|a| {
+            (
+                String::from_utf8(a.key.to_vec()).unwrap(),
+                String::from_utf8(a.value.to_vec()).unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// Returns the sample count and sum of a histogram in the registry.
+fn histogram(registry: &Registry, name: &str) -> (u64, f64) {
+    let family = registry
+        .gather()
+        .into_iter()
+        .find(|f| f.get_name() == name)
+        .expect("Histogram not registered");
+    let h = family.get_metric()[0].get_histogram();
+    (h.get_sample_count(), h.get_sample_sum())
+}
+
+#[test]
+fn block_events() {
+    let metrics = BlockMetrics::default();
+
+    metrics.begin_block();
+    metrics.deliver_tx(100, Duration::from_millis(3));
+    metrics.deliver_tx(50, Duration::from_millis(2));
+    let attrs = attributes(&metrics.end_block());
+    assert_eq!(attrs["deliver_tx_us"], "5000");
+    assert_eq!(attrs["messages"], "2");
+    assert_eq!(attrs["bytes"], "150");
+    assert!(!attrs.contains_key("previous_commit_us"));
+    metrics.commit(Duration::from_millis(7));
+
+    // The next block starts from zero, and reports the last commit.
+    metrics.begin_block();
+    let attrs = attributes(&metrics.end_block());
+    assert_eq!(attrs["deliver_tx_us"], "0");
+    assert_eq!(attrs["messages"], "0");
+    assert_eq!(attrs["bytes"], "0");
+    assert_eq!(attrs["previous_commit_us"], "7000");
+}
+
+#[test]
+fn histograms() {
+    let registry = Registry::new();
+    let metrics = BlockMetrics::with_registry(&registry).unwrap();
+
+    metrics.begin_block();
+    metrics.deliver_tx(100, Duration::from_millis(3));
+    metrics.deliver_tx(50, Duration::from_millis(2));
+    metrics.end_block();
+    metrics.commit(Duration::from_millis(7));
+
+    metrics.begin_block();
+    metrics.deliver_tx(10, Duration::from_millis(1));
+    metrics.end_block();
+    metrics.commit(Duration::from_millis(3));
+
+    let (count, sum) = histogram(&registry, "many_abci_block_messages");
+    assert_eq!((count, sum), (2, 3.0));
+    let (count, sum) = histogram(&registry, "many_abci_block_bytes");
+    assert_eq!((count, sum), (2, 160.0));
+    let (count, sum) = histogram(&registry, "many_abci_block_deliver_tx_seconds");
+    assert_eq!(count, 2);
+    assert!((sum - 0.006).abs() < 1e-9);
+    let (count, sum) = histogram(&registry, "many_abci_block_commit_seconds");
+    assert_eq!(count, 2);
+    assert!((sum - 0.010).abs() < 1e-9);
+}
@@ -0,0 +1,218 @@
+use async_trait::async_trait;
+use coset::CoseSign1;
+use many_abci::backend::{Backend, SystemResolver};
+use many_abci::backend_status::{BackendInfo, SharedStatus};
+use many_abci::listener::{ListenAddr, Listener};
+use many_abci::many_app::AbciModuleMany;
+use many_abci::read_pool::ReadPool;
+use many_identity::verifiers::AnonymousVerifier;
+use many_identity::{Address, AnonymousIdentity, Identity};
+use many_identity_dsa::ed25519::generate_random_ed25519_identity;
+use many_identity_dsa::CoseKeyVerifier;
+use many_modules::abci_backend::EndpointInfo;
+use many_modules::base;
+use many_protocol::{
+    decode_response_from_cose_sign1, encode_cose_sign1_from_request, RequestMessageBuilder,
+};
+use many_server::transport::LowLevelManyRequestHandler;
+use many_server::ManyServer;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tendermint_rpc::{Method, MockClient, MockRequestMethodMatcher};
+
+/// A replica counting the requests it answers, which fails them while it is
+/// down.
+#[derive(Clone)]
+struct Replica {
+    server: Arc<Mutex<ManyServer>>,
+    calls: Arc<AtomicUsize>,
+    down: Arc<AtomicBool>,
+}
+
+impl Debug for Replica {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Replica")
+    }
+}
+
+#[async_trait]
+impl LowLevelManyRequestHandler for Replica {
+    async fn execute(&self, envelope: CoseSign1) -> Result<CoseSign1, String> {
+        if self.down.load(Ordering::Relaxed) {
+            return Err("The replica is down.".to_string());
+        }
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.server.execute(envelope).await
+    }
+}
+
+impl Replica {
+    fn calls(&self) -> usize {
+        self.calls.swap(0, Ordering::Relaxed)
+    }
+
+    fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::Relaxed);
+    }
+}
+
+/// Three replicas, and the pool balancing on them.
+async fn pool() -> (Vec<Replica>, Arc<ReadPool>) {
+    let mut replicas = vec![];
+    let mut urls = vec![];
+    for _ in 0..3 {
+        let replica = Replica {
+            server: ManyServer::simple(
+                "replica",
+                generate_random_ed25519_identity(),
+                (AnonymousVerifier, CoseKeyVerifier),
+                None,
+            ),
+            calls: Arc::new(AtomicUsize::new(0)),
+            down: Arc::new(AtomicBool::new(false)),
+        };
+        let listener = Listener::bind(
+            ListenAddr::Tcp("127.0.0.1:0".to_string()),
+            replica.clone(),
+            None,
+        )
+        .unwrap();
+        urls.push(format!("http://{}", listener.local_addr().unwrap()));
+        tokio::spawn(listener.serve());
+        replicas.push(replica);
+    }
+
+    let pool = tokio::task::spawn_blocking(move || {
+        let backends = urls
+            .iter()
+            .map(|url| Backend::new(url.as_str(), Arc::new(SystemResolver)).unwrap())
+            .collect();
+        Arc::new(ReadPool::new(backends))
+    })
+    .await
+    .unwrap();
+    (replicas, pool)
+}
+
+fn envelope(method: &str) -> CoseSign1 {
+    let message = RequestMessageBuilder::default()
+        .from(Address::anonymous())
+        .method(method.to_string())
+        .build()
+        .unwrap();
+    encode_cose_sign1_from_request(message, &AnonymousIdentity).unwrap()
+}
+
+fn calls(replicas: &[Replica]) -> Vec<usize> {
+    replicas.iter().map(Replica::calls).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn round_robin() {
+    let (replicas, pool) = pool().await;
+    for _ in 0..9 {
+        assert!(pool.send_envelope(envelope("status")).await.is_ok());
+    }
+    assert_eq!(calls(&replicas), vec![3, 3, 3]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failover_and_probe() {
+    let (replicas, pool) = pool().await;
+
+    // The failing query is answered by the next replica.
+    replicas[1].set_down(true);
+    for _ in 0..6 {
+        assert!(pool.send_envelope(envelope("status")).await.is_ok());
+    }
+    assert_eq!(pool.healthy(), vec![true, false, true]);
+    let counts = calls(&replicas);
+    assert_eq!(counts[1], 0);
+    assert_eq!(counts.iter().sum::<usize>(), 6);
+
+    // Out of rotation until a health check passes.
+    replicas[1].set_down(false);
+    for _ in 0..6 {
+        assert!(pool.send_envelope(envelope("status")).await.is_ok());
+    }
+    assert_eq!(calls(&replicas)[1], 0);
+
+    pool.probe().await;
+    assert_eq!(pool.healthy(), vec![true, true, true]);
+    calls(&replicas);
+    for _ in 0..6 {
+        assert!(pool.send_envelope(envelope("status")).await.is_ok());
+    }
+    assert_eq!(calls(&replicas), vec![2, 2, 2]);
+
+    // A replica failing its health check is taken out of rotation.
+    replicas[0].set_down(true);
+    pool.probe().await;
+    assert_eq!(pool.healthy(), vec![false, true, true]);
+
+    // No replica answers.
+    replicas[1].set_down(true);
+    replicas[2].set_down(true);
+    assert!(pool.send_envelope(envelope("status")).await.is_err());
+    assert_eq!(pool.healthy(), vec![false, false, false]);
+}
+
+/// A bridge whose Tendermint node accepts every broadcast, with a command
+/// and a query.
+fn bridge(pool: Arc<ReadPool>) -> AbciModuleMany<MockClient<MockRequestMethodMatcher>> {
+    let matcher = MockRequestMethodMatcher::default().map(
+        Method::BroadcastTxSync,
+        Ok(format!(
+            r#"{{"jsonrpc":"2.0","id":"","result":{{"code":0,"data":"","log":"","hash":"{}"}}}}"#,
+            hex::encode_upper([1; 32])
+        )),
+    );
+    let (client, driver) = MockClient::new(matcher);
+    tokio::spawn(driver.run());
+
+    let status = base::StatusBuilder::default()
+        .name("many-ledger".to_string())
+        .version(1)
+        .attributes(vec![])
+        .build()
+        .unwrap();
+    let endpoints = BTreeMap::from([
+        ("ledger.send".to_string(), EndpointInfo { is_command: true }),
+        (
+            "ledger.info".to_string(),
+            EndpointInfo { is_command: false },
+        ),
+    ]);
+    let status = Arc::new(SharedStatus::new(
+        BackendInfo::new(status, endpoints).unwrap(),
+    ));
+    AbciModuleMany::new(client, status, generate_random_ed25519_identity(), None)
+        .with_read_pool(pool)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_not_balanced() {
+    let (replicas, pool) = pool().await;
+    let bridge = bridge(pool);
+    let sender = generate_random_ed25519_identity();
+    let envelope = |method: &str| {
+        let message = RequestMessageBuilder::default()
+            .from(sender.address())
+            .method(method.to_string())
+            .build()
+            .unwrap();
+        encode_cose_sign1_from_request(message, &sender).unwrap()
+    };
+
+    for _ in 0..3 {
+        bridge.execute(envelope("ledger.info")).await.unwrap();
+    }
+    assert_eq!(calls(&replicas), vec![1, 1, 1]);
+
+    let response = bridge.execute(envelope("ledger.send")).await.unwrap();
+    let response = decode_response_from_cose_sign1(&response, None, &CoseKeyVerifier).unwrap();
+    assert!(response.data.is_ok());
+    assert_eq!(calls(&replicas), vec![0, 0, 0]);
+}
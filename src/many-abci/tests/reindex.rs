@@ -0,0 +1,211 @@
+use coset::CborSerializable;
+use many_abci::reindex::{ReindexCacheArgs, ReindexCacheModuleBackend, Reindexer};
+use many_abci::replay::{AvailableBlocks, BlockSource, HeightRange, RecordedBlock, RecordedTx};
+use many_abci::request_index::RequestIndex;
+use many_abci::tx_location::{tx_hash, TxLocation, TxLocations};
+use many_identity::{Address, Identity};
+use many_identity_dsa::ed25519::generate_random_ed25519_identity;
+use many_protocol::{encode_cose_sign1_from_request, RequestMessageBuilder};
+use many_types::{CborRange, Timestamp};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+const START: u64 = 1_700_000_000;
+
+/// A chain of blocks of which Tendermint pruned the ones before `earliest`.
+struct Pruned {
+    earliest: u64,
+    blocks: BTreeMap<u64, RecordedBlock>,
+}
+
+impl Pruned {
+    fn new(earliest: u64, blocks: Vec<Vec<Vec<u8>>>) -> Self {
+        Self {
+            earliest,
+            blocks: blocks
+                .into_iter()
+                .enumerate()
+                .map(|(i, txs)| {
+                    let height = i as u64 + 1;
+                    let block = RecordedBlock {
+                        height,
+                        time: Some(START + height * 10),
+                        txs: txs
+                            .into_iter()
+                            .map(|tx| RecordedTx {
+                                tx,
+                                ..Default::default()
+                            })
+                            .collect(),
+                    };
+                    (height, block)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl BlockSource for Pruned {
+    fn block(&self, height: u64) -> Result<RecordedBlock, String> {
+        self.blocks
+            .get(&height)
+            .filter(|_| height >= self.earliest)
+            .cloned()
+            .ok_or_else(|| format!("No block {}", height))
+    }
+}
+
+impl AvailableBlocks for Pruned {
+    fn available_blocks(&self) -> Result<HeightRange, String> {
+        Ok(HeightRange {
+            from: self.earliest,
+            to: *self.blocks.keys().last().unwrap(),
+        })
+    }
+}
+
+fn request(identity: &impl Identity, nonce: u8) -> Vec<u8> {
+    let mut message = RequestMessageBuilder::default()
+        .from(identity.address())
+        .method("ledger.send".to_string())
+        .build()
+        .unwrap();
+    message.nonce = Some(vec![nonce]);
+    encode_cose_sign1_from_request(message, identity)
+        .unwrap()
+        .to_vec()
+        .unwrap()
+}
+
+/// Five blocks of one request each, with a transaction which is not a MANY
+/// request in the fourth one. The first two blocks were pruned.
+fn chain(identity: &impl Identity) -> Pruned {
+    Pruned::new(
+        3,
+        vec![
+            vec![request(identity, 1)],
+            vec![request(identity, 2)],
+            vec![request(identity, 3)],
+            vec![b"garbage".to_vec(), request(identity, 4)],
+            vec![request(identity, 5)],
+        ],
+    )
+}
+
+fn nonces(index: &RequestIndex, sender: &Address) -> Vec<u8> {
+    index
+        .find(sender, &CborRange::default(), &CborRange::default())
+        .iter()
+        .map(|r| r.nonce.as_ref().unwrap()[0])
+        .collect()
+}
+
+#[test]
+fn skips_pruned_blocks() {
+    let id = generate_random_ed25519_identity();
+    let index = Arc::new(RequestIndex::in_memory(100, None));
+    let locations = Arc::new(TxLocations::new(100));
+    let source = chain(&id);
+    let pruned = source.blocks[&1].txs[0].tx.clone();
+    let request = source.blocks[&4].txs[1].tx.clone();
+    let mut reindexer = Reindexer::new(source)
+        .with_request_index(index.clone())
+        .with_tx_locations(locations.clone());
+
+    assert_eq!(reindexer.step(2), Ok(5));
+    assert_eq!(reindexer.progress().next_height, 5);
+    assert_eq!(reindexer.progress().pruned, 2);
+    assert_eq!(reindexer.progress().txs, 3);
+    assert_eq!(nonces(&index, &id.address()), vec![3, 4]);
+
+    reindexer.run(2).unwrap();
+    assert_eq!(reindexer.progress().next_height, 6);
+    assert_eq!(reindexer.progress().txs, 4);
+    assert_eq!(nonces(&index, &id.address()), vec![3, 4, 5]);
+
+    let found = index.find(&id.address(), &CborRange::default(), &CborRange::default());
+    assert_eq!(found[1].hash.to_vec(), tx_hash(&request));
+    assert_eq!(found[1].broadcast_at, Timestamp::new(START + 40).unwrap());
+    assert_eq!(
+        locations.get(&tx_hash(&request)),
+        Some(TxLocation {
+            height: 4,
+            index: 1,
+            time: Some(Timestamp::new(START + 40).unwrap()),
+        })
+    );
+    assert!(locations.get(&tx_hash(b"garbage")).is_some());
+    assert!(locations.get(&tx_hash(&pruned)).is_none());
+}
+
+#[test]
+fn restart_no_duplicates() {
+    let id = generate_random_ed25519_identity();
+    let index = Arc::new(RequestIndex::in_memory(100, None));
+    let mut reindexer = Reindexer::new(chain(&id)).with_request_index(index.clone());
+    reindexer.run(10).unwrap();
+    assert_eq!(index.len(), 3);
+
+    reindexer.restart(1);
+    reindexer.run(10).unwrap();
+    assert_eq!(index.len(), 3);
+    assert_eq!(nonces(&index, &id.address()), vec![3, 4, 5]);
+}
+
+#[test]
+fn resume_from_progress_file() {
+    let id = generate_random_ed25519_identity();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("requests.reindex");
+
+    let mut reindexer = Reindexer::new(chain(&id))
+        .with_progress_file(&path)
+        .unwrap();
+    assert!(!reindexer.started());
+    reindexer.step(1).unwrap();
+    assert_eq!(reindexer.progress().next_height, 4);
+
+    let index = Arc::new(RequestIndex::in_memory(100, None));
+    let mut reindexer = Reindexer::new(chain(&id))
+        .with_request_index(index.clone())
+        .with_progress_file(&path)
+        .unwrap();
+    assert!(reindexer.started());
+    assert_eq!(reindexer.progress().pruned, 2);
+    reindexer.run(10).unwrap();
+    assert_eq!(nonces(&index, &id.address()), vec![4, 5]);
+}
+
+#[test]
+fn admins_only() {
+    let id = generate_random_ed25519_identity();
+    let admin = generate_random_ed25519_identity().address();
+    let index = Arc::new(RequestIndex::in_memory(100, None));
+    let mut reindexer = Reindexer::new(chain(&id))
+        .with_request_index(index.clone())
+        .with_admins(BTreeSet::from([admin]));
+
+    assert!(reindexer
+        .reindex_cache(&id.address(), ReindexCacheArgs::default())
+        .is_err());
+    assert!(index.is_empty());
+
+    let returns = reindexer
+        .reindex_cache(
+            &admin,
+            ReindexCacheArgs {
+                from_height: None,
+                max_blocks: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(returns.latest_height, 5);
+    assert!(!returns.done);
+
+    let returns = reindexer
+        .reindex_cache(&admin, ReindexCacheArgs::default())
+        .unwrap();
+    assert!(returns.done);
+    assert_eq!(returns.progress.next_height, 6);
+    assert_eq!(index.len(), 3);
+}
@@ -0,0 +1,177 @@
+use many_abci::replay::{replay, BlockSource, HeightRange, RecordedBlock, RecordedTx};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tendermint_abci::Application;
+use tendermint_proto::abci::{
+    RequestBeginBlock, RequestDeliverTx, RequestEndBlock, ResponseBeginBlock, ResponseCommit,
+    ResponseDeliverTx, ResponseEndBlock,
+};
+
+/// A chain of blocks whose recorded result of each transaction is the
+/// transaction itself.
+struct Scripted(BTreeMap<u64, RecordedBlock>);
+
+impl Scripted {
+    fn new(blocks: &[&[&[u8]]]) -> Self {
+        Self(
+            blocks
+                .iter()
+                .enumerate()
+                .map(|(i, txs)| {
+                    let height = i as u64 + 1;
+                    let block = RecordedBlock {
+                        height,
+                        time: Some(height * 10),
+                        txs: txs
+                            .iter()
+                            .map(|tx| RecordedTx {
+                                tx: tx.to_vec(),
+                                code: 0,
+                                data: tx.to_vec(),
+                                log: String::new(),
+                            })
+                            .collect(),
+                    };
+                    (height, block)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl BlockSource for Scripted {
+    fn block(&self, height: u64) -> Result<RecordedBlock, String> {
+        self.0
+            .get(&height)
+            .cloned()
+            .ok_or_else(|| format!("No block {}", height))
+    }
+}
+
+/// An application echoing transactions, except the ones it diverges on.
+#[derive(Clone, Default)]
+struct Mock {
+    diverge_on: Vec<Vec<u8>>,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl Mock {
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl Application for Mock {
+    fn begin_block(&self, request: RequestBeginBlock) -> ResponseBeginBlock {
+        let time = request.header.unwrap().time.unwrap().seconds;
+        self.calls.lock().unwrap().push(format!("begin {}", time));
+        Default::default()
+    }
+
+    fn deliver_tx(&self, request: RequestDeliverTx) -> ResponseDeliverTx {
+        let tx = request.tx.to_vec();
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("deliver {}", String::from_utf8_lossy(&tx)));
+        if self.diverge_on.contains(&tx) {
+            ResponseDeliverTx {
+                code: 1,
+                log: "Diverged".to_string(),
+                ..Default::default()
+            }
+        } else {
+            ResponseDeliverTx {
+                data: tx.into(),
+                ..Default::default()
+            }
+        }
+    }
+
+    fn end_block(&self, _request: RequestEndBlock) -> ResponseEndBlock {
+        self.calls.lock().unwrap().push("end".to_string());
+        Default::default()
+    }
+
+    fn commit(&self) -> ResponseCommit {
+        self.calls.lock().unwrap().push("commit".to_string());
+        Default::default()
+    }
+}
+
+fn chain() -> Scripted {
+    Scripted::new(&[&[b"a", b"b"], &[], &[b"c", b"d", b"e"], &[b"f"]])
+}
+
+#[test]
+fn height_range() {
+    assert_eq!(
+        "2..5".parse::<HeightRange>(),
+        Ok(HeightRange { from: 2, to: 5 })
+    );
+    assert_eq!(
+        "3..3".parse::<HeightRange>(),
+        Ok(HeightRange { from: 3, to: 3 })
+    );
+    assert!("5..2".parse::<HeightRange>().is_err());
+    assert!("0..2".parse::<HeightRange>().is_err());
+    assert!("5".parse::<HeightRange>().is_err());
+    assert!("a..b".parse::<HeightRange>().is_err());
+}
+
+#[test]
+fn no_divergence() {
+    let app = Mock::default();
+    let report = replay(&chain(), &app, "2..4".parse().unwrap()).unwrap();
+    assert_eq!(report.blocks, 3);
+    assert_eq!(report.txs, 4);
+    assert_eq!(report.divergence, None);
+    assert_eq!(
+        app.calls(),
+        vec![
+            "begin 20",
+            "end",
+            "commit",
+            "begin 30",
+            "deliver c",
+            "deliver d",
+            "deliver e",
+            "end",
+            "commit",
+            "begin 40",
+            "deliver f",
+            "end",
+            "commit",
+        ]
+    );
+}
+
+#[test]
+fn first_divergence() {
+    let app = Mock {
+        diverge_on: vec![b"d".to_vec(), b"e".to_vec(), b"f".to_vec()],
+        ..Default::default()
+    };
+    let report = replay(&chain(), &app, "1..4".parse().unwrap()).unwrap();
+
+    // The block of the divergence is completed, and the replay stops there.
+    assert_eq!(report.blocks, 3);
+    assert_eq!(report.txs, 5);
+    assert!(!app.calls().contains(&"begin 40".to_string()));
+
+    let divergence = report.divergence.unwrap();
+    assert_eq!(divergence.height, 3);
+    assert_eq!(divergence.index, 1);
+    assert_eq!(divergence.expected.tx, b"d");
+    assert_eq!(divergence.actual.code, 1);
+
+    let dump = divergence.to_string();
+    assert!(dump.contains("Divergence at height 3, transaction 1."));
+    assert!(dump.contains(&format!("envelope: {}", hex::encode(b"d"))));
+    assert!(dump.contains("\"Diverged\""));
+}
+
+#[test]
+fn missing_block() {
+    assert!(replay(&chain(), &Mock::default(), "3..5".parse().unwrap()).is_err());
+}
@@ -0,0 +1,184 @@
+use many_abci::request_index::{RequestIndex, RequestRecord};
+use many_identity::Address;
+use many_types::{CborRange, Timestamp};
+use minicbor::bytes::ByteVec;
+use std::io::Write;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::Duration;
+
+fn sender(i: u32) -> Address {
+    Address::from_str("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp")
+        .unwrap()
+        .with_subresource_id(i)
+        .unwrap()
+}
+
+fn record(sender: Address, nonce: u8, secs: u64) -> RequestRecord {
+    RequestRecord {
+        sender,
+        nonce: Some(vec![nonce].into()),
+        timestamp: Timestamp::new(secs).unwrap(),
+        hash: vec![nonce; 32].into(),
+        broadcast_at: Timestamp::new(secs).unwrap(),
+    }
+}
+
+fn all(index: &RequestIndex, sender: Address) -> Vec<u8> {
+    index
+        .find(&sender, &CborRange::default(), &CborRange::default())
+        .iter()
+        .map(|r| r.nonce.as_ref().unwrap()[0])
+        .collect()
+}
+
+fn now() -> u64 {
+    Timestamp::now()
+        .as_system_time()
+        .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn lookups() {
+    let index = RequestIndex::in_memory(100, None);
+    for i in 0..5u8 {
+        index.record(record(sender(1), i, 100 + i as u64)).unwrap();
+        index.record(record(sender(2), i, 100 + i as u64)).unwrap();
+    }
+    index
+        .record(RequestRecord {
+            nonce: None,
+            ..record(sender(1), 9, 200)
+        })
+        .unwrap();
+
+    assert_eq!(index.len(), 11);
+    assert_eq!(
+        index
+            .find(&sender(1), &CborRange::default(), &CborRange::default())
+            .len(),
+        6
+    );
+    assert!(all(&index, sender(3)).is_empty());
+
+    let nonces = CborRange {
+        start: Bound::Included(ByteVec::from(vec![1])),
+        end: Bound::Excluded(ByteVec::from(vec![3])),
+    };
+    let found = index.find(&sender(1), &nonces, &CborRange::default());
+    assert_eq!(
+        found,
+        vec![record(sender(1), 1, 101), record(sender(1), 2, 102)]
+    );
+
+    let times = CborRange {
+        start: Bound::Included(Timestamp::new(103).unwrap()),
+        end: Bound::Unbounded,
+    };
+    let found = index.find(&sender(2), &CborRange::default(), &times);
+    assert_eq!(
+        found,
+        vec![record(sender(2), 3, 103), record(sender(2), 4, 104)]
+    );
+}
+
+#[test]
+fn eviction_by_capacity() {
+    let index = RequestIndex::in_memory(3, None);
+    for i in 0..5u8 {
+        index.record(record(sender(1), i, 100)).unwrap();
+    }
+    assert_eq!(all(&index, sender(1)), vec![2, 3, 4]);
+}
+
+#[test]
+fn eviction_by_age() {
+    let index = RequestIndex::in_memory(100, Some(Duration::from_secs(60)));
+    index.record(record(sender(1), 0, 1_000)).unwrap();
+    index.record(record(sender(1), 1, 1_030)).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![0, 1]);
+
+    index.record(record(sender(1), 2, 1_070)).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![1, 2]);
+}
+
+#[test]
+fn persisted_across_restarts() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("requests.cbor");
+    let t = now();
+
+    {
+        let index = RequestIndex::open(&path, 100, None).unwrap();
+        assert!(index.is_empty());
+        for i in 0..3u8 {
+            index.record(record(sender(1), i, t)).unwrap();
+        }
+    }
+
+    let index = RequestIndex::open(&path, 100, None).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![0, 1, 2]);
+    index.record(record(sender(1), 3, t)).unwrap();
+
+    // Capacity and age limits apply to the records read back.
+    drop(index);
+    let index = RequestIndex::open(&path, 2, None).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![2, 3]);
+
+    drop(index);
+    let index = RequestIndex::open(&path, 100, Some(Duration::from_secs(60))).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![2, 3]);
+    index.record(record(sender(1), 4, t - 3_600)).unwrap();
+    drop(index);
+    let index = RequestIndex::open(&path, 100, Some(Duration::from_secs(60))).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![2, 3]);
+}
+
+#[test]
+fn compacted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("requests.cbor");
+    let t = now();
+
+    let index = RequestIndex::open(&path, 2, None).unwrap();
+    for i in 0..10u8 {
+        index.record(record(sender(1), i, t)).unwrap();
+    }
+    let size = std::fs::metadata(&path).unwrap().len();
+    let record_size = minicbor::to_vec(record(sender(1), 0, t)).unwrap().len() as u64;
+    assert!(size <= 5 * record_size, "{} bytes", size);
+
+    drop(index);
+    let index = RequestIndex::open(&path, 2, None).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![8, 9]);
+}
+
+#[test]
+fn truncated_record_is_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("requests.cbor");
+    let t = now();
+
+    {
+        let index = RequestIndex::open(&path, 100, None).unwrap();
+        index.record(record(sender(1), 0, t)).unwrap();
+    }
+    let partial = minicbor::to_vec(record(sender(1), 1, t)).unwrap();
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&partial[..partial.len() / 2])
+        .unwrap();
+
+    let index = RequestIndex::open(&path, 100, None).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![0]);
+    index.record(record(sender(1), 2, t)).unwrap();
+
+    drop(index);
+    let index = RequestIndex::open(&path, 100, None).unwrap();
+    assert_eq!(all(&index, sender(1)), vec![0, 2]);
+}
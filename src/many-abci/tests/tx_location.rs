@@ -0,0 +1,117 @@
+use many_abci::tx_location::{
+    tx_hash, with_location, TxLocation, TxLocationArgs, TxLocationModule, TxLocationModuleBackend,
+    TxLocations, TX_LOCATION,
+};
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::ManyModule;
+use many_protocol::{RequestMessageBuilder, ResponseMessage};
+use many_types::Timestamp;
+use std::sync::{Arc, Mutex};
+
+/// Deliver a scripted chain of blocks, as the ABCI application does.
+fn deliver(locations: &TxLocations, blocks: &[&[&[u8]]]) {
+    for (i, txs) in blocks.iter().enumerate() {
+        let height = i as u64 + 1;
+        locations.begin_block(height, Timestamp::new(height * 10).ok());
+        for tx in txs.iter() {
+            locations.deliver_tx(tx);
+        }
+    }
+}
+
+fn location(height: u64, index: u64) -> TxLocation {
+    TxLocation {
+        height,
+        index,
+        time: Timestamp::new(height * 10).ok(),
+    }
+}
+
+#[test]
+fn recorded_per_block() {
+    let locations = TxLocations::default();
+    deliver(&locations, &[&[b"a", b"b"], &[], &[b"c"]]);
+
+    assert_eq!(locations.get(&tx_hash(b"a")), Some(location(1, 0)));
+    assert_eq!(locations.get(&tx_hash(b"b")), Some(location(1, 1)));
+    assert_eq!(locations.get(&tx_hash(b"c")), Some(location(3, 0)));
+    assert_eq!(locations.get(&tx_hash(b"d")), None);
+}
+
+#[test]
+fn bounded() {
+    let locations = TxLocations::new(2);
+    deliver(&locations, &[&[b"a", b"b"], &[b"c"]]);
+
+    assert_eq!(locations.get(&tx_hash(b"a")), None);
+    assert_eq!(locations.get(&tx_hash(b"b")), Some(location(1, 1)));
+    assert_eq!(locations.get(&tx_hash(b"c")), Some(location(2, 0)));
+}
+
+/// The location recorded when the block was delivered reaches the client in
+/// the attributes of the response.
+#[test]
+fn attribute_roundtrip() {
+    let locations = TxLocations::default();
+    deliver(&locations, &[&[b"a"], &[b"b", b"c"]]);
+    let recorded = locations.get(&tx_hash(b"c")).unwrap();
+
+    let response = with_location(
+        ResponseMessage {
+            data: Ok(vec![1, 2, 3]),
+            ..Default::default()
+        },
+        &recorded,
+    );
+    let response = ResponseMessage::from_bytes(&response.to_bytes().unwrap()).unwrap();
+
+    let attribute = response.attributes.get_attribute(TX_LOCATION.id).unwrap();
+    assert_eq!(TxLocation::from_attribute(attribute), Some(location(2, 1)));
+    assert_eq!(response.data, Ok(vec![1, 2, 3]));
+}
+
+#[test]
+fn attribute_without_time() {
+    let location = TxLocation {
+        height: 5,
+        index: 2,
+        time: None,
+    };
+    assert_eq!(
+        TxLocation::from_attribute(&location.as_attribute()),
+        Some(location)
+    );
+}
+
+struct Recorded(Arc<TxLocations>);
+
+impl TxLocationModuleBackend for Recorded {
+    fn tx_location(&self, args: TxLocationArgs) -> Result<TxLocation, ManyError> {
+        self.0
+            .get(args.token.as_slice())
+            .ok_or_else(|| ManyError::unknown("Unknown transaction."))
+    }
+}
+
+#[tokio::test]
+async fn tx_location_endpoint() {
+    let locations = Arc::new(TxLocations::default());
+    deliver(&locations, &[&[b"a"], &[b"b"]]);
+    let module = TxLocationModule::new(Arc::new(Mutex::new(Recorded(locations))));
+
+    let request = RequestMessageBuilder::default()
+        .from(Address::anonymous())
+        .method("blockchain.txLocation".to_string())
+        .data(
+            minicbor::to_vec(TxLocationArgs {
+                token: tx_hash(b"b").into(),
+            })
+            .unwrap(),
+        )
+        .build()
+        .unwrap();
+    let response = module.execute(request).await.unwrap();
+    let returned: TxLocation = minicbor::decode(&response.data.unwrap()).unwrap();
+    assert_eq!(returned, location(2, 0));
+}
@@ -0,0 +1,224 @@
+use many_abci::validator::{
+    ParamInfo, ParamValue, PendingValidatorUpdate, UpdateValidatorArgs, ValidatorParams,
+    ValidatorUpdates, VALIDATOR_UPDATE_EVENT,
+};
+use many_error::ManyError;
+use many_identity::Address;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use tendermint_proto::abci::ValidatorUpdate;
+use tendermint_proto::crypto::public_key::Sum;
+
+fn admin() -> Address {
+    Address::from_str("maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp").unwrap()
+}
+
+fn key(i: u8) -> Vec<u8> {
+    vec![i; 32]
+}
+
+fn address(key: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    sha2::Sha256::digest(key)[..20].to_vec()
+}
+
+/// Four validators with 10 voting power each.
+fn validators() -> Vec<(Vec<u8>, u64)> {
+    (1..=4).map(|i| (address(&key(i)), 10)).collect()
+}
+
+fn update(public_key: Vec<u8>, power: u64, replaces: Option<Vec<u8>>) -> UpdateValidatorArgs {
+    UpdateValidatorArgs {
+        public_key: public_key.into(),
+        power,
+        replaces: replaces.map(Into::into),
+    }
+}
+
+fn params() -> ValidatorParams {
+    ValidatorParams {
+        admins: BTreeSet::from([admin()]),
+        quorum_margin: 67,
+    }
+}
+
+fn updates() -> ValidatorUpdates {
+    ValidatorUpdates::default()
+}
+
+/// The keys and powers of the updates returned in EndBlock.
+fn returned(updates: &[ValidatorUpdate]) -> Vec<(Vec<u8>, i64)> {
+    updates
+        .iter()
+        .map(|u| match u.pub_key.as_ref().and_then(|k| k.sum.clone()) {
+            Some(Sum::Ed25519(key)) => (key.to_vec(), u.power),
+            _ => panic!("Unexpected key type"),
+        })
+        .collect()
+}
+
+#[test]
+fn power_change() {
+    let updates = updates();
+
+    updates.begin_block(10, validators());
+    updates
+        .request(&params(), &admin(), update(key(1), 15, None))
+        .unwrap();
+    assert_eq!(
+        updates.pending().updates,
+        vec![PendingValidatorUpdate {
+            public_key: key(1).into(),
+            power: 15,
+            effective_height: None,
+        }]
+    );
+
+    let (returned_updates, events) = updates.end_block();
+    assert_eq!(returned(&returned_updates), vec![(key(1), 15)]);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].r#type, VALIDATOR_UPDATE_EVENT);
+
+    // The update is pending until Tendermint uses it.
+    updates.begin_block(11, validators());
+    assert_eq!(updates.pending().updates[0].effective_height, Some(12));
+    assert!(updates.end_block().0.is_empty());
+    updates.begin_block(12, validators());
+    assert!(updates.pending().updates.is_empty());
+}
+
+#[test]
+fn key_swap() {
+    let updates = updates();
+
+    updates.begin_block(10, validators());
+    updates
+        .request(&params(), &admin(), update(key(5), 10, Some(key(1))))
+        .unwrap();
+    let (returned_updates, _) = updates.end_block();
+    assert_eq!(returned(&returned_updates), vec![(key(1), 0), (key(5), 10)]);
+
+    // A key which is not a validator cannot be replaced.
+    updates.begin_block(11, validators());
+    assert!(updates
+        .request(&params(), &admin(), update(key(6), 10, Some(key(9))))
+        .is_err());
+    assert!(updates.end_block().0.is_empty());
+}
+
+#[test]
+fn quorum_margin() {
+    let updates = updates();
+
+    // Removing a validator of four keeps 75% of the voting power.
+    updates.begin_block(10, validators());
+    updates
+        .request(&params(), &admin(), update(key(1), 0, None))
+        .unwrap();
+
+    // Removing a second one in the same block would keep 50%.
+    assert!(updates
+        .request(&params(), &admin(), update(key(2), 0, None))
+        .is_err());
+    let (returned_updates, _) = updates.end_block();
+    assert_eq!(returned(&returned_updates), vec![(key(1), 0)]);
+
+    // Adding power first allows it.
+    updates.begin_block(11, validators());
+    updates
+        .request(&params(), &admin(), update(key(5), 20, None))
+        .unwrap();
+    updates
+        .request(&params(), &admin(), update(key(1), 0, None))
+        .unwrap();
+    updates
+        .request(&params(), &admin(), update(key(2), 0, None))
+        .unwrap();
+    let (returned_updates, _) = updates.end_block();
+    assert_eq!(
+        returned(&returned_updates),
+        vec![(key(1), 0), (key(2), 0), (key(5), 20)]
+    );
+}
+
+#[test]
+fn admins_only() {
+    let updates = updates();
+
+    updates.begin_block(10, validators());
+    assert_eq!(
+        updates.request(&params(), &Address::anonymous(), update(key(1), 5, None)),
+        Err(ManyError::invalid_from_identity())
+    );
+    assert!(updates
+        .request(&params(), &admin(), update(vec![1; 31], 5, None))
+        .is_err());
+    assert_eq!(updates.end_block(), (vec![], vec![]));
+}
+
+#[test]
+fn no_admins() {
+    let updates = updates();
+
+    updates.begin_block(10, validators());
+    assert_eq!(
+        updates.request(
+            &ValidatorParams::default(),
+            &admin(),
+            update(key(1), 5, None)
+        ),
+        Err(ManyError::invalid_from_identity())
+    );
+}
+
+#[test]
+fn params_from_governance() {
+    let mut e = minicbor::Encoder::new(vec![]);
+    e.map(1).unwrap().u32(0).unwrap().map(3).unwrap();
+    e.str("validators.admins").unwrap();
+    e.encode(ParamInfo {
+        value: ParamValue::AddressSet(BTreeSet::from([admin()])),
+    })
+    .unwrap();
+    // Other parameters are skipped.
+    e.str("args.strict").unwrap();
+    e.map(1).unwrap().u32(1).unwrap();
+    e.array(2).unwrap().u32(0).unwrap();
+    e.array(1).unwrap().bool(true).unwrap();
+    e.str("validators.quorumMargin").unwrap();
+    e.encode(ParamInfo {
+        value: ParamValue::Integer(50),
+    })
+    .unwrap();
+
+    let params = ValidatorParams::decode(e.writer()).unwrap();
+    assert_eq!(
+        params,
+        ValidatorParams {
+            admins: BTreeSet::from([admin()]),
+            quorum_margin: 50,
+        }
+    );
+
+    // Half of the voting power can be removed in one block.
+    let updates = updates();
+    updates.begin_block(10, validators());
+    updates
+        .request(&params, &admin(), update(key(1), 0, None))
+        .unwrap();
+    updates
+        .request(&params, &admin(), update(key(2), 0, None))
+        .unwrap();
+    assert_eq!(
+        returned(&updates.end_block().0),
+        vec![(key(1), 0), (key(2), 0)]
+    );
+
+    // Without the parameters, nobody can update the validators.
+    let mut e = minicbor::Encoder::new(vec![]);
+    e.map(1).unwrap().u32(0).unwrap().map(0).unwrap();
+    assert_eq!(
+        ValidatorParams::decode(e.writer()).unwrap(),
+        ValidatorParams::default()
+    );
+}
@@ -10,5 +10,60 @@ define_attribute_many_error!(
         5: pub fn subres_alt_unsupported() => "Subresource alternative owner unsupported.",
         6: pub fn key_not_found() => "The key was not found.",
         7: pub fn cannot_disable_empty_key() => "Unable to disable an empty key.",
+        8: pub fn verify_unauthorized() => "Only the kvstore identity can verify the store.",
+        9: pub fn read_denied() => "You do not have the authorization to read this key.",
+        10: pub fn key_immutable() => "The key is immutable.",
+        11: pub fn not_a_counter() => "The key is not a counter.",
+        12: pub fn counter_underflow() => "The counter cannot go below zero.",
+        13: pub fn key_exists() => "The key already exists.",
+        14: pub fn key_is_counter() => "The key is a counter, it can only be changed by adding to it.",
+        15: pub fn invalid_content_type(content_type) => "Invalid content type '{content_type}'.",
+        16: pub fn lock_held(expires) => "The key is locked until {expires}, in seconds since the epoch.",
+        17: pub fn stale_lock_token() => "The lock token is not the one of the current lock of the key.",
+        18: pub fn invalid_lease(max) => "The lease must be between 1 and {max} seconds.",
+        19: pub fn lock_token_required() => "The key can only be put with the token of its lock.",
+        20: pub fn quota_exceeded(usage, quota)
+            => "The owner would use {usage} bytes, over its quota of {quota} bytes.",
+        21: pub fn usage_denied() => "You do not have the authorization to read the usage of this owner.",
+        22: pub fn invalid_capability(reason) => "Invalid capability: {reason}.",
+        23: pub fn capability_expired(expiry)
+            => "The capability expired at {expiry}, in seconds since the epoch.",
+        24: pub fn capability_exhausted(max_uses) => "The capability was already used {max_uses} times.",
+        25: pub fn capability_revoked() => "The capability was revoked by its issuer.",
+        26: pub fn capability_key_denied(prefix)
+            => "The capability only allows keys starting with '{prefix}' (hexadecimal).",
+        27: pub fn capability_value_too_large(max) => "The capability only allows values of up to {max} bytes.",
+        28: pub fn capability_issuer_denied() => "The issuer of the capability is not allowed to put values.",
+        29: pub fn capability_revoke_denied() => "Only the issuer of a capability can revoke it.",
+        30: pub fn bulk_disable_pending(id)
+            => "The key is being disabled by the bulk disable {id}, it cannot change until it is done.",
+        31: pub fn prefix_move_pending(id)
+            => "The key is being moved by the prefix move {id}, it cannot change until it is done.",
+        32: pub fn overlapping_prefixes() => "The source and destination prefixes overlap.",
+        33: pub fn invalid_move_confirmation()
+            => "The confirmation does not match the move, the keys to move changed since the dry run.",
+        34: pub fn not_a_json_key() => "The key is not under a JSON prefix.",
+        35: pub fn malformed_json(reason) => "The value is not a JSON document: {reason}.",
+        36: pub fn invalid_patch(reason) => "Invalid JSON patch: {reason}.",
+        37: pub fn version_conflict(expected, actual)
+            => "Expected version {expected} of the document, but it is at version {actual}.",
+        38: pub fn json_too_large(size, max, prefix)
+            => "The document would be {size} bytes, over the limit of {max} bytes of the prefix '{prefix}'.",
+        39: pub fn derived_not_enabled()
+            => "The derived fields of keys are not available before the KvStoreDerived migration.",
+        40: pub fn usage_not_enabled()
+            => "The usage of owners is not available before the KvStoreUsage migration.",
+        41: pub fn json_prefixes_denied()
+            => "Only the identity of the store can set the JSON prefixes.",
+        42: pub fn quota_denied() => "Only the identity of the store can set the quota.",
+        43: pub fn reserved_prefixes_denied()
+            => "Only the identity of the store can reserve prefixes.",
+        44: pub fn invalid_reserved_prefixes(reason) => "Invalid reserved prefixes: {reason}.",
+        45: pub fn prefix_reserved(prefix) => "The prefix '{prefix}' is reserved to another owner.",
+        46: pub fn tenant_throttled(prefix, retry_after)
+            => "Too many writes under the prefix '{prefix}', retry in {retry_after} seconds.",
+        47: pub fn prefix_not_reserved(prefix) => "The prefix '{prefix}' is not reserved.",
+        48: pub fn tenant_stats_denied()
+            => "Only the owner of the prefix can read its statistics.",
     }
 );
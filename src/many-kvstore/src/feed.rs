@@ -0,0 +1,345 @@
+use many_modules::events::{self, EventId, EventInfo, EventLog, EventsModuleBackend};
+use many_types::{CborRange, SortOrder};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, warn};
+
+/// Number of records read and delivered at once. This is also the maximum
+/// number of events returned by a single `events.list` call.
+pub const BATCH_SIZE: u64 = 100;
+
+/// Default size after which the NDJSON file is rotated.
+pub const DEFAULT_FILE_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How long to wait for new events when the feed has caught up.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delay before retrying a failed delivery. It doubles on every failure,
+/// up to `MAX_RETRY_DELAY`.
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// Left-shift the height by this amount of bits
+const HEIGHT_EVENTID_SHIFT: u32 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Put,
+    Disable,
+}
+
+/// A single mutation of the store, as exported to the feed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    /// The ID of the event in the event log, in hexadecimal.
+    #[serde(with = "hex::serde")]
+    pub id: Vec<u8>,
+
+    /// The block height, as encoded in the event ID.
+    pub height: u64,
+
+    /// Seconds since the UNIX epoch.
+    pub time: u64,
+
+    #[serde(with = "hex::serde")]
+    pub key: Vec<u8>,
+
+    pub operation: Operation,
+
+    /// The owner of the key recorded in the event.
+    pub actor: Option<String>,
+
+    /// The SHA3-256 hash of the value put, in hexadecimal.
+    pub value_hash: Option<String>,
+}
+
+impl ChangeRecord {
+    /// Returns the record of an event, or `None` if the event does not
+    /// mutate the store.
+    pub fn from_event(log: &EventLog) -> Option<Self> {
+        let (key, operation, actor, value_hash) = match &log.content {
+            EventInfo::KvStorePut { key, value, owner } => (
+                key,
+                Operation::Put,
+                owner,
+                Some(hex::encode(Sha3_256::digest(value.as_slice()))),
+            ),
+            EventInfo::KvStoreDisable { key, owner, .. } => (key, Operation::Disable, owner, None),
+            _ => return None,
+        };
+
+        let id = log.id.as_ref().to_vec();
+        let height = (id
+            .iter()
+            .fold(0u128, |acc, b| acc.wrapping_shl(8) | *b as u128)
+            >> HEIGHT_EVENTID_SHIFT) as u64;
+        let time = log
+            .time
+            .as_system_time()
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+
+        Some(Self {
+            id,
+            height,
+            time,
+            key: key.as_slice().to_vec(),
+            operation,
+            actor: actor.as_ref().map(|a| a.to_string()),
+            value_hash,
+        })
+    }
+}
+
+fn ndjson(records: &[ChangeRecord]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut out, record).map_err(|e| e.to_string())?;
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+/// A destination of the change feed. Records are delivered in order; a
+/// batch that fails is delivered again, so sinks see every record at least
+/// once.
+pub trait Sink: Send {
+    fn deliver(&mut self, records: &[ChangeRecord]) -> Result<(), String>;
+}
+
+/// Appends records to an NDJSON file. When the file grows past its maximum
+/// size, it is renamed with the ID of its last record as suffix.
+pub struct FileSink {
+    path: PathBuf,
+    max_size: u64,
+}
+
+impl FileSink {
+    pub fn new(path: impl Into<PathBuf>, max_size: u64) -> Self {
+        Self {
+            path: path.into(),
+            max_size,
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn deliver(&mut self, records: &[ChangeRecord]) -> Result<(), String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| e.to_string())?;
+        file.write_all(&ndjson(records)?)
+            .map_err(|e| e.to_string())?;
+        file.sync_data().map_err(|e| e.to_string())?;
+
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        if let (true, Some(last)) = (size >= self.max_size, records.last()) {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(format!(".{}", hex::encode(&last.id)));
+            std::fs::rename(&self.path, rotated).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// POSTs batches of records to an HTTP endpoint, as an NDJSON body. Any
+/// response other than a success is a failed delivery.
+pub struct HttpSink {
+    url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl HttpSink {
+    pub fn new(url: impl Into<String>) -> Result<Self, String> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            url: url.into(),
+            client,
+        })
+    }
+}
+
+impl Sink for HttpSink {
+    fn deliver(&mut self, records: &[ChangeRecord]) -> Result<(), String> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/x-ndjson")
+            .body(ndjson(records)?)
+            .send()
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("{} returned {}", self.url, response.status()))
+        }
+    }
+}
+
+struct Output {
+    name: String,
+    sink: Box<dyn Sink>,
+    cursor_path: PathBuf,
+
+    /// The last event delivered.
+    cursor: Option<EventId>,
+
+    retry_delay: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl Output {
+    fn save_cursor(&self, cursor: &EventId) -> std::io::Result<()> {
+        let tmp = self.cursor_path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(hex::encode(cursor.as_ref()).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.cursor_path)
+    }
+}
+
+/// Exports every committed mutation of the store to sinks.
+///
+/// The event log is the queue of the feed: each sink has a cursor, persisted
+/// in the feed directory after every successful delivery, and the next batch
+/// is read from the log after it. A crash between a delivery and the save of
+/// the cursor delivers that batch again, but never skips events. A failing
+/// sink is retried with backoff without holding anything but its current
+/// batch, and never holds back the other sinks.
+///
+/// Only committed events are read, and the store is only locked for as long
+/// as it takes to read a batch. Errors are logged; they never reach the
+/// block processing.
+pub struct Feed<T: EventsModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    dir: PathBuf,
+    outputs: Vec<Output>,
+}
+
+impl<T: EventsModuleBackend + Send + 'static> Feed<T> {
+    /// Create a feed keeping its cursors in the given directory.
+    pub fn new(backend: Arc<Mutex<T>>, dir: impl AsRef<Path>) -> Result<Self, String> {
+        std::fs::create_dir_all(dir.as_ref()).map_err(|e| e.to_string())?;
+        Ok(Self {
+            backend,
+            dir: dir.as_ref().to_path_buf(),
+            outputs: vec![],
+        })
+    }
+
+    /// Add a sink, resuming from its saved cursor if any. The name of a sink
+    /// must be stable across restarts.
+    pub fn with_sink(mut self, name: &str, sink: impl Sink + 'static) -> Result<Self, String> {
+        let cursor_path = self.dir.join(format!("{}.cursor", name));
+        let cursor = match std::fs::read_to_string(&cursor_path) {
+            Ok(hex) => Some(EventId::from(
+                hex::decode(hex.trim()).map_err(|e| e.to_string())?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        self.outputs.push(Output {
+            name: name.to_string(),
+            sink: Box::new(sink),
+            cursor_path,
+            cursor,
+            retry_delay: MIN_RETRY_DELAY,
+            retry_at: None,
+        });
+        Ok(self)
+    }
+
+    fn read(&self, cursor: &Option<EventId>) -> Result<Vec<EventLog>, String> {
+        let backend = self.backend.lock().map_err(|e| e.to_string())?;
+        let list = backend
+            .list(events::ListArgs {
+                count: Some(BATCH_SIZE),
+                order: Some(SortOrder::Ascending),
+                filter: Some(events::EventFilter {
+                    kind: Some(
+                        vec![
+                            events::EventKind::KvStorePut,
+                            events::EventKind::KvStoreDisable,
+                        ]
+                        .into(),
+                    ),
+                    id_range: Some(CborRange {
+                        start: cursor.clone().map_or(Bound::Unbounded, Bound::Excluded),
+                        end: Bound::Unbounded,
+                    }),
+                    ..events::EventFilter::default()
+                }),
+            })
+            .map_err(|e| e.to_string())?;
+        Ok(list.events)
+    }
+
+    fn deliver(&mut self, i: usize) -> Result<usize, String> {
+        let logs = self.read(&self.outputs[i].cursor)?;
+        let last = match logs.last() {
+            Some(log) => log.id.clone(),
+            None => return Ok(0),
+        };
+        let records: Vec<_> = logs.iter().filter_map(ChangeRecord::from_event).collect();
+
+        let output = &mut self.outputs[i];
+        output.sink.deliver(&records)?;
+        output.save_cursor(&last).map_err(|e| e.to_string())?;
+        output.cursor = Some(last);
+        Ok(records.len())
+    }
+
+    /// Deliver at most a batch to every sink that is not waiting to retry.
+    /// Returns the number of records delivered.
+    pub fn poll(&mut self) -> usize {
+        let mut delivered = 0;
+        for i in 0..self.outputs.len() {
+            if matches!(self.outputs[i].retry_at, Some(at) if at > Instant::now()) {
+                continue;
+            }
+
+            let result = self.deliver(i);
+            let output = &mut self.outputs[i];
+            match result {
+                Ok(n) => {
+                    debug!("feed {}: delivered {} records", output.name, n);
+                    output.retry_delay = MIN_RETRY_DELAY;
+                    output.retry_at = None;
+                    delivered += n;
+                }
+                Err(e) => {
+                    warn!(
+                        "feed {}: {}; retrying in {:?}",
+                        output.name, e, output.retry_delay
+                    );
+                    output.retry_at = Some(Instant::now() + output.retry_delay);
+                    output.retry_delay = (output.retry_delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Run the feed on a background thread.
+    pub fn spawn(mut self) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            if self.poll() == 0 {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        })
+    }
+}
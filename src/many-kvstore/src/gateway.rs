@@ -0,0 +1,179 @@
+use crate::error;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::kvstore::{GetArgs, KvStoreModuleBackend, QueryArgs};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Method, Response, StatusCode};
+use tracing::{debug, warn};
+
+const STORE_PATH_PREFIX: &str = "/store/";
+
+/// Content types recognized from the first bytes of a value.
+const MAGIC_PREFIXES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"<!DOCTYPE html", "text/html"),
+    (b"<html", "text/html"),
+    (b"<?xml", "application/xml"),
+];
+
+/// Decode the key part of a gateway URL. Keys starting with `0x` are
+/// hexadecimal, anything else is percent-escaped.
+pub fn decode_key(s: &str) -> Option<Vec<u8>> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return hex::decode(hex).ok();
+    }
+
+    let bytes = s.as_bytes();
+    let mut key = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            key.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            key.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(key)
+}
+
+/// Recognize the content type of a value, first from its content, then from
+/// the extension of its key.
+pub fn sniff_content_type(key: &[u8], value: &[u8]) -> Option<String> {
+    if let Some((_, mime)) = MAGIC_PREFIXES
+        .iter()
+        .find(|(prefix, _)| value.starts_with(prefix))
+    {
+        return Some(mime.to_string());
+    }
+
+    std::str::from_utf8(key)
+        .ok()
+        .and_then(|k| new_mime_guess::from_path(k).first())
+        .map(|m| m.essence_str().to_string())
+}
+
+/// Guess the content type of a value, falling back to binary data.
+pub fn guess_content_type(key: &[u8], value: &[u8]) -> String {
+    sniff_content_type(key, value).unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+/// A read-only HTTP gateway over the kvstore, for clients that cannot speak
+/// MANY. Values are read as the anonymous identity, so only keys that are
+/// readable by anyone are served.
+pub struct Gateway<T: KvStoreModuleBackend> {
+    server: tiny_http::Server,
+    backend: Arc<Mutex<T>>,
+}
+
+impl<T: KvStoreModuleBackend + 'static> Gateway<T> {
+    pub fn new(addr: SocketAddr, backend: Arc<Mutex<T>>) -> Result<Self, String> {
+        let server = tiny_http::Server::http(addr).map_err(|e| e.to_string())?;
+        Ok(Self { server, backend })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.server.server_addr()
+    }
+
+    /// Serve requests on a background thread.
+    pub fn spawn(self) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || self.run())
+    }
+
+    pub fn run(self) {
+        for request in self.server.incoming_requests() {
+            let response = match request.method() {
+                Method::Get | Method::Head => self.handle(request.url()),
+                x => {
+                    warn!("Gateway received unknown method: {}", x);
+                    Response::empty(StatusCode::from(405)).boxed()
+                }
+            };
+
+            // Ignore errors on return.
+            let _ = request.respond(response);
+        }
+    }
+
+    fn handle(&self, url: &str) -> tiny_http::ResponseBox {
+        debug!("gateway: {}", url);
+        let path = url.split('?').next().unwrap_or_default();
+        let key = match path
+            .strip_prefix(STORE_PATH_PREFIX)
+            .filter(|k| !k.is_empty())
+            .map(decode_key)
+        {
+            Some(Some(key)) => key,
+            Some(None) => return Response::empty(400).boxed(),
+            None => return Response::empty(404).boxed(),
+        };
+
+        let (value, owner) = {
+            let backend = match self.backend.lock() {
+                Ok(backend) => backend,
+                Err(_) => return Response::empty(500).boxed(),
+            };
+            let value = backend.get(
+                &Address::anonymous(),
+                GetArgs {
+                    key: key.clone().into(),
+                },
+            );
+            let owner = backend
+                .query(
+                    &Address::anonymous(),
+                    QueryArgs {
+                        key: key.clone().into(),
+                    },
+                )
+                .ok()
+                .and_then(|m| m.owner);
+            (value, owner)
+        };
+
+        let value = match value {
+            Ok(r) => match r.value {
+                Some(value) => value,
+                None => return Response::empty(404).boxed(),
+            },
+            Err(e) => return Response::empty(status_for_error(&e)).boxed(),
+        };
+
+        let mut headers = vec![
+            Header::from_bytes("Content-Type", guess_content_type(&key, &value)).unwrap(),
+            Header::from_bytes("X-Many-Size", value.len().to_string()).unwrap(),
+        ];
+        if let Some(owner) = owner {
+            headers.push(Header::from_bytes("X-Many-Owner", owner.to_string()).unwrap());
+        }
+
+        // tiny_http drops the body of responses to HEAD requests.
+        let len = value.len();
+        Response::new(
+            StatusCode::from(200),
+            headers,
+            std::io::Cursor::new(value.to_vec()),
+            Some(len),
+            None,
+        )
+        .boxed()
+    }
+}
+
+fn status_for_error(e: &ManyError) -> u16 {
+    if e.code() == error::key_disabled().code() {
+        410
+    } else if e.code() == error::permission_denied().code() {
+        403
+    } else {
+        500
+    }
+}
@@ -1,3 +1,8 @@
 pub mod error;
+pub mod feed;
+pub mod gateway;
+pub mod migration;
 pub mod module;
+pub mod recovery;
 pub mod storage;
+pub mod webhook;
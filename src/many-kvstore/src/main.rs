@@ -1,22 +1,28 @@
 use crate::module::account::AccountFeatureModule;
 use clap::Parser;
 use many_identity::verifiers::AnonymousVerifier;
-use many_identity::Address;
+use many_identity::{Address, Identity};
 use many_identity_dsa::{CoseKeyIdentity, CoseKeyVerifier};
-use many_modules::account::features::Feature;
-use many_modules::{abci_backend, account, events, kvstore};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::account::features::{Feature, TryCreateFeature};
+use many_modules::{abci_backend, account, events, kvstore, ManyModule};
 use many_server::transport::http::HttpServer;
 use many_server::ManyServer;
+use many_storage::{event_schema, integrity};
 use std::collections::BTreeSet;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use tracing::level_filters::LevelFilter;
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 mod error;
+mod feed;
+mod gateway;
 mod module;
+mod recovery;
 mod storage;
+mod webhook;
 
 use module::*;
 
@@ -65,11 +71,108 @@ struct Opts {
     #[clap(long, arg_enum, default_value_t = LogStrategy::Terminal)]
     logmode: LogStrategy,
 
+    /// Path to a JSON5 file containing the configurations for the
+    /// migrations
+    #[clap(long, short)]
+    migrations_config: Option<PathBuf>,
+
     /// Path to a JSON file containing an array of MANY addresses
     /// Only addresses from this array will be able to execute commands, e.g., send, put, ...
     /// Any addresses will be able to execute queries, e.g., balance, get, ...
     #[clap(long)]
     allow_addrs: Option<PathBuf>,
+
+    /// The address and port to bind to for a read-only HTTP gateway serving
+    /// public values under `/store/<key>`. Disabled if not specified.
+    #[clap(long)]
+    gateway_addr: Option<SocketAddr>,
+
+    /// Append every committed mutation of the store to this NDJSON file.
+    #[clap(long, requires = "feed_dir")]
+    feed_file: Option<PathBuf>,
+
+    /// The size in bytes after which the feed file is rotated.
+    #[clap(long, default_value_t = feed::DEFAULT_FILE_MAX_SIZE)]
+    feed_file_max_size: u64,
+
+    /// POST every committed mutation of the store to this URL, in batches
+    /// of NDJSON records.
+    #[clap(long, requires = "feed_dir")]
+    feed_url: Option<String>,
+
+    /// The directory where the change feed keeps its resume cursors.
+    #[clap(long)]
+    feed_dir: Option<PathBuf>,
+
+    /// Call back the URLs registered in this JSON5 file when their key, or a
+    /// key under their prefix, changes. The webhooks are local to this node.
+    /// Their delivery status is returned by `server.webhooks` to the
+    /// identity of the server.
+    #[clap(long, requires = "feed_dir")]
+    webhooks: Option<PathBuf>,
+
+    /// Verify the consistency of the persistent store, print the
+    /// discrepancies found and exit.
+    #[clap(long)]
+    verify: bool,
+
+    /// Repair the discrepancies found by `--verify` when possible.
+    #[clap(long, requires = "verify")]
+    repair: bool,
+
+    /// Exit instead of recovering the store when the last run did not shut
+    /// down cleanly, e.g. to restore it from a snapshot instead.
+    #[clap(long)]
+    fail_on_recovery: bool,
+
+    /// The maximum number of records verified when recovering the store
+    /// after an unclean shutdown.
+    #[clap(long, default_value_t = recovery::DEFAULT_RECOVERY_LIMIT)]
+    recovery_limit: u64,
+
+    /// Check the merk tree against the bytes of the persistent store in the
+    /// background, keeping where the pass is in this file. Disabled if not
+    /// specified.
+    #[clap(long)]
+    integrity_cursor: Option<PathBuf>,
+
+    /// The number of nodes of the merk tree checked at once.
+    #[clap(long, default_value_t = integrity::DEFAULT_SLICE_SIZE)]
+    integrity_slice_size: u64,
+
+    /// The number of seconds between two checks of the merk tree.
+    #[clap(long, default_value_t = integrity::DEFAULT_INTERVAL_SECS)]
+    integrity_interval: u64,
+
+    /// Exit when the merk tree is found corrupted, instead of only logging
+    /// it.
+    #[clap(long, requires = "integrity-cursor")]
+    halt_on_corruption: bool,
+
+    /// Warn owners in the response of their puts once they use this
+    /// percentage of their quota.
+    #[clap(long, default_value_t = quota::DEFAULT_QUOTA_WARNING_PERCENT)]
+    quota_warning: u64,
+}
+
+/// Warn the owners of the values put through a module when they near their
+/// quota.
+fn warned<M: ManyModule>(
+    inner: M,
+    module: &Arc<Mutex<KvStoreModuleImpl>>,
+    threshold: u64,
+) -> quota::QuotaWarningModule<M, KvStoreModuleImpl> {
+    quota::QuotaWarningModule::new(inner, module.clone(), threshold)
+}
+
+/// Serve the puts of a module carrying a capability, on behalf of its
+/// issuer.
+fn delegated<M: ManyModule>(
+    inner: M,
+    module: &Arc<Mutex<KvStoreModuleImpl>>,
+    allow_addrs: Option<BTreeSet<Address>>,
+) -> capability::CapabilityPutModule<M, KvStoreModuleImpl> {
+    capability::CapabilityPutModule::new(inner, module.clone(), allow_addrs)
 }
 
 fn main() {
@@ -83,7 +186,23 @@ fn main() {
         persistent,
         clean,
         logmode,
+        migrations_config,
         allow_addrs,
+        gateway_addr,
+        feed_file,
+        feed_file_max_size,
+        feed_url,
+        feed_dir,
+        webhooks,
+        verify,
+        repair,
+        fail_on_recovery,
+        recovery_limit,
+        integrity_cursor,
+        integrity_slice_size,
+        integrity_interval,
+        halt_on_corruption,
+        quota_warning,
     } = Opts::parse();
 
     let verbose_level = 2 + verbose - quiet;
@@ -134,14 +253,118 @@ fn main() {
         json5::from_str(&content).unwrap()
     });
 
-    let module = if let Some(state) = state {
-        KvStoreModuleImpl::new(state, persistent, abci).unwrap()
+    let migrations = migrations_config
+        .map(|file| {
+            let contents = std::fs::read_to_string(file)
+                .expect("Could not read file passed to --migrations_config");
+            json5::from_str(&contents).expect("Could not parse file passed to --migrations_config")
+        })
+        .unwrap_or_default();
+
+    info!("Migrations: {:?}", migrations);
+
+    let mut module = if let Some(state) = state {
+        KvStoreModuleImpl::new(state, &persistent, abci).unwrap()
     } else {
-        KvStoreModuleImpl::load(persistent, abci).unwrap()
-    };
+        KvStoreModuleImpl::load(&persistent, abci).unwrap()
+    }
+    .with_migrations(migrations);
 
+    if verify {
+        let discrepancies = module.verify_all(repair).unwrap();
+        for d in &discrepancies {
+            println!(
+                "{:?} at {}: found {}, expected {}",
+                d.kind,
+                hex::encode(d.key.as_slice()),
+                d.actual
+                    .as_ref()
+                    .map_or_else(|| "nothing".to_string(), |x| hex::encode(x.as_slice())),
+                d.expected
+                    .as_ref()
+                    .map_or_else(|| "unknown".to_string(), |x| hex::encode(x.as_slice())),
+            );
+        }
+
+        let unrepaired = discrepancies
+            .iter()
+            .filter(|d| !repair || d.expected.is_none())
+            .count();
+        info!(
+            discrepancies = discrepancies.len(),
+            repaired = discrepancies.len() - unrepaired
+        );
+        std::process::exit(if unrepaired == 0 { 0 } else { 1 });
+    }
+
+    if let Err(e) = recovery::check(&mut module, &persistent, recovery_limit, fail_on_recovery) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    let endpoints = module
+        .init()
+        .expect("Could not list the endpoints.")
+        .endpoints;
     let module = Arc::new(Mutex::new(module));
 
+    let mut posture = posture::PostureBuilder::new(endpoints)
+        .with_verifiers(&["anonymous", "cose-key"])
+        .with_listener("many", addr)
+        // The quota is part of the state, returned by kvstore.usage.
+        .with_limit("quota-warning", format!("{quota_warning}%"));
+
+    if let Some(gateway_addr) = gateway_addr {
+        let gateway =
+            gateway::Gateway::new(gateway_addr, module.clone()).expect("Could not start gateway");
+        info!("HTTP gateway listening on {}", gateway.addr());
+        posture = posture.with_listener("gateway", gateway.addr());
+        gateway.spawn();
+    }
+
+    let webhooks = webhooks.map(|path| {
+        Arc::new(webhook::Webhooks::from_file(&path).unwrap_or_else(|e| {
+            error!("Could not read the webhooks of {}: {}", path.display(), e);
+            std::process::exit(1);
+        }))
+    });
+
+    if let Some(feed_dir) = feed_dir {
+        let mut feed = feed::Feed::new(module.clone(), feed_dir).expect("Could not start feed");
+        if let Some(path) = feed_file {
+            info!("Change feed appending to {}", path.display());
+            feed = feed
+                .with_sink("file", feed::FileSink::new(path, feed_file_max_size))
+                .expect("Could not add feed file");
+        }
+        if let Some(url) = feed_url {
+            info!("Change feed posting to {}", url);
+            feed = feed
+                .with_sink("http", feed::HttpSink::new(url).unwrap())
+                .expect("Could not add feed URL");
+        }
+        if let Some(webhooks) = &webhooks {
+            info!("Calling {} webhooks", webhooks.hooks().len());
+            webhooks.spawn().expect("Could not start the webhooks");
+            feed = feed
+                .with_sink("webhooks", webhooks.sink())
+                .expect("Could not add the webhooks");
+        }
+        feed.spawn();
+    }
+
+    if let Some(path) = integrity_cursor {
+        info!(
+            "Checking the merk tree in the background, {} nodes every {}s",
+            integrity_slice_size, integrity_interval
+        );
+        integrity::Scrubber::new(module.clone(), path, integrity_slice_size)
+            .expect("Could not start the integrity checks")
+            .with_halt_on_corruption(halt_on_corruption)
+            .spawn(std::time::Duration::from_secs(integrity_interval));
+    }
+
+    let server_id = key.address();
     let many = ManyServer::simple(
         "many-kvstore",
         key,
@@ -151,24 +374,73 @@ fn main() {
 
     {
         let mut s = many.lock().unwrap();
-        s.add_module(kvstore::KvStoreModule::new(module.clone()));
+        s.add_module(posture.open(kvstore::KvStoreModule::new(module.clone())));
         let kvstore_command_module = kvstore::KvStoreCommandsModule::new(module.clone());
-        if let Some(path) = allow_addrs {
-            let allow_addrs: BTreeSet<Address> =
-                json5::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
-            s.add_module(allow_addrs::AllowAddrsModule {
-                inner: kvstore_command_module,
-                allow_addrs,
-            });
+        let allow_addrs: Option<BTreeSet<Address>> = allow_addrs
+            .map(|path| json5::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap());
+        posture = posture.with_allow_addrs(allow_addrs.as_ref());
+        if let Some(allowed) = allow_addrs.clone() {
+            s.add_module(posture.gated(warned(
+                delegated(
+                    allow_addrs::AllowAddrsModule {
+                        inner: kvstore_command_module,
+                        allow_addrs: allowed,
+                    },
+                    &module,
+                    allow_addrs,
+                ),
+                &module,
+                quota_warning,
+            )));
         } else {
-            s.add_module(kvstore_command_module);
+            s.add_module(posture.gated(warned(
+                delegated(kvstore_command_module, &module, None),
+                &module,
+                quota_warning,
+            )));
         }
-        s.add_module(events::EventsModule::new(module.clone()));
+        s.add_module(posture.open(event_schema::EventSchemaModule::new(
+            events::EventsModule::new(module.clone()),
+        )));
+        s.add_module(posture.open(verify::KvStoreVerifyModule::new(module.clone())));
+        s.add_module(posture.open(policy::KvStorePolicyModule::new(module.clone())));
+        s.add_module(posture.open(counter::KvStoreCounterModule::new(module.clone())));
+        s.add_module(posture.open(list::KvStoreListModule::new(module.clone())));
+        s.add_module(posture.open(warned(
+            derived::KvStoreDerivedModule::new(module.clone()),
+            &module,
+            quota_warning,
+        )));
+        s.add_module(posture.open(warned(
+            lock::KvStoreLockModule::new(module.clone()),
+            &module,
+            quota_warning,
+        )));
+        s.add_module(posture.open(quota::KvStoreQuotaModule::new(module.clone())));
+        s.add_module(posture.open(capability::KvStoreCapabilityModule::new(module.clone())));
+        s.add_module(posture.open(bulk_disable::KvStoreBulkDisableModule::new(module.clone())));
+        s.add_module(posture.open(move_prefix::KvStoreMovePrefixModule::new(module.clone())));
+        s.add_module(posture.open(tenant::KvStoreTenantModule::new(module.clone())));
+        s.add_module(posture.open(warned(
+            json_patch::KvStoreJsonPatchModule::new(module.clone()),
+            &module,
+            quota_warning,
+        )));
 
-        s.add_module(AccountFeatureModule::new(
+        s.add_module(posture.open(AccountFeatureModule::new(
             account::AccountModule::new(module.clone()),
-            [Feature::with_id(2)],
-        ));
+            [
+                Feature::with_id(2),
+                Feature::with_id(policy::AccountKvStoreDefaults::ID),
+            ],
+        )));
+
+        let posture = posture.build();
+        info!("{}", posture);
+        s.add_module(posture::PostureModule::new(posture, server_id));
+        if let Some(webhooks) = webhooks {
+            s.add_module(module::webhook::WebhooksModule::new(webhooks, server_id));
+        }
         if abci {
             s.set_timeout(u64::MAX);
             s.add_module(abci_backend::AbciModule::new(module));
@@ -185,4 +457,8 @@ fn main() {
 
     let runtime = tokio::runtime::Runtime::new().unwrap();
     runtime.block_on(many_server.bind(addr)).unwrap();
+
+    recovery::DirtyMarker::new(&persistent)
+        .clear()
+        .expect("Could not clear the dirty marker");
 }
@@ -0,0 +1,4 @@
+pub mod derived;
+pub mod usage;
+
+pub use many_storage::migration::Migration;
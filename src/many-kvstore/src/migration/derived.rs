@@ -0,0 +1,34 @@
+use merk::Op;
+use serde::{Deserialize, Serialize};
+
+use super::Migration;
+
+/// The name of the migration from which the fields derived from the value
+/// of a key are written when it is put.
+pub const KVSTORE_DERIVED_MIGRATION: &str = "KvStoreDerived";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KvStoreDerived {
+    block_height: u64,
+    issue: Option<String>,
+}
+
+#[typetag::serde]
+impl Migration for KvStoreDerived {
+    fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    fn name(&self) -> &str {
+        KVSTORE_DERIVED_MIGRATION
+    }
+
+    /// The keys already put have no derived fields until they are put again.
+    fn migrate(&self, _persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)> {
+        vec![]
+    }
+}
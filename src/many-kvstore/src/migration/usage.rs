@@ -0,0 +1,35 @@
+use merk::Op;
+use serde::{Deserialize, Serialize};
+
+use super::Migration;
+use crate::storage::KvStoreStorage;
+
+/// The name of the migration from which the usage of owners is counted when
+/// they put values.
+pub const KVSTORE_USAGE_MIGRATION: &str = "KvStoreUsage";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KvStoreUsage {
+    block_height: u64,
+    issue: Option<String>,
+}
+
+#[typetag::serde]
+impl Migration for KvStoreUsage {
+    fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    fn name(&self) -> &str {
+        KVSTORE_USAGE_MIGRATION
+    }
+
+    /// Count the values put before the migration, so quotas apply to them.
+    fn migrate(&self, persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)> {
+        KvStoreStorage::backfill_usage(persistent_store)
+    }
+}
@@ -2,6 +2,7 @@ use crate::{
     error,
     storage::{AclMap, KvStoreStorage},
 };
+use derived::DerivedMetadata;
 use many_error::{ManyError, Reason};
 use many_identity::Address;
 use many_modules::abci_backend::{
@@ -14,21 +15,50 @@ use many_modules::kvstore::{
     KvStoreCommandsModuleBackend, KvStoreModuleBackend, PutArgs, PutReturn, QueryArgs,
     QueryReturns,
 };
+use many_storage::migration::Migration;
 use many_types::{Either, Timestamp};
-use std::collections::BTreeMap;
+use policy::PolicyOverrides;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::path::Path;
 use tracing::info;
 
 pub mod account;
 pub mod allow_addrs;
+pub mod bulk_disable;
+pub mod capability;
+pub mod counter;
+pub mod derived;
 mod event;
+pub mod json_patch;
+pub mod list;
+pub mod lock;
+pub mod move_prefix;
+pub mod policy;
+pub use many_storage::posture;
+pub mod quota;
+pub mod tenant;
+pub mod verify;
+pub mod webhook;
 
 // The initial state schema, loaded from JSON.
 #[derive(serde::Deserialize, Debug, Default)]
 pub struct InitialStateJson {
     acl: AclMap,
     identity: Address,
+
+    /// The rules declaring the values under prefixes as JSON documents.
+    #[serde(default)]
+    json_prefixes: Vec<json_patch::JsonPrefix>,
+
+    /// The maximum number of bytes of values each owner can put.
+    #[serde(default)]
+    quota: Option<u64>,
+
+    /// The prefixes reserved to the tenants of the store.
+    #[serde(default)]
+    reserved_prefixes: Vec<tenant::ReservedPrefix>,
+
     hash: Option<String>,
 }
 
@@ -76,9 +106,13 @@ impl KvStoreModuleImpl {
         persistence_store_path: P,
         blockchain: bool,
     ) -> Result<Self, ManyError> {
+        tenant::validate_reserved_prefixes(&initial_state.reserved_prefixes)?;
         let storage = KvStoreStorage::new(
             initial_state.acl,
             initial_state.identity,
+            &initial_state.json_prefixes,
+            initial_state.quota,
+            &initial_state.reserved_prefixes,
             persistence_store_path,
             blockchain,
         )
@@ -99,6 +133,11 @@ impl KvStoreModuleImpl {
 
         Ok(Self { storage })
     }
+
+    pub fn with_migrations(mut self, migrations: BTreeSet<Box<dyn Migration>>) -> Self {
+        self.storage = self.storage.with_migrations(migrations);
+        self
+    }
 }
 
 // This module is always supported, but will only be added when created using an ABCI
@@ -113,6 +152,37 @@ impl ManyAbciModuleBackend for KvStoreModuleImpl {
                 ("kvstore.query".to_string(), EndpointInfo { is_command: false }),
                 ("kvstore.put".to_string(), EndpointInfo { is_command: true }),
                 ("kvstore.disable".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.verify".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.effectivePolicy".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.setDefaultPolicy".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.counterCreate".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.counterAdd".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.queryKind".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.list".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.queryDerived".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.putWithContentType".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.lockAcquire".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.lockRenew".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.lockRelease".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.putWithLock".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.usage".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.setQuota".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.revokeCapability".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.disablePrefix".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.pendingDisables".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.movePrefix".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.pendingMoves".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.patch".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.jsonVersion".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.jsonPrefixes".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.setJsonPrefixes".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.reservedPrefixes".to_string(), EndpointInfo { is_command: false }),
+                ("kvstore.setReservedPrefixes".to_string(), EndpointInfo { is_command: true }),
+                ("kvstore.tenantStats".to_string(), EndpointInfo { is_command: false }),
+
+                // Server
+                ("server.posture".to_string(), EndpointInfo { is_command: false }),
+                ("server.webhooks".to_string(), EndpointInfo { is_command: false }),
 
                 // Accounts
                 ("account.create".to_string(), EndpointInfo { is_command: true }),
@@ -150,6 +220,12 @@ impl ManyAbciModuleBackend for KvStoreModuleImpl {
             self.storage.set_time(time);
         }
 
+        // Before any transaction, so every node scans the same keys.
+        self.storage
+            .drain_bulk_disables(bulk_disable::BULK_DISABLE_KEYS_PER_BLOCK)?;
+        self.storage
+            .drain_prefix_moves(move_prefix::PREFIX_MOVE_KEYS_PER_BLOCK)?;
+
         Ok(BeginBlockReturn {})
     }
 
@@ -187,7 +263,8 @@ impl KvStoreModuleBackend for KvStoreModuleImpl {
         Ok(InfoReturns { hash: hash.into() })
     }
 
-    fn get(&self, _sender: &Address, args: GetArgs) -> Result<GetReturns, ManyError> {
+    fn get(&self, sender: &Address, args: GetArgs) -> Result<GetReturns, ManyError> {
+        self.verify_read(sender, &args.key)?;
         let value = self.storage.get(&args.key)?;
         Ok(GetReturns {
             value: value.map(|x| x.into()),
@@ -195,24 +272,39 @@ impl KvStoreModuleBackend for KvStoreModuleImpl {
     }
 
     fn query(&self, _sender: &Address, args: QueryArgs) -> Result<QueryReturns, ManyError> {
-        minicbor::decode(
+        let mut returns: QueryReturns = minicbor::decode(
             &self
                 .storage
                 .get_metadata(&args.key)?
                 .ok_or_else(error::key_not_found)?,
         )
-        .map_err(|e| ManyError::deserialization_error(e.to_string()))
+        .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+        if let Some(disable) = self
+            .storage
+            .bulk_disable_of(&args.key, returns.owner.as_ref())?
+        {
+            returns.disabled = Some(disable.disabled_value());
+        }
+        Ok(returns)
     }
 }
 
 impl KvStoreCommandsModuleBackend for KvStoreModuleImpl {
     fn put(&mut self, sender: &Address, args: PutArgs) -> Result<PutReturn, ManyError> {
+        self.put_with_overrides(sender, args, PolicyOverrides::default())
+    }
+
+    fn disable(&mut self, sender: &Address, args: DisableArgs) -> Result<DisableReturn, ManyError> {
+        if self.storage.get(&args.key)?.is_none() {
+            return Err(error::cannot_disable_empty_key());
+        }
         let key: Vec<u8> = args.key.into();
         let owner = if let Some(ref alternative_owner) = args.alternative_owner {
             self.validate_alternative_owner(
                 sender,
                 alternative_owner,
-                [Role::CanKvStorePut, Role::Owner],
+                [Role::CanKvStoreDisable, Role::Owner],
             )?;
             alternative_owner
         } else {
@@ -220,25 +312,55 @@ impl KvStoreCommandsModuleBackend for KvStoreModuleImpl {
         };
 
         self.verify_acl(owner, key.clone())?;
+        if self.storage.get_policy(&key)?.immutable {
+            return Err(error::key_immutable());
+        }
+        self.check_prefix_move(&key)?;
+
+        let maybe_reason = if let Some(reason) = args.reason {
+            Either::Right(reason)
+        } else {
+            Either::Left(true)
+        };
 
         let meta = KvStoreMetadata {
             owner: Some(*owner),
-            disabled: Some(Either::Left(false)),
+            disabled: Some(maybe_reason),
         };
-        self.storage.put(&meta, &key, args.value.into())?;
-        Ok(PutReturn {})
+
+        self.storage.disable(&meta, &key)?;
+        Ok(DisableReturn {})
     }
+}
 
-    fn disable(&mut self, sender: &Address, args: DisableArgs) -> Result<DisableReturn, ManyError> {
-        if self.storage.get(&args.key)?.is_none() {
-            return Err(error::cannot_disable_empty_key());
-        }
+impl KvStoreModuleImpl {
+    /// Put a value, with policy values that win over the default policy of
+    /// the owner. The policy of the key is fixed at put time.
+    pub fn put_with_overrides(
+        &mut self,
+        sender: &Address,
+        args: PutArgs,
+        overrides: PolicyOverrides,
+    ) -> Result<PutReturn, ManyError> {
+        self.put_value(sender, args, overrides, None, None)
+    }
+
+    /// Put a value, with its content type if declared, and the token of the
+    /// lock of the key if held.
+    pub(crate) fn put_value(
+        &mut self,
+        sender: &Address,
+        args: PutArgs,
+        overrides: PolicyOverrides,
+        content_type: Option<String>,
+        lock_token: Option<u64>,
+    ) -> Result<PutReturn, ManyError> {
         let key: Vec<u8> = args.key.into();
         let owner = if let Some(ref alternative_owner) = args.alternative_owner {
             self.validate_alternative_owner(
                 sender,
                 alternative_owner,
-                [Role::CanKvStoreDisable, Role::Owner],
+                [Role::CanKvStorePut, Role::Owner],
             )?;
             alternative_owner
         } else {
@@ -246,19 +368,30 @@ impl KvStoreCommandsModuleBackend for KvStoreModuleImpl {
         };
 
         self.verify_acl(owner, key.clone())?;
-
-        let maybe_reason = if let Some(reason) = args.reason {
-            Either::Right(reason)
-        } else {
-            Either::Left(true)
-        };
+        if self.storage.get_policy(&key)?.immutable {
+            return Err(error::key_immutable());
+        }
+        self.check_bulk_disable(owner, &key)?;
+        self.check_prefix_move(&key)?;
+        if self.storage.get_kind(&key)?.is_counter() {
+            return Err(error::key_is_counter());
+        }
+        self.check_lock(owner, &key, lock_token)?;
+        self.check_quota(owner, &key, args.value.len())?;
+        let tenant = self.check_tenant(owner, &key)?;
+        let version = self.check_json(&key, &args.value)?;
+        let content_type =
+            content_type.or_else(|| version.map(|_| json_patch::JSON_CONTENT_TYPE.to_string()));
 
         let meta = KvStoreMetadata {
             owner: Some(*owner),
-            disabled: Some(maybe_reason),
+            disabled: Some(Either::Left(false)),
         };
-
-        self.storage.disable(&meta, &key)?;
-        Ok(DisableReturn {})
+        let policy = self.default_policy(owner)?.with_overrides(overrides);
+        let derived = DerivedMetadata::compute(&key, &args.value, content_type);
+        self.storage
+            .put(&meta, &policy, &derived, version, &key, args.value.into())?;
+        self.record_tenant_write(tenant)?;
+        Ok(PutReturn {})
     }
 }
@@ -1,3 +1,4 @@
+use super::policy::AccountKvStoreDefaults;
 use super::{error, KvStoreMetadata, KvStoreModuleImpl};
 use coset::CoseSign1;
 use many_error::{ManyError, ManyErrorCode};
@@ -29,6 +30,11 @@ fn validate_features_for_account(account: &account::Account) -> Result<(), ManyE
             return Err(e);
         }
     }
+    if let Err(e) = features.get::<AccountKvStoreDefaults>() {
+        if e.code() != ManyErrorCode::AttributeNotFound {
+            return Err(e);
+        }
+    }
 
     Ok(())
 }
@@ -315,4 +321,30 @@ impl KvStoreModuleImpl {
         }
         Ok(())
     }
+
+    /// Verify if user is permitted to read the value at the given key
+    pub(crate) fn verify_read(&self, sender: &Address, key: &[u8]) -> Result<(), ManyError> {
+        let policy = self.storage.get_policy(key)?;
+        if policy.public || policy.readers.contains(sender) {
+            return Ok(());
+        }
+
+        if let Some(meta_cbor) = self.storage.get_metadata(key)? {
+            let meta: KvStoreMetadata = minicbor::decode(&meta_cbor)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+            if let Some(owner) = meta.owner {
+                if &owner == sender {
+                    return Ok(());
+                }
+                // Identities with a role in the owner account can read its keys.
+                if let Some(account) = self.storage.get_account(&owner) {
+                    if !account.get_roles(sender).is_empty() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Err(error::read_denied())
+    }
 }
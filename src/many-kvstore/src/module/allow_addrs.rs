@@ -1,3 +1,4 @@
+use crate::module::posture::SenderGate;
 use coset::CoseSign1;
 use many_error::ManyError;
 use many_identity::Address;
@@ -17,6 +18,12 @@ impl<T: kvstore::KvStoreCommandsModuleBackend> Debug for AllowAddrsModule<T> {
     }
 }
 
+impl<T: kvstore::KvStoreCommandsModuleBackend> SenderGate for AllowAddrsModule<T> {
+    fn admits(&self, _method: &str, sender: &Address) -> bool {
+        self.allow_addrs.contains(sender)
+    }
+}
+
 #[async_trait::async_trait]
 impl<T: kvstore::KvStoreCommandsModuleBackend> ManyModule for AllowAddrsModule<T> {
     fn info(&self) -> &ManyModuleInfo {
@@ -28,7 +35,7 @@ impl<T: kvstore::KvStoreCommandsModuleBackend> ManyModule for AllowAddrsModule<T
     }
 
     async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
-        if !self.allow_addrs.contains(&message.from()) {
+        if !self.admits(&message.method, &message.from()) {
             return Err(ManyError::invalid_from_identity());
         }
 
@@ -0,0 +1,232 @@
+use crate::error;
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::{ManyError, Reason};
+use many_identity::Address;
+use many_modules::account::Role;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::Either;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Number of keys the pending bulk disables scan at the beginning of each
+/// block. This must be the same on every node.
+pub const BULK_DISABLE_KEYS_PER_BLOCK: usize = 1000;
+
+/// The disable of all the keys of an owner starting with a prefix. Its keys
+/// are disabled in chunks over the next blocks, so a large disable does not
+/// exceed the time budget of a block, but they read as disabled as soon as
+/// it is accepted.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+#[cbor(map)]
+pub struct BulkDisable {
+    #[n(0)]
+    pub id: u64,
+
+    #[n(1)]
+    pub prefix: ByteVec,
+
+    #[n(2)]
+    pub owner: Address,
+
+    #[n(3)]
+    pub reason: Option<Reason<u64>>,
+
+    /// The last key scanned. Keys up to this one were disabled.
+    #[n(4)]
+    pub processed: Option<ByteVec>,
+
+    /// The number of keys disabled so far.
+    #[n(5)]
+    pub disabled: u64,
+}
+
+impl BulkDisable {
+    /// Whether a key of an owner is disabled by this bulk disable, but was
+    /// not processed yet.
+    pub fn covers(&self, key: &[u8], owner: &Address) -> bool {
+        &self.owner == owner
+            && key.starts_with(&self.prefix)
+            && self
+                .processed
+                .as_ref()
+                .map_or(true, |processed| key > processed.as_slice())
+    }
+
+    /// The value of the `disabled` metadata of the keys.
+    pub fn disabled_value(&self) -> Either<bool, Reason<u64>> {
+        match &self.reason {
+            Some(reason) => Either::Right(reason.clone()),
+            None => Either::Left(true),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+#[cbor(map)]
+pub struct DisablePrefixArgs {
+    /// Disable the keys starting with this prefix. All the keys of the owner
+    /// are disabled if it is empty.
+    #[n(0)]
+    pub prefix: ByteVec,
+
+    #[n(1)]
+    pub alternative_owner: Option<Address>,
+
+    #[n(2)]
+    pub reason: Option<Reason<u64>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct DisablePrefixReturns {
+    /// The ID of the bulk disable, to follow its progress.
+    #[n(0)]
+    pub id: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PendingDisablesArgs {
+    /// Only return the bulk disables of this owner.
+    #[n(0)]
+    pub owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+#[cbor(map)]
+pub struct PendingDisablesReturns {
+    /// The bulk disables whose keys are not all disabled yet, oldest first.
+    #[n(0)]
+    pub pending: Vec<BulkDisable>,
+}
+
+pub trait KvStoreBulkDisableModuleBackend: Send {
+    fn disable_prefix(
+        &mut self,
+        sender: &Address,
+        args: DisablePrefixArgs,
+    ) -> Result<DisablePrefixReturns, ManyError>;
+
+    fn pending_disables(
+        &self,
+        sender: &Address,
+        args: PendingDisablesArgs,
+    ) -> Result<PendingDisablesReturns, ManyError>;
+}
+
+impl KvStoreModuleImpl {
+    /// Refuse changing a key whose bulk disable is pending, as the bulk
+    /// disable would disable it again.
+    pub(crate) fn check_bulk_disable(&self, owner: &Address, key: &[u8]) -> Result<(), ManyError> {
+        match self.storage.bulk_disable_of(key, Some(owner))? {
+            Some(disable) => Err(error::bulk_disable_pending(disable.id.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl KvStoreBulkDisableModuleBackend for KvStoreModuleImpl {
+    fn disable_prefix(
+        &mut self,
+        sender: &Address,
+        args: DisablePrefixArgs,
+    ) -> Result<DisablePrefixReturns, ManyError> {
+        let owner = if let Some(ref alternative_owner) = args.alternative_owner {
+            self.validate_alternative_owner(
+                sender,
+                alternative_owner,
+                [Role::CanKvStoreDisable, Role::Owner],
+            )?;
+            *alternative_owner
+        } else {
+            *sender
+        };
+
+        let id = self
+            .storage
+            .add_bulk_disable(owner, args.prefix.into(), args.reason)?;
+        Ok(DisablePrefixReturns { id })
+    }
+
+    fn pending_disables(
+        &self,
+        _sender: &Address,
+        args: PendingDisablesArgs,
+    ) -> Result<PendingDisablesReturns, ManyError> {
+        let pending = self
+            .storage
+            .pending_bulk_disables()?
+            .into_iter()
+            .filter(|disable| args.owner.map_or(true, |owner| disable.owner == owner))
+            .collect();
+        Ok(PendingDisablesReturns { pending })
+    }
+}
+
+const ENDPOINTS: &[&str] = &["kvstore.disablePrefix", "kvstore.pendingDisables"];
+
+/// A module to disable all the keys starting with a prefix.
+pub struct KvStoreBulkDisableModule<T: KvStoreBulkDisableModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreBulkDisableModuleBackend> KvStoreBulkDisableModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreBulkDisableModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStoreBulkDisableModuleBackend> Debug for KvStoreBulkDisableModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreBulkDisableModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreBulkDisableModuleBackend> ManyModule for KvStoreBulkDisableModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.disablePrefix" => decode_args::<DisablePrefixArgs>(&message.data).map(|_| ()),
+            "kvstore.pendingDisables" => {
+                decode_args::<PendingDisablesArgs>(&message.data).map(|_| ())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.disablePrefix" => decode_args(&message.data)
+                .and_then(|args| backend.disable_prefix(&from, args))
+                .and_then(encode_returns),
+            "kvstore.pendingDisables" => decode_args(&message.data)
+                .and_then(|args| backend.pending_disables(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
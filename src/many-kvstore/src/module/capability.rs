@@ -0,0 +1,365 @@
+use crate::error;
+use crate::module::policy::PolicyOverrides;
+use crate::module::posture::SenderGate;
+use crate::module::KvStoreModuleImpl;
+use coset::{CborSerializable, CoseSign1, CoseSign1Builder};
+use many_error::ManyError;
+use many_identity::{Address, Identity, Verifier};
+use many_identity_dsa::CoseKeyVerifier;
+use many_modules::kvstore::{PutArgs, PutReturn};
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use sha2::Digest;
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// The request attribute carrying a capability token, the bytes of a COSE
+/// Sign1 envelope of the capability signed by its issuer. The ID is outside
+/// the range of the local response attributes of many-abci.
+pub const CAPABILITY: Attribute = Attribute::id(2000);
+
+/// The puts an issuer delegates to the holders of a token, e.g. devices
+/// writing telemetry without an identity of their own being allowed.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct Capability {
+    /// The identity signing the capability, which owns the values put.
+    #[n(0)]
+    pub issuer: Address,
+
+    /// The prefix of the keys which can be put.
+    #[n(1)]
+    pub prefix: ByteVec,
+
+    /// The maximum size of each value, in bytes.
+    #[n(2)]
+    pub max_value_size: u64,
+
+    /// The block time from which the capability cannot be used anymore.
+    #[n(3)]
+    pub expiry: Timestamp,
+
+    /// The number of puts allowed.
+    #[n(4)]
+    pub max_uses: u64,
+}
+
+impl Capability {
+    /// Sign the capability as its issuer, returning the token to add to
+    /// requests.
+    pub fn sign(&self, identity: &impl Identity) -> Result<Vec<u8>, ManyError> {
+        if identity.address() != self.issuer {
+            return Err(error::invalid_capability(
+                "it must be signed by its issuer".to_string(),
+            ));
+        }
+        let payload =
+            minicbor::to_vec(self).map_err(|e| ManyError::serialization_error(e.to_string()))?;
+        identity
+            .sign_1(CoseSign1Builder::new().payload(payload).build())?
+            .to_vec()
+            .map_err(|e| ManyError::serialization_error(e.to_string()))
+    }
+}
+
+/// A capability whose signature was verified, with the hash of its payload
+/// under which its uses are counted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedCapability {
+    pub capability: Capability,
+    pub hash: Vec<u8>,
+}
+
+impl SignedCapability {
+    pub fn verify(token: &[u8]) -> Result<Self, ManyError> {
+        let envelope =
+            CoseSign1::from_slice(token).map_err(|e| error::invalid_capability(e.to_string()))?;
+        let signer = CoseKeyVerifier
+            .verify_1(&envelope)
+            .map_err(|e| error::invalid_capability(e.to_string()))?;
+        let payload = envelope
+            .payload
+            .as_deref()
+            .ok_or_else(|| error::invalid_capability("it has no payload".to_string()))?;
+        let capability: Capability =
+            minicbor::decode(payload).map_err(|e| error::invalid_capability(e.to_string()))?;
+
+        if signer.is_anonymous() || signer != capability.issuer {
+            return Err(error::invalid_capability(
+                "it is not signed by its issuer".to_string(),
+            ));
+        }
+        Ok(Self {
+            hash: sha2::Sha256::digest(payload).to_vec(),
+            capability,
+        })
+    }
+}
+
+/// The token of the capability attribute of a request, if any.
+fn token(message: &RequestMessage) -> Option<Result<&[u8], ManyError>> {
+    let attribute = message.attributes.get_attribute(CAPABILITY.id)?;
+    Some(match attribute.arguments.as_slice() {
+        [CborAny::Bytes(token)] => Ok(token.as_slice()),
+        _ => Err(error::invalid_capability(
+            "the attribute must hold the token".to_string(),
+        )),
+    })
+}
+
+/// The issuer of the capability of a request, without verifying it.
+pub(crate) fn issuer(message: &RequestMessage) -> Option<Address> {
+    let envelope = CoseSign1::from_slice(token(message)?.ok()?).ok()?;
+    let capability: Capability = minicbor::decode(envelope.payload.as_deref()?).ok()?;
+    Some(capability.issuer)
+}
+
+/// The uses of a capability, kept in the store so every node agrees on
+/// them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct CapabilityState {
+    #[n(0)]
+    pub uses: u64,
+
+    #[n(1)]
+    pub revoked: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct RevokeCapabilityArgs {
+    /// The token of the capability.
+    #[n(0)]
+    pub token: ByteVec,
+}
+
+pub trait KvStoreCapabilityModuleBackend: Send {
+    /// Put a value on behalf of the issuer of a capability, within its
+    /// constraints.
+    fn put_with_capability(
+        &mut self,
+        sender: &Address,
+        capability: SignedCapability,
+        args: PutArgs,
+    ) -> Result<PutReturn, ManyError>;
+
+    fn revoke_capability(
+        &mut self,
+        sender: &Address,
+        args: RevokeCapabilityArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+}
+
+fn timestamp_secs(timestamp: Timestamp) -> Result<u64, ManyError> {
+    Ok(timestamp
+        .as_system_time()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| ManyError::unknown(e.to_string()))?
+        .as_secs())
+}
+
+impl KvStoreCapabilityModuleBackend for KvStoreModuleImpl {
+    fn put_with_capability(
+        &mut self,
+        _sender: &Address,
+        capability: SignedCapability,
+        args: PutArgs,
+    ) -> Result<PutReturn, ManyError> {
+        let SignedCapability { capability, hash } = capability;
+        let mut state = self.storage.get_capability(&hash)?;
+        if state.revoked {
+            return Err(error::capability_revoked());
+        }
+        let expiry = timestamp_secs(capability.expiry)?;
+        if self.now_secs()? >= expiry {
+            return Err(error::capability_expired(expiry.to_string()));
+        }
+        if state.uses >= capability.max_uses {
+            return Err(error::capability_exhausted(capability.max_uses.to_string()));
+        }
+        if !args.key.starts_with(capability.prefix.as_slice()) {
+            return Err(error::capability_key_denied(hex::encode(
+                capability.prefix.as_slice(),
+            )));
+        }
+        if args.value.len() as u64 > capability.max_value_size {
+            return Err(error::capability_value_too_large(
+                capability.max_value_size.to_string(),
+            ));
+        }
+
+        self.put_value(
+            &capability.issuer,
+            args,
+            PolicyOverrides::default(),
+            None,
+            None,
+        )?;
+        state.uses += 1;
+        self.storage.set_capability(&hash, &state)?;
+        Ok(PutReturn {})
+    }
+
+    fn revoke_capability(
+        &mut self,
+        sender: &Address,
+        args: RevokeCapabilityArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let SignedCapability { capability, hash } = SignedCapability::verify(&args.token)?;
+        if sender != &capability.issuer {
+            return Err(error::capability_revoke_denied());
+        }
+
+        let mut state = self.storage.get_capability(&hash)?;
+        state.revoked = true;
+        self.storage.set_capability(&hash, &state)?;
+        Ok(EmptyReturn)
+    }
+}
+
+/// A module for the revocation of capabilities.
+pub struct KvStoreCapabilityModule<T: KvStoreCapabilityModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreCapabilityModuleBackend> KvStoreCapabilityModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreCapabilityModule".to_string(),
+                attribute: None,
+                endpoints: vec!["kvstore.revokeCapability".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: KvStoreCapabilityModuleBackend> Debug for KvStoreCapabilityModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreCapabilityModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreCapabilityModuleBackend> ManyModule for KvStoreCapabilityModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.revokeCapability" => {
+                decode_args::<RevokeCapabilityArgs>(&message.data).map(|_| ())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.revokeCapability" => decode_args(&message.data)
+                .and_then(|args| backend.revoke_capability(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
+
+/// The only method which can be called with a capability.
+const CAPABILITY_METHOD: &str = "kvstore.put";
+
+/// Wraps the module of the puts, serving the puts carrying a capability on
+/// behalf of its issuer. Their sender does not need to be allowed, but the
+/// issuer does.
+pub struct CapabilityPutModule<M: ManyModule, T: KvStoreCapabilityModuleBackend> {
+    inner: M,
+    backend: Arc<Mutex<T>>,
+    allow_addrs: Option<BTreeSet<Address>>,
+}
+
+impl<M: ManyModule, T: KvStoreCapabilityModuleBackend> CapabilityPutModule<M, T> {
+    pub fn new(inner: M, backend: Arc<Mutex<T>>, allow_addrs: Option<BTreeSet<Address>>) -> Self {
+        Self {
+            inner,
+            backend,
+            allow_addrs,
+        }
+    }
+
+    fn put(&self, message: &RequestMessage, token: &[u8]) -> Result<PutReturn, ManyError> {
+        if message.method != CAPABILITY_METHOD {
+            return Err(error::invalid_capability(format!(
+                "'{}' cannot be called with a capability",
+                message.method
+            )));
+        }
+        let capability = SignedCapability::verify(token)?;
+        if let Some(allow_addrs) = &self.allow_addrs {
+            if !allow_addrs.contains(&capability.capability.issuer) {
+                return Err(error::capability_issuer_denied());
+            }
+        }
+
+        let args = decode_args(&message.data)?;
+        self.backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .put_with_capability(&message.from(), capability, args)
+    }
+}
+
+/// A put carrying a capability is served whatever its sender, on behalf of
+/// the issuer of the capability.
+impl<M: ManyModule + SenderGate, T: KvStoreCapabilityModuleBackend> SenderGate
+    for CapabilityPutModule<M, T>
+{
+    fn admits(&self, method: &str, sender: &Address) -> bool {
+        method == CAPABILITY_METHOD || self.inner.admits(method, sender)
+    }
+}
+
+impl<M: ManyModule, T: KvStoreCapabilityModuleBackend> Debug for CapabilityPutModule<M, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CapabilityPutModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule, T: KvStoreCapabilityModuleBackend> ManyModule for CapabilityPutModule<M, T> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let data = match token(&message) {
+            Some(token) => token
+                .and_then(|token| self.put(&message, token))
+                .and_then(encode_returns),
+            None => return self.inner.execute(message).await,
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,326 @@
+use crate::error;
+use crate::module::{KvStoreMetadata, KvStoreModuleImpl};
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::account::Role;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::Either;
+use minicbor::data::{Tag, Type};
+use minicbor::encode::{Error, Write};
+use minicbor::{Decode, Decoder, Encode, Encoder};
+use num_bigint::{BigInt, Sign};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// A signed integer of any size. It is encoded as a CBOR integer when it
+/// fits in one, and as a bignum otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct CounterValue(pub BigInt);
+
+impl From<i64> for CounterValue {
+    fn from(value: i64) -> Self {
+        Self(BigInt::from(value))
+    }
+}
+
+impl<C> Encode<C> for CounterValue {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>, _: &mut C) -> Result<(), Error<W::Error>> {
+        if let Ok(value) = i64::try_from(&self.0) {
+            e.i64(value)?;
+        } else if self.0.sign() == Sign::Minus {
+            // A negative bignum holds -1 - n.
+            let n = -&self.0 - BigInt::from(1);
+            e.tag(Tag::NegBignum)?.bytes(&n.magnitude().to_bytes_be())?;
+        } else {
+            e.tag(Tag::PosBignum)?
+                .bytes(&self.0.magnitude().to_bytes_be())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'b, C> Decode<'b, C> for CounterValue {
+    fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, minicbor::decode::Error> {
+        match d.datatype()? {
+            Type::Tag => {
+                let tag = d.tag()?;
+                let n = BigInt::from_bytes_be(Sign::Plus, d.bytes()?);
+                match tag {
+                    Tag::PosBignum => Ok(Self(n)),
+                    Tag::NegBignum => Ok(Self(-n - BigInt::from(1))),
+                    _ => Err(minicbor::decode::Error::message("Invalid tag.")),
+                }
+            }
+            Type::U8 | Type::U16 | Type::U32 | Type::U64 => Ok(Self(BigInt::from(d.u64()?))),
+            _ => Ok(Self(BigInt::from(d.i64()?))),
+        }
+    }
+}
+
+/// The kind of value held by a key. Keys are plain bytes unless they were
+/// created as counters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum ValueKind {
+    #[n(0)]
+    Bytes,
+
+    /// A counter that cannot go below zero.
+    #[n(1)]
+    Counter,
+
+    /// A counter that can go below zero.
+    #[n(2)]
+    SignedCounter,
+}
+
+impl ValueKind {
+    pub fn is_counter(&self) -> bool {
+        self != &ValueKind::Bytes
+    }
+
+    /// The stored value of a counter: the canonical big-endian bytes of its
+    /// integer, in two's complement for signed counters.
+    pub fn encode_counter(&self, value: &BigInt) -> Vec<u8> {
+        match self {
+            ValueKind::SignedCounter => value.to_signed_bytes_be(),
+            _ => value.magnitude().to_bytes_be(),
+        }
+    }
+
+    pub fn decode_counter(&self, bytes: &[u8]) -> BigInt {
+        match self {
+            ValueKind::SignedCounter => BigInt::from_signed_bytes_be(bytes),
+            _ => BigInt::from_bytes_be(Sign::Plus, bytes),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct CounterCreateArgs {
+    #[n(0)]
+    pub key: Vec<u8>,
+
+    /// Whether the counter can go below zero.
+    #[n(1)]
+    pub signed: bool,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct CounterAddArgs {
+    #[n(0)]
+    pub key: Vec<u8>,
+
+    /// The value to add, which can be negative.
+    #[n(1)]
+    pub delta: CounterValue,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct CounterAddReturns {
+    /// The value of the counter after the add.
+    #[n(0)]
+    pub value: CounterValue,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct QueryKindArgs {
+    #[n(0)]
+    pub key: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct QueryKindReturns {
+    #[n(0)]
+    pub kind: ValueKind,
+}
+
+pub trait KvStoreCounterModuleBackend: Send {
+    fn counter_create(
+        &mut self,
+        sender: &Address,
+        args: CounterCreateArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+
+    fn counter_add(
+        &mut self,
+        sender: &Address,
+        args: CounterAddArgs,
+    ) -> Result<CounterAddReturns, ManyError>;
+
+    fn query_kind(
+        &self,
+        sender: &Address,
+        args: QueryKindArgs,
+    ) -> Result<QueryKindReturns, ManyError>;
+}
+
+impl KvStoreModuleImpl {
+    /// The owner a command on a key acts as, checked like a put.
+    pub(crate) fn command_owner(
+        &self,
+        sender: &Address,
+        key: &[u8],
+        alternative_owner: Option<Address>,
+    ) -> Result<Address, ManyError> {
+        let owner = if let Some(ref alternative_owner) = alternative_owner {
+            self.validate_alternative_owner(
+                sender,
+                alternative_owner,
+                [Role::CanKvStorePut, Role::Owner],
+            )?;
+            *alternative_owner
+        } else {
+            *sender
+        };
+
+        self.verify_acl(&owner, key.to_vec())?;
+        if self.storage.get_policy(key)?.immutable {
+            return Err(error::key_immutable());
+        }
+        self.check_bulk_disable(&owner, key)?;
+        self.check_prefix_move(key)?;
+        Ok(owner)
+    }
+}
+
+impl KvStoreCounterModuleBackend for KvStoreModuleImpl {
+    fn counter_create(
+        &mut self,
+        sender: &Address,
+        args: CounterCreateArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let owner = self.command_owner(sender, &args.key, args.alternative_owner)?;
+        if self.storage.get_metadata(&args.key)?.is_some() {
+            return Err(error::key_exists());
+        }
+        let tenant = self.check_tenant(&owner, &args.key)?;
+
+        let kind = if args.signed {
+            ValueKind::SignedCounter
+        } else {
+            ValueKind::Counter
+        };
+        let meta = KvStoreMetadata {
+            owner: Some(owner),
+            disabled: Some(Either::Left(false)),
+        };
+        let policy = self.default_policy(&owner)?;
+        self.storage
+            .create_counter(&meta, &policy, &args.key, kind)?;
+        self.record_tenant_write(tenant)?;
+        Ok(EmptyReturn)
+    }
+
+    fn counter_add(
+        &mut self,
+        sender: &Address,
+        args: CounterAddArgs,
+    ) -> Result<CounterAddReturns, ManyError> {
+        let owner = self.command_owner(sender, &args.key, args.alternative_owner)?;
+        let tenant = self.check_tenant(&owner, &args.key)?;
+        let value = self.storage.counter_add(&owner, &args.key, &args.delta.0)?;
+        self.record_tenant_write(tenant)?;
+        Ok(CounterAddReturns {
+            value: CounterValue(value),
+        })
+    }
+
+    fn query_kind(
+        &self,
+        sender: &Address,
+        args: QueryKindArgs,
+    ) -> Result<QueryKindReturns, ManyError> {
+        self.verify_read(sender, &args.key)?;
+        if self.storage.get_metadata(&args.key)?.is_none() {
+            return Err(error::key_not_found());
+        }
+        Ok(QueryKindReturns {
+            kind: self.storage.get_kind(&args.key)?,
+        })
+    }
+}
+
+const ENDPOINTS: &[&str] = &[
+    "kvstore.counterCreate",
+    "kvstore.counterAdd",
+    "kvstore.queryKind",
+];
+
+/// A module for counter keys.
+pub struct KvStoreCounterModule<T: KvStoreCounterModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreCounterModuleBackend> KvStoreCounterModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreCounterModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStoreCounterModuleBackend> Debug for KvStoreCounterModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreCounterModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreCounterModuleBackend> ManyModule for KvStoreCounterModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.counterCreate" => decode_args::<CounterCreateArgs>(&message.data).map(|_| ()),
+            "kvstore.counterAdd" => decode_args::<CounterAddArgs>(&message.data).map(|_| ()),
+            "kvstore.queryKind" => decode_args::<QueryKindArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.counterCreate" => decode_args(&message.data)
+                .and_then(|args| backend.counter_create(&from, args))
+                .and_then(encode_returns),
+            "kvstore.counterAdd" => decode_args(&message.data)
+                .and_then(|args| backend.counter_add(&from, args))
+                .and_then(encode_returns),
+            "kvstore.queryKind" => decode_args(&message.data)
+                .and_then(|args| backend.query_kind(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
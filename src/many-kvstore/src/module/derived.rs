@@ -0,0 +1,206 @@
+use crate::error;
+use crate::gateway::sniff_content_type;
+use crate::module::policy::PolicyOverrides;
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::kvstore::PutArgs;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use sha2::Digest;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Maximum length of a declared content type, in bytes.
+pub const MAXIMUM_CONTENT_TYPE_LENGTH: usize = 255;
+
+/// Fields computed by the server from the value of a key when it is put.
+/// Keys put before the `KvStoreDerived` migration, and counters, have none.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct DerivedMetadata {
+    /// The SHA-256 hash of the value.
+    #[n(0)]
+    pub hash: ByteVec,
+
+    /// The size of the value in bytes.
+    #[n(1)]
+    pub length: u64,
+
+    /// The content type declared when putting the value, or else recognized
+    /// from the value and the key.
+    #[n(2)]
+    pub content_type: Option<String>,
+}
+
+impl DerivedMetadata {
+    /// The cost is linear in the size of the value, which is bounded by the
+    /// size of the request.
+    pub fn compute(key: &[u8], value: &[u8], content_type: Option<String>) -> Self {
+        Self {
+            hash: sha2::Sha256::digest(value).to_vec().into(),
+            length: value.len() as u64,
+            content_type: content_type.or_else(|| sniff_content_type(key, value)),
+        }
+    }
+}
+
+/// Check a declared content type, which must look like `type/subtype`.
+fn validate_content_type(content_type: &str) -> Result<(), ManyError> {
+    let valid = content_type.len() <= MAXIMUM_CONTENT_TYPE_LENGTH
+        && content_type.is_ascii()
+        && !content_type.chars().any(|c| c.is_ascii_control())
+        && matches!(content_type.split_once('/'), Some((t, s)) if !t.is_empty() && !s.is_empty());
+    if valid {
+        Ok(())
+    } else {
+        Err(error::invalid_content_type(content_type.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct QueryDerivedArgs {
+    #[n(0)]
+    pub key: ByteVec,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PutWithContentTypeArgs {
+    #[n(0)]
+    pub key: ByteVec,
+
+    #[n(1)]
+    pub value: ByteVec,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+
+    #[n(3)]
+    pub content_type: String,
+}
+
+pub trait KvStoreDerivedModuleBackend: Send {
+    fn query_derived(
+        &self,
+        sender: &Address,
+        args: QueryDerivedArgs,
+    ) -> Result<DerivedMetadata, ManyError>;
+
+    fn put_with_content_type(
+        &mut self,
+        sender: &Address,
+        args: PutWithContentTypeArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+}
+
+impl KvStoreDerivedModuleBackend for KvStoreModuleImpl {
+    fn query_derived(
+        &self,
+        sender: &Address,
+        args: QueryDerivedArgs,
+    ) -> Result<DerivedMetadata, ManyError> {
+        if !self.storage.derived_enabled() {
+            return Err(error::derived_not_enabled());
+        }
+        // The hash tells whether a value is a given one, so it is only given
+        // to the readers of the key.
+        self.verify_read(sender, &args.key)?;
+        self.storage
+            .get_derived(&args.key)?
+            .ok_or_else(error::key_not_found)
+    }
+
+    fn put_with_content_type(
+        &mut self,
+        sender: &Address,
+        args: PutWithContentTypeArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        validate_content_type(&args.content_type)?;
+        // The declared type would be lost.
+        if !self.storage.derived_enabled() {
+            return Err(error::derived_not_enabled());
+        }
+        self.put_value(
+            sender,
+            PutArgs {
+                key: args.key,
+                value: args.value,
+                alternative_owner: args.alternative_owner,
+            },
+            PolicyOverrides::default(),
+            Some(args.content_type),
+            None,
+        )?;
+        Ok(EmptyReturn)
+    }
+}
+
+const ENDPOINTS: &[&str] = &["kvstore.queryDerived", "kvstore.putWithContentType"];
+
+/// A module for the fields derived from the values of keys.
+pub struct KvStoreDerivedModule<T: KvStoreDerivedModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreDerivedModuleBackend> KvStoreDerivedModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreDerivedModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStoreDerivedModuleBackend> Debug for KvStoreDerivedModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreDerivedModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreDerivedModuleBackend> ManyModule for KvStoreDerivedModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.queryDerived" => decode_args::<QueryDerivedArgs>(&message.data).map(|_| ()),
+            "kvstore.putWithContentType" => {
+                let args: PutWithContentTypeArgs = decode_args(&message.data)?;
+                validate_content_type(&args.content_type)
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.queryDerived" => decode_args(&message.data)
+                .and_then(|args| backend.query_derived(&from, args))
+                .and_then(encode_returns),
+            "kvstore.putWithContentType" => decode_args(&message.data)
+                .and_then(|args| backend.put_with_content_type(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -2,6 +2,7 @@ use super::KvStoreModuleImpl;
 use many_error::ManyError;
 use many_identity::Address;
 use many_modules::events;
+use many_storage::event_schema::decode_event;
 use many_types::{CborRange, Timestamp, VecOrSingle};
 
 const MAXIMUM_EVENT_COUNT: usize = 100;
@@ -36,8 +37,7 @@ impl events::EventsModuleBackend for KvStoreModuleImpl {
 
         let iter = Box::new(iter.map(|item| {
             let (_k, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
-            minicbor::decode::<events::EventLog>(v.as_slice())
-                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+            decode_event(v.as_slice())
         }));
 
         let iter = filter_account(iter, filter.account);
@@ -0,0 +1,517 @@
+use crate::error;
+use crate::module::policy::PolicyOverrides;
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::kvstore::PutArgs;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use serde_json::Value;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// The content type of the values under a JSON prefix.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// A rule declaring the values of the keys under a prefix as JSON documents.
+/// Rules are part of the state, set by the initial state or by the identity
+/// of the store.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, Encode, Decode)]
+#[cbor(map)]
+pub struct JsonPrefix {
+    #[n(0)]
+    pub prefix: String,
+
+    /// The maximum size of a document under the prefix, in bytes.
+    #[serde(default)]
+    #[n(1)]
+    pub max_size: Option<u64>,
+}
+
+impl JsonPrefix {
+    fn check_size(&self, size: usize) -> Result<(), ManyError> {
+        match self.max_size {
+            Some(max) if size as u64 > max => Err(error::json_too_large(
+                size.to_string(),
+                max.to_string(),
+                self.prefix.clone(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The reference tokens of a JSON pointer (RFC 6901), unescaped. The empty
+/// pointer is the whole document.
+pub fn parse_pointer(pointer: &str) -> Result<Vec<String>, String> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+    let rest = pointer
+        .strip_prefix('/')
+        .ok_or_else(|| format!("pointer '{}' does not start with '/'", pointer))?;
+    rest.split('/')
+        .map(|token| {
+            let mut unescaped = String::with_capacity(token.len());
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                if c != '~' {
+                    unescaped.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('0') => unescaped.push('~'),
+                    Some('1') => unescaped.push('/'),
+                    _ => return Err(format!("pointer '{}' has an invalid escape", pointer)),
+                }
+            }
+            Ok(unescaped)
+        })
+        .collect()
+}
+
+/// The index of an array designated by a reference token, which has no
+/// leading zeros.
+fn array_index(token: &str) -> Result<usize, String> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    if !valid {
+        return Err(format!("'{}' is not an array index", token));
+    }
+    token
+        .parse()
+        .map_err(|_| format!("'{}' is not an array index", token))
+}
+
+fn resolve<'a>(
+    document: &'a mut Value,
+    tokens: &[String],
+    pointer: &str,
+) -> Result<&'a mut Value, String> {
+    tokens
+        .iter()
+        .try_fold(document, |value, token| match value {
+            Value::Object(map) => map.get_mut(token),
+            Value::Array(array) => array_index(token).ok().and_then(|i| array.get_mut(i)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("path '{}' does not exist", pointer))
+}
+
+enum Operation {
+    Add(String, Value),
+    Remove(String),
+    Replace(String, Value),
+}
+
+impl Operation {
+    fn decode(operation: Value) -> Result<Self, String> {
+        let mut object = match operation {
+            Value::Object(object) => object,
+            _ => return Err("not an object".to_string()),
+        };
+        let path = match object.remove("path") {
+            Some(Value::String(path)) => path,
+            _ => return Err("no path".to_string()),
+        };
+        let op = match object.remove("op") {
+            Some(Value::String(op)) => op,
+            _ => return Err("no op".to_string()),
+        };
+        let mut value = || object.remove("value").ok_or_else(|| "no value".to_string());
+        match op.as_str() {
+            "add" => Ok(Self::Add(path, value()?)),
+            "remove" => Ok(Self::Remove(path)),
+            "replace" => Ok(Self::Replace(path, value()?)),
+            op => Err(format!(
+                "unsupported op '{}', only add, remove and replace are",
+                op
+            )),
+        }
+    }
+
+    fn apply(self, document: &mut Value) -> Result<(), String> {
+        match self {
+            Self::Add(path, value) => {
+                let tokens = parse_pointer(&path)?;
+                let (last, parent) = match tokens.split_last() {
+                    Some(split) => split,
+                    None => {
+                        *document = value;
+                        return Ok(());
+                    }
+                };
+                match resolve(document, parent, &path)? {
+                    Value::Object(map) => {
+                        map.insert(last.clone(), value);
+                    }
+                    Value::Array(array) => {
+                        let index = if last == "-" {
+                            array.len()
+                        } else {
+                            array_index(last)?
+                        };
+                        if index > array.len() {
+                            return Err(format!("path '{}' is past the end of its array", path));
+                        }
+                        array.insert(index, value);
+                    }
+                    _ => return Err(format!("path '{}' is not in an object or array", path)),
+                }
+            }
+            Self::Remove(path) => {
+                let tokens = parse_pointer(&path)?;
+                let (last, parent) = tokens
+                    .split_last()
+                    .ok_or_else(|| "the whole document cannot be removed".to_string())?;
+                let removed = match resolve(document, parent, &path)? {
+                    Value::Object(map) => map.remove(last).is_some(),
+                    Value::Array(array) => match array_index(last)? {
+                        index if index < array.len() => {
+                            array.remove(index);
+                            true
+                        }
+                        _ => false,
+                    },
+                    _ => false,
+                };
+                if !removed {
+                    return Err(format!("path '{}' does not exist", path));
+                }
+            }
+            Self::Replace(path, value) => {
+                let tokens = parse_pointer(&path)?;
+                *resolve(document, &tokens, &path)? = value;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply a JSON Patch (RFC 6902) to a document. Only the add, remove and
+/// replace operations are supported. The operations are applied in order,
+/// and the document is unchanged if any of them fails.
+pub fn apply_patch(document: &Value, patch: &str) -> Result<Value, ManyError> {
+    let operations: Vec<Value> = serde_json::from_str(patch)
+        .map_err(|e| error::invalid_patch(format!("not an array of operations: {}", e)))?;
+    let mut patched = document.clone();
+    for (i, operation) in operations.into_iter().enumerate() {
+        Operation::decode(operation)
+            .and_then(|operation| operation.apply(&mut patched))
+            .map_err(|e| error::invalid_patch(format!("operation {}: {}", i, e)))?;
+    }
+    Ok(patched)
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(array) => {
+            out.push('[');
+            for (i, value) in array.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// The canonical serialization of a document, written by every node after a
+/// patch: no whitespace, and the members of objects sorted by the bytes of
+/// their name.
+pub fn canonical(value: &Value) -> String {
+    let mut out = String::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn parse_document(value: &[u8]) -> Result<Value, ManyError> {
+    serde_json::from_slice(value).map_err(|e| error::malformed_json(e.to_string()))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PatchArgs {
+    #[n(0)]
+    pub key: ByteVec,
+
+    /// The JSON Patch to apply, as JSON text.
+    #[n(1)]
+    pub patch: String,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+
+    /// Only patch the document if it is at this version.
+    #[n(3)]
+    pub expected_version: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PatchReturns {
+    /// The version of the patched document.
+    #[n(0)]
+    pub version: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct JsonVersionArgs {
+    #[n(0)]
+    pub key: ByteVec,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct JsonVersionReturns {
+    /// The number of times the document was put or patched, 0 if it never
+    /// was.
+    #[n(0)]
+    pub version: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct JsonPrefixesArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct JsonPrefixesReturns {
+    #[n(0)]
+    pub prefixes: Vec<JsonPrefix>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetJsonPrefixesArgs {
+    /// The rules replacing the current ones.
+    #[n(0)]
+    pub prefixes: Vec<JsonPrefix>,
+}
+
+pub trait KvStoreJsonPatchModuleBackend: Send {
+    fn patch(&mut self, sender: &Address, args: PatchArgs) -> Result<PatchReturns, ManyError>;
+
+    fn json_version(
+        &self,
+        sender: &Address,
+        args: JsonVersionArgs,
+    ) -> Result<JsonVersionReturns, ManyError>;
+
+    fn json_prefixes(
+        &self,
+        sender: &Address,
+        args: JsonPrefixesArgs,
+    ) -> Result<JsonPrefixesReturns, ManyError>;
+
+    fn set_json_prefixes(
+        &mut self,
+        sender: &Address,
+        args: SetJsonPrefixesArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+}
+
+impl KvStoreModuleImpl {
+    /// The longest JSON prefix of a key, if any.
+    pub(crate) fn json_prefix(&self, key: &[u8]) -> Result<Option<JsonPrefix>, ManyError> {
+        Ok(self
+            .storage
+            .get_json_prefixes()?
+            .into_iter()
+            .filter(|rule| key.starts_with(rule.prefix.as_bytes()))
+            .max_by_key(|rule| rule.prefix.len()))
+    }
+
+    /// Check a value put under a JSON prefix, returning the version it will
+    /// have. Values of other keys have no version.
+    pub(crate) fn check_json(&self, key: &[u8], value: &[u8]) -> Result<Option<u64>, ManyError> {
+        match self.json_prefix(key)? {
+            Some(rule) => {
+                parse_document(value)?;
+                rule.check_size(value.len())?;
+                Ok(Some(self.storage.get_json_version(key)? + 1))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl KvStoreJsonPatchModuleBackend for KvStoreModuleImpl {
+    fn patch(&mut self, sender: &Address, args: PatchArgs) -> Result<PatchReturns, ManyError> {
+        if self.json_prefix(&args.key)?.is_none() {
+            return Err(error::not_a_json_key());
+        }
+        self.verify_read(sender, &args.key)?;
+        let version = self.storage.get_json_version(&args.key)?;
+        if let Some(expected) = args.expected_version {
+            if expected != version {
+                return Err(error::version_conflict(
+                    expected.to_string(),
+                    version.to_string(),
+                ));
+            }
+        }
+
+        let value = self
+            .storage
+            .get(&args.key)?
+            .ok_or_else(error::key_not_found)?;
+        let patched = apply_patch(&parse_document(&value)?, &args.patch)?;
+
+        // A patch changes the value, not who can read it.
+        let policy = self.storage.get_policy(&args.key)?;
+        self.put_value(
+            sender,
+            PutArgs {
+                key: args.key,
+                value: canonical(&patched).into_bytes().into(),
+                alternative_owner: args.alternative_owner,
+            },
+            PolicyOverrides {
+                readers: Some(policy.readers),
+                public: Some(policy.public),
+                immutable: Some(policy.immutable),
+            },
+            Some(JSON_CONTENT_TYPE.to_string()),
+            None,
+        )?;
+        Ok(PatchReturns {
+            version: version + 1,
+        })
+    }
+
+    fn json_version(
+        &self,
+        sender: &Address,
+        args: JsonVersionArgs,
+    ) -> Result<JsonVersionReturns, ManyError> {
+        if self.json_prefix(&args.key)?.is_none() {
+            return Err(error::not_a_json_key());
+        }
+        self.verify_read(sender, &args.key)?;
+        Ok(JsonVersionReturns {
+            version: self.storage.get_json_version(&args.key)?,
+        })
+    }
+
+    fn json_prefixes(
+        &self,
+        _sender: &Address,
+        _args: JsonPrefixesArgs,
+    ) -> Result<JsonPrefixesReturns, ManyError> {
+        Ok(JsonPrefixesReturns {
+            prefixes: self.storage.get_json_prefixes()?,
+        })
+    }
+
+    fn set_json_prefixes(
+        &mut self,
+        sender: &Address,
+        args: SetJsonPrefixesArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        if sender != &self.storage.identity() {
+            return Err(error::json_prefixes_denied());
+        }
+        self.storage.set_json_prefixes(&args.prefixes)?;
+        Ok(EmptyReturn)
+    }
+}
+
+const ENDPOINTS: &[&str] = &[
+    "kvstore.patch",
+    "kvstore.jsonVersion",
+    "kvstore.jsonPrefixes",
+    "kvstore.setJsonPrefixes",
+];
+
+/// A module patching the JSON documents of the keys under JSON prefixes.
+pub struct KvStoreJsonPatchModule<T: KvStoreJsonPatchModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreJsonPatchModuleBackend> KvStoreJsonPatchModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreJsonPatchModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStoreJsonPatchModuleBackend> Debug for KvStoreJsonPatchModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreJsonPatchModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreJsonPatchModuleBackend> ManyModule for KvStoreJsonPatchModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.patch" => decode_args::<PatchArgs>(&message.data).map(|_| ()),
+            "kvstore.jsonVersion" => decode_args::<JsonVersionArgs>(&message.data).map(|_| ()),
+            "kvstore.jsonPrefixes" => decode_args::<JsonPrefixesArgs>(&message.data).map(|_| ()),
+            "kvstore.setJsonPrefixes" => {
+                decode_args::<SetJsonPrefixesArgs>(&message.data).map(|_| ())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.patch" => decode_args(&message.data)
+                .and_then(|args| backend.patch(&from, args))
+                .and_then(encode_returns),
+            "kvstore.jsonVersion" => decode_args(&message.data)
+                .and_then(|args| backend.json_version(&from, args))
+                .and_then(encode_returns),
+            "kvstore.jsonPrefixes" => decode_args(&message.data)
+                .and_then(|args| backend.json_prefixes(&from, args))
+                .and_then(encode_returns),
+            "kvstore.setJsonPrefixes" => decode_args(&message.data)
+                .and_then(|args| backend.set_json_prefixes(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
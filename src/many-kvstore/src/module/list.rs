@@ -0,0 +1,191 @@
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::Either;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Number of keys scanned by a list call if no count is given.
+pub const DEFAULT_LIST_COUNT: u64 = 100;
+
+/// Maximum number of keys scanned by a single list call.
+pub const MAXIMUM_LIST_COUNT: u64 = 1000;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ListArgs {
+    /// Only list keys starting with this prefix. All keys are listed if it is
+    /// empty.
+    #[n(0)]
+    pub prefix: ByteVec,
+
+    /// Start after this key, which is the `next` key of the previous page.
+    #[n(1)]
+    pub after: Option<ByteVec>,
+
+    #[n(2)]
+    pub count: Option<u64>,
+
+    /// Only list keys whose value has this SHA-256 hash, e.g. to find
+    /// duplicates.
+    #[n(3)]
+    pub hash: Option<ByteVec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ListEntry {
+    #[n(0)]
+    pub key: ByteVec,
+
+    /// The size of the value in bytes.
+    #[n(1)]
+    pub size: u64,
+
+    #[n(2)]
+    pub owner: Option<Address>,
+
+    #[n(3)]
+    pub disabled: bool,
+
+    /// The SHA-256 hash of the value, if it was computed when it was put.
+    #[n(4)]
+    pub hash: Option<ByteVec>,
+
+    #[n(5)]
+    pub content_type: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ListReturns {
+    /// The keys the sender can read.
+    #[n(0)]
+    pub entries: Vec<ListEntry>,
+
+    /// The key to continue after, if there might be more keys. A page can
+    /// have fewer entries than requested (even none) and still have a next
+    /// key, as keys the sender cannot read are skipped.
+    #[n(1)]
+    pub next: Option<ByteVec>,
+}
+
+pub trait KvStoreListModuleBackend: Send {
+    fn list(&self, sender: &Address, args: ListArgs) -> Result<ListReturns, ManyError>;
+}
+
+impl KvStoreListModuleBackend for KvStoreModuleImpl {
+    fn list(&self, sender: &Address, args: ListArgs) -> Result<ListReturns, ManyError> {
+        let count = args
+            .count
+            .map_or(DEFAULT_LIST_COUNT, |c| c.min(MAXIMUM_LIST_COUNT)) as usize;
+        let keys = self.storage.list(
+            &args.prefix,
+            args.after.as_ref().map(|a| a.as_slice()),
+            count,
+        )?;
+
+        let next = if keys.len() == count {
+            keys.last().map(|(k, _)| k.clone().into())
+        } else {
+            None
+        };
+
+        let mut entries = vec![];
+        for (key, meta) in keys {
+            if self.verify_read(sender, &key).is_err() {
+                continue;
+            }
+            let derived = self.storage.get_derived(&key)?;
+            if let Some(hash) = &args.hash {
+                if derived.as_ref().map(|d| &d.hash) != Some(hash) {
+                    continue;
+                }
+            }
+            let (hash, content_type) = match derived {
+                Some(derived) => (Some(derived.hash), derived.content_type),
+                None => (None, None),
+            };
+            entries.push(ListEntry {
+                size: self.storage.value_size(&key)?.unwrap_or_default(),
+                owner: meta.owner,
+                disabled: !matches!(meta.disabled, None | Some(Either::Left(false)))
+                    || self
+                        .storage
+                        .bulk_disable_of(&key, meta.owner.as_ref())?
+                        .is_some(),
+                hash,
+                content_type,
+                key: key.into(),
+            });
+        }
+
+        Ok(ListReturns { entries, next })
+    }
+}
+
+/// A module listing the keys of the store.
+pub struct KvStoreListModule<T: KvStoreListModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreListModuleBackend> KvStoreListModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreListModule".to_string(),
+                attribute: None,
+                endpoints: vec!["kvstore.list".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: KvStoreListModuleBackend> Debug for KvStoreListModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreListModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreListModuleBackend> ManyModule for KvStoreListModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.list" => minicbor::decode::<ListArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.list" => minicbor::decode(&message.data)
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+                .and_then(|args| backend.list(&from, args))
+                .and_then(|returns| {
+                    minicbor::to_vec(returns)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))
+                }),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
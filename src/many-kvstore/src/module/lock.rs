@@ -0,0 +1,369 @@
+use crate::error;
+use crate::module::policy::PolicyOverrides;
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::kvstore::PutArgs;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// Maximum duration of a lease, in seconds. Holders renew their lease to
+/// keep a lock for longer.
+pub const MAXIMUM_LEASE_SECS: u64 = 24 * 60 * 60;
+
+/// The lock of a key. The token is increased every time the lock is
+/// acquired, so a holder which lost its lock cannot put with its token
+/// anymore.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LockState {
+    #[n(0)]
+    pub holder: Option<Address>,
+
+    #[n(1)]
+    pub token: u64,
+
+    /// The block time the lease ends at, in seconds since the epoch.
+    #[n(2)]
+    pub expires: u64,
+
+    /// Whether the key can only be put with the token of its lock.
+    #[n(3)]
+    pub required: bool,
+}
+
+impl LockState {
+    /// A lock is held until the first block at or after its expiry.
+    pub fn is_held(&self, now: u64) -> bool {
+        self.holder.is_some() && now < self.expires
+    }
+
+    fn is_held_by(&self, holder: &Address, token: u64, now: u64) -> bool {
+        self.is_held(now) && self.holder.as_ref() == Some(holder) && self.token == token
+    }
+}
+
+fn validate_lease(lease_secs: u64) -> Result<(), ManyError> {
+    if (1..=MAXIMUM_LEASE_SECS).contains(&lease_secs) {
+        Ok(())
+    } else {
+        Err(error::invalid_lease(MAXIMUM_LEASE_SECS.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LockAcquireArgs {
+    #[n(0)]
+    pub key: ByteVec,
+
+    #[n(1)]
+    pub lease_secs: u64,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LockRenewArgs {
+    #[n(0)]
+    pub key: ByteVec,
+
+    #[n(1)]
+    pub token: u64,
+
+    #[n(2)]
+    pub lease_secs: u64,
+
+    #[n(3)]
+    pub alternative_owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LockReleaseArgs {
+    #[n(0)]
+    pub key: ByteVec,
+
+    #[n(1)]
+    pub token: u64,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LockReturns {
+    /// The fencing token to put the key with.
+    #[n(0)]
+    pub token: u64,
+
+    #[n(1)]
+    pub expires: Timestamp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PutWithLockArgs {
+    #[n(0)]
+    pub key: ByteVec,
+
+    #[n(1)]
+    pub value: ByteVec,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+
+    /// The token of the lock held on the key.
+    #[n(3)]
+    pub token: Option<u64>,
+
+    /// Whether the key can only be put with the token of its lock from now
+    /// on. This can only be set when creating the key.
+    #[n(4)]
+    pub lock_required: Option<bool>,
+}
+
+pub trait KvStoreLockModuleBackend: Send {
+    fn lock_acquire(
+        &mut self,
+        sender: &Address,
+        args: LockAcquireArgs,
+    ) -> Result<LockReturns, ManyError>;
+
+    fn lock_renew(
+        &mut self,
+        sender: &Address,
+        args: LockRenewArgs,
+    ) -> Result<LockReturns, ManyError>;
+
+    fn lock_release(
+        &mut self,
+        sender: &Address,
+        args: LockReleaseArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+
+    fn put_with_lock(
+        &mut self,
+        sender: &Address,
+        args: PutWithLockArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+}
+
+impl KvStoreModuleImpl {
+    /// The block time, in seconds. Leases expire by block time so every
+    /// node agrees on whether a lock is held.
+    pub(crate) fn now_secs(&self) -> Result<u64, ManyError> {
+        Ok(self
+            .storage
+            .now()
+            .as_system_time()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .as_secs())
+    }
+
+    /// Check that `owner` can put `key` given its lock. Keys which do not
+    /// require a lock can be put without a token, even when locked.
+    pub(crate) fn check_lock(
+        &self,
+        owner: &Address,
+        key: &[u8],
+        token: Option<u64>,
+    ) -> Result<(), ManyError> {
+        let lock = self.storage.get_lock(key)?.unwrap_or_default();
+        match token {
+            Some(token) if !lock.is_held_by(owner, token, self.now_secs()?) => {
+                Err(error::stale_lock_token())
+            }
+            None if lock.required => Err(error::lock_token_required()),
+            _ => Ok(()),
+        }
+    }
+
+    fn lock_returns(lock: &LockState) -> Result<LockReturns, ManyError> {
+        Ok(LockReturns {
+            token: lock.token,
+            expires: Timestamp::new(lock.expires)?,
+        })
+    }
+}
+
+impl KvStoreLockModuleBackend for KvStoreModuleImpl {
+    fn lock_acquire(
+        &mut self,
+        sender: &Address,
+        args: LockAcquireArgs,
+    ) -> Result<LockReturns, ManyError> {
+        validate_lease(args.lease_secs)?;
+        let owner = self.command_owner(sender, &args.key, args.alternative_owner)?;
+        let now = self.now_secs()?;
+        let mut lock = self.storage.get_lock(&args.key)?.unwrap_or_default();
+        if lock.is_held(now) {
+            return Err(error::lock_held(lock.expires.to_string()));
+        }
+
+        lock.holder = Some(owner);
+        lock.token += 1;
+        lock.expires = now + args.lease_secs;
+        self.storage.set_lock(&args.key, &lock)?;
+        Self::lock_returns(&lock)
+    }
+
+    fn lock_renew(
+        &mut self,
+        sender: &Address,
+        args: LockRenewArgs,
+    ) -> Result<LockReturns, ManyError> {
+        validate_lease(args.lease_secs)?;
+        let owner = self.command_owner(sender, &args.key, args.alternative_owner)?;
+        let now = self.now_secs()?;
+        let mut lock = self.storage.get_lock(&args.key)?.unwrap_or_default();
+        if !lock.is_held_by(&owner, args.token, now) {
+            return Err(error::stale_lock_token());
+        }
+
+        lock.expires = now + args.lease_secs;
+        self.storage.set_lock(&args.key, &lock)?;
+        Self::lock_returns(&lock)
+    }
+
+    fn lock_release(
+        &mut self,
+        sender: &Address,
+        args: LockReleaseArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let owner = self.command_owner(sender, &args.key, args.alternative_owner)?;
+        let now = self.now_secs()?;
+        let mut lock = self.storage.get_lock(&args.key)?.unwrap_or_default();
+        if !lock.is_held_by(&owner, args.token, now) {
+            return Err(error::stale_lock_token());
+        }
+
+        // The token is kept, so the next holder gets a greater one.
+        lock.holder = None;
+        lock.expires = 0;
+        self.storage.set_lock(&args.key, &lock)?;
+        Ok(EmptyReturn)
+    }
+
+    fn put_with_lock(
+        &mut self,
+        sender: &Address,
+        args: PutWithLockArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let key = args.key.to_vec();
+        if args.lock_required.is_some() && self.storage.get_metadata(&key)?.is_some() {
+            return Err(error::key_exists());
+        }
+
+        self.put_value(
+            sender,
+            PutArgs {
+                key: args.key,
+                value: args.value,
+                alternative_owner: args.alternative_owner,
+            },
+            PolicyOverrides::default(),
+            None,
+            args.token,
+        )?;
+
+        if args.lock_required == Some(true) {
+            let mut lock = self.storage.get_lock(&key)?.unwrap_or_default();
+            lock.required = true;
+            self.storage.set_lock(&key, &lock)?;
+        }
+        Ok(EmptyReturn)
+    }
+}
+
+const ENDPOINTS: &[&str] = &[
+    "kvstore.lockAcquire",
+    "kvstore.lockRenew",
+    "kvstore.lockRelease",
+    "kvstore.putWithLock",
+];
+
+/// A module for the lease-based locks on keys.
+pub struct KvStoreLockModule<T: KvStoreLockModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreLockModuleBackend> KvStoreLockModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreLockModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStoreLockModuleBackend> Debug for KvStoreLockModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreLockModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreLockModuleBackend> ManyModule for KvStoreLockModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.lockAcquire" => {
+                let args: LockAcquireArgs = decode_args(&message.data)?;
+                validate_lease(args.lease_secs)
+            }
+            "kvstore.lockRenew" => {
+                let args: LockRenewArgs = decode_args(&message.data)?;
+                validate_lease(args.lease_secs)
+            }
+            "kvstore.lockRelease" => decode_args::<LockReleaseArgs>(&message.data).map(|_| ()),
+            "kvstore.putWithLock" => decode_args::<PutWithLockArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.lockAcquire" => decode_args(&message.data)
+                .and_then(|args| backend.lock_acquire(&from, args))
+                .and_then(encode_returns),
+            "kvstore.lockRenew" => decode_args(&message.data)
+                .and_then(|args| backend.lock_renew(&from, args))
+                .and_then(encode_returns),
+            "kvstore.lockRelease" => decode_args(&message.data)
+                .and_then(|args| backend.lock_release(&from, args))
+                .and_then(encode_returns),
+            "kvstore.putWithLock" => decode_args(&message.data)
+                .and_then(|args| backend.put_with_lock(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,329 @@
+use crate::error;
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::account::Role;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use sha2::Digest;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Number of keys the pending prefix moves rename at the beginning of each
+/// block. This must be the same on every node.
+pub const PREFIX_MOVE_KEYS_PER_BLOCK: usize = 1000;
+
+/// Number of keys read at once when checking a move before accepting it.
+const SCAN_PAGE_SIZE: usize = 1000;
+
+/// The rename of all the keys under a prefix to another prefix, keeping
+/// their metadata. Its keys are moved in chunks over the next blocks, like
+/// bulk disables, and each key reads at its old location until its chunk is
+/// processed, then at its new one.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PrefixMove {
+    #[n(0)]
+    pub id: u64,
+
+    #[n(1)]
+    pub source: ByteVec,
+
+    #[n(2)]
+    pub destination: ByteVec,
+
+    #[n(3)]
+    pub owner: Address,
+
+    /// The number of keys to move.
+    #[n(4)]
+    pub count: u64,
+
+    /// The last key moved, at its old location.
+    #[n(5)]
+    pub processed: Option<ByteVec>,
+
+    /// The number of keys moved so far.
+    #[n(6)]
+    pub moved: u64,
+}
+
+impl PrefixMove {
+    /// The new location of a key under the source prefix.
+    pub fn destination_of(&self, key: &[u8]) -> Vec<u8> {
+        [self.destination.as_slice(), &key[self.source.len()..]].concat()
+    }
+
+    /// Whether a key is under the source or the destination prefix.
+    pub fn covers(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.source) || key.starts_with(&self.destination)
+    }
+
+    /// Whether the keys under a prefix could be under the source or the
+    /// destination prefix.
+    pub fn overlaps(&self, prefix: &[u8]) -> bool {
+        [self.source.as_slice(), self.destination.as_slice()]
+            .iter()
+            .any(|p| p.starts_with(prefix) || prefix.starts_with(p))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct MovePrefixArgs {
+    /// Move the keys starting with this prefix.
+    #[n(0)]
+    pub source: ByteVec,
+
+    /// Replace the source prefix of the keys with this one.
+    #[n(1)]
+    pub destination: ByteVec,
+
+    #[n(2)]
+    pub alternative_owner: Option<Address>,
+
+    /// The confirmation returned by a dry run of the same move. Without it,
+    /// the move is only checked, and nothing changes.
+    #[n(3)]
+    pub confirmation: Option<ByteVec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct MovePrefixReturns {
+    /// The number of keys to move.
+    #[n(0)]
+    pub count: u64,
+
+    /// The confirmation to execute the move with.
+    #[n(1)]
+    pub confirmation: ByteVec,
+
+    /// The ID of the move, to follow its progress, if it was executed.
+    #[n(2)]
+    pub id: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PendingMovesArgs {
+    /// Only return the prefix moves of this owner.
+    #[n(0)]
+    pub owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PendingMovesReturns {
+    /// The prefix moves whose keys are not all moved yet, oldest first.
+    #[n(0)]
+    pub pending: Vec<PrefixMove>,
+}
+
+pub trait KvStoreMovePrefixModuleBackend: Send {
+    fn move_prefix(
+        &mut self,
+        sender: &Address,
+        args: MovePrefixArgs,
+    ) -> Result<MovePrefixReturns, ManyError>;
+
+    fn pending_moves(
+        &self,
+        sender: &Address,
+        args: PendingMovesArgs,
+    ) -> Result<PendingMovesReturns, ManyError>;
+}
+
+/// The confirmation of a move, which changes if the number of keys to move
+/// changes between the dry run and the execution.
+fn confirmation(owner: &Address, source: &[u8], destination: &[u8], count: u64) -> Vec<u8> {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(owner.to_vec());
+    for prefix in [source, destination] {
+        hasher.update((prefix.len() as u64).to_be_bytes());
+        hasher.update(prefix);
+    }
+    hasher.update(count.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+impl KvStoreModuleImpl {
+    /// Refuse changing a key under a prefix being moved, so every key is at
+    /// exactly one location until the move is done.
+    pub(crate) fn check_prefix_move(&self, key: &[u8]) -> Result<(), ManyError> {
+        match self.storage.prefix_move_of(key)? {
+            Some(prefix_move) => Err(error::prefix_move_pending(prefix_move.id.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that every key under the source prefix can be moved by its
+    /// owner, and that their new locations are free, returning the number
+    /// of keys.
+    fn check_move(
+        &self,
+        owner: &Address,
+        source: &[u8],
+        destination: &[u8],
+    ) -> Result<u64, ManyError> {
+        if source.starts_with(destination) || destination.starts_with(source) {
+            return Err(error::overlapping_prefixes());
+        }
+        if let Some(pending) = self
+            .storage
+            .pending_prefix_moves()?
+            .into_iter()
+            .find(|m| m.overlaps(source) || m.overlaps(destination))
+        {
+            return Err(error::prefix_move_pending(pending.id.to_string()));
+        }
+
+        let mut count = 0;
+        let mut after: Option<Vec<u8>> = None;
+        loop {
+            let keys = self
+                .storage
+                .list(source, after.as_deref(), SCAN_PAGE_SIZE)?;
+            let done = keys.len() < SCAN_PAGE_SIZE;
+            for (key, meta) in keys {
+                if meta.owner.as_ref() != Some(owner) {
+                    return Err(error::permission_denied());
+                }
+                if self.storage.get_policy(&key)?.immutable {
+                    return Err(error::key_immutable());
+                }
+                self.check_bulk_disable(owner, &key)?;
+
+                let new_key = [destination, &key[source.len()..]].concat();
+                self.verify_acl(owner, new_key.clone())?;
+                if self.storage.get_metadata(&new_key)?.is_some() {
+                    return Err(error::key_exists());
+                }
+                count += 1;
+                after = Some(key);
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl KvStoreMovePrefixModuleBackend for KvStoreModuleImpl {
+    fn move_prefix(
+        &mut self,
+        sender: &Address,
+        args: MovePrefixArgs,
+    ) -> Result<MovePrefixReturns, ManyError> {
+        let owner = if let Some(ref alternative_owner) = args.alternative_owner {
+            self.validate_alternative_owner(
+                sender,
+                alternative_owner,
+                [Role::CanKvStorePut, Role::Owner],
+            )?;
+            *alternative_owner
+        } else {
+            *sender
+        };
+
+        let count = self.check_move(&owner, &args.source, &args.destination)?;
+        let expected = confirmation(&owner, &args.source, &args.destination, count);
+        let id = match args.confirmation {
+            None => None,
+            Some(given) if given.as_slice() == expected.as_slice() => {
+                Some(self.storage.add_prefix_move(
+                    owner,
+                    args.source.into(),
+                    args.destination.into(),
+                    count,
+                )?)
+            }
+            Some(_) => return Err(error::invalid_move_confirmation()),
+        };
+        Ok(MovePrefixReturns {
+            count,
+            confirmation: expected.into(),
+            id,
+        })
+    }
+
+    fn pending_moves(
+        &self,
+        _sender: &Address,
+        args: PendingMovesArgs,
+    ) -> Result<PendingMovesReturns, ManyError> {
+        let pending = self
+            .storage
+            .pending_prefix_moves()?
+            .into_iter()
+            .filter(|m| args.owner.map_or(true, |owner| m.owner == owner))
+            .collect();
+        Ok(PendingMovesReturns { pending })
+    }
+}
+
+const ENDPOINTS: &[&str] = &["kvstore.movePrefix", "kvstore.pendingMoves"];
+
+/// A module to move all the keys under a prefix to another prefix.
+pub struct KvStoreMovePrefixModule<T: KvStoreMovePrefixModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreMovePrefixModuleBackend> KvStoreMovePrefixModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreMovePrefixModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStoreMovePrefixModuleBackend> Debug for KvStoreMovePrefixModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreMovePrefixModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreMovePrefixModuleBackend> ManyModule for KvStoreMovePrefixModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.movePrefix" => decode_args::<MovePrefixArgs>(&message.data).map(|_| ()),
+            "kvstore.pendingMoves" => decode_args::<PendingMovesArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.movePrefix" => decode_args(&message.data)
+                .and_then(|args| backend.move_prefix(&from, args))
+                .and_then(encode_returns),
+            "kvstore.pendingMoves" => decode_args(&message.data)
+                .and_then(|args| backend.pending_moves(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,268 @@
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::{ManyError, ManyErrorCode};
+use many_identity::Address;
+use many_modules::account::features::{Feature, FeatureId, FeatureInfo, TryCreateFeature};
+use many_modules::account::{self, Role};
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::cbor::CborAny;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Who can read a key, and whether it can be changed after being put.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct AccessPolicy {
+    /// Identities that can read the key when it is private, besides its
+    /// owner and the identities with a role in the owner account.
+    #[n(0)]
+    pub readers: BTreeSet<Address>,
+
+    #[n(1)]
+    pub public: bool,
+
+    /// An immutable key cannot be put again, nor disabled.
+    #[n(2)]
+    pub immutable: bool,
+}
+
+/// The policy of keys put before policies existed.
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        Self {
+            readers: BTreeSet::new(),
+            public: true,
+            immutable: false,
+        }
+    }
+}
+
+impl AccessPolicy {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Apply the values given to a single put, which always win over the
+    /// default policy.
+    pub fn with_overrides(self, overrides: PolicyOverrides) -> Self {
+        Self {
+            readers: overrides.readers.unwrap_or(self.readers),
+            public: overrides.public.unwrap_or(self.public),
+            immutable: overrides.immutable.unwrap_or(self.immutable),
+        }
+    }
+}
+
+/// The policy values given to a single put. Missing values are inherited
+/// from the default policy of the owner.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PolicyOverrides {
+    #[n(0)]
+    pub readers: Option<BTreeSet<Address>>,
+
+    #[n(1)]
+    pub public: Option<bool>,
+
+    #[n(2)]
+    pub immutable: Option<bool>,
+}
+
+/// An account feature holding the default policy of the keys owned by the
+/// account.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountKvStoreDefaults {
+    pub policy: AccessPolicy,
+}
+
+impl TryCreateFeature for AccountKvStoreDefaults {
+    // Feature IDs up to 2 are defined by many-modules. This one is local to
+    // the kvstore.
+    const ID: FeatureId = 1000;
+
+    fn try_create(f: &Feature) -> Result<Self, ManyError> {
+        match f.arguments().as_slice() {
+            [] => Ok(Self::default()),
+            [CborAny::Bytes(bytes)] => Ok(Self {
+                policy: minicbor::decode(bytes)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))?,
+            }),
+            _ => Err(ManyError::invalid_attribute_arguments()),
+        }
+    }
+}
+
+impl FeatureInfo for AccountKvStoreDefaults {
+    fn as_feature(&self) -> Feature {
+        Feature::new(
+            Self::ID,
+            vec![CborAny::Bytes(
+                minicbor::to_vec(&self.policy).expect("Could not encode the access policy"),
+            )],
+        )
+    }
+
+    fn roles() -> BTreeSet<Role> {
+        BTreeSet::new()
+    }
+}
+
+/// Returns the default policy of an account, or the default policy if the
+/// account does not have the feature.
+pub(crate) fn account_default_policy(
+    account: &account::Account,
+) -> Result<AccessPolicy, ManyError> {
+    match account.features().get::<AccountKvStoreDefaults>() {
+        Ok(defaults) => Ok(defaults.policy),
+        Err(e) if e.code() == ManyErrorCode::AttributeNotFound => Ok(AccessPolicy::default()),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct EffectivePolicyArgs {
+    /// The owner of the put, or the sender if missing.
+    #[n(0)]
+    pub owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct EffectivePolicyReturns {
+    #[n(0)]
+    pub policy: AccessPolicy,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetDefaultPolicyArgs {
+    #[n(0)]
+    pub account: Address,
+
+    #[n(1)]
+    pub policy: AccessPolicy,
+}
+
+pub trait KvStorePolicyModuleBackend: Send {
+    fn effective_policy(
+        &self,
+        sender: &Address,
+        args: EffectivePolicyArgs,
+    ) -> Result<EffectivePolicyReturns, ManyError>;
+
+    fn set_default_policy(
+        &mut self,
+        sender: &Address,
+        args: SetDefaultPolicyArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+}
+
+impl KvStorePolicyModuleBackend for KvStoreModuleImpl {
+    fn effective_policy(
+        &self,
+        sender: &Address,
+        args: EffectivePolicyArgs,
+    ) -> Result<EffectivePolicyReturns, ManyError> {
+        let owner = args.owner.unwrap_or(*sender);
+        Ok(EffectivePolicyReturns {
+            policy: self.default_policy(&owner)?,
+        })
+    }
+
+    fn set_default_policy(
+        &mut self,
+        sender: &Address,
+        args: SetDefaultPolicyArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let account = self
+            .storage
+            .get_account(&args.account)
+            .ok_or_else(|| account::errors::unknown_account(args.account))?;
+
+        account.needs_role(sender, [Role::Owner])?;
+        self.storage
+            .set_default_policy(account, &args.account, args.policy)?;
+        Ok(EmptyReturn)
+    }
+}
+
+impl KvStoreModuleImpl {
+    /// The policy a put owned by `owner` inherits. Only accounts have a
+    /// default policy, other identities use the default one.
+    pub fn default_policy(&self, owner: &Address) -> Result<AccessPolicy, ManyError> {
+        match self.storage.get_account(owner) {
+            Some(account) => account_default_policy(&account),
+            None => Ok(AccessPolicy::default()),
+        }
+    }
+}
+
+const ENDPOINTS: &[&str] = &["kvstore.effectivePolicy", "kvstore.setDefaultPolicy"];
+
+/// A module for the default access policies of accounts.
+pub struct KvStorePolicyModule<T: KvStorePolicyModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStorePolicyModuleBackend> KvStorePolicyModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStorePolicyModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStorePolicyModuleBackend> Debug for KvStorePolicyModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStorePolicyModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStorePolicyModuleBackend> ManyModule for KvStorePolicyModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.effectivePolicy" => {
+                decode_args::<EffectivePolicyArgs>(&message.data).map(|_| ())
+            }
+            "kvstore.setDefaultPolicy" => {
+                decode_args::<SetDefaultPolicyArgs>(&message.data).map(|_| ())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.effectivePolicy" => decode_args(&message.data)
+                .and_then(|args| backend.effective_policy(&from, args))
+                .and_then(encode_returns),
+            "kvstore.setDefaultPolicy" => decode_args(&message.data)
+                .and_then(|args| backend.set_default_policy(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,306 @@
+use crate::error;
+use crate::module::capability;
+use crate::module::derived::PutWithContentTypeArgs;
+use crate::module::json_patch::PatchArgs;
+use crate::module::lock::PutWithLockArgs;
+use crate::module::posture::SenderGate;
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::kvstore::PutArgs;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Default share of the quota, in percent, from which puts are answered
+/// with a warning.
+pub const DEFAULT_QUOTA_WARNING_PERCENT: u64 = 80;
+
+/// The attribute added to the response of a put when the owner uses more
+/// than the warning share of its quota, with its usage, quota and the
+/// percentage used as arguments. The ID is in the range many-abci keeps out
+/// of consensus, as the warning share is configured per node.
+pub const QUOTA_WARNING: Attribute = Attribute::id(1001);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct UsageArgs {
+    /// The owner to get the usage of, or the sender if omitted.
+    #[n(0)]
+    pub owner: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct UsageReturns {
+    /// The number of bytes of the values put by the owner.
+    #[n(0)]
+    pub usage: u64,
+
+    #[n(1)]
+    pub quota: Option<u64>,
+}
+
+impl UsageReturns {
+    /// The share of the quota used, in percent.
+    pub fn percent(&self) -> Option<u64> {
+        self.quota.map(|quota| {
+            self.usage
+                .saturating_mul(100)
+                .checked_div(quota)
+                .unwrap_or(100)
+        })
+    }
+
+    /// The warning for a usage over `threshold` percent of the quota.
+    pub fn warning(&self, threshold: u64) -> Option<Attribute> {
+        match (self.quota, self.percent()) {
+            (Some(quota), Some(percent)) if percent >= threshold => Some(
+                QUOTA_WARNING
+                    .with_argument(CborAny::Int(self.usage as i64))
+                    .with_argument(CborAny::Int(quota as i64))
+                    .with_argument(CborAny::Int(percent as i64)),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetQuotaArgs {
+    /// The maximum number of bytes of values each owner can put, or no
+    /// quota if omitted.
+    #[n(0)]
+    pub quota: Option<u64>,
+}
+
+pub trait KvStoreQuotaModuleBackend: Send {
+    fn usage(&self, sender: &Address, args: UsageArgs) -> Result<UsageReturns, ManyError>;
+
+    fn set_quota(&mut self, sender: &Address, args: SetQuotaArgs)
+        -> Result<EmptyReturn, ManyError>;
+}
+
+impl KvStoreModuleImpl {
+    /// Check that a put of `len` bytes does not bring the usage of `owner`
+    /// over its quota. Owners over quota can still shrink their values.
+    /// Quotas apply once usage is counted.
+    pub(crate) fn check_quota(
+        &self,
+        owner: &Address,
+        key: &[u8],
+        len: usize,
+    ) -> Result<(), ManyError> {
+        if !self.storage.usage_enabled() {
+            return Ok(());
+        }
+        if let Some(quota) = self.storage.get_quota()? {
+            let usage = self.storage.usage_after_put(owner, key, len)?;
+            if usage > quota && usage > self.storage.get_usage(owner)? {
+                return Err(error::quota_exceeded(usage.to_string(), quota.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl KvStoreQuotaModuleBackend for KvStoreModuleImpl {
+    fn usage(&self, sender: &Address, args: UsageArgs) -> Result<UsageReturns, ManyError> {
+        if !self.storage.usage_enabled() {
+            return Err(error::usage_not_enabled());
+        }
+        let owner = args.owner.unwrap_or(*sender);
+        if &owner != sender {
+            // Any role in an account allows reading its usage.
+            let has_role = self
+                .storage
+                .get_account(&owner)
+                .and_then(|account| account.roles.get(sender).map(|r| !r.is_empty()))
+                .unwrap_or(false);
+            if !has_role {
+                return Err(error::usage_denied());
+            }
+        }
+
+        Ok(UsageReturns {
+            usage: self.storage.get_usage(&owner)?,
+            quota: self.storage.get_quota()?,
+        })
+    }
+
+    fn set_quota(
+        &mut self,
+        sender: &Address,
+        args: SetQuotaArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        if sender != &self.storage.identity() {
+            return Err(error::quota_denied());
+        }
+        self.storage.set_quota(args.quota)?;
+        Ok(EmptyReturn)
+    }
+}
+
+/// A module for the usage of owners.
+pub struct KvStoreQuotaModule<T: KvStoreQuotaModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreQuotaModuleBackend> KvStoreQuotaModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreQuotaModule".to_string(),
+                attribute: None,
+                endpoints: vec!["kvstore.usage".to_string(), "kvstore.setQuota".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: KvStoreQuotaModuleBackend> Debug for KvStoreQuotaModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreQuotaModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreQuotaModuleBackend> ManyModule for KvStoreQuotaModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.usage" => decode_args::<UsageArgs>(&message.data).map(|_| ()),
+            "kvstore.setQuota" => decode_args::<SetQuotaArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.usage" => decode_args(&message.data)
+                .and_then(|args| backend.usage(&from, args))
+                .and_then(encode_returns),
+            "kvstore.setQuota" => decode_args(&message.data)
+                .and_then(|args| backend.set_quota(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
+
+/// The owner of the value put by a message, if it is a put. Puts with a
+/// capability are owned by its issuer.
+fn put_owner(message: &RequestMessage) -> Option<Address> {
+    let alternative_owner = match message.method.as_str() {
+        "kvstore.put" => {
+            decode_args::<PutArgs>(&message.data)
+                .ok()?
+                .alternative_owner
+        }
+        "kvstore.putWithContentType" => {
+            decode_args::<PutWithContentTypeArgs>(&message.data)
+                .ok()?
+                .alternative_owner
+        }
+        "kvstore.putWithLock" => {
+            decode_args::<PutWithLockArgs>(&message.data)
+                .ok()?
+                .alternative_owner
+        }
+        "kvstore.patch" => {
+            decode_args::<PatchArgs>(&message.data)
+                .ok()?
+                .alternative_owner
+        }
+        _ => return None,
+    };
+    Some(
+        alternative_owner
+            .or_else(|| capability::issuer(message))
+            .unwrap_or_else(|| message.from()),
+    )
+}
+
+/// Wraps a module serving puts, adding a warning to their response when the
+/// owner uses more than a share of its quota. The warning is added after
+/// the put is executed, so the share can differ between nodes.
+pub struct QuotaWarningModule<M: ManyModule, T: KvStoreQuotaModuleBackend> {
+    inner: M,
+    backend: Arc<Mutex<T>>,
+    threshold: u64,
+}
+
+impl<M: ManyModule, T: KvStoreQuotaModuleBackend> QuotaWarningModule<M, T> {
+    pub fn new(inner: M, backend: Arc<Mutex<T>>, threshold: u64) -> Self {
+        Self {
+            inner,
+            backend,
+            threshold,
+        }
+    }
+}
+
+impl<M: ManyModule + SenderGate, T: KvStoreQuotaModuleBackend> SenderGate
+    for QuotaWarningModule<M, T>
+{
+    fn admits(&self, method: &str, sender: &Address) -> bool {
+        self.inner.admits(method, sender)
+    }
+}
+
+impl<M: ManyModule, T: KvStoreQuotaModuleBackend> Debug for QuotaWarningModule<M, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("QuotaWarningModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule, T: KvStoreQuotaModuleBackend> ManyModule for QuotaWarningModule<M, T> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let owner = put_owner(&message);
+        let response = self.inner.execute(message).await?;
+        let owner = match owner {
+            Some(owner) if response.data.is_ok() => owner,
+            _ => return Ok(response),
+        };
+
+        let warning = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .usage(&owner, UsageArgs::default())
+            .ok()
+            .and_then(|usage| usage.warning(self.threshold));
+        Ok(match warning {
+            Some(warning) => response.with_attribute(warning),
+            None => response,
+        })
+    }
+}
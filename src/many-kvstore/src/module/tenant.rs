@@ -0,0 +1,377 @@
+use crate::error;
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::account::Role;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// The period writes are counted over under prefixes without a write limit,
+/// in seconds.
+pub const DEFAULT_WRITES_PERIOD: u64 = 3600;
+
+/// The number of writes allowed under a reserved prefix in each period.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, Encode, Decode)]
+#[cbor(map)]
+pub struct WriteLimit {
+    #[n(0)]
+    pub writes: u64,
+
+    /// The length of a period, in seconds.
+    #[n(1)]
+    pub period: u64,
+}
+
+/// A prefix reserved to an owner, the namespace of a tenant of the store.
+/// Only the owner can write keys under it. Reservations are part of the
+/// state, set by the initial state or by the identity of the store.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, Encode, Decode)]
+#[cbor(map)]
+pub struct ReservedPrefix {
+    #[n(0)]
+    pub prefix: String,
+
+    #[n(1)]
+    pub owner: Address,
+
+    #[serde(default)]
+    #[n(2)]
+    pub write_limit: Option<WriteLimit>,
+}
+
+impl ReservedPrefix {
+    fn period(&self) -> u64 {
+        self.write_limit
+            .as_ref()
+            .map_or(DEFAULT_WRITES_PERIOD, |limit| limit.period)
+    }
+}
+
+/// Check that reserved prefixes are not empty and do not overlap, so a key
+/// belongs to one tenant at most.
+pub fn validate_reserved_prefixes(prefixes: &[ReservedPrefix]) -> Result<(), ManyError> {
+    for (i, reserved) in prefixes.iter().enumerate() {
+        if reserved.prefix.is_empty() {
+            return Err(error::invalid_reserved_prefixes(
+                "a prefix is empty".to_string(),
+            ));
+        }
+        if reserved.period() == 0 {
+            return Err(error::invalid_reserved_prefixes(format!(
+                "the write limit of '{}' has an empty period",
+                reserved.prefix
+            )));
+        }
+        if let Some(other) = prefixes[i + 1..].iter().find(|other| {
+            reserved.prefix.starts_with(&other.prefix) || other.prefix.starts_with(&reserved.prefix)
+        }) {
+            return Err(error::invalid_reserved_prefixes(format!(
+                "'{}' and '{}' overlap",
+                reserved.prefix, other.prefix
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The writes under a reserved prefix in a period. Periods start at
+/// multiples of their length, in seconds since the epoch.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct TenantWrites {
+    #[n(0)]
+    pub period_start: u64,
+
+    #[n(1)]
+    pub writes: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReservedPrefixesArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReservedPrefixesReturns {
+    #[n(0)]
+    pub prefixes: Vec<ReservedPrefix>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetReservedPrefixesArgs {
+    /// The reservations replacing the current ones.
+    #[n(0)]
+    pub prefixes: Vec<ReservedPrefix>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct TenantStatsArgs {
+    #[n(0)]
+    pub prefix: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct TenantStatsReturns {
+    /// The number of keys with a value under the prefix.
+    #[n(0)]
+    pub keys: u64,
+
+    /// The size of the values under the prefix, in bytes.
+    #[n(1)]
+    pub bytes: u64,
+
+    /// The writes in the current period.
+    #[n(2)]
+    pub writes: u64,
+
+    /// The start of the current period, in seconds since the epoch.
+    #[n(3)]
+    pub period_start: u64,
+
+    #[n(4)]
+    pub write_limit: Option<WriteLimit>,
+}
+
+pub trait KvStoreTenantModuleBackend: Send {
+    fn reserved_prefixes(
+        &self,
+        sender: &Address,
+        args: ReservedPrefixesArgs,
+    ) -> Result<ReservedPrefixesReturns, ManyError>;
+
+    fn set_reserved_prefixes(
+        &mut self,
+        sender: &Address,
+        args: SetReservedPrefixesArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+
+    fn tenant_stats(
+        &self,
+        sender: &Address,
+        args: TenantStatsArgs,
+    ) -> Result<TenantStatsReturns, ManyError>;
+}
+
+impl KvStoreModuleImpl {
+    /// The reserved prefix of a key, if any.
+    pub(crate) fn reserved_prefix(&self, key: &[u8]) -> Result<Option<ReservedPrefix>, ManyError> {
+        Ok(self
+            .storage
+            .get_reserved_prefixes()?
+            .into_iter()
+            .find(|reserved| key.starts_with(reserved.prefix.as_bytes())))
+    }
+
+    /// The writes under a reserved prefix in the current period, by block
+    /// time so every node agrees on them.
+    fn current_writes(&self, reserved: &ReservedPrefix) -> Result<TenantWrites, ManyError> {
+        let now = self.now_secs()?;
+        let period_start = now - now % reserved.period();
+        Ok(
+            match self.storage.get_tenant_writes(reserved.prefix.as_bytes())? {
+                Some(writes) if writes.period_start == period_start => writes,
+                _ => TenantWrites {
+                    period_start,
+                    writes: 0,
+                },
+            },
+        )
+    }
+
+    /// Check a write of `owner` to a key, returning its reserved prefix and
+    /// the writes under it once the write is done, to record with
+    /// `record_tenant_write`. Writes under the prefix of another owner are
+    /// denied, and writes over the limit of the prefix are throttled until
+    /// the next period.
+    pub(crate) fn check_tenant(
+        &self,
+        owner: &Address,
+        key: &[u8],
+    ) -> Result<Option<(ReservedPrefix, TenantWrites)>, ManyError> {
+        let reserved = match self.reserved_prefix(key)? {
+            Some(reserved) => reserved,
+            None => return Ok(None),
+        };
+        if &reserved.owner != owner {
+            return Err(error::prefix_reserved(reserved.prefix));
+        }
+
+        let writes = self.current_writes(&reserved)?;
+        if let Some(limit) = &reserved.write_limit {
+            if writes.writes >= limit.writes {
+                let retry_after =
+                    (writes.period_start + limit.period).saturating_sub(self.now_secs()?);
+                return Err(error::tenant_throttled(
+                    reserved.prefix,
+                    retry_after.to_string(),
+                ));
+            }
+        }
+        Ok(Some((
+            reserved,
+            TenantWrites {
+                writes: writes.writes + 1,
+                ..writes
+            },
+        )))
+    }
+
+    pub(crate) fn record_tenant_write(
+        &mut self,
+        tenant: Option<(ReservedPrefix, TenantWrites)>,
+    ) -> Result<(), ManyError> {
+        match tenant {
+            Some((reserved, writes)) => self
+                .storage
+                .set_tenant_writes(reserved.prefix.as_bytes(), &writes),
+            None => Ok(()),
+        }
+    }
+}
+
+impl KvStoreTenantModuleBackend for KvStoreModuleImpl {
+    fn reserved_prefixes(
+        &self,
+        _sender: &Address,
+        _args: ReservedPrefixesArgs,
+    ) -> Result<ReservedPrefixesReturns, ManyError> {
+        Ok(ReservedPrefixesReturns {
+            prefixes: self.storage.get_reserved_prefixes()?,
+        })
+    }
+
+    fn set_reserved_prefixes(
+        &mut self,
+        sender: &Address,
+        args: SetReservedPrefixesArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        if sender != &self.storage.identity() {
+            return Err(error::reserved_prefixes_denied());
+        }
+        validate_reserved_prefixes(&args.prefixes)?;
+        self.storage.set_reserved_prefixes(&args.prefixes)?;
+        Ok(EmptyReturn)
+    }
+
+    fn tenant_stats(
+        &self,
+        sender: &Address,
+        args: TenantStatsArgs,
+    ) -> Result<TenantStatsReturns, ManyError> {
+        let reserved = self
+            .storage
+            .get_reserved_prefixes()?
+            .into_iter()
+            .find(|reserved| reserved.prefix == args.prefix)
+            .ok_or_else(|| error::prefix_not_reserved(args.prefix))?;
+
+        // The owner of an account owning the prefix can read its statistics
+        // too.
+        let allowed = sender == &reserved.owner
+            || sender == &self.storage.identity()
+            || self
+                .storage
+                .get_account(&reserved.owner)
+                .and_then(|account| {
+                    account
+                        .roles
+                        .get(sender)
+                        .map(|roles| roles.contains(&Role::Owner))
+                })
+                .unwrap_or(false);
+        if !allowed {
+            return Err(error::tenant_stats_denied());
+        }
+
+        let (keys, bytes) = self.storage.prefix_size(reserved.prefix.as_bytes())?;
+        let writes = self.current_writes(&reserved)?;
+        Ok(TenantStatsReturns {
+            keys,
+            bytes,
+            writes: writes.writes,
+            period_start: writes.period_start,
+            write_limit: reserved.write_limit,
+        })
+    }
+}
+
+const ENDPOINTS: &[&str] = &[
+    "kvstore.reservedPrefixes",
+    "kvstore.setReservedPrefixes",
+    "kvstore.tenantStats",
+];
+
+/// A module for the prefixes reserved to the tenants of the store.
+pub struct KvStoreTenantModule<T: KvStoreTenantModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreTenantModuleBackend> KvStoreTenantModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreTenantModule".to_string(),
+                attribute: None,
+                endpoints: ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: KvStoreTenantModuleBackend> Debug for KvStoreTenantModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreTenantModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreTenantModuleBackend> ManyModule for KvStoreTenantModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.reservedPrefixes" => {
+                decode_args::<ReservedPrefixesArgs>(&message.data).map(|_| ())
+            }
+            "kvstore.setReservedPrefixes" => {
+                decode_args::<SetReservedPrefixesArgs>(&message.data).map(|_| ())
+            }
+            "kvstore.tenantStats" => decode_args::<TenantStatsArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        let data = match message.method.as_str() {
+            "kvstore.reservedPrefixes" => decode_args(&message.data)
+                .and_then(|args| backend.reserved_prefixes(&from, args))
+                .and_then(encode_returns),
+            "kvstore.setReservedPrefixes" => decode_args(&message.data)
+                .and_then(|args| backend.set_reserved_prefixes(&from, args))
+                .and_then(encode_returns),
+            "kvstore.tenantStats" => decode_args(&message.data)
+                .and_then(|args| backend.tenant_stats(&from, args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,269 @@
+use crate::module::KvStoreModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::integrity::{IntegrityBackend, TreeCheck};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Default number of records walked by a single `kvstore.verify` call.
+pub const DEFAULT_VERIFY_LIMIT: u64 = 1000;
+
+/// Maximum number of records walked by a single `kvstore.verify` call, so
+/// a query never holds the store for long.
+pub const MAX_VERIFY_LIMIT: u64 = 10_000;
+
+/// The structures of the store, in the order they are verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum VerifyPhase {
+    /// Values, checked against their metadata.
+    #[n(0)]
+    Values,
+
+    /// Metadata, checked for decoding.
+    #[n(1)]
+    Metadata,
+
+    /// Events, checked against the events counter.
+    #[n(2)]
+    Events,
+
+    /// Accounts, checked against the account ID counter.
+    #[n(3)]
+    Accounts,
+}
+
+impl VerifyPhase {
+    pub fn next(self) -> Option<Self> {
+        match self {
+            VerifyPhase::Values => Some(VerifyPhase::Metadata),
+            VerifyPhase::Metadata => Some(VerifyPhase::Events),
+            VerifyPhase::Events => Some(VerifyPhase::Accounts),
+            VerifyPhase::Accounts => None,
+        }
+    }
+}
+
+/// Where to resume a verification.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct VerifyCursor {
+    #[n(0)]
+    pub phase: VerifyPhase,
+
+    /// The last storage key verified in this phase.
+    #[n(1)]
+    pub key: Option<ByteVec>,
+
+    /// What was counted so far in this phase.
+    #[n(2)]
+    pub count: u64,
+}
+
+impl Default for VerifyCursor {
+    fn default() -> Self {
+        Self {
+            phase: VerifyPhase::Values,
+            key: None,
+            count: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum DiscrepancyKind {
+    /// A value has no metadata.
+    #[n(0)]
+    MissingMetadata,
+
+    /// Metadata cannot be decoded.
+    #[n(1)]
+    InvalidMetadata,
+
+    /// A value has metadata without an owner.
+    #[n(2)]
+    MissingOwner,
+
+    /// The events counter does not match the number of events.
+    #[n(3)]
+    EventCount,
+
+    /// The account ID counter would reuse the ID of an existing account.
+    #[n(4)]
+    AccountCounter,
+}
+
+/// An inconsistency between the primary data and the structures derived
+/// from it.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct Discrepancy {
+    #[n(0)]
+    pub kind: DiscrepancyKind,
+
+    /// The storage key holding the inconsistent data.
+    #[n(1)]
+    pub key: ByteVec,
+
+    /// The data currently stored at the key.
+    #[n(2)]
+    pub actual: Option<ByteVec>,
+
+    /// The data a repair would store at the key, if it can be recovered.
+    #[n(3)]
+    pub expected: Option<ByteVec>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct VerifyArgs {
+    /// Where to resume, or the beginning of the store if missing.
+    #[n(0)]
+    pub cursor: Option<VerifyCursor>,
+
+    #[n(1)]
+    pub limit: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct VerifyReturns {
+    #[n(0)]
+    pub discrepancies: Vec<Discrepancy>,
+
+    /// The cursor to pass to the next call, or missing if the whole store
+    /// was verified.
+    #[n(1)]
+    pub next: Option<VerifyCursor>,
+}
+
+pub trait KvStoreVerifyModuleBackend: Send {
+    fn verify(&self, sender: &Address, args: VerifyArgs) -> Result<VerifyReturns, ManyError>;
+}
+
+impl KvStoreVerifyModuleBackend for KvStoreModuleImpl {
+    fn verify(&self, sender: &Address, args: VerifyArgs) -> Result<VerifyReturns, ManyError> {
+        let limit = args
+            .limit
+            .unwrap_or(DEFAULT_VERIFY_LIMIT)
+            .min(MAX_VERIFY_LIMIT);
+        let (discrepancies, next) =
+            self.storage
+                .verify(sender, args.cursor.unwrap_or_default(), limit as usize)?;
+        Ok(VerifyReturns {
+            discrepancies,
+            next,
+        })
+    }
+}
+
+impl IntegrityBackend for KvStoreModuleImpl {
+    fn check_tree(&self, after: Option<&[u8]>, limit: usize) -> Result<TreeCheck, ManyError> {
+        self.storage.check_tree(after, limit)
+    }
+}
+
+impl KvStoreModuleImpl {
+    /// Verify the whole store at once, repairing what can be repaired if
+    /// asked to. Returns all the discrepancies found, repaired or not.
+    /// This should only be used offline, as it does not yield.
+    pub fn verify_all(&mut self, repair: bool) -> Result<Vec<Discrepancy>, ManyError> {
+        self.verify_bounded(usize::MAX, repair).map(|(d, _)| d)
+    }
+
+    /// Verify the store until about `limit` records were walked, repairing
+    /// what can be repaired if asked to. Returns the discrepancies found and
+    /// whether the whole store was verified.
+    pub fn verify_bounded(
+        &mut self,
+        limit: usize,
+        repair: bool,
+    ) -> Result<(Vec<Discrepancy>, bool), ManyError> {
+        let identity = self.storage.identity();
+        let mut cursor = Some(VerifyCursor::default());
+        let mut discrepancies = Vec::new();
+        let mut walked = 0;
+        while let Some(c) = cursor {
+            if walked >= limit {
+                break;
+            }
+            let chunk = (MAX_VERIFY_LIMIT as usize).min(limit - walked);
+            let (mut d, next) = self.storage.verify(&identity, c, chunk)?;
+            discrepancies.append(&mut d);
+            cursor = next;
+            walked += chunk;
+        }
+
+        if repair {
+            self.storage.repair(&discrepancies)?;
+        }
+        Ok((discrepancies, cursor.is_none()))
+    }
+}
+
+/// A module for verifying the consistency of the store.
+pub struct KvStoreVerifyModule<T: KvStoreVerifyModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: KvStoreVerifyModuleBackend> KvStoreVerifyModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "KvStoreVerifyModule".to_string(),
+                attribute: None,
+                endpoints: vec!["kvstore.verify".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: KvStoreVerifyModuleBackend> Debug for KvStoreVerifyModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KvStoreVerifyModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: KvStoreVerifyModuleBackend> ManyModule for KvStoreVerifyModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "kvstore.verify" => minicbor::decode::<VerifyArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = match message.method.as_str() {
+            "kvstore.verify" => {
+                let args: VerifyArgs = minicbor::decode(&message.data)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+                let backend = self
+                    .backend
+                    .lock()
+                    .map_err(|e| ManyError::unknown(e.to_string()))?;
+                backend.verify(&from, args).and_then(|r| {
+                    minicbor::to_vec(r).map_err(|e| ManyError::serialization_error(e.to_string()))
+                })
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
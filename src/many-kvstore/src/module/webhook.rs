@@ -0,0 +1,151 @@
+use crate::webhook::Webhooks;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct WebhooksArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LastError {
+    #[n(0)]
+    pub time: Timestamp,
+
+    #[n(1)]
+    pub message: String,
+}
+
+/// The deliveries of a webhook since the start of the node.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct WebhookStatus {
+    #[n(0)]
+    pub name: String,
+
+    #[n(1)]
+    pub url: String,
+
+    /// The number of changes waiting to be delivered.
+    #[n(2)]
+    pub queued: u64,
+
+    #[n(3)]
+    pub delivered: u64,
+
+    /// The number of changes given up after all their retries failed.
+    #[n(4)]
+    pub failed: u64,
+
+    /// The number of attempts which failed, retried or not.
+    #[n(5)]
+    pub failed_attempts: u64,
+
+    /// The number of changes dropped because the queue was full.
+    #[n(6)]
+    pub dropped: u64,
+
+    #[n(7)]
+    pub last_error: Option<LastError>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct WebhooksReturns {
+    #[n(0)]
+    pub webhooks: Vec<WebhookStatus>,
+}
+
+/// A module returning the delivery status of the webhooks of the node to
+/// the identity of the server.
+pub struct WebhooksModule {
+    webhooks: Arc<Webhooks>,
+    server: Address,
+    info: ManyModuleInfo,
+}
+
+impl WebhooksModule {
+    pub fn new(webhooks: Arc<Webhooks>, server: Address) -> Self {
+        Self {
+            webhooks,
+            server,
+            info: ManyModuleInfo {
+                name: "WebhooksModule".to_string(),
+                attribute: None,
+                endpoints: vec!["server.webhooks".to_string()],
+            },
+        }
+    }
+
+    pub fn returns(&self) -> Result<WebhooksReturns, ManyError> {
+        let webhooks = self
+            .webhooks
+            .hooks()
+            .iter()
+            .map(|hook| {
+                let metrics = &hook.metrics;
+                Ok(WebhookStatus {
+                    name: hook.config.name.clone(),
+                    url: hook.config.url.clone(),
+                    queued: metrics.queued.load(Ordering::Relaxed),
+                    delivered: metrics.delivered.load(Ordering::Relaxed),
+                    failed: metrics.failed.load(Ordering::Relaxed),
+                    failed_attempts: metrics.failed_attempts.load(Ordering::Relaxed),
+                    dropped: metrics.dropped.load(Ordering::Relaxed),
+                    last_error: metrics
+                        .last_error()
+                        .map(|(time, message)| {
+                            Timestamp::from_system_time(time)
+                                .map(|time| LastError { time, message })
+                        })
+                        .transpose()?,
+                })
+            })
+            .collect::<Result<_, ManyError>>()?;
+        Ok(WebhooksReturns { webhooks })
+    }
+}
+
+impl Debug for WebhooksModule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WebhooksModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl ManyModule for WebhooksModule {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "server.webhooks" => minicbor::decode::<WebhooksArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let data = match message.method.as_str() {
+            "server.webhooks" if message.from() != self.server => {
+                Err(ManyError::invalid_from_identity())
+            }
+            "server.webhooks" => self.returns().and_then(|r| {
+                minicbor::to_vec(r).map_err(|e| ManyError::serialization_error(e.to_string()))
+            }),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,147 @@
+use crate::module::verify::DiscrepancyKind;
+use crate::module::KvStoreModuleImpl;
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::{error, info, warn};
+
+/// The file marking a store as in use. It is written at startup and removed
+/// on clean exit, so finding it at startup means the last run was killed.
+pub const DIRTY_MARKER_FILE: &str = "many-kvstore.dirty";
+
+/// The file where the report of the last recovery is written.
+pub const RECOVERY_REPORT_FILE: &str = "last-recovery.json";
+
+/// Default maximum number of records walked by the recovery pass, so a
+/// large store does not delay startup for too long.
+pub const DEFAULT_RECOVERY_LIMIT: u64 = 1_000_000;
+
+/// What the recovery pass found and repaired after an unclean shutdown.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    /// The height of the last block committed before the shutdown. Anything
+    /// after it was not committed and is lost.
+    pub last_committed_height: u64,
+
+    /// Metadata records repaired from the event log.
+    pub indexes_repaired: usize,
+
+    /// Event and account counters corrected.
+    pub counters_corrected: usize,
+
+    /// Discrepancies that could not be repaired.
+    pub unrepaired: usize,
+
+    /// Whether the whole store was verified within the limit.
+    pub complete: bool,
+
+    pub duration_ms: u64,
+}
+
+/// The dirty marker of a store.
+pub struct DirtyMarker {
+    path: PathBuf,
+}
+
+impl DirtyMarker {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(DIRTY_MARKER_FILE),
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn set(&self) -> std::io::Result<()> {
+        std::fs::write(&self.path, std::process::id().to_string())
+    }
+
+    pub fn clear(&self) -> std::io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Run a bounded consistency pass over the store, repairing what can be
+/// repaired.
+pub fn recover(module: &mut KvStoreModuleImpl, limit: u64) -> Result<RecoveryReport, String> {
+    let start = Instant::now();
+    let last_committed_height = ManyAbciModuleBackend::info(module)
+        .map_err(|e| e.to_string())?
+        .height;
+    let (discrepancies, complete) = module
+        .verify_bounded(limit as usize, true)
+        .map_err(|e| e.to_string())?;
+
+    let repaired = |counter: bool| {
+        discrepancies
+            .iter()
+            .filter(|d| d.expected.is_some())
+            .filter(|d| {
+                matches!(
+                    d.kind,
+                    DiscrepancyKind::EventCount | DiscrepancyKind::AccountCounter
+                ) == counter
+            })
+            .count()
+    };
+
+    Ok(RecoveryReport {
+        last_committed_height,
+        indexes_repaired: repaired(false),
+        counters_corrected: repaired(true),
+        unrepaired: discrepancies
+            .iter()
+            .filter(|d| d.expected.is_none())
+            .count(),
+        complete,
+        duration_ms: start.elapsed().as_millis() as u64,
+    })
+}
+
+/// Check whether the last run of the store in `dir` exited cleanly, and run
+/// a recovery if it did not, unless `fail_on_recovery` is set. The report is
+/// logged and written next to the marker. The marker is then set for this
+/// run.
+pub fn check(
+    module: &mut KvStoreModuleImpl,
+    dir: &Path,
+    limit: u64,
+    fail_on_recovery: bool,
+) -> Result<Option<RecoveryReport>, String> {
+    let marker = DirtyMarker::new(dir);
+    let report = if marker.is_set() {
+        if fail_on_recovery {
+            error!("The store was not shut down cleanly, aborting.");
+            return Err("The store needs recovery.".to_string());
+        }
+
+        warn!("The store was not shut down cleanly, recovering.");
+        let report = recover(module, limit)?;
+        info!(
+            last_committed_height = report.last_committed_height,
+            indexes_repaired = report.indexes_repaired,
+            counters_corrected = report.counters_corrected,
+            unrepaired = report.unrepaired,
+            complete = report.complete,
+            duration_ms = report.duration_ms,
+            "recovery"
+        );
+        std::fs::write(
+            dir.join(RECOVERY_REPORT_FILE),
+            serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?,
+        )
+        .map_err(|e| e.to_string())?;
+        Some(report)
+    } else {
+        None
+    };
+
+    marker.set().map_err(|e| e.to_string())?;
+    Ok(report)
+}
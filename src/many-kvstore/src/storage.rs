@@ -1,22 +1,42 @@
+use crate::module::derived::DerivedMetadata;
+use crate::module::json_patch::JsonPrefix;
+use crate::module::policy::AccessPolicy;
+use crate::module::tenant::ReservedPrefix;
 use crate::module::{KvStoreMetadata, KvStoreMetadataWrapper};
 use many_error::ManyError;
 use many_identity::Address;
 use many_modules::abci_backend::AbciCommitInfo;
 use many_modules::events::EventInfo;
+use many_storage::migration::{active_migrations, run_migrations, Migration};
 use many_types::{Either, Timestamp};
 use merk::{BatchEntry, Op};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
+use tracing::info;
 
 mod account;
+mod bulk_disable;
+mod capability;
+mod counter;
+mod derived;
 mod event;
+mod integrity;
+mod json_patch;
+mod list;
+mod lock;
+mod move_prefix;
+mod policy;
+mod quota;
+mod tenant;
+mod verify;
 
 use crate::error;
 use event::EventId;
 
 const KVSTORE_ROOT: &[u8] = b"s";
 const KVSTORE_ACL_ROOT: &[u8] = b"a";
+const KVSTORE_POLICY_ROOT: &[u8] = b"p";
 
 // Left-shift the height by this amount of bits
 const HEIGHT_EVENTID_SHIFT: u64 = 32;
@@ -43,11 +63,16 @@ pub struct KvStoreStorage {
     current_hash: Option<Vec<u8>>,
     next_account_id: u32,
     account_identity: Address,
+
+    active_migrations: BTreeSet<String>,
+    all_migrations: BTreeSet<Box<dyn Migration>>,
 }
 
 impl std::fmt::Debug for KvStoreStorage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("KvStoreStorage").finish()
+        f.debug_struct("KvStoreStorage")
+            .field("active_migrations", &self.active_migrations)
+            .finish()
     }
 }
 
@@ -61,6 +86,11 @@ impl KvStoreStorage {
         self.current_time.unwrap_or_else(Timestamp::now)
     }
 
+    #[inline]
+    pub fn identity(&self) -> Address {
+        self.account_identity
+    }
+
     pub fn load<P: AsRef<Path>>(persistent_path: P, blockchain: bool) -> Result<Self, String> {
         let persistent_store = merk::Merk::open(persistent_path).map_err(|e| e.to_string())?;
 
@@ -89,6 +119,9 @@ impl KvStoreStorage {
 
         let latest_event_id = EventId::from(height << HEIGHT_EVENTID_SHIFT);
 
+        let active_migrations = active_migrations(&persistent_store);
+        info!("Active migrations: {:?}", active_migrations);
+
         Ok(Self {
             persistent_store,
             blockchain,
@@ -97,12 +130,17 @@ impl KvStoreStorage {
             latest_event_id,
             next_account_id,
             account_identity,
+            active_migrations,
+            all_migrations: BTreeSet::new(),
         })
     }
 
     pub fn new<P: AsRef<Path>>(
         acl: AclMap,
         identity: Address,
+        json_prefixes: &[JsonPrefix],
+        quota: Option<u64>,
+        reserved_prefixes: &[ReservedPrefix],
         persistent_path: P,
         blockchain: bool,
     ) -> Result<Self, String> {
@@ -111,6 +149,18 @@ impl KvStoreStorage {
         let mut batch: Vec<BatchEntry> = Vec::new();
 
         batch.push((b"/config/identity".to_vec(), Op::Put(identity.to_vec())));
+        // Stores without JSON prefixes, quota or reserved prefixes keep the
+        // hash they had before those were part of the state.
+        if !json_prefixes.is_empty() {
+            batch.push(Self::json_prefixes_entry(json_prefixes).map_err(|e| e.to_string())?);
+        }
+        if let Some(quota) = quota {
+            batch.push(Self::quota_entry(quota));
+        }
+        if !reserved_prefixes.is_empty() {
+            batch
+                .push(Self::reserved_prefixes_entry(reserved_prefixes).map_err(|e| e.to_string())?);
+        }
 
         // Initialize DB with ACL
         for (k, v) in acl.into_iter() {
@@ -134,9 +184,16 @@ impl KvStoreStorage {
             latest_event_id: EventId::from(vec![0]),
             next_account_id: 0,
             account_identity: identity,
+            active_migrations: BTreeSet::new(),
+            all_migrations: BTreeSet::new(),
         })
     }
 
+    pub fn with_migrations(mut self, all_migrations: BTreeSet<Box<dyn Migration>>) -> Self {
+        self.all_migrations = all_migrations;
+        self
+    }
+
     fn inc_height(&mut self) -> u64 {
         let current_height = self.get_height();
         self.persistent_store
@@ -162,6 +219,19 @@ impl KvStoreStorage {
     pub fn commit(&mut self) -> AbciCommitInfo {
         let height = self.inc_height();
         let retain_height = 0;
+
+        // Committing before the migrations so they see the state of the
+        // block.
+        self.persistent_store.commit(&[]).unwrap();
+
+        // The store of deployments without migrations stays as it was.
+        run_migrations(
+            height + 1,
+            &self.all_migrations,
+            &mut self.active_migrations,
+            &mut self.persistent_store,
+            false,
+        );
         self.persistent_store.commit(&[]).unwrap();
 
         let hash = self.persistent_store.root_hash().to_vec();
@@ -198,6 +268,9 @@ impl KvStoreStorage {
                     _ => return Err(error::key_disabled()),
                 }
             }
+            if self.bulk_disable_of(key, meta.owner.as_ref())?.is_some() {
+                return Err(error::key_disabled());
+            }
         }
         self._get(key, KVSTORE_ROOT)
     }
@@ -206,26 +279,69 @@ impl KvStoreStorage {
         self._get(key, KVSTORE_ACL_ROOT)
     }
 
+    /// The batch writing a value with its metadata and policy.
+    fn put_batch(
+        &self,
+        meta: &KvStoreMetadata,
+        policy: &AccessPolicy,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Vec<BatchEntry>, ManyError> {
+        let mut batch: Vec<BatchEntry> = vec![(
+            vec![KVSTORE_ACL_ROOT.to_vec(), key.to_vec()].concat(),
+            Op::Put(
+                minicbor::to_vec(meta)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        )];
+
+        // Only keys with a non-default policy have a policy record, so the
+        // store of existing deployments does not change.
+        if !policy.is_default() {
+            batch.push((
+                vec![KVSTORE_POLICY_ROOT.to_vec(), key.to_vec()].concat(),
+                Op::Put(
+                    minicbor::to_vec(policy)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            ));
+        } else if self._get(key, KVSTORE_POLICY_ROOT)?.is_some() {
+            batch.push((
+                vec![KVSTORE_POLICY_ROOT.to_vec(), key.to_vec()].concat(),
+                Op::Delete,
+            ));
+        }
+
+        batch.push((
+            vec![KVSTORE_ROOT.to_vec(), key.to_vec()].concat(),
+            Op::Put(value),
+        ));
+        Ok(batch)
+    }
+
     pub fn put(
         &mut self,
         meta: &KvStoreMetadata,
+        policy: &AccessPolicy,
+        derived: &DerivedMetadata,
+        version: Option<u64>,
         key: &[u8],
         value: Vec<u8>,
     ) -> Result<(), ManyError> {
+        let mut batch = self.put_batch(meta, policy, key, value.clone())?;
+        if self.derived_enabled() {
+            batch.push(Self::derived_entry(key, derived)?);
+        }
+        if let Some(version) = version {
+            batch.push(Self::version_entry(key, version));
+        }
+        if let (true, Some(owner)) = (self.usage_enabled(), &meta.owner) {
+            let usage = self.usage_after_put(owner, key, value.len())?;
+            batch.push(Self::usage_entry(owner, usage));
+        }
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
         self.persistent_store
-            .apply(&[
-                (
-                    vec![KVSTORE_ACL_ROOT.to_vec(), key.to_vec()].concat(),
-                    Op::Put(
-                        minicbor::to_vec(meta)
-                            .map_err(|e| ManyError::serialization_error(e.to_string()))?,
-                    ),
-                ),
-                (
-                    vec![KVSTORE_ROOT.to_vec(), key.to_vec()].concat(),
-                    Op::Put(value.clone()),
-                ),
-            ])
+            .apply(&batch)
             .map_err(|e| ManyError::unknown(e.to_string()))?;
 
         self.log_event(EventInfo::KvStorePut {
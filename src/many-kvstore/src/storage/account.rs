@@ -6,8 +6,10 @@ use many_modules::{account, events};
 use many_types::Either;
 use merk::Op;
 
+pub(super) const ACCOUNTS_ROOT: &[u8] = b"/accounts/";
+
 fn key_for_account(id: &Address) -> Vec<u8> {
-    format!("/accounts/{}", id).into_bytes()
+    vec![ACCOUNTS_ROOT, id.to_string().as_bytes()].concat()
 }
 
 impl KvStoreStorage {
@@ -0,0 +1,171 @@
+use super::{KvStoreStorage, KVSTORE_ACL_ROOT};
+use crate::module::bulk_disable::BulkDisable;
+use crate::module::KvStoreMetadata;
+use many_error::{ManyError, Reason};
+use many_identity::Address;
+use many_modules::events::EventInfo;
+use many_types::Either;
+use merk::{BatchEntry, Op};
+use minicbor::{Decode, Encode};
+
+/// The bulk disables not done yet, and the ID of the next one.
+const BULK_DISABLES_KEY: &[u8] = b"/config/bulk_disables";
+
+#[derive(Debug, Default, Encode, Decode)]
+#[cbor(map)]
+struct BulkDisables {
+    #[n(0)]
+    next_id: u64,
+
+    #[n(1)]
+    pending: Vec<BulkDisable>,
+}
+
+fn is_disabled(meta: &KvStoreMetadata) -> bool {
+    !matches!(meta.disabled, None | Some(Either::Left(false)))
+}
+
+impl KvStoreStorage {
+    fn get_bulk_disables(&self) -> Result<BulkDisables, ManyError> {
+        self.persistent_store
+            .get(BULK_DISABLES_KEY)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map_or(Ok(BulkDisables::default()), |cbor| {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+    }
+
+    fn bulk_disables_entry(disables: &BulkDisables) -> Result<BatchEntry, ManyError> {
+        Ok((
+            BULK_DISABLES_KEY.to_vec(),
+            Op::Put(
+                minicbor::to_vec(disables)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        ))
+    }
+
+    /// The bulk disables whose keys are not all disabled yet, oldest first.
+    pub fn pending_bulk_disables(&self) -> Result<Vec<BulkDisable>, ManyError> {
+        Ok(self.get_bulk_disables()?.pending)
+    }
+
+    /// The pending bulk disable covering a key of an owner, if any. The key
+    /// is disabled from the moment the bulk disable is accepted, before its
+    /// metadata is changed.
+    pub fn bulk_disable_of(
+        &self,
+        key: &[u8],
+        owner: Option<&Address>,
+    ) -> Result<Option<BulkDisable>, ManyError> {
+        let owner = match owner {
+            Some(owner) => owner,
+            None => return Ok(None),
+        };
+        let disable = self
+            .pending_bulk_disables()?
+            .into_iter()
+            .find(|disable| disable.covers(key, owner));
+        match disable {
+            Some(_) if self.get_policy(key)?.immutable => Ok(None),
+            disable => Ok(disable),
+        }
+    }
+
+    /// Record a bulk disable of the keys of an owner starting with a prefix,
+    /// returning its ID. Its keys are disabled by `drain_bulk_disables`,
+    /// right away if the store is not part of a blockchain.
+    pub fn add_bulk_disable(
+        &mut self,
+        owner: Address,
+        prefix: Vec<u8>,
+        reason: Option<Reason<u64>>,
+    ) -> Result<u64, ManyError> {
+        let mut disables = self.get_bulk_disables()?;
+        let id = disables.next_id;
+        disables.next_id += 1;
+        disables.pending.push(BulkDisable {
+            id,
+            prefix: prefix.into(),
+            owner,
+            reason,
+            processed: None,
+            disabled: 0,
+        });
+        self.persistent_store
+            .apply(&[Self::bulk_disables_entry(&disables)?])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.drain_bulk_disables(usize::MAX)?;
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(id)
+    }
+
+    /// Disable up to `budget` keys of the pending bulk disables, in the order
+    /// they were accepted. Keys are scanned from the committed store, so
+    /// this must run before any transaction of the block, and with the same
+    /// budget on every node.
+    pub fn drain_bulk_disables(&mut self, mut budget: usize) -> Result<(), ManyError> {
+        let mut disables = self.get_bulk_disables()?;
+        if disables.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch: Vec<BatchEntry> = vec![];
+        let mut events = vec![];
+        let mut done = 0;
+        for disable in disables.pending.iter_mut() {
+            if budget == 0 {
+                break;
+            }
+            let keys = self.list(
+                &disable.prefix,
+                disable.processed.as_ref().map(|p| p.as_slice()),
+                budget,
+            )?;
+            budget -= keys.len();
+            let finished = budget > 0;
+
+            for (key, mut meta) in keys {
+                disable.processed = Some(key.clone().into());
+                if meta.owner.as_ref() != Some(&disable.owner)
+                    || is_disabled(&meta)
+                    || self.get_policy(&key)?.immutable
+                {
+                    continue;
+                }
+                meta.disabled = Some(disable.disabled_value());
+                batch.push((
+                    vec![KVSTORE_ACL_ROOT, &key].concat(),
+                    Op::Put(
+                        minicbor::to_vec(&meta)
+                            .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                    ),
+                ));
+                events.push(EventInfo::KvStoreDisable {
+                    key: key.into(),
+                    owner: meta.owner,
+                    reason: disable.reason.clone(),
+                });
+                disable.disabled += 1;
+            }
+
+            if finished {
+                done += 1;
+            }
+        }
+        disables.pending.drain(..done);
+
+        batch.push(Self::bulk_disables_entry(&disables)?);
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.persistent_store
+            .apply(&batch)
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        for event in events {
+            self.log_event(event);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use super::KvStoreStorage;
+use crate::module::capability::CapabilityState;
+use many_error::ManyError;
+use merk::Op;
+
+/// The uses and revocation of each capability, by the hash of its payload.
+pub(super) const KVSTORE_CAPABILITY_ROOT: &[u8] = b"c";
+
+impl KvStoreStorage {
+    pub fn get_capability(&self, hash: &[u8]) -> Result<CapabilityState, ManyError> {
+        self._get(hash, KVSTORE_CAPABILITY_ROOT)?
+            .map_or(Ok(CapabilityState::default()), |cbor| {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+    }
+
+    pub fn set_capability(
+        &mut self,
+        hash: &[u8],
+        state: &CapabilityState,
+    ) -> Result<(), ManyError> {
+        self.persistent_store
+            .apply(&[(
+                vec![KVSTORE_CAPABILITY_ROOT.to_vec(), hash.to_vec()].concat(),
+                Op::Put(
+                    minicbor::to_vec(state)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            )])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use super::{KvStoreStorage, KVSTORE_ROOT};
+use crate::error;
+use crate::module::counter::ValueKind;
+use crate::module::policy::AccessPolicy;
+use crate::module::KvStoreMetadata;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events::EventInfo;
+use merk::Op;
+use num_bigint::{BigInt, Sign};
+
+/// The kind of the keys that are not plain bytes. Only counters have a kind
+/// record, so the store of existing deployments does not change.
+pub(super) const KVSTORE_KIND_ROOT: &[u8] = b"k";
+
+impl KvStoreStorage {
+    pub fn get_kind(&self, key: &[u8]) -> Result<ValueKind, ManyError> {
+        match self._get(key, KVSTORE_KIND_ROOT)? {
+            Some(cbor) => {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            }
+            None => Ok(ValueKind::Bytes),
+        }
+    }
+
+    /// Create a counter at zero.
+    pub fn create_counter(
+        &mut self,
+        meta: &KvStoreMetadata,
+        policy: &AccessPolicy,
+        key: &[u8],
+        kind: ValueKind,
+    ) -> Result<(), ManyError> {
+        let value = kind.encode_counter(&BigInt::from(0));
+        let mut batch = self.put_batch(meta, policy, key, value.clone())?;
+        batch.push((
+            vec![KVSTORE_KIND_ROOT.to_vec(), key.to_vec()].concat(),
+            Op::Put(
+                minicbor::to_vec(kind)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        ));
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        self.persistent_store
+            .apply(&batch)
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        self.log_event(EventInfo::KvStorePut {
+            key: key.to_vec().into(),
+            value: value.into(),
+            owner: meta.owner,
+        });
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Add a delta to a counter and return its new value. The new value is
+    /// written in a single operation, so concurrent adds in a block are
+    /// applied one after the other.
+    pub fn counter_add(
+        &mut self,
+        owner: &Address,
+        key: &[u8],
+        delta: &BigInt,
+    ) -> Result<BigInt, ManyError> {
+        let bytes = self.get(key)?.ok_or_else(error::key_not_found)?;
+        let kind = self.get_kind(key)?;
+        if !kind.is_counter() {
+            return Err(error::not_a_counter());
+        }
+
+        let value = kind.decode_counter(&bytes) + delta;
+        if kind == ValueKind::Counter && value.sign() == Sign::Minus {
+            return Err(error::counter_underflow());
+        }
+        let new_bytes = kind.encode_counter(&value);
+
+        self.persistent_store
+            .apply(&[(
+                vec![KVSTORE_ROOT.to_vec(), key.to_vec()].concat(),
+                Op::Put(new_bytes.clone()),
+            )])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        self.log_event(EventInfo::KvStorePut {
+            key: key.to_vec().into(),
+            value: new_bytes.into(),
+            owner: Some(*owner),
+        });
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(value)
+    }
+}
@@ -0,0 +1,38 @@
+use super::KvStoreStorage;
+use crate::migration::derived::KVSTORE_DERIVED_MIGRATION;
+use crate::module::derived::DerivedMetadata;
+use many_error::ManyError;
+use merk::{BatchEntry, Op};
+
+/// The fields derived from the value of each key, written with the value.
+pub(super) const KVSTORE_DERIVED_ROOT: &[u8] = b"d";
+
+impl KvStoreStorage {
+    /// Whether the derived fields are written. Writing them changes the
+    /// state, so stores start writing them at the activation height of the
+    /// migration.
+    pub(crate) fn derived_enabled(&self) -> bool {
+        self.active_migrations.contains(KVSTORE_DERIVED_MIGRATION)
+    }
+
+    pub fn get_derived(&self, key: &[u8]) -> Result<Option<DerivedMetadata>, ManyError> {
+        self._get(key, KVSTORE_DERIVED_ROOT)?
+            .map(|cbor| {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+            .transpose()
+    }
+
+    pub(super) fn derived_entry(
+        key: &[u8],
+        derived: &DerivedMetadata,
+    ) -> Result<BatchEntry, ManyError> {
+        Ok((
+            vec![KVSTORE_DERIVED_ROOT.to_vec(), key.to_vec()].concat(),
+            Op::Put(
+                minicbor::to_vec(derived)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        ))
+    }
+}
@@ -1,12 +1,13 @@
 use super::KvStoreStorage;
 use many_modules::events;
+use many_storage::event_schema::{encode_event, event_schema_version};
 use many_types::{CborRange, SortOrder};
 use merk::tree::Tree;
 use merk::{rocksdb, Op};
 use std::collections::Bound;
 use std::ops::RangeBounds;
 
-const EVENTS_ROOT: &[u8] = b"/events/";
+pub(super) const EVENTS_ROOT: &[u8] = b"/events/";
 
 pub type EventId = events::EventId;
 
@@ -58,7 +59,10 @@ impl KvStoreStorage {
             .apply(&[
                 (
                     key_for_event(event.id.clone()),
-                    Op::Put(minicbor::to_vec(&event).unwrap()),
+                    Op::Put(
+                        encode_event(&event, event_schema_version(&self.active_migrations))
+                            .unwrap(),
+                    ),
                 ),
                 (
                     b"/events_count".to_vec(),
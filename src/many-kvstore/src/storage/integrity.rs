@@ -0,0 +1,10 @@
+use super::KvStoreStorage;
+use many_error::ManyError;
+use many_storage::integrity::{self, TreeCheck};
+
+impl KvStoreStorage {
+    /// Check at most `limit` nodes of the committed merk tree after a key.
+    pub fn check_tree(&self, after: Option<&[u8]>, limit: usize) -> Result<TreeCheck, ManyError> {
+        integrity::check_tree(&self.persistent_store, after, limit)
+    }
+}
@@ -0,0 +1,68 @@
+use super::KvStoreStorage;
+use crate::module::json_patch::JsonPrefix;
+use many_error::ManyError;
+use merk::{BatchEntry, Op};
+
+/// The version of each document under a JSON prefix, written with its value.
+pub(super) const KVSTORE_VERSION_ROOT: &[u8] = b"v";
+
+/// The rules declaring the values under prefixes as JSON documents.
+const KVSTORE_JSON_PREFIXES_KEY: &[u8] = b"/config/json_prefixes";
+
+impl KvStoreStorage {
+    /// The number of times a document was put or patched, 0 if it never was.
+    pub fn get_json_version(&self, key: &[u8]) -> Result<u64, ManyError> {
+        Ok(self._get(key, KVSTORE_VERSION_ROOT)?.map_or(0, |x| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(x.as_slice());
+            u64::from_be_bytes(bytes)
+        }))
+    }
+
+    pub(super) fn version_entry(key: &[u8], version: u64) -> BatchEntry {
+        (
+            vec![KVSTORE_VERSION_ROOT.to_vec(), key.to_vec()].concat(),
+            Op::Put(version.to_be_bytes().to_vec()),
+        )
+    }
+
+    pub(super) fn json_prefixes_entry(prefixes: &[JsonPrefix]) -> Result<BatchEntry, ManyError> {
+        Ok((
+            KVSTORE_JSON_PREFIXES_KEY.to_vec(),
+            Op::Put(
+                minicbor::to_vec(prefixes)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        ))
+    }
+
+    /// The rules declaring the values under prefixes as JSON documents, none
+    /// if they were never set.
+    pub fn get_json_prefixes(&self) -> Result<Vec<JsonPrefix>, ManyError> {
+        match self
+            .persistent_store
+            .get(KVSTORE_JSON_PREFIXES_KEY)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+        {
+            Some(cbor) => {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Replace the rules declaring the values under prefixes as JSON
+    /// documents. The values already put are not checked.
+    pub fn set_json_prefixes(&mut self, prefixes: &[JsonPrefix]) -> Result<(), ManyError> {
+        self.persistent_store
+            .apply(&[Self::json_prefixes_entry(prefixes)?])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store
+                .commit(&[])
+                .expect("Could not commit to store.");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use super::{KvStoreStorage, KVSTORE_ACL_ROOT, KVSTORE_ROOT};
+use crate::module::KvStoreMetadata;
+use many_error::ManyError;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::Tree;
+
+impl KvStoreStorage {
+    /// Returns up to `limit` keys starting with the prefix and their metadata,
+    /// in key order, starting after the `after` key if any.
+    pub fn list(
+        &self,
+        prefix: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, KvStoreMetadata)>, ManyError> {
+        let root = vec![KVSTORE_ACL_ROOT, prefix].concat();
+        let lower = match after {
+            Some(after) if after >= prefix => vec![KVSTORE_ACL_ROOT, after, &[0]].concat(),
+            _ => root.clone(),
+        };
+
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_lower_bound(lower);
+        // The ACL root is a single byte, so this bound always exists.
+        if let Some(upper) = prefix_end(&root) {
+            opts.set_iterate_upper_bound(upper);
+        }
+
+        self.persistent_store
+            .iter_opt(IteratorMode::Start, opts)
+            .take(limit)
+            .map(|item| {
+                let (k, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+                let v = Tree::decode(k.to_vec(), v.as_ref());
+                let meta = minicbor::decode(v.value())
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+                Ok((k[KVSTORE_ACL_ROOT.len()..].to_vec(), meta))
+            })
+            .collect()
+    }
+
+    /// The size in bytes of the value of a key, if it has one.
+    pub fn value_size(&self, key: &[u8]) -> Result<Option<u64>, ManyError> {
+        Ok(self
+            ._get(key, KVSTORE_ROOT)?
+            .map(|value| value.len() as u64))
+    }
+}
+
+/// Returns the first key after all keys starting with the prefix, or `None`
+/// if there is none (the prefix is all `0xFF`).
+pub(super) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
@@ -0,0 +1,34 @@
+use super::KvStoreStorage;
+use crate::module::lock::LockState;
+use many_error::ManyError;
+use merk::Op;
+
+/// The lock of each key that was ever locked, or created as requiring one.
+pub(super) const KVSTORE_LOCK_ROOT: &[u8] = b"l";
+
+impl KvStoreStorage {
+    pub fn get_lock(&self, key: &[u8]) -> Result<Option<LockState>, ManyError> {
+        self._get(key, KVSTORE_LOCK_ROOT)?
+            .map(|cbor| {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+            .transpose()
+    }
+
+    pub fn set_lock(&mut self, key: &[u8], lock: &LockState) -> Result<(), ManyError> {
+        self.persistent_store
+            .apply(&[(
+                vec![KVSTORE_LOCK_ROOT.to_vec(), key.to_vec()].concat(),
+                Op::Put(
+                    minicbor::to_vec(lock)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            )])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use super::counter::KVSTORE_KIND_ROOT;
+use super::derived::KVSTORE_DERIVED_ROOT;
+use super::json_patch::KVSTORE_VERSION_ROOT;
+use super::lock::KVSTORE_LOCK_ROOT;
+use super::{KvStoreStorage, KVSTORE_ACL_ROOT, KVSTORE_POLICY_ROOT, KVSTORE_ROOT};
+use crate::module::move_prefix::PrefixMove;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events::EventInfo;
+use merk::{BatchEntry, Op};
+use minicbor::{Decode, Encode};
+
+/// The prefix moves not done yet, and the ID of the next one.
+const PREFIX_MOVES_KEY: &[u8] = b"/config/prefix_moves";
+
+/// Every record kept for a key, which a move renames together.
+const KEY_ROOTS: &[&[u8]] = &[
+    KVSTORE_ACL_ROOT,
+    KVSTORE_DERIVED_ROOT,
+    KVSTORE_KIND_ROOT,
+    KVSTORE_LOCK_ROOT,
+    KVSTORE_POLICY_ROOT,
+    KVSTORE_ROOT,
+    KVSTORE_VERSION_ROOT,
+];
+
+#[derive(Debug, Default, Encode, Decode)]
+#[cbor(map)]
+struct PrefixMoves {
+    #[n(0)]
+    next_id: u64,
+
+    #[n(1)]
+    pending: Vec<PrefixMove>,
+}
+
+impl KvStoreStorage {
+    fn get_prefix_moves(&self) -> Result<PrefixMoves, ManyError> {
+        self.persistent_store
+            .get(PREFIX_MOVES_KEY)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map_or(Ok(PrefixMoves::default()), |cbor| {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+    }
+
+    fn prefix_moves_entry(moves: &PrefixMoves) -> Result<BatchEntry, ManyError> {
+        Ok((
+            PREFIX_MOVES_KEY.to_vec(),
+            Op::Put(
+                minicbor::to_vec(moves)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        ))
+    }
+
+    /// The prefix moves whose keys are not all moved yet, oldest first.
+    pub fn pending_prefix_moves(&self) -> Result<Vec<PrefixMove>, ManyError> {
+        Ok(self.get_prefix_moves()?.pending)
+    }
+
+    /// The pending prefix move whose source or destination covers a key, if
+    /// any. A key being moved, or in the way of one, cannot change until the
+    /// move is done.
+    pub fn prefix_move_of(&self, key: &[u8]) -> Result<Option<PrefixMove>, ManyError> {
+        Ok(self
+            .pending_prefix_moves()?
+            .into_iter()
+            .find(|m| m.covers(key)))
+    }
+
+    /// Record the move of the keys of an owner under a prefix to another
+    /// prefix, returning its ID. Its keys are moved by `drain_prefix_moves`,
+    /// right away if the store is not part of a blockchain.
+    pub fn add_prefix_move(
+        &mut self,
+        owner: Address,
+        source: Vec<u8>,
+        destination: Vec<u8>,
+        count: u64,
+    ) -> Result<u64, ManyError> {
+        let mut moves = self.get_prefix_moves()?;
+        let id = moves.next_id;
+        moves.next_id += 1;
+        moves.pending.push(PrefixMove {
+            id,
+            source: source.into(),
+            destination: destination.into(),
+            owner,
+            count,
+            processed: None,
+            moved: 0,
+        });
+        self.persistent_store
+            .apply(&[Self::prefix_moves_entry(&moves)?])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.drain_prefix_moves(usize::MAX)?;
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(id)
+    }
+
+    /// Move up to `budget` keys of the pending prefix moves, in the order
+    /// they were accepted. Every record of a key is renamed in the same
+    /// batch, so a key reads at its old location until its chunk is
+    /// processed, then at its new one. Keys are scanned from the committed
+    /// store, so this must run before any transaction of the block, and with
+    /// the same budget on every node.
+    pub fn drain_prefix_moves(&mut self, mut budget: usize) -> Result<(), ManyError> {
+        let mut moves = self.get_prefix_moves()?;
+        if moves.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch: Vec<BatchEntry> = vec![];
+        let mut events = vec![];
+        let mut done = 0;
+        for prefix_move in moves.pending.iter_mut() {
+            if budget == 0 {
+                break;
+            }
+            let keys = self.list(
+                &prefix_move.source,
+                prefix_move.processed.as_ref().map(|p| p.as_slice()),
+                budget,
+            )?;
+            budget -= keys.len();
+            let finished = budget > 0;
+
+            for (key, meta) in keys {
+                prefix_move.processed = Some(key.clone().into());
+                let new_key = prefix_move.destination_of(&key);
+                // Keys put in the block which accepted the move were not
+                // checked. They stay where they are.
+                if meta.owner.as_ref() != Some(&prefix_move.owner)
+                    || self._get(&new_key, KVSTORE_ACL_ROOT)?.is_some()
+                {
+                    continue;
+                }
+                for root in KEY_ROOTS {
+                    if let Some(record) = self._get(&key, root)? {
+                        batch.push((vec![root, new_key.as_slice()].concat(), Op::Put(record)));
+                        batch.push((vec![root, key.as_slice()].concat(), Op::Delete));
+                    }
+                }
+                if let Some(value) = self._get(&key, KVSTORE_ROOT)? {
+                    events.push(EventInfo::KvStorePut {
+                        key: new_key.into(),
+                        value: value.into(),
+                        owner: meta.owner,
+                    });
+                }
+                prefix_move.moved += 1;
+            }
+
+            if finished {
+                done += 1;
+            }
+        }
+        moves.pending.drain(..done);
+
+        batch.push(Self::prefix_moves_entry(&moves)?);
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.persistent_store
+            .apply(&batch)
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        for event in events {
+            self.log_event(event);
+        }
+        Ok(())
+    }
+}
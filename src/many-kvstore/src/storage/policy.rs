@@ -0,0 +1,39 @@
+use super::{KvStoreStorage, KVSTORE_POLICY_ROOT};
+use crate::module::policy::{AccessPolicy, AccountKvStoreDefaults};
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::account::features::FeatureInfo;
+use many_modules::{account, events};
+use std::collections::BTreeMap;
+
+impl KvStoreStorage {
+    /// Returns the policy of a key. Keys without a policy record use the
+    /// default policy.
+    pub fn get_policy(&self, key: &[u8]) -> Result<AccessPolicy, ManyError> {
+        match self._get(key, KVSTORE_POLICY_ROOT)? {
+            Some(cbor) => {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            }
+            None => Ok(AccessPolicy::default()),
+        }
+    }
+
+    /// Set the default policy of an account, replacing the previous one.
+    /// Keys already put keep their policy.
+    pub fn set_default_policy(
+        &mut self,
+        mut account: account::Account,
+        id: &Address,
+        policy: AccessPolicy,
+    ) -> Result<(), ManyError> {
+        let feature = AccountKvStoreDefaults { policy }.as_feature();
+        account.features.insert(feature.clone());
+
+        self.log_event(events::EventInfo::AccountAddFeatures {
+            account: *id,
+            roles: BTreeMap::new(),
+            features: account::features::FeatureSet::from_iter([feature]),
+        });
+        self.commit_account(id, account)
+    }
+}
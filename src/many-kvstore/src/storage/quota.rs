@@ -0,0 +1,136 @@
+use super::counter::KVSTORE_KIND_ROOT;
+use super::{KvStoreStorage, KVSTORE_ACL_ROOT, KVSTORE_ROOT};
+use crate::migration::usage::KVSTORE_USAGE_MIGRATION;
+use crate::module::KvStoreMetadata;
+use many_error::ManyError;
+use many_identity::Address;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::Tree;
+use merk::{BatchEntry, Op};
+use std::collections::BTreeMap;
+
+/// The number of bytes of the values put by each owner.
+pub(super) const KVSTORE_USAGE_ROOT: &[u8] = b"u";
+
+/// The maximum number of bytes of values each owner can put.
+const KVSTORE_QUOTA_KEY: &[u8] = b"/config/quota";
+
+fn key_for_usage(owner: &Address) -> Vec<u8> {
+    vec![KVSTORE_USAGE_ROOT.to_vec(), owner.to_vec()].concat()
+}
+
+impl KvStoreStorage {
+    /// Whether the usage of owners is counted. Writing it changes the state,
+    /// so stores start counting at the activation height of the migration.
+    pub(crate) fn usage_enabled(&self) -> bool {
+        self.active_migrations.contains(KVSTORE_USAGE_MIGRATION)
+    }
+
+    /// The number of bytes of the values put by an owner. Counters are not
+    /// counted.
+    pub fn get_usage(&self, owner: &Address) -> Result<u64, ManyError> {
+        Ok(self
+            .persistent_store
+            .get(&key_for_usage(owner))
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map_or(0, |x| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(x.as_slice());
+                u64::from_be_bytes(bytes)
+            }))
+    }
+
+    /// The usage of the owner of a key once its value is replaced by one of
+    /// `len` bytes. Only owners can put a key, so the current value is
+    /// counted in the usage of the same owner.
+    pub fn usage_after_put(
+        &self,
+        owner: &Address,
+        key: &[u8],
+        len: usize,
+    ) -> Result<u64, ManyError> {
+        let current = self._get(key, KVSTORE_ROOT)?.map_or(0, |v| v.len() as u64);
+        Ok(self.get_usage(owner)?.saturating_sub(current) + len as u64)
+    }
+
+    pub(super) fn quota_entry(quota: u64) -> BatchEntry {
+        (
+            KVSTORE_QUOTA_KEY.to_vec(),
+            Op::Put(quota.to_be_bytes().to_vec()),
+        )
+    }
+
+    /// The maximum number of bytes of values each owner can put, if any.
+    pub fn get_quota(&self) -> Result<Option<u64>, ManyError> {
+        Ok(self
+            .persistent_store
+            .get(KVSTORE_QUOTA_KEY)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map(|x| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(x.as_slice());
+                u64::from_be_bytes(bytes)
+            }))
+    }
+
+    /// Replace the quota of every owner, or remove it. Owners already over
+    /// the new quota keep their values.
+    pub fn set_quota(&mut self, quota: Option<u64>) -> Result<(), ManyError> {
+        let entry = match quota {
+            Some(quota) => Self::quota_entry(quota),
+            None => (KVSTORE_QUOTA_KEY.to_vec(), Op::Delete),
+        };
+        self.persistent_store
+            .apply(&[entry])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store
+                .commit(&[])
+                .expect("Could not commit to store.");
+        }
+        Ok(())
+    }
+
+    pub(super) fn usage_entry(owner: &Address, usage: u64) -> BatchEntry {
+        (key_for_usage(owner), Op::Put(usage.to_be_bytes().to_vec()))
+    }
+
+    /// The usage of every owner of the values already put, as written by
+    /// their puts.
+    pub(crate) fn backfill_usage(persistent_store: &merk::Merk) -> Vec<BatchEntry> {
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_lower_bound(KVSTORE_ACL_ROOT.to_vec());
+        // The ACL root is a single byte, so this bound always exists.
+        opts.set_iterate_upper_bound(vec![KVSTORE_ACL_ROOT[0] + 1]);
+
+        let mut usage: BTreeMap<Address, u64> = BTreeMap::new();
+        for item in persistent_store.iter_opt(IteratorMode::Start, opts) {
+            let (k, v) = item.expect("Error while reading the DB");
+            let key = &k[KVSTORE_ACL_ROOT.len()..];
+            let meta: KvStoreMetadata =
+                minicbor::decode(Tree::decode(k.to_vec(), v.as_ref()).value())
+                    .expect("Could not decode the metadata of a key");
+            let owner = match meta.owner {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let get = |root: &[u8]| {
+                persistent_store
+                    .get(&[root, key].concat())
+                    .expect("Error while reading the DB")
+            };
+            // Counters are not counted.
+            if get(KVSTORE_KIND_ROOT).is_some() {
+                continue;
+            }
+            if let Some(value) = get(KVSTORE_ROOT) {
+                *usage.entry(owner).or_default() += value.len() as u64;
+            }
+        }
+        usage
+            .iter()
+            .map(|(owner, usage)| Self::usage_entry(owner, *usage))
+            .collect()
+    }
+}
@@ -0,0 +1,114 @@
+use super::list::prefix_end;
+use super::{KvStoreStorage, KVSTORE_ROOT};
+use crate::module::tenant::{ReservedPrefix, TenantWrites};
+use many_error::ManyError;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::Tree;
+use merk::{BatchEntry, Op};
+
+/// The writes under each reserved prefix in the current period.
+const KVSTORE_TENANT_WRITES_ROOT: &[u8] = b"t";
+
+/// The prefixes reserved to owners.
+const KVSTORE_RESERVED_PREFIXES_KEY: &[u8] = b"/config/reserved_prefixes";
+
+fn key_for_tenant_writes(prefix: &[u8]) -> Vec<u8> {
+    vec![KVSTORE_TENANT_WRITES_ROOT, prefix].concat()
+}
+
+impl KvStoreStorage {
+    pub(super) fn reserved_prefixes_entry(
+        prefixes: &[ReservedPrefix],
+    ) -> Result<BatchEntry, ManyError> {
+        Ok((
+            KVSTORE_RESERVED_PREFIXES_KEY.to_vec(),
+            Op::Put(
+                minicbor::to_vec(prefixes)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        ))
+    }
+
+    /// The prefixes reserved to owners, none if they were never set.
+    pub fn get_reserved_prefixes(&self) -> Result<Vec<ReservedPrefix>, ManyError> {
+        match self
+            .persistent_store
+            .get(KVSTORE_RESERVED_PREFIXES_KEY)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+        {
+            Some(cbor) => {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Replace the prefixes reserved to owners. The keys already under a
+    /// prefix keep their owner.
+    pub fn set_reserved_prefixes(&mut self, prefixes: &[ReservedPrefix]) -> Result<(), ManyError> {
+        self.persistent_store
+            .apply(&[Self::reserved_prefixes_entry(prefixes)?])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store
+                .commit(&[])
+                .expect("Could not commit to store.");
+        }
+        Ok(())
+    }
+
+    /// The writes under a reserved prefix in the last period written.
+    pub fn get_tenant_writes(&self, prefix: &[u8]) -> Result<Option<TenantWrites>, ManyError> {
+        self.persistent_store
+            .get(&key_for_tenant_writes(prefix))
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map(|cbor| {
+                minicbor::decode(&cbor).map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+            .transpose()
+    }
+
+    pub fn set_tenant_writes(
+        &mut self,
+        prefix: &[u8],
+        writes: &TenantWrites,
+    ) -> Result<(), ManyError> {
+        self.persistent_store
+            .apply(&[(
+                key_for_tenant_writes(prefix),
+                Op::Put(
+                    minicbor::to_vec(writes)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            )])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store
+                .commit(&[])
+                .expect("Could not commit to store.");
+        }
+        Ok(())
+    }
+
+    /// The number of keys with a value under a prefix, and the size of their
+    /// values in bytes.
+    pub fn prefix_size(&self, prefix: &[u8]) -> Result<(u64, u64), ManyError> {
+        let root = vec![KVSTORE_ROOT, prefix].concat();
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_lower_bound(root.clone());
+        // The root is a single byte, so this bound always exists.
+        if let Some(upper) = prefix_end(&root) {
+            opts.set_iterate_upper_bound(upper);
+        }
+
+        let mut size = (0, 0);
+        for item in self.persistent_store.iter_opt(IteratorMode::Start, opts) {
+            let (k, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            size.0 += 1;
+            size.1 += Tree::decode(k.to_vec(), v.as_ref()).value().len() as u64;
+        }
+        Ok(size)
+    }
+}
@@ -0,0 +1,269 @@
+use super::account::ACCOUNTS_ROOT;
+use super::event::EVENTS_ROOT;
+use super::{KvStoreStorage, KVSTORE_ACL_ROOT, KVSTORE_ROOT};
+use crate::error;
+use crate::module::verify::{Discrepancy, DiscrepancyKind, VerifyCursor, VerifyPhase};
+use crate::module::KvStoreMetadata;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events::EventInfo;
+use many_storage::event_schema::decode_event;
+use many_types::{CborRange, Either, SortOrder};
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::Tree;
+use merk::Op;
+use std::ops::Bound;
+use std::str::FromStr;
+use tracing::info;
+
+/// Returns the first key after all keys starting with the prefix.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    *end.last_mut().unwrap() += 1;
+    end
+}
+
+impl KvStoreStorage {
+    /// Read committed records between two keys. Verification only reads
+    /// committed data, so it is not confused by a block being executed.
+    fn scan(
+        &self,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, ManyError> {
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_lower_bound(lower);
+        opts.set_iterate_upper_bound(upper);
+
+        self.persistent_store
+            .iter_opt(IteratorMode::Start, opts)
+            .take(limit)
+            .map(|item| {
+                item.map(|(k, v)| {
+                    let value = Tree::decode(k.to_vec(), v.as_ref()).value().to_vec();
+                    (k.to_vec(), value)
+                })
+                .map_err(|e| ManyError::unknown(e.to_string()))
+            })
+            .collect()
+    }
+
+    fn get_committed(&self, key: &[u8]) -> Result<Option<Vec<u8>>, ManyError> {
+        let upper = vec![key, &[0]].concat();
+        Ok(self
+            .scan(key.to_vec(), upper, 1)?
+            .pop()
+            .map(|(_, value)| value))
+    }
+
+    fn get_committed_u64(&self, key: &[u8]) -> Result<Option<u64>, ManyError> {
+        Ok(self.get_committed(key)?.and_then(|x| {
+            let bytes: [u8; 8] = x.as_slice().try_into().ok()?;
+            Some(u64::from_be_bytes(bytes))
+        }))
+    }
+
+    /// Rebuild the metadata of a key from the last event that wrote it.
+    fn metadata_from_events(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let meta = self
+            .iter(
+                CborRange {
+                    start: Bound::Unbounded,
+                    end: Bound::Unbounded,
+                },
+                SortOrder::Descending,
+            )
+            .filter_map(|item| decode_event(&item.ok()?.1).ok())
+            .find_map(|event| match event.content {
+                EventInfo::KvStorePut { key: k, owner, .. } if k.as_slice() == key => {
+                    Some(KvStoreMetadata {
+                        owner,
+                        disabled: Some(Either::Left(false)),
+                    })
+                }
+                EventInfo::KvStoreDisable {
+                    key: k,
+                    owner,
+                    reason,
+                } if k.as_slice() == key => Some(KvStoreMetadata {
+                    owner,
+                    disabled: Some(reason.map_or(Either::Left(true), Either::Right)),
+                }),
+                _ => None,
+            })?;
+        minicbor::to_vec(meta).ok()
+    }
+
+    /// Verify a chunk of at most `limit` records, starting at the cursor.
+    /// Returns the discrepancies found and the cursor to continue from, if
+    /// the store was not verified completely.
+    pub fn verify(
+        &self,
+        sender: &Address,
+        mut cursor: VerifyCursor,
+        limit: usize,
+    ) -> Result<(Vec<Discrepancy>, Option<VerifyCursor>), ManyError> {
+        if sender != &self.account_identity {
+            return Err(error::verify_unauthorized());
+        }
+
+        let mut discrepancies = Vec::new();
+        let mut budget = limit.max(1);
+        loop {
+            let prefix = match cursor.phase {
+                VerifyPhase::Values => KVSTORE_ROOT,
+                VerifyPhase::Metadata => KVSTORE_ACL_ROOT,
+                VerifyPhase::Events => EVENTS_ROOT,
+                VerifyPhase::Accounts => ACCOUNTS_ROOT,
+            };
+            let lower = cursor
+                .key
+                .as_ref()
+                .map_or_else(|| prefix.to_vec(), |k| vec![k.as_slice(), &[0]].concat());
+
+            let records = self.scan(lower, prefix_end(prefix), budget)?;
+            budget -= records.len();
+            for (key, value) in records {
+                let record = &key[prefix.len()..];
+                match cursor.phase {
+                    VerifyPhase::Values => self.verify_value(record, &mut discrepancies)?,
+                    VerifyPhase::Metadata => {
+                        if minicbor::decode::<KvStoreMetadata>(&value).is_err() {
+                            discrepancies.push(Discrepancy {
+                                kind: DiscrepancyKind::InvalidMetadata,
+                                key: key.clone().into(),
+                                actual: Some(value.into()),
+                                expected: self.metadata_from_events(record).map(Into::into),
+                            });
+                        }
+                    }
+                    VerifyPhase::Events => cursor.count += 1,
+                    VerifyPhase::Accounts => {
+                        let id = std::str::from_utf8(record)
+                            .ok()
+                            .and_then(|s| Address::from_str(s).ok())
+                            .and_then(|a| a.subresource_id());
+                        if let Some(id) = id {
+                            cursor.count = cursor.count.max(id as u64 + 1);
+                        }
+                    }
+                }
+                cursor.key = Some(key.into());
+            }
+
+            if budget == 0 {
+                return Ok((discrepancies, Some(cursor)));
+            }
+
+            self.verify_counters(&cursor, &mut discrepancies)?;
+            cursor = match cursor.phase.next() {
+                Some(phase) => VerifyCursor {
+                    phase,
+                    key: None,
+                    count: 0,
+                },
+                None => return Ok((discrepancies, None)),
+            };
+        }
+    }
+
+    fn verify_value(
+        &self,
+        record: &[u8],
+        discrepancies: &mut Vec<Discrepancy>,
+    ) -> Result<(), ManyError> {
+        let meta_key = vec![KVSTORE_ACL_ROOT, record].concat();
+        let actual = self.get_committed(&meta_key)?;
+        let kind = match &actual {
+            None => DiscrepancyKind::MissingMetadata,
+            Some(cbor) => match minicbor::decode::<KvStoreMetadata>(cbor) {
+                Ok(meta) if meta.owner.is_none() => DiscrepancyKind::MissingOwner,
+                // Undecodable metadata is reported when verifying metadata.
+                _ => return Ok(()),
+            },
+        };
+
+        discrepancies.push(Discrepancy {
+            kind,
+            key: meta_key.into(),
+            actual: actual.map(Into::into),
+            expected: self.metadata_from_events(record).map(Into::into),
+        });
+        Ok(())
+    }
+
+    /// Compare the counters with what was counted once a phase is done.
+    fn verify_counters(
+        &self,
+        cursor: &VerifyCursor,
+        discrepancies: &mut Vec<Discrepancy>,
+    ) -> Result<(), ManyError> {
+        match cursor.phase {
+            VerifyPhase::Events => {
+                let stored = self.get_committed_u64(b"/events_count")?;
+                if stored.unwrap_or(0) != cursor.count {
+                    discrepancies.push(Discrepancy {
+                        kind: DiscrepancyKind::EventCount,
+                        key: b"/events_count".to_vec().into(),
+                        actual: stored.map(|x| x.to_be_bytes().to_vec().into()),
+                        expected: Some(cursor.count.to_be_bytes().to_vec().into()),
+                    });
+                }
+            }
+            VerifyPhase::Accounts => {
+                let stored = self.get_committed(b"/config/account_id")?;
+                let next_id = stored.as_ref().and_then(|x| {
+                    let bytes: [u8; 4] = x.as_slice().try_into().ok()?;
+                    Some(u32::from_be_bytes(bytes) as u64)
+                });
+                if next_id.unwrap_or(0) < cursor.count {
+                    discrepancies.push(Discrepancy {
+                        kind: DiscrepancyKind::AccountCounter,
+                        key: b"/config/account_id".to_vec().into(),
+                        actual: stored.map(Into::into),
+                        expected: Some((cursor.count as u32).to_be_bytes().to_vec().into()),
+                    });
+                }
+            }
+            VerifyPhase::Values | VerifyPhase::Metadata => {}
+        }
+        Ok(())
+    }
+
+    /// Write the expected data of the discrepancies that can be repaired,
+    /// and commit. This should only be used offline.
+    pub fn repair(&mut self, discrepancies: &[Discrepancy]) -> Result<(), ManyError> {
+        let mut batch: Vec<_> = discrepancies
+            .iter()
+            .filter_map(|d| {
+                let expected = d.expected.as_ref()?;
+                info!(
+                    "repairing {:?} at {}",
+                    d.kind,
+                    hex::encode(d.key.as_slice())
+                );
+                Some((d.key.to_vec(), Op::Put(expected.to_vec())))
+            })
+            .collect();
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        batch.dedup_by(|(a, _), (b, _)| a == b);
+
+        self.persistent_store
+            .apply(&batch)
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        self.persistent_store
+            .commit(&[])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if let Some(d) = discrepancies
+            .iter()
+            .find(|d| d.kind == DiscrepancyKind::AccountCounter && d.expected.is_some())
+        {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(d.expected.as_ref().unwrap());
+            self.next_account_id = u32::from_be_bytes(bytes);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use crate::feed::{ChangeRecord, Sink};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+/// The header with the HMAC-SHA256 of the body, keyed with the secret of the
+/// webhook, as `sha256=<hex>`.
+pub const SIGNATURE_HEADER: &str = "X-Many-Signature";
+
+/// The header with the name of the webhook.
+pub const NAME_HEADER: &str = "X-Many-Webhook";
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_min_backoff_ms() -> u64 {
+    100
+}
+
+fn default_max_backoff_ms() -> u64 {
+    60_000
+}
+
+fn default_max_queue() -> usize {
+    1_000
+}
+
+/// How the changes are delivered to a webhook.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct DeliveryPolicy {
+    /// The number of times a failed delivery is retried before it is given
+    /// up.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+
+    /// The delay before the first retry. It doubles on every failure, up to
+    /// `max_backoff_ms`.
+    #[serde(default = "default_min_backoff_ms")]
+    pub min_backoff_ms: u64,
+
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+
+    /// The number of changes waiting to be delivered after which new ones
+    /// are dropped.
+    #[serde(default = "default_max_queue")]
+    pub max_queue: usize,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            min_backoff_ms: default_min_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            max_queue: default_max_queue(),
+        }
+    }
+}
+
+/// A webhook, as registered in the webhooks file. It is called on the
+/// changes of a single key, or of every key starting with a prefix.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct HookConfig {
+    pub name: String,
+    pub key: Option<String>,
+    pub prefix: Option<String>,
+    pub url: String,
+
+    /// The key of the HMAC of the bodies sent.
+    pub secret: String,
+
+    #[serde(default)]
+    pub policy: DeliveryPolicy,
+}
+
+impl HookConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.key.is_some() == self.prefix.is_some() {
+            return Err(format!(
+                "Webhook {} must have either a key or a prefix.",
+                self.name
+            ));
+        }
+        if self.secret.is_empty() {
+            return Err(format!("Webhook {} has an empty secret.", self.name));
+        }
+        if self.policy.max_queue == 0 {
+            return Err(format!("Webhook {} has a queue of 0 changes.", self.name));
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, key: &[u8]) -> bool {
+        match (&self.key, &self.prefix) {
+            (Some(k), _) => k.as_bytes() == key,
+            (_, Some(prefix)) => key.starts_with(prefix.as_bytes()),
+            _ => false,
+        }
+    }
+}
+
+/// The HMAC-SHA256 of a body, as sent in `SIGNATURE_HEADER`.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Counters of the deliveries of a webhook, shared with whoever reports
+/// them.
+#[derive(Debug, Default)]
+pub struct HookMetrics {
+    /// The number of changes waiting to be delivered.
+    pub queued: AtomicU64,
+
+    pub delivered: AtomicU64,
+
+    /// The number of changes given up after all their retries failed.
+    pub failed: AtomicU64,
+
+    /// The number of attempts which failed, retried or not.
+    pub failed_attempts: AtomicU64,
+
+    /// The number of changes dropped because the queue was full.
+    pub dropped: AtomicU64,
+
+    last_error: Mutex<Option<(SystemTime, String)>>,
+}
+
+impl HookMetrics {
+    /// The last failed attempt, and when it happened.
+    pub fn last_error(&self) -> Option<(SystemTime, String)> {
+        self.last_error.lock().ok().and_then(|last| last.clone())
+    }
+}
+
+pub struct Hook {
+    pub config: HookConfig,
+    pub metrics: HookMetrics,
+    queue: Mutex<VecDeque<ChangeRecord>>,
+    ready: Condvar,
+}
+
+impl Hook {
+    fn new(config: HookConfig) -> Self {
+        Self {
+            config,
+            metrics: HookMetrics::default(),
+            queue: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn enqueue(&self, record: &ChangeRecord) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= self.config.policy.max_queue {
+            warn!(
+                "webhook {}: {} changes queued, dropping the change of {}",
+                self.config.name,
+                queue.len(),
+                hex::encode(&record.key)
+            );
+            self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        queue.push_back(record.clone());
+        self.metrics
+            .queued
+            .store(queue.len() as u64, Ordering::Relaxed);
+        self.ready.notify_one();
+    }
+
+    fn next(&self) -> ChangeRecord {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(record) = queue.pop_front() {
+                self.metrics
+                    .queued
+                    .store(queue.len() as u64, Ordering::Relaxed);
+                return record;
+            }
+            queue = self.ready.wait(queue).unwrap();
+        }
+    }
+
+    /// POST a change, signed.
+    fn post(&self, client: &reqwest::blocking::Client, body: &[u8]) -> Result<(), String> {
+        let response = client
+            .post(&self.config.url)
+            .header("Content-Type", "application/json")
+            .header(NAME_HEADER, &self.config.name)
+            .header(SIGNATURE_HEADER, signature(&self.config.secret, body))
+            .body(body.to_vec())
+            .send()
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} returned {}",
+                self.config.url,
+                response.status()
+            ))
+        }
+    }
+
+    /// Deliver a change, retrying with backoff per the policy of the
+    /// webhook. Returns whether it was delivered.
+    fn deliver(&self, client: &reqwest::blocking::Client, record: &ChangeRecord) -> bool {
+        let body = match serde_json::to_vec(record) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("webhook {}: {}", self.config.name, e);
+                self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        };
+
+        let policy = &self.config.policy;
+        let mut delay = Duration::from_millis(policy.min_backoff_ms);
+        for attempt in 0..=policy.max_retries {
+            match self.post(client, &body) {
+                Ok(()) => {
+                    debug!(
+                        "webhook {}: delivered {}",
+                        self.config.name,
+                        hex::encode(&record.id)
+                    );
+                    self.metrics.delivered.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                Err(e) => {
+                    self.metrics.failed_attempts.fetch_add(1, Ordering::Relaxed);
+                    if let Ok(mut last) = self.metrics.last_error.lock() {
+                        *last = Some((SystemTime::now(), e.clone()));
+                    }
+                    if attempt == policy.max_retries {
+                        warn!(
+                            "webhook {}: {}; giving up the change {}",
+                            self.config.name,
+                            e,
+                            hex::encode(&record.id)
+                        );
+                        break;
+                    }
+                    warn!(
+                        "webhook {}: {}; retrying in {:?}",
+                        self.config.name, e, delay
+                    );
+                    std::thread::sleep(delay);
+                    delay = (delay * 2).min(Duration::from_millis(policy.max_backoff_ms));
+                }
+            }
+        }
+        self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    fn run(&self, client: reqwest::blocking::Client) {
+        loop {
+            let record = self.next();
+            self.deliver(&client, &record);
+        }
+    }
+}
+
+/// Calls back URLs when the keys they registered for change.
+///
+/// The webhooks are registered in a file local to the node, not in the
+/// state of the chain. They are a sink of the change feed, which fans the
+/// committed changes out to the queues of the webhooks they match. Each
+/// webhook is then called on its own thread, one change at a time and in
+/// order, so a slow or failing one never holds back the feed or the other
+/// webhooks. A change is retried with backoff, then given up. Changes still
+/// queued when the node stops are not delivered.
+pub struct Webhooks {
+    hooks: Vec<Arc<Hook>>,
+}
+
+impl Webhooks {
+    pub fn new(configs: Vec<HookConfig>) -> Result<Self, String> {
+        let mut names = std::collections::BTreeSet::new();
+        for config in &configs {
+            config.validate()?;
+            if !names.insert(config.name.as_str()) {
+                return Err(format!("Duplicate webhook {}.", config.name));
+            }
+        }
+        Ok(Self {
+            hooks: configs
+                .into_iter()
+                .map(|c| Arc::new(Hook::new(c)))
+                .collect(),
+        })
+    }
+
+    /// Read the webhooks from a JSON5 file with an array of `HookConfig`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path.as_ref()).map_err(|e| e.to_string())?;
+        Self::new(json5::from_str(&contents).map_err(|e| e.to_string())?)
+    }
+
+    pub fn hooks(&self) -> &[Arc<Hook>] {
+        &self.hooks
+    }
+
+    /// Start calling the webhooks, each on a background thread.
+    pub fn spawn(&self) -> Result<(), String> {
+        for hook in &self.hooks {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .map_err(|e| e.to_string())?;
+            let hook = hook.clone();
+            std::thread::spawn(move || hook.run(client));
+        }
+        Ok(())
+    }
+
+    /// The sink of the change feed fanning changes out to the webhooks.
+    pub fn sink(self: &Arc<Self>) -> WebhookSink {
+        WebhookSink(self.clone())
+    }
+}
+
+pub struct WebhookSink(Arc<Webhooks>);
+
+impl Sink for WebhookSink {
+    /// Only queues the changes, so it never fails.
+    fn deliver(&mut self, records: &[ChangeRecord]) -> Result<(), String> {
+        for record in records {
+            for hook in &self.0.hooks {
+                if hook.config.matches(&record.key) {
+                    hook.enqueue(record);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+pub mod common;
+
+use crate::common::*;
+use many_error::Reason;
+use many_identity::testing::identity;
+use many_kvstore::error;
+use many_kvstore::module::bulk_disable::{
+    DisablePrefixArgs, KvStoreBulkDisableModuleBackend, PendingDisablesArgs,
+    BULK_DISABLE_KEYS_PER_BLOCK,
+};
+use many_kvstore::module::list::{KvStoreListModuleBackend, ListArgs};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_types::Either;
+
+fn key(i: usize) -> Vec<u8> {
+    format!("logs/{:05}", i).into_bytes()
+}
+
+fn disable_prefix(setup: &mut Setup, prefix: &[u8]) -> u64 {
+    setup
+        .module_impl
+        .disable_prefix(
+            &identity(1),
+            DisablePrefixArgs {
+                prefix: prefix.to_vec().into(),
+                alternative_owner: None,
+                reason: Some(Reason::new(
+                    1,
+                    Some("Expired".to_string()),
+                    Default::default(),
+                )),
+            },
+        )
+        .unwrap()
+        .id
+}
+
+fn pending(setup: &Setup) -> Vec<(u64, u64)> {
+    setup
+        .module_impl
+        .pending_disables(&identity(1), PendingDisablesArgs::default())
+        .unwrap()
+        .pending
+        .into_iter()
+        .map(|d| (d.id, d.disabled))
+        .collect()
+}
+
+fn hash(setup: &Setup) -> Vec<u8> {
+    ManyAbciModuleBackend::info(&setup.module_impl)
+        .unwrap()
+        .hash
+        .to_vec()
+}
+
+/// The keys of identity(1) under `logs/`, with one of identity(2) and one
+/// outside of the prefix.
+fn filled(count: usize) -> Setup {
+    let mut setup = Setup::new(true);
+    setup.block(|setup| {
+        for i in 0..count {
+            setup.put(&identity(1), key(i), vec![1], None).unwrap();
+        }
+        setup
+            .put(&identity(2), b"logs/other".to_vec(), vec![2], None)
+            .unwrap();
+        setup
+            .put(&identity(1), b"kept".to_vec(), vec![3], None)
+            .unwrap();
+    });
+    setup
+}
+
+#[test]
+fn replicas_converge() {
+    let count = BULK_DISABLE_KEYS_PER_BLOCK * 2 + 500;
+    let mut replicas = [filled(count), filled(count)];
+    assert_eq!(hash(&replicas[0]), hash(&replicas[1]));
+
+    for replica in replicas.iter_mut() {
+        replica.block(|setup| assert_eq!(disable_prefix(setup, b"logs/"), 0));
+    }
+    assert_eq!(hash(&replicas[0]), hash(&replicas[1]));
+    assert_eq!(pending(&replicas[0]), vec![(0, 0)]);
+
+    let mut blocks = 0;
+    while !pending(&replicas[0]).is_empty() {
+        for replica in replicas.iter_mut() {
+            replica.block(|_| {});
+        }
+        assert_eq!(hash(&replicas[0]), hash(&replicas[1]));
+        assert_eq!(pending(&replicas[0]), pending(&replicas[1]));
+        blocks += 1;
+    }
+    assert_eq!(blocks, 3);
+}
+
+#[test]
+fn disabled_mid_drain() {
+    let count = BULK_DISABLE_KEYS_PER_BLOCK + 10;
+    let mut setup = filled(count);
+    setup.block(|setup| disable_prefix(setup, b"logs/"));
+
+    // Nothing was processed, yet every key reads as disabled.
+    assert_eq!(pending(&setup), vec![(0, 0)]);
+    for i in [0, count / 2, count - 1] {
+        assert_many_err(setup.get(&identity(1), key(i)), error::key_disabled());
+        assert!(matches!(
+            setup.query(&identity(1), key(i)).unwrap().disabled,
+            Some(Either::Right(_))
+        ));
+    }
+    assert!(setup.get(&identity(2), b"logs/other".to_vec()).is_ok());
+    assert!(setup.get(&identity(1), b"kept".to_vec()).is_ok());
+
+    // The first chunk.
+    setup.block(|_| {});
+    assert_eq!(
+        pending(&setup),
+        vec![(0, BULK_DISABLE_KEYS_PER_BLOCK as u64)]
+    );
+    let entries = setup
+        .module_impl
+        .list(
+            &identity(1),
+            ListArgs {
+                prefix: b"logs/".to_vec().into(),
+                after: Some(key(count - 20).into()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .entries;
+    assert_eq!(
+        entries
+            .iter()
+            .filter(|e| e.owner == Some(identity(1)))
+            .count(),
+        19
+    );
+    assert!(entries
+        .iter()
+        .all(|e| e.disabled == (e.owner == Some(identity(1)))));
+
+    // Keys not processed yet cannot change.
+    assert_many_err(
+        setup.put(&identity(1), key(count - 1), vec![4], None),
+        error::bulk_disable_pending("0".to_string()),
+    );
+
+    setup.block(|_| {});
+    assert!(pending(&setup).is_empty());
+    assert_many_err(
+        setup.get(&identity(1), key(count - 1)),
+        error::key_disabled(),
+    );
+    setup
+        .put(&identity(1), key(count - 1), vec![4], None)
+        .unwrap();
+    assert_eq!(
+        setup.get(&identity(1), key(count - 1)).unwrap().value,
+        Some(vec![4].into())
+    );
+}
+
+#[test]
+fn immediate_outside_blockchain() {
+    let mut setup = setup();
+    for i in 0..10 {
+        setup.put(&identity(1), key(i), vec![1], None).unwrap();
+    }
+    disable_prefix(&mut setup, b"logs/");
+
+    assert!(pending(&setup).is_empty());
+    for i in 0..10 {
+        assert_many_err(setup.get(&identity(1), key(i)), error::key_disabled());
+    }
+}
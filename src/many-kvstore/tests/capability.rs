@@ -0,0 +1,259 @@
+pub mod common;
+
+use crate::common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::{Address, Identity};
+use many_identity_dsa::ecdsa::generate_random_ecdsa_identity;
+use many_identity_dsa::CoseKeyIdentity;
+use many_kvstore::error;
+use many_kvstore::module::capability::{
+    Capability, CapabilityPutModule, KvStoreCapabilityModuleBackend, RevokeCapabilityArgs,
+    SignedCapability, CAPABILITY,
+};
+use many_kvstore::module::quota::{KvStoreQuotaModuleBackend, UsageArgs};
+use many_modules::abci_backend::{AbciBlock, ManyAbciModuleBackend};
+use many_modules::kvstore::{KvStoreCommandsModule, PutArgs};
+use many_modules::ManyModule;
+use many_protocol::RequestMessageBuilder;
+use many_types::cbor::CborAny;
+use many_types::Timestamp;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+const NOW: u64 = 1_000_000;
+
+/// Execute a block at a given time, in seconds.
+fn at<R>(setup: &mut Setup, secs: u64, f: impl FnOnce(&mut Setup) -> R) -> R {
+    setup
+        .module_impl
+        .begin_block(AbciBlock { time: Some(secs) })
+        .unwrap();
+    let r = f(setup);
+    setup.module_impl.end_block().unwrap();
+    setup.module_impl.commit().unwrap();
+    r
+}
+
+fn capability(issuer: &CoseKeyIdentity) -> Capability {
+    Capability {
+        issuer: issuer.address(),
+        prefix: b"telemetry/".to_vec().into(),
+        max_value_size: 10,
+        expiry: Timestamp::new(NOW + 100).unwrap(),
+        max_uses: 2,
+    }
+}
+
+fn put(setup: &mut Setup, token: &[u8], key: &[u8], value: &[u8]) -> Result<(), ManyError> {
+    let capability = SignedCapability::verify(token)?;
+    setup
+        .module_impl
+        .put_with_capability(
+            &identity(7),
+            capability,
+            PutArgs {
+                key: key.to_vec().into(),
+                value: value.to_vec().into(),
+                alternative_owner: None,
+            },
+        )
+        .map(|_| ())
+}
+
+#[test]
+fn delegated_put() {
+    let mut setup = setup();
+    let issuer = generate_random_ecdsa_identity();
+    let token = capability(&issuer).sign(&issuer).unwrap();
+
+    at(&mut setup, NOW, |setup| {
+        put(setup, &token, b"telemetry/a", b"42").unwrap();
+    });
+
+    // The value is owned by the issuer, not the device.
+    let query = setup.query(&identity(7), b"telemetry/a".to_vec()).unwrap();
+    assert_eq!(query.owner, Some(issuer.address()));
+    let value = setup.get(&identity(7), b"telemetry/a".to_vec()).unwrap();
+    assert_eq!(value.value.unwrap().as_slice(), b"42");
+}
+
+#[test]
+fn expiry() {
+    let mut setup = setup();
+    let issuer = generate_random_ecdsa_identity();
+    let token = capability(&issuer).sign(&issuer).unwrap();
+
+    at(&mut setup, NOW + 99, |setup| {
+        put(setup, &token, b"telemetry/a", b"1").unwrap();
+    });
+    at(&mut setup, NOW + 100, |setup| {
+        assert_many_err(
+            put(setup, &token, b"telemetry/a", b"2"),
+            error::capability_expired((NOW + 100).to_string()),
+        );
+    });
+}
+
+#[test]
+fn uses_exhausted() {
+    let mut setup = setup();
+    let issuer = generate_random_ecdsa_identity();
+    let token = capability(&issuer).sign(&issuer).unwrap();
+
+    at(&mut setup, NOW, |setup| {
+        put(setup, &token, b"telemetry/a", b"1").unwrap();
+        put(setup, &token, b"telemetry/b", b"2").unwrap();
+        assert_many_err(
+            put(setup, &token, b"telemetry/c", b"3"),
+            error::capability_exhausted("2".to_string()),
+        );
+    });
+
+    // Signing the same capability again does not reset its uses.
+    let token = capability(&issuer).sign(&issuer).unwrap();
+    at(&mut setup, NOW, |setup| {
+        assert_many_err(
+            put(setup, &token, b"telemetry/c", b"3"),
+            error::capability_exhausted("2".to_string()),
+        );
+    });
+
+    // Failed puts do not use the capability.
+    let mut other = capability(&issuer);
+    other.max_uses = 1;
+    let token = other.sign(&issuer).unwrap();
+    at(&mut setup, NOW, |setup| {
+        assert!(put(setup, &token, b"other/a", b"1").is_err());
+        put(setup, &token, b"telemetry/a", b"1").unwrap();
+    });
+}
+
+#[test]
+fn revocation() {
+    let mut setup = setup();
+    let issuer = generate_random_ecdsa_identity();
+    let token = capability(&issuer).sign(&issuer).unwrap();
+    let revoke = |setup: &mut Setup, sender: &Address| {
+        setup.module_impl.revoke_capability(
+            sender,
+            RevokeCapabilityArgs {
+                token: token.clone().into(),
+            },
+        )
+    };
+
+    at(&mut setup, NOW, |setup| {
+        put(setup, &token, b"telemetry/a", b"1").unwrap();
+        assert_many_err(
+            revoke(setup, &identity(7)),
+            error::capability_revoke_denied(),
+        );
+        revoke(setup, &issuer.address()).unwrap();
+        assert_many_err(
+            put(setup, &token, b"telemetry/b", b"2"),
+            error::capability_revoked(),
+        );
+    });
+}
+
+#[test]
+fn constraints() {
+    let mut setup = setup();
+    let issuer = generate_random_ecdsa_identity();
+    let token = capability(&issuer).sign(&issuer).unwrap();
+
+    at(&mut setup, NOW, |setup| {
+        assert_many_err(
+            put(setup, &token, b"config/a", b"1"),
+            error::capability_key_denied(hex::encode(b"telemetry/")),
+        );
+        assert_many_err(
+            put(setup, &token, b"telemetry/a", &[0; 11]),
+            error::capability_value_too_large("10".to_string()),
+        );
+        put(setup, &token, b"telemetry/a", &[0; 10]).unwrap();
+    });
+}
+
+#[test]
+fn invalid_signature() {
+    let issuer = generate_random_ecdsa_identity();
+    let other = generate_random_ecdsa_identity();
+
+    // Only the issuer can sign its capability.
+    assert!(capability(&issuer).sign(&other).is_err());
+
+    let mut token = capability(&issuer).sign(&issuer).unwrap();
+    let last = token.len() - 1;
+    token[last] ^= 1;
+    assert!(SignedCapability::verify(&token).is_err());
+    assert!(SignedCapability::verify(b"garbage").is_err());
+}
+
+#[tokio::test]
+async fn module() {
+    let setup = setup_with_migrations(USAGE_MIGRATION);
+    let issuer = generate_random_ecdsa_identity();
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let module = |allowed: Address| {
+        CapabilityPutModule::new(
+            KvStoreCommandsModule::new(backend.clone()),
+            backend.clone(),
+            Some(BTreeSet::from([allowed])),
+        )
+    };
+    let request = |method: &str, token: Vec<u8>| {
+        let mut request = RequestMessageBuilder::default()
+            .from(identity(7))
+            .method(method.to_string())
+            .data(
+                minicbor::to_vec(PutArgs {
+                    key: b"telemetry/a".to_vec().into(),
+                    value: b"42".to_vec().into(),
+                    alternative_owner: None,
+                })
+                .unwrap(),
+            )
+            .build()
+            .unwrap();
+        request
+            .attributes
+            .insert(CAPABILITY.with_argument(CborAny::Bytes(token)));
+        request
+    };
+    let token = capability(&issuer).sign(&issuer).unwrap();
+    backend
+        .lock()
+        .unwrap()
+        .begin_block(AbciBlock { time: Some(NOW) })
+        .unwrap();
+
+    // The issuer must be allowed to put.
+    let response = module(identity(1))
+        .execute(request("kvstore.put", token.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.data, Err(error::capability_issuer_denied()));
+
+    // Only puts can be delegated.
+    let response = module(issuer.address())
+        .execute(request("kvstore.disable", token.clone()))
+        .await
+        .unwrap();
+    assert!(response.data.is_err());
+
+    let response = module(issuer.address())
+        .execute(request("kvstore.put", token))
+        .await
+        .unwrap();
+    assert!(response.data.is_ok());
+
+    // The value counts in the usage of the issuer.
+    let usage = backend
+        .lock()
+        .unwrap()
+        .usage(&issuer.address(), UsageArgs::default())
+        .unwrap();
+    assert_eq!(usage.usage, 2);
+}
@@ -2,6 +2,13 @@ use many_error::{ManyError, Reason};
 use many_identity::testing::identity;
 use many_identity::{Address, Identity};
 use many_identity_dsa::ecdsa::generate_random_ecdsa_identity;
+use many_kvstore::module::json_patch::{
+    JsonPrefix, KvStoreJsonPatchModuleBackend, SetJsonPrefixesArgs,
+};
+use many_kvstore::module::quota::{KvStoreQuotaModuleBackend, SetQuotaArgs};
+use many_kvstore::module::tenant::{
+    KvStoreTenantModuleBackend, ReservedPrefix, SetReservedPrefixesArgs,
+};
 use many_kvstore::module::KvStoreModuleImpl;
 use many_modules::abci_backend::{AbciBlock, ManyAbciModuleBackend};
 use many_modules::account;
@@ -20,6 +27,10 @@ pub static MFX_SYMBOL: Lazy<Address> = Lazy::new(|| {
     Address::from_str("mqbfbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wiaaaaqnz").unwrap()
 });
 
+/// The identity of the store in the staging initial state.
+pub static STORE_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
 pub fn assert_many_err<I: std::fmt::Debug + PartialEq>(r: Result<I, ManyError>, err: ManyError) {
     assert_eq!(r, Err(err));
 }
@@ -51,6 +62,34 @@ impl Setup {
         }
     }
 
+    pub fn with_quota(mut self, quota: u64) -> Self {
+        self.module_impl
+            .set_quota(&STORE_IDENTITY, SetQuotaArgs { quota: Some(quota) })
+            .expect("Could not set the quota");
+        self
+    }
+
+    pub fn with_migrations(mut self, migrations: &str) -> Self {
+        self.module_impl = self
+            .module_impl
+            .with_migrations(json5::from_str(migrations).unwrap());
+        self
+    }
+
+    pub fn with_json_prefixes(mut self, prefixes: Vec<JsonPrefix>) -> Self {
+        self.module_impl
+            .set_json_prefixes(&STORE_IDENTITY, SetJsonPrefixesArgs { prefixes })
+            .expect("Could not set the JSON prefixes");
+        self
+    }
+
+    pub fn with_reserved_prefixes(mut self, prefixes: Vec<ReservedPrefix>) -> Self {
+        self.module_impl
+            .set_reserved_prefixes(&STORE_IDENTITY, SetReservedPrefixesArgs { prefixes })
+            .expect("Could not reserve the prefixes");
+        self
+    }
+
     /// Execute a block begin+inner_f+end+commit.
     /// See https://docs.tendermint.com/master/spec/abci/abci.html#block-execution
     pub fn block<R>(&mut self, inner_f: impl FnOnce(&mut Self) -> R) -> (u64, R) {
@@ -124,6 +163,19 @@ pub fn setup() -> Setup {
     Setup::default()
 }
 
+/// The migration writing the derived fields of keys.
+pub const DERIVED_MIGRATION: &str = r#"[{ type: "KvStoreDerived", block_height: 1 }]"#;
+
+/// The migration counting the usage of owners.
+pub const USAGE_MIGRATION: &str = r#"[{ type: "KvStoreUsage", block_height: 1 }]"#;
+
+/// A setup whose migrations are active, after its first block.
+pub fn setup_with_migrations(migrations: &str) -> Setup {
+    let mut setup = Setup::default().with_migrations(migrations);
+    setup.block(|_| {});
+    setup
+}
+
 #[derive(Clone)]
 #[non_exhaustive]
 pub enum AccountType {
@@ -216,6 +268,16 @@ pub struct SetupWithAccount {
 }
 
 impl SetupWithAccount {
+    /// Activate migrations, after the next block.
+    pub fn with_migrations(self, migrations: &str) -> Self {
+        let mut inner = self.inner.into_inner().with_migrations(migrations);
+        inner.block(|_| {});
+        Self {
+            inner: RefCell::new(inner),
+            account_id: self.account_id,
+        }
+    }
+
     pub fn put(
         &mut self,
         sender: &Address,
@@ -0,0 +1,203 @@
+pub mod common;
+
+use crate::common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::counter::{
+    CounterAddArgs, CounterCreateArgs, CounterValue, KvStoreCounterModuleBackend, QueryKindArgs,
+    ValueKind,
+};
+use many_modules::kvstore::KvStoreModuleBackend;
+use minicbor::bytes::ByteVec;
+use num_bigint::BigInt;
+
+fn create(
+    setup: &mut Setup,
+    sender: &Address,
+    key: &[u8],
+    signed: bool,
+) -> Result<(), many_error::ManyError> {
+    setup
+        .module_impl
+        .counter_create(
+            sender,
+            CounterCreateArgs {
+                key: key.to_vec(),
+                signed,
+                alternative_owner: None,
+            },
+        )
+        .map(|_| ())
+}
+
+fn add(
+    setup: &mut Setup,
+    sender: &Address,
+    key: &[u8],
+    delta: BigInt,
+) -> Result<BigInt, many_error::ManyError> {
+    setup
+        .module_impl
+        .counter_add(
+            sender,
+            CounterAddArgs {
+                key: key.to_vec(),
+                delta: CounterValue(delta),
+                alternative_owner: None,
+            },
+        )
+        .map(|r| r.value.0)
+}
+
+fn kind(setup: &Setup, key: &[u8]) -> ValueKind {
+    setup
+        .module_impl
+        .query_kind(&identity(1), QueryKindArgs { key: key.to_vec() })
+        .unwrap()
+        .kind
+}
+
+#[test]
+fn value_encoding() {
+    assert_eq!(minicbor::to_vec(CounterValue::from(-1)).unwrap(), [0x20]);
+    assert_eq!(
+        minicbor::to_vec(CounterValue(BigInt::from(u64::MAX))).unwrap()[0],
+        0xc2
+    );
+    for value in [
+        BigInt::from(0),
+        BigInt::from(i64::MIN),
+        BigInt::from(u64::MAX),
+        BigInt::from(u128::MAX),
+        -BigInt::from(u128::MAX),
+    ] {
+        let bytes = minicbor::to_vec(CounterValue(value.clone())).unwrap();
+        assert_eq!(
+            minicbor::decode::<CounterValue>(&bytes).unwrap(),
+            CounterValue(value)
+        );
+    }
+}
+
+#[test]
+fn create_and_add() {
+    let mut setup = setup();
+    let id = setup.id;
+    create(&mut setup, &id, b"hits", false).unwrap();
+    assert_eq!(kind(&setup, b"hits"), ValueKind::Counter);
+    assert_eq!(
+        setup.get(&id, b"hits".to_vec()).unwrap().value,
+        Some(ByteVec::from(vec![0]))
+    );
+
+    assert_eq!(
+        add(&mut setup, &id, b"hits", BigInt::from(300)),
+        Ok(BigInt::from(300))
+    );
+    assert_eq!(
+        add(&mut setup, &id, b"hits", BigInt::from(-44)),
+        Ok(BigInt::from(256))
+    );
+    assert_eq!(
+        setup.get(&id, b"hits".to_vec()).unwrap().value,
+        Some(ByteVec::from(vec![1, 0]))
+    );
+
+    // The key cannot be created again, nor put.
+    assert_many_err(create(&mut setup, &id, b"hits", true), error::key_exists());
+    assert_many_err(
+        setup.put(&id, b"hits".to_vec(), vec![1], None),
+        error::key_is_counter(),
+    );
+}
+
+#[test]
+fn underflow() {
+    let mut setup = setup();
+    let id = setup.id;
+    create(&mut setup, &id, b"unsigned", false).unwrap();
+    add(&mut setup, &id, b"unsigned", BigInt::from(1)).unwrap();
+    assert_many_err(
+        add(&mut setup, &id, b"unsigned", BigInt::from(-2)),
+        error::counter_underflow(),
+    );
+    assert_eq!(
+        add(&mut setup, &id, b"unsigned", BigInt::from(-1)),
+        Ok(BigInt::from(0))
+    );
+
+    create(&mut setup, &id, b"signed", true).unwrap();
+    assert_eq!(kind(&setup, b"signed"), ValueKind::SignedCounter);
+    assert_eq!(
+        add(&mut setup, &id, b"signed", BigInt::from(-129)),
+        Ok(BigInt::from(-129))
+    );
+    assert_eq!(
+        setup.get(&id, b"signed".to_vec()).unwrap().value,
+        Some(ByteVec::from(vec![0xff, 0x7f]))
+    );
+}
+
+#[test]
+fn not_a_counter() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.put(&id, b"plain".to_vec(), vec![1], None).unwrap();
+    assert_eq!(kind(&setup, b"plain"), ValueKind::Bytes);
+    assert_many_err(
+        add(&mut setup, &id, b"plain", BigInt::from(1)),
+        error::not_a_counter(),
+    );
+    assert_many_err(
+        add(&mut setup, &id, b"missing", BigInt::from(1)),
+        error::key_not_found(),
+    );
+}
+
+#[test]
+fn permission_denied() {
+    let mut setup = setup();
+    let id = setup.id;
+    create(&mut setup, &id, b"hits", false).unwrap();
+    assert_many_err(
+        add(&mut setup, &identity(5), b"hits", BigInt::from(1)),
+        error::permission_denied(),
+    );
+}
+
+/// Run many adds over several blocks, and return the final value and hash.
+fn hammer() -> (BigInt, Vec<u8>) {
+    let mut setup = Setup::new(true);
+    let id = identity(1);
+    setup.block(|setup| create(setup, &id, b"hits", true).unwrap());
+
+    let mut expected = BigInt::from(0);
+    for block in 0..10i64 {
+        setup.block(|setup| {
+            for i in 0..100i64 {
+                let delta = if i % 3 == 0 {
+                    BigInt::from(-(block * i))
+                } else {
+                    BigInt::from(u64::MAX) * (block + i)
+                };
+                expected += &delta;
+                assert_eq!(add(setup, &id, b"hits", delta), Ok(expected.clone()));
+            }
+        });
+    }
+
+    let value = setup.get(&id, b"hits".to_vec()).unwrap().value.unwrap();
+    assert_eq!(BigInt::from_signed_bytes_be(&value), expected);
+    let hash = setup
+        .module_impl
+        .info(&id, many_modules::kvstore::InfoArg {})
+        .unwrap()
+        .hash;
+    (expected, hash.to_vec())
+}
+
+#[test]
+fn many_adds() {
+    assert_eq!(hammer(), hammer());
+}
@@ -0,0 +1,259 @@
+pub mod common;
+
+use crate::common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::counter::{CounterCreateArgs, KvStoreCounterModuleBackend};
+use many_kvstore::module::derived::{
+    DerivedMetadata, KvStoreDerivedModuleBackend, PutWithContentTypeArgs, QueryDerivedArgs,
+};
+use many_kvstore::module::list::{KvStoreListModuleBackend, ListArgs};
+use many_kvstore::module::policy::PolicyOverrides;
+use many_modules::kvstore::PutArgs;
+use sha2::Digest;
+
+fn sha256(value: &[u8]) -> Vec<u8> {
+    sha2::Sha256::digest(value).to_vec()
+}
+
+fn derived(setup: &Setup, sender: &Address, key: &[u8]) -> Result<DerivedMetadata, ManyError> {
+    setup.module_impl.query_derived(
+        sender,
+        QueryDerivedArgs {
+            key: key.to_vec().into(),
+        },
+    )
+}
+
+fn put_typed(
+    setup: &mut Setup,
+    sender: &Address,
+    key: &[u8],
+    value: &[u8],
+    content_type: &str,
+) -> Result<(), ManyError> {
+    setup
+        .module_impl
+        .put_with_content_type(
+            sender,
+            PutWithContentTypeArgs {
+                key: key.to_vec().into(),
+                value: value.to_vec().into(),
+                alternative_owner: None,
+                content_type: content_type.to_string(),
+            },
+        )
+        .map(|_| ())
+}
+
+#[test]
+fn not_enabled() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup
+        .put(&id, b"a".to_vec(), b"hello".to_vec(), None)
+        .unwrap();
+    assert_many_err(derived(&setup, &id, b"a"), error::derived_not_enabled());
+    assert_many_err(
+        put_typed(&mut setup, &id, b"b", b"{}", "application/json"),
+        error::derived_not_enabled(),
+    );
+
+    // Keys put before the migration have no derived fields.
+    let mut setup = setup.with_migrations(DERIVED_MIGRATION);
+    setup.block(|_| {});
+    assert_many_err(derived(&setup, &id, b"a"), error::key_not_found());
+    setup
+        .put(&id, b"a".to_vec(), b"hello".to_vec(), None)
+        .unwrap();
+    assert_eq!(derived(&setup, &id, b"a").unwrap().length, 5);
+}
+
+#[test]
+fn put_and_overwrite() {
+    let mut setup = setup_with_migrations(DERIVED_MIGRATION);
+    let id = setup.id;
+    setup
+        .put(&id, b"a".to_vec(), b"hello".to_vec(), None)
+        .unwrap();
+    assert_eq!(
+        derived(&setup, &id, b"a").unwrap(),
+        DerivedMetadata {
+            hash: sha256(b"hello").into(),
+            length: 5,
+            content_type: None,
+        }
+    );
+
+    setup
+        .put(&id, b"a".to_vec(), b"%PDF-1.4 ...".to_vec(), None)
+        .unwrap();
+    assert_eq!(
+        derived(&setup, &id, b"a").unwrap(),
+        DerivedMetadata {
+            hash: sha256(b"%PDF-1.4 ...").into(),
+            length: 12,
+            content_type: Some("application/pdf".to_string()),
+        }
+    );
+
+    // The type is recognized from the extension of the key too.
+    setup
+        .put(&id, b"b.json".to_vec(), b"{}".to_vec(), None)
+        .unwrap();
+    assert_eq!(
+        derived(&setup, &id, b"b.json").unwrap().content_type,
+        Some("application/json".to_string())
+    );
+
+    assert_many_err(derived(&setup, &id, b"missing"), error::key_not_found());
+}
+
+#[test]
+fn declared_content_type() {
+    let mut setup = setup_with_migrations(DERIVED_MIGRATION);
+    let id = setup.id;
+    put_typed(
+        &mut setup,
+        &id,
+        b"a.json",
+        b"{}",
+        "text/plain; charset=utf-8",
+    )
+    .unwrap();
+    assert_eq!(
+        setup.get(&id, b"a.json".to_vec()).unwrap().value,
+        Some(b"{}".to_vec().into())
+    );
+    assert_eq!(
+        derived(&setup, &id, b"a.json").unwrap(),
+        DerivedMetadata {
+            hash: sha256(b"{}").into(),
+            length: 2,
+            content_type: Some("text/plain; charset=utf-8".to_string()),
+        }
+    );
+
+    // An overwrite without a declared type sniffs it again.
+    setup
+        .put(&id, b"a.json".to_vec(), b"[]".to_vec(), None)
+        .unwrap();
+    assert_eq!(
+        derived(&setup, &id, b"a.json").unwrap().content_type,
+        Some("application/json".to_string())
+    );
+
+    for invalid in ["", "text", "/plain", "text/", "text/\nplain"] {
+        assert_eq!(
+            put_typed(&mut setup, &id, b"b", b"", invalid)
+                .unwrap_err()
+                .code(),
+            error::invalid_content_type("").code()
+        );
+    }
+    assert!(setup.get(&id, b"b".to_vec()).unwrap().value.is_none());
+
+    // The usual put checks apply.
+    assert_eq!(
+        put_typed(&mut setup, &identity(1), b"a.json", b"", "text/plain")
+            .unwrap_err()
+            .code(),
+        error::permission_denied().code()
+    );
+}
+
+#[test]
+fn readers_only() {
+    let mut setup = setup_with_migrations(DERIVED_MIGRATION);
+    let id = setup.id;
+    setup
+        .module_impl
+        .put_with_overrides(
+            &id,
+            PutArgs {
+                key: b"private".to_vec().into(),
+                value: b"secret".to_vec().into(),
+                alternative_owner: None,
+            },
+            PolicyOverrides {
+                public: Some(false),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert!(derived(&setup, &id, b"private").is_ok());
+    assert_eq!(
+        derived(&setup, &identity(1), b"private")
+            .unwrap_err()
+            .code(),
+        error::read_denied().code()
+    );
+}
+
+#[test]
+fn counters_have_none() {
+    let mut setup = setup_with_migrations(DERIVED_MIGRATION);
+    let id = setup.id;
+    setup
+        .module_impl
+        .counter_create(
+            &id,
+            CounterCreateArgs {
+                key: b"c".to_vec(),
+                signed: false,
+                alternative_owner: None,
+            },
+        )
+        .unwrap();
+    assert_many_err(derived(&setup, &id, b"c"), error::key_not_found());
+}
+
+fn keys_with_hash(setup: &Setup, sender: &Address, hash: &[u8]) -> Vec<Vec<u8>> {
+    let entries = setup
+        .module_impl
+        .list(
+            sender,
+            ListArgs {
+                prefix: b"k/".to_vec().into(),
+                hash: Some(hash.to_vec().into()),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .entries;
+    assert!(entries.iter().all(|e| e.hash == Some(hash.to_vec().into())));
+    entries.into_iter().map(|e| e.key.to_vec()).collect()
+}
+
+#[test]
+fn list_by_hash() {
+    let mut setup = setup_with_migrations(DERIVED_MIGRATION);
+    let id = setup.id;
+    setup
+        .put(&id, b"k/1".to_vec(), b"same".to_vec(), None)
+        .unwrap();
+    setup
+        .put(&id, b"k/2".to_vec(), b"other".to_vec(), None)
+        .unwrap();
+    setup
+        .put(&id, b"k/3".to_vec(), b"same".to_vec(), None)
+        .unwrap();
+
+    assert_eq!(
+        keys_with_hash(&setup, &id, &sha256(b"same")),
+        vec![b"k/1".to_vec(), b"k/3".to_vec()]
+    );
+    assert!(keys_with_hash(&setup, &id, &sha256(b"none")).is_empty());
+
+    // An overwrite removes the key from the duplicates.
+    setup
+        .put(&id, b"k/3".to_vec(), b"new".to_vec(), None)
+        .unwrap();
+    assert_eq!(
+        keys_with_hash(&setup, &id, &sha256(b"same")),
+        vec![b"k/1".to_vec()]
+    );
+}
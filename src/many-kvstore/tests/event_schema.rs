@@ -0,0 +1,152 @@
+pub mod common;
+
+use common::*;
+use many_error::Reason;
+use many_identity::testing::identity;
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::events::{self, EventId, EventInfo, EventLog, EventsModuleBackend};
+use many_modules::ManyModule;
+use many_protocol::RequestMessageBuilder;
+use many_storage::event_schema::{
+    decode_event, decoded_versions, encode_event, EventSchemaModule, EVENT_SCHEMA,
+    EVENT_SCHEMA_VERSION, OLDEST_EVENT_SCHEMA_VERSION,
+};
+use many_types::cbor::CborAny;
+use many_types::Timestamp;
+use std::sync::{Arc, Mutex};
+
+/// One event of each payload logged by the key-value store.
+///
+/// The payloads are listed field by field, so this stops compiling when one
+/// of them changes. When it does, bump `EVENT_SCHEMA_VERSION`, add a decoder
+/// upgrading the previous version, and add the new version to `fixture`.
+fn samples() -> Vec<EventLog> {
+    let contents = vec![
+        EventInfo::KvStorePut {
+            key: b"invoices/42".to_vec().into(),
+            value: vec![1, 2, 3].into(),
+            owner: Some(identity(1)),
+        },
+        EventInfo::KvStoreDisable {
+            key: b"invoices/42".to_vec().into(),
+            owner: Some(identity(1)),
+            reason: Some(Reason::new(1, Some("Paid".to_string()), Default::default())),
+        },
+    ];
+    contents
+        .into_iter()
+        .enumerate()
+        .map(|(i, content)| EventLog {
+            id: EventId::from(vec![1, i as u8]),
+            time: Timestamp::new(1_700_000_000 + i as u64).unwrap(),
+            content,
+        })
+        .collect()
+}
+
+/// An event as stored with a schema version.
+fn fixture(version: u64, event: &EventLog) -> Vec<u8> {
+    let payload = minicbor::to_vec(event).unwrap();
+    match version {
+        // Unversioned.
+        0 => payload,
+        // `[1, event]`.
+        1 => [vec![0x82, 0x01], payload].concat(),
+        _ => panic!("No fixture for the event schema version {}.", version),
+    }
+}
+
+#[test]
+fn decoders_cover_versions() {
+    assert_eq!(
+        decoded_versions() as u64,
+        EVENT_SCHEMA_VERSION - OLDEST_EVENT_SCHEMA_VERSION + 1
+    );
+}
+
+#[test]
+fn encode_decode() {
+    for event in samples() {
+        for version in OLDEST_EVENT_SCHEMA_VERSION..=EVENT_SCHEMA_VERSION {
+            assert_eq!(
+                encode_event(&event, version).unwrap(),
+                fixture(version, &event)
+            );
+        }
+
+        let expected = minicbor::to_vec(&event).unwrap();
+        for version in OLDEST_EVENT_SCHEMA_VERSION..=EVENT_SCHEMA_VERSION {
+            let decoded = decode_event(&fixture(version, &event)).unwrap();
+            assert_eq!(minicbor::to_vec(&decoded).unwrap(), expected, "{}", version);
+        }
+    }
+}
+
+/// A put in a block, returning the hash of the store after it.
+fn put_block(setup: &mut Setup) -> Vec<u8> {
+    let id = setup.id;
+    setup.block(|setup| setup.put(&id, vec![1], vec![2], None).unwrap());
+    ManyAbciModuleBackend::info(&setup.module_impl)
+        .unwrap()
+        .hash
+        .to_vec()
+}
+
+#[test]
+fn versioned_after_migration() {
+    const MIGRATIONS: &str = r#"[{ type: "VersionedEvents", block_height: 3 }]"#;
+    let mut unmigrated = Setup::new(true);
+    let mut setup = Setup::new(true).with_migrations(MIGRATIONS);
+    setup.id = unmigrated.id;
+
+    // Events are logged as before until the migration is active.
+    assert_eq!(put_block(&mut setup), put_block(&mut unmigrated));
+    assert_eq!(put_block(&mut setup), put_block(&mut unmigrated));
+    assert_ne!(put_block(&mut setup), put_block(&mut unmigrated));
+
+    // Events of both versions are listed.
+    put_block(&mut setup);
+    let returns = setup
+        .module_impl
+        .list(events::ListArgs {
+            count: None,
+            order: None,
+            filter: None,
+        })
+        .unwrap();
+    assert_eq!(returns.events.len(), 4);
+}
+
+#[tokio::test]
+async fn info_versions() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.put(&id, vec![1], vec![2], None).unwrap();
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let module = EventSchemaModule::new(events::EventsModule::new(backend));
+
+    let response = module
+        .execute(
+            RequestMessageBuilder::default()
+                .from(id)
+                .method("events.info".to_string())
+                .data(minicbor::to_vec(events::InfoArgs {}).unwrap())
+                .build()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let info: events::InfoReturn = minicbor::decode(&response.data.unwrap()).unwrap();
+    assert_eq!(info.total, 1);
+    assert_eq!(
+        response
+            .attributes
+            .get_attribute(EVENT_SCHEMA.id)
+            .unwrap()
+            .arguments,
+        vec![
+            CborAny::Int(OLDEST_EVENT_SCHEMA_VERSION as i64),
+            CborAny::Int(EVENT_SCHEMA_VERSION as i64)
+        ]
+    );
+}
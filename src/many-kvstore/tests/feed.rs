@@ -0,0 +1,297 @@
+use many_identity::testing::identity;
+use many_kvstore::feed::{ChangeRecord, Feed, FileSink, HttpSink, Operation, Sink, BATCH_SIZE};
+use many_kvstore::module::KvStoreModuleImpl;
+use many_modules::abci_backend::{AbciBlock, ManyAbciModuleBackend};
+use many_modules::events::{self, EventsModuleBackend};
+use many_modules::kvstore::{DisableArgs, KvStoreCommandsModuleBackend, PutArgs};
+use sha3::{Digest, Sha3_256};
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const STATE: &str = r#"{
+    identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+    acl: {}
+}"#;
+
+fn module(blockchain: bool) -> Arc<Mutex<KvStoreModuleImpl>> {
+    let path = tempfile::tempdir().unwrap().into_path();
+    Arc::new(Mutex::new(
+        KvStoreModuleImpl::new(json5::from_str(STATE).unwrap(), path, blockchain).unwrap(),
+    ))
+}
+
+fn put(module: &Arc<Mutex<KvStoreModuleImpl>>, key: &[u8], value: &[u8]) {
+    module
+        .lock()
+        .unwrap()
+        .put(
+            &identity(1),
+            PutArgs {
+                key: key.to_vec().into(),
+                value: value.to_vec().into(),
+                alternative_owner: None,
+            },
+        )
+        .unwrap();
+}
+
+/// The IDs of the mutation events in the event log.
+fn log(module: &Arc<Mutex<KvStoreModuleImpl>>) -> Vec<Vec<u8>> {
+    let module = module.lock().unwrap();
+    let mut ids = vec![];
+    loop {
+        let list = module
+            .list(events::ListArgs {
+                count: Some(100),
+                order: None,
+                filter: Some(events::EventFilter {
+                    id_range: ids.last().map(|id: &Vec<u8>| many_types::CborRange {
+                        start: std::ops::Bound::Excluded(events::EventId::from(id.clone())),
+                        end: std::ops::Bound::Unbounded,
+                    }),
+                    ..events::EventFilter::default()
+                }),
+            })
+            .unwrap();
+        if list.events.is_empty() {
+            return ids;
+        }
+        ids.extend(list.events.iter().map(|e| e.id.as_ref().to_vec()));
+    }
+}
+
+fn records(path: &Path) -> Vec<ChangeRecord> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect()
+}
+
+fn ids(records: &[ChangeRecord]) -> Vec<Vec<u8>> {
+    records.iter().map(|r| r.id.clone()).collect()
+}
+
+/// Poll until the feed delivered everything.
+fn drain(feed: &mut Feed<KvStoreModuleImpl>) {
+    while feed.poll() > 0 {}
+}
+
+/// A sink which fails a number of times before recording deliveries.
+struct FlakySink {
+    failures: usize,
+    delivered: Arc<Mutex<Vec<ChangeRecord>>>,
+}
+
+impl Sink for FlakySink {
+    fn deliver(&mut self, records: &[ChangeRecord]) -> Result<(), String> {
+        if self.failures > 0 {
+            self.failures -= 1;
+            return Err("Unavailable".to_string());
+        }
+        self.delivered.lock().unwrap().extend_from_slice(records);
+        Ok(())
+    }
+}
+
+#[test]
+fn file() {
+    let module = module(false);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("feed.ndjson");
+    put(&module, b"foo", b"value");
+    put(&module, b"bar", b"value");
+    module
+        .lock()
+        .unwrap()
+        .disable(
+            &identity(1),
+            DisableArgs {
+                key: b"foo".to_vec().into(),
+                alternative_owner: None,
+                reason: None,
+            },
+        )
+        .unwrap();
+
+    let mut feed = Feed::new(module.clone(), dir.path().join("cursors"))
+        .unwrap()
+        .with_sink("file", FileSink::new(&path, u64::MAX))
+        .unwrap();
+    assert_eq!(feed.poll(), 3);
+    assert_eq!(feed.poll(), 0);
+
+    let records = records(&path);
+    assert_eq!(ids(&records), log(&module));
+    assert_eq!(records[0].key, b"foo");
+    assert_eq!(records[0].operation, Operation::Put);
+    assert_eq!(records[0].actor, Some(identity(1).to_string()));
+    assert_eq!(
+        records[0].value_hash,
+        Some(hex::encode(Sha3_256::digest(b"value")))
+    );
+    assert_eq!(records[2].operation, Operation::Disable);
+    assert_eq!(records[2].value_hash, None);
+}
+
+#[test]
+fn resume() {
+    let module = module(false);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("feed.ndjson");
+    let cursors = dir.path().join("cursors");
+    for i in 0..BATCH_SIZE + 50 {
+        put(&module, format!("key{}", i).as_bytes(), b"value");
+    }
+
+    let mut feed = Feed::new(module.clone(), &cursors)
+        .unwrap()
+        .with_sink("file", FileSink::new(&path, u64::MAX))
+        .unwrap();
+    assert_eq!(feed.poll() as u64, BATCH_SIZE);
+    drop(feed);
+
+    // Crash after appending the first batch, but before saving its cursor.
+    let delivered = records(&path);
+    std::fs::write(cursors.join("file.cursor"), hex::encode(&delivered[49].id)).unwrap();
+
+    for i in 0..10 {
+        put(&module, format!("more{}", i).as_bytes(), b"value");
+    }
+    let mut feed = Feed::new(module.clone(), &cursors)
+        .unwrap()
+        .with_sink("file", FileSink::new(&path, u64::MAX))
+        .unwrap();
+    drain(&mut feed);
+
+    // Every event is delivered in order; only the unsaved batch is repeated.
+    let records = ids(&records(&path));
+    let log = log(&module);
+    assert_eq!(records.len(), log.len() + 50);
+    assert_eq!(records[..100], log[..100]);
+    assert_eq!(records[100..], log[50..]);
+}
+
+#[test]
+fn retry() {
+    let module = module(false);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("feed.ndjson");
+    let delivered = Arc::new(Mutex::new(vec![]));
+    put(&module, b"foo", b"value");
+
+    let mut feed = Feed::new(module.clone(), dir.path())
+        .unwrap()
+        .with_sink(
+            "flaky",
+            FlakySink {
+                failures: 1,
+                delivered: delivered.clone(),
+            },
+        )
+        .unwrap()
+        .with_sink("file", FileSink::new(&path, u64::MAX))
+        .unwrap();
+
+    // A failing sink does not hold back the others, and waits before
+    // retrying.
+    assert_eq!(feed.poll(), 1);
+    assert_eq!(records(&path).len(), 1);
+    assert_eq!(feed.poll(), 0);
+    assert!(delivered.lock().unwrap().is_empty());
+
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(feed.poll(), 1);
+    assert_eq!(ids(&delivered.lock().unwrap()), log(&module));
+}
+
+#[test]
+fn rotate() {
+    let module = module(false);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("feed.ndjson");
+    put(&module, b"foo", b"value");
+
+    let mut feed = Feed::new(module.clone(), dir.path())
+        .unwrap()
+        .with_sink("file", FileSink::new(&path, 1))
+        .unwrap();
+    assert_eq!(feed.poll(), 1);
+
+    let id = hex::encode(&log(&module)[0]);
+    assert!(!path.exists());
+    assert_eq!(
+        ids(&records(&dir.path().join(format!("feed.ndjson.{}", id)))),
+        log(&module)
+    );
+}
+
+#[test]
+fn committed_only() {
+    let module = module(true);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("feed.ndjson");
+    let mut feed = Feed::new(module.clone(), dir.path())
+        .unwrap()
+        .with_sink("file", FileSink::new(&path, u64::MAX))
+        .unwrap();
+
+    {
+        let mut m = module.lock().unwrap();
+        m.init().unwrap();
+        m.begin_block(AbciBlock { time: Some(1) }).unwrap();
+    }
+    put(&module, b"foo", b"value");
+    assert_eq!(feed.poll(), 0);
+
+    {
+        let mut m = module.lock().unwrap();
+        m.end_block().unwrap();
+        m.commit().unwrap();
+    }
+    assert_eq!(feed.poll(), 1);
+    assert_eq!(ids(&records(&path)), log(&module));
+}
+
+#[test]
+fn http() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/feed", server.server_addr());
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let b = bodies.clone();
+    std::thread::spawn(move || {
+        for (i, mut request) in server.incoming_requests().enumerate() {
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            // The first request fails.
+            let status = if i == 0 { 503 } else { 200 };
+            if status == 200 {
+                b.lock().unwrap().push(body);
+            }
+            request.respond(tiny_http::Response::empty(status)).unwrap();
+        }
+    });
+
+    let module = module(false);
+    let dir = tempfile::tempdir().unwrap();
+    put(&module, b"foo", b"value");
+    put(&module, b"bar", b"value");
+
+    let mut feed = Feed::new(module.clone(), dir.path())
+        .unwrap()
+        .with_sink("http", HttpSink::new(url).unwrap())
+        .unwrap();
+    assert_eq!(feed.poll(), 0);
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(feed.poll(), 2);
+
+    let bodies = bodies.lock().unwrap();
+    assert_eq!(bodies.len(), 1);
+    let records: Vec<ChangeRecord> = bodies[0]
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(ids(&records), log(&module));
+}
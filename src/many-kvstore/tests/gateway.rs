@@ -0,0 +1,104 @@
+pub mod common;
+
+use crate::common::{setup, Setup};
+use many_kvstore::gateway::{decode_key, guess_content_type, Gateway};
+use many_kvstore::module::KvStoreModuleImpl;
+use reqwest::StatusCode;
+use std::sync::{Arc, Mutex};
+
+/// Start a gateway on a random port and return its base URL.
+fn start(module_impl: KvStoreModuleImpl) -> String {
+    let gateway = Gateway::new(
+        "127.0.0.1:0".parse().unwrap(),
+        Arc::new(Mutex::new(module_impl)),
+    )
+    .unwrap();
+    let url = format!("http://{}", gateway.addr());
+    gateway.spawn();
+    url
+}
+
+#[test]
+fn keys() {
+    assert_eq!(decode_key("0x010203"), Some(vec![1, 2, 3]));
+    assert_eq!(decode_key("foo%2Fbar.txt"), Some(b"foo/bar.txt".to_vec()));
+    assert_eq!(decode_key("foo/bar"), Some(b"foo/bar".to_vec()));
+    assert_eq!(decode_key("0xZZ"), None);
+    assert_eq!(decode_key("foo%2"), None);
+}
+
+#[test]
+fn content_types() {
+    assert_eq!(guess_content_type(b"a.json", b"{}"), "application/json");
+    assert_eq!(
+        guess_content_type(b"a", b"\x89PNG\r\n\x1a\n..."),
+        "image/png"
+    );
+    assert_eq!(guess_content_type(b"a.txt", b"%PDF-1.4"), "application/pdf");
+    assert_eq!(guess_content_type(b"a", b"..."), "application/octet-stream");
+}
+
+#[tokio::test]
+async fn get_and_head() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup
+        .put(&id, b"site/index.html".to_vec(), b"hello".to_vec(), None)
+        .unwrap();
+    let Setup { module_impl, .. } = setup;
+    let url = start(module_impl);
+
+    let response = reqwest::get(format!("{}/store/site%2Findex.html", url))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/html");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"hello");
+
+    let hex_url = format!("{}/store/0x{}", url, hex::encode(b"site/index.html"));
+    let response = reqwest::Client::new().head(hex_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-many-size"], "5");
+    assert_eq!(response.headers()["x-many-owner"], id.to_string().as_str());
+    assert_eq!(response.headers()["content-length"], "5");
+}
+
+#[tokio::test]
+async fn missing_and_disabled() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup
+        .put(&id, b"gone".to_vec(), b"x".to_vec(), None)
+        .unwrap();
+    setup.disable(&id, b"gone".to_vec(), None, None).unwrap();
+    let Setup { module_impl, .. } = setup;
+    let url = start(module_impl);
+
+    let status = |path: &'static str| {
+        let url = url.clone();
+        async move {
+            reqwest::get(format!("{}{}", url, path))
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+    assert_eq!(status("/store/missing").await, StatusCode::NOT_FOUND);
+    assert_eq!(status("/store/gone").await, StatusCode::GONE);
+    assert_eq!(status("/store/0xZZ").await, StatusCode::BAD_REQUEST);
+    assert_eq!(status("/other").await, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn read_only() {
+    let Setup { module_impl, .. } = setup();
+    let url = start(module_impl);
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/store/foo", url))
+        .body("bar")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
@@ -0,0 +1,140 @@
+use many_identity::testing::identity;
+use many_storage::integrity::{Corruption, CorruptionKind, Scrubber};
+use many_kvstore::module::KvStoreModuleImpl;
+use many_modules::kvstore::{KvStoreCommandsModuleBackend, PutArgs};
+use merk::rocksdb::{Options, DB};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+const STATE: &str = r#"{
+    identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+    acl: {}
+}"#;
+
+/// Create a store with a few values, and return its path.
+fn fixture() -> PathBuf {
+    let path = tempfile::tempdir().unwrap().into_path();
+    let mut module_impl =
+        KvStoreModuleImpl::new(json5::from_str(STATE).unwrap(), path.clone(), false).unwrap();
+    for key in ["foo", "bar", "baz", "qux", "quux"] {
+        module_impl
+            .put(
+                &identity(1),
+                PutArgs {
+                    key: key.as_bytes().to_vec().into(),
+                    value: b"value".to_vec().into(),
+                    alternative_owner: None,
+                },
+            )
+            .unwrap();
+    }
+    path
+}
+
+/// Change the raw bytes of a node of the merk tree, behind its back.
+fn corrupt(path: &Path, key: &[u8], f: impl FnOnce(&mut Vec<u8>)) {
+    let opts = Options::default();
+    let db = DB::open_cf(&opts, path, DB::list_cf(&opts, path).unwrap()).unwrap();
+    let mut bytes = db.get(key).unwrap().unwrap();
+    f(&mut bytes);
+    db.put(key, bytes).unwrap();
+}
+
+fn scrubber(path: PathBuf, slice_size: u64) -> Scrubber<KvStoreModuleImpl> {
+    let module_impl = KvStoreModuleImpl::load(path.clone(), false).unwrap();
+    Scrubber::new(
+        Arc::new(Mutex::new(module_impl)),
+        path.with_extension("cursor"),
+        slice_size,
+    )
+    .unwrap()
+}
+
+/// Check the whole tree once, and return the corruptions found.
+fn full_pass(scrubber: &mut Scrubber<KvStoreModuleImpl>) -> Vec<Corruption> {
+    let metrics = scrubber.metrics();
+    let passes = metrics.passes.load(Ordering::Relaxed);
+    let mut corruptions = vec![];
+    while metrics.passes.load(Ordering::Relaxed) == passes {
+        corruptions.append(&mut scrubber.poll().unwrap().unwrap());
+    }
+    corruptions
+}
+
+#[test]
+fn clean() {
+    let mut scrubber = scrubber(fixture(), 2);
+    assert_eq!(full_pass(&mut scrubber), vec![]);
+    assert_eq!(scrubber.cursor(), None);
+
+    let metrics = scrubber.metrics();
+    assert!(metrics.nodes_checked.load(Ordering::Relaxed) > 5);
+    assert_eq!(metrics.corruptions.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn value() {
+    let path = fixture();
+    corrupt(&path, b"sfoo", |bytes| *bytes.last_mut().unwrap() ^= 0xff);
+
+    let mut scrubber = scrubber(path, 2);
+    assert_eq!(
+        full_pass(&mut scrubber),
+        vec![Corruption::new(CorruptionKind::ValueHash, b"sfoo", b"sfoo")]
+    );
+    assert_eq!(scrubber.metrics().corruptions.load(Ordering::Relaxed), 1);
+
+    // It is found again on every pass.
+    assert_eq!(full_pass(&mut scrubber).len(), 1);
+}
+
+#[test]
+fn truncated() {
+    let path = fixture();
+    corrupt(&path, b"sbaz", |bytes| bytes.truncate(3));
+
+    let mut scrubber = scrubber(path, 3);
+    assert_eq!(
+        full_pass(&mut scrubber),
+        vec![Corruption::new(
+            CorruptionKind::Undecodable,
+            b"sbaz",
+            b"sbaz"
+        )]
+    );
+}
+
+#[test]
+fn cursor() {
+    let path = fixture();
+    let mut scrubber1 = scrubber(path.clone(), 1);
+    scrubber1.poll().unwrap().unwrap();
+    let cursor = scrubber1.cursor().unwrap().to_vec();
+    drop(scrubber1);
+
+    // A restart resumes the pass where it was.
+    let mut scrubber2 = scrubber(path.clone(), 1);
+    assert_eq!(scrubber2.cursor(), Some(cursor.as_slice()));
+    full_pass(&mut scrubber2);
+    let resumed = scrubber2.metrics().nodes_checked.load(Ordering::Relaxed);
+
+    let mut scrubber3 = scrubber(path, 1);
+    full_pass(&mut scrubber3);
+    assert_eq!(
+        scrubber3.metrics().nodes_checked.load(Ordering::Relaxed),
+        resumed + 1
+    );
+}
+
+#[test]
+fn yields() {
+    let module_impl = KvStoreModuleImpl::load(fixture(), false).unwrap();
+    let backend = Arc::new(Mutex::new(module_impl));
+    let dir = tempfile::tempdir().unwrap();
+    let mut scrubber = Scrubber::new(backend.clone(), dir.path().join("cursor"), 10).unwrap();
+
+    let _block = backend.lock().unwrap();
+    assert_eq!(scrubber.poll().unwrap(), None);
+    assert_eq!(scrubber.cursor(), None);
+}
@@ -0,0 +1,379 @@
+pub mod common;
+
+use crate::common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::derived::KvStoreDerivedModuleBackend;
+use many_kvstore::module::json_patch::{
+    apply_patch, canonical, parse_pointer, JsonPrefix, JsonPrefixesArgs, JsonVersionArgs,
+    KvStoreJsonPatchModuleBackend, PatchArgs, SetJsonPrefixesArgs, JSON_CONTENT_TYPE,
+};
+use many_kvstore::module::KvStoreModuleImpl;
+use serde_json::{json, Value};
+
+fn prefixes() -> Vec<JsonPrefix> {
+    vec![
+        JsonPrefix {
+            prefix: "docs/".to_string(),
+            max_size: None,
+        },
+        JsonPrefix {
+            prefix: "docs/small/".to_string(),
+            max_size: Some(16),
+        },
+    ]
+}
+
+fn json_setup() -> Setup {
+    setup().with_json_prefixes(prefixes())
+}
+
+fn patch(
+    setup: &mut Setup,
+    sender: &Address,
+    key: &[u8],
+    patch: Value,
+    expected_version: Option<u64>,
+) -> Result<u64, ManyError> {
+    setup
+        .module_impl
+        .patch(
+            sender,
+            PatchArgs {
+                key: key.to_vec().into(),
+                patch: patch.to_string(),
+                alternative_owner: None,
+                expected_version,
+            },
+        )
+        .map(|r| r.version)
+}
+
+fn version(setup: &Setup, key: &[u8]) -> Result<u64, ManyError> {
+    setup
+        .module_impl
+        .json_version(
+            &identity(9),
+            JsonVersionArgs {
+                key: key.to_vec().into(),
+            },
+        )
+        .map(|r| r.version)
+}
+
+fn value(setup: &Setup, key: &[u8]) -> String {
+    let value = setup.get(&identity(9), key.to_vec()).unwrap().value;
+    String::from_utf8(value.unwrap().to_vec()).unwrap()
+}
+
+/// Apply a patch to a document, returning the error message on failure.
+fn apply(document: Value, patch: Value) -> Result<Value, String> {
+    apply_patch(&document, &patch.to_string()).map_err(|e| e.to_string())
+}
+
+#[test]
+fn pointers() {
+    assert_eq!(parse_pointer(""), Ok(vec![]));
+    assert_eq!(parse_pointer("/"), Ok(vec!["".to_string()]));
+    assert_eq!(
+        parse_pointer("/a~1b/m~0n/~01"),
+        Ok(vec!["a/b".to_string(), "m~n".to_string(), "~1".to_string()])
+    );
+    assert!(parse_pointer("a").is_err());
+    assert!(parse_pointer("/a~").is_err());
+    assert!(parse_pointer("/a~2").is_err());
+
+    let document = json!({ "": 1, "a/b": 2, "list": [1, 2, 3] });
+    assert_eq!(
+        apply(
+            document.clone(),
+            json!([{ "op": "replace", "path": "/", "value": 0 }])
+        ),
+        Ok(json!({ "": 0, "a/b": 2, "list": [1, 2, 3] }))
+    );
+    assert_eq!(
+        apply(
+            document.clone(),
+            json!([{ "op": "remove", "path": "/a~1b" }])
+        ),
+        Ok(json!({ "": 1, "list": [1, 2, 3] }))
+    );
+
+    // Arrays.
+    let list = |patch| apply(document.clone(), patch).map(|d| d["list"].clone());
+    assert_eq!(
+        list(json!([{ "op": "add", "path": "/list/-", "value": 4 }])),
+        Ok(json!([1, 2, 3, 4]))
+    );
+    assert_eq!(
+        list(json!([{ "op": "add", "path": "/list/0", "value": 0 }])),
+        Ok(json!([0, 1, 2, 3]))
+    );
+    assert_eq!(
+        list(json!([{ "op": "add", "path": "/list/3", "value": 4 }])),
+        Ok(json!([1, 2, 3, 4]))
+    );
+    assert_eq!(
+        list(json!([{ "op": "remove", "path": "/list/1" }])),
+        Ok(json!([1, 3]))
+    );
+    for path in ["/list/4", "/list/01", "/list/+1", "/list/-1"] {
+        assert!(list(json!([{ "op": "add", "path": path, "value": 0 }])).is_err());
+    }
+    for path in ["/list/3", "/list/-", "/list/00"] {
+        assert!(list(json!([{ "op": "remove", "path": path }])).is_err());
+        assert!(list(json!([{ "op": "replace", "path": path, "value": 0 }])).is_err());
+    }
+
+    // Missing and scalar parents.
+    assert_eq!(
+        apply(
+            document.clone(),
+            json!([{ "op": "add", "path": "/x/y", "value": 0 }])
+        ),
+        Err(error::invalid_patch("operation 0: path '/x/y' does not exist").to_string())
+    );
+    assert!(apply(
+        document.clone(),
+        json!([{ "op": "add", "path": "//x", "value": 0 }])
+    )
+    .is_err());
+
+    // The whole document.
+    assert_eq!(
+        apply(
+            document.clone(),
+            json!([{ "op": "add", "path": "", "value": [] }])
+        ),
+        Ok(json!([]))
+    );
+    assert!(apply(document, json!([{ "op": "remove", "path": "" }])).is_err());
+}
+
+#[test]
+fn operations() {
+    let document = json!({ "a": 1 });
+
+    // Operations are applied in order, and all or none are.
+    assert_eq!(
+        apply(
+            document.clone(),
+            json!([
+                { "op": "add", "path": "/b", "value": { "c": [] } },
+                { "op": "add", "path": "/b/c/-", "value": "x" },
+                { "op": "remove", "path": "/a" },
+            ])
+        ),
+        Ok(json!({ "b": { "c": ["x"] } }))
+    );
+    assert_eq!(
+        apply(
+            document.clone(),
+            json!([
+                { "op": "add", "path": "/b", "value": 2 },
+                { "op": "remove", "path": "/c" },
+            ])
+        ),
+        Err(error::invalid_patch("operation 1: path '/c' does not exist").to_string())
+    );
+
+    assert_eq!(
+        apply(
+            document.clone(),
+            json!([{ "op": "move", "from": "/a", "path": "/b" }])
+        ),
+        Err(error::invalid_patch(
+            "operation 0: unsupported op 'move', only add, remove and replace are"
+        )
+        .to_string())
+    );
+    assert!(apply(document.clone(), json!([{ "op": "add", "path": "/b" }])).is_err());
+    assert!(apply(document.clone(), json!([{ "path": "/a" }])).is_err());
+    assert!(apply(document.clone(), json!({ "op": "remove", "path": "/a" })).is_err());
+    assert!(apply_patch(&document, "[").is_err());
+}
+
+#[test]
+fn canonicalization() {
+    let document: Value =
+        serde_json::from_str(r#"{ "b": [ 1.5, {"z": null, "y": true} ], "a": "é\"" }"#).unwrap();
+    let text = canonical(&document);
+    assert_eq!(text, r#"{"a":"é\"","b":[1.5,{"y":true,"z":null}]}"#);
+
+    // Stable across parses, and independent of the order of the members.
+    let parsed: Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(canonical(&parsed), text);
+    let reordered = json!({ "a": "é\"", "b": [1.5, { "z": null, "y": true }] });
+    assert_eq!(canonical(&reordered), text);
+
+    // Patched documents are stored canonicalized.
+    let mut setup = json_setup();
+    let id = setup.id;
+    setup
+        .put(
+            &id,
+            b"docs/a".to_vec(),
+            b"{ \"b\": 1,\n \"a\": 2 }".to_vec(),
+            None,
+        )
+        .unwrap();
+    patch(&mut setup, &id, b"docs/a", json!([]), None).unwrap();
+    assert_eq!(value(&setup, b"docs/a"), r#"{"a":2,"b":1}"#);
+}
+
+#[test]
+fn versions() {
+    let mut setup = json_setup();
+    let id = setup.id;
+    assert_eq!(version(&setup, b"docs/a"), Ok(0));
+
+    setup
+        .put(&id, b"docs/a".to_vec(), br#"{"n":0}"#.to_vec(), None)
+        .unwrap();
+    assert_eq!(version(&setup, b"docs/a"), Ok(1));
+
+    let increment = |n: u64| json!([{ "op": "replace", "path": "/n", "value": n }]);
+    assert_eq!(
+        patch(&mut setup, &id, b"docs/a", increment(1), Some(1)),
+        Ok(2)
+    );
+    assert_eq!(patch(&mut setup, &id, b"docs/a", increment(2), None), Ok(3));
+
+    // A writer which read an older version loses.
+    assert_eq!(
+        patch(&mut setup, &id, b"docs/a", increment(9), Some(2)),
+        Err(error::version_conflict("2", "3"))
+    );
+    assert_eq!(value(&setup, b"docs/a"), r#"{"n":2}"#);
+
+    // Puts are versioned too.
+    setup
+        .put(&id, b"docs/a".to_vec(), br#"{"n":5}"#.to_vec(), None)
+        .unwrap();
+    assert_eq!(
+        patch(&mut setup, &id, b"docs/a", increment(6), Some(3)),
+        Err(error::version_conflict("3", "4"))
+    );
+    assert_eq!(
+        patch(&mut setup, &id, b"docs/a", increment(6), Some(4)),
+        Ok(5)
+    );
+}
+
+#[test]
+fn json_keys() {
+    // A value put before its prefix was declared as JSON.
+    let mut setup = setup_with_migrations(DERIVED_MIGRATION);
+    let id = setup.id;
+    setup
+        .put(&id, b"docs/raw".to_vec(), b"not json".to_vec(), None)
+        .unwrap();
+    let mut setup = setup.with_json_prefixes(prefixes());
+    assert!(matches!(
+        patch(&mut setup, &id, b"docs/raw", json!([]), None),
+        Err(e) if e.code() == error::malformed_json("").code()
+    ));
+
+    setup
+        .put(&id, b"other".to_vec(), b"{}".to_vec(), None)
+        .unwrap();
+    assert_eq!(
+        patch(&mut setup, &id, b"other", json!([]), None),
+        Err(error::not_a_json_key())
+    );
+    assert_eq!(version(&setup, b"other"), Err(error::not_a_json_key()));
+    assert_eq!(
+        patch(&mut setup, &id, b"docs/missing", json!([]), None),
+        Err(error::key_not_found())
+    );
+
+    // Values under a JSON prefix are JSON documents within its size limit.
+    assert!(setup
+        .put(&id, b"docs/a".to_vec(), b"{".to_vec(), None)
+        .is_err());
+    setup
+        .put(
+            &id,
+            b"docs/small/a".to_vec(),
+            br#"{"a":"0123"}"#.to_vec(),
+            None,
+        )
+        .unwrap();
+    assert_eq!(
+        patch(
+            &mut setup,
+            &id,
+            b"docs/small/a",
+            json!([{ "op": "add", "path": "/b", "value": 1 }]),
+            None
+        ),
+        Err(error::json_too_large("18", "16", "docs/small/"))
+    );
+    assert_eq!(value(&setup, b"docs/small/a"), r#"{"a":"0123"}"#);
+    assert_eq!(
+        setup
+            .module_impl
+            .query_derived(
+                &id,
+                many_kvstore::module::derived::QueryDerivedArgs {
+                    key: b"docs/small/a".to_vec().into()
+                }
+            )
+            .unwrap()
+            .content_type
+            .as_deref(),
+        Some(JSON_CONTENT_TYPE)
+    );
+
+    // Only writers of the key can patch it.
+    assert_eq!(
+        patch(&mut setup, &identity(5), b"docs/small/a", json!([]), None),
+        Err(error::permission_denied())
+    );
+}
+
+/// The prefixes are part of the state: set by the initial state, or by the
+/// identity of the store.
+#[test]
+fn prefixes_in_state() {
+    let json_prefixes = |module_impl: &KvStoreModuleImpl| {
+        module_impl
+            .json_prefixes(&identity(9), JsonPrefixesArgs {})
+            .unwrap()
+            .prefixes
+    };
+
+    let mut setup = setup();
+    assert_eq!(json_prefixes(&setup.module_impl), vec![]);
+    let id = setup.id;
+    assert_eq!(
+        setup.module_impl.set_json_prefixes(
+            &id,
+            SetJsonPrefixesArgs {
+                prefixes: prefixes()
+            }
+        ),
+        Err(error::json_prefixes_denied())
+    );
+    let setup = setup.with_json_prefixes(prefixes());
+    assert_eq!(json_prefixes(&setup.module_impl), prefixes());
+
+    let state = json5::from_str(
+        r#"{
+            identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+            acl: {},
+            json_prefixes: [{ prefix: "docs/", max_size: 16 }],
+        }"#,
+    )
+    .unwrap();
+    let module_impl = KvStoreModuleImpl::new(state, tempfile::tempdir().unwrap(), false).unwrap();
+    assert_eq!(
+        json_prefixes(&module_impl),
+        vec![JsonPrefix {
+            prefix: "docs/".to_string(),
+            max_size: Some(16),
+        }]
+    );
+}
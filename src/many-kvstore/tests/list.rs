@@ -0,0 +1,129 @@
+pub mod common;
+
+use crate::common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::module::list::{KvStoreListModuleBackend, ListArgs, ListEntry};
+use many_kvstore::module::policy::PolicyOverrides;
+use many_modules::kvstore::PutArgs;
+use minicbor::bytes::ByteVec;
+use sha2::Digest;
+
+fn list(
+    setup: &Setup,
+    sender: &Address,
+    prefix: &[u8],
+    after: Option<&[u8]>,
+    count: Option<u64>,
+) -> (Vec<ListEntry>, Option<ByteVec>) {
+    let returns = setup
+        .module_impl
+        .list(
+            sender,
+            ListArgs {
+                prefix: prefix.to_vec().into(),
+                after: after.map(|a| a.to_vec().into()),
+                count,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    (returns.entries, returns.next)
+}
+
+fn keys(entries: &[ListEntry]) -> Vec<&[u8]> {
+    entries.iter().map(|e| e.key.as_slice()).collect()
+}
+
+#[test]
+fn prefix() {
+    let mut setup = setup_with_migrations(DERIVED_MIGRATION);
+    let id = setup.id;
+    setup.put(&id, b"app/a".to_vec(), vec![1], None).unwrap();
+    setup
+        .put(&id, b"app/b".to_vec(), vec![1, 2, 3], None)
+        .unwrap();
+    setup.put(&id, b"apq".to_vec(), vec![1], None).unwrap();
+    setup.put(&id, b"other".to_vec(), vec![1], None).unwrap();
+    setup.disable(&id, b"app/b".to_vec(), None, None).unwrap();
+
+    let (entries, next) = list(&setup, &identity(1), b"app/", None, None);
+    assert_eq!(next, None);
+    assert_eq!(
+        entries,
+        vec![
+            ListEntry {
+                key: b"app/a".to_vec().into(),
+                size: 1,
+                owner: Some(id),
+                disabled: false,
+                hash: Some(sha2::Sha256::digest([1]).to_vec().into()),
+                content_type: None,
+            },
+            ListEntry {
+                key: b"app/b".to_vec().into(),
+                size: 3,
+                owner: Some(id),
+                disabled: true,
+                hash: Some(sha2::Sha256::digest([1, 2, 3]).to_vec().into()),
+                content_type: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn pages() {
+    let mut setup = setup();
+    let id = setup.id;
+    for i in 0..5u8 {
+        setup.put(&id, vec![b'k', i], vec![i], None).unwrap();
+    }
+
+    let (entries, next) = list(&setup, &id, b"k", None, Some(2));
+    assert_eq!(keys(&entries), vec![&[b'k', 0][..], &[b'k', 1]]);
+    assert_eq!(next, Some(vec![b'k', 1].into()));
+
+    let (entries, next) = list(&setup, &id, b"k", Some(&[b'k', 1]), Some(2));
+    assert_eq!(keys(&entries), vec![&[b'k', 2][..], &[b'k', 3]]);
+
+    let (entries, next) = list(
+        &setup,
+        &id,
+        b"k",
+        next.as_ref().map(|n| n.as_slice()),
+        Some(2),
+    );
+    assert_eq!(keys(&entries), vec![&[b'k', 4][..]]);
+    assert_eq!(next, None);
+}
+
+#[test]
+fn unreadable_keys_are_skipped() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.put(&id, b"public".to_vec(), vec![1], None).unwrap();
+    setup
+        .module_impl
+        .put_with_overrides(
+            &id,
+            PutArgs {
+                key: b"private".to_vec().into(),
+                value: vec![1].into(),
+                alternative_owner: None,
+            },
+            PolicyOverrides {
+                public: Some(false),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let (entries, _) = list(&setup, &identity(5), b"p", None, None);
+    assert_eq!(keys(&entries), vec![b"public".as_slice()]);
+    let (entries, _) = list(&setup, &id, b"p", None, None);
+    assert_eq!(
+        keys(&entries),
+        vec![b"private".as_slice(), b"public".as_slice()]
+    );
+}
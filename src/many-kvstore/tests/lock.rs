@@ -0,0 +1,254 @@
+pub mod common;
+
+use crate::common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::lock::{
+    KvStoreLockModuleBackend, LockAcquireArgs, LockReleaseArgs, LockRenewArgs, PutWithLockArgs,
+    MAXIMUM_LEASE_SECS,
+};
+use many_modules::abci_backend::{AbciBlock, ManyAbciModuleBackend};
+
+/// Execute a block at a given time, in seconds.
+fn at<R>(setup: &mut Setup, secs: u64, f: impl FnOnce(&mut Setup) -> R) -> R {
+    setup
+        .module_impl
+        .begin_block(AbciBlock { time: Some(secs) })
+        .unwrap();
+    let r = f(setup);
+    setup.module_impl.end_block().unwrap();
+    setup.module_impl.commit().unwrap();
+    r
+}
+
+fn acquire(setup: &mut Setup, sender: &Address, lease_secs: u64) -> Result<u64, ManyError> {
+    setup
+        .module_impl
+        .lock_acquire(
+            sender,
+            LockAcquireArgs {
+                key: b"k".to_vec().into(),
+                lease_secs,
+                alternative_owner: None,
+            },
+        )
+        .map(|r| r.token)
+}
+
+fn renew(
+    setup: &mut Setup,
+    sender: &Address,
+    token: u64,
+    lease_secs: u64,
+) -> Result<u64, ManyError> {
+    setup
+        .module_impl
+        .lock_renew(
+            sender,
+            LockRenewArgs {
+                key: b"k".to_vec().into(),
+                token,
+                lease_secs,
+                alternative_owner: None,
+            },
+        )
+        .map(|r| r.token)
+}
+
+fn release(setup: &mut Setup, sender: &Address, token: u64) -> Result<(), ManyError> {
+    setup
+        .module_impl
+        .lock_release(
+            sender,
+            LockReleaseArgs {
+                key: b"k".to_vec().into(),
+                token,
+                alternative_owner: None,
+            },
+        )
+        .map(|_| ())
+}
+
+fn put_locked(
+    setup: &mut Setup,
+    sender: &Address,
+    value: &[u8],
+    token: Option<u64>,
+    lock_required: Option<bool>,
+) -> Result<(), ManyError> {
+    setup
+        .module_impl
+        .put_with_lock(
+            sender,
+            PutWithLockArgs {
+                key: b"k".to_vec().into(),
+                value: value.to_vec().into(),
+                alternative_owner: None,
+                token,
+                lock_required,
+            },
+        )
+        .map(|_| ())
+}
+
+fn value(setup: &Setup) -> Vec<u8> {
+    setup
+        .get(&setup.id, b"k".to_vec())
+        .unwrap()
+        .value
+        .unwrap()
+        .to_vec()
+}
+
+/// A key created as requiring a lock.
+fn shared_setup() -> Setup {
+    let mut setup = Setup::new(true);
+    let id = setup.id;
+    at(&mut setup, 1_000, |s| {
+        put_locked(s, &id, b"0", None, Some(true)).unwrap()
+    });
+    setup
+}
+
+#[test]
+fn expiry_race() {
+    let mut setup = shared_setup();
+    let id = setup.id;
+
+    let token = at(&mut setup, 1_000, |s| acquire(s, &id, 10).unwrap());
+    assert_eq!(token, 1);
+    at(&mut setup, 1_009, |s| {
+        assert_many_err(acquire(s, &id, 10), error::lock_held("1010"));
+        put_locked(s, &id, b"1", Some(token), None).unwrap();
+    });
+
+    // The lease ends at the first block at or after its expiry, whatever
+    // the order of the transactions in that block.
+    at(&mut setup, 1_010, |s| {
+        assert_many_err(
+            put_locked(s, &id, b"2", Some(token), None),
+            error::stale_lock_token(),
+        );
+        assert_eq!(acquire(s, &id, 10).unwrap(), 2);
+        assert_many_err(
+            put_locked(s, &id, b"2", Some(token), None),
+            error::stale_lock_token(),
+        );
+        put_locked(s, &id, b"2", Some(2), None).unwrap();
+    });
+    assert_eq!(value(&setup), b"2");
+}
+
+#[test]
+fn renewals() {
+    let mut setup = shared_setup();
+    let id = setup.id;
+
+    let token = at(&mut setup, 1_000, |s| acquire(s, &id, 10).unwrap());
+    at(&mut setup, 1_008, |s| {
+        assert_eq!(renew(s, &id, token, 10).unwrap(), token);
+    });
+    at(&mut setup, 1_015, |s| {
+        assert_many_err(acquire(s, &id, 10), error::lock_held("1018"));
+        put_locked(s, &id, b"1", Some(token), None).unwrap();
+    });
+
+    // An expired lease cannot be renewed.
+    at(&mut setup, 1_018, |s| {
+        assert_many_err(renew(s, &id, token, 10), error::stale_lock_token());
+    });
+
+    let token = at(&mut setup, 1_020, |s| acquire(s, &id, 10).unwrap());
+    assert_eq!(token, 2);
+    at(&mut setup, 1_021, |s| {
+        assert_many_err(renew(s, &id, 1, 10), error::stale_lock_token());
+        assert_eq!(
+            renew(s, &id, token, 0).unwrap_err().code(),
+            error::invalid_lease("").code()
+        );
+        assert_eq!(
+            renew(s, &id, token, MAXIMUM_LEASE_SECS + 1)
+                .unwrap_err()
+                .code(),
+            error::invalid_lease("").code()
+        );
+    });
+}
+
+#[test]
+fn release_bumps_token() {
+    let mut setup = shared_setup();
+    let id = setup.id;
+
+    let token = at(&mut setup, 1_000, |s| acquire(s, &id, 100).unwrap());
+    at(&mut setup, 1_001, |s| {
+        assert_many_err(release(s, &id, token + 1), error::stale_lock_token());
+        release(s, &id, token).unwrap();
+        assert_many_err(release(s, &id, token), error::stale_lock_token());
+        assert_many_err(
+            put_locked(s, &id, b"1", Some(token), None),
+            error::stale_lock_token(),
+        );
+        assert_eq!(acquire(s, &id, 100).unwrap(), token + 1);
+    });
+}
+
+#[test]
+fn stale_tokens() {
+    let mut setup = shared_setup();
+    let id = setup.id;
+
+    // Keys created as requiring a lock cannot be put without its token.
+    at(&mut setup, 1_000, |s| {
+        assert_many_err(
+            put_locked(s, &id, b"1", None, None),
+            error::lock_token_required(),
+        );
+        assert_many_err(
+            s.put(&id, b"k".to_vec(), b"1".to_vec(), None),
+            error::lock_token_required(),
+        );
+        assert_many_err(
+            put_locked(s, &id, b"1", Some(0), None),
+            error::stale_lock_token(),
+        );
+    });
+
+    let token = at(&mut setup, 1_000, |s| acquire(s, &id, 10).unwrap());
+    at(&mut setup, 1_001, |s| {
+        assert_many_err(
+            put_locked(s, &id, b"1", Some(token + 1), None),
+            error::stale_lock_token(),
+        );
+        // The token does not give access to the key.
+        assert_eq!(
+            put_locked(s, &identity(1), b"1", Some(token), None)
+                .unwrap_err()
+                .code(),
+            error::permission_denied().code()
+        );
+        // Whether a lock is required is set when the key is created.
+        assert_many_err(
+            put_locked(s, &id, b"1", Some(token), Some(false)),
+            error::key_exists(),
+        );
+    });
+    assert_eq!(value(&setup), b"0");
+}
+
+#[test]
+fn advisory_lock() {
+    let mut setup = Setup::new(true);
+    let id = setup.id;
+
+    at(&mut setup, 1_000, |s| {
+        s.put(&id, b"k".to_vec(), b"0".to_vec(), None).unwrap();
+        let token = acquire(s, &id, 10).unwrap();
+        // Keys which do not require a lock can still be put without it.
+        s.put(&id, b"k".to_vec(), b"1".to_vec(), None).unwrap();
+        put_locked(s, &id, b"2", Some(token), None).unwrap();
+    });
+    assert_eq!(value(&setup), b"2");
+}
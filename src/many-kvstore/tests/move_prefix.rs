@@ -0,0 +1,258 @@
+pub mod common;
+
+use crate::common::*;
+use many_identity::testing::identity;
+use many_kvstore::error;
+use many_kvstore::module::move_prefix::{
+    KvStoreMovePrefixModuleBackend, MovePrefixArgs, MovePrefixReturns, PendingMovesArgs,
+    PREFIX_MOVE_KEYS_PER_BLOCK,
+};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_types::Either;
+
+fn key(prefix: &str, i: usize) -> Vec<u8> {
+    format!("{}/{:05}", prefix, i).into_bytes()
+}
+
+fn move_prefix(
+    setup: &mut Setup,
+    source: &str,
+    destination: &str,
+    confirmation: Option<Vec<u8>>,
+) -> Result<MovePrefixReturns, many_error::ManyError> {
+    setup.module_impl.move_prefix(
+        &identity(1),
+        MovePrefixArgs {
+            source: source.as_bytes().to_vec().into(),
+            destination: destination.as_bytes().to_vec().into(),
+            alternative_owner: None,
+            confirmation: confirmation.map(Into::into),
+        },
+    )
+}
+
+/// Dry run a move, then execute it with its confirmation.
+fn confirmed_move(setup: &mut Setup, source: &str, destination: &str) -> MovePrefixReturns {
+    let dry_run = move_prefix(setup, source, destination, None).unwrap();
+    assert_eq!(dry_run.id, None);
+    move_prefix(
+        setup,
+        source,
+        destination,
+        Some(dry_run.confirmation.to_vec()),
+    )
+    .unwrap()
+}
+
+fn pending(setup: &Setup) -> Vec<(u64, u64)> {
+    setup
+        .module_impl
+        .pending_moves(&identity(1), PendingMovesArgs::default())
+        .unwrap()
+        .pending
+        .into_iter()
+        .map(|m| (m.id, m.moved))
+        .collect()
+}
+
+fn hash(setup: &Setup) -> Vec<u8> {
+    ManyAbciModuleBackend::info(&setup.module_impl)
+        .unwrap()
+        .hash
+        .to_vec()
+}
+
+/// The keys of identity(1) under `v1/`, with one of identity(2) elsewhere.
+fn filled(count: usize) -> Setup {
+    let mut setup = Setup::new(true);
+    setup.block(|setup| {
+        for i in 0..count {
+            setup
+                .put(&identity(1), key("v1", i), vec![i as u8], None)
+                .unwrap();
+        }
+        setup
+            .put(&identity(2), b"v3/other".to_vec(), vec![2], None)
+            .unwrap();
+    });
+    setup
+}
+
+#[test]
+fn replicas_converge() {
+    let count = PREFIX_MOVE_KEYS_PER_BLOCK * 2 + 500;
+    let mut replicas = [filled(count), filled(count)];
+    assert_eq!(hash(&replicas[0]), hash(&replicas[1]));
+
+    for replica in replicas.iter_mut() {
+        replica.block(|setup| {
+            let returns = confirmed_move(setup, "v1/", "v2/");
+            assert_eq!(returns.count, count as u64);
+            assert_eq!(returns.id, Some(0));
+        });
+    }
+    assert_eq!(hash(&replicas[0]), hash(&replicas[1]));
+    assert_eq!(pending(&replicas[0]), vec![(0, 0)]);
+
+    let mut blocks = 0;
+    while !pending(&replicas[0]).is_empty() {
+        for replica in replicas.iter_mut() {
+            replica.block(|_| {});
+        }
+        assert_eq!(hash(&replicas[0]), hash(&replicas[1]));
+        assert_eq!(pending(&replicas[0]), pending(&replicas[1]));
+        blocks += 1;
+    }
+    assert_eq!(blocks, 3);
+
+    for replica in &replicas {
+        for i in [0, count / 2, count - 1] {
+            assert_many_err(
+                replica.query(&identity(1), key("v1", i)),
+                error::key_not_found(),
+            );
+            assert_eq!(
+                replica.get(&identity(1), key("v2", i)).unwrap().value,
+                Some(vec![i as u8].into())
+            );
+        }
+    }
+}
+
+#[test]
+fn reads_mid_move() {
+    let count = PREFIX_MOVE_KEYS_PER_BLOCK + 10;
+    let mut setup = filled(count);
+    setup.block(|setup| confirmed_move(setup, "v1/", "v2/"));
+
+    // Nothing was moved yet.
+    assert_eq!(pending(&setup), vec![(0, 0)]);
+    assert!(setup.get(&identity(1), key("v1", count - 1)).is_ok());
+    assert_many_err(
+        setup.query(&identity(1), key("v2", 0)),
+        error::key_not_found(),
+    );
+
+    // The first chunk. Every key is at exactly one location.
+    setup.block(|_| {});
+    assert_eq!(
+        pending(&setup),
+        vec![(0, PREFIX_MOVE_KEYS_PER_BLOCK as u64)]
+    );
+    for i in 0..count {
+        let old = setup.query(&identity(1), key("v1", i)).is_ok();
+        let new = setup.query(&identity(1), key("v2", i)).is_ok();
+        assert_ne!(old, new);
+        assert_eq!(new, i < PREFIX_MOVE_KEYS_PER_BLOCK);
+    }
+
+    // Keys under either prefix cannot change until the move is done.
+    for k in [key("v1", count - 1), key("v2", 0), key("v2", count + 1)] {
+        assert_many_err(
+            setup.put(&identity(1), k, vec![4], None),
+            error::prefix_move_pending("0".to_string()),
+        );
+    }
+    assert_many_err(
+        move_prefix(&mut setup, "v2/a", "v4/", None),
+        error::prefix_move_pending("0".to_string()),
+    );
+    setup
+        .put(&identity(1), key("v4", 0), vec![4], None)
+        .unwrap();
+
+    setup.block(|_| {});
+    assert!(pending(&setup).is_empty());
+    assert_eq!(
+        setup.get(&identity(1), key("v2", count - 1)).unwrap().value,
+        Some(vec![(count - 1) as u8].into())
+    );
+    setup
+        .put(&identity(1), key("v1", 0), vec![4], None)
+        .unwrap();
+}
+
+#[test]
+fn metadata_preserved() {
+    let mut setup = setup();
+    setup
+        .put(&identity(1), key("v1", 0), vec![1], None)
+        .unwrap();
+    setup
+        .disable(&identity(1), key("v1", 0), None, None)
+        .unwrap();
+    setup
+        .put(&identity(1), key("v1", 1), vec![2], None)
+        .unwrap();
+
+    let returns = confirmed_move(&mut setup, "v1/", "v2/");
+    assert_eq!(returns.count, 2);
+    assert!(pending(&setup).is_empty());
+
+    let query = setup.query(&identity(1), key("v2", 0)).unwrap();
+    assert_eq!(query.owner, Some(identity(1)));
+    assert_eq!(query.disabled, Some(Either::Left(true)));
+    assert_many_err(setup.get(&identity(1), key("v2", 0)), error::key_disabled());
+    assert_eq!(
+        setup.get(&identity(1), key("v2", 1)).unwrap().value,
+        Some(vec![2].into())
+    );
+}
+
+#[test]
+fn permissions() {
+    let mut setup = setup();
+    setup
+        .put(&identity(1), key("v1", 0), vec![1], None)
+        .unwrap();
+    setup
+        .put(&identity(2), key("v3", 0), vec![1], None)
+        .unwrap();
+
+    // The source must only have keys of the owner.
+    setup
+        .put(&identity(2), key("v5", 0), vec![1], None)
+        .unwrap();
+    setup
+        .put(&identity(1), key("v5", 1), vec![1], None)
+        .unwrap();
+    assert_many_err(
+        move_prefix(&mut setup, "v5/", "v6/", None),
+        error::permission_denied(),
+    );
+
+    // The destination must not have keys of another owner, or in the way.
+    assert_many_err(
+        move_prefix(&mut setup, "v1/", "v3/", None),
+        error::permission_denied(),
+    );
+    setup
+        .put(&identity(1), key("v2", 0), vec![1], None)
+        .unwrap();
+    assert_many_err(
+        move_prefix(&mut setup, "v1/", "v2/", None),
+        error::key_exists(),
+    );
+
+    assert_many_err(
+        move_prefix(&mut setup, "v1/", "v1/old/", None),
+        error::overlapping_prefixes(),
+    );
+
+    // The confirmation is for the keys of the dry run.
+    let dry_run = move_prefix(&mut setup, "v1/", "v4/", None).unwrap();
+    setup
+        .put(&identity(1), key("v1", 1), vec![1], None)
+        .unwrap();
+    assert_many_err(
+        move_prefix(
+            &mut setup,
+            "v1/",
+            "v4/",
+            Some(dry_run.confirmation.to_vec()),
+        ),
+        error::invalid_move_confirmation(),
+    );
+    assert!(pending(&setup).is_empty());
+    assert!(setup.get(&identity(1), key("v1", 0)).is_ok());
+}
@@ -0,0 +1,238 @@
+pub mod common;
+
+use crate::common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::policy::{
+    AccessPolicy, AccountKvStoreDefaults, EffectivePolicyArgs, KvStorePolicyModuleBackend,
+    PolicyOverrides, SetDefaultPolicyArgs,
+};
+use many_modules::account::features::{FeatureInfo, TryCreateFeature};
+use many_modules::account::AccountModuleBackend;
+use many_modules::{account, kvstore::PutArgs};
+use std::collections::BTreeSet;
+
+fn private_policy() -> AccessPolicy {
+    AccessPolicy {
+        readers: BTreeSet::from([identity(10)]),
+        public: false,
+        immutable: false,
+    }
+}
+
+fn set_default(setup: &SetupWithAccount, policy: AccessPolicy) {
+    let id = setup.id();
+    setup
+        .module_impl_mut()
+        .set_default_policy(
+            &id,
+            SetDefaultPolicyArgs {
+                account: setup.account_id,
+                policy,
+            },
+        )
+        .expect("Could not set the default policy");
+}
+
+fn effective(setup: &SetupWithAccount, owner: Address) -> AccessPolicy {
+    setup
+        .module_impl()
+        .effective_policy(&identity(1), EffectivePolicyArgs { owner: Some(owner) })
+        .unwrap()
+        .policy
+}
+
+fn put_with(
+    setup: &SetupWithAccount,
+    key: Vec<u8>,
+    overrides: PolicyOverrides,
+) -> Result<(), many_error::ManyError> {
+    let id = setup.id();
+    setup
+        .module_impl_mut()
+        .put_with_overrides(
+            &id,
+            PutArgs {
+                key: key.into(),
+                value: vec![1].into(),
+                alternative_owner: Some(setup.account_id),
+            },
+            overrides,
+        )
+        .map(|_| ())
+}
+
+#[test]
+fn feature_roundtrip() {
+    let feature = AccountKvStoreDefaults {
+        policy: private_policy(),
+    }
+    .as_feature();
+    assert_eq!(
+        AccountKvStoreDefaults::try_create(&feature).unwrap().policy,
+        private_policy()
+    );
+}
+
+#[test]
+fn default_policy() {
+    let setup = setup_with_account(AccountType::KvStore);
+    assert_eq!(effective(&setup, setup.account_id), AccessPolicy::default());
+    assert_eq!(effective(&setup, identity(1)), AccessPolicy::default());
+}
+
+#[test]
+fn inherited() {
+    let mut setup = setup_with_account(AccountType::KvStore);
+    let id = setup.id();
+    set_default(&setup, private_policy());
+    assert_eq!(effective(&setup, setup.account_id), private_policy());
+
+    let account_id = setup.account_id;
+    assert!(setup.put(&id, vec![1], vec![2], Some(account_id)).is_ok());
+
+    // The owner, the account members and the readers can read it.
+    assert!(setup.get(&account_id, vec![1]).is_ok());
+    assert!(setup.get(&id, vec![1]).is_ok());
+    assert!(setup.get(&identity(2), vec![1]).is_ok());
+    assert!(setup.get(&identity(10), vec![1]).is_ok());
+    assert_many_err(setup.get(&identity(11), vec![1]), error::read_denied());
+    assert_many_err(
+        setup.get(&Address::anonymous(), vec![1]),
+        error::read_denied(),
+    );
+
+    // Puts owned by the sender itself do not inherit the account policy.
+    assert!(setup.put(&id, vec![2], vec![2], None).is_ok());
+    assert!(setup.get(&Address::anonymous(), vec![2]).is_ok());
+}
+
+#[test]
+fn overrides_win() {
+    let setup = setup_with_account(AccountType::KvStore);
+    set_default(&setup, private_policy());
+
+    assert!(put_with(
+        &setup,
+        vec![1],
+        PolicyOverrides {
+            public: Some(true),
+            ..Default::default()
+        }
+    )
+    .is_ok());
+    assert!(setup.get(&Address::anonymous(), vec![1]).is_ok());
+
+    assert!(put_with(
+        &setup,
+        vec![2],
+        PolicyOverrides {
+            readers: Some(BTreeSet::from([identity(11)])),
+            ..Default::default()
+        }
+    )
+    .is_ok());
+    assert!(setup.get(&identity(11), vec![2]).is_ok());
+    assert_many_err(setup.get(&identity(10), vec![2]), error::read_denied());
+}
+
+#[test]
+fn immutable() {
+    let mut setup = setup_with_account(AccountType::KvStore);
+    let id = setup.id();
+    let account_id = setup.account_id;
+    set_default(
+        &setup,
+        AccessPolicy {
+            immutable: true,
+            ..AccessPolicy::default()
+        },
+    );
+
+    assert!(setup.put(&id, vec![1], vec![2], Some(account_id)).is_ok());
+    assert_many_err(
+        setup.put(&id, vec![1], vec![3], Some(account_id)),
+        error::key_immutable(),
+    );
+    assert_many_err(
+        setup.disable(&id, vec![1], Some(account_id), None),
+        error::key_immutable(),
+    );
+    assert_eq!(setup.get(&id, vec![1]).unwrap().value, Some(vec![2].into()));
+
+    // An explicit value wins.
+    assert!(put_with(
+        &setup,
+        vec![2],
+        PolicyOverrides {
+            immutable: Some(false),
+            ..Default::default()
+        }
+    )
+    .is_ok());
+    assert!(setup.put(&id, vec![2], vec![3], Some(account_id)).is_ok());
+}
+
+#[test]
+fn change_mid_stream() {
+    let mut setup = setup_with_account(AccountType::KvStore);
+    let id = setup.id();
+    let account_id = setup.account_id;
+
+    assert!(setup.put(&id, vec![1], vec![2], Some(account_id)).is_ok());
+    set_default(&setup, private_policy());
+    assert!(setup.put(&id, vec![2], vec![2], Some(account_id)).is_ok());
+    set_default(&setup, AccessPolicy::default());
+    assert!(setup.put(&id, vec![3], vec![2], Some(account_id)).is_ok());
+
+    // Existing keys keep the policy they were put with.
+    assert!(setup.get(&Address::anonymous(), vec![1]).is_ok());
+    assert_many_err(
+        setup.get(&Address::anonymous(), vec![2]),
+        error::read_denied(),
+    );
+    assert!(setup.get(&Address::anonymous(), vec![3]).is_ok());
+
+    // Putting a key again applies the current default.
+    assert!(setup.put(&id, vec![2], vec![3], Some(account_id)).is_ok());
+    assert!(setup.get(&Address::anonymous(), vec![2]).is_ok());
+}
+
+#[test]
+fn set_default_unauthorized() {
+    let setup = setup_with_account(AccountType::KvStore);
+    let account_id = setup.account_id;
+    assert!(setup
+        .module_impl_mut()
+        .set_default_policy(
+            &identity(2),
+            SetDefaultPolicyArgs {
+                account: account_id,
+                policy: private_policy(),
+            },
+        )
+        .is_err());
+    assert_eq!(effective(&setup, account_id), AccessPolicy::default());
+}
+
+#[test]
+fn account_info() {
+    let setup = setup_with_account(AccountType::KvStore);
+    set_default(&setup, private_policy());
+    let info = AccountModuleBackend::info(
+        &*setup.module_impl(),
+        &identity(1),
+        account::InfoArgs {
+            account: setup.account_id,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        info.features
+            .get::<AccountKvStoreDefaults>()
+            .unwrap()
+            .policy,
+        private_policy()
+    );
+}
@@ -0,0 +1,135 @@
+pub mod common;
+
+use crate::common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::module::allow_addrs::AllowAddrsModule;
+use many_kvstore::module::capability::CapabilityPutModule;
+use many_kvstore::module::posture::{Posture, PostureArgs, PostureBuilder, PostureModule};
+use many_kvstore::module::KvStoreModuleImpl;
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::kvstore::{KvStoreCommandsModule, KvStoreModule};
+use many_modules::ManyModule;
+use many_protocol::RequestMessageBuilder;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+fn builder(backend: &Arc<Mutex<KvStoreModuleImpl>>) -> PostureBuilder {
+    let endpoints = backend.lock().unwrap().init().unwrap().endpoints;
+    PostureBuilder::new(endpoints)
+        .with_verifiers(&["anonymous", "cose-key"])
+        .with_listener("many", "127.0.0.1:8000")
+        .with_limit("quota", "none")
+}
+
+fn names(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+#[test]
+fn open() {
+    let backend = Arc::new(Mutex::new(setup().module_impl));
+    let mut posture = builder(&backend).with_allow_addrs(None);
+    posture.gated(CapabilityPutModule::new(
+        KvStoreCommandsModule::new(backend.clone()),
+        backend.clone(),
+        None,
+    ));
+    posture.open(KvStoreModule::new(backend.clone()));
+    let posture = posture.build();
+
+    assert_eq!(posture.allow_addrs, None);
+    assert!(!posture.read_only);
+    assert_eq!(posture.commands, names(&["kvstore.disable", "kvstore.put"]));
+    assert_eq!(posture.anonymous_commands, posture.commands);
+    assert_eq!(
+        posture.verifiers,
+        vec!["anonymous".to_string(), "cose-key".to_string()]
+    );
+    assert_eq!(
+        posture.listeners,
+        BTreeMap::from([("many".to_string(), "127.0.0.1:8000".to_string())])
+    );
+}
+
+#[test]
+fn allow_list() {
+    let backend = Arc::new(Mutex::new(setup().module_impl));
+    let allowed = BTreeSet::from([identity(1), identity(2)]);
+    let mut posture = builder(&backend).with_allow_addrs(Some(&allowed));
+    posture.gated(CapabilityPutModule::new(
+        AllowAddrsModule {
+            inner: KvStoreCommandsModule::new(backend.clone()),
+            allow_addrs: allowed.clone(),
+        },
+        backend.clone(),
+        Some(allowed.clone()),
+    ));
+    let posture = posture.build();
+
+    assert_eq!(posture.allow_addrs, Some(2));
+    assert_eq!(posture.commands, names(&["kvstore.disable", "kvstore.put"]));
+    // Puts carrying a capability are served whatever their sender.
+    assert_eq!(posture.anonymous_commands, names(&["kvstore.put"]));
+
+    // Without capabilities, the allow list gates every command.
+    let mut posture = builder(&backend).with_allow_addrs(Some(&allowed));
+    posture.gated(AllowAddrsModule {
+        inner: KvStoreCommandsModule::new(backend.clone()),
+        allow_addrs: allowed,
+    });
+    assert_eq!(posture.build().anonymous_commands, BTreeSet::new());
+}
+
+#[test]
+fn read_only() {
+    let backend = Arc::new(Mutex::new(setup().module_impl));
+    let mut posture = builder(&backend);
+    posture.open(KvStoreModule::new(backend.clone()));
+    let posture = posture.build();
+
+    assert!(posture.read_only);
+    assert!(posture.commands.is_empty());
+    assert_eq!(
+        posture.to_string(),
+        "Security posture:\n\
+         \x20 verifiers: anonymous, cose-key\n\
+         \x20 allow list: none\n\
+         \x20 read-only: yes (0 command endpoints)\n\
+         \x20 commands open to anonymous senders: none\n\
+         \x20 listeners: many=127.0.0.1:8000\n\
+         \x20 limits: quota=none"
+    );
+}
+
+#[tokio::test]
+async fn module() {
+    let server = identity(1);
+    let posture = Posture {
+        allow_addrs: Some(3),
+        commands: names(&["kvstore.put"]),
+        ..Default::default()
+    };
+    let module = PostureModule::new(posture.clone(), server);
+    let request = |from: Address| {
+        RequestMessageBuilder::default()
+            .from(from)
+            .method("server.posture".to_string())
+            .data(minicbor::to_vec(PostureArgs {}).unwrap())
+            .build()
+            .unwrap()
+    };
+
+    let response = module.execute(request(server)).await.unwrap();
+    assert_eq!(
+        minicbor::decode::<Posture>(&response.data.unwrap()).unwrap(),
+        posture
+    );
+
+    // Only the server itself can read its posture.
+    let response = module.execute(request(identity(2))).await.unwrap();
+    assert_eq!(response.data, Err(ManyError::invalid_from_identity()));
+    let response = module.execute(request(Address::anonymous())).await.unwrap();
+    assert_eq!(response.data, Err(ManyError::invalid_from_identity()));
+}
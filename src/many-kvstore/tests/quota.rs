@@ -0,0 +1,248 @@
+pub mod common;
+
+use crate::common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::counter::{CounterCreateArgs, KvStoreCounterModuleBackend};
+use many_kvstore::module::quota::{
+    KvStoreQuotaModuleBackend, QuotaWarningModule, SetQuotaArgs, UsageArgs, QUOTA_WARNING,
+};
+use many_kvstore::module::KvStoreModuleImpl;
+use many_modules::kvstore::{KvStoreCommandsModule, PutArgs};
+use many_modules::ManyModule;
+use many_protocol::{RequestMessageBuilder, ResponseMessage};
+use many_types::cbor::CborAny;
+use std::sync::{Arc, Mutex};
+
+fn usage(setup: &Setup, sender: &Address, owner: Option<Address>) -> u64 {
+    setup
+        .module_impl
+        .usage(sender, UsageArgs { owner })
+        .unwrap()
+        .usage
+}
+
+#[test]
+fn usage_follows_puts() {
+    let mut setup = setup_with_migrations(USAGE_MIGRATION);
+    let id = setup.id;
+
+    setup.put(&id, b"a".to_vec(), vec![0; 100], None).unwrap();
+    assert_eq!(usage(&setup, &id, None), 100);
+
+    // Replacing a value only counts the new one.
+    setup.put(&id, b"a".to_vec(), vec![0; 40], None).unwrap();
+    assert_eq!(usage(&setup, &id, None), 40);
+
+    setup.put(&id, b"b".to_vec(), vec![0; 60], None).unwrap();
+    assert_eq!(usage(&setup, &id, Some(id)), 100);
+    assert_eq!(usage(&setup, &identity(5), None), 0);
+}
+
+#[test]
+fn backfill() {
+    let mut setup = setup().with_quota(100);
+    let id = setup.id;
+
+    // Usage is neither counted nor limited before the migration.
+    setup.put(&id, b"a".to_vec(), vec![0; 150], None).unwrap();
+    setup
+        .put(&identity(5), b"b".to_vec(), vec![0; 20], None)
+        .unwrap();
+    setup
+        .module_impl
+        .counter_create(
+            &id,
+            CounterCreateArgs {
+                key: b"c".to_vec(),
+                signed: false,
+                alternative_owner: None,
+            },
+        )
+        .unwrap();
+    assert_many_err(
+        setup.module_impl.usage(&id, UsageArgs::default()),
+        error::usage_not_enabled(),
+    );
+
+    // The values put before are counted, except counters.
+    let mut setup = setup.with_migrations(USAGE_MIGRATION);
+    setup.block(|_| {});
+    assert_eq!(usage(&setup, &id, None), 150);
+    assert_eq!(usage(&setup, &identity(5), None), 20);
+    assert_many_err(
+        setup.put(&id, b"d".to_vec(), vec![0; 10], None),
+        error::quota_exceeded("160".to_string(), "100".to_string()),
+    );
+    setup.put(&id, b"a".to_vec(), vec![0; 50], None).unwrap();
+    assert_eq!(usage(&setup, &id, None), 50);
+}
+
+#[test]
+fn hard_limit() {
+    let mut setup = setup_with_migrations(USAGE_MIGRATION).with_quota(100);
+    let id = setup.id;
+
+    setup.put(&id, b"a".to_vec(), vec![0; 80], None).unwrap();
+    assert_many_err(
+        setup.put(&id, b"b".to_vec(), vec![0; 30], None),
+        error::quota_exceeded("110".to_string(), "100".to_string()),
+    );
+    setup.put(&id, b"b".to_vec(), vec![0; 20], None).unwrap();
+
+    let returns = setup.module_impl.usage(&id, UsageArgs::default()).unwrap();
+    assert_eq!(returns.usage, 100);
+    assert_eq!(returns.quota, Some(100));
+    assert_eq!(returns.percent(), Some(100));
+}
+
+/// The quota is part of the state: set by the initial state, or by the
+/// identity of the store.
+#[test]
+fn quota_in_state() {
+    let mut setup = setup_with_migrations(USAGE_MIGRATION).with_quota(100);
+    let id = setup.id;
+    assert_many_err(
+        setup
+            .module_impl
+            .set_quota(&id, SetQuotaArgs { quota: None }),
+        error::quota_denied(),
+    );
+    assert_many_err(
+        setup.put(&id, b"a".to_vec(), vec![0; 110], None),
+        error::quota_exceeded("110".to_string(), "100".to_string()),
+    );
+
+    setup
+        .module_impl
+        .set_quota(&STORE_IDENTITY, SetQuotaArgs { quota: None })
+        .unwrap();
+    setup.put(&id, b"a".to_vec(), vec![0; 110], None).unwrap();
+    let returns = setup.module_impl.usage(&id, UsageArgs::default()).unwrap();
+    assert_eq!(returns.quota, None);
+
+    let state = json5::from_str(
+        r#"{
+            identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+            acl: {},
+            quota: 1000,
+        }"#,
+    )
+    .unwrap();
+    let mut setup = Setup::default();
+    setup.module_impl = KvStoreModuleImpl::new(state, tempfile::tempdir().unwrap(), false)
+        .unwrap()
+        .with_migrations(json5::from_str(USAGE_MIGRATION).unwrap());
+    setup.block(|_| {});
+    let returns = setup.module_impl.usage(&id, UsageArgs::default()).unwrap();
+    assert_eq!(returns.quota, Some(1000));
+}
+
+#[test]
+fn usage_denied() {
+    let setup = setup_with_migrations(USAGE_MIGRATION);
+    assert_many_err(
+        setup.module_impl.usage(
+            &identity(5),
+            UsageArgs {
+                owner: Some(setup.id),
+            },
+        ),
+        error::usage_denied(),
+    );
+}
+
+#[test]
+fn account_usage() {
+    let mut setup = setup_with_account(AccountType::KvStore).with_migrations(USAGE_MIGRATION);
+    let account_id = setup.account_id;
+
+    setup
+        .put(&identity(2), b"a".to_vec(), vec![0; 10], Some(account_id))
+        .unwrap();
+
+    // Owners and role holders of the account can read its usage.
+    for sender in [setup.id(), identity(2), identity(3)] {
+        let returns = setup
+            .module_impl()
+            .usage(
+                &sender,
+                UsageArgs {
+                    owner: Some(account_id),
+                },
+            )
+            .unwrap();
+        assert_eq!(returns.usage, 10);
+    }
+    assert!(setup
+        .module_impl()
+        .usage(
+            &identity(5),
+            UsageArgs {
+                owner: Some(account_id),
+            },
+        )
+        .is_err());
+}
+
+async fn put(
+    module: &QuotaWarningModule<KvStoreCommandsModule<KvStoreModuleImpl>, KvStoreModuleImpl>,
+    from: Address,
+    key: &[u8],
+    len: usize,
+) -> ResponseMessage {
+    let request = RequestMessageBuilder::default()
+        .from(from)
+        .method("kvstore.put".to_string())
+        .data(
+            minicbor::to_vec(PutArgs {
+                key: key.to_vec().into(),
+                value: vec![0; len].into(),
+                alternative_owner: None,
+            })
+            .unwrap(),
+        )
+        .build()
+        .unwrap();
+    module.execute(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn warning_threshold() {
+    let setup = setup_with_migrations(USAGE_MIGRATION).with_quota(1_000);
+    let id = setup.id;
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let module = QuotaWarningModule::new(KvStoreCommandsModule::new(backend.clone()), backend, 80);
+
+    let response = put(&module, id, b"a", 700).await;
+    assert!(response.data.is_ok());
+    assert!(response
+        .attributes
+        .get_attribute(QUOTA_WARNING.id)
+        .is_none());
+
+    // Crossing the threshold adds the warning to successful puts.
+    let response = put(&module, id, b"b", 150).await;
+    assert!(response.data.is_ok());
+    let warning = response.attributes.get_attribute(QUOTA_WARNING.id).unwrap();
+    assert_eq!(
+        warning.arguments,
+        vec![CborAny::Int(850), CborAny::Int(1_000), CborAny::Int(85)]
+    );
+
+    // Refused puts have no warning.
+    let response = put(&module, id, b"c", 200).await;
+    assert!(response.data.is_err());
+    assert!(response
+        .attributes
+        .get_attribute(QUOTA_WARNING.id)
+        .is_none());
+
+    // Other owners are not warned.
+    let response = put(&module, identity(5), b"d", 10).await;
+    assert!(response
+        .attributes
+        .get_attribute(QUOTA_WARNING.id)
+        .is_none());
+}
@@ -0,0 +1,122 @@
+use many_identity::testing::identity;
+use many_kvstore::module::KvStoreModuleImpl;
+use many_kvstore::recovery::{self, DirtyMarker, RecoveryReport};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::kvstore::{KvStoreCommandsModuleBackend, PutArgs};
+use merk::Op;
+use std::path::{Path, PathBuf};
+
+const STATE: &str = r#"{
+    identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+    acl: {
+      "010203": { owner: "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp" }
+    }
+}"#;
+
+/// Create a store with a few values, and return its path.
+fn fixture() -> PathBuf {
+    let path = tempfile::tempdir().unwrap().into_path();
+    let mut module_impl =
+        KvStoreModuleImpl::new(json5::from_str(STATE).unwrap(), path.clone(), false).unwrap();
+    for key in [b"foo".to_vec(), b"bar".to_vec()] {
+        module_impl
+            .put(
+                &identity(1),
+                PutArgs {
+                    key: key.into(),
+                    value: b"value".to_vec().into(),
+                    alternative_owner: None,
+                },
+            )
+            .unwrap();
+    }
+    path
+}
+
+/// Leave the store as a killed process would: with the dirty marker set and
+/// a write only half done.
+fn crash(path: &Path) {
+    let mut batch = vec![
+        (b"afoo".to_vec(), Op::Delete),
+        (
+            b"/events_count".to_vec(),
+            Op::Put(1u64.to_be_bytes().to_vec()),
+        ),
+    ];
+    batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut merk = merk::Merk::open(path).unwrap();
+    merk.apply(&batch).unwrap();
+    merk.commit(&[]).unwrap();
+
+    DirtyMarker::new(path).set().unwrap();
+}
+
+#[test]
+fn clean_start() {
+    let path = fixture();
+    let mut module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+
+    let report = recovery::check(&mut module_impl, &path, 1_000, false).unwrap();
+    assert_eq!(report, None);
+    assert!(!path.join(recovery::RECOVERY_REPORT_FILE).exists());
+
+    // The marker is set for the current run, and cleared on clean exit.
+    let marker = DirtyMarker::new(&path);
+    assert!(marker.is_set());
+    marker.clear().unwrap();
+    assert!(!marker.is_set());
+    assert!(marker.clear().is_ok());
+}
+
+#[test]
+fn recover_after_crash() {
+    let path = fixture();
+    crash(&path);
+    let mut module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+    let height = ManyAbciModuleBackend::info(&module_impl).unwrap().height;
+
+    let report = recovery::check(&mut module_impl, &path, 1_000, false)
+        .unwrap()
+        .expect("A recovery should have run");
+    assert_eq!(report.last_committed_height, height);
+    assert_eq!(report.indexes_repaired, 1);
+    assert_eq!(report.counters_corrected, 1);
+    assert_eq!(report.unrepaired, 0);
+    assert!(report.complete);
+
+    let content = std::fs::read_to_string(path.join(recovery::RECOVERY_REPORT_FILE)).unwrap();
+    let written: RecoveryReport = serde_json::from_str(&content).unwrap();
+    assert_eq!(written, report);
+    assert!(DirtyMarker::new(&path).is_set());
+
+    // Everything was repaired.
+    let (discrepancies, complete) = module_impl.verify_bounded(1_000, false).unwrap();
+    assert!(discrepancies.is_empty());
+    assert!(complete);
+}
+
+#[test]
+fn recovery_limit() {
+    let path = fixture();
+    crash(&path);
+    let mut module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+
+    let report = recovery::check(&mut module_impl, &path, 1, false)
+        .unwrap()
+        .unwrap();
+    assert!(!report.complete);
+}
+
+#[test]
+fn fail_on_recovery() {
+    let path = fixture();
+    crash(&path);
+    let mut module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+
+    assert!(recovery::check(&mut module_impl, &path, 1_000, true).is_err());
+    assert!(!path.join(recovery::RECOVERY_REPORT_FILE).exists());
+
+    // Nothing was repaired.
+    let (discrepancies, _) = module_impl.verify_bounded(1_000, false).unwrap();
+    assert_eq!(discrepancies.len(), 2);
+}
@@ -0,0 +1,240 @@
+pub mod common;
+
+use crate::common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::tenant::{
+    KvStoreTenantModuleBackend, ReservedPrefix, SetReservedPrefixesArgs, TenantStatsArgs,
+    TenantStatsReturns, WriteLimit,
+};
+use many_kvstore::module::KvStoreModuleImpl;
+use many_modules::abci_backend::{AbciBlock, ManyAbciModuleBackend};
+use many_modules::kvstore::{KvStoreCommandsModuleBackend, PutArgs};
+use std::sync::{Arc, Mutex};
+
+fn reserved(prefix: &str, owner: Address, write_limit: Option<(u64, u64)>) -> ReservedPrefix {
+    ReservedPrefix {
+        prefix: prefix.to_string(),
+        owner,
+        write_limit: write_limit.map(|(writes, period)| WriteLimit { writes, period }),
+    }
+}
+
+fn stats(
+    module_impl: &KvStoreModuleImpl,
+    sender: &Address,
+) -> Result<TenantStatsReturns, many_error::ManyError> {
+    module_impl.tenant_stats(
+        sender,
+        TenantStatsArgs {
+            prefix: "team/".to_string(),
+        },
+    )
+}
+
+/// Execute a block at a given time, in seconds.
+fn at<R>(setup: &mut Setup, secs: u64, f: impl FnOnce(&mut Setup) -> R) -> R {
+    setup
+        .module_impl
+        .begin_block(AbciBlock { time: Some(secs) })
+        .unwrap();
+    let r = f(setup);
+    setup.module_impl.end_block().unwrap();
+    setup.module_impl.commit().unwrap();
+    r
+}
+
+#[test]
+fn reserved_to_owner() {
+    let setup = Setup::default();
+    let id = setup.id;
+    let mut setup = setup.with_reserved_prefixes(vec![reserved("team/", id, None)]);
+
+    assert!(setup
+        .put(&id, b"team/a".to_vec(), b"1".to_vec(), None)
+        .is_ok());
+    assert_many_err(
+        setup.put(&identity(5), b"team/b".to_vec(), b"1".to_vec(), None),
+        error::prefix_reserved("team/".to_string()),
+    );
+    assert!(setup
+        .put(&identity(5), b"other/b".to_vec(), b"1".to_vec(), None)
+        .is_ok());
+}
+
+#[test]
+fn set_reserved_prefixes() {
+    let mut setup = Setup::default();
+    let id = setup.id;
+    assert_many_err(
+        setup.module_impl.set_reserved_prefixes(
+            &id,
+            SetReservedPrefixesArgs {
+                prefixes: vec![reserved("team/", id, None)],
+            },
+        ),
+        error::reserved_prefixes_denied(),
+    );
+    assert!(setup
+        .module_impl
+        .set_reserved_prefixes(
+            &STORE_IDENTITY,
+            SetReservedPrefixesArgs {
+                prefixes: vec![reserved("team/", id, None), reserved("team/a", id, None)],
+            },
+        )
+        .is_err());
+    assert!(setup
+        .module_impl
+        .set_reserved_prefixes(
+            &STORE_IDENTITY,
+            SetReservedPrefixesArgs {
+                prefixes: vec![reserved("team/", id, Some((1, 0)))],
+            },
+        )
+        .is_err());
+}
+
+#[test]
+fn tenant_stats() {
+    let setup = Setup::new(true);
+    let id = setup.id;
+    let mut setup = setup.with_reserved_prefixes(vec![reserved("team/", id, None)]);
+    at(&mut setup, 7_200, |s| {
+        s.put(&id, b"team/a".to_vec(), b"123".to_vec(), None)
+            .unwrap();
+        s.put(&id, b"team/b".to_vec(), b"12345".to_vec(), None)
+            .unwrap();
+        s.put(&id, b"team/a".to_vec(), b"1".to_vec(), None).unwrap();
+        s.put(&id, b"other".to_vec(), b"123".to_vec(), None)
+            .unwrap();
+    });
+
+    assert_eq!(
+        stats(&setup.module_impl, &id).unwrap(),
+        TenantStatsReturns {
+            keys: 2,
+            bytes: 6,
+            writes: 3,
+            period_start: 7_200,
+            write_limit: None,
+        }
+    );
+    assert!(stats(&setup.module_impl, &STORE_IDENTITY).is_ok());
+    assert_many_err(
+        stats(&setup.module_impl, &identity(5)),
+        error::tenant_stats_denied(),
+    );
+    assert_many_err(
+        setup.module_impl.tenant_stats(
+            &id,
+            TenantStatsArgs {
+                prefix: "other".to_string(),
+            },
+        ),
+        error::prefix_not_reserved("other".to_string()),
+    );
+
+    // Writes are counted again in the next period.
+    at(&mut setup, 10_800, |_| {});
+    assert_eq!(stats(&setup.module_impl, &id).unwrap().writes, 0);
+}
+
+#[test]
+fn throttled() {
+    let setup = Setup::new(true);
+    let id = setup.id;
+    let mut setup = setup.with_reserved_prefixes(vec![reserved("team/", id, Some((2, 60)))]);
+    at(&mut setup, 1_000, |s| {
+        s.put(&id, b"team/a".to_vec(), b"1".to_vec(), None).unwrap();
+        s.put(&id, b"team/b".to_vec(), b"1".to_vec(), None).unwrap();
+        assert_many_err(
+            s.put(&id, b"team/c".to_vec(), b"1".to_vec(), None),
+            error::tenant_throttled("team/".to_string(), "20".to_string()),
+        );
+    });
+    at(&mut setup, 1_019, |s| {
+        assert_many_err(
+            s.put(&id, b"team/c".to_vec(), b"1".to_vec(), None),
+            error::tenant_throttled("team/".to_string(), "1".to_string()),
+        );
+    });
+    at(&mut setup, 1_020, |s| {
+        s.put(&id, b"team/c".to_vec(), b"1".to_vec(), None).unwrap();
+    });
+    assert_eq!(stats(&setup.module_impl, &id).unwrap().writes, 1);
+}
+
+#[test]
+fn throttled_across_restarts() {
+    let id = identity(1);
+    let state = json5::from_str(&format!(
+        r#"{{
+            identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+            acl: {{}},
+            reserved_prefixes: [
+                {{ prefix: "team/", owner: "{}", write_limit: {{ writes: 1, period: 1000000000 }} }},
+            ],
+        }}"#,
+        id
+    ))
+    .unwrap();
+    let path = tempfile::tempdir().unwrap().into_path();
+    let put = |module_impl: &mut KvStoreModuleImpl, key: &[u8]| {
+        module_impl.put(
+            &id,
+            PutArgs {
+                key: key.to_vec().into(),
+                value: b"1".to_vec().into(),
+                alternative_owner: None,
+            },
+        )
+    };
+
+    let mut module_impl = KvStoreModuleImpl::new(state, &path, false).unwrap();
+    put(&mut module_impl, b"team/a").unwrap();
+    drop(module_impl);
+
+    let mut module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+    assert!(put(&mut module_impl, b"team/b").is_err());
+    assert_eq!(stats(&module_impl, &id).unwrap().writes, 1);
+}
+
+#[test]
+fn concurrent_writes() {
+    let setup = Setup::default();
+    let id = setup.id;
+    let setup =
+        setup.with_reserved_prefixes(vec![reserved("team/", id, Some((1_000, 1_000_000_000)))]);
+    let module_impl = Arc::new(Mutex::new(setup.module_impl));
+
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let module_impl = module_impl.clone();
+            std::thread::spawn(move || {
+                for j in 0..10 {
+                    module_impl
+                        .lock()
+                        .unwrap()
+                        .put(
+                            &id,
+                            PutArgs {
+                                key: format!("team/{}-{}", i, j).into_bytes().into(),
+                                value: b"1".to_vec().into(),
+                                alternative_owner: None,
+                            },
+                        )
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let stats = stats(&module_impl.lock().unwrap(), &id).unwrap();
+    assert_eq!(stats.keys, 80);
+    assert_eq!(stats.writes, 80);
+}
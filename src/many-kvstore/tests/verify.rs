@@ -0,0 +1,198 @@
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_kvstore::error;
+use many_kvstore::module::verify::{DiscrepancyKind, KvStoreVerifyModuleBackend, VerifyArgs};
+use many_kvstore::module::KvStoreModuleImpl;
+use many_modules::account::{self, AccountModuleBackend};
+use many_modules::kvstore::{KvStoreCommandsModuleBackend, PutArgs};
+use merk::Op;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const STATE: &str = r#"{
+    identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+    acl: {
+      "010203": { owner: "maffbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wijp" }
+    }
+}"#;
+
+fn kvstore_identity() -> Address {
+    Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap()
+}
+
+/// Create a store with a few values and an account, and return its path.
+fn fixture() -> PathBuf {
+    let path = tempfile::tempdir().unwrap().into_path();
+    let mut module_impl =
+        KvStoreModuleImpl::new(json5::from_str(STATE).unwrap(), path.clone(), false).unwrap();
+    for key in [b"foo".to_vec(), b"bar".to_vec(), b"baz".to_vec()] {
+        module_impl
+            .put(
+                &identity(1),
+                PutArgs {
+                    key: key.into(),
+                    value: b"value".to_vec().into(),
+                    alternative_owner: None,
+                },
+            )
+            .unwrap();
+    }
+    create_account(&mut module_impl);
+    path
+}
+
+fn create_account(module_impl: &mut KvStoreModuleImpl) -> Address {
+    module_impl
+        .create(
+            &identity(1),
+            account::CreateArgs {
+                description: None,
+                roles: None,
+                features: account::features::FeatureSet::default(),
+            },
+        )
+        .unwrap()
+        .id
+}
+
+/// Write raw entries in a store which is not opened.
+fn corrupt(path: &Path, mut batch: Vec<(Vec<u8>, Op)>) {
+    batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut merk = merk::Merk::open(path).unwrap();
+    merk.apply(&batch).unwrap();
+    merk.commit(&[]).unwrap();
+}
+
+fn verify_kinds(module_impl: &mut KvStoreModuleImpl, repair: bool) -> Vec<DiscrepancyKind> {
+    module_impl
+        .verify_all(repair)
+        .unwrap()
+        .into_iter()
+        .map(|d| d.kind)
+        .collect()
+}
+
+#[test]
+fn consistent() {
+    let mut module_impl = KvStoreModuleImpl::load(fixture(), false).unwrap();
+    assert_eq!(verify_kinds(&mut module_impl, false), vec![]);
+}
+
+#[test]
+fn metadata() {
+    let path = fixture();
+    corrupt(
+        &path,
+        vec![
+            (b"afoo".to_vec(), Op::Delete),
+            (b"abar".to_vec(), Op::Put(vec![0xff, 0x00])),
+            (
+                b"abaz".to_vec(),
+                Op::Put(
+                    minicbor::to_vec(many_kvstore::module::KvStoreMetadata {
+                        owner: None,
+                        disabled: None,
+                    })
+                    .unwrap(),
+                ),
+            ),
+        ],
+    );
+
+    let mut module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+    let discrepancies = module_impl.verify_all(false).unwrap();
+    assert_eq!(
+        discrepancies.iter().map(|d| d.kind).collect::<Vec<_>>(),
+        vec![
+            DiscrepancyKind::MissingOwner,
+            DiscrepancyKind::MissingMetadata,
+            DiscrepancyKind::InvalidMetadata,
+        ]
+    );
+    assert_eq!(discrepancies[1].key.as_slice(), b"afoo");
+    assert!(discrepancies.iter().all(|d| d.expected.is_some()));
+
+    assert_eq!(verify_kinds(&mut module_impl, true).len(), 3);
+    assert_eq!(verify_kinds(&mut module_impl, false), vec![]);
+    let meta = many_modules::kvstore::KvStoreModuleBackend::query(
+        &module_impl,
+        &identity(1),
+        many_modules::kvstore::QueryArgs {
+            key: b"foo".to_vec().into(),
+        },
+    )
+    .unwrap();
+    assert_eq!(meta.owner, Some(identity(1)));
+}
+
+#[test]
+fn counters() {
+    let path = fixture();
+    corrupt(
+        &path,
+        vec![
+            (
+                b"/config/account_id".to_vec(),
+                Op::Put(0u32.to_be_bytes().to_vec()),
+            ),
+            (
+                b"/events_count".to_vec(),
+                Op::Put(1u64.to_be_bytes().to_vec()),
+            ),
+        ],
+    );
+
+    let mut module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+    assert_eq!(
+        verify_kinds(&mut module_impl, true),
+        vec![DiscrepancyKind::EventCount, DiscrepancyKind::AccountCounter]
+    );
+    assert_eq!(verify_kinds(&mut module_impl, false), vec![]);
+
+    // The next account does not reuse the existing ID.
+    let account = create_account(&mut module_impl);
+    assert_eq!(account.subresource_id(), Some(1));
+}
+
+#[test]
+fn chunked() {
+    let path = fixture();
+    corrupt(&path, vec![(b"abaz".to_vec(), Op::Delete)]);
+    let module_impl = KvStoreModuleImpl::load(&path, false).unwrap();
+
+    let mut args = VerifyArgs {
+        cursor: None,
+        limit: Some(1),
+    };
+    let mut discrepancies = vec![];
+    let mut calls = 0;
+    loop {
+        let r = module_impl.verify(&kvstore_identity(), args).unwrap();
+        discrepancies.extend(r.discrepancies);
+        calls += 1;
+        match r.next {
+            Some(cursor) => {
+                args = VerifyArgs {
+                    cursor: Some(cursor),
+                    limit: Some(1),
+                }
+            }
+            None => break,
+        }
+    }
+
+    assert_eq!(discrepancies.len(), 1);
+    assert_eq!(discrepancies[0].kind, DiscrepancyKind::MissingMetadata);
+    assert_eq!(discrepancies[0].key.as_slice(), b"abaz");
+    // 3 values, 3 metadata, 4 events and an account.
+    assert!(calls >= 11);
+}
+
+#[test]
+fn unauthorized() {
+    let module_impl = KvStoreModuleImpl::load(fixture(), false).unwrap();
+    assert_eq!(
+        module_impl.verify(&identity(1), VerifyArgs::default()),
+        Err(error::verify_unauthorized())
+    );
+}
@@ -0,0 +1,286 @@
+use hmac::{Hmac, Mac};
+use many_identity::testing::identity;
+use many_kvstore::feed::{ChangeRecord, Feed, Sink};
+use many_kvstore::module::webhook::WebhooksModule;
+use many_kvstore::module::KvStoreModuleImpl;
+use many_kvstore::webhook::{DeliveryPolicy, HookConfig, Webhooks, NAME_HEADER, SIGNATURE_HEADER};
+use many_modules::kvstore::{KvStoreCommandsModuleBackend, PutArgs};
+use sha2::Sha256;
+use std::io::Read;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const STATE: &str = r#"{
+    identity: "mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow",
+    acl: {}
+}"#;
+
+fn module() -> Arc<Mutex<KvStoreModuleImpl>> {
+    let path = tempfile::tempdir().unwrap().into_path();
+    Arc::new(Mutex::new(
+        KvStoreModuleImpl::new(json5::from_str(STATE).unwrap(), path, false).unwrap(),
+    ))
+}
+
+fn put(module: &Arc<Mutex<KvStoreModuleImpl>>, key: &[u8]) {
+    module
+        .lock()
+        .unwrap()
+        .put(
+            &identity(1),
+            PutArgs {
+                key: key.to_vec().into(),
+                value: b"value".to_vec().into(),
+                alternative_owner: None,
+            },
+        )
+        .unwrap();
+}
+
+/// A request received by the sink.
+struct Received {
+    hook: String,
+    signature: String,
+    body: Vec<u8>,
+}
+
+/// A local HTTP sink whose first `failures` requests fail. Returns its URL
+/// and the requests which succeeded.
+fn sink(failures: usize) -> (String, Arc<Mutex<Vec<Received>>>) {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", server.server_addr());
+    let received = Arc::new(Mutex::new(vec![]));
+    let r = received.clone();
+    std::thread::spawn(move || {
+        for (i, mut request) in server.incoming_requests().enumerate() {
+            let header = |name: &str| {
+                request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv(name))
+                    .map(|h| h.value.to_string())
+                    .unwrap_or_default()
+            };
+            let (hook, signature) = (header(NAME_HEADER), header(SIGNATURE_HEADER));
+            let mut body = vec![];
+            request.as_reader().read_to_end(&mut body).unwrap();
+            let status = if i < failures {
+                503
+            } else {
+                r.lock().unwrap().push(Received {
+                    hook,
+                    signature,
+                    body,
+                });
+                200
+            };
+            request.respond(tiny_http::Response::empty(status)).unwrap();
+        }
+    });
+    (url, received)
+}
+
+fn hook(name: &str, key: Option<&str>, prefix: Option<&str>, url: &str) -> HookConfig {
+    HookConfig {
+        name: name.to_string(),
+        key: key.map(str::to_string),
+        prefix: prefix.map(str::to_string),
+        url: url.to_string(),
+        secret: format!("{}-secret", name),
+        policy: DeliveryPolicy {
+            min_backoff_ms: 10,
+            ..DeliveryPolicy::default()
+        },
+    }
+}
+
+fn wait_for(condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(10), "Timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Verify the HMAC of a body independently of the server.
+fn verify(secret: &str, received: &Received) -> bool {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(&received.body);
+    let expected = received
+        .signature
+        .strip_prefix("sha256=")
+        .unwrap_or_default();
+    mac.verify_slice(&hex::decode(expected).unwrap_or_default())
+        .is_ok()
+}
+
+#[test]
+fn fan_out() {
+    let (url, received) = sink(0);
+    let webhooks = Arc::new(
+        Webhooks::new(vec![
+            hook("exact", Some("foo"), None, &url),
+            hook("prefix", None, Some("app/"), &url),
+        ])
+        .unwrap(),
+    );
+    webhooks.spawn().unwrap();
+
+    let module = module();
+    for key in [&b"foo"[..], b"app/a", b"other", b"app/b", b"food"] {
+        put(&module, key);
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let mut feed = Feed::new(module, dir.path())
+        .unwrap()
+        .with_sink("webhooks", webhooks.sink())
+        .unwrap();
+    assert_eq!(feed.poll(), 5);
+
+    wait_for(|| received.lock().unwrap().len() == 3);
+    let received = received.lock().unwrap();
+    let keys = |name: &str| -> Vec<Vec<u8>> {
+        received
+            .iter()
+            .filter(|r| r.hook == name)
+            .map(|r| serde_json::from_slice::<ChangeRecord>(&r.body).unwrap().key)
+            .collect()
+    };
+    assert_eq!(keys("exact"), vec![b"foo".to_vec()]);
+    assert_eq!(keys("prefix"), vec![b"app/a".to_vec(), b"app/b".to_vec()]);
+
+    for r in received.iter() {
+        assert!(verify(&format!("{}-secret", r.hook), r));
+        assert!(!verify("another secret", r));
+    }
+
+    for hook in webhooks.hooks() {
+        assert_eq!(hook.metrics.failed_attempts.load(Ordering::Relaxed), 0);
+        assert_eq!(hook.metrics.queued.load(Ordering::Relaxed), 0);
+    }
+}
+
+#[test]
+fn retry() {
+    let (url, received) = sink(2);
+    let webhooks = Arc::new(Webhooks::new(vec![hook("flaky", Some("foo"), None, &url)]).unwrap());
+    webhooks.spawn().unwrap();
+    let module = module();
+    put(&module, b"foo");
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut feed = Feed::new(module, dir.path())
+        .unwrap()
+        .with_sink("webhooks", webhooks.sink())
+        .unwrap();
+    assert_eq!(feed.poll(), 1);
+
+    // Delivered on the third attempt, signed every time.
+    let metrics = &webhooks.hooks()[0].metrics;
+    wait_for(|| metrics.delivered.load(Ordering::Relaxed) == 1);
+    assert_eq!(metrics.failed_attempts.load(Ordering::Relaxed), 2);
+    assert_eq!(metrics.failed.load(Ordering::Relaxed), 0);
+    assert!(metrics.last_error().unwrap().1.contains("503"));
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert!(verify("flaky-secret", &received[0]));
+
+    // The status query reports the same.
+    let status = WebhooksModule::new(webhooks.clone(), identity(0))
+        .returns()
+        .unwrap();
+    assert_eq!(status.webhooks.len(), 1);
+    assert_eq!(status.webhooks[0].name, "flaky");
+    assert_eq!(status.webhooks[0].delivered, 1);
+    assert_eq!(status.webhooks[0].failed_attempts, 2);
+    assert!(status.webhooks[0]
+        .last_error
+        .as_ref()
+        .unwrap()
+        .message
+        .contains("503"));
+}
+
+#[test]
+fn give_up() {
+    let (url, received) = sink(3);
+    let mut config = hook("down", Some("foo"), None, &url);
+    config.policy.max_retries = 1;
+    let webhooks = Arc::new(Webhooks::new(vec![config]).unwrap());
+    webhooks.spawn().unwrap();
+    let module = module();
+    put(&module, b"foo");
+    put(&module, b"foo");
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut feed = Feed::new(module, dir.path())
+        .unwrap()
+        .with_sink("webhooks", webhooks.sink())
+        .unwrap();
+    assert_eq!(feed.poll(), 2);
+
+    // The first change is given up after 2 attempts, the second one is
+    // delivered on its second attempt.
+    let metrics = &webhooks.hooks()[0].metrics;
+    wait_for(|| metrics.delivered.load(Ordering::Relaxed) == 1);
+    assert_eq!(metrics.failed.load(Ordering::Relaxed), 1);
+    assert_eq!(metrics.failed_attempts.load(Ordering::Relaxed), 3);
+    assert_eq!(received.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn queue_full() {
+    let mut config = hook("slow", None, Some(""), "http://127.0.0.1:1/");
+    config.policy.max_queue = 2;
+    let webhooks = Arc::new(Webhooks::new(vec![config]).unwrap());
+
+    // Not spawned, so nothing is taken off the queue.
+    let record = |key: &[u8]| ChangeRecord {
+        id: vec![1],
+        height: 1,
+        time: 0,
+        key: key.to_vec(),
+        operation: many_kvstore::feed::Operation::Put,
+        actor: None,
+        value_hash: None,
+    };
+    let mut sink = webhooks.sink();
+    sink.deliver(&[record(b"a"), record(b"b"), record(b"c")])
+        .unwrap();
+    let metrics = &webhooks.hooks()[0].metrics;
+    assert_eq!(metrics.queued.load(Ordering::Relaxed), 2);
+    assert_eq!(metrics.dropped.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn config() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("webhooks.json5");
+    std::fs::write(
+        &path,
+        r#"[
+            { name: "a", key: "foo", url: "http://localhost/a", secret: "s" },
+            { name: "b", prefix: "app/", url: "http://localhost/b", secret: "s",
+              policy: { max_retries: 2, max_queue: 10 } },
+        ]"#,
+    )
+    .unwrap();
+    let webhooks = Webhooks::from_file(&path).unwrap();
+    let hooks = webhooks.hooks();
+    assert_eq!(hooks[0].config.policy, DeliveryPolicy::default());
+    assert_eq!(hooks[1].config.policy.max_retries, 2);
+    assert_eq!(hooks[1].config.policy.max_queue, 10);
+    assert_eq!(hooks[1].config.policy.min_backoff_ms, 100);
+    assert!(hooks[1].config.matches(b"app/x"));
+    assert!(!hooks[1].config.matches(b"ap"));
+    assert!(hooks[0].config.matches(b"foo"));
+    assert!(!hooks[0].config.matches(b"foobar"));
+
+    let both = hook("both", Some("foo"), Some("foo"), "http://localhost/");
+    assert!(Webhooks::new(vec![both]).is_err());
+    let neither = hook("neither", None, None, "http://localhost/");
+    assert!(Webhooks::new(vec![neither]).is_err());
+    let duplicate = hook("a", Some("foo"), None, "http://localhost/");
+    assert!(Webhooks::new(vec![duplicate.clone(), duplicate]).is_err());
+}
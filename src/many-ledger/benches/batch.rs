@@ -0,0 +1,90 @@
+//! Compares sending through `ledger.send` one message at a time with sending
+//! the same transfers in `ledger.batch` messages. This measures the storage
+//! side only; a batch also saves one envelope verification per send.
+//!
+//! Run with `cargo bench --bench batch`.
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::json::InitialStateJson;
+use many_ledger::module::batch::{BatchArgs, BatchOperation, LedgerBatchModuleBackend};
+use many_ledger::module::LedgerModuleImpl;
+use many_modules::ledger::{self, LedgerCommandsModuleBackend};
+use many_types::ledger::Symbol;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const SENDS: u32 = 5_000;
+const BATCH_SIZE: u32 = 500;
+
+fn setup(sender: Address, symbol: Symbol) -> (LedgerModuleImpl, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut module_impl = LedgerModuleImpl::new(
+        Some(
+            InitialStateJson::read("../../staging/ledger_state.json5")
+                .or_else(|_| InitialStateJson::read("staging/ledger_state.json5"))
+                .expect("Could not read initial state."),
+        ),
+        dir.path(),
+        false,
+    )
+    .unwrap();
+    module_impl.set_balance_only_for_testing(sender, u64::MAX, symbol);
+    (module_impl, dir)
+}
+
+fn send_args(i: u32, symbol: Symbol) -> ledger::SendArgs {
+    ledger::SendArgs {
+        from: None,
+        to: identity(i % 100 + 1),
+        amount: 1u32.into(),
+        symbol,
+    }
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<12} {:>8.1} ms {:>10.0} sends/s",
+        name,
+        elapsed.as_secs_f64() * 1000.0,
+        SENDS as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let symbol =
+        Address::from_str("mqbfbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wiaaaaqnz").unwrap();
+    let sender = identity(0);
+
+    let (mut module_impl, _dir) = setup(sender, symbol);
+    let start = Instant::now();
+    for i in 0..SENDS {
+        module_impl.send(&sender, send_args(i, symbol)).unwrap();
+    }
+    let single = start.elapsed();
+
+    let (mut module_impl, _dir) = setup(sender, symbol);
+    let start = Instant::now();
+    for chunk in 0..SENDS / BATCH_SIZE {
+        let operations = (chunk * BATCH_SIZE..(chunk + 1) * BATCH_SIZE)
+            .map(|i| BatchOperation::Send(send_args(i, symbol)))
+            .collect();
+        module_impl
+            .batch(
+                &sender,
+                BatchArgs {
+                    operations,
+                    atomic: true,
+                },
+            )
+            .unwrap();
+    }
+    let batched = start.elapsed();
+
+    println!("{} sends, batches of {}:", SENDS, BATCH_SIZE);
+    report("ledger.send", single);
+    report("ledger.batch", batched);
+    println!(
+        "speedup      {:>8.2}x",
+        single.as_secs_f64() / batched.as_secs_f64()
+    );
+}
@@ -16,5 +16,69 @@ define_attribute_many_error!(
             => "Unable to send tokens to a destination (to) that is the same as the source (from).",
         9: pub fn amount_is_zero()
             => "Unable to send zero (0) token.",
+        10: pub fn unknown_escrow(escrow) => "Unknown escrow: {escrow}.",
+        11: pub fn escrow_already_settled()
+            => "This escrow was already released or refunded.",
+        12: pub fn escrow_expired() => "This escrow timed out and can only be refunded.",
+        13: pub fn escrow_not_expired()
+            => "This escrow can only be refunded by its payer after its timeout.",
+        14: pub fn invalid_escrow_timeout() => "The escrow timeout must be greater than zero.",
+        15: pub fn unknown_snapshot(snapshot) => "Unknown snapshot: {snapshot}.",
+        16: pub fn invalid_symbol_name_policy(reason)
+            => "Invalid symbol name policy: {reason}.",
+        17: pub fn symbol_name_length(name, min, max)
+            => "Symbol name '{name}' must be between {min} and {max} characters long.",
+        18: pub fn symbol_name_character(name, character)
+            => "Symbol name '{name}' contains a character which is not allowed: {character}.",
+        19: pub fn symbol_name_reserved(name, prefix)
+            => "Symbol name '{name}' uses the reserved prefix '{prefix}'.",
+        20: pub fn symbol_name_denied(name) => "Symbol name '{name}' is not allowed.",
+        21: pub fn symbol_name_taken(name, other)
+            => "Symbol name '{name}' is too similar to the existing symbol name '{other}'.",
+        22: pub fn unknown_swap(swap) => "Unknown swap: {swap}.",
+        23: pub fn swap_already_settled()
+            => "This swap was already accepted, cancelled or expired.",
+        24: pub fn swap_expired() => "This swap expired and can only be refunded.",
+        25: pub fn invalid_swap_expiry() => "The swap expiry must be greater than zero.",
+        26: pub fn swap_same_symbol() => "A swap must exchange two different symbols.",
+        27: pub fn memo_index_disabled() => "The memo index is not enabled on this node.",
+        28: pub fn unknown_parameter(name) => "Unknown governance parameter: {name}.",
+        29: pub fn invalid_parameter_value(name, reason)
+            => "Invalid value for governance parameter '{name}': {reason}.",
+        30: pub fn batch_empty() => "A batch must have at least one operation.",
+        31: pub fn batch_too_large(count, max)
+            => "A batch of {count} operations exceeds the maximum of {max}.",
+        32: pub fn batch_payload_too_large(size, max)
+            => "A batch of {size} bytes exceeds the maximum of {max} bytes.",
+        33: pub fn recovery_not_enabled(account)
+            => "Account {account} does not have recovery enabled.",
+        34: pub fn not_recovery_identity()
+            => "Only the recovery identity of the account can recover it.",
+        35: pub fn account_active(claimable)
+            => "An owner of the account was active recently. It can be claimed from {claimable} (seconds since the epoch).",
+        36: pub fn recovery_challenge_pending(completable)
+            => "The recovery can be completed from {completable} (seconds since the epoch), unless an owner vetoes it.",
+        37: pub fn invalid_recovery_inactivity()
+            => "The recovery inactivity period must be greater than zero.",
+        38: pub fn invalid_recovery_identity()
+            => "The recovery identity cannot be anonymous or the account itself.",
+        39: pub fn invalid_attachment(reason) => "Invalid attachment: {reason}.",
+        40: pub fn attachment_not_allowed(method)
+            => "Attachments are not supported on '{method}'.",
+        41: pub fn attachment_query_needs_one() => "Query an attachment by either event or token.",
+        42: pub fn unknown_fields(method, fields)
+            => "Unknown fields in the arguments of '{method}': {fields}.",
+        43: pub fn transfer_rejected_by_policy(to, policy)
+            => "{to} does not accept this transfer, its receive policy is '{policy}'.",
+        44: pub fn message_expired(method, window)
+            => "The timestamp of the message is outside of the {window} seconds window of '{method}' around the block time. Sign it again.",
+        45: pub fn message_stale(method, timestamp, now, not_before, not_after)
+            => "The timestamp {timestamp} of the message is outside of the window of '{method}', from {not_before} to {not_after} around the server time {now} (seconds since the epoch). Sign it again.",
+        46: pub fn stats_not_enabled()
+            => "The statistics of the chain are not available before the LedgerStats migration.",
+        47: pub fn unknown_attachment_fields(method, fields)
+            => "Unknown fields in the attachment of '{method}': {fields}.",
+        48: pub fn recovery_not_available()
+            => "Account recovery is not available before the AccountRecovery migration.",
     }
 );
@@ -1,4 +1,5 @@
 use crate::error;
+use crate::module::name_policy::SymbolNamePolicy;
 use crate::storage::LedgerStorage;
 use many_error::ManyError;
 use many_identity::Address;
@@ -135,6 +136,9 @@ pub struct InitialStateJson {
     pub accounts: Option<Vec<AccountJson>>,
     pub id_store_seed: Option<u64>,
     pub id_store_keys: Option<BTreeMap<String, String>>,
+    pub symbol_name_policy: Option<SymbolNamePolicy>,
+    /// Initial values of governance parameters, by name.
+    pub params: Option<BTreeMap<String, serde_json::Value>>,
     pub hash: Option<String>,
 }
 
@@ -2,11 +2,13 @@ use clap::Parser;
 use many_identity::verifiers::AnonymousVerifier;
 use many_identity::{Address, Identity};
 use many_identity_dsa::{CoseKeyIdentity, CoseKeyVerifier};
-use many_modules::account::features::Feature;
-use many_modules::{abci_backend, account, data, events, idstore, ledger};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::account::features::{Feature, TryCreateFeature};
+use many_modules::{abci_backend, account, data, events, idstore, ledger, ManyModule};
 use many_protocol::ManyUrl;
 use many_server::transport::http::HttpServer;
 use many_server::ManyServer;
+use many_storage::event_schema;
 use std::collections::BTreeSet;
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -102,6 +104,70 @@ struct Opts {
     /// Any addresses will be able to execute queries, e.g., balance, get, ...
     #[clap(long)]
     allow_addrs: Option<PathBuf>,
+
+    /// Verify the consistency of the persistent store, print the
+    /// discrepancies found and the supply of each symbol, and exit.
+    #[clap(long)]
+    verify: bool,
+
+    /// Repair the discrepancies found by `--verify` when possible.
+    #[clap(long, requires = "verify")]
+    repair: bool,
+
+    /// Maintain a node-local index of event memos, to serve
+    /// `events.searchMemo`. The index is built from the event log at
+    /// startup and kept in memory; it is not part of the consensus state.
+    #[clap(long)]
+    memo_index: bool,
+
+    /// The maximum number of tokens held by the memo index. The oldest
+    /// events are dropped from the index past this.
+    #[clap(long, default_value_t = 1_000_000)]
+    memo_index_max_entries: usize,
+
+    /// Check the merk tree against the bytes of the persistent store in the
+    /// background, keeping where the pass is in this file. Disabled if not
+    /// specified.
+    #[clap(long)]
+    integrity_cursor: Option<PathBuf>,
+
+    /// The number of nodes of the merk tree checked at once.
+    #[clap(long, default_value_t = many_storage::integrity::DEFAULT_SLICE_SIZE)]
+    integrity_slice_size: u64,
+
+    /// The number of seconds between two checks of the merk tree.
+    #[clap(long, default_value_t = many_storage::integrity::DEFAULT_INTERVAL_SECS)]
+    integrity_interval: u64,
+
+    /// Exit when the merk tree is found corrupted, instead of only logging
+    /// it.
+    #[clap(long, requires = "integrity-cursor")]
+    halt_on_corruption: bool,
+}
+
+type Counted<M> = stats::TransactionCountModule<
+    recovery::ActivityModule<
+        expiry::ExpiryModule<strict_args::StrictArgsModule<M, LedgerModuleImpl>, LedgerModuleImpl>,
+        LedgerModuleImpl,
+    >,
+    LedgerModuleImpl,
+>;
+
+/// Count the commands of a module which succeed in the chain statistics, and
+/// record the activity of their senders for the recovery of accounts. Their
+/// timestamp is checked against the expiry window, and their arguments in
+/// strict mode.
+fn counted<M: ManyModule>(inner: M, backend: &Arc<Mutex<LedgerModuleImpl>>) -> Counted<M> {
+    stats::TransactionCountModule::new(
+        recovery::ActivityModule::new(
+            expiry::ExpiryModule::new(
+                strict_args::StrictArgsModule::new(inner, backend.clone()),
+                backend.clone(),
+            ),
+            backend.clone(),
+        ),
+        backend.clone(),
+    )
 }
 
 fn main() {
@@ -117,6 +183,14 @@ fn main() {
         logmode,
         migrations_config,
         allow_addrs,
+        verify,
+        repair,
+        memo_index,
+        memo_index_max_entries,
+        integrity_cursor,
+        integrity_slice_size,
+        integrity_interval,
+        halt_on_corruption,
         ..
     } = Opts::parse();
 
@@ -188,8 +262,69 @@ fn main() {
     let module_impl = LedgerModuleImpl::new(state, persistent, abci)
         .unwrap()
         .with_migrations(migrations);
+    let module_impl = if memo_index {
+        module_impl
+            .with_memo_index(memo_index_max_entries)
+            .expect("Could not build the memo index")
+    } else {
+        module_impl
+    };
+
+    if verify {
+        let mut module_impl = module_impl;
+        let result = module_impl.verify_all(repair).unwrap();
+        for d in &result.discrepancies {
+            println!(
+                "{:?} at {:?}: found {}, expected {}{}",
+                d.kind,
+                String::from_utf8_lossy(d.key.as_slice()),
+                d.actual
+                    .as_ref()
+                    .map_or_else(|| "nothing".to_string(), |x| hex::encode(x.as_slice())),
+                d.expected
+                    .as_ref()
+                    .map_or_else(|| "nothing".to_string(), |x| hex::encode(x.as_slice())),
+                if d.repairable {
+                    ""
+                } else {
+                    " (not repairable)"
+                },
+            );
+        }
+        for (symbol, supply) in result.supply.unwrap_or_default() {
+            println!("Supply of {}: {}", symbol, supply);
+        }
+
+        let unrepaired = result
+            .discrepancies
+            .iter()
+            .filter(|d| !repair || !d.repairable)
+            .count();
+        info!(
+            discrepancies = result.discrepancies.len(),
+            repaired = result.discrepancies.len() - unrepaired
+        );
+        std::process::exit(if unrepaired == 0 { 0 } else { 1 });
+    }
+
+    let endpoints = module_impl
+        .init()
+        .expect("Could not list the endpoints.")
+        .endpoints;
     let module_impl = Arc::new(Mutex::new(module_impl));
 
+    let mut posture = posture::PostureBuilder::new(endpoints)
+        .with_verifiers(&["anonymous", "cose-key"])
+        .with_listener("many", addr)
+        .with_limit(
+            "memo-index",
+            if memo_index {
+                format!("{memo_index_max_entries} entries")
+            } else {
+                "off".to_string()
+            },
+        );
+
     #[cfg(feature = "balance_testing")]
     {
         use std::str::FromStr;
@@ -216,6 +351,21 @@ fn main() {
         }
     }
 
+    let integrity_metrics = integrity_cursor.map(|path| {
+        info!(
+            "Checking the merk tree in the background, {} nodes every {}s",
+            integrity_slice_size, integrity_interval
+        );
+        let scrubber =
+            many_storage::integrity::Scrubber::new(module_impl.clone(), path, integrity_slice_size)
+                .expect("Could not start the integrity checks")
+                .with_halt_on_corruption(halt_on_corruption);
+        let metrics = scrubber.metrics();
+        scrubber.spawn(std::time::Duration::from_secs(integrity_interval));
+        metrics
+    });
+
+    let server_id = key.address();
     let many = ManyServer::simple(
         "many-ledger",
         key,
@@ -225,19 +375,73 @@ fn main() {
 
     {
         let mut s = many.lock().unwrap();
-        s.add_module(ledger::LedgerModule::new(module_impl.clone()));
+        s.add_module(posture.open(escrow::EscrowBalanceModule {
+            inner: ledger::LedgerModule::new(module_impl.clone()),
+            backend: module_impl.clone(),
+        }));
+        s.add_module(posture.open(counted(
+            escrow::LedgerEscrowModule::new(module_impl.clone()),
+            &module_impl,
+        )));
+        s.add_module(posture.open(counted(
+            swap::LedgerSwapModule::new(module_impl.clone()),
+            &module_impl,
+        )));
+        s.add_module(posture.open(counted(
+            snapshot::LedgerSnapshotModule::new(module_impl.clone()),
+            &module_impl,
+        )));
+        s.add_module(posture.open(counted(
+            name_policy::LedgerNamePolicyModule::new(module_impl.clone()),
+            &module_impl,
+        )));
+        s.add_module(posture.open(counted(
+            receive_policy::LedgerReceivePolicyModule::new(module_impl.clone()),
+            &module_impl,
+        )));
+        s.add_module(posture.open(counted(
+            governance::LedgerGovernanceModule::new(module_impl.clone()),
+            &module_impl,
+        )));
+        s.add_module(posture.open(verify::LedgerVerifyModule::new(module_impl.clone())));
+        s.add_module(posture.open(stats::LedgerStatsModule::new(module_impl.clone())));
+        s.add_module(posture.open(attachment::LedgerAttachmentModule::new(module_impl.clone())));
         let ledger_command_module = ledger::LedgerCommandsModule::new(module_impl.clone());
+        let batch_module = batch::LedgerBatchModule::new(module_impl.clone());
         if let Some(path) = allow_addrs {
             let allow_addrs: BTreeSet<Address> =
                 json5::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
-            s.add_module(AllowAddrsModule {
-                inner: ledger_command_module,
-                allow_addrs,
-            });
+            posture = posture.with_allow_addrs(Some(&allow_addrs));
+            s.add_module(counted(
+                posture.gated(batch_module.with_allow_addrs(allow_addrs.clone())),
+                &module_impl,
+            ));
+            s.add_module(counted(
+                attachment::AttachmentModule::new(
+                    posture.gated(AllowAddrsModule {
+                        inner: ledger_command_module,
+                        allow_addrs,
+                    }),
+                    module_impl.clone(),
+                ),
+                &module_impl,
+            ));
         } else {
-            s.add_module(ledger_command_module);
+            s.add_module(counted(posture.gated(batch_module), &module_impl));
+            s.add_module(counted(
+                attachment::AttachmentModule::new(
+                    posture.gated(ledger_command_module),
+                    module_impl.clone(),
+                ),
+                &module_impl,
+            ));
         }
-        s.add_module(events::EventsModule::new(module_impl.clone()));
+        s.add_module(posture.open(event_schema::EventSchemaModule::new(
+            events::EventsModule::new(module_impl.clone()),
+        )));
+        s.add_module(posture.open(memo_search::LedgerMemoSearchModule::new(
+            module_impl.clone(),
+        )));
 
         let idstore_module = idstore::IdStoreModule::new(module_impl.clone());
         #[cfg(feature = "webauthn_testing")]
@@ -248,25 +452,58 @@ fn main() {
             } = Opts::parse();
 
             if disable_webauthn_only_for_testing {
-                s.add_module(IdStoreWebAuthnModule {
-                    inner: idstore_module,
-                    check_webauthn: false,
-                });
+                posture = posture.with_limit("webauthn-checks", "disabled");
+                s.add_module(posture.open(counted(
+                    IdStoreWebAuthnModule {
+                        inner: idstore_module,
+                        check_webauthn: false,
+                    },
+                    &module_impl,
+                )));
             } else {
-                s.add_module(idstore_module);
+                s.add_module(posture.open(counted(idstore_module, &module_impl)));
             }
         }
         #[cfg(not(feature = "webauthn_testing"))]
-        s.add_module(idstore_module);
-
-        s.add_module(AccountFeatureModule::new(
-            account::AccountModule::new(module_impl.clone()),
-            [Feature::with_id(0), Feature::with_id(1)],
-        ));
-        s.add_module(account::features::multisig::AccountMultisigModule::new(
+        s.add_module(posture.open(counted(idstore_module, &module_impl)));
+
+        s.add_module(posture.open(counted(
+            AccountFeatureModule::new(
+                account::AccountModule::new(module_impl.clone()),
+                [
+                    Feature::with_id(0),
+                    Feature::with_id(1),
+                    Feature::with_id(recovery::AccountRecovery::ID),
+                ],
+            ),
+            &module_impl,
+        )));
+        s.add_module(posture.open(counted(
+            attachment::AttachmentModule::new(
+                account::features::multisig::AccountMultisigModule::new(module_impl.clone()),
+                module_impl.clone(),
+            ),
+            &module_impl,
+        )));
+        s.add_module(posture.open(counted(
+            recovery::AccountRecoveryModule::new(module_impl.clone()),
+            &module_impl,
+        )));
+        s.add_module(posture.open(data::DataModule::new(module_impl.clone())));
+        s.add_module(expiry::ReplayProtectionModule::new(
             module_impl.clone(),
+            if abci {
+                expiry::Clock::Block
+            } else {
+                expiry::Clock::Wall
+            },
         ));
-        s.add_module(data::DataModule::new(module_impl.clone()));
+        let posture = posture.build();
+        info!("{}", posture);
+        s.add_module(posture::PostureModule::new(posture, server_id));
+        if let Some(metrics) = integrity_metrics {
+            s.add_module(integrity::IntegrityModule::new(metrics, server_id));
+        }
         if abci {
             s.set_timeout(u64::MAX);
             s.add_module(abci_backend::AbciModule::new(module_impl));
@@ -1,13 +1,13 @@
 pub mod data;
-
-use merk::Op;
-use std::{collections::BTreeSet, fmt::Debug};
-use tracing::info;
+pub mod multisig;
+pub mod recovery;
+pub mod roles;
+pub mod stats;
 
 #[cfg(feature = "migrate_blocks")]
 use many_protocol::ResponseMessage;
 
-use crate::storage::MIGRATIONS_KEY;
+pub use many_storage::migration::{run_migrations, Migration};
 
 #[cfg(feature = "block_9400")]
 mod block_9400;
@@ -20,57 +20,3 @@ pub fn migrate(tx_id: &[u8], response: ResponseMessage) -> ResponseMessage {
         _ => response,
     }
 }
-
-#[typetag::serde(tag = "type")]
-pub trait Migration: Debug + Send + Sync {
-    fn migrate(&self, persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)>;
-    fn block_height(&self) -> u64;
-    fn issue(&self) -> Option<&str>;
-    fn name(&self) -> &str;
-}
-
-impl PartialEq<Box<dyn Migration>> for Box<dyn Migration> {
-    fn eq(&self, other: &Box<dyn Migration>) -> bool {
-        self.name().eq(other.name())
-    }
-}
-
-impl Eq for Box<dyn Migration> {}
-
-impl PartialOrd<Box<dyn Migration>> for Box<dyn Migration> {
-    fn partial_cmp(&self, other: &Box<dyn Migration>) -> Option<std::cmp::Ordering> {
-        self.name().partial_cmp(other.name())
-    }
-}
-
-impl Ord for Box<dyn Migration> {
-    fn cmp(&self, other: &Box<dyn Migration>) -> std::cmp::Ordering {
-        self.name().cmp(other.name())
-    }
-}
-
-pub fn run_migrations(
-    current_height: u64,
-    all_migrations: &BTreeSet<Box<dyn Migration>>,
-    active_migrations: &mut BTreeSet<String>,
-    persistent_store: &mut merk::Merk,
-) {
-    let mut operations = vec![];
-    for migration in all_migrations {
-        if current_height >= migration.block_height()
-            && active_migrations.insert(migration.name().to_string())
-        {
-            info!("Migration {:?} being applied", migration);
-            operations.append(&mut migration.migrate(persistent_store))
-        }
-    }
-    operations.push((
-        MIGRATIONS_KEY.to_vec(),
-        Op::Put(
-            minicbor::to_vec(active_migrations.clone())
-                .expect("Could not encode migrations to cbor"),
-        ),
-    ));
-    operations.sort_by(|(a, _), (b, _)| a.cmp(b));
-    persistent_store.apply(&operations).unwrap();
-}
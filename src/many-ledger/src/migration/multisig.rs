@@ -0,0 +1,34 @@
+use merk::Op;
+use serde::{Deserialize, Serialize};
+
+use super::Migration;
+
+/// The name of the migration from which a multisig transaction sending funds
+/// is checked when it is submitted, rather than only when it is executed.
+pub const CHECKED_MULTISIG_SENDS_MIGRATION: &str = "CheckedMultisigSends";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckedMultisigSends {
+    block_height: u64,
+    issue: Option<String>,
+}
+
+#[typetag::serde]
+impl Migration for CheckedMultisigSends {
+    fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    fn name(&self) -> &str {
+        CHECKED_MULTISIG_SENDS_MIGRATION
+    }
+
+    /// The transactions already submitted are checked when executed.
+    fn migrate(&self, _persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)> {
+        vec![]
+    }
+}
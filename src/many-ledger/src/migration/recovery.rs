@@ -0,0 +1,34 @@
+use merk::Op;
+use serde::{Deserialize, Serialize};
+
+use super::Migration;
+
+/// The name of the migration from which accounts can configure their
+/// recovery, and the activity of their owners is recorded.
+pub const ACCOUNT_RECOVERY_MIGRATION: &str = "AccountRecovery";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountRecovery {
+    block_height: u64,
+    issue: Option<String>,
+}
+
+#[typetag::serde]
+impl Migration for AccountRecovery {
+    fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    fn name(&self) -> &str {
+        ACCOUNT_RECOVERY_MIGRATION
+    }
+
+    /// No account has configured its recovery yet.
+    fn migrate(&self, _persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)> {
+        vec![]
+    }
+}
@@ -0,0 +1,35 @@
+use merk::Op;
+use serde::{Deserialize, Serialize};
+
+use super::Migration;
+
+/// The name of the migration from which the role events only list the roles
+/// which were actually added or removed. Before it, they list the roles of
+/// the arguments, which replaying a chain must keep.
+pub const CHANGED_ROLE_EVENTS_MIGRATION: &str = "ChangedRoleEvents";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangedRoleEvents {
+    block_height: u64,
+    issue: Option<String>,
+}
+
+#[typetag::serde]
+impl Migration for ChangedRoleEvents {
+    fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    fn name(&self) -> &str {
+        CHANGED_ROLE_EVENTS_MIGRATION
+    }
+
+    /// The events already logged are kept as they are.
+    fn migrate(&self, _persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)> {
+        vec![]
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use many_types::ledger::TokenAmount;
+use merk::rocksdb::{self, ReadOptions};
+use merk::Op;
+use serde::{Deserialize, Serialize};
+
+use super::Migration;
+use crate::storage::stats::{
+    counter_entry, ACCOUNTS_KEY, NONZERO_ADDRESSES_KEY, NONZERO_SYMBOLS_ROOT, PENDING_MULTISIG_KEY,
+};
+use crate::storage::{
+    LedgerStorage, MultisigTransactionStorage, ACCOUNTS_ROOT, BALANCES_ROOT, EVENTS_ROOT,
+    MULTISIG_TRANSACTIONS_ROOT,
+};
+use many_storage::event_schema::decode_event;
+
+/// The name of the migration which starts maintaining the `/stats/`
+/// counters of `ledger.stats`.
+pub const LEDGER_STATS_MIGRATION: &str = "LedgerStats";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LedgerStats {
+    block_height: u64,
+    issue: Option<String>,
+}
+
+/// The keys and values under `root`, from the last one if `reverse` is set.
+fn entries_under<'a>(
+    persistent_store: &'a merk::Merk,
+    root: &[u8],
+    reverse: bool,
+) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a {
+    let mut upper_bound = root.to_vec();
+    *upper_bound.last_mut().unwrap() += 1;
+    let mut opts = ReadOptions::default();
+    opts.set_iterate_lower_bound(root.to_vec());
+    opts.set_iterate_upper_bound(upper_bound);
+
+    let mode = if reverse {
+        rocksdb::IteratorMode::End
+    } else {
+        rocksdb::IteratorMode::Start
+    };
+    persistent_store.iter_opt(mode, opts).map(|item| {
+        let (key, value) = item.expect("Error while reading the DB");
+        let value = merk::tree::Tree::decode(key.to_vec(), value.as_ref());
+        (key.to_vec(), value.value().to_vec())
+    })
+}
+
+#[typetag::serde]
+impl Migration for LedgerStats {
+    fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    fn name(&self) -> &str {
+        LEDGER_STATS_MIGRATION
+    }
+
+    /// Count what the counters would have counted since the start of the
+    /// chain, except the transactions which are counted from now on.
+    fn migrate(&self, persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)> {
+        // Balance keys are `/balances/<address>/<symbol>`.
+        let mut nonzero_symbols: BTreeMap<Vec<u8>, u64> = BTreeMap::new();
+        for (key, value) in entries_under(persistent_store, BALANCES_ROOT, false) {
+            if TokenAmount::from(value).is_zero() {
+                continue;
+            }
+            let address = key[BALANCES_ROOT.len()..]
+                .split(|b| *b == b'/')
+                .next()
+                .unwrap_or_default();
+            *nonzero_symbols.entry(address.to_vec()).or_default() += 1;
+        }
+
+        let accounts = entries_under(persistent_store, ACCOUNTS_ROOT, false).count() as u64;
+        let pending_multisig = entries_under(persistent_store, MULTISIG_TRANSACTIONS_ROOT, false)
+            .filter_map(|(_, value)| minicbor::decode::<MultisigTransactionStorage>(&value).ok())
+            .filter(|tx| !tx.disabled)
+            .count() as u64;
+        let last_event = entries_under(persistent_store, EVENTS_ROOT, true)
+            .next()
+            .map(|(_, value)| decode_event(&value).expect("Could not decode the last event"));
+
+        let mut operations = vec![
+            counter_entry(ACCOUNTS_KEY, accounts),
+            counter_entry(NONZERO_ADDRESSES_KEY, nonzero_symbols.len() as u64),
+            counter_entry(PENDING_MULTISIG_KEY, pending_multisig),
+        ];
+        operations.extend(nonzero_symbols.into_iter().map(|(address, count)| {
+            counter_entry(&[NONZERO_SYMBOLS_ROOT, &address].concat(), count)
+        }));
+        operations.extend(last_event.as_ref().map(LedgerStorage::last_event_entry));
+        operations
+    }
+}
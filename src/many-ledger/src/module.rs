@@ -1,5 +1,6 @@
 use crate::json::InitialStateJson;
 use crate::migration::Migration;
+use crate::module::posture::SenderGate;
 use crate::storage::migration_ext::data::DataExt;
 use crate::{error, storage::LedgerStorage};
 use coset::{CborSerializable, CoseKey, CoseSign1};
@@ -17,16 +18,33 @@ use many_modules::data::{
 };
 use many_modules::{account, events, idstore, ledger, EmptyReturn, ManyModule, ManyModuleInfo};
 use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::event_schema;
 use many_types::cbor::CborAny;
 use many_types::ledger::Symbol;
 use many_types::{CborRange, Timestamp, VecOrSingle};
 use minicbor::bytes::ByteVec;
-use minicbor::decode;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
 use std::path::Path;
 use tracing::info;
 
+pub mod attachment;
+pub mod batch;
+pub mod escrow;
+pub mod expiry;
+pub mod governance;
+pub mod integrity;
+pub mod memo_search;
+pub mod name_policy;
+pub use many_storage::posture;
+pub mod receive_policy;
+pub mod recovery;
+pub mod snapshot;
+pub mod stats;
+pub mod strict_args;
+pub mod swap;
+pub mod verify;
+
 const MAXIMUM_EVENT_COUNT: usize = 100;
 
 fn get_roles_for_account(account: &account::Account) -> BTreeSet<account::Role> {
@@ -59,6 +77,11 @@ pub(crate) fn validate_features_for_account(account: &account::Account) -> Resul
             return Err(e);
         }
     }
+    if let Err(e) = features.get::<recovery::AccountRecovery>() {
+        if e.code() != ManyErrorCode::AttributeNotFound {
+            return Err(e);
+        }
+    }
 
     Ok(())
 }
@@ -201,6 +224,17 @@ impl LedgerModuleImpl {
                 }
                 storage.commit_persistent_store().expect("Could not commit");
             }
+            if let Some(policy) = state.symbol_name_policy {
+                storage.init_symbol_name_policy(policy)?;
+                storage.commit_persistent_store().expect("Could not commit");
+            }
+            if let Some(params) = state.params {
+                for (name, value) in params {
+                    let param = governance::Param::from_name(&name)?;
+                    storage.init_param(param, param.value_from_json(&value)?)?;
+                }
+                storage.commit_persistent_store().expect("Could not commit");
+            }
             if let Some(h) = state.hash {
                 // Verify the hash.
                 let actual = hex::encode(storage.hash());
@@ -232,6 +266,28 @@ impl LedgerModuleImpl {
         self.storage
             .set_balance_only_for_testing(account, balance, symbol);
     }
+
+    /// Verify the sender can move funds from the `from` address, either
+    /// because it is the sender itself or an account it has the roles for.
+    fn verify_can_transact(&self, sender: &Address, from: &Address) -> Result<(), ManyError> {
+        if from != sender {
+            if let Some(account) = self.storage.get_account(from) {
+                if !account.has_role(sender, account::Role::Owner) {
+                    if account
+                        .features
+                        .has_id(account::features::ledger::AccountLedger::ID)
+                    {
+                        account.needs_role(sender, [account::Role::CanLedgerTransact])?;
+                    } else {
+                        return Err(error::unauthorized());
+                    }
+                }
+            } else {
+                return Err(error::unauthorized());
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ledger::LedgerModuleBackend for LedgerModuleImpl {
@@ -290,22 +346,7 @@ impl ledger::LedgerCommandsModuleBackend for LedgerModuleImpl {
         } = args;
 
         let from = from.as_ref().unwrap_or(sender);
-        if from != sender {
-            if let Some(account) = self.storage.get_account(from) {
-                if !account.has_role(sender, account::Role::Owner) {
-                    if account
-                        .features
-                        .has_id(account::features::ledger::AccountLedger::ID)
-                    {
-                        account.needs_role(sender, [account::Role::CanLedgerTransact])?;
-                    } else {
-                        return Err(error::unauthorized());
-                    }
-                }
-            } else {
-                return Err(error::unauthorized());
-            }
-        }
+        self.verify_can_transact(sender, from)?;
 
         self.storage.send(from, &to, &symbol, amount)?;
         Ok(EmptyReturn)
@@ -342,8 +383,7 @@ impl events::EventsModuleBackend for LedgerModuleImpl {
 
         let iter = Box::new(iter.map(|item| {
             let (_k, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
-            decode::<events::EventLog>(v.as_slice())
-                .map_err(|e| ManyError::deserialization_error(e.to_string()))
+            event_schema::decode_event(v.as_slice())
         }));
 
         let iter = filter_account(iter, filter.account);
@@ -367,10 +407,56 @@ impl ManyAbciModuleBackend for LedgerModuleImpl {
                 ("ledger.info".to_string(), EndpointInfo { is_command: false }),
                 ("ledger.balance".to_string(), EndpointInfo { is_command: false }),
                 ("ledger.send".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.batch".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.attachment".to_string(), EndpointInfo { is_command: false }),
+
+                // Escrow
+                ("ledger.escrowCreate".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.escrowRelease".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.escrowRefund".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.escrowInfo".to_string(), EndpointInfo { is_command: false }),
+
+                // Swaps
+                ("ledger.swapCreate".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.swapAccept".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.swapCancel".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.swapInfo".to_string(), EndpointInfo { is_command: false }),
+
+                // Token snapshots
+                ("tokens.snapshot".to_string(), EndpointInfo { is_command: true }),
+                ("tokens.snapshotBalance".to_string(), EndpointInfo { is_command: false }),
+                ("tokens.listSnapshots".to_string(), EndpointInfo { is_command: false }),
+
+                // Symbol name policy
+                ("tokens.namePolicy".to_string(), EndpointInfo { is_command: false }),
+                ("tokens.setNamePolicy".to_string(), EndpointInfo { is_command: true }),
+                ("tokens.checkSymbolName".to_string(), EndpointInfo { is_command: false }),
+                ("tokens.namesDigest".to_string(), EndpointInfo { is_command: false }),
+
+                // Receive policies
+                ("ledger.receivePolicy".to_string(), EndpointInfo { is_command: false }),
+                ("ledger.setReceivePolicy".to_string(), EndpointInfo { is_command: true }),
+                ("ledger.checkReceive".to_string(), EndpointInfo { is_command: false }),
+
+                // Governance parameters
+                ("governance.params".to_string(), EndpointInfo { is_command: false }),
+                ("governance.setParam".to_string(), EndpointInfo { is_command: true }),
+                ("governance.changes".to_string(), EndpointInfo { is_command: false }),
+
+                // Integrity verification
+                ("ledger.verify".to_string(), EndpointInfo { is_command: false }),
+
+                // Chain statistics
+                ("ledger.stats".to_string(), EndpointInfo { is_command: false }),
+
+                // Replay protection
+                ("ledger.replayProtection".to_string(), EndpointInfo { is_command: false }),
 
                 // Events
                 ("events.info".to_string(), EndpointInfo { is_command: false }),
                 ("events.list".to_string(), EndpointInfo { is_command: false }),
+                // Node-local, not consensus state.
+                ("events.searchMemo".to_string(), EndpointInfo { is_command: false }),
 
                 // IdStore
                 ("idstore.store".to_string(), EndpointInfo { is_command: true }),
@@ -397,10 +483,19 @@ impl ManyAbciModuleBackend for LedgerModuleImpl {
                 ("account.multisigExecute".to_string(), EndpointInfo { is_command: true }),
                 ("account.multisigWithdraw".to_string(), EndpointInfo { is_command: true }),
 
+                // Account Features - Recovery
+                ("account.setRecovery".to_string(), EndpointInfo { is_command: true }),
+                ("account.recover".to_string(), EndpointInfo { is_command: true }),
+                ("account.recoveryInfo".to_string(), EndpointInfo { is_command: false }),
+
                 // Data Attributes
                 ("data.info".to_string(), EndpointInfo { is_command: false }),
                 ("data.getInfo".to_string(), EndpointInfo { is_command: false }),
                 ("data.query".to_string(), EndpointInfo { is_command: false }),
+
+                // Server
+                ("server.posture".to_string(), EndpointInfo { is_command: false }),
+                ("server.integrity".to_string(), EndpointInfo { is_command: false }),
             ]),
         })
     }
@@ -916,6 +1011,12 @@ pub struct AllowAddrsModule<T: ledger::LedgerCommandsModuleBackend> {
     pub allow_addrs: BTreeSet<Address>,
 }
 
+impl<T: ledger::LedgerCommandsModuleBackend> SenderGate for AllowAddrsModule<T> {
+    fn admits(&self, _method: &str, sender: &Address) -> bool {
+        self.allow_addrs.contains(sender)
+    }
+}
+
 impl<T: ledger::LedgerCommandsModuleBackend> Debug for AllowAddrsModule<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("AllowAddrsModule")
@@ -933,7 +1034,7 @@ impl<T: ledger::LedgerCommandsModuleBackend> ManyModule for AllowAddrsModule<T>
     }
 
     async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
-        if !self.allow_addrs.contains(&message.from()) {
+        if !self.admits(&message.method, &message.from()) {
             return Err(ManyError::invalid_from_identity());
         }
 
@@ -0,0 +1,267 @@
+use crate::error;
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events::EventId;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// The request attribute carrying an attachment, as its CBOR encoding.
+pub const ATTACHMENT: Attribute = Attribute::id(2000);
+
+/// The commands which can carry an attachment.
+pub const ATTACHABLE_ENDPOINTS: [&str; 2] = ["ledger.send", "account.multisigSubmitTransaction"];
+
+/// The maximum length of the key of an attachment.
+pub const MAX_ATTACHMENT_KEY_LEN: usize = 256;
+
+/// The length of the SHA-256 hash of an attachment.
+pub const ATTACHMENT_HASH_LEN: usize = 32;
+
+/// A reference to a value stored in a key-value store, such as an invoice,
+/// recorded with a transaction instead of the value itself. The ledger only
+/// checks its format; clients check the value against its hash when they
+/// fetch it.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct Attachment {
+    /// The key-value store holding the value.
+    #[n(0)]
+    pub server: Address,
+
+    #[n(1)]
+    pub key: ByteVec,
+
+    /// The SHA-256 hash of the value.
+    #[n(2)]
+    pub hash: ByteVec,
+}
+
+impl Attachment {
+    pub fn validate(&self) -> Result<(), ManyError> {
+        if self.server.is_anonymous() {
+            return Err(error::invalid_attachment("the server cannot be anonymous"));
+        }
+        if self.key.is_empty() || self.key.len() > MAX_ATTACHMENT_KEY_LEN {
+            return Err(error::invalid_attachment(format!(
+                "the key must be between 1 and {} bytes long",
+                MAX_ATTACHMENT_KEY_LEN
+            )));
+        }
+        if self.hash.len() != ATTACHMENT_HASH_LEN {
+            return Err(error::invalid_attachment(format!(
+                "the hash must be {} bytes long",
+                ATTACHMENT_HASH_LEN
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the attachment of a request, if it has one.
+    pub fn from_request(message: &RequestMessage) -> Result<Option<Self>, ManyError> {
+        let attribute = match message.attributes.get_attribute(ATTACHMENT.id) {
+            Some(attribute) => attribute,
+            None => return Ok(None),
+        };
+        let attachment: Self = match attribute.arguments.as_slice() {
+            [CborAny::Bytes(bytes)] => {
+                minicbor::decode(bytes).map_err(|e| error::invalid_attachment(e.to_string()))?
+            }
+            _ => return Err(ManyError::invalid_attribute_arguments()),
+        };
+        attachment.validate()?;
+        Ok(Some(attachment))
+    }
+}
+
+/// The attachment of an event, or of the multisig transaction submitted
+/// with a token.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct AttachmentArgs {
+    #[n(0)]
+    pub event: Option<EventId>,
+
+    #[n(1)]
+    pub token: Option<ByteVec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct AttachmentReturns {
+    #[n(0)]
+    pub attachment: Option<Attachment>,
+}
+
+pub trait LedgerAttachmentModuleBackend: Send {
+    fn attachment(
+        &self,
+        sender: &Address,
+        args: AttachmentArgs,
+    ) -> Result<AttachmentReturns, ManyError>;
+
+    /// The ID of the last event logged.
+    fn latest_event_id(&self) -> EventId;
+
+    /// Record an attachment on the transfers and multisig submissions logged
+    /// after an event.
+    fn attach(&mut self, since: &EventId, attachment: &Attachment) -> Result<(), ManyError>;
+}
+
+impl LedgerAttachmentModuleBackend for LedgerModuleImpl {
+    fn attachment(
+        &self,
+        _sender: &Address,
+        args: AttachmentArgs,
+    ) -> Result<AttachmentReturns, ManyError> {
+        let attachment = match args {
+            AttachmentArgs {
+                event: Some(event),
+                token: None,
+            } => self.storage.get_event_attachment(&event)?,
+            AttachmentArgs {
+                event: None,
+                token: Some(token),
+            } => self.storage.get_multisig_attachment(&token)?,
+            _ => return Err(error::attachment_query_needs_one()),
+        };
+        Ok(AttachmentReturns { attachment })
+    }
+
+    fn latest_event_id(&self) -> EventId {
+        self.storage.latest_event_id()
+    }
+
+    fn attach(&mut self, since: &EventId, attachment: &Attachment) -> Result<(), ManyError> {
+        self.storage.attach(since, attachment)
+    }
+}
+
+const ATTACHMENT_ENDPOINTS: [&str; 1] = ["ledger.attachment"];
+
+/// A module to query the attachments of transactions.
+pub struct LedgerAttachmentModule<T: LedgerAttachmentModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerAttachmentModuleBackend> LedgerAttachmentModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerAttachmentModule".to_string(),
+                attribute: None,
+                endpoints: ATTACHMENT_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerAttachmentModuleBackend> Debug for LedgerAttachmentModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerAttachmentModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerAttachmentModuleBackend> ManyModule for LedgerAttachmentModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.attachment" => decode_args::<AttachmentArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "ledger.attachment" => decode_args(&message.data)
+                    .and_then(|args| backend.attachment(&from, args))
+                    .and_then(encode_returns),
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
+
+/// Records the attachment of the commands of a module which carry one, on
+/// the events they log when they succeed.
+pub struct AttachmentModule<M: ManyModule, T: LedgerAttachmentModuleBackend> {
+    inner: M,
+    backend: Arc<Mutex<T>>,
+}
+
+impl<M: ManyModule, T: LedgerAttachmentModuleBackend> AttachmentModule<M, T> {
+    pub fn new(inner: M, backend: Arc<Mutex<T>>) -> Self {
+        Self { inner, backend }
+    }
+
+    fn attachment(message: &RequestMessage) -> Result<Option<Attachment>, ManyError> {
+        let attachment = Attachment::from_request(message)?;
+        if attachment.is_some() && !ATTACHABLE_ENDPOINTS.contains(&message.method.as_str()) {
+            return Err(error::attachment_not_allowed(message.method.clone()));
+        }
+        Ok(attachment)
+    }
+}
+
+impl<M: ManyModule, T: LedgerAttachmentModuleBackend> Debug for AttachmentModule<M, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AttachmentModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule, T: LedgerAttachmentModuleBackend> ManyModule for AttachmentModule<M, T> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        Self::attachment(message)?;
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let attachment = match Self::attachment(&message) {
+            Ok(Some(attachment)) => attachment,
+            Ok(None) => return self.inner.execute(message).await,
+            Err(e) => return Ok(ResponseMessage::from_request(&message, &message.to, Err(e))),
+        };
+
+        let since = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .latest_event_id();
+        let response = self.inner.execute(message).await?;
+
+        if response.data.is_ok() {
+            self.backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?
+                .attach(&since, &attachment)?;
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,268 @@
+use crate::error;
+use crate::module::posture::SenderGate;
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ledger, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::encode_returns;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of operations in a batch.
+pub const MAXIMUM_BATCH_COUNT: usize = 500;
+
+/// Maximum size of the encoded arguments of a batch, in bytes.
+pub const MAXIMUM_BATCH_SIZE: usize = 256 * 1024;
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum BatchOperation {
+    /// A send, as with `ledger.send`.
+    #[n(0)]
+    Send(#[n(0)] ledger::SendArgs),
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+#[cbor(map)]
+pub struct BatchArgs {
+    /// The operations, executed in order.
+    #[n(0)]
+    pub operations: Vec<BatchOperation>,
+
+    /// If true, nothing is executed unless every operation succeeds.
+    /// Otherwise operations which fail are skipped.
+    #[n(1)]
+    pub atomic: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum BatchItemStatus {
+    #[n(0)]
+    Executed,
+    #[n(1)]
+    Failed,
+    /// Not executed, because another operation of an atomic batch failed.
+    #[n(2)]
+    Skipped,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+#[cbor(map)]
+pub struct BatchItemResult {
+    #[n(0)]
+    pub status: BatchItemStatus,
+
+    #[n(1)]
+    pub error: Option<ManyError>,
+}
+
+impl BatchItemResult {
+    fn executed() -> Self {
+        Self {
+            status: BatchItemStatus::Executed,
+            error: None,
+        }
+    }
+
+    fn failed(error: ManyError) -> Self {
+        Self {
+            status: BatchItemStatus::Failed,
+            error: Some(error),
+        }
+    }
+
+    fn skipped() -> Self {
+        Self {
+            status: BatchItemStatus::Skipped,
+            error: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+#[cbor(map)]
+pub struct BatchReturns {
+    /// The result of each operation, in the order of the arguments.
+    #[n(0)]
+    pub results: Vec<BatchItemResult>,
+}
+
+pub trait LedgerBatchModuleBackend: Send {
+    fn batch(&mut self, sender: &Address, args: BatchArgs) -> Result<BatchReturns, ManyError>;
+}
+
+impl LedgerBatchModuleBackend for LedgerModuleImpl {
+    fn batch(&mut self, sender: &Address, args: BatchArgs) -> Result<BatchReturns, ManyError> {
+        let BatchArgs { operations, atomic } = args;
+        if operations.is_empty() {
+            return Err(error::batch_empty());
+        }
+        if operations.len() > MAXIMUM_BATCH_COUNT {
+            return Err(error::batch_too_large(
+                operations.len(),
+                MAXIMUM_BATCH_COUNT,
+            ));
+        }
+
+        let sends: Vec<_> = operations
+            .into_iter()
+            .map(|operation| match operation {
+                BatchOperation::Send(ledger::SendArgs {
+                    from,
+                    to,
+                    amount,
+                    symbol,
+                }) => (from.unwrap_or(*sender), to, symbol, amount),
+            })
+            .collect();
+
+        // Sends cannot change accounts, so permissions are checked up front.
+        let mut unauthorized: Vec<Option<ManyError>> = sends
+            .iter()
+            .map(|(from, ..)| self.verify_can_transact(sender, from).err())
+            .collect();
+
+        if atomic {
+            let first_unauthorized = unauthorized
+                .iter()
+                .position(Option::is_some)
+                .unwrap_or(sends.len());
+            let failure = match self.storage.check_sends(
+                sends[..first_unauthorized]
+                    .iter()
+                    .map(|(from, to, symbol, amount)| (from, to, symbol, amount)),
+            ) {
+                Err(failure) => Some(failure),
+                Ok(()) => unauthorized
+                    .get_mut(first_unauthorized)
+                    .and_then(Option::take)
+                    .map(|e| (first_unauthorized, e)),
+            };
+
+            if let Some((index, e)) = failure {
+                let mut results: Vec<_> =
+                    sends.iter().map(|_| BatchItemResult::skipped()).collect();
+                results[index] = BatchItemResult::failed(e);
+                return Ok(BatchReturns { results });
+            }
+        }
+
+        let results = self.storage.with_single_commit(|storage| {
+            sends
+                .into_iter()
+                .zip(unauthorized)
+                .map(|((from, to, symbol, amount), unauthorized)| {
+                    match unauthorized
+                        .map_or_else(|| storage.send(&from, &to, &symbol, amount), Err)
+                    {
+                        Ok(()) => BatchItemResult::executed(),
+                        Err(e) => BatchItemResult::failed(e),
+                    }
+                })
+                .collect()
+        });
+        Ok(BatchReturns { results })
+    }
+}
+
+/// A module executing several ledger operations of a sender in a single
+/// message.
+pub struct LedgerBatchModule<T: LedgerBatchModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    allow_addrs: Option<BTreeSet<Address>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerBatchModuleBackend> LedgerBatchModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            allow_addrs: None,
+            info: ManyModuleInfo {
+                name: "LedgerBatchModule".to_string(),
+                attribute: None,
+                endpoints: vec!["ledger.batch".to_string()],
+            },
+        }
+    }
+
+    /// Only accept batches from these addresses, as `ledger.send` does when
+    /// the server has an allow list.
+    pub fn with_allow_addrs(mut self, allow_addrs: BTreeSet<Address>) -> Self {
+        self.allow_addrs = Some(allow_addrs);
+        self
+    }
+}
+
+impl<T: LedgerBatchModuleBackend> Debug for LedgerBatchModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerBatchModule")
+    }
+}
+
+fn decode_args(data: &[u8]) -> Result<BatchArgs, ManyError> {
+    if data.len() > MAXIMUM_BATCH_SIZE {
+        return Err(error::batch_payload_too_large(
+            data.len(),
+            MAXIMUM_BATCH_SIZE,
+        ));
+    }
+    many_storage::cbor::decode_args(data)
+}
+
+impl<T: LedgerBatchModuleBackend> SenderGate for LedgerBatchModule<T> {
+    fn admits(&self, _method: &str, sender: &Address) -> bool {
+        self.allow_addrs
+            .as_ref()
+            .map_or(true, |allow_addrs| allow_addrs.contains(sender))
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerBatchModuleBackend> ManyModule for LedgerBatchModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.batch" => {
+                let args = decode_args(&message.data)?;
+                if args.operations.len() > MAXIMUM_BATCH_COUNT {
+                    return Err(error::batch_too_large(
+                        args.operations.len(),
+                        MAXIMUM_BATCH_COUNT,
+                    ));
+                }
+                Ok(())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        if !self.admits(&message.method, &from) {
+            return Err(ManyError::invalid_from_identity());
+        }
+
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "ledger.batch" => decode_args(&message.data)
+                    .and_then(|args| backend.batch(&from, args))
+                    .and_then(encode_returns),
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
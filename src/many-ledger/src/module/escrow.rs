@@ -0,0 +1,324 @@
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ledger, EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum EscrowState {
+    #[n(0)]
+    Pending,
+    #[n(1)]
+    Released,
+    #[n(2)]
+    Refunded,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct EscrowInfo {
+    #[n(0)]
+    pub payer: Address,
+
+    #[n(1)]
+    pub payee: Address,
+
+    #[n(2)]
+    pub arbiter: Option<Address>,
+
+    #[n(3)]
+    pub symbol: Symbol,
+
+    #[n(4)]
+    pub amount: TokenAmount,
+
+    #[n(5)]
+    pub timeout: Timestamp,
+
+    #[n(6)]
+    pub state: EscrowState,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct EscrowCreateArgs {
+    /// The payer, if different from the sender (e.g. a ledger account).
+    #[n(0)]
+    pub from: Option<Address>,
+
+    #[n(1)]
+    pub payee: Address,
+
+    #[n(2)]
+    pub symbol: Symbol,
+
+    #[n(3)]
+    pub amount: TokenAmount,
+
+    #[n(4)]
+    pub timeout_in_secs: u64,
+
+    #[n(5)]
+    pub arbiter: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct EscrowCreateReturns {
+    #[n(0)]
+    pub escrow: Address,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct EscrowArgs {
+    #[n(0)]
+    pub escrow: Address,
+}
+
+pub type EscrowReleaseArgs = EscrowArgs;
+pub type EscrowRefundArgs = EscrowArgs;
+pub type EscrowInfoArgs = EscrowArgs;
+pub type EscrowInfoReturns = EscrowInfo;
+
+/// The `ledger.balance` return type, with the amounts locked in pending
+/// escrows and swaps. Clients that do not know about escrows will ignore the field.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct BalanceWithEscrowReturns {
+    #[n(0)]
+    pub balances: BTreeMap<Symbol, TokenAmount>,
+
+    #[n(1)]
+    pub escrowed: BTreeMap<Symbol, TokenAmount>,
+}
+
+pub trait LedgerEscrowModuleBackend: Send {
+    fn escrow_create(
+        &mut self,
+        sender: &Address,
+        args: EscrowCreateArgs,
+    ) -> Result<EscrowCreateReturns, ManyError>;
+    fn escrow_release(
+        &mut self,
+        sender: &Address,
+        args: EscrowReleaseArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn escrow_refund(
+        &mut self,
+        sender: &Address,
+        args: EscrowRefundArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn escrow_info(
+        &self,
+        sender: &Address,
+        args: EscrowInfoArgs,
+    ) -> Result<EscrowInfoReturns, ManyError>;
+    fn escrowed(
+        &self,
+        account: &Address,
+        symbols: BTreeSet<Symbol>,
+    ) -> Result<BTreeMap<Symbol, TokenAmount>, ManyError>;
+}
+
+impl LedgerEscrowModuleBackend for LedgerModuleImpl {
+    fn escrow_create(
+        &mut self,
+        sender: &Address,
+        args: EscrowCreateArgs,
+    ) -> Result<EscrowCreateReturns, ManyError> {
+        let EscrowCreateArgs {
+            from,
+            payee,
+            symbol,
+            amount,
+            timeout_in_secs,
+            arbiter,
+        } = args;
+
+        let from = from.as_ref().unwrap_or(sender);
+        self.verify_can_transact(sender, from)?;
+
+        let escrow =
+            self.storage
+                .create_escrow(from, &payee, arbiter, &symbol, amount, timeout_in_secs)?;
+        Ok(EscrowCreateReturns { escrow })
+    }
+
+    fn escrow_release(
+        &mut self,
+        sender: &Address,
+        args: EscrowReleaseArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        self.storage.release_escrow(sender, &args.escrow)?;
+        Ok(EmptyReturn)
+    }
+
+    fn escrow_refund(
+        &mut self,
+        sender: &Address,
+        args: EscrowRefundArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        self.storage.refund_escrow(sender, &args.escrow)?;
+        Ok(EmptyReturn)
+    }
+
+    fn escrow_info(
+        &self,
+        _sender: &Address,
+        args: EscrowInfoArgs,
+    ) -> Result<EscrowInfoReturns, ManyError> {
+        self.storage.get_escrow(&args.escrow)
+    }
+
+    fn escrowed(
+        &self,
+        account: &Address,
+        symbols: BTreeSet<Symbol>,
+    ) -> Result<BTreeMap<Symbol, TokenAmount>, ManyError> {
+        // Funds locked in pending swaps are reported as escrowed too.
+        let mut escrowed = self.storage.get_escrowed(account);
+        for (symbol, amount) in self.storage.get_swap_locked(account) {
+            *escrowed.entry(symbol).or_default() += amount;
+        }
+        Ok(escrowed
+            .into_iter()
+            .filter(|(k, _)| symbols.is_empty() || symbols.contains(k))
+            .collect())
+    }
+}
+
+const ESCROW_ENDPOINTS: [&str; 4] = [
+    "ledger.escrowCreate",
+    "ledger.escrowRelease",
+    "ledger.escrowRefund",
+    "ledger.escrowInfo",
+];
+
+/// A module for escrow transfers.
+pub struct LedgerEscrowModule<T: LedgerEscrowModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerEscrowModuleBackend> LedgerEscrowModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerEscrowModule".to_string(),
+                attribute: None,
+                endpoints: ESCROW_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerEscrowModuleBackend> Debug for LedgerEscrowModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerEscrowModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerEscrowModuleBackend> ManyModule for LedgerEscrowModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.escrowCreate" => decode_args::<EscrowCreateArgs>(&message.data).map(|_| ()),
+            "ledger.escrowRelease" | "ledger.escrowRefund" | "ledger.escrowInfo" => {
+                decode_args::<EscrowArgs>(&message.data).map(|_| ())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "ledger.escrowCreate" => {
+                    encode_returns(backend.escrow_create(&from, decode_args(&message.data)?)?)
+                }
+                "ledger.escrowRelease" => {
+                    encode_returns(backend.escrow_release(&from, decode_args(&message.data)?)?)
+                }
+                "ledger.escrowRefund" => {
+                    encode_returns(backend.escrow_refund(&from, decode_args(&message.data)?)?)
+                }
+                "ledger.escrowInfo" => {
+                    encode_returns(backend.escrow_info(&from, decode_args(&message.data)?)?)
+                }
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
+
+/// Wraps the ledger module to add the `escrowed` field to `ledger.balance`.
+pub struct EscrowBalanceModule<T: ledger::LedgerModuleBackend + LedgerEscrowModuleBackend> {
+    pub inner: ledger::LedgerModule<T>,
+    pub backend: Arc<Mutex<T>>,
+}
+
+impl<T: ledger::LedgerModuleBackend + LedgerEscrowModuleBackend> Debug for EscrowBalanceModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EscrowBalanceModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ledger::LedgerModuleBackend + LedgerEscrowModuleBackend> ManyModule
+    for EscrowBalanceModule<T>
+{
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        if message.method != "ledger.balance" {
+            return self.inner.execute(message).await;
+        }
+
+        let ledger::BalanceArgs { account, symbols } = decode_args(&message.data)?;
+        let account = account.unwrap_or_else(|| message.from());
+        let mut response = self.inner.execute(message).await?;
+
+        if let Ok(data) = &response.data {
+            let ledger::BalanceReturns { balances } = decode_args(data)?;
+            let escrowed = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?
+                .escrowed(
+                    &account,
+                    symbols.unwrap_or_default().0.into_iter().collect(),
+                )?;
+            response.data = encode_returns(BalanceWithEscrowReturns { balances, escrowed });
+        }
+
+        Ok(response)
+    }
+}
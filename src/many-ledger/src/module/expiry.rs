@@ -0,0 +1,292 @@
+use crate::error;
+use crate::module::governance::Param;
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::attributes::Attribute;
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// The expiry window of a method in seconds, the override of the method if
+/// any, or `None` if any timestamp is accepted.
+pub fn window(default: u64, overrides: &BTreeMap<String, u64>, method: &str) -> Option<u64> {
+    let window = overrides.get(method).copied().unwrap_or(default);
+    (window != 0).then_some(window)
+}
+
+fn secs(timestamp: Timestamp) -> Result<i128, ManyError> {
+    Ok(timestamp
+        .as_system_time()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| ManyError::unknown(e.to_string()))?
+        .as_secs() as i128)
+}
+
+/// Check that the timestamp of a message is within the window of its method
+/// around the time of execution, both bounds included. A message without a
+/// timestamp is only accepted if the method has no window. A message outside
+/// of the window is rejected with the server time and the bounds of the
+/// window, so the client can tell its clock is off.
+pub fn check(
+    method: &str,
+    timestamp: Option<Timestamp>,
+    now: Timestamp,
+    window: Option<u64>,
+) -> Result<(), ManyError> {
+    let window = match window {
+        Some(window) => window,
+        None => return Ok(()),
+    };
+    let timestamp = secs(
+        timestamp.ok_or_else(|| error::message_expired(method.to_string(), window.to_string()))?,
+    )?;
+    let now = secs(now)?;
+    let (not_before, not_after) = (now - window as i128, now + window as i128);
+    if timestamp < not_before || timestamp > not_after {
+        return Err(error::message_stale(
+            method.to_string(),
+            timestamp,
+            now,
+            not_before,
+            not_after,
+        ));
+    }
+    Ok(())
+}
+
+pub trait ExpiryModuleBackend: Send {
+    /// The default expiry window in seconds, 0 for none, and the windows of
+    /// specific methods.
+    fn expiry_windows(&self) -> Result<(u64, BTreeMap<String, u64>), ManyError>;
+
+    /// The expiry window of a method in seconds, if any. All nodes must
+    /// agree on it.
+    fn expiry_window(&self, method: &str) -> Result<Option<u64>, ManyError> {
+        let (default, overrides) = self.expiry_windows()?;
+        Ok(window(default, &overrides, method))
+    }
+
+    /// The time of the block being executed, or the wall clock when the
+    /// server is not behind the ABCI.
+    fn block_time(&self) -> Timestamp;
+}
+
+impl ExpiryModuleBackend for LedgerModuleImpl {
+    fn expiry_windows(&self) -> Result<(u64, BTreeMap<String, u64>), ManyError> {
+        Ok((
+            self.storage.param_integer(Param::TxExpiryInSecs)?,
+            self.storage.param_integer_map(Param::TxExpiryOverrides)?,
+        ))
+    }
+
+    fn block_time(&self) -> Timestamp {
+        self.storage.now()
+    }
+}
+
+/// Reject the commands of a module whose timestamp is too far from the block
+/// time, per the `tx.expiryInSecs` and `tx.expiryOverrides` parameters, so
+/// an envelope signed long ago cannot be executed. This is checked when
+/// executing the command, against the block time, so every node decides the
+/// same.
+pub struct ExpiryModule<M: ManyModule, T: ExpiryModuleBackend> {
+    inner: M,
+    backend: Arc<Mutex<T>>,
+    commands: BTreeSet<String>,
+}
+
+impl<M: ManyModule, T: ExpiryModuleBackend + ManyAbciModuleBackend> ExpiryModule<M, T> {
+    /// The commands are the endpoints of the module which the ABCI frontend
+    /// delivers as transactions. Queries are not checked.
+    pub fn new(inner: M, backend: Arc<Mutex<T>>) -> Self {
+        let endpoints = backend
+            .lock()
+            .unwrap()
+            .init()
+            .expect("Could not list the endpoints.")
+            .endpoints;
+        let commands = inner
+            .info()
+            .endpoints
+            .iter()
+            .filter(|e| endpoints.get(*e).map_or(false, |info| info.is_command))
+            .cloned()
+            .collect();
+        Self {
+            inner,
+            backend,
+            commands,
+        }
+    }
+}
+
+impl<M: ManyModule, T: ExpiryModuleBackend> Debug for ExpiryModule<M, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ExpiryModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule, T: ExpiryModuleBackend> ManyModule for ExpiryModule<M, T> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        if self.commands.contains(&message.method) {
+            let (window, now) = {
+                let backend = self
+                    .backend
+                    .lock()
+                    .map_err(|e| ManyError::unknown(e.to_string()))?;
+                (
+                    backend.expiry_window(&message.method)?,
+                    backend.block_time(),
+                )
+            };
+            if let Err(e) = check(&message.method, message.timestamp, now, window) {
+                return Ok(ResponseMessage::from_request(&message, &message.to, Err(e)));
+            }
+        }
+        self.inner.execute(message).await
+    }
+}
+
+/// The attribute of the status of the server when it answers
+/// `ledger.replayProtection`.
+pub const REPLAY_PROTECTION: Attribute = Attribute::id(2002);
+
+/// The time the timestamps of messages are checked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum Clock {
+    /// The time of the block executing the message, when behind the ABCI.
+    #[n(0)]
+    Block,
+
+    /// The wall clock of the server, when it executes messages directly.
+    #[n(1)]
+    Wall,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReplayProtectionArgs {}
+
+/// How the server protects itself against messages replayed.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReplayProtection {
+    #[n(0)]
+    pub clock: Clock,
+
+    /// The window around the clock in seconds, 0 if any timestamp is
+    /// accepted.
+    #[n(1)]
+    pub window: u64,
+
+    /// The windows of specific methods, overriding `window`.
+    #[n(2)]
+    pub overrides: BTreeMap<String, u64>,
+
+    /// Whether messages must have a nonce.
+    #[n(3)]
+    pub nonce_required: bool,
+
+    /// How long the nonces of messages are remembered to reject a second
+    /// message with the same one, in seconds. 0 if they are not.
+    #[n(4)]
+    pub nonce_retention: u64,
+}
+
+const REPLAY_PROTECTION_ENDPOINTS: [&str; 1] = ["ledger.replayProtection"];
+
+/// A module returning the replay protection of the server, so clients do not
+/// have to guess which timestamps it accepts. The windows are governance
+/// parameters, so they are read when queried. The ledger does not require
+/// nonces, nor remember them.
+pub struct ReplayProtectionModule<T: ExpiryModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    clock: Clock,
+    info: ManyModuleInfo,
+}
+
+impl<T: ExpiryModuleBackend> ReplayProtectionModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>, clock: Clock) -> Self {
+        Self {
+            backend,
+            clock,
+            info: ManyModuleInfo {
+                name: "ReplayProtectionModule".to_string(),
+                attribute: Some(REPLAY_PROTECTION),
+                endpoints: REPLAY_PROTECTION_ENDPOINTS
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect(),
+            },
+        }
+    }
+
+    pub fn replay_protection(
+        &self,
+        _args: ReplayProtectionArgs,
+    ) -> Result<ReplayProtection, ManyError> {
+        let (window, overrides) = self
+            .backend
+            .lock()
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .expiry_windows()?;
+        Ok(ReplayProtection {
+            clock: self.clock,
+            window,
+            overrides,
+            nonce_required: false,
+            nonce_retention: 0,
+        })
+    }
+}
+
+impl<T: ExpiryModuleBackend> Debug for ReplayProtectionModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReplayProtectionModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ExpiryModuleBackend> ManyModule for ReplayProtectionModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.replayProtection" => {
+                decode_args::<ReplayProtectionArgs>(&message.data).map(|_| ())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let data = match message.method.as_str() {
+            "ledger.replayProtection" => decode_args(&message.data)
+                .and_then(|args| self.replay_protection(args))
+                .and_then(encode_returns),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
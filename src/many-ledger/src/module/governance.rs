@@ -0,0 +1,464 @@
+use crate::error;
+use crate::module::LedgerModuleImpl;
+use crate::storage::{
+    MINIMUM_EVENT_RETENTION_BLOCKS, MULTISIG_DEFAULT_EXECUTE_AUTOMATICALLY,
+    MULTISIG_DEFAULT_TIMEOUT_IN_SECS, MULTISIG_MAXIMUM_TIMEOUT_IN_SECS,
+    VALIDATOR_DEFAULT_QUORUM_MARGIN,
+};
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::ledger::TokenAmount;
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of parameter changes returned by a single query.
+pub const MAXIMUM_CHANGE_COUNT: usize = 100;
+
+/// The type of the value of a parameter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum ParamType {
+    #[n(0)]
+    Bool,
+    #[n(1)]
+    Integer,
+    #[n(2)]
+    Amount,
+    #[n(3)]
+    Address,
+    #[n(4)]
+    IntegerMap,
+    #[n(5)]
+    AddressSet,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum ParamValue {
+    #[n(0)]
+    Bool(#[n(0)] bool),
+    #[n(1)]
+    Integer(#[n(0)] u64),
+    #[n(2)]
+    Amount(#[n(0)] TokenAmount),
+    #[n(3)]
+    Address(#[n(0)] Address),
+    #[n(4)]
+    IntegerMap(#[n(0)] BTreeMap<String, u64>),
+    #[n(5)]
+    AddressSet(#[n(0)] BTreeSet<Address>),
+}
+
+impl ParamValue {
+    pub fn param_type(&self) -> ParamType {
+        match self {
+            ParamValue::Bool(_) => ParamType::Bool,
+            ParamValue::Integer(_) => ParamType::Integer,
+            ParamValue::Amount(_) => ParamType::Amount,
+            ParamValue::Address(_) => ParamType::Address,
+            ParamValue::IntegerMap(_) => ParamType::IntegerMap,
+            ParamValue::AddressSet(_) => ParamType::AddressSet,
+        }
+    }
+}
+
+/// A consensus setting which can be changed at runtime by the governance
+/// address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Param {
+    /// The address allowed to change parameters. If it is an account, any of
+    /// its owners can.
+    Governance,
+
+    /// A flat fee charged to the sender of a send, on top of the amount and
+    /// in the same symbol.
+    SendFee,
+
+    /// The address receiving send fees.
+    SendFeeCollector,
+
+    /// Whether a send to its source is accepted, as a send event which does
+    /// not change balances, e.g. for chains using them as memos. Otherwise
+    /// it is rejected.
+    SendAllowSelf,
+
+    /// Number of blocks events are kept for, or 0 to keep them forever.
+    EventRetentionBlocks,
+
+    MultisigDefaultTimeoutInSecs,
+    MultisigDefaultExecuteAutomatically,
+
+    /// Whether commands whose arguments have unknown fields are rejected,
+    /// instead of ignoring these fields.
+    StrictArgs,
+
+    /// Number of seconds the timestamp of a command can be from the block
+    /// time, before or after, or 0 to accept any timestamp.
+    TxExpiryInSecs,
+
+    /// The expiry window of specific methods, overriding `TxExpiryInSecs`,
+    /// e.g. a longer one for multisig approvals signed offline. 0 accepts any
+    /// timestamp.
+    TxExpiryOverrides,
+
+    /// The addresses allowed to update the validators with
+    /// `blockchain.updateValidator`, which many-abci executes. It reads them
+    /// from here, so every node checks the same ones. Empty refuses every
+    /// update.
+    ValidatorAdmins,
+
+    /// Minimum share of the total voting power, in percent, which must remain
+    /// after the validator updates of a block.
+    ValidatorQuorumMargin,
+}
+
+impl Param {
+    pub const ALL: [Param; 12] = [
+        Param::Governance,
+        Param::SendFee,
+        Param::SendFeeCollector,
+        Param::SendAllowSelf,
+        Param::EventRetentionBlocks,
+        Param::MultisigDefaultTimeoutInSecs,
+        Param::MultisigDefaultExecuteAutomatically,
+        Param::StrictArgs,
+        Param::TxExpiryInSecs,
+        Param::TxExpiryOverrides,
+        Param::ValidatorAdmins,
+        Param::ValidatorQuorumMargin,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Param::Governance => "governance",
+            Param::SendFee => "send.fee",
+            Param::SendFeeCollector => "send.feeCollector",
+            Param::SendAllowSelf => "send.allowSelf",
+            Param::EventRetentionBlocks => "events.retentionBlocks",
+            Param::MultisigDefaultTimeoutInSecs => "multisig.defaultTimeoutInSecs",
+            Param::MultisigDefaultExecuteAutomatically => "multisig.defaultExecuteAutomatically",
+            Param::StrictArgs => "args.strict",
+            Param::TxExpiryInSecs => "tx.expiryInSecs",
+            Param::TxExpiryOverrides => "tx.expiryOverrides",
+            Param::ValidatorAdmins => "validators.admins",
+            Param::ValidatorQuorumMargin => "validators.quorumMargin",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, ManyError> {
+        Self::ALL
+            .into_iter()
+            .find(|p| p.name() == name)
+            .ok_or_else(|| error::unknown_parameter(name))
+    }
+
+    pub fn param_type(&self) -> ParamType {
+        match self {
+            Param::Governance | Param::SendFeeCollector => ParamType::Address,
+            Param::SendFee => ParamType::Amount,
+            Param::EventRetentionBlocks
+            | Param::MultisigDefaultTimeoutInSecs
+            | Param::TxExpiryInSecs
+            | Param::ValidatorQuorumMargin => ParamType::Integer,
+            Param::SendAllowSelf
+            | Param::MultisigDefaultExecuteAutomatically
+            | Param::StrictArgs => ParamType::Bool,
+            Param::TxExpiryOverrides => ParamType::IntegerMap,
+            Param::ValidatorAdmins => ParamType::AddressSet,
+        }
+    }
+
+    /// The value of the parameter until it is set. Addresses default to the
+    /// ledger identity.
+    pub fn default_value(&self, ledger: &Address) -> ParamValue {
+        match self {
+            Param::Governance | Param::SendFeeCollector => ParamValue::Address(*ledger),
+            Param::SendFee => ParamValue::Amount(TokenAmount::zero()),
+            Param::SendAllowSelf | Param::StrictArgs => ParamValue::Bool(false),
+            Param::EventRetentionBlocks | Param::TxExpiryInSecs => ParamValue::Integer(0),
+            Param::MultisigDefaultTimeoutInSecs => {
+                ParamValue::Integer(MULTISIG_DEFAULT_TIMEOUT_IN_SECS)
+            }
+            Param::MultisigDefaultExecuteAutomatically => {
+                ParamValue::Bool(MULTISIG_DEFAULT_EXECUTE_AUTOMATICALLY)
+            }
+            Param::TxExpiryOverrides => ParamValue::IntegerMap(BTreeMap::new()),
+            Param::ValidatorAdmins => ParamValue::AddressSet(BTreeSet::new()),
+            Param::ValidatorQuorumMargin => ParamValue::Integer(VALIDATOR_DEFAULT_QUORUM_MARGIN),
+        }
+    }
+
+    /// Check the type and range of a value. This does not check that an
+    /// account exists, which the storage does.
+    pub fn validate(&self, value: &ParamValue) -> Result<(), ManyError> {
+        if value.param_type() != self.param_type() {
+            return Err(error::invalid_parameter_value(
+                self.name(),
+                format!("expected a value of type {:?}", self.param_type()),
+            ));
+        }
+
+        match (self, value) {
+            (Param::Governance | Param::SendFeeCollector, ParamValue::Address(a))
+                if a.is_anonymous() =>
+            {
+                Err(error::invalid_parameter_value(
+                    self.name(),
+                    "the address cannot be anonymous",
+                ))
+            }
+            (Param::EventRetentionBlocks, ParamValue::Integer(n))
+                if *n != 0 && *n < MINIMUM_EVENT_RETENTION_BLOCKS =>
+            {
+                Err(error::invalid_parameter_value(
+                    self.name(),
+                    format!("must be 0 or at least {MINIMUM_EVENT_RETENTION_BLOCKS}"),
+                ))
+            }
+            (Param::MultisigDefaultTimeoutInSecs, ParamValue::Integer(n))
+                if *n == 0 || *n > MULTISIG_MAXIMUM_TIMEOUT_IN_SECS =>
+            {
+                Err(error::invalid_parameter_value(
+                    self.name(),
+                    format!("must be between 1 and {MULTISIG_MAXIMUM_TIMEOUT_IN_SECS}"),
+                ))
+            }
+            (Param::TxExpiryOverrides, ParamValue::IntegerMap(overrides))
+                if overrides.keys().any(|method| method.is_empty()) =>
+            {
+                Err(error::invalid_parameter_value(
+                    self.name(),
+                    "method names cannot be empty",
+                ))
+            }
+            (Param::ValidatorAdmins, ParamValue::AddressSet(admins))
+                if admins.iter().any(Address::is_anonymous) =>
+            {
+                Err(error::invalid_parameter_value(
+                    self.name(),
+                    "the addresses cannot be anonymous",
+                ))
+            }
+            (Param::ValidatorQuorumMargin, ParamValue::Integer(n)) if *n > 100 => Err(
+                error::invalid_parameter_value(self.name(), "must be at most 100"),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read a value from the initial state JSON.
+    pub fn value_from_json(&self, value: &serde_json::Value) -> Result<ParamValue, ManyError> {
+        let invalid = |e: String| error::invalid_parameter_value(self.name(), e);
+        match self.param_type() {
+            ParamType::Bool => value
+                .as_bool()
+                .map(ParamValue::Bool)
+                .ok_or_else(|| invalid("expected a boolean".to_string())),
+            ParamType::Integer => value
+                .as_u64()
+                .map(ParamValue::Integer)
+                .ok_or_else(|| invalid("expected an unsigned integer".to_string())),
+            ParamType::Amount => serde_json::from_value(value.clone())
+                .map(ParamValue::Amount)
+                .map_err(|e| invalid(e.to_string())),
+            ParamType::Address => serde_json::from_value(value.clone())
+                .map(ParamValue::Address)
+                .map_err(|e| invalid(e.to_string())),
+            ParamType::IntegerMap => serde_json::from_value(value.clone())
+                .map(ParamValue::IntegerMap)
+                .map_err(|e| invalid(e.to_string())),
+            ParamType::AddressSet => serde_json::from_value(value.clone())
+                .map(ParamValue::AddressSet)
+                .map_err(|e| invalid(e.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ParamInfo {
+    #[n(0)]
+    pub param_type: ParamType,
+
+    #[n(1)]
+    pub value: ParamValue,
+
+    /// Whether the parameter was never set.
+    #[n(2)]
+    pub is_default: bool,
+}
+
+/// A change of a parameter, kept in the consensus state.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ParamChange {
+    #[n(0)]
+    pub name: String,
+
+    #[n(1)]
+    pub old_value: ParamValue,
+
+    #[n(2)]
+    pub new_value: ParamValue,
+
+    #[n(3)]
+    pub changed_by: Address,
+
+    #[n(4)]
+    pub time: Timestamp,
+
+    #[n(5)]
+    pub height: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ParamsArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ParamsReturns {
+    #[n(0)]
+    pub params: BTreeMap<String, ParamInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetParamArgs {
+    #[n(0)]
+    pub name: String,
+
+    #[n(1)]
+    pub value: ParamValue,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ChangesArgs {
+    /// Only return changes of this parameter.
+    #[n(0)]
+    pub name: Option<String>,
+
+    #[n(1)]
+    pub count: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ChangesReturns {
+    /// The changes, latest first.
+    #[n(0)]
+    pub changes: Vec<ParamChange>,
+}
+
+pub trait LedgerGovernanceModuleBackend: Send {
+    fn params(&self, sender: &Address, args: ParamsArgs) -> Result<ParamsReturns, ManyError>;
+    fn set_param(&mut self, sender: &Address, args: SetParamArgs)
+        -> Result<EmptyReturn, ManyError>;
+    fn changes(&self, sender: &Address, args: ChangesArgs) -> Result<ChangesReturns, ManyError>;
+}
+
+impl LedgerGovernanceModuleBackend for LedgerModuleImpl {
+    fn params(&self, _sender: &Address, _args: ParamsArgs) -> Result<ParamsReturns, ManyError> {
+        Ok(ParamsReturns {
+            params: self.storage.get_params()?,
+        })
+    }
+
+    fn set_param(
+        &mut self,
+        sender: &Address,
+        args: SetParamArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let param = Param::from_name(&args.name)?;
+        self.storage.set_param(sender, param, args.value)?;
+        Ok(EmptyReturn)
+    }
+
+    fn changes(&self, _sender: &Address, args: ChangesArgs) -> Result<ChangesReturns, ManyError> {
+        let param = args.name.as_deref().map(Param::from_name).transpose()?;
+        let count = args.count.map_or(MAXIMUM_CHANGE_COUNT, |c| {
+            std::cmp::min(c as usize, MAXIMUM_CHANGE_COUNT)
+        });
+        Ok(ChangesReturns {
+            changes: self.storage.get_param_changes(param, count)?,
+        })
+    }
+}
+
+const GOVERNANCE_ENDPOINTS: [&str; 3] = [
+    "governance.params",
+    "governance.setParam",
+    "governance.changes",
+];
+
+/// A module for the runtime-adjustable consensus parameters.
+pub struct LedgerGovernanceModule<T: LedgerGovernanceModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerGovernanceModuleBackend> LedgerGovernanceModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerGovernanceModule".to_string(),
+                attribute: None,
+                endpoints: GOVERNANCE_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerGovernanceModuleBackend> Debug for LedgerGovernanceModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerGovernanceModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerGovernanceModuleBackend> ManyModule for LedgerGovernanceModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "governance.params" => decode_args::<ParamsArgs>(&message.data).map(|_| ()),
+            "governance.setParam" => decode_args::<SetParamArgs>(&message.data).map(|_| ()),
+            "governance.changes" => decode_args::<ChangesArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "governance.params" => {
+                    encode_returns(backend.params(&from, decode_args(&message.data)?)?)
+                }
+                "governance.setParam" => {
+                    encode_returns(backend.set_param(&from, decode_args(&message.data)?)?)
+                }
+                "governance.changes" => {
+                    encode_returns(backend.changes(&from, decode_args(&message.data)?)?)
+                }
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
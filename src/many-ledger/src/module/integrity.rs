@@ -0,0 +1,194 @@
+use crate::module::LedgerModuleImpl;
+use crate::storage::{
+    ACCOUNTS_ROOT, BALANCES_ROOT, EVENTS_ROOT, IDSTORE_ROOT, MULTISIG_TRANSACTIONS_ROOT,
+};
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::integrity::{
+    Corruption, IntegrityBackend, IntegrityMetrics, PassReport, TreeCheck,
+};
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// The parts of the ledger, by the prefix of their keys.
+const SECTIONS: &[(&[u8], &str)] = &[
+    (BALANCES_ROOT, "balances"),
+    (ACCOUNTS_ROOT, "accounts"),
+    (MULTISIG_TRANSACTIONS_ROOT, "multisig"),
+    (EVENTS_ROOT, "events"),
+    (IDSTORE_ROOT, "idstore"),
+];
+
+/// The part of the ledger a key belongs to.
+pub fn section(key: &[u8]) -> &'static str {
+    SECTIONS
+        .iter()
+        .find(|(prefix, _)| key.starts_with(prefix))
+        .map_or("other", |(_, name)| name)
+}
+
+impl IntegrityBackend for LedgerModuleImpl {
+    fn check_tree(&self, after: Option<&[u8]>, limit: usize) -> Result<TreeCheck, ManyError> {
+        self.storage.check_tree(after, limit)
+    }
+}
+
+/// A corruption of the merk tree found by the background checks.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct Finding {
+    #[n(0)]
+    pub kind: String,
+
+    /// The part of the ledger of the first key.
+    #[n(1)]
+    pub section: String,
+
+    /// The first and last keys of the nodes involved.
+    #[n(2)]
+    pub first: ByteVec,
+
+    #[n(3)]
+    pub last: ByteVec,
+}
+
+impl From<&Corruption> for Finding {
+    fn from(corruption: &Corruption) -> Self {
+        Self {
+            kind: corruption.kind.as_str().to_string(),
+            section: section(&corruption.first).to_string(),
+            first: ByteVec::from(corruption.first.clone()),
+            last: ByteVec::from(corruption.last.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LastPass {
+    #[n(0)]
+    pub completed_at: Timestamp,
+
+    #[n(1)]
+    pub duration_ms: u64,
+
+    #[n(2)]
+    pub nodes_checked: u64,
+
+    #[n(3)]
+    pub findings: Vec<Finding>,
+}
+
+impl TryFrom<PassReport> for LastPass {
+    type Error = ManyError;
+
+    fn try_from(report: PassReport) -> Result<Self, ManyError> {
+        Ok(Self {
+            completed_at: Timestamp::from_system_time(report.completed_at)?,
+            duration_ms: report.duration.as_millis() as u64,
+            nodes_checked: report.nodes_checked,
+            findings: report.corruptions.iter().map(Finding::from).collect(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct IntegrityArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct IntegrityReturns {
+    /// The number of passes over the whole tree completed since the start.
+    #[n(0)]
+    pub passes: u64,
+
+    /// The number of nodes checked since the start.
+    #[n(1)]
+    pub nodes_checked: u64,
+
+    /// The number of corruptions found since the start.
+    #[n(2)]
+    pub corruptions: u64,
+
+    #[n(3)]
+    pub last_pass: Option<LastPass>,
+}
+
+/// A module returning the state of the background checks of the merk tree
+/// to the identity of the server.
+pub struct IntegrityModule {
+    metrics: Arc<IntegrityMetrics>,
+    server: Address,
+    info: ManyModuleInfo,
+}
+
+impl IntegrityModule {
+    pub fn new(metrics: Arc<IntegrityMetrics>, server: Address) -> Self {
+        Self {
+            metrics,
+            server,
+            info: ManyModuleInfo {
+                name: "IntegrityModule".to_string(),
+                attribute: None,
+                endpoints: vec!["server.integrity".to_string()],
+            },
+        }
+    }
+
+    fn returns(&self) -> Result<IntegrityReturns, ManyError> {
+        Ok(IntegrityReturns {
+            passes: self.metrics.passes.load(Ordering::Relaxed),
+            nodes_checked: self.metrics.nodes_checked.load(Ordering::Relaxed),
+            corruptions: self.metrics.corruptions.load(Ordering::Relaxed),
+            last_pass: self
+                .metrics
+                .last_pass()
+                .map(TryInto::try_into)
+                .transpose()?,
+        })
+    }
+}
+
+impl Debug for IntegrityModule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IntegrityModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl ManyModule for IntegrityModule {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "server.integrity" => minicbor::decode::<IntegrityArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let data = match message.method.as_str() {
+            "server.integrity" if message.from() != self.server => {
+                Err(ManyError::invalid_from_identity())
+            }
+            "server.integrity" => self.returns().and_then(|r| {
+                minicbor::to_vec(r).map_err(|e| ManyError::serialization_error(e.to_string()))
+            }),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,151 @@
+use crate::error;
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events::EventId;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Default number of events returned by a search.
+pub const DEFAULT_MEMO_SEARCH_COUNT: u64 = 20;
+
+/// Maximum number of events returned by a search.
+pub const MAX_MEMO_SEARCH_COUNT: u64 = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SearchMemoArgs {
+    #[n(0)]
+    pub query: String,
+
+    #[n(1)]
+    pub count: Option<u64>,
+
+    /// Only return events older than this one, i.e. the last event of the
+    /// previous page.
+    #[n(2)]
+    pub before: Option<EventId>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SearchMemoReturns {
+    /// The matching events, most recent first.
+    #[n(0)]
+    pub events: Vec<EventId>,
+
+    #[n(1)]
+    pub more: bool,
+
+    /// The oldest event in the index of this node. Older events are not
+    /// searched.
+    #[n(2)]
+    pub indexed_since: Option<EventId>,
+}
+
+pub trait LedgerMemoSearchModuleBackend: Send {
+    fn search_memo(
+        &self,
+        sender: &Address,
+        args: SearchMemoArgs,
+    ) -> Result<SearchMemoReturns, ManyError>;
+}
+
+impl LedgerMemoSearchModuleBackend for LedgerModuleImpl {
+    fn search_memo(
+        &self,
+        _sender: &Address,
+        args: SearchMemoArgs,
+    ) -> Result<SearchMemoReturns, ManyError> {
+        let index = self
+            .storage
+            .memo_index()
+            .ok_or_else(error::memo_index_disabled)?;
+        let count = args
+            .count
+            .unwrap_or(DEFAULT_MEMO_SEARCH_COUNT)
+            .min(MAX_MEMO_SEARCH_COUNT);
+
+        let (events, more) = index.search(&args.query, args.before, count as usize);
+        Ok(SearchMemoReturns {
+            events,
+            more,
+            indexed_since: index.oldest(),
+        })
+    }
+}
+
+impl LedgerModuleImpl {
+    /// Enable the memo index, building it from the existing event log.
+    pub fn with_memo_index(mut self, max_entries: usize) -> Result<Self, ManyError> {
+        self.storage = self.storage.with_memo_index(max_entries)?;
+        Ok(self)
+    }
+}
+
+/// A module searching the memos of events. The index is local to the node
+/// and is not part of the consensus state, so two nodes can return different
+/// results, e.g. if their index bounds differ.
+pub struct LedgerMemoSearchModule<T: LedgerMemoSearchModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerMemoSearchModuleBackend> LedgerMemoSearchModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerMemoSearchModule".to_string(),
+                attribute: None,
+                endpoints: vec!["events.searchMemo".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: LedgerMemoSearchModuleBackend> Debug for LedgerMemoSearchModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerMemoSearchModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerMemoSearchModuleBackend> ManyModule for LedgerMemoSearchModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "events.searchMemo" => minicbor::decode::<SearchMemoArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = match message.method.as_str() {
+            "events.searchMemo" => {
+                let args: SearchMemoArgs = minicbor::decode(&message.data)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+                let backend = self
+                    .backend
+                    .lock()
+                    .map_err(|e| ManyError::unknown(e.to_string()))?;
+                backend.search_memo(&from, args).and_then(|r| {
+                    minicbor::to_vec(r).map_err(|e| ManyError::serialization_error(e.to_string()))
+                })
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,349 @@
+use crate::error;
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::ledger::Symbol;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use unicode_normalization::UnicodeNormalization;
+
+/// A class of characters allowed in symbol names.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Encode, Decode, serde::Deserialize,
+)]
+#[cbor(index_only)]
+#[serde(rename_all = "snake_case")]
+pub enum CharacterClass {
+    #[n(0)]
+    AsciiUppercase,
+    #[n(1)]
+    AsciiLowercase,
+    #[n(2)]
+    AsciiDigit,
+    #[n(3)]
+    Dash,
+    #[n(4)]
+    Underscore,
+    #[n(5)]
+    Dot,
+
+    /// Any Unicode alphabetic character.
+    #[n(6)]
+    Alphabetic,
+
+    /// Any Unicode numeric character.
+    #[n(7)]
+    Numeric,
+}
+
+impl CharacterClass {
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            CharacterClass::AsciiUppercase => c.is_ascii_uppercase(),
+            CharacterClass::AsciiLowercase => c.is_ascii_lowercase(),
+            CharacterClass::AsciiDigit => c.is_ascii_digit(),
+            CharacterClass::Dash => c == '-',
+            CharacterClass::Underscore => c == '_',
+            CharacterClass::Dot => c == '.',
+            CharacterClass::Alphabetic => c.is_alphabetic(),
+            CharacterClass::Numeric => c.is_numeric(),
+        }
+    }
+}
+
+/// The rules symbol names must follow. The policy applies to names given
+/// after it is set; names of existing symbols are grandfathered.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, serde::Deserialize)]
+#[cbor(map)]
+#[serde(default)]
+pub struct SymbolNamePolicy {
+    /// Names cannot start with any of these prefixes.
+    #[n(0)]
+    pub reserved_prefixes: Vec<String>,
+
+    #[n(1)]
+    pub denied_names: Vec<String>,
+
+    /// The classes of characters names are made of. Any character is allowed
+    /// if this is empty.
+    #[n(2)]
+    pub allowed_characters: BTreeSet<CharacterClass>,
+
+    /// Bounds of the length of names, in characters.
+    #[n(3)]
+    pub min_length: u64,
+    #[n(4)]
+    pub max_length: u64,
+}
+
+impl Default for SymbolNamePolicy {
+    fn default() -> Self {
+        Self {
+            reserved_prefixes: vec![],
+            denied_names: vec![],
+            allowed_characters: BTreeSet::new(),
+            min_length: 1,
+            max_length: 32,
+        }
+    }
+}
+
+/// Returns the form of a name used to compare names with each other. Names
+/// are NFKC normalized, so compatibility characters (e.g. fullwidth letters
+/// or ligatures) compare equal to their plain form, then lowercased.
+///
+/// This does not catch confusable characters of different scripts; restrict
+/// the allowed character classes to prevent those.
+pub fn comparison_key(name: &str) -> String {
+    name.nfkc().collect::<String>().to_lowercase()
+}
+
+impl SymbolNamePolicy {
+    pub fn validate(&self) -> Result<(), ManyError> {
+        if self.min_length == 0 || self.min_length > self.max_length {
+            return Err(error::invalid_symbol_name_policy(format!(
+                "length bounds {}..={}",
+                self.min_length, self.max_length
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check a name against the policy, and against the names already taken.
+    pub fn check<'a>(
+        &self,
+        name: &str,
+        taken: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), ManyError> {
+        let length = name.chars().count() as u64;
+        if length < self.min_length || length > self.max_length {
+            return Err(error::symbol_name_length(
+                name,
+                self.min_length,
+                self.max_length,
+            ));
+        }
+
+        if !self.allowed_characters.is_empty() {
+            if let Some(c) = name.chars().find(|c| {
+                !self
+                    .allowed_characters
+                    .iter()
+                    .any(|class| class.contains(*c))
+            }) {
+                return Err(error::symbol_name_character(name, c.escape_unicode()));
+            }
+        }
+
+        let key = comparison_key(name);
+        if let Some(prefix) = self
+            .reserved_prefixes
+            .iter()
+            .find(|p| key.starts_with(&comparison_key(p)))
+        {
+            return Err(error::symbol_name_reserved(name, prefix));
+        }
+        if self.denied_names.iter().any(|n| comparison_key(n) == key) {
+            return Err(error::symbol_name_denied(name));
+        }
+        if let Some(other) = taken.into_iter().find(|n| comparison_key(n) == key) {
+            return Err(error::symbol_name_taken(name, other));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct NamePolicyArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct NamePolicyReturns {
+    #[n(0)]
+    pub policy: SymbolNamePolicy,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetNamePolicyArgs {
+    #[n(0)]
+    pub policy: SymbolNamePolicy,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct CheckSymbolNameArgs {
+    #[n(0)]
+    pub name: String,
+
+    /// The symbol being renamed, if any.
+    #[n(1)]
+    pub symbol: Option<Symbol>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct NamesDigestArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct NamesDigestReturns {
+    /// The SHA3-256 of the local names of the symbols, as returned by
+    /// `ledger.info`.
+    #[n(0)]
+    pub digest: ByteVec,
+}
+
+pub trait LedgerNamePolicyModuleBackend: Send {
+    fn name_policy(
+        &self,
+        sender: &Address,
+        args: NamePolicyArgs,
+    ) -> Result<NamePolicyReturns, ManyError>;
+    fn set_name_policy(
+        &mut self,
+        sender: &Address,
+        args: SetNamePolicyArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn check_symbol_name(
+        &self,
+        sender: &Address,
+        args: CheckSymbolNameArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn names_digest(
+        &self,
+        sender: &Address,
+        args: NamesDigestArgs,
+    ) -> Result<NamesDigestReturns, ManyError>;
+}
+
+impl LedgerNamePolicyModuleBackend for LedgerModuleImpl {
+    fn name_policy(
+        &self,
+        _sender: &Address,
+        _args: NamePolicyArgs,
+    ) -> Result<NamePolicyReturns, ManyError> {
+        Ok(NamePolicyReturns {
+            policy: self.storage.get_symbol_name_policy()?,
+        })
+    }
+
+    fn set_name_policy(
+        &mut self,
+        sender: &Address,
+        args: SetNamePolicyArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        self.storage.set_symbol_name_policy(sender, args.policy)?;
+        Ok(EmptyReturn)
+    }
+
+    fn check_symbol_name(
+        &self,
+        _sender: &Address,
+        args: CheckSymbolNameArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        self.storage
+            .check_symbol_name(args.symbol.as_ref(), &args.name)?;
+        Ok(EmptyReturn)
+    }
+
+    fn names_digest(
+        &self,
+        _sender: &Address,
+        _args: NamesDigestArgs,
+    ) -> Result<NamesDigestReturns, ManyError> {
+        Ok(NamesDigestReturns {
+            digest: self.storage.symbol_names_digest()?.into(),
+        })
+    }
+}
+
+const NAME_POLICY_ENDPOINTS: [&str; 4] = [
+    "tokens.namePolicy",
+    "tokens.setNamePolicy",
+    "tokens.checkSymbolName",
+    "tokens.namesDigest",
+];
+
+/// A module for the policy of symbol names.
+pub struct LedgerNamePolicyModule<T: LedgerNamePolicyModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerNamePolicyModuleBackend> LedgerNamePolicyModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerNamePolicyModule".to_string(),
+                attribute: None,
+                endpoints: NAME_POLICY_ENDPOINTS
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerNamePolicyModuleBackend> Debug for LedgerNamePolicyModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerNamePolicyModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerNamePolicyModuleBackend> ManyModule for LedgerNamePolicyModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "tokens.namePolicy" => decode_args::<NamePolicyArgs>(&message.data).map(|_| ()),
+            "tokens.setNamePolicy" => decode_args::<SetNamePolicyArgs>(&message.data).map(|_| ()),
+            "tokens.checkSymbolName" => {
+                decode_args::<CheckSymbolNameArgs>(&message.data).map(|_| ())
+            }
+            "tokens.namesDigest" => decode_args::<NamesDigestArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "tokens.namePolicy" => {
+                    encode_returns(backend.name_policy(&from, decode_args(&message.data)?)?)
+                }
+                "tokens.setNamePolicy" => {
+                    encode_returns(backend.set_name_policy(&from, decode_args(&message.data)?)?)
+                }
+                "tokens.checkSymbolName" => {
+                    encode_returns(backend.check_symbol_name(&from, decode_args(&message.data)?)?)
+                }
+                "tokens.namesDigest" => {
+                    encode_returns(backend.names_digest(&from, decode_args(&message.data)?)?)
+                }
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
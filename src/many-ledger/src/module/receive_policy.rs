@@ -0,0 +1,244 @@
+use crate::error;
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{account, EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::ledger::{Symbol, TokenAmount};
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Which transfers an address accepts, e.g. to keep unwanted tokens and
+/// dust out of its history. Addresses accept every transfer unless they opt
+/// in to another policy.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub enum ReceivePolicy {
+    #[default]
+    #[n(0)]
+    Open,
+
+    /// Only transfers from these addresses are accepted.
+    #[n(1)]
+    Allowlist(#[n(0)] BTreeSet<Address>),
+
+    /// Only transfers of at least these amounts are accepted. Transfers of
+    /// the symbols not listed are rejected.
+    #[n(2)]
+    MinimumAmount(#[n(0)] BTreeMap<Symbol, TokenAmount>),
+}
+
+impl ReceivePolicy {
+    /// The name of the policy, in errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReceivePolicy::Open => "open",
+            ReceivePolicy::Allowlist(_) => "allowlist",
+            ReceivePolicy::MinimumAmount(_) => "minimum amount",
+        }
+    }
+
+    /// Check a transfer to the address with this policy.
+    pub fn check(
+        &self,
+        from: &Address,
+        to: &Address,
+        symbol: &Symbol,
+        amount: &TokenAmount,
+    ) -> Result<(), ManyError> {
+        let accepted = match self {
+            ReceivePolicy::Open => true,
+            ReceivePolicy::Allowlist(allowed) => allowed.contains(from),
+            ReceivePolicy::MinimumAmount(minimums) => minimums
+                .get(symbol)
+                .map_or(false, |minimum| amount >= minimum),
+        };
+        if accepted {
+            Ok(())
+        } else {
+            Err(error::transfer_rejected_by_policy(*to, self.name()))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReceivePolicyArgs {
+    #[n(0)]
+    pub address: Address,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ReceivePolicyReturns {
+    #[n(0)]
+    pub policy: ReceivePolicy,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetReceivePolicyArgs {
+    /// The address to set the policy of, the sender if omitted. Owners of an
+    /// account can set its policy.
+    #[n(0)]
+    pub address: Option<Address>,
+
+    #[n(1)]
+    pub policy: ReceivePolicy,
+}
+
+/// The arguments of a send, to check against the policy of its recipient
+/// before sending it.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct CheckReceiveArgs {
+    /// The sender if omitted.
+    #[n(0)]
+    pub from: Option<Address>,
+
+    #[n(1)]
+    pub to: Address,
+
+    #[n(2)]
+    pub symbol: Symbol,
+
+    #[n(3)]
+    pub amount: TokenAmount,
+}
+
+pub trait LedgerReceivePolicyModuleBackend: Send {
+    fn receive_policy(
+        &self,
+        sender: &Address,
+        args: ReceivePolicyArgs,
+    ) -> Result<ReceivePolicyReturns, ManyError>;
+    fn set_receive_policy(
+        &mut self,
+        sender: &Address,
+        args: SetReceivePolicyArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn check_receive(
+        &self,
+        sender: &Address,
+        args: CheckReceiveArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+}
+
+impl LedgerReceivePolicyModuleBackend for LedgerModuleImpl {
+    fn receive_policy(
+        &self,
+        _sender: &Address,
+        args: ReceivePolicyArgs,
+    ) -> Result<ReceivePolicyReturns, ManyError> {
+        Ok(ReceivePolicyReturns {
+            policy: self.storage.get_receive_policy(&args.address)?,
+        })
+    }
+
+    fn set_receive_policy(
+        &mut self,
+        sender: &Address,
+        args: SetReceivePolicyArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let address = args.address.unwrap_or(*sender);
+        if &address != sender {
+            let account = self
+                .storage
+                .get_account(&address)
+                .ok_or_else(error::unauthorized)?;
+            account.needs_role(sender, [account::Role::Owner])?;
+        }
+        self.storage.set_receive_policy(&address, args.policy)?;
+        Ok(EmptyReturn)
+    }
+
+    fn check_receive(
+        &self,
+        sender: &Address,
+        args: CheckReceiveArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let from = args.from.unwrap_or(*sender);
+        self.storage
+            .check_receive(&from, &args.to, &args.symbol, &args.amount)?;
+        Ok(EmptyReturn)
+    }
+}
+
+const RECEIVE_POLICY_ENDPOINTS: [&str; 3] = [
+    "ledger.receivePolicy",
+    "ledger.setReceivePolicy",
+    "ledger.checkReceive",
+];
+
+/// A module for the transfers each address accepts.
+pub struct LedgerReceivePolicyModule<T: LedgerReceivePolicyModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerReceivePolicyModuleBackend> LedgerReceivePolicyModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerReceivePolicyModule".to_string(),
+                attribute: None,
+                endpoints: RECEIVE_POLICY_ENDPOINTS
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerReceivePolicyModuleBackend> Debug for LedgerReceivePolicyModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerReceivePolicyModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerReceivePolicyModuleBackend> ManyModule for LedgerReceivePolicyModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.receivePolicy" => decode_args::<ReceivePolicyArgs>(&message.data).map(|_| ()),
+            "ledger.setReceivePolicy" => {
+                decode_args::<SetReceivePolicyArgs>(&message.data).map(|_| ())
+            }
+            "ledger.checkReceive" => decode_args::<CheckReceiveArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "ledger.receivePolicy" => decode_args(&message.data)
+                    .and_then(|args| backend.receive_policy(&from, args))
+                    .and_then(encode_returns),
+                "ledger.setReceivePolicy" => decode_args(&message.data)
+                    .and_then(|args| backend.set_receive_policy(&from, args))
+                    .and_then(encode_returns),
+                "ledger.checkReceive" => decode_args(&message.data)
+                    .and_then(|args| backend.check_receive(&from, args))
+                    .and_then(encode_returns),
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
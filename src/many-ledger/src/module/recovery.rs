@@ -0,0 +1,347 @@
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::account::features::{Feature, FeatureId, FeatureInfo, TryCreateFeature};
+use many_modules::account::{self, Role};
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::cbor::CborAny;
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// The recovery of an account by a designated identity, once its owners
+/// have been inactive for long enough.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct RecoveryConfig {
+    /// The identity which can claim the ownership of the account.
+    #[n(0)]
+    pub recovery: Address,
+
+    /// The number of seconds without a command from an owner after which
+    /// the recovery identity can claim the account.
+    #[n(1)]
+    pub inactivity_secs: u64,
+
+    /// The number of seconds between a claim and the transfer of the
+    /// ownership, during which any command from an owner vetoes the claim.
+    #[n(2)]
+    pub challenge_secs: u64,
+}
+
+/// An account feature holding its recovery configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountRecovery {
+    pub config: RecoveryConfig,
+}
+
+impl TryCreateFeature for AccountRecovery {
+    // Feature IDs up to 2 are defined by many-modules. This one is local to
+    // the ledger.
+    const ID: FeatureId = 1000;
+
+    fn try_create(f: &Feature) -> Result<Self, ManyError> {
+        match f.arguments().as_slice() {
+            [CborAny::Bytes(bytes)] => Ok(Self {
+                config: minicbor::decode(bytes)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))?,
+            }),
+            _ => Err(ManyError::invalid_attribute_arguments()),
+        }
+    }
+}
+
+impl FeatureInfo for AccountRecovery {
+    fn as_feature(&self) -> Feature {
+        Feature::new(
+            Self::ID,
+            vec![CborAny::Bytes(
+                minicbor::to_vec(&self.config).expect("Could not encode the recovery config"),
+            )],
+        )
+    }
+
+    fn roles() -> BTreeSet<Role> {
+        BTreeSet::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SetRecoveryArgs {
+    #[n(0)]
+    pub account: Address,
+
+    #[n(1)]
+    pub recovery: Address,
+
+    #[n(2)]
+    pub inactivity_secs: u64,
+
+    #[n(3)]
+    pub challenge_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct RecoverArgs {
+    #[n(0)]
+    pub account: Address,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct RecoverReturns {
+    /// Whether the ownership of the account was transferred. If not, the
+    /// call opened a claim.
+    #[n(0)]
+    pub recovered: bool,
+
+    /// When the claim opened by the call can be completed, by calling
+    /// `account.recover` again.
+    #[n(1)]
+    pub completable: Option<Timestamp>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct RecoveryInfoArgs {
+    #[n(0)]
+    pub account: Address,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct RecoveryInfoReturns {
+    #[n(0)]
+    pub config: RecoveryConfig,
+
+    /// The block time of the last command of an owner of the account.
+    #[n(1)]
+    pub last_activity: Timestamp,
+
+    /// When the recovery identity can claim the account.
+    #[n(2)]
+    pub claimable: Timestamp,
+
+    /// When the pending claim was opened, if an owner did not veto it.
+    #[n(3)]
+    pub claimed: Option<Timestamp>,
+
+    /// When the pending claim can be completed.
+    #[n(4)]
+    pub completable: Option<Timestamp>,
+}
+
+pub trait AccountRecoveryModuleBackend: Send {
+    fn set_recovery(
+        &mut self,
+        sender: &Address,
+        args: SetRecoveryArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn recover(&mut self, sender: &Address, args: RecoverArgs)
+        -> Result<RecoverReturns, ManyError>;
+    fn recovery_info(
+        &self,
+        sender: &Address,
+        args: RecoveryInfoArgs,
+    ) -> Result<RecoveryInfoReturns, ManyError>;
+
+    /// Record that an identity sent a command which succeeded.
+    fn record_activity(&mut self, sender: &Address) -> Result<(), ManyError>;
+}
+
+impl AccountRecoveryModuleBackend for LedgerModuleImpl {
+    fn set_recovery(
+        &mut self,
+        sender: &Address,
+        args: SetRecoveryArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let SetRecoveryArgs {
+            account: id,
+            recovery,
+            inactivity_secs,
+            challenge_secs,
+        } = args;
+        let account = self
+            .storage
+            .get_account(&id)
+            .ok_or_else(|| account::errors::unknown_account(id))?;
+
+        if !account.has_role(sender, Role::Owner) {
+            return Err(account::errors::user_needs_role("owner"));
+        }
+
+        self.storage.set_recovery(
+            &id,
+            account,
+            RecoveryConfig {
+                recovery,
+                inactivity_secs,
+                challenge_secs,
+            },
+        )?;
+        Ok(EmptyReturn)
+    }
+
+    fn recover(
+        &mut self,
+        sender: &Address,
+        args: RecoverArgs,
+    ) -> Result<RecoverReturns, ManyError> {
+        self.storage.recover(sender, &args.account)
+    }
+
+    fn recovery_info(
+        &self,
+        _sender: &Address,
+        args: RecoveryInfoArgs,
+    ) -> Result<RecoveryInfoReturns, ManyError> {
+        self.storage.get_recovery_info(&args.account)
+    }
+
+    fn record_activity(&mut self, sender: &Address) -> Result<(), ManyError> {
+        self.storage.record_activity(sender)
+    }
+}
+
+const RECOVERY_ENDPOINTS: [&str; 3] = [
+    "account.setRecovery",
+    "account.recover",
+    "account.recoveryInfo",
+];
+
+/// A module for the recovery of accounts whose owners are inactive.
+pub struct AccountRecoveryModule<T: AccountRecoveryModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: AccountRecoveryModuleBackend> AccountRecoveryModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "AccountRecoveryModule".to_string(),
+                attribute: None,
+                endpoints: RECOVERY_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: AccountRecoveryModuleBackend> Debug for AccountRecoveryModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AccountRecoveryModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: AccountRecoveryModuleBackend> ManyModule for AccountRecoveryModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "account.setRecovery" => decode_args::<SetRecoveryArgs>(&message.data).map(|_| ()),
+            "account.recover" => decode_args::<RecoverArgs>(&message.data).map(|_| ()),
+            "account.recoveryInfo" => decode_args::<RecoveryInfoArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "account.setRecovery" => decode_args(&message.data)
+                    .and_then(|args| backend.set_recovery(&from, args))
+                    .and_then(encode_returns),
+                "account.recover" => decode_args(&message.data)
+                    .and_then(|args| backend.recover(&from, args))
+                    .and_then(encode_returns),
+                "account.recoveryInfo" => decode_args(&message.data)
+                    .and_then(|args| backend.recovery_info(&from, args))
+                    .and_then(encode_returns),
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
+
+/// Records the activity of the senders of the commands of a module which
+/// succeed, which vetoes the recovery of the accounts they own.
+pub struct ActivityModule<M: ManyModule, T: AccountRecoveryModuleBackend> {
+    inner: M,
+    backend: Arc<Mutex<T>>,
+    commands: BTreeSet<String>,
+}
+
+impl<M: ManyModule, T: AccountRecoveryModuleBackend + ManyAbciModuleBackend> ActivityModule<M, T> {
+    /// The commands are the endpoints of the module which the ABCI frontend
+    /// delivers as transactions.
+    pub fn new(inner: M, backend: Arc<Mutex<T>>) -> Self {
+        let endpoints = backend
+            .lock()
+            .unwrap()
+            .init()
+            .expect("Could not list the endpoints.")
+            .endpoints;
+        let commands = inner
+            .info()
+            .endpoints
+            .iter()
+            .filter(|e| endpoints.get(*e).map_or(false, |info| info.is_command))
+            .cloned()
+            .collect();
+        Self {
+            inner,
+            backend,
+            commands,
+        }
+    }
+}
+
+impl<M: ManyModule, T: AccountRecoveryModuleBackend> Debug for ActivityModule<M, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ActivityModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule, T: AccountRecoveryModuleBackend> ManyModule for ActivityModule<M, T> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let is_command = self.commands.contains(&message.method);
+        let from = message.from();
+        let response = self.inner.execute(message).await?;
+
+        if is_command && response.data.is_ok() && !from.is_anonymous() {
+            self.backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?
+                .record_activity(&from)?;
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,210 @@
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{events, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::Timestamp;
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SnapshotInfo {
+    #[n(0)]
+    pub id: u64,
+
+    /// The height of the last committed block when the snapshot was taken.
+    #[n(1)]
+    pub height: u64,
+
+    #[n(2)]
+    pub symbol: Symbol,
+
+    /// The ID of the last event included in the snapshot.
+    #[n(3)]
+    pub event_id: events::EventId,
+
+    /// The root hash of the ledger state when the snapshot was taken.
+    #[n(4)]
+    pub hash: ByteVec,
+
+    #[n(5)]
+    pub time: Timestamp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SnapshotArgs {
+    #[n(0)]
+    pub symbol: Symbol,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SnapshotReturns {
+    #[n(0)]
+    pub snapshot: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SnapshotBalanceArgs {
+    #[n(0)]
+    pub snapshot: u64,
+
+    /// The account, if different from the sender.
+    #[n(1)]
+    pub account: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SnapshotBalanceReturns {
+    #[n(0)]
+    pub symbol: Symbol,
+
+    #[n(1)]
+    pub balance: TokenAmount,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ListSnapshotsArgs {
+    #[n(0)]
+    pub symbol: Option<Symbol>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct ListSnapshotsReturns {
+    #[n(0)]
+    pub snapshots: Vec<SnapshotInfo>,
+}
+
+pub trait LedgerSnapshotModuleBackend: Send {
+    fn snapshot(
+        &mut self,
+        sender: &Address,
+        args: SnapshotArgs,
+    ) -> Result<SnapshotReturns, ManyError>;
+    fn snapshot_balance(
+        &self,
+        sender: &Address,
+        args: SnapshotBalanceArgs,
+    ) -> Result<SnapshotBalanceReturns, ManyError>;
+    fn list_snapshots(
+        &self,
+        sender: &Address,
+        args: ListSnapshotsArgs,
+    ) -> Result<ListSnapshotsReturns, ManyError>;
+}
+
+impl LedgerSnapshotModuleBackend for LedgerModuleImpl {
+    fn snapshot(
+        &mut self,
+        sender: &Address,
+        args: SnapshotArgs,
+    ) -> Result<SnapshotReturns, ManyError> {
+        let snapshot = self.storage.create_snapshot(sender, &args.symbol)?;
+        Ok(SnapshotReturns { snapshot })
+    }
+
+    fn snapshot_balance(
+        &self,
+        sender: &Address,
+        args: SnapshotBalanceArgs,
+    ) -> Result<SnapshotBalanceReturns, ManyError> {
+        let account = args.account.as_ref().unwrap_or(sender);
+        let symbol = self.storage.get_snapshot(args.snapshot)?.symbol;
+        let balance = self.storage.get_snapshot_balance(args.snapshot, account)?;
+        Ok(SnapshotBalanceReturns { symbol, balance })
+    }
+
+    fn list_snapshots(
+        &self,
+        _sender: &Address,
+        args: ListSnapshotsArgs,
+    ) -> Result<ListSnapshotsReturns, ManyError> {
+        Ok(ListSnapshotsReturns {
+            snapshots: self.storage.list_snapshots(args.symbol)?,
+        })
+    }
+}
+
+const SNAPSHOT_ENDPOINTS: [&str; 3] = [
+    "tokens.snapshot",
+    "tokens.snapshotBalance",
+    "tokens.listSnapshots",
+];
+
+/// A module for point-in-time snapshots of token holders.
+pub struct LedgerSnapshotModule<T: LedgerSnapshotModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerSnapshotModuleBackend> LedgerSnapshotModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerSnapshotModule".to_string(),
+                attribute: None,
+                endpoints: SNAPSHOT_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerSnapshotModuleBackend> Debug for LedgerSnapshotModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerSnapshotModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerSnapshotModuleBackend> ManyModule for LedgerSnapshotModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "tokens.snapshot" => decode_args::<SnapshotArgs>(&message.data).map(|_| ()),
+            "tokens.snapshotBalance" => {
+                decode_args::<SnapshotBalanceArgs>(&message.data).map(|_| ())
+            }
+            "tokens.listSnapshots" => decode_args::<ListSnapshotsArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "tokens.snapshot" => {
+                    encode_returns(backend.snapshot(&from, decode_args(&message.data)?)?)
+                }
+                "tokens.snapshotBalance" => {
+                    encode_returns(backend.snapshot_balance(&from, decode_args(&message.data)?)?)
+                }
+                "tokens.listSnapshots" => {
+                    encode_returns(backend.list_snapshots(&from, decode_args(&message.data)?)?)
+                }
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -0,0 +1,198 @@
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::{events, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct StatsArgs {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct LastEvent {
+    #[n(0)]
+    pub id: events::EventId,
+
+    #[n(1)]
+    pub time: Timestamp,
+}
+
+/// Statistics of the chain, read from counters maintained with the state so
+/// every node returns the same values at the same height.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct StatsReturns {
+    /// The number of commands which succeeded, counted since this counter
+    /// was introduced.
+    #[n(0)]
+    pub transactions: u64,
+
+    /// The number of events ever logged, including pruned ones.
+    #[n(1)]
+    pub events: u64,
+
+    /// The number of addresses with a nonzero balance of any symbol.
+    #[n(2)]
+    pub nonzero_addresses: u64,
+
+    #[n(3)]
+    pub accounts: u64,
+
+    #[n(4)]
+    pub symbols: u64,
+
+    #[n(5)]
+    pub pending_multisig: u64,
+
+    #[n(6)]
+    pub last_event: Option<LastEvent>,
+}
+
+pub trait LedgerStatsModuleBackend: Send {
+    fn stats(&self, sender: &Address, args: StatsArgs) -> Result<StatsReturns, ManyError>;
+
+    /// Count a command which succeeded.
+    fn count_transaction(&mut self);
+}
+
+impl LedgerStatsModuleBackend for LedgerModuleImpl {
+    fn stats(&self, _sender: &Address, _args: StatsArgs) -> Result<StatsReturns, ManyError> {
+        self.storage.stats()
+    }
+
+    fn count_transaction(&mut self) {
+        self.storage.count_transaction()
+    }
+}
+
+const STATS_ENDPOINTS: [&str; 1] = ["ledger.stats"];
+
+/// A module for the statistics of the chain.
+pub struct LedgerStatsModule<T: LedgerStatsModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerStatsModuleBackend> LedgerStatsModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerStatsModule".to_string(),
+                attribute: None,
+                endpoints: STATS_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerStatsModuleBackend> Debug for LedgerStatsModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerStatsModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerStatsModuleBackend> ManyModule for LedgerStatsModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.stats" => decode_args::<StatsArgs>(&message.data).map(|_| ()),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "ledger.stats" => decode_args(&message.data)
+                    .and_then(|args| backend.stats(&from, args))
+                    .and_then(encode_returns),
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
+
+/// Counts the commands of a module which succeed as transactions.
+pub struct TransactionCountModule<M: ManyModule, T: LedgerStatsModuleBackend> {
+    inner: M,
+    backend: Arc<Mutex<T>>,
+    commands: BTreeSet<String>,
+}
+
+impl<M: ManyModule, T: LedgerStatsModuleBackend + ManyAbciModuleBackend>
+    TransactionCountModule<M, T>
+{
+    /// The commands are the endpoints of the module which the ABCI frontend
+    /// delivers as transactions.
+    pub fn new(inner: M, backend: Arc<Mutex<T>>) -> Self {
+        let endpoints = backend
+            .lock()
+            .unwrap()
+            .init()
+            .expect("Could not list the endpoints.")
+            .endpoints;
+        let commands = inner
+            .info()
+            .endpoints
+            .iter()
+            .filter(|e| endpoints.get(*e).map_or(false, |info| info.is_command))
+            .cloned()
+            .collect();
+        Self {
+            inner,
+            backend,
+            commands,
+        }
+    }
+}
+
+impl<M: ManyModule, T: LedgerStatsModuleBackend> Debug for TransactionCountModule<M, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TransactionCountModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule, T: LedgerStatsModuleBackend> ManyModule for TransactionCountModule<M, T> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let is_command = self.commands.contains(&message.method);
+        let response = self.inner.execute(message).await?;
+
+        if is_command && response.data.is_ok() {
+            self.backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?
+                .count_transaction();
+        }
+        Ok(response)
+    }
+}
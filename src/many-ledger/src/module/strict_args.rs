@@ -0,0 +1,228 @@
+use crate::error;
+use crate::module::attachment::{Attachment, ATTACHMENT};
+use crate::module::governance::{self, Param};
+use crate::module::LedgerModuleImpl;
+use crate::module::{batch, escrow, receive_policy, recovery, swap};
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_modules::account::features::multisig;
+use many_modules::{account, ledger, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::cbor::CborAny;
+use minicbor::data::Type;
+use minicbor::{Decoder, Encode};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// A key of the map of arguments of a command.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FieldKey {
+    Index(u64),
+    Name(String),
+
+    /// Any other key, as its CBOR encoding.
+    Other(Vec<u8>),
+}
+
+impl Display for FieldKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldKey::Index(i) => write!(f, "{}", i),
+            FieldKey::Name(name) => write!(f, "{:?}", name),
+            FieldKey::Other(bytes) => write!(f, "h'{}'", hex::encode(bytes)),
+        }
+    }
+}
+
+/// The keys of a CBOR map, and whether their value is null. Anything else
+/// than a map has no keys.
+fn map_keys(bytes: &[u8]) -> Result<BTreeMap<FieldKey, bool>, minicbor::decode::Error> {
+    let mut d = Decoder::new(bytes);
+    let mut keys = BTreeMap::new();
+    if !matches!(d.datatype()?, Type::Map | Type::MapIndef) {
+        return Ok(keys);
+    }
+
+    let len = d.map()?;
+    let mut i = 0;
+    while len.map_or(true, |len| i < len) {
+        if len.is_none() && d.datatype()? == Type::Break {
+            break;
+        }
+        let start = d.position();
+        let key = match d.datatype()? {
+            Type::U8 | Type::U16 | Type::U32 | Type::U64 => FieldKey::Index(d.u64()?),
+            Type::String => FieldKey::Name(d.str()?.to_string()),
+            _ => {
+                d.skip()?;
+                FieldKey::Other(bytes[start..d.position()].to_vec())
+            }
+        };
+        let is_null = d.datatype()? == Type::Null;
+        d.skip()?;
+        keys.insert(key, is_null);
+        i += 1;
+    }
+    Ok(keys)
+}
+
+/// The fields of arguments which their type ignores when decoding them. The
+/// arguments are decoded and encoded back, and the fields which were not
+/// kept are unknown. Null fields are ignored, like absent optional fields.
+///
+/// Only the fields of the top level map are checked.
+pub fn unknown_fields<T>(data: &[u8]) -> Result<Vec<FieldKey>, ManyError>
+where
+    T: for<'b> minicbor::Decode<'b, ()> + Encode<()>,
+{
+    let args: T =
+        minicbor::decode(data).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    let encoded =
+        minicbor::to_vec(&args).map_err(|e| ManyError::serialization_error(e.to_string()))?;
+    let known = map_keys(&encoded).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+    let given = map_keys(data).map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+
+    Ok(given
+        .into_iter()
+        .filter(|(key, is_null)| !is_null && !known.contains_key(key))
+        .map(|(key, _)| key)
+        .collect())
+}
+
+type FieldsChecker = fn(&[u8]) -> Result<Vec<FieldKey>, ManyError>;
+
+/// The commands whose arguments are checked in strict mode. Queries are not
+/// checked.
+fn checker(method: &str) -> Option<FieldsChecker> {
+    Some(match method {
+        "ledger.send" => unknown_fields::<ledger::SendArgs>,
+        "ledger.setReceivePolicy" => unknown_fields::<receive_policy::SetReceivePolicyArgs>,
+        "ledger.batch" => unknown_fields::<batch::BatchArgs>,
+        "ledger.escrowCreate" => unknown_fields::<escrow::EscrowCreateArgs>,
+        "ledger.escrowRelease" | "ledger.escrowRefund" => unknown_fields::<escrow::EscrowArgs>,
+        "ledger.swapCreate" => unknown_fields::<swap::SwapCreateArgs>,
+        "ledger.swapAccept" => unknown_fields::<swap::SwapAcceptArgs>,
+        "ledger.swapCancel" => unknown_fields::<swap::SwapArgs>,
+        "governance.setParam" => unknown_fields::<governance::SetParamArgs>,
+        "account.setRecovery" => unknown_fields::<recovery::SetRecoveryArgs>,
+        "account.recover" => unknown_fields::<recovery::RecoverArgs>,
+        "account.create" => unknown_fields::<account::CreateArgs>,
+        "account.setDescription" => unknown_fields::<account::SetDescriptionArgs>,
+        "account.addRoles" => unknown_fields::<account::AddRolesArgs>,
+        "account.removeRoles" => unknown_fields::<account::RemoveRolesArgs>,
+        "account.disable" => unknown_fields::<account::DisableArgs>,
+        "account.addFeatures" => unknown_fields::<account::AddFeaturesArgs>,
+        "account.multisigSubmitTransaction" => unknown_fields::<multisig::SubmitTransactionArgs>,
+        "account.multisigApprove" => unknown_fields::<multisig::ApproveArgs>,
+        "account.multisigRevoke" => unknown_fields::<multisig::RevokeArgs>,
+        "account.multisigExecute" => unknown_fields::<multisig::ExecuteArgs>,
+        "account.multisigWithdraw" => unknown_fields::<multisig::WithdrawArgs>,
+        "account.multisigSetDefaults" => unknown_fields::<multisig::SetDefaultsArgs>,
+        _ => return None,
+    })
+}
+
+fn join(fields: &[FieldKey]) -> String {
+    fields
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Check the arguments of a command in strict mode.
+pub fn check(method: &str, data: &[u8]) -> Result<(), ManyError> {
+    let fields = match checker(method) {
+        Some(checker) => checker(data)?,
+        None => return Ok(()),
+    };
+    if fields.is_empty() {
+        Ok(())
+    } else {
+        Err(error::unknown_fields(method.to_string(), join(&fields)))
+    }
+}
+
+/// Check the attachment of a command in strict mode, if it has one. An
+/// attachment which is not a map is left to the attachment module to reject.
+pub fn check_attachment(message: &RequestMessage) -> Result<(), ManyError> {
+    let arguments = match message.attributes.get_attribute(ATTACHMENT.id) {
+        Some(attribute) => attribute.arguments.as_slice(),
+        None => return Ok(()),
+    };
+    let fields = match arguments {
+        [CborAny::Bytes(bytes)] => unknown_fields::<Attachment>(bytes)?,
+        _ => return Ok(()),
+    };
+    if fields.is_empty() {
+        Ok(())
+    } else {
+        Err(error::unknown_attachment_fields(
+            message.method.clone(),
+            join(&fields),
+        ))
+    }
+}
+
+pub trait StrictArgsModuleBackend: Send {
+    /// Whether the arguments of commands are decoded strictly. All nodes
+    /// must agree on it.
+    fn strict_args(&self) -> Result<bool, ManyError>;
+}
+
+impl StrictArgsModuleBackend for LedgerModuleImpl {
+    fn strict_args(&self) -> Result<bool, ManyError> {
+        self.storage.param_bool(Param::StrictArgs)
+    }
+}
+
+/// Reject the commands of a module whose arguments have unknown fields, e.g.
+/// a misspelled optional field, when the `args.strict` parameter is set.
+/// This is checked when executing the command, so every node decides with
+/// the parameter of the same state.
+pub struct StrictArgsModule<M: ManyModule, T: StrictArgsModuleBackend> {
+    inner: M,
+    backend: Arc<Mutex<T>>,
+}
+
+impl<M: ManyModule, T: StrictArgsModuleBackend> StrictArgsModule<M, T> {
+    pub fn new(inner: M, backend: Arc<Mutex<T>>) -> Self {
+        Self { inner, backend }
+    }
+}
+
+impl<M: ManyModule, T: StrictArgsModuleBackend> Debug for StrictArgsModule<M, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StrictArgsModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule, T: StrictArgsModuleBackend> ManyModule for StrictArgsModule<M, T> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        if checker(&message.method).is_some() {
+            let strict = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?
+                .strict_args()?;
+            if strict {
+                let checked =
+                    check(&message.method, &message.data).and_then(|_| check_attachment(&message));
+                if let Err(e) = checked {
+                    return Ok(ResponseMessage::from_request(&message, &message.to, Err(e)));
+                }
+            }
+        }
+        self.inner.execute(message).await
+    }
+}
@@ -0,0 +1,276 @@
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{EmptyReturn, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_storage::cbor::{decode_args, encode_returns};
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::Timestamp;
+use minicbor::{Decode, Encode};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum SwapState {
+    #[n(0)]
+    Pending,
+    #[n(1)]
+    Accepted,
+    #[n(2)]
+    Cancelled,
+    #[n(3)]
+    Expired,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SwapInfo {
+    #[n(0)]
+    pub maker: Address,
+
+    /// The only identity that can accept the swap, if any.
+    #[n(1)]
+    pub counterparty: Option<Address>,
+
+    /// The symbol and amount locked by the maker.
+    #[n(2)]
+    pub symbol: Symbol,
+
+    #[n(3)]
+    pub amount: TokenAmount,
+
+    /// The symbol and amount the maker wants in exchange.
+    #[n(4)]
+    pub want_symbol: Symbol,
+
+    #[n(5)]
+    pub want_amount: TokenAmount,
+
+    #[n(6)]
+    pub expiry: Timestamp,
+
+    #[n(7)]
+    pub state: SwapState,
+
+    /// The identity that accepted the swap, once accepted.
+    #[n(8)]
+    pub taker: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SwapCreateArgs {
+    /// The maker, if different from the sender (e.g. a ledger account).
+    #[n(0)]
+    pub from: Option<Address>,
+
+    #[n(1)]
+    pub symbol: Symbol,
+
+    #[n(2)]
+    pub amount: TokenAmount,
+
+    #[n(3)]
+    pub want_symbol: Symbol,
+
+    #[n(4)]
+    pub want_amount: TokenAmount,
+
+    #[n(5)]
+    pub counterparty: Option<Address>,
+
+    #[n(6)]
+    pub expires_in_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SwapCreateReturns {
+    #[n(0)]
+    pub swap: Address,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SwapAcceptArgs {
+    #[n(0)]
+    pub swap: Address,
+
+    /// The taker, if different from the sender (e.g. a ledger account).
+    #[n(1)]
+    pub from: Option<Address>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct SwapArgs {
+    #[n(0)]
+    pub swap: Address,
+}
+
+pub type SwapCancelArgs = SwapArgs;
+pub type SwapInfoArgs = SwapArgs;
+pub type SwapInfoReturns = SwapInfo;
+
+pub trait LedgerSwapModuleBackend: Send {
+    fn swap_create(
+        &mut self,
+        sender: &Address,
+        args: SwapCreateArgs,
+    ) -> Result<SwapCreateReturns, ManyError>;
+    fn swap_accept(
+        &mut self,
+        sender: &Address,
+        args: SwapAcceptArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn swap_cancel(
+        &mut self,
+        sender: &Address,
+        args: SwapCancelArgs,
+    ) -> Result<EmptyReturn, ManyError>;
+    fn swap_info(&self, sender: &Address, args: SwapInfoArgs)
+        -> Result<SwapInfoReturns, ManyError>;
+}
+
+impl LedgerSwapModuleBackend for LedgerModuleImpl {
+    fn swap_create(
+        &mut self,
+        sender: &Address,
+        args: SwapCreateArgs,
+    ) -> Result<SwapCreateReturns, ManyError> {
+        let SwapCreateArgs {
+            from,
+            symbol,
+            amount,
+            want_symbol,
+            want_amount,
+            counterparty,
+            expires_in_secs,
+        } = args;
+
+        let from = from.as_ref().unwrap_or(sender);
+        self.verify_can_transact(sender, from)?;
+
+        let swap = self.storage.create_swap(
+            from,
+            counterparty,
+            &symbol,
+            amount,
+            &want_symbol,
+            want_amount,
+            expires_in_secs,
+        )?;
+        Ok(SwapCreateReturns { swap })
+    }
+
+    fn swap_accept(
+        &mut self,
+        sender: &Address,
+        args: SwapAcceptArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let from = args.from.as_ref().unwrap_or(sender);
+        self.verify_can_transact(sender, from)?;
+
+        self.storage.accept_swap(from, &args.swap)?;
+        Ok(EmptyReturn)
+    }
+
+    fn swap_cancel(
+        &mut self,
+        sender: &Address,
+        args: SwapCancelArgs,
+    ) -> Result<EmptyReturn, ManyError> {
+        let info = self.storage.get_swap(&args.swap)?;
+        self.verify_can_transact(sender, &info.maker)?;
+
+        self.storage.cancel_swap(&args.swap)?;
+        Ok(EmptyReturn)
+    }
+
+    fn swap_info(
+        &self,
+        _sender: &Address,
+        args: SwapInfoArgs,
+    ) -> Result<SwapInfoReturns, ManyError> {
+        self.storage.get_swap(&args.swap)
+    }
+}
+
+const SWAP_ENDPOINTS: [&str; 4] = [
+    "ledger.swapCreate",
+    "ledger.swapAccept",
+    "ledger.swapCancel",
+    "ledger.swapInfo",
+];
+
+/// A module for swapping tokens of different symbols between two parties.
+pub struct LedgerSwapModule<T: LedgerSwapModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerSwapModuleBackend> LedgerSwapModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerSwapModule".to_string(),
+                attribute: None,
+                endpoints: SWAP_ENDPOINTS.iter().map(|e| e.to_string()).collect(),
+            },
+        }
+    }
+}
+
+impl<T: LedgerSwapModuleBackend> Debug for LedgerSwapModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerSwapModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerSwapModuleBackend> ManyModule for LedgerSwapModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.swapCreate" => decode_args::<SwapCreateArgs>(&message.data).map(|_| ()),
+            "ledger.swapAccept" => decode_args::<SwapAcceptArgs>(&message.data).map(|_| ()),
+            "ledger.swapCancel" | "ledger.swapInfo" => {
+                decode_args::<SwapArgs>(&message.data).map(|_| ())
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = {
+            let mut backend = self
+                .backend
+                .lock()
+                .map_err(|e| ManyError::unknown(e.to_string()))?;
+            match message.method.as_str() {
+                "ledger.swapCreate" => {
+                    encode_returns(backend.swap_create(&from, decode_args(&message.data)?)?)
+                }
+                "ledger.swapAccept" => {
+                    encode_returns(backend.swap_accept(&from, decode_args(&message.data)?)?)
+                }
+                "ledger.swapCancel" => {
+                    encode_returns(backend.swap_cancel(&from, decode_args(&message.data)?)?)
+                }
+                "ledger.swapInfo" => {
+                    encode_returns(backend.swap_info(&from, decode_args(&message.data)?)?)
+                }
+                _ => Err(ManyError::invalid_method_name(message.method.clone())),
+            }
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
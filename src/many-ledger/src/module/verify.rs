@@ -0,0 +1,323 @@
+use crate::module::LedgerModuleImpl;
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::ledger::{Symbol, TokenAmount};
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+/// Default number of records walked by a single `ledger.verify` call.
+pub const DEFAULT_VERIFY_LIMIT: u64 = 1000;
+
+/// Maximum number of records walked by a single `ledger.verify` call, so
+/// a query never holds the ledger for long.
+pub const MAX_VERIFY_LIMIT: u64 = 10_000;
+
+/// The structures of the ledger, in the order they are verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum VerifyPhase {
+    /// Balances, summed into the supply of each symbol.
+    #[n(0)]
+    Balances,
+
+    /// Escrows, checked against their balance and timeout.
+    #[n(1)]
+    Escrows,
+
+    /// The amounts escrowed by each payer, checked against pending escrows.
+    #[n(2)]
+    Escrowed,
+
+    /// The escrow timeout index, checked against pending escrows.
+    #[n(3)]
+    EscrowTimeouts,
+
+    /// The recall phrase and address indexes of the identity store.
+    #[n(4)]
+    IdStore,
+
+    /// Multisig transactions, checked against their account.
+    #[n(5)]
+    Multisig,
+
+    /// Events, checked against the events counter.
+    #[n(6)]
+    Events,
+
+    /// Accounts, checked against the account ID counter.
+    #[n(7)]
+    Accounts,
+}
+
+impl VerifyPhase {
+    pub fn next(self) -> Option<Self> {
+        match self {
+            VerifyPhase::Balances => Some(VerifyPhase::Escrows),
+            VerifyPhase::Escrows => Some(VerifyPhase::Escrowed),
+            VerifyPhase::Escrowed => Some(VerifyPhase::EscrowTimeouts),
+            VerifyPhase::EscrowTimeouts => Some(VerifyPhase::IdStore),
+            VerifyPhase::IdStore => Some(VerifyPhase::Multisig),
+            VerifyPhase::Multisig => Some(VerifyPhase::Events),
+            VerifyPhase::Events => Some(VerifyPhase::Accounts),
+            VerifyPhase::Accounts => None,
+        }
+    }
+}
+
+/// Where to resume a verification, and what was accumulated so far.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct VerifyCursor {
+    #[n(0)]
+    pub phase: VerifyPhase,
+
+    /// The last storage key verified in this phase.
+    #[n(1)]
+    pub key: Option<ByteVec>,
+
+    /// What was counted so far in this phase.
+    #[n(2)]
+    pub count: u64,
+
+    /// The lowest account ID counter that does not reuse an existing ID.
+    #[n(3)]
+    pub next_account_id: u64,
+
+    /// The sum of all balances, per symbol.
+    #[n(4)]
+    pub supply: BTreeMap<Symbol, TokenAmount>,
+
+    /// The amounts locked in pending escrows, by storage key of the
+    /// escrowed amount. Entries are removed as they are verified.
+    #[n(5)]
+    pub escrowed: BTreeMap<ByteVec, TokenAmount>,
+
+    /// The number of entries in the address index of the identity store.
+    #[n(6)]
+    pub idstore_addresses: u64,
+}
+
+impl Default for VerifyCursor {
+    fn default() -> Self {
+        Self {
+            phase: VerifyPhase::Balances,
+            key: None,
+            count: 0,
+            next_account_id: 0,
+            supply: BTreeMap::new(),
+            escrowed: BTreeMap::new(),
+            idstore_addresses: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(index_only)]
+pub enum DiscrepancyKind {
+    /// A balance is held in a symbol the ledger does not know.
+    #[n(0)]
+    UnknownSymbol,
+
+    /// An escrow cannot be decoded.
+    #[n(1)]
+    InvalidEscrow,
+
+    /// A pending escrow does not hold its amount.
+    #[n(2)]
+    EscrowBalance,
+
+    /// A pending escrow is missing from the timeout index.
+    #[n(3)]
+    MissingEscrowTimeout,
+
+    /// The timeout index has an entry for an escrow which is not pending.
+    #[n(4)]
+    StaleEscrowTimeout,
+
+    /// The amount escrowed by a payer does not match its pending escrows.
+    #[n(5)]
+    EscrowedAmount,
+
+    /// The recall phrase and address indexes of the identity store do not
+    /// have the same number of entries.
+    #[n(6)]
+    IdStoreCount,
+
+    /// A multisig transaction cannot be decoded.
+    #[n(7)]
+    InvalidMultisigTransaction,
+
+    /// A pending multisig transaction belongs to an unknown account.
+    #[n(8)]
+    MultisigAccount,
+
+    /// The events counter does not match the number of events.
+    #[n(9)]
+    EventCount,
+
+    /// The account ID counter would reuse the ID of an existing account.
+    #[n(10)]
+    AccountCounter,
+}
+
+/// An inconsistency between the primary records and the indexes and
+/// counters derived from them.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct Discrepancy {
+    #[n(0)]
+    pub kind: DiscrepancyKind,
+
+    /// The storage key holding the inconsistent data.
+    #[n(1)]
+    pub key: ByteVec,
+
+    /// The data currently stored at the key.
+    #[n(2)]
+    pub actual: Option<ByteVec>,
+
+    /// The data expected at the key. Missing if the key should not exist,
+    /// or if it cannot be known.
+    #[n(3)]
+    pub expected: Option<ByteVec>,
+
+    /// Whether writing the expected data (or deleting the key) fixes the
+    /// discrepancy. Other discrepancies need to be investigated.
+    #[n(4)]
+    pub repairable: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct VerifyArgs {
+    /// Where to resume, or the beginning of the ledger if missing.
+    #[n(0)]
+    pub cursor: Option<VerifyCursor>,
+
+    #[n(1)]
+    pub limit: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct VerifyReturns {
+    #[n(0)]
+    pub discrepancies: Vec<Discrepancy>,
+
+    /// The cursor to pass to the next call, or missing if the whole ledger
+    /// was verified.
+    #[n(1)]
+    pub next: Option<VerifyCursor>,
+
+    /// The total supply of each symbol, once the whole ledger was verified.
+    #[n(2)]
+    pub supply: Option<BTreeMap<Symbol, TokenAmount>>,
+}
+
+pub trait LedgerVerifyModuleBackend: Send {
+    fn verify(&self, sender: &Address, args: VerifyArgs) -> Result<VerifyReturns, ManyError>;
+}
+
+impl LedgerVerifyModuleBackend for LedgerModuleImpl {
+    fn verify(&self, sender: &Address, args: VerifyArgs) -> Result<VerifyReturns, ManyError> {
+        let limit = args
+            .limit
+            .unwrap_or(DEFAULT_VERIFY_LIMIT)
+            .min(MAX_VERIFY_LIMIT);
+        self.storage
+            .verify(sender, args.cursor.unwrap_or_default(), limit as usize)
+    }
+}
+
+impl LedgerModuleImpl {
+    /// Verify the whole ledger at once, repairing what can be repaired if
+    /// asked to. Returns all the discrepancies found, repaired or not.
+    /// This should only be used offline, as it does not yield.
+    pub fn verify_all(&mut self, repair: bool) -> Result<VerifyReturns, ManyError> {
+        let identity = self.storage.identity();
+        let mut result = VerifyReturns {
+            discrepancies: vec![],
+            next: Some(VerifyCursor::default()),
+            supply: None,
+        };
+        while let Some(cursor) = result.next {
+            let mut r = self
+                .storage
+                .verify(&identity, cursor, MAX_VERIFY_LIMIT as usize)?;
+            r.discrepancies = [result.discrepancies, r.discrepancies].concat();
+            result = r;
+        }
+
+        if repair {
+            self.storage.repair(&result.discrepancies)?;
+        }
+        Ok(result)
+    }
+}
+
+/// A module for verifying the consistency of the ledger.
+pub struct LedgerVerifyModule<T: LedgerVerifyModuleBackend> {
+    backend: Arc<Mutex<T>>,
+    info: ManyModuleInfo,
+}
+
+impl<T: LedgerVerifyModuleBackend> LedgerVerifyModule<T> {
+    pub fn new(backend: Arc<Mutex<T>>) -> Self {
+        Self {
+            backend,
+            info: ManyModuleInfo {
+                name: "LedgerVerifyModule".to_string(),
+                attribute: None,
+                endpoints: vec!["ledger.verify".to_string()],
+            },
+        }
+    }
+}
+
+impl<T: LedgerVerifyModuleBackend> Debug for LedgerVerifyModule<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LedgerVerifyModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: LedgerVerifyModuleBackend> ManyModule for LedgerVerifyModule<T> {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "ledger.verify" => minicbor::decode::<VerifyArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let from = message.from();
+        let data = match message.method.as_str() {
+            "ledger.verify" => {
+                let args: VerifyArgs = minicbor::decode(&message.data)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+                let backend = self
+                    .backend
+                    .lock()
+                    .map_err(|e| ManyError::unknown(e.to_string()))?;
+                backend.verify(&from, args).and_then(|r| {
+                    minicbor::to_vec(r).map_err(|e| ManyError::serialization_error(e.to_string()))
+                })
+            }
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
@@ -1,9 +1,25 @@
+mod attachment;
+mod batch;
+mod escrow;
+mod governance;
+mod integrity;
+pub mod memo_index;
 pub mod migration_ext;
+mod name_policy;
+mod receive_policy;
+mod recovery;
+mod snapshot;
+pub(crate) mod stats;
+mod swap;
+mod verify;
 
 use crate::error;
 #[cfg(feature = "migrate_blocks")]
 use crate::migration;
+use crate::migration::multisig::CHECKED_MULTISIG_SENDS_MIGRATION;
+use crate::migration::roles::CHANGED_ROLE_EVENTS_MIGRATION;
 use crate::migration::{run_migrations, Migration};
+use crate::module::governance::Param;
 use crate::module::validate_account;
 use many_error::ManyError;
 use many_identity::Address;
@@ -11,13 +27,14 @@ use many_modules::abci_backend::AbciCommitInfo;
 use many_modules::account::features::FeatureInfo;
 use many_modules::{account, events, idstore, EmptyReturn};
 use many_protocol::ResponseMessage;
+use many_storage::event_schema::{encode_event, event_schema_version};
+use many_storage::migration::active_migrations;
 use many_types::ledger::{Symbol, TokenAmount};
 use many_types::{CborRange, Either, SortOrder, Timestamp};
 use merk::rocksdb::ReadOptions;
 use merk::tree::Tree;
 use merk::{rocksdb, BatchEntry, Op};
 use migration_ext::data::DataExt;
-use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet, Bound};
 use std::ops::RangeBounds;
 use std::path::Path;
@@ -175,11 +192,18 @@ impl MultisigTransactionStorage {
 }
 
 pub const MULTISIG_DEFAULT_THRESHOLD: u64 = 1;
+// Defaults of the `multisig.defaultTimeoutInSecs` and
+// `multisig.defaultExecuteAutomatically` governance parameters.
 pub const MULTISIG_DEFAULT_TIMEOUT_IN_SECS: u64 = 60 * 60 * 24; // A day.
 pub const MULTISIG_DEFAULT_EXECUTE_AUTOMATICALLY: bool = false;
 pub const MULTISIG_MAXIMUM_TIMEOUT_IN_SECS: u64 = 185 * 60 * 60 * 24; // ~6 months.
 
-pub const MIGRATIONS_KEY: &[u8] = b"/config/migrations";
+/// Events cannot be kept for fewer blocks than this, so clients have time to
+/// read them.
+pub const MINIMUM_EVENT_RETENTION_BLOCKS: u64 = 1000;
+
+/// Default of the `validators.quorumMargin` governance parameter.
+pub const VALIDATOR_DEFAULT_QUORUM_MARGIN: u64 = 67;
 
 #[derive(Clone, minicbor::Encode, minicbor::Decode)]
 #[cbor(map)]
@@ -205,6 +229,8 @@ impl IdStoreRootSeparator {
     }
 }
 
+pub(crate) const ACCOUNTS_ROOT: &[u8] = b"/accounts/";
+pub(crate) const BALANCES_ROOT: &[u8] = b"/balances/";
 pub(crate) const EVENTS_ROOT: &[u8] = b"/events/";
 pub(crate) const MULTISIG_TRANSACTIONS_ROOT: &[u8] = b"/multisig/";
 pub(crate) const IDSTORE_ROOT: &[u8] = b"/idstore/";
@@ -219,7 +245,7 @@ const EVENT_ID_KEY_SIZE_IN_BYTES: usize = 32;
 
 /// Returns the key for the persistent kv-store.
 pub(super) fn key_for_account_balance(id: &Address, symbol: &Symbol) -> Vec<u8> {
-    format!("/balances/{}/{}", id, symbol).into_bytes()
+    vec![BALANCES_ROOT, format!("{}/{}", id, symbol).as_bytes()].concat()
 }
 
 /// Returns the storage key for an event in the kv-store.
@@ -237,7 +263,7 @@ pub(super) fn key_for_event(id: events::EventId) -> Vec<u8> {
 }
 
 pub(super) fn key_for_account(id: &Address) -> Vec<u8> {
-    format!("/accounts/{}", id).into_bytes()
+    vec![ACCOUNTS_ROOT, id.to_string().as_bytes()].concat()
 }
 
 /// Returns the storage key for a multisig pending transaction.
@@ -275,6 +301,9 @@ pub struct LedgerStorage {
 
     active_migrations: BTreeSet<String>,
     all_migrations: BTreeSet<Box<dyn Migration>>,
+
+    /// Node-local index of event memos, if enabled.
+    memo_index: Option<memo_index::MemoIndex>,
 }
 
 impl LedgerStorage {
@@ -292,9 +321,10 @@ impl LedgerStorage {
         let key = key_for_account_balance(&account, &symbol);
         let amount = TokenAmount::from(amount);
 
-        self.persistent_store
-            .apply(&[(key, Op::Put(amount.to_vec()))])
-            .unwrap();
+        let mut batch = self.balance_stats_entries([(&account, &symbol, &amount)]);
+        batch.push((key, Op::Put(amount.to_vec())));
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.persistent_store.apply(&batch).unwrap();
 
         // Always commit to the store. In blockchain mode this will fail.
         self.persistent_store.commit(&[]).unwrap();
@@ -320,6 +350,11 @@ impl LedgerStorage {
         self.current_time.unwrap_or_else(Timestamp::now)
     }
 
+    #[inline]
+    pub fn identity(&self) -> Address {
+        self.account_identity
+    }
+
     pub fn load<P: AsRef<Path>>(persistent_path: P, blockchain: bool) -> Result<Self, String> {
         let persistent_store = merk::Merk::open(persistent_path).map_err(|e| e.to_string())?;
 
@@ -354,11 +389,7 @@ impl LedgerStorage {
 
         let latest_tid = events::EventId::from(height << HEIGHT_EVENTID_SHIFT);
 
-        let active_migrations = persistent_store
-            .get(MIGRATIONS_KEY)
-            .expect("Could not open storage.")
-            .map(|x| minicbor::decode(&x).expect("Could not read migrations"))
-            .unwrap_or_default();
+        let active_migrations = active_migrations(&persistent_store);
 
         info!("Active migrations: {:?}", active_migrations);
 
@@ -373,6 +404,7 @@ impl LedgerStorage {
             account_identity,
             active_migrations,
             all_migrations: BTreeSet::new(),
+            memo_index: None,
         })
     }
 
@@ -405,6 +437,7 @@ impl LedgerStorage {
             b"/config/symbols".to_vec(),
             Op::Put(minicbor::to_vec(&symbols).map_err(|e| e.to_string())?),
         ));
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
 
         persistent_store
             .apply(batch.as_slice())
@@ -438,6 +471,7 @@ impl LedgerStorage {
             account_identity: identity,
             active_migrations: BTreeSet::new(),
             all_migrations: BTreeSet::new(),
+            memo_index: None,
         })
     }
 
@@ -476,8 +510,15 @@ impl LedgerStorage {
             })
     }
 
+    /// The ID the next account will get, without allocating it.
+    fn peek_account_id(&self) -> Address {
+        self.account_identity
+            .with_subresource_id(self.next_account_id)
+            .expect("Too many accounts")
+    }
+
     fn new_account_id(&mut self) -> Address {
-        let current_id = self.next_account_id;
+        let id = self.peek_account_id();
         self.next_account_id += 1;
         self.persistent_store
             .apply(&[(
@@ -486,9 +527,7 @@ impl LedgerStorage {
             )])
             .unwrap();
 
-        self.account_identity
-            .with_subresource_id(current_id)
-            .expect("Too many accounts")
+        id
     }
 
     pub(crate) fn inc_idstore_seed(&mut self) -> u64 {
@@ -536,6 +575,7 @@ impl LedgerStorage {
             .iter_opt(IteratorMode::From(&bound, Direction::Reverse), options);
 
         let mut batch = vec![];
+        let mut expired = 0;
 
         for item in it {
             let (k, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
@@ -548,6 +588,7 @@ impl LedgerStorage {
             if now >= storage.info.timeout {
                 if !storage.disabled {
                     storage.disable(account::features::multisig::MultisigTransactionState::Expired);
+                    expired += 1;
 
                     if let Ok(v) = minicbor::to_vec(storage) {
                         batch.push((k.to_vec(), Op::Put(v)));
@@ -563,8 +604,12 @@ impl LedgerStorage {
         }
 
         if !batch.is_empty() {
-            // Reverse the batch so keys are in sorted order.
+            // Reverse the batch so keys are in sorted order. The stats come
+            // after the multisig transactions.
             batch.reverse();
+            if expired > 0 && self.stats_enabled() {
+                batch.push(self.counter_delta_entry(stats::PENDING_MULTISIG_KEY, -expired));
+            }
             self.persistent_store.apply(&batch).unwrap();
         }
 
@@ -580,19 +625,44 @@ impl LedgerStorage {
         // errors.
         let _ = self.check_timed_out_multisig_transactions();
 
+        // Then refund escrows and swaps that timed out. This needs to happen before the
+        // height and hash are updated so every node agrees on the result.
+        match self.check_timed_out_escrows() {
+            Ok(failures) => {
+                for (id, e) in failures {
+                    tracing::error!("Could not refund timed out escrow {}: {}", id, e);
+                }
+            }
+            Err(e) => tracing::error!("Could not refund timed out escrows: {}", e),
+        }
+        match self.check_expired_swaps() {
+            Ok(failures) => {
+                for (id, e) in failures {
+                    tracing::error!("Could not refund expired swap {}: {}", id, e);
+                }
+            }
+            Err(e) => tracing::error!("Could not refund expired swaps: {}", e),
+        }
+
         let height = self.inc_height();
         let retain_height = 0;
 
+        if let Err(e) = self.prune_events(height) {
+            tracing::error!("Could not prune events: {}", e);
+        }
+
         // Committing before the migration so that the migration has
         // the actual state of the database when setting its
         // attributes.
         self.persistent_store.commit(&[]).unwrap();
 
+        // The ledger always recorded its active migrations.
         run_migrations(
             height + 1,
             &self.all_migrations,
             &mut self.active_migrations,
             &mut self.persistent_store,
+            true,
         );
 
         self.persistent_store.commit(&[]).unwrap();
@@ -627,24 +697,59 @@ impl LedgerStorage {
             content,
         };
 
-        self.persistent_store
-            .apply(&[
-                (
-                    key_for_event(event.id.clone()),
-                    Op::Put(minicbor::to_vec(&event).unwrap()),
-                ),
-                (
-                    b"/events_count".to_vec(),
-                    Op::Put((current_nb_events + 1).to_be_bytes().to_vec()),
+        let mut batch = vec![
+            (
+                key_for_event(event.id.clone()),
+                Op::Put(
+                    encode_event(&event, event_schema_version(&self.active_migrations)).unwrap(),
                 ),
-            ])
-            .unwrap();
+            ),
+            (
+                b"/events_count".to_vec(),
+                Op::Put((current_nb_events + 1).to_be_bytes().to_vec()),
+            ),
+        ];
+        if self.stats_enabled() {
+            batch.push(Self::last_event_entry(&event));
+        }
+        self.persistent_store.apply(&batch).unwrap();
+
+        self.index_memo(&event);
 
         if !self.blockchain {
             self.persistent_store.commit(&[]).unwrap();
         }
     }
 
+    /// Delete the events logged before the retention window. The events
+    /// count keeps counting every event ever logged.
+    fn prune_events(&mut self, height: u64) -> Result<(), ManyError> {
+        use rocksdb::IteratorMode;
+
+        let retention = self.param_integer(Param::EventRetentionBlocks)?;
+        if retention == 0 || height <= retention {
+            return Ok(());
+        }
+
+        let mut options = ReadOptions::default();
+        options.set_iterate_lower_bound(EVENTS_ROOT);
+        options.set_iterate_upper_bound(key_for_event(events::EventId::from(
+            (height - retention) << HEIGHT_EVENTID_SHIFT,
+        )));
+
+        let mut batch: Vec<BatchEntry> = vec![];
+        for item in self.persistent_store.iter_opt(IteratorMode::Start, options) {
+            let (k, _) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            batch.push((k.to_vec(), Op::Delete));
+        }
+
+        if !batch.is_empty() {
+            debug!("Pruning {} events", batch.len());
+            self.persistent_store.apply(&batch).unwrap();
+        }
+        Ok(())
+    }
+
     pub fn get_balance(&self, identity: &Address, symbol: &Symbol) -> TokenAmount {
         if identity.is_anonymous() {
             TokenAmount::zero()
@@ -695,16 +800,25 @@ impl LedgerStorage {
         }
     }
 
-    pub fn send(
-        &mut self,
+    /// Checks that a send to its source is allowed, which depends on the
+    /// `send.allowSelf` parameter.
+    pub fn check_self_send(&self, from: &Address, to: &Address) -> Result<(), ManyError> {
+        if from == to && !self.param_bool(Param::SendAllowSelf)? {
+            return Err(error::destination_is_source());
+        }
+        Ok(())
+    }
+
+    /// Checks a send, except for the balance of the sender, and returns the
+    /// fee to pay on top of the amount and its collector, if any.
+    fn check_send(
+        &self,
         from: &Address,
         to: &Address,
         symbol: &Symbol,
-        amount: TokenAmount,
-    ) -> Result<(), ManyError> {
-        if from == to {
-            return Err(error::destination_is_source());
-        }
+        amount: &TokenAmount,
+    ) -> Result<Option<(Address, TokenAmount)>, ManyError> {
+        self.check_self_send(from, to)?;
 
         if amount.is_zero() {
             return Err(error::amount_is_zero());
@@ -714,31 +828,80 @@ impl LedgerStorage {
             return Err(error::anonymous_cannot_hold_funds());
         }
 
-        let mut amount_from = self.get_balance(from, symbol);
-        if amount > amount_from {
+        self.check_receive(from, to, symbol, amount)?;
+
+        // The fee is paid on top of the amount. The collector does not pay it,
+        // and sends to their source do not move funds.
+        let fee = self.param_amount(Param::SendFee)?;
+        let fee_collector = self.param_address(Param::SendFeeCollector)?;
+        if fee.is_zero() || from == &fee_collector || from == to {
+            Ok(None)
+        } else {
+            Ok(Some((fee_collector, fee)))
+        }
+    }
+
+    pub fn send(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        symbol: &Symbol,
+        amount: TokenAmount,
+    ) -> Result<(), ManyError> {
+        let fee = self.check_send(from, to, symbol, &amount)?;
+
+        let total = fee
+            .as_ref()
+            .map_or_else(|| amount.clone(), |(_, fee)| amount.clone() + fee.clone());
+        if total > self.get_balance(from, symbol) {
             return Err(error::insufficient_funds());
         }
 
         info!("send({} => {}, {} {})", from, to, &amount, symbol);
+        if from == to {
+            // Only the event is recorded.
+            self.log_event(events::EventInfo::Send {
+                from: *from,
+                to: *to,
+                symbol: *symbol,
+                amount,
+            });
+        } else {
+            self.transfer(from, to, symbol, amount);
+        }
+        if let Some((fee_collector, fee)) = fee {
+            self.transfer(from, &fee_collector, symbol, fee);
+        }
 
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+
+        Ok(())
+    }
+
+    /// Move funds the sender is known to have, and log the send.
+    fn transfer(&mut self, from: &Address, to: &Address, symbol: &Symbol, amount: TokenAmount) {
+        let mut amount_from = self.get_balance(from, symbol);
         let mut amount_to = self.get_balance(to, symbol);
         amount_to += amount.clone();
         amount_from -= amount.clone();
 
+        let mut batch: Vec<BatchEntry> = vec![
+            (
+                key_for_account_balance(from, symbol),
+                Op::Put(amount_from.to_vec()),
+            ),
+            (
+                key_for_account_balance(to, symbol),
+                Op::Put(amount_to.to_vec()),
+            ),
+        ];
+        batch.extend(
+            self.balance_stats_entries([(from, symbol, &amount_from), (to, symbol, &amount_to)]),
+        );
         // Keys in batch must be sorted.
-        let key_from = key_for_account_balance(from, symbol);
-        let key_to = key_for_account_balance(to, symbol);
-
-        let batch: Vec<BatchEntry> = match key_from.cmp(&key_to) {
-            Ordering::Less | Ordering::Equal => vec![
-                (key_from, Op::Put(amount_from.to_vec())),
-                (key_to, Op::Put(amount_to.to_vec())),
-            ],
-            _ => vec![
-                (key_to, Op::Put(amount_to.to_vec())),
-                (key_from, Op::Put(amount_from.to_vec())),
-            ],
-        };
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
 
         self.update_data_attributes(from, to, amount.clone(), symbol);
 
@@ -750,12 +913,6 @@ impl LedgerStorage {
             symbol: *symbol,
             amount,
         });
-
-        if !self.blockchain {
-            self.persistent_store.commit(&[]).unwrap();
-        }
-
-        Ok(())
     }
 
     pub fn hash(&self) -> Vec<u8> {
@@ -798,19 +955,15 @@ impl LedgerStorage {
                                 // The account can approve but should not be included in the threshold.
                 ),
             );
-            multisig.arg.timeout_in_secs = Some(
-                multisig
-                    .arg
-                    .timeout_in_secs
-                    .map_or(MULTISIG_DEFAULT_TIMEOUT_IN_SECS, |v| {
-                        MULTISIG_MAXIMUM_TIMEOUT_IN_SECS.min(v)
-                    }),
-            );
+            multisig.arg.timeout_in_secs = Some(multisig.arg.timeout_in_secs.map_or(
+                self.param_integer(Param::MultisigDefaultTimeoutInSecs)?,
+                |v| MULTISIG_MAXIMUM_TIMEOUT_IN_SECS.min(v),
+            ));
             multisig.arg.execute_automatically = Some(
                 multisig
                     .arg
                     .execute_automatically
-                    .unwrap_or(MULTISIG_DEFAULT_EXECUTE_AUTOMATICALLY),
+                    .unwrap_or(self.param_bool(Param::MultisigDefaultExecuteAutomatically)?),
             );
 
             account.features.insert(multisig.as_feature());
@@ -913,20 +1066,51 @@ impl LedgerStorage {
         Ok(())
     }
 
+    /// Whether role events only list the roles which changed.
+    fn changed_role_events(&self) -> bool {
+        self.active_migrations
+            .contains(CHANGED_ROLE_EVENTS_MIGRATION)
+    }
+
+    /// Whether multisig transactions sending funds are checked when they are
+    /// submitted. Rejecting them changes the results of the commands, so
+    /// chains start at the activation height of the migration.
+    fn checked_multisig_sends(&self) -> bool {
+        self.active_migrations
+            .contains(CHECKED_MULTISIG_SENDS_MIGRATION)
+    }
+
     pub fn add_roles(
         &mut self,
         mut account: account::Account,
         args: account::AddRolesArgs,
     ) -> Result<(), ManyError> {
+        // Once the migration is active the event only lists the roles which
+        // were added, so the roles of the account can be reconstructed from
+        // its events.
+        let mut added: BTreeMap<Address, BTreeSet<account::Role>> = BTreeMap::new();
         for (id, roles) in &args.roles {
             for r in roles {
-                account.add_role(id, *r);
+                if !account.has_role(id, *r) {
+                    account.add_role(id, *r);
+                    added.entry(*id).or_default().insert(*r);
+                }
             }
         }
 
+        // New owners of an account with recovery can veto claims too.
+        if self.has_recovery(&args.account)? {
+            self.watch_owners(&args.account, &account)?;
+        }
+
+        let roles = if self.changed_role_events() {
+            added
+        } else {
+            args.roles
+        };
         self.log_event(events::EventInfo::AccountAddRoles {
             account: args.account,
-            roles: args.clone().roles,
+            roles,
         });
         self.commit_account(&args.account, account)?;
         Ok(())
@@ -948,15 +1132,24 @@ impl LedgerStorage {
             return Err(account::errors::account_must_own_itself());
         }
 
+        let mut removed: BTreeMap<Address, BTreeSet<account::Role>> = BTreeMap::new();
         for (id, roles) in &args.roles {
             for r in roles {
-                account.remove_role(id, *r);
+                if account.has_role(id, *r) {
+                    account.remove_role(id, *r);
+                    removed.entry(*id).or_default().insert(*r);
+                }
             }
         }
 
+        let roles = if self.changed_role_events() {
+            removed
+        } else {
+            args.roles
+        };
         self.log_event(events::EventInfo::AccountRemoveRoles {
             account: args.account,
-            roles: args.clone().roles,
+            roles,
         });
         self.commit_account(&args.account, account)?;
         Ok(())
@@ -972,20 +1165,33 @@ impl LedgerStorage {
                 return Err(ManyError::unknown("Feature already part of the account."));
             }
         }
+        let mut added: BTreeMap<Address, BTreeSet<account::Role>> = BTreeMap::new();
         if let Some(ref r) = args.roles {
             for (id, new_r) in r {
                 for role in new_r {
-                    account.roles.entry(*id).or_default().insert(*role);
+                    if account.roles.entry(*id).or_default().insert(*role) {
+                        added.entry(*id).or_default().insert(*role);
+                    }
                 }
             }
         }
 
         validate_account(&account)?;
+        if self.has_recovery(&args.account)? {
+            self.watch_owners(&args.account, &account)?;
+        }
 
+        // Once the migration is active the event only lists the roles which
+        // were added, as for `add_roles`.
+        let roles = if self.changed_role_events() {
+            added
+        } else {
+            args.roles.unwrap_or_default()
+        };
         self.log_event(events::EventInfo::AccountAddFeatures {
             account: args.account,
-            roles: args.clone().roles.unwrap_or_default(), // TODO: Verify this
-            features: args.clone().features,
+            roles,
+            features: args.features,
         });
         self.commit_account(&args.account, account)?;
         Ok(())
@@ -1020,14 +1226,24 @@ impl LedgerStorage {
     ) -> Result<(), ManyError> {
         tracing::debug!("commit({:?})", account);
 
+        let key = key_for_account(id);
+        let is_new = self
+            .persistent_store
+            .get(&key)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .is_none();
+        let mut batch = vec![(
+            key,
+            Op::Put(
+                minicbor::to_vec(account)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        )];
+        if is_new {
+            batch.extend(self.account_stats_entry());
+        }
         self.persistent_store
-            .apply(&[(
-                key_for_account(id),
-                Op::Put(
-                    minicbor::to_vec(account)
-                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
-                ),
-            )])
+            .apply(&batch)
             .map_err(|e| ManyError::unknown(e.to_string()))?;
 
         if !self.blockchain {
@@ -1044,15 +1260,14 @@ impl LedgerStorage {
         tx: &MultisigTransactionStorage,
     ) -> Result<(), ManyError> {
         debug!("{:?}", tx);
-        self.persistent_store
-            .apply(&[(
-                key_for_multisig_transaction(tx_id),
-                Op::Put(
-                    minicbor::to_vec(tx)
-                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
-                ),
-            )])
-            .unwrap();
+        let mut batch = vec![(
+            key_for_multisig_transaction(tx_id),
+            Op::Put(
+                minicbor::to_vec(tx).map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        )];
+        batch.extend(self.multisig_stats_entry(tx_id, !tx.disabled));
+        self.persistent_store.apply(&batch).unwrap();
 
         if !self.blockchain {
             self.persistent_store
@@ -1080,6 +1295,22 @@ impl LedgerStorage {
             [account::Role::CanMultisigSubmit, account::Role::Owner],
         )?;
 
+        // A send to its source, or refused by its recipient, would fail at
+        // execution.
+        if let events::AccountMultisigTransaction::Send(many_modules::ledger::SendArgs {
+            from,
+            to,
+            symbol,
+            amount,
+        }) = arg.transaction.as_ref()
+        {
+            let from = from.unwrap_or(account_id);
+            if self.checked_multisig_sends() {
+                self.check_self_send(&from, to)?;
+                self.check_receive(&from, to, symbol, amount)?;
+            }
+        }
+
         let multisig_f = account
             .features
             .get::<account::features::multisig::MultisigAccountFeature>()?;
@@ -1098,7 +1329,7 @@ impl LedgerStorage {
             _ => multisig_f
                 .arg
                 .timeout_in_secs
-                .unwrap_or(MULTISIG_DEFAULT_TIMEOUT_IN_SECS),
+                .unwrap_or(self.param_integer(Param::MultisigDefaultTimeoutInSecs)?),
         }
         .min(MULTISIG_MAXIMUM_TIMEOUT_IN_SECS);
         let execute_automatically = match arg.execute_automatically {
@@ -1107,7 +1338,7 @@ impl LedgerStorage {
             _ => multisig_f
                 .arg
                 .execute_automatically
-                .unwrap_or(MULTISIG_DEFAULT_EXECUTE_AUTOMATICALLY),
+                .unwrap_or(self.param_bool(Param::MultisigDefaultExecuteAutomatically)?),
         };
         let time = self.now();
 
@@ -1315,9 +1546,9 @@ impl LedgerStorage {
         let v =
             minicbor::to_vec(storage).map_err(|e| ManyError::serialization_error(e.to_string()))?;
 
-        self.persistent_store
-            .apply(&[(key_for_multisig_transaction(tx_id), Op::Put(v))])
-            .unwrap();
+        let mut batch = vec![(key_for_multisig_transaction(tx_id), Op::Put(v))];
+        batch.extend(self.multisig_stats_entry(tx_id, false));
+        self.persistent_store.apply(&batch).unwrap();
         if !self.blockchain {
             self.persistent_store
                 .commit(&[])
@@ -0,0 +1,90 @@
+use crate::module::attachment::Attachment;
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_modules::events;
+use many_storage::event_schema::decode_event;
+use many_types::{CborRange, SortOrder};
+use merk::Op;
+use std::ops::Bound;
+
+pub(crate) const EVENT_ATTACHMENTS_ROOT: &[u8] = b"/attachments/events/";
+pub(crate) const MULTISIG_ATTACHMENTS_ROOT: &[u8] = b"/attachments/multisig/";
+
+/// Returns the storage key for the attachment of an event.
+fn key_for_event_attachment(id: &events::EventId) -> Vec<u8> {
+    vec![EVENT_ATTACHMENTS_ROOT, id.as_ref()].concat()
+}
+
+/// Returns the storage key for the attachment of a multisig transaction.
+fn key_for_multisig_attachment(token: &[u8]) -> Vec<u8> {
+    vec![MULTISIG_ATTACHMENTS_ROOT, token].concat()
+}
+
+impl LedgerStorage {
+    pub fn latest_event_id(&self) -> events::EventId {
+        self.latest_tid.clone()
+    }
+
+    fn get_attachment(&self, key: &[u8]) -> Result<Option<Attachment>, ManyError> {
+        self.persistent_store
+            .get(key)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map(|bytes| {
+                minicbor::decode(&bytes)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+            .transpose()
+    }
+
+    pub fn get_event_attachment(
+        &self,
+        id: &events::EventId,
+    ) -> Result<Option<Attachment>, ManyError> {
+        self.get_attachment(&key_for_event_attachment(id))
+    }
+
+    pub fn get_multisig_attachment(&self, token: &[u8]) -> Result<Option<Attachment>, ManyError> {
+        self.get_attachment(&key_for_multisig_attachment(token))
+    }
+
+    /// Record an attachment on the transfers and multisig submissions logged
+    /// after an event. A multisig submission also records it on its token.
+    pub fn attach(
+        &mut self,
+        since: &events::EventId,
+        attachment: &Attachment,
+    ) -> Result<(), ManyError> {
+        let value = minicbor::to_vec(attachment)
+            .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+
+        let mut batch = vec![];
+        let range = CborRange {
+            start: Bound::Excluded(since.clone()),
+            end: Bound::Unbounded,
+        };
+        for item in self.iter(range, SortOrder::Ascending) {
+            let (_, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            let event = decode_event(&v)?;
+            match &event.content {
+                events::EventInfo::Send { .. } => {}
+                events::EventInfo::AccountMultisigSubmit {
+                    token: Some(token), ..
+                } => batch.push((key_for_multisig_attachment(token), Op::Put(value.clone()))),
+                _ => continue,
+            }
+            batch.push((key_for_event_attachment(&event.id), Op::Put(value.clone())));
+        }
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        self.persistent_store
+            .apply(&batch)
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store
+                .commit(&[])
+                .expect("Could not commit to store.");
+        }
+        Ok(())
+    }
+}
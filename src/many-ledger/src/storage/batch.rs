@@ -0,0 +1,66 @@
+use crate::error;
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_identity::Address;
+use many_types::ledger::{Symbol, TokenAmount};
+use std::collections::BTreeMap;
+
+impl LedgerStorage {
+    /// Checks that every send would succeed if they were executed in order,
+    /// without changing anything. Returns the index of the first send which
+    /// would fail and its error.
+    pub fn check_sends<'a>(
+        &self,
+        sends: impl IntoIterator<Item = (&'a Address, &'a Address, &'a Symbol, &'a TokenAmount)>,
+    ) -> Result<(), (usize, ManyError)> {
+        // Balances changed by the previous sends.
+        let mut balances: BTreeMap<(Address, Symbol), TokenAmount> = BTreeMap::new();
+        let balance = |balances: &mut BTreeMap<(Address, Symbol), TokenAmount>,
+                       id: &Address,
+                       symbol: &Symbol| {
+            balances
+                .entry((*id, *symbol))
+                .or_insert_with(|| self.get_balance(id, symbol))
+                .clone()
+        };
+
+        for (i, (from, to, symbol, amount)) in sends.into_iter().enumerate() {
+            let fee = self
+                .check_send(from, to, symbol, amount)
+                .map_err(|e| (i, e))?;
+
+            let total = fee
+                .as_ref()
+                .map_or_else(|| amount.clone(), |(_, fee)| amount.clone() + fee.clone());
+            let mut from_balance = balance(&mut balances, from, symbol);
+            if total > from_balance {
+                return Err((i, error::insufficient_funds()));
+            }
+            from_balance -= total;
+            balances.insert((*from, *symbol), from_balance);
+
+            let to_balance = balance(&mut balances, to, symbol) + amount.clone();
+            balances.insert((*to, *symbol), to_balance);
+
+            if let Some((fee_collector, fee)) = fee {
+                let collector_balance = balance(&mut balances, &fee_collector, symbol) + fee;
+                balances.insert((fee_collector, *symbol), collector_balance);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `f` and commits its changes to the persistent store once, instead
+    /// of after every change. In blockchain mode, changes are committed with
+    /// the block anyway.
+    pub fn with_single_commit<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let blockchain = std::mem::replace(&mut self.blockchain, true);
+        let result = f(self);
+        self.blockchain = blockchain;
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        result
+    }
+}
@@ -0,0 +1,277 @@
+use crate::error;
+use crate::module::escrow::{EscrowInfo, EscrowState};
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_identity::Address;
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::Timestamp;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::Op;
+use std::collections::BTreeMap;
+use tracing::info;
+
+pub(crate) const ESCROWS_ROOT: &[u8] = b"/escrow/";
+pub(crate) const ESCROWED_ROOT: &[u8] = b"/escrowed/";
+pub(crate) const ESCROW_TIMEOUTS_ROOT: &[u8] = b"/escrow_timeouts/";
+
+/// Returns the storage key for an escrow.
+pub(super) fn key_for_escrow(id: &Address) -> Vec<u8> {
+    vec![ESCROWS_ROOT, id.to_string().as_bytes()].concat()
+}
+
+/// Returns the storage key for the total amount a payer has locked in
+/// pending escrows for a symbol.
+pub(super) fn key_for_escrowed(payer: &Address, symbol: &Symbol) -> Vec<u8> {
+    vec![ESCROWED_ROOT, format!("{}/{}", payer, symbol).as_bytes()].concat()
+}
+
+/// Returns the storage key of an escrow in the timeout index. The timeout
+/// is stored big endian so the index is ordered by time.
+pub(super) fn key_for_escrow_timeout(timeout: u64, id: &Address) -> Vec<u8> {
+    vec![
+        ESCROW_TIMEOUTS_ROOT.to_vec(),
+        timeout.to_be_bytes().to_vec(),
+        id.to_vec(),
+    ]
+    .concat()
+}
+
+pub(super) fn timestamp_secs(t: Timestamp) -> Result<u64, ManyError> {
+    Ok(t.as_system_time()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| ManyError::unknown(e.to_string()))?
+        .as_secs())
+}
+
+impl LedgerStorage {
+    pub fn get_escrow(&self, id: &Address) -> Result<EscrowInfo, ManyError> {
+        let bytes = self
+            .persistent_store
+            .get(&key_for_escrow(id))
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .ok_or_else(|| error::unknown_escrow(*id))?;
+        minicbor::decode(&bytes).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    /// Returns the amounts locked by this payer in pending escrows.
+    pub fn get_escrowed(&self, payer: &Address) -> BTreeMap<Symbol, TokenAmount> {
+        let mut result = BTreeMap::new();
+        for symbol in self.symbols.keys() {
+            if let Ok(Some(value)) = self.persistent_store.get(&key_for_escrowed(payer, symbol)) {
+                result.insert(*symbol, TokenAmount::from(value));
+            }
+        }
+        result
+    }
+
+    fn update_escrowed(&mut self, payer: &Address, symbol: &Symbol, amount: TokenAmount) {
+        let key = key_for_escrowed(payer, symbol);
+        let op = if amount.is_zero() {
+            Op::Delete
+        } else {
+            Op::Put(amount.to_vec())
+        };
+        self.persistent_store.apply(&[(key, op)]).unwrap();
+    }
+
+    fn commit_escrow(&mut self, id: &Address, info: &EscrowInfo) -> Result<(), ManyError> {
+        self.persistent_store
+            .apply(&[(
+                key_for_escrow(id),
+                Op::Put(
+                    minicbor::to_vec(info)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            )])
+            .unwrap();
+        Ok(())
+    }
+
+    /// Lock funds from the payer into a new escrow. The escrow is held by a
+    /// new subresource of the ledger identity, which has no account and
+    /// cannot be spent from by anyone but the ledger itself.
+    pub fn create_escrow(
+        &mut self,
+        payer: &Address,
+        payee: &Address,
+        arbiter: Option<Address>,
+        symbol: &Symbol,
+        amount: TokenAmount,
+        timeout_in_secs: u64,
+    ) -> Result<Address, ManyError> {
+        // Validate everything before allocating an ID, so that a failed
+        // creation does not change the state.
+        if timeout_in_secs == 0 {
+            return Err(error::invalid_escrow_timeout());
+        }
+        if payer == payee {
+            return Err(error::destination_is_source());
+        }
+        if payee.is_anonymous() {
+            return Err(error::anonymous_cannot_hold_funds());
+        }
+        if !self.symbols.contains_key(symbol) {
+            return Err(error::unknown_symbol(*symbol));
+        }
+        // The same checks as the send to the escrow, including its fee.
+        let fee = self.check_send(payer, &self.peek_account_id(), symbol, &amount)?;
+        let total = fee
+            .as_ref()
+            .map_or_else(|| amount.clone(), |(_, fee)| amount.clone() + fee.clone());
+        if total > self.get_balance(payer, symbol) {
+            return Err(error::insufficient_funds());
+        }
+
+        let timeout = Timestamp::from_system_time(
+            self.now()
+                .as_system_time()?
+                .checked_add(std::time::Duration::from_secs(timeout_in_secs))
+                .ok_or_else(|| ManyError::unknown("Invalid time.".to_string()))?,
+        )?;
+
+        let id = self.new_account_id();
+        self.send(payer, &id, symbol, amount.clone())?;
+
+        let mut escrowed = self.get_escrowed(payer).remove(symbol).unwrap_or_default();
+        escrowed += amount.clone();
+        self.update_escrowed(payer, symbol, escrowed);
+
+        self.commit_escrow(
+            &id,
+            &EscrowInfo {
+                payer: *payer,
+                payee: *payee,
+                arbiter,
+                symbol: *symbol,
+                amount,
+                timeout,
+                state: EscrowState::Pending,
+            },
+        )?;
+        self.persistent_store
+            .apply(&[(
+                key_for_escrow_timeout(timestamp_secs(timeout)?, &id),
+                Op::Put(vec![]),
+            )])
+            .unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+
+        Ok(id)
+    }
+
+    /// Release the escrowed funds to the payee. Only the payer or the arbiter
+    /// can release, and only before the timeout.
+    pub fn release_escrow(&mut self, sender: &Address, id: &Address) -> Result<(), ManyError> {
+        let info = self.get_escrow(id)?;
+        if info.state != EscrowState::Pending {
+            return Err(error::escrow_already_settled());
+        }
+        if *sender != info.payer && Some(*sender) != info.arbiter {
+            return Err(error::unauthorized());
+        }
+        if self.now() >= info.timeout {
+            return Err(error::escrow_expired());
+        }
+
+        self.settle_escrow(id, info, EscrowState::Released)
+    }
+
+    /// Return the escrowed funds to the payer. The arbiter can refund at any
+    /// time, the payer only once the escrow timed out.
+    pub fn refund_escrow(&mut self, sender: &Address, id: &Address) -> Result<(), ManyError> {
+        let info = self.get_escrow(id)?;
+        if info.state != EscrowState::Pending {
+            return Err(error::escrow_already_settled());
+        }
+        if Some(*sender) != info.arbiter {
+            if *sender != info.payer {
+                return Err(error::unauthorized());
+            }
+            if self.now() < info.timeout {
+                return Err(error::escrow_not_expired());
+            }
+        }
+
+        self.settle_escrow(id, info, EscrowState::Refunded)
+    }
+
+    fn settle_escrow(
+        &mut self,
+        id: &Address,
+        mut info: EscrowInfo,
+        state: EscrowState,
+    ) -> Result<(), ManyError> {
+        let to = match state {
+            EscrowState::Released => info.payee,
+            _ => info.payer,
+        };
+        info!("settle_escrow({}, {:?} => {})", id, state, to);
+        // Nothing can fail once the funds moved.
+        let timeout_key = key_for_escrow_timeout(timestamp_secs(info.timeout)?, id);
+
+        // The escrow holds the funds, so they move without the fee and the
+        // checks of a send, which could leave them stuck.
+        self.transfer(id, &to, &info.symbol, info.amount.clone());
+
+        let mut escrowed = self
+            .get_escrowed(&info.payer)
+            .remove(&info.symbol)
+            .unwrap_or_default();
+        escrowed -= info.amount.clone();
+        self.update_escrowed(&info.payer, &info.symbol, escrowed);
+
+        self.persistent_store
+            .apply(&[(timeout_key, Op::Delete)])
+            .unwrap();
+
+        info.state = state;
+        self.commit_escrow(id, &info)?;
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Refund every pending escrow whose timeout is at or before the current
+    /// block time. This runs at commit so the outcome is the same on every
+    /// node; a release in the same block as the timeout already failed.
+    ///
+    /// Each escrow is refunded on its own, and those which could not be are
+    /// returned with their error. They stay pending and are tried again at
+    /// the next commit.
+    pub fn check_timed_out_escrows(&mut self) -> Result<BTreeMap<Address, ManyError>, ManyError> {
+        let mut options = ReadOptions::default();
+        options.set_iterate_lower_bound(ESCROW_TIMEOUTS_ROOT);
+        options.set_iterate_upper_bound(
+            vec![
+                ESCROW_TIMEOUTS_ROOT.to_vec(),
+                (timestamp_secs(self.now())? + 1).to_be_bytes().to_vec(),
+            ]
+            .concat(),
+        );
+
+        let mut expired = vec![];
+        for item in self.persistent_store.iter_opt(IteratorMode::Start, options) {
+            let (k, _) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            let id = Address::from_bytes(&k[ESCROW_TIMEOUTS_ROOT.len() + 8..])?;
+            expired.push(id);
+        }
+
+        let mut failures = BTreeMap::new();
+        for id in expired {
+            let result = self.get_escrow(&id).and_then(|info| match info.state {
+                EscrowState::Pending => self.settle_escrow(&id, info, EscrowState::Refunded),
+                _ => Ok(()),
+            });
+            if let Err(e) = result {
+                failures.insert(id, e);
+            }
+        }
+
+        Ok(failures)
+    }
+}
@@ -0,0 +1,244 @@
+use crate::error;
+use crate::module::governance::{Param, ParamChange, ParamInfo, ParamValue};
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::account;
+use many_types::ledger::TokenAmount;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::Tree;
+use merk::{BatchEntry, Op};
+use std::collections::BTreeMap;
+use tracing::info;
+
+pub(crate) const PARAMS_ROOT: &[u8] = b"/config/params/";
+pub(crate) const PARAM_CHANGES_ROOT: &[u8] = b"/config/param_changes/";
+const PARAM_CHANGES_COUNT_KEY: &[u8] = b"/config/param_changes_count";
+
+fn key_for_param(param: Param) -> Vec<u8> {
+    vec![PARAMS_ROOT, param.name().as_bytes()].concat()
+}
+
+fn key_for_param_change(index: u64) -> Vec<u8> {
+    vec![PARAM_CHANGES_ROOT, &index.to_be_bytes()].concat()
+}
+
+fn invalid_type(param: Param) -> ManyError {
+    ManyError::unknown(format!(
+        "Parameter '{}' has a value of the wrong type.",
+        param.name()
+    ))
+}
+
+impl LedgerStorage {
+    fn get_param_if_set(&self, param: Param) -> Result<Option<ParamValue>, ManyError> {
+        self.persistent_store
+            .get(&key_for_param(param))
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map(|bytes| {
+                minicbor::decode(&bytes)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+            .transpose()
+    }
+
+    /// Returns the value of a parameter, or its default if it was never set.
+    pub fn get_param(&self, param: Param) -> Result<ParamValue, ManyError> {
+        Ok(self
+            .get_param_if_set(param)?
+            .unwrap_or_else(|| param.default_value(&self.account_identity)))
+    }
+
+    pub fn get_params(&self) -> Result<BTreeMap<String, ParamInfo>, ManyError> {
+        Param::ALL
+            .into_iter()
+            .map(|param| {
+                let set = self.get_param_if_set(param)?;
+                let info = ParamInfo {
+                    param_type: param.param_type(),
+                    is_default: set.is_none(),
+                    value: set.unwrap_or_else(|| param.default_value(&self.account_identity)),
+                };
+                Ok((param.name().to_string(), info))
+            })
+            .collect()
+    }
+
+    pub fn param_bool(&self, param: Param) -> Result<bool, ManyError> {
+        match self.get_param(param)? {
+            ParamValue::Bool(b) => Ok(b),
+            _ => Err(invalid_type(param)),
+        }
+    }
+
+    pub fn param_integer(&self, param: Param) -> Result<u64, ManyError> {
+        match self.get_param(param)? {
+            ParamValue::Integer(n) => Ok(n),
+            _ => Err(invalid_type(param)),
+        }
+    }
+
+    pub fn param_amount(&self, param: Param) -> Result<TokenAmount, ManyError> {
+        match self.get_param(param)? {
+            ParamValue::Amount(amount) => Ok(amount),
+            _ => Err(invalid_type(param)),
+        }
+    }
+
+    pub fn param_address(&self, param: Param) -> Result<Address, ManyError> {
+        match self.get_param(param)? {
+            ParamValue::Address(address) => Ok(address),
+            _ => Err(invalid_type(param)),
+        }
+    }
+
+    pub fn param_integer_map(&self, param: Param) -> Result<BTreeMap<String, u64>, ManyError> {
+        match self.get_param(param)? {
+            ParamValue::IntegerMap(map) => Ok(map),
+            _ => Err(invalid_type(param)),
+        }
+    }
+
+    /// Check that the sender can change parameters. This is the governance
+    /// address, or any owner of it if it is an account.
+    fn check_governance(&self, sender: &Address) -> Result<(), ManyError> {
+        let governance = self.param_address(Param::Governance)?;
+        if sender == &governance {
+            return Ok(());
+        }
+        match self.get_account(&governance) {
+            Some(account) if account.has_role(sender, account::Role::Owner) => Ok(()),
+            _ => Err(error::unauthorized()),
+        }
+    }
+
+    fn validate_param(&self, param: Param, value: &ParamValue) -> Result<(), ManyError> {
+        param.validate(value)?;
+
+        // Handing governance to an account that does not exist would lock
+        // everyone out.
+        if let (Param::Governance, ParamValue::Address(address)) = (param, value) {
+            if address.is_subresource() && self.get_account(address).is_none() {
+                return Err(error::invalid_parameter_value(
+                    param.name(),
+                    format!("unknown account {address}"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn nb_param_changes(&self) -> u64 {
+        self.persistent_store
+            .get(PARAM_CHANGES_COUNT_KEY)
+            .unwrap()
+            .map_or(0, |x| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(x.as_slice());
+                u64::from_be_bytes(bytes)
+            })
+    }
+
+    /// Set a parameter and record the change. Only the governance address can
+    /// do this.
+    pub fn set_param(
+        &mut self,
+        sender: &Address,
+        param: Param,
+        value: ParamValue,
+    ) -> Result<(), ManyError> {
+        self.check_governance(sender)?;
+        self.validate_param(param, &value)?;
+        info!("set_param({}, {:?})", param.name(), value);
+
+        let index = self.nb_param_changes();
+        let change = ParamChange {
+            name: param.name().to_string(),
+            old_value: self.get_param(param)?,
+            new_value: value.clone(),
+            changed_by: *sender,
+            time: self.now(),
+            height: self.get_height(),
+        };
+
+        let mut batch: Vec<BatchEntry> = vec![
+            (
+                key_for_param(param),
+                Op::Put(
+                    minicbor::to_vec(&value)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            ),
+            (
+                key_for_param_change(index),
+                Op::Put(
+                    minicbor::to_vec(&change)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            ),
+            (
+                PARAM_CHANGES_COUNT_KEY.to_vec(),
+                Op::Put((index + 1).to_be_bytes().to_vec()),
+            ),
+        ];
+        // Keys in batch must be sorted.
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.persistent_store.apply(&batch).unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Set a parameter without checking the sender or recording a change,
+    /// e.g. from the initial state.
+    pub(crate) fn init_param(&mut self, param: Param, value: ParamValue) -> Result<(), ManyError> {
+        self.validate_param(param, &value)?;
+        info!("init_param({}, {:?})", param.name(), value);
+
+        self.persistent_store
+            .apply(&[(
+                key_for_param(param),
+                Op::Put(
+                    minicbor::to_vec(&value)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            )])
+            .unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Returns the last parameter changes, latest first, optionally only of a
+    /// single parameter.
+    pub fn get_param_changes(
+        &self,
+        param: Option<Param>,
+        count: usize,
+    ) -> Result<Vec<ParamChange>, ManyError> {
+        let mut options = ReadOptions::default();
+        options.set_iterate_lower_bound(PARAM_CHANGES_ROOT);
+        let mut bound = PARAM_CHANGES_ROOT.to_vec();
+        bound[PARAM_CHANGES_ROOT.len() - 1] += 1;
+        options.set_iterate_upper_bound(bound);
+
+        let mut changes = vec![];
+        for item in self.persistent_store.iter_opt(IteratorMode::End, options) {
+            if changes.len() >= count {
+                break;
+            }
+            let (k, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            let v = Tree::decode(k.to_vec(), v.as_ref());
+            let change: ParamChange = minicbor::decode(v.value())
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+            if param.map_or(true, |p| p.name() == change.name) {
+                changes.push(change);
+            }
+        }
+        Ok(changes)
+    }
+}
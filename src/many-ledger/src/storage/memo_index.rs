@@ -0,0 +1,225 @@
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_modules::events::{self, EventId};
+use many_storage::event_schema::decode_event;
+use many_types::{CborRange, SortOrder};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use tracing::info;
+
+/// Tokens longer than this are truncated, so a single memo cannot use much
+/// of the index.
+const MAX_TOKEN_LEN: usize = 64;
+
+/// Split a text into the normalized tokens it is indexed and searched by:
+/// lowercase runs of alphanumeric characters.
+pub fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase().chars().take(MAX_TOKEN_LEN).collect())
+        .collect()
+}
+
+/// Returns the memo text of an event, if it has one.
+fn event_memo(info: &events::EventInfo) -> Option<String> {
+    match info {
+        events::EventInfo::AccountMultisigSubmit {
+            memo: Some(memo), ..
+        } => Some(memo.iter_str().cloned().collect::<Vec<_>>().join(" ")),
+        _ => None,
+    }
+}
+
+/// A node-local inverted index of event memos. It is kept in memory, outside
+/// of the persistent store, so it is not part of the consensus state. When
+/// it holds more than `max_entries` tokens, the oldest events are dropped.
+#[derive(Debug)]
+pub struct MemoIndex {
+    postings: BTreeMap<String, BTreeSet<EventId>>,
+    events: BTreeMap<EventId, BTreeSet<String>>,
+    entries: usize,
+    max_entries: usize,
+}
+
+impl MemoIndex {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            postings: BTreeMap::new(),
+            events: BTreeMap::new(),
+            entries: 0,
+            max_entries,
+        }
+    }
+
+    pub fn insert(&mut self, id: EventId, text: &str) {
+        let tokens = tokenize(text);
+        if tokens.is_empty() {
+            return;
+        }
+
+        for token in &tokens {
+            self.postings
+                .entry(token.clone())
+                .or_default()
+                .insert(id.clone());
+        }
+        self.entries += tokens.len();
+        self.events.insert(id, tokens);
+
+        while self.entries > self.max_entries {
+            self.evict_oldest();
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let (id, tokens) = match self.events.pop_first() {
+            Some(x) => x,
+            None => return,
+        };
+        for token in tokens {
+            if let Some(ids) = self.postings.get_mut(&token) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+            self.entries -= 1;
+        }
+    }
+
+    /// The oldest event still in the index. Older events may match but are
+    /// not returned.
+    pub fn oldest(&self) -> Option<EventId> {
+        self.events.keys().next().cloned()
+    }
+
+    /// Returns the events whose memo contains every token of the query, most
+    /// recent first, starting before `before` if given, and whether there
+    /// are more.
+    pub fn search(
+        &self,
+        query: &str,
+        before: Option<EventId>,
+        count: usize,
+    ) -> (Vec<EventId>, bool) {
+        let tokens = tokenize(query);
+        let mut postings = Vec::with_capacity(tokens.len());
+        for token in &tokens {
+            match self.postings.get(token) {
+                Some(ids) => postings.push(ids),
+                None => return (vec![], false),
+            }
+        }
+        // Walk the rarest token, and check the others.
+        postings.sort_by_key(|ids| ids.len());
+        let (rarest, others) = match postings.split_first() {
+            Some(x) => x,
+            None => return (vec![], false),
+        };
+
+        let mut matches = rarest
+            .iter()
+            .rev()
+            .filter(|id| before.as_ref().map_or(true, |b| *id < b))
+            .filter(|id| others.iter().all(|ids| ids.contains(*id)));
+
+        let result: Vec<EventId> = matches.by_ref().take(count).cloned().collect();
+        let more = matches.next().is_some();
+        (result, more)
+    }
+}
+
+impl LedgerStorage {
+    /// Enable the memo index, building it from the events already logged.
+    pub fn with_memo_index(mut self, max_entries: usize) -> Result<Self, ManyError> {
+        let mut index = MemoIndex::new(max_entries);
+        let mut count = 0;
+        let range = CborRange {
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+        };
+        for item in self.iter(range, SortOrder::Ascending) {
+            let (_, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            let event = decode_event(&v)?;
+            if let Some(text) = event_memo(&event.content) {
+                index.insert(event.id, &text);
+                count += 1;
+            }
+        }
+        info!("Memo index built from {} events", count);
+
+        self.memo_index = Some(index);
+        Ok(self)
+    }
+
+    pub fn memo_index(&self) -> Option<&MemoIndex> {
+        self.memo_index.as_ref()
+    }
+
+    /// Add a new event to the memo index, if enabled.
+    pub(super) fn index_memo(&mut self, event: &events::EventLog) {
+        if let Some(index) = &mut self.memo_index {
+            if let Some(text) = event_memo(&event.content) {
+                index.insert(event.id.clone(), &text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<String> {
+        tokenize(text).into_iter().collect()
+    }
+
+    #[test]
+    fn tokenization() {
+        assert_eq!(tokens("Invoice #4217, RENT"), ["4217", "invoice", "rent"]);
+        assert_eq!(tokens("rent rent  Rent"), ["rent"]);
+        assert_eq!(tokens("  --  "), Vec::<String>::new());
+        assert_eq!(tokens("café/Ünïcode"), ["café", "ünïcode"]);
+        assert_eq!(tokens(&"a".repeat(100)), ["a".repeat(MAX_TOKEN_LEN)]);
+    }
+
+    #[test]
+    fn search() {
+        let mut index = MemoIndex::new(100);
+        index.insert(EventId::from(1), "Invoice 4217");
+        index.insert(EventId::from(2), "Invoice 4218");
+        index.insert(EventId::from(3), "invoice 4217 (second payment)");
+
+        let (ids, more) = index.search("INVOICE 4217", None, 10);
+        assert_eq!(ids, vec![EventId::from(3), EventId::from(1)]);
+        assert!(!more);
+
+        // Pagination.
+        let (ids, more) = index.search("invoice", None, 2);
+        assert_eq!(ids, vec![EventId::from(3), EventId::from(2)]);
+        assert!(more);
+        let (ids, more) = index.search("invoice", Some(EventId::from(2)), 2);
+        assert_eq!(ids, vec![EventId::from(1)]);
+        assert!(!more);
+
+        assert_eq!(index.search("4219", None, 10), (vec![], false));
+        assert_eq!(index.search("", None, 10), (vec![], false));
+    }
+
+    #[test]
+    fn bounded() {
+        let mut index = MemoIndex::new(4);
+        index.insert(EventId::from(1), "a b");
+        index.insert(EventId::from(2), "a c");
+        assert_eq!(index.oldest(), Some(EventId::from(1)));
+
+        index.insert(EventId::from(3), "a d");
+        assert_eq!(index.oldest(), Some(EventId::from(2)));
+        assert_eq!(index.search("b", None, 10), (vec![], false));
+        assert_eq!(
+            index.search("a", None, 10).0,
+            vec![EventId::from(3), EventId::from(2)]
+        );
+        assert_eq!(index.entries, 4);
+    }
+}
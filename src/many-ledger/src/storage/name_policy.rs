@@ -0,0 +1,84 @@
+use crate::error;
+use crate::module::name_policy::SymbolNamePolicy;
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_identity::Address;
+use many_types::ledger::Symbol;
+use merk::Op;
+use sha3::{Digest, Sha3_256};
+use tracing::info;
+
+pub(crate) const SYMBOL_NAME_POLICY_KEY: &[u8] = b"/config/symbol_name_policy";
+
+impl LedgerStorage {
+    /// Returns the symbol name policy, or the default policy if none was set.
+    pub fn get_symbol_name_policy(&self) -> Result<SymbolNamePolicy, ManyError> {
+        match self.persistent_store.get(SYMBOL_NAME_POLICY_KEY).unwrap() {
+            Some(bytes) => minicbor::decode(&bytes)
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            None => Ok(SymbolNamePolicy::default()),
+        }
+    }
+
+    /// Set the symbol name policy. Only the ledger identity can do this.
+    pub fn set_symbol_name_policy(
+        &mut self,
+        sender: &Address,
+        policy: SymbolNamePolicy,
+    ) -> Result<(), ManyError> {
+        if sender != &self.account_identity {
+            return Err(error::unauthorized());
+        }
+        self.init_symbol_name_policy(policy)
+    }
+
+    /// Set the symbol name policy without checking the sender, e.g. from the
+    /// initial state.
+    pub(crate) fn init_symbol_name_policy(
+        &mut self,
+        policy: SymbolNamePolicy,
+    ) -> Result<(), ManyError> {
+        policy.validate()?;
+        info!("set_symbol_name_policy({:?})", policy);
+
+        self.persistent_store
+            .apply(&[(
+                SYMBOL_NAME_POLICY_KEY.to_vec(),
+                Op::Put(
+                    minicbor::to_vec(&policy)
+                        .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                ),
+            )])
+            .unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// A digest of the local names of the symbols, so clients caching them
+    /// can cheaply tell whether they changed.
+    pub fn symbol_names_digest(&self) -> Result<Vec<u8>, ManyError> {
+        let names = minicbor::to_vec(&self.symbols)
+            .map_err(|e| ManyError::serialization_error(e.to_string()))?;
+        Ok(Sha3_256::digest(&names).to_vec())
+    }
+
+    /// Check a name given to a symbol, or to a new symbol if `symbol` is
+    /// `None`. A symbol being renamed does not conflict with its own name.
+    pub fn check_symbol_name(&self, symbol: Option<&Symbol>, name: &str) -> Result<(), ManyError> {
+        if let Some(symbol) = symbol {
+            if !self.symbols.contains_key(symbol) {
+                return Err(error::unknown_symbol(*symbol));
+            }
+        }
+
+        let taken = self
+            .symbols
+            .iter()
+            .filter(|(s, _)| symbol != Some(*s))
+            .map(|(_, n)| n.as_str());
+        self.get_symbol_name_policy()?.check(name, taken)
+    }
+}
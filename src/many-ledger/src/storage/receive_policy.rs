@@ -0,0 +1,71 @@
+use crate::module::receive_policy::ReceivePolicy;
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_identity::Address;
+use many_types::ledger::{Symbol, TokenAmount};
+use merk::Op;
+use tracing::info;
+
+pub(crate) const RECEIVE_POLICY_ROOT: &[u8] = b"/receive_policy/";
+
+/// Returns the storage key for the receive policy of an address. Only
+/// addresses with a policy other than open have one.
+fn key_for_receive_policy(address: &Address) -> Vec<u8> {
+    vec![RECEIVE_POLICY_ROOT, address.to_string().as_bytes()].concat()
+}
+
+impl LedgerStorage {
+    pub fn get_receive_policy(&self, address: &Address) -> Result<ReceivePolicy, ManyError> {
+        match self
+            .persistent_store
+            .get(&key_for_receive_policy(address))
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+        {
+            Some(bytes) => minicbor::decode(&bytes)
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            None => Ok(ReceivePolicy::Open),
+        }
+    }
+
+    pub fn set_receive_policy(
+        &mut self,
+        address: &Address,
+        policy: ReceivePolicy,
+    ) -> Result<(), ManyError> {
+        info!("set_receive_policy({}, {:?})", address, policy);
+        let key = key_for_receive_policy(address);
+        let op = match policy {
+            ReceivePolicy::Open if self.persistent_store.get(&key).unwrap().is_none() => {
+                return Ok(());
+            }
+            ReceivePolicy::Open => Op::Delete,
+            policy => Op::Put(
+                minicbor::to_vec(&policy)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        };
+        self.persistent_store
+            .apply(&[(key, op)])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Check a transfer against the receive policy of its recipient. Sends
+    /// to their source do not move funds, and are always accepted.
+    pub fn check_receive(
+        &self,
+        from: &Address,
+        to: &Address,
+        symbol: &Symbol,
+        amount: &TokenAmount,
+    ) -> Result<(), ManyError> {
+        if from == to {
+            return Ok(());
+        }
+        self.get_receive_policy(to)?.check(from, to, symbol, amount)
+    }
+}
@@ -0,0 +1,278 @@
+use super::escrow::timestamp_secs;
+use crate::error;
+use crate::migration::recovery::ACCOUNT_RECOVERY_MIGRATION;
+use crate::module::recovery::{
+    AccountRecovery, RecoverReturns, RecoveryConfig, RecoveryInfoReturns,
+};
+use crate::storage::LedgerStorage;
+use many_error::{ManyError, ManyErrorCode};
+use many_identity::Address;
+use many_modules::account::features::FeatureInfo;
+use many_modules::{account, events};
+use many_types::Timestamp;
+use merk::Op;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::warn;
+
+pub(crate) const RECOVERY_ACTIVITY_ROOT: &[u8] = b"/recovery/activity/";
+pub(crate) const RECOVERY_CLAIMS_ROOT: &[u8] = b"/recovery/claims/";
+
+/// Returns the storage key for the block time of the last command of an
+/// identity. Only the owners of accounts which configured their recovery have
+/// one, and the accounts themselves record when they configured it.
+pub(super) fn key_for_activity(id: &Address) -> Vec<u8> {
+    vec![RECOVERY_ACTIVITY_ROOT, id.to_string().as_bytes()].concat()
+}
+
+/// Returns the storage key for the block time of the pending recovery
+/// claim of an account.
+pub(super) fn key_for_claim(account: &Address) -> Vec<u8> {
+    vec![RECOVERY_CLAIMS_ROOT, account.to_string().as_bytes()].concat()
+}
+
+fn recovery_config(id: &Address, account: &account::Account) -> Result<RecoveryConfig, ManyError> {
+    match account.features().get::<AccountRecovery>() {
+        Ok(recovery) => Ok(recovery.config),
+        Err(e) if e.code() == ManyErrorCode::AttributeNotFound => {
+            Err(error::recovery_not_enabled(*id))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+impl LedgerStorage {
+    /// Whether accounts can configure their recovery. Recording activity
+    /// changes the state, so chains start at the activation height of the
+    /// migration.
+    pub(crate) fn recovery_enabled(&self) -> bool {
+        self.active_migrations.contains(ACCOUNT_RECOVERY_MIGRATION)
+    }
+
+    fn get_secs(&self, key: &[u8]) -> Result<Option<u64>, ManyError> {
+        Ok(self
+            .persistent_store
+            .get(key)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map(|x| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(x.as_slice());
+                u64::from_be_bytes(bytes)
+            }))
+    }
+
+    fn apply_recovery(&mut self, key: Vec<u8>, secs: Option<u64>) -> Result<(), ManyError> {
+        let op = match secs {
+            Some(secs) => Op::Put(secs.to_be_bytes().to_vec()),
+            None => Op::Delete,
+        };
+        self.persistent_store
+            .apply(&[(key, op)])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if !self.blockchain {
+            self.persistent_store
+                .commit(&[])
+                .expect("Could not commit to store.");
+        }
+        Ok(())
+    }
+
+    /// Record the block time as the last activity of an identity, if it owns
+    /// an account which configured its recovery.
+    pub fn record_activity(&mut self, id: &Address) -> Result<(), ManyError> {
+        if !self.recovery_enabled() || self.get_secs(&key_for_activity(id))?.is_none() {
+            return Ok(());
+        }
+        let now = timestamp_secs(self.now())?;
+        self.apply_recovery(key_for_activity(id), Some(now))
+    }
+
+    /// Whether an account configured its recovery.
+    pub(crate) fn has_recovery(&self, id: &Address) -> Result<bool, ManyError> {
+        Ok(self.get_secs(&key_for_activity(id))?.is_some())
+    }
+
+    /// Start recording the activity of the owners of an account, as of now
+    /// for the owners without a record yet.
+    pub(crate) fn watch_owners(
+        &mut self,
+        id: &Address,
+        account: &account::Account,
+    ) -> Result<(), ManyError> {
+        let now = timestamp_secs(self.now())?;
+        for (owner, roles) in &account.roles {
+            if owner == id || !roles.contains(&account::Role::Owner) {
+                continue;
+            }
+            if self.get_secs(&key_for_activity(owner))?.is_none() {
+                self.apply_recovery(key_for_activity(owner), Some(now))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The block time of the last command of an owner of the account, other
+    /// than the account itself. The owners which never sent a command are
+    /// considered active when the recovery was configured.
+    fn last_owner_activity(
+        &self,
+        id: &Address,
+        account: &account::Account,
+        configured: u64,
+    ) -> Result<u64, ManyError> {
+        let mut last = configured;
+        for (owner, roles) in &account.roles {
+            if owner == id || !roles.contains(&account::Role::Owner) {
+                continue;
+            }
+            if let Some(secs) = self.get_secs(&key_for_activity(owner))? {
+                last = last.max(secs);
+            }
+        }
+        Ok(last)
+    }
+
+    /// Returns the time of the pending claim of an account, ignoring claims
+    /// an owner vetoed by sending a command since.
+    fn pending_claim(&self, id: &Address, last_activity: u64) -> Result<Option<u64>, ManyError> {
+        Ok(self
+            .get_secs(&key_for_claim(id))?
+            .filter(|claimed| last_activity < *claimed))
+    }
+
+    pub fn get_recovery_info(&self, id: &Address) -> Result<RecoveryInfoReturns, ManyError> {
+        let account = self
+            .get_account(id)
+            .ok_or_else(|| account::errors::unknown_account(*id))?;
+        let config = recovery_config(id, &account)?;
+        // The feature alone, e.g. given when creating the account, does not
+        // enable the recovery; its owners would not be watched.
+        let configured = self
+            .get_secs(&key_for_activity(id))?
+            .ok_or_else(|| error::recovery_not_enabled(*id))?;
+        let last_activity = self.last_owner_activity(id, &account, configured)?;
+        let claimed = self.pending_claim(id, last_activity)?;
+
+        Ok(RecoveryInfoReturns {
+            last_activity: Timestamp::new(last_activity)?,
+            claimable: Timestamp::new(last_activity.saturating_add(config.inactivity_secs))?,
+            claimed: claimed.map(Timestamp::new).transpose()?,
+            completable: claimed
+                .map(|c| Timestamp::new(c.saturating_add(config.challenge_secs)))
+                .transpose()?,
+            config,
+        })
+    }
+
+    /// Configure the recovery of an account, replacing its previous
+    /// configuration and dropping any pending claim.
+    pub fn set_recovery(
+        &mut self,
+        id: &Address,
+        mut account: account::Account,
+        config: RecoveryConfig,
+    ) -> Result<(), ManyError> {
+        if !self.recovery_enabled() {
+            return Err(error::recovery_not_available());
+        }
+        if config.inactivity_secs == 0 {
+            return Err(error::invalid_recovery_inactivity());
+        }
+        if config.recovery.is_anonymous() || &config.recovery == id {
+            return Err(error::invalid_recovery_identity());
+        }
+
+        let feature = AccountRecovery { config }.as_feature();
+        account.features.insert(feature.clone());
+
+        // The account itself never sends commands, so its activity records
+        // when the recovery was configured.
+        let now = timestamp_secs(self.now())?;
+        self.apply_recovery(key_for_activity(id), Some(now))?;
+        self.watch_owners(id, &account)?;
+        self.apply_recovery(key_for_claim(id), None)?;
+
+        self.log_event(events::EventInfo::AccountAddFeatures {
+            account: *id,
+            roles: BTreeMap::new(),
+            features: account::features::FeatureSet::from_iter([feature]),
+        });
+        self.commit_account(id, account)
+    }
+
+    /// Claim an account as its recovery identity. The first call opens a
+    /// claim once the owners have been inactive long enough, and a second
+    /// call after the challenge window transfers the ownership, unless an
+    /// owner sent a command in the meantime.
+    pub fn recover(&mut self, sender: &Address, id: &Address) -> Result<RecoverReturns, ManyError> {
+        let info = self.get_recovery_info(id)?;
+        if sender != &info.config.recovery {
+            return Err(error::not_recovery_identity());
+        }
+        let now = timestamp_secs(self.now())?;
+
+        let completable = match info.completable {
+            Some(completable) => timestamp_secs(completable)?,
+            None => {
+                let claimable = timestamp_secs(info.claimable)?;
+                if now < claimable {
+                    return Err(error::account_active(claimable));
+                }
+                self.apply_recovery(key_for_claim(id), Some(now))?;
+                return Ok(RecoverReturns {
+                    recovered: false,
+                    completable: Some(Timestamp::new(
+                        now.saturating_add(info.config.challenge_secs),
+                    )?),
+                });
+            }
+        };
+        if now < completable {
+            return Err(error::recovery_challenge_pending(completable));
+        }
+
+        let account = self
+            .get_account(id)
+            .ok_or_else(|| account::errors::unknown_account(*id))?;
+        let owners: BTreeMap<Address, BTreeSet<account::Role>> = account
+            .roles
+            .iter()
+            .filter(|(owner, roles)| {
+                *owner != id && *owner != sender && roles.contains(&account::Role::Owner)
+            })
+            .map(|(owner, _)| (*owner, BTreeSet::from([account::Role::Owner])))
+            .collect();
+        warn!(
+            "Account {} recovered by {}, removing the owner role of {:?}",
+            id,
+            sender,
+            owners.keys().collect::<Vec<_>>()
+        );
+
+        self.add_roles(
+            account,
+            account::AddRolesArgs {
+                account: *id,
+                roles: BTreeMap::from([(*sender, BTreeSet::from([account::Role::Owner]))]),
+            },
+        )?;
+        if !owners.is_empty() {
+            let account = self
+                .get_account(id)
+                .ok_or_else(|| account::errors::unknown_account(*id))?;
+            self.remove_roles(
+                account,
+                account::RemoveRolesArgs {
+                    account: *id,
+                    roles: owners,
+                },
+            )?;
+        }
+        self.apply_recovery(key_for_claim(id), None)?;
+
+        Ok(RecoverReturns {
+            recovered: true,
+            completable: None,
+        })
+    }
+}
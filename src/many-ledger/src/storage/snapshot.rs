@@ -0,0 +1,147 @@
+use crate::error;
+use crate::module::snapshot::SnapshotInfo;
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events;
+use many_storage::event_schema::decode_event;
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::{CborRange, SortOrder};
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::Tree;
+use merk::Op;
+use std::ops::Bound;
+use tracing::info;
+
+pub(crate) const SNAPSHOTS_ROOT: &[u8] = b"/snapshots/";
+
+/// Returns the storage key for a snapshot. IDs are stored big endian so
+/// snapshots are listed in creation order.
+fn key_for_snapshot(id: u64) -> Vec<u8> {
+    vec![SNAPSHOTS_ROOT, &id.to_be_bytes()].concat()
+}
+
+impl LedgerStorage {
+    /// Record a snapshot of the balances of a symbol at the current block.
+    /// Nothing is copied; balances at the snapshot are reconstructed from
+    /// the events logged after it.
+    pub fn create_snapshot(&mut self, sender: &Address, symbol: &Symbol) -> Result<u64, ManyError> {
+        if sender != &self.account_identity {
+            return Err(error::unauthorized());
+        }
+        if !self.symbols.contains_key(symbol) {
+            return Err(error::unknown_symbol(*symbol));
+        }
+
+        let id = self
+            .persistent_store
+            .get(b"/config/snapshot_id")
+            .unwrap()
+            .map_or(0, |x| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(x.as_slice());
+                u64::from_be_bytes(bytes)
+            });
+
+        let snapshot = SnapshotInfo {
+            id,
+            height: self.get_height(),
+            symbol: *symbol,
+            event_id: self.latest_tid.clone(),
+            hash: self.hash().into(),
+            time: self.now(),
+        };
+        info!("create_snapshot({}, {} at {})", id, symbol, snapshot.height);
+
+        self.persistent_store
+            .apply(&[
+                (
+                    b"/config/snapshot_id".to_vec(),
+                    Op::Put((id + 1).to_be_bytes().to_vec()),
+                ),
+                (
+                    key_for_snapshot(id),
+                    Op::Put(
+                        minicbor::to_vec(&snapshot)
+                            .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+                    ),
+                ),
+            ])
+            .unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+
+        Ok(id)
+    }
+
+    pub fn get_snapshot(&self, id: u64) -> Result<SnapshotInfo, ManyError> {
+        let bytes = self
+            .persistent_store
+            .get(&key_for_snapshot(id))
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .ok_or_else(|| error::unknown_snapshot(id))?;
+        minicbor::decode(&bytes).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    pub fn list_snapshots(&self, symbol: Option<Symbol>) -> Result<Vec<SnapshotInfo>, ManyError> {
+        let mut options = ReadOptions::default();
+        options.set_iterate_lower_bound(SNAPSHOTS_ROOT);
+        let mut bound = SNAPSHOTS_ROOT.to_vec();
+        bound[SNAPSHOTS_ROOT.len() - 1] += 1;
+        options.set_iterate_upper_bound(bound);
+
+        let mut snapshots = vec![];
+        for item in self.persistent_store.iter_opt(IteratorMode::Start, options) {
+            let (k, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            let v = Tree::decode(k.to_vec(), v.as_ref());
+            let snapshot: SnapshotInfo = minicbor::decode(v.value())
+                .map_err(|e| ManyError::deserialization_error(e.to_string()))?;
+            if symbol.map_or(true, |s| s == snapshot.symbol) {
+                snapshots.push(snapshot);
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Returns the balance of an account at a snapshot, by undoing the
+    /// transfers of the snapshot's symbol that were logged after it.
+    pub fn get_snapshot_balance(
+        &self,
+        id: u64,
+        account: &Address,
+    ) -> Result<TokenAmount, ManyError> {
+        let snapshot = self.get_snapshot(id)?;
+        let mut balance = self.get_balance(account, &snapshot.symbol);
+
+        let range = CborRange {
+            start: Bound::Excluded(snapshot.event_id),
+            end: Bound::Unbounded,
+        };
+        for item in self.iter(range, SortOrder::Ascending) {
+            let (_, v) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            let event = decode_event(&v)?;
+
+            if let events::EventInfo::Send {
+                from,
+                to,
+                symbol,
+                amount,
+            } = event.content
+            {
+                if symbol != snapshot.symbol {
+                    continue;
+                }
+                if &to == account {
+                    balance -= amount.clone();
+                }
+                if &from == account {
+                    balance += amount;
+                }
+            }
+        }
+
+        Ok(balance)
+    }
+}
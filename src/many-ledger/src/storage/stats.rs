@@ -0,0 +1,191 @@
+use crate::error;
+use crate::migration::stats::LEDGER_STATS_MIGRATION;
+use crate::module::stats::{LastEvent, StatsReturns};
+use crate::storage::{key_for_multisig_transaction, LedgerStorage};
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events;
+use many_types::ledger::{Symbol, TokenAmount};
+use merk::{BatchEntry, Op};
+use std::collections::BTreeMap;
+
+// Counters maintained with the changes they count, so the stats of the
+// chain can be read without scanning the store. They are only written once
+// the `LedgerStats` migration, which backfills them, is active.
+pub(crate) const TRANSACTIONS_KEY: &[u8] = b"/stats/transactions";
+pub(crate) const ACCOUNTS_KEY: &[u8] = b"/stats/accounts";
+pub(crate) const NONZERO_ADDRESSES_KEY: &[u8] = b"/stats/nonzero_addresses";
+pub(crate) const PENDING_MULTISIG_KEY: &[u8] = b"/stats/pending_multisig";
+pub(crate) const LAST_EVENT_KEY: &[u8] = b"/stats/last_event";
+
+/// The number of symbols each address has a nonzero balance of. Addresses
+/// without any have no record.
+pub(crate) const NONZERO_SYMBOLS_ROOT: &[u8] = b"/stats/nonzero_symbols/";
+
+pub(crate) fn key_for_nonzero_symbols(id: &Address) -> Vec<u8> {
+    vec![NONZERO_SYMBOLS_ROOT, id.to_string().as_bytes()].concat()
+}
+
+pub(crate) fn counter_entry(key: &[u8], value: u64) -> BatchEntry {
+    (key.to_vec(), Op::Put(value.to_be_bytes().to_vec()))
+}
+
+impl LedgerStorage {
+    /// Whether the counters are maintained. Writing them changes the state,
+    /// so chains start counting at the activation height of the migration.
+    pub(crate) fn stats_enabled(&self) -> bool {
+        self.active_migrations.contains(LEDGER_STATS_MIGRATION)
+    }
+
+    pub(crate) fn get_counter(&self, key: &[u8]) -> u64 {
+        self.persistent_store.get(key).unwrap().map_or(0, |x| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(x.as_slice());
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    /// The entry adding `delta` to a counter. Counters cannot go below zero,
+    /// which can only happen on stores which were not counted from the start.
+    pub(crate) fn counter_delta_entry(&self, key: &[u8], delta: i64) -> BatchEntry {
+        let value = self.get_counter(key);
+        counter_entry(key, value.saturating_add_signed(delta))
+    }
+
+    /// Count a transaction processed. This is done once the transaction
+    /// succeeded, and never for queries.
+    pub fn count_transaction(&mut self) {
+        if !self.stats_enabled() {
+            return;
+        }
+        let entry = self.counter_delta_entry(TRANSACTIONS_KEY, 1);
+        self.persistent_store.apply(&[entry]).unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+    }
+
+    /// The entries updating the number of addresses with a nonzero balance,
+    /// given the balances about to be written. An address can appear more
+    /// than once.
+    pub(crate) fn balance_stats_entries<'a>(
+        &self,
+        balances: impl IntoIterator<Item = (&'a Address, &'a Symbol, &'a TokenAmount)>,
+    ) -> Vec<BatchEntry> {
+        if !self.stats_enabled() {
+            return vec![];
+        }
+
+        // Whether each balance is nonzero, and the number of symbols each
+        // address has a nonzero balance of, after the previous changes.
+        let mut nonzero: BTreeMap<(Address, Symbol), bool> = BTreeMap::new();
+        let mut counts: BTreeMap<Address, (u64, u64)> = BTreeMap::new();
+
+        for (id, symbol, amount) in balances {
+            let was = *nonzero
+                .entry((*id, *symbol))
+                .or_insert_with(|| !self.get_balance(id, symbol).is_zero());
+            let is = !amount.is_zero();
+            if was == is {
+                continue;
+            }
+            nonzero.insert((*id, *symbol), is);
+
+            let (_, count) = counts.entry(*id).or_insert_with(|| {
+                let count = self.get_counter(&key_for_nonzero_symbols(id));
+                (count, count)
+            });
+            *count = if is {
+                *count + 1
+            } else {
+                count.saturating_sub(1)
+            };
+        }
+
+        let mut delta = 0i64;
+        let mut entries = vec![];
+        for (id, (before, after)) in counts {
+            if before == after {
+                continue;
+            }
+            match (before, after) {
+                (0, _) => delta += 1,
+                (_, 0) => delta -= 1,
+                _ => {}
+            }
+            entries.push(if after == 0 {
+                (key_for_nonzero_symbols(&id), Op::Delete)
+            } else {
+                counter_entry(&key_for_nonzero_symbols(&id), after)
+            });
+        }
+        if delta != 0 {
+            entries.push(self.counter_delta_entry(NONZERO_ADDRESSES_KEY, delta));
+        }
+        entries
+    }
+
+    /// The entry counting a new account.
+    pub(crate) fn account_stats_entry(&self) -> Option<BatchEntry> {
+        self.stats_enabled()
+            .then(|| self.counter_delta_entry(ACCOUNTS_KEY, 1))
+    }
+
+    /// The entry updating the number of pending multisig transactions, if
+    /// the transaction written changes it.
+    pub(crate) fn multisig_stats_entry(&self, tx_id: &[u8], pending: bool) -> Option<BatchEntry> {
+        if !self.stats_enabled() {
+            return None;
+        }
+        let was_pending = self
+            .persistent_store
+            .get(&key_for_multisig_transaction(tx_id))
+            .unwrap()
+            .and_then(|bytes| minicbor::decode::<super::MultisigTransactionStorage>(&bytes).ok())
+            .map_or(false, |tx| !tx.disabled);
+        match (was_pending, pending) {
+            (false, true) => Some(self.counter_delta_entry(PENDING_MULTISIG_KEY, 1)),
+            (true, false) => Some(self.counter_delta_entry(PENDING_MULTISIG_KEY, -1)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn last_event_entry(event: &events::EventLog) -> BatchEntry {
+        (
+            LAST_EVENT_KEY.to_vec(),
+            Op::Put(
+                minicbor::to_vec(LastEvent {
+                    id: event.id.clone(),
+                    time: event.time,
+                })
+                .unwrap(),
+            ),
+        )
+    }
+
+    pub fn stats(&self) -> Result<StatsReturns, ManyError> {
+        if !self.stats_enabled() {
+            return Err(error::stats_not_enabled());
+        }
+        let last_event = self
+            .persistent_store
+            .get(LAST_EVENT_KEY)
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .map(|bytes| {
+                minicbor::decode(&bytes)
+                    .map_err(|e| ManyError::deserialization_error(e.to_string()))
+            })
+            .transpose()?;
+
+        Ok(StatsReturns {
+            transactions: self.get_counter(TRANSACTIONS_KEY),
+            events: self.nb_events(),
+            nonzero_addresses: self.get_counter(NONZERO_ADDRESSES_KEY),
+            accounts: self.get_counter(ACCOUNTS_KEY),
+            symbols: self.symbols.len() as u64,
+            pending_multisig: self.get_counter(PENDING_MULTISIG_KEY),
+            last_event,
+        })
+    }
+}
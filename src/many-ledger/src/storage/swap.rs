@@ -0,0 +1,367 @@
+use super::escrow::timestamp_secs;
+use super::key_for_account_balance;
+use crate::error;
+use crate::module::swap::{SwapInfo, SwapState};
+use crate::storage::migration_ext::data::DataExt;
+use crate::storage::LedgerStorage;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::events;
+use many_types::ledger::{Symbol, TokenAmount};
+use many_types::Timestamp;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::{BatchEntry, Op};
+use std::collections::BTreeMap;
+use tracing::info;
+
+pub(crate) const SWAPS_ROOT: &[u8] = b"/swap/";
+pub(crate) const SWAP_LOCKED_ROOT: &[u8] = b"/swap_locked/";
+pub(crate) const SWAP_EXPIRIES_ROOT: &[u8] = b"/swap_expiries/";
+
+/// Returns the storage key for a swap.
+fn key_for_swap(id: &Address) -> Vec<u8> {
+    vec![SWAPS_ROOT, id.to_string().as_bytes()].concat()
+}
+
+/// Returns the storage key for the total amount a maker has locked in
+/// pending swaps for a symbol.
+fn key_for_swap_locked(maker: &Address, symbol: &Symbol) -> Vec<u8> {
+    vec![SWAP_LOCKED_ROOT, format!("{}/{}", maker, symbol).as_bytes()].concat()
+}
+
+/// Returns the storage key of a swap in the expiry index, ordered by time.
+fn key_for_swap_expiry(expiry: u64, id: &Address) -> Vec<u8> {
+    vec![
+        SWAP_EXPIRIES_ROOT.to_vec(),
+        expiry.to_be_bytes().to_vec(),
+        id.to_vec(),
+    ]
+    .concat()
+}
+
+impl LedgerStorage {
+    pub fn get_swap(&self, id: &Address) -> Result<SwapInfo, ManyError> {
+        let bytes = self
+            .persistent_store
+            .get(&key_for_swap(id))
+            .map_err(|e| ManyError::unknown(e.to_string()))?
+            .ok_or_else(|| error::unknown_swap(*id))?;
+        minicbor::decode(&bytes).map_err(|e| ManyError::deserialization_error(e.to_string()))
+    }
+
+    /// Returns the amounts locked by this maker in pending swaps.
+    pub fn get_swap_locked(&self, maker: &Address) -> BTreeMap<Symbol, TokenAmount> {
+        let mut result = BTreeMap::new();
+        for symbol in self.symbols.keys() {
+            if let Ok(Some(value)) = self
+                .persistent_store
+                .get(&key_for_swap_locked(maker, symbol))
+            {
+                result.insert(*symbol, TokenAmount::from(value));
+            }
+        }
+        result
+    }
+
+    /// Returns the operation updating the amount a maker has locked for a
+    /// symbol, after adding `add` and removing `remove`.
+    fn swap_locked_op(
+        &self,
+        maker: &Address,
+        symbol: &Symbol,
+        add: TokenAmount,
+        remove: TokenAmount,
+    ) -> BatchEntry {
+        let mut locked = self
+            .get_swap_locked(maker)
+            .remove(symbol)
+            .unwrap_or_default();
+        locked += add;
+        locked -= remove;
+
+        let op = if locked.is_zero() {
+            Op::Delete
+        } else {
+            Op::Put(locked.to_vec())
+        };
+        (key_for_swap_locked(maker, symbol), op)
+    }
+
+    fn swap_op(id: &Address, info: &SwapInfo) -> Result<BatchEntry, ManyError> {
+        Ok((
+            key_for_swap(id),
+            Op::Put(
+                minicbor::to_vec(info)
+                    .map_err(|e| ManyError::serialization_error(e.to_string()))?,
+            ),
+        ))
+    }
+
+    /// Lock funds from the maker into a new swap. Like escrows, the swap is
+    /// held by a new subresource of the ledger identity that only the ledger
+    /// can spend from.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_swap(
+        &mut self,
+        maker: &Address,
+        counterparty: Option<Address>,
+        symbol: &Symbol,
+        amount: TokenAmount,
+        want_symbol: &Symbol,
+        want_amount: TokenAmount,
+        expires_in_secs: u64,
+    ) -> Result<Address, ManyError> {
+        // Validate everything before allocating an ID, so that a failed
+        // creation does not change the state.
+        if expires_in_secs == 0 {
+            return Err(error::invalid_swap_expiry());
+        }
+        if symbol == want_symbol {
+            return Err(error::swap_same_symbol());
+        }
+        if amount.is_zero() || want_amount.is_zero() {
+            return Err(error::amount_is_zero());
+        }
+        if Some(*maker) == counterparty {
+            return Err(error::destination_is_source());
+        }
+        if maker.is_anonymous() || counterparty.map_or(false, |c| c.is_anonymous()) {
+            return Err(error::anonymous_cannot_hold_funds());
+        }
+        for s in [symbol, want_symbol] {
+            if !self.symbols.contains_key(s) {
+                return Err(error::unknown_symbol(*s));
+            }
+        }
+        if amount > self.get_balance(maker, symbol) {
+            return Err(error::insufficient_funds());
+        }
+
+        let expiry = Timestamp::from_system_time(
+            self.now()
+                .as_system_time()?
+                .checked_add(std::time::Duration::from_secs(expires_in_secs))
+                .ok_or_else(|| ManyError::unknown("Invalid time.".to_string()))?,
+        )?;
+
+        let id = self.new_account_id();
+        self.send(maker, &id, symbol, amount.clone())?;
+
+        let info = SwapInfo {
+            maker: *maker,
+            counterparty,
+            symbol: *symbol,
+            amount: amount.clone(),
+            want_symbol: *want_symbol,
+            want_amount,
+            expiry,
+            state: SwapState::Pending,
+            taker: None,
+        };
+        let mut batch = vec![
+            Self::swap_op(&id, &info)?,
+            (
+                key_for_swap_expiry(timestamp_secs(expiry)?, &id),
+                Op::Put(vec![]),
+            ),
+            self.swap_locked_op(maker, symbol, amount, TokenAmount::zero()),
+        ];
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.persistent_store.apply(&batch).unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+
+        Ok(id)
+    }
+
+    /// Settle both legs of a swap: the locked funds go to the taker, and the
+    /// wanted funds go from the taker to the maker. Everything is checked
+    /// before the balances, the swap and its indices are updated in a
+    /// single batch, so a failure leaves the state untouched.
+    pub fn accept_swap(&mut self, taker: &Address, id: &Address) -> Result<(), ManyError> {
+        let mut info = self.get_swap(id)?;
+        if info.state != SwapState::Pending {
+            return Err(error::swap_already_settled());
+        }
+        if self.now() >= info.expiry {
+            return Err(error::swap_expired());
+        }
+        if info.counterparty.map_or(false, |c| c != *taker) {
+            return Err(error::unauthorized());
+        }
+        if *taker == info.maker {
+            return Err(error::destination_is_source());
+        }
+        if taker.is_anonymous() {
+            return Err(error::anonymous_cannot_hold_funds());
+        }
+
+        let mut taker_wanted = self.get_balance(taker, &info.want_symbol);
+        if info.want_amount > taker_wanted {
+            return Err(error::insufficient_funds());
+        }
+        let mut locked = self.get_balance(id, &info.symbol);
+        if info.amount > locked {
+            return Err(error::insufficient_funds());
+        }
+        let mut taker_received = self.get_balance(taker, &info.symbol);
+        let mut maker_wanted = self.get_balance(&info.maker, &info.want_symbol);
+
+        info!(
+            "accept_swap({}, {} {} <=> {} {})",
+            id, info.amount, info.symbol, info.want_amount, info.want_symbol
+        );
+
+        locked -= info.amount.clone();
+        taker_received += info.amount.clone();
+        taker_wanted -= info.want_amount.clone();
+        maker_wanted += info.want_amount.clone();
+
+        self.update_data_attributes(id, taker, info.amount.clone(), &info.symbol);
+        self.update_data_attributes(
+            taker,
+            &info.maker,
+            info.want_amount.clone(),
+            &info.want_symbol,
+        );
+
+        let expiry_key = key_for_swap_expiry(timestamp_secs(info.expiry)?, id);
+        let locked_op = self.swap_locked_op(
+            &info.maker,
+            &info.symbol,
+            TokenAmount::zero(),
+            info.amount.clone(),
+        );
+        info.state = SwapState::Accepted;
+        info.taker = Some(*taker);
+
+        let mut batch: Vec<BatchEntry> = vec![
+            (
+                key_for_account_balance(id, &info.symbol),
+                Op::Put(locked.to_vec()),
+            ),
+            (
+                key_for_account_balance(taker, &info.symbol),
+                Op::Put(taker_received.to_vec()),
+            ),
+            (
+                key_for_account_balance(taker, &info.want_symbol),
+                Op::Put(taker_wanted.to_vec()),
+            ),
+            (
+                key_for_account_balance(&info.maker, &info.want_symbol),
+                Op::Put(maker_wanted.to_vec()),
+            ),
+            (expiry_key, Op::Delete),
+            locked_op,
+            Self::swap_op(id, &info)?,
+        ];
+        batch.extend(self.balance_stats_entries([
+            (id, &info.symbol, &locked),
+            (taker, &info.symbol, &taker_received),
+            (taker, &info.want_symbol, &taker_wanted),
+            (&info.maker, &info.want_symbol, &maker_wanted),
+        ]));
+        // Keys in batch must be sorted.
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.persistent_store.apply(&batch).unwrap();
+
+        self.log_event(events::EventInfo::Send {
+            from: *id,
+            to: *taker,
+            symbol: info.symbol,
+            amount: info.amount,
+        });
+        self.log_event(events::EventInfo::Send {
+            from: *taker,
+            to: info.maker,
+            symbol: info.want_symbol,
+            amount: info.want_amount,
+        });
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Return the locked funds of a pending swap to its maker.
+    pub fn cancel_swap(&mut self, id: &Address) -> Result<(), ManyError> {
+        let info = self.get_swap(id)?;
+        if info.state != SwapState::Pending {
+            return Err(error::swap_already_settled());
+        }
+        self.refund_swap(id, info, SwapState::Cancelled)
+    }
+
+    fn refund_swap(
+        &mut self,
+        id: &Address,
+        mut info: SwapInfo,
+        state: SwapState,
+    ) -> Result<(), ManyError> {
+        info!("refund_swap({}, {:?})", id, state);
+        // Nothing can fail once the funds moved.
+        let expiry_key = key_for_swap_expiry(timestamp_secs(info.expiry)?, id);
+        info.state = state;
+        let swap_op = Self::swap_op(id, &info)?;
+
+        // The swap holds the funds, so they move without the fee and the
+        // checks of a send, which could leave them stuck.
+        self.transfer(id, &info.maker, &info.symbol, info.amount.clone());
+
+        let locked_op = self.swap_locked_op(
+            &info.maker,
+            &info.symbol,
+            TokenAmount::zero(),
+            info.amount.clone(),
+        );
+
+        let mut batch = vec![(expiry_key, Op::Delete), locked_op, swap_op];
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.persistent_store.apply(&batch).unwrap();
+
+        if !self.blockchain {
+            self.persistent_store.commit(&[]).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Refund every pending swap whose expiry is at or before the current
+    /// block time. Like escrow timeouts, this runs at commit so every node
+    /// agrees on the outcome. As for escrows, each swap is refunded on its
+    /// own and those which could not be are returned with their error.
+    pub fn check_expired_swaps(&mut self) -> Result<BTreeMap<Address, ManyError>, ManyError> {
+        let mut options = ReadOptions::default();
+        options.set_iterate_lower_bound(SWAP_EXPIRIES_ROOT);
+        options.set_iterate_upper_bound(
+            vec![
+                SWAP_EXPIRIES_ROOT.to_vec(),
+                (timestamp_secs(self.now())? + 1).to_be_bytes().to_vec(),
+            ]
+            .concat(),
+        );
+
+        let mut expired = vec![];
+        for item in self.persistent_store.iter_opt(IteratorMode::Start, options) {
+            let (k, _) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+            let id = Address::from_bytes(&k[SWAP_EXPIRIES_ROOT.len() + 8..])?;
+            expired.push(id);
+        }
+
+        let mut failures = BTreeMap::new();
+        for id in expired {
+            let result = self.get_swap(&id).and_then(|info| match info.state {
+                SwapState::Pending => self.refund_swap(&id, info, SwapState::Expired),
+                _ => Ok(()),
+            });
+            if let Err(e) = result {
+                failures.insert(id, e);
+            }
+        }
+
+        Ok(failures)
+    }
+}
@@ -0,0 +1,432 @@
+use super::escrow::{
+    key_for_escrow, key_for_escrow_timeout, key_for_escrowed, timestamp_secs, ESCROWED_ROOT,
+    ESCROWS_ROOT, ESCROW_TIMEOUTS_ROOT,
+};
+use super::{
+    key_for_account, key_for_account_balance, LedgerStorage, MultisigTransactionStorage,
+    ACCOUNTS_ROOT, BALANCES_ROOT, EVENTS_ROOT, IDSTORE_ROOT, MULTISIG_TRANSACTIONS_ROOT,
+};
+use crate::error;
+use crate::module::escrow::{EscrowInfo, EscrowState};
+use crate::module::verify::{
+    Discrepancy, DiscrepancyKind, VerifyCursor, VerifyPhase, VerifyReturns,
+};
+use many_error::ManyError;
+use many_identity::Address;
+use many_types::ledger::TokenAmount;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::Tree;
+use merk::Op;
+use minicbor::bytes::ByteVec;
+use std::str::FromStr;
+use tracing::info;
+
+/// Returns the first key after all keys starting with the prefix.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    *end.last_mut().unwrap() += 1;
+    end
+}
+
+fn parse_address(bytes: &[u8]) -> Option<Address> {
+    Address::from_str(std::str::from_utf8(bytes).ok()?).ok()
+}
+
+impl LedgerStorage {
+    /// Read committed records between two keys. Verification only reads
+    /// committed data, so it is not confused by a block being executed.
+    fn scan(
+        &self,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, ManyError> {
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_lower_bound(lower);
+        opts.set_iterate_upper_bound(upper);
+
+        self.persistent_store
+            .iter_opt(IteratorMode::Start, opts)
+            .take(limit)
+            .map(|item| {
+                item.map(|(k, v)| {
+                    let value = Tree::decode(k.to_vec(), v.as_ref()).value().to_vec();
+                    (k.to_vec(), value)
+                })
+                .map_err(|e| ManyError::unknown(e.to_string()))
+            })
+            .collect()
+    }
+
+    fn get_committed(&self, key: &[u8]) -> Result<Option<Vec<u8>>, ManyError> {
+        let upper = vec![key, &[0]].concat();
+        Ok(self
+            .scan(key.to_vec(), upper, 1)?
+            .pop()
+            .map(|(_, value)| value))
+    }
+
+    fn get_committed_escrow(&self, id: &Address) -> Result<Option<EscrowInfo>, ManyError> {
+        Ok(self
+            .get_committed(&key_for_escrow(id))?
+            .and_then(|bytes| minicbor::decode(&bytes).ok()))
+    }
+
+    /// Verify a chunk of at most `limit` records, starting at the cursor.
+    /// Returns the discrepancies found and the cursor to continue from, if
+    /// the ledger was not verified completely.
+    pub fn verify(
+        &self,
+        sender: &Address,
+        mut cursor: VerifyCursor,
+        limit: usize,
+    ) -> Result<VerifyReturns, ManyError> {
+        if sender != &self.account_identity {
+            return Err(error::unauthorized());
+        }
+
+        let mut discrepancies = Vec::new();
+        let mut budget = limit.max(1);
+        loop {
+            let prefix = match cursor.phase {
+                VerifyPhase::Balances => BALANCES_ROOT,
+                VerifyPhase::Escrows => ESCROWS_ROOT,
+                VerifyPhase::Escrowed => ESCROWED_ROOT,
+                VerifyPhase::EscrowTimeouts => ESCROW_TIMEOUTS_ROOT,
+                VerifyPhase::IdStore => IDSTORE_ROOT,
+                VerifyPhase::Multisig => MULTISIG_TRANSACTIONS_ROOT,
+                VerifyPhase::Events => EVENTS_ROOT,
+                VerifyPhase::Accounts => ACCOUNTS_ROOT,
+            };
+            let lower = cursor
+                .key
+                .as_ref()
+                .map_or_else(|| prefix.to_vec(), |k| vec![k.as_slice(), &[0]].concat());
+
+            let records = self.scan(lower, prefix_end(prefix), budget)?;
+            budget -= records.len();
+            for (key, value) in records {
+                let record = &key[prefix.len()..];
+                let discrepancy = match cursor.phase {
+                    VerifyPhase::Balances => self.verify_balance(&mut cursor, record, &value),
+                    VerifyPhase::Escrows => {
+                        self.verify_escrow(&mut cursor, record, &value, &mut discrepancies)?;
+                        None
+                    }
+                    VerifyPhase::Escrowed => {
+                        let expected = cursor.escrowed.remove(&ByteVec::from(key.clone()));
+                        let actual = TokenAmount::from(value.clone());
+                        (expected.as_ref() != Some(&actual)).then(|| Discrepancy {
+                            kind: DiscrepancyKind::EscrowedAmount,
+                            key: key.clone().into(),
+                            actual: Some(value.into()),
+                            expected: expected.map(|x| x.to_vec().into()),
+                            repairable: true,
+                        })
+                    }
+                    VerifyPhase::EscrowTimeouts => self.verify_escrow_timeout(&key, record)?,
+                    VerifyPhase::IdStore => {
+                        if record.starts_with(b"00") {
+                            cursor.count += 1;
+                        } else {
+                            cursor.idstore_addresses += 1;
+                        }
+                        None
+                    }
+                    VerifyPhase::Multisig => self.verify_multisig(&key, &value)?,
+                    VerifyPhase::Events => {
+                        cursor.count += 1;
+                        None
+                    }
+                    VerifyPhase::Accounts => {
+                        if let Some(id) = parse_address(record).and_then(|a| a.subresource_id()) {
+                            cursor.next_account_id = cursor.next_account_id.max(id as u64 + 1);
+                        }
+                        None
+                    }
+                };
+                discrepancies.extend(discrepancy);
+                cursor.key = Some(key.into());
+            }
+
+            if budget == 0 {
+                return Ok(VerifyReturns {
+                    discrepancies,
+                    next: Some(cursor),
+                    supply: None,
+                });
+            }
+
+            self.verify_phase_end(&mut cursor, &mut discrepancies)?;
+            cursor = match cursor.phase.next() {
+                Some(phase) => VerifyCursor {
+                    phase,
+                    key: None,
+                    count: 0,
+                    ..cursor
+                },
+                None => {
+                    return Ok(VerifyReturns {
+                        discrepancies,
+                        next: None,
+                        supply: Some(cursor.supply),
+                    })
+                }
+            };
+        }
+    }
+
+    fn verify_balance(
+        &self,
+        cursor: &mut VerifyCursor,
+        record: &[u8],
+        value: &[u8],
+    ) -> Option<Discrepancy> {
+        let symbol = record
+            .iter()
+            .rposition(|b| *b == b'/')
+            .and_then(|i| parse_address(&record[i + 1..]));
+
+        match symbol {
+            Some(symbol) if self.symbols.contains_key(&symbol) => {
+                *cursor.supply.entry(symbol).or_default() += TokenAmount::from(value.to_vec());
+                None
+            }
+            _ => Some(Discrepancy {
+                kind: DiscrepancyKind::UnknownSymbol,
+                key: vec![BALANCES_ROOT, record].concat().into(),
+                actual: Some(value.to_vec().into()),
+                expected: None,
+                repairable: false,
+            }),
+        }
+    }
+
+    fn verify_escrow(
+        &self,
+        cursor: &mut VerifyCursor,
+        record: &[u8],
+        value: &[u8],
+        discrepancies: &mut Vec<Discrepancy>,
+    ) -> Result<(), ManyError> {
+        let key = vec![ESCROWS_ROOT, record].concat();
+        let (id, info) = match (parse_address(record), minicbor::decode::<EscrowInfo>(value)) {
+            (Some(id), Ok(info)) => (id, info),
+            _ => {
+                discrepancies.push(Discrepancy {
+                    kind: DiscrepancyKind::InvalidEscrow,
+                    key: key.into(),
+                    actual: Some(value.to_vec().into()),
+                    expected: None,
+                    repairable: false,
+                });
+                return Ok(());
+            }
+        };
+
+        // Escrows are held by subresources from the same counter as accounts.
+        if let Some(subresource) = id.subresource_id() {
+            cursor.next_account_id = cursor.next_account_id.max(subresource as u64 + 1);
+        }
+        if info.state != EscrowState::Pending {
+            return Ok(());
+        }
+
+        *cursor
+            .escrowed
+            .entry(key_for_escrowed(&info.payer, &info.symbol).into())
+            .or_default() += info.amount.clone();
+
+        let balance_key = key_for_account_balance(&id, &info.symbol);
+        let balance = self
+            .get_committed(&balance_key)?
+            .map(TokenAmount::from)
+            .unwrap_or_default();
+        if balance != info.amount {
+            discrepancies.push(Discrepancy {
+                kind: DiscrepancyKind::EscrowBalance,
+                key: balance_key.into(),
+                actual: Some(balance.to_vec().into()),
+                expected: Some(info.amount.to_vec().into()),
+                repairable: false,
+            });
+        }
+
+        let timeout_key = key_for_escrow_timeout(timestamp_secs(info.timeout)?, &id);
+        if self.get_committed(&timeout_key)?.is_none() {
+            discrepancies.push(Discrepancy {
+                kind: DiscrepancyKind::MissingEscrowTimeout,
+                key: timeout_key.into(),
+                actual: None,
+                expected: Some(vec![].into()),
+                repairable: true,
+            });
+        }
+        Ok(())
+    }
+
+    fn verify_escrow_timeout(
+        &self,
+        key: &[u8],
+        record: &[u8],
+    ) -> Result<Option<Discrepancy>, ManyError> {
+        let stale = match record.split_at(8.min(record.len())) {
+            (timeout, id) if timeout.len() == 8 => match Address::from_bytes(id) {
+                Ok(id) => match self.get_committed_escrow(&id)? {
+                    Some(info) if info.state == EscrowState::Pending => {
+                        timestamp_secs(info.timeout)?.to_be_bytes() != timeout
+                    }
+                    _ => true,
+                },
+                Err(_) => true,
+            },
+            _ => true,
+        };
+
+        Ok(stale.then(|| Discrepancy {
+            kind: DiscrepancyKind::StaleEscrowTimeout,
+            key: key.to_vec().into(),
+            actual: Some(vec![].into()),
+            expected: None,
+            repairable: true,
+        }))
+    }
+
+    fn verify_multisig(&self, key: &[u8], value: &[u8]) -> Result<Option<Discrepancy>, ManyError> {
+        let kind = match minicbor::decode::<MultisigTransactionStorage>(value) {
+            Err(_) => DiscrepancyKind::InvalidMultisigTransaction,
+            Ok(tx) if !tx.disabled => {
+                if self.get_committed(&key_for_account(&tx.account))?.is_some() {
+                    return Ok(None);
+                }
+                DiscrepancyKind::MultisigAccount
+            }
+            Ok(_) => return Ok(None),
+        };
+
+        Ok(Some(Discrepancy {
+            kind,
+            key: key.to_vec().into(),
+            actual: Some(value.to_vec().into()),
+            expected: None,
+            repairable: false,
+        }))
+    }
+
+    /// Compare the counters and indexes with what was accumulated once a
+    /// phase is done.
+    fn verify_phase_end(
+        &self,
+        cursor: &mut VerifyCursor,
+        discrepancies: &mut Vec<Discrepancy>,
+    ) -> Result<(), ManyError> {
+        match cursor.phase {
+            VerifyPhase::Escrowed => {
+                // Payers with pending escrows but no escrowed amount.
+                for (key, amount) in std::mem::take(&mut cursor.escrowed) {
+                    discrepancies.push(Discrepancy {
+                        kind: DiscrepancyKind::EscrowedAmount,
+                        key,
+                        actual: None,
+                        expected: Some(amount.to_vec().into()),
+                        repairable: true,
+                    });
+                }
+            }
+            VerifyPhase::IdStore => {
+                if cursor.count != cursor.idstore_addresses {
+                    discrepancies.push(Discrepancy {
+                        kind: DiscrepancyKind::IdStoreCount,
+                        key: IDSTORE_ROOT.to_vec().into(),
+                        actual: Some(cursor.idstore_addresses.to_be_bytes().to_vec().into()),
+                        expected: Some(cursor.count.to_be_bytes().to_vec().into()),
+                        repairable: false,
+                    });
+                }
+            }
+            VerifyPhase::Events => {
+                let stored = self.get_committed(b"/events_count")?;
+                let count = stored.as_ref().and_then(|x| {
+                    let bytes: [u8; 8] = x.as_slice().try_into().ok()?;
+                    Some(u64::from_be_bytes(bytes))
+                });
+                if count.unwrap_or(0) != cursor.count {
+                    discrepancies.push(Discrepancy {
+                        kind: DiscrepancyKind::EventCount,
+                        key: b"/events_count".to_vec().into(),
+                        actual: stored.map(Into::into),
+                        expected: Some(cursor.count.to_be_bytes().to_vec().into()),
+                        repairable: true,
+                    });
+                }
+            }
+            VerifyPhase::Accounts => {
+                let stored = self.get_committed(b"/config/account_id")?;
+                let next_id = stored.as_ref().and_then(|x| {
+                    let bytes: [u8; 4] = x.as_slice().try_into().ok()?;
+                    Some(u32::from_be_bytes(bytes) as u64)
+                });
+                if next_id.unwrap_or(0) < cursor.next_account_id {
+                    discrepancies.push(Discrepancy {
+                        kind: DiscrepancyKind::AccountCounter,
+                        key: b"/config/account_id".to_vec().into(),
+                        actual: stored.map(Into::into),
+                        expected: Some(
+                            (cursor.next_account_id as u32)
+                                .to_be_bytes()
+                                .to_vec()
+                                .into(),
+                        ),
+                        repairable: true,
+                    });
+                }
+            }
+            VerifyPhase::Balances
+            | VerifyPhase::Escrows
+            | VerifyPhase::EscrowTimeouts
+            | VerifyPhase::Multisig => {}
+        }
+        Ok(())
+    }
+
+    /// Write the expected data of the repairable discrepancies, or delete
+    /// their key, and commit. This should only be used offline.
+    pub fn repair(&mut self, discrepancies: &[Discrepancy]) -> Result<(), ManyError> {
+        let mut batch: Vec<_> = discrepancies
+            .iter()
+            .filter(|d| d.repairable)
+            .map(|d| {
+                info!(
+                    "repairing {:?} at {}",
+                    d.kind,
+                    hex::encode(d.key.as_slice())
+                );
+                let op = match &d.expected {
+                    Some(expected) => Op::Put(expected.to_vec()),
+                    None => Op::Delete,
+                };
+                (d.key.to_vec(), op)
+            })
+            .collect();
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+        batch.dedup_by(|(a, _), (b, _)| a == b);
+
+        self.persistent_store
+            .apply(&batch)
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+        self.persistent_store
+            .commit(&[])
+            .map_err(|e| ManyError::unknown(e.to_string()))?;
+
+        if let Some(expected) = discrepancies
+            .iter()
+            .find(|d| d.kind == DiscrepancyKind::AccountCounter)
+            .and_then(|d| d.expected.as_ref())
+        {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(expected);
+            self.next_account_id = u32::from_be_bytes(bytes);
+        }
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@ use many_ledger::module::LedgerModuleImpl;
 use many_modules::account;
 use many_modules::account::features::{FeatureInfo, TryCreateFeature};
 use many_modules::account::AccountModuleBackend;
+use many_modules::events::{self, EventsModuleBackend};
 use many_types::{Either, VecOrSingle};
 use std::collections::{BTreeMap, BTreeSet};
 
@@ -568,3 +569,106 @@ fn empty_feature_add_features() {
     assert!(result.is_err());
     assert_many_err(result, account::errors::empty_feature());
 }
+
+#[test]
+/// Role events list the roles of the arguments until the ChangedRoleEvents
+/// migration, and only the roles which changed after it.
+fn role_events_after_migration() {
+    let mut setup = Setup::new(true);
+    setup.module_impl = setup.module_impl.with_migrations(
+        json5::from_str(r#"[{ type: "ChangedRoleEvents", block_height: 3 }]"#).unwrap(),
+    );
+    let (_, account_id) = setup.block(|setup| setup.create_account_(AccountType::Multisig));
+
+    let roles = BTreeMap::from([(
+        identity(4),
+        BTreeSet::from([account::Role::CanLedgerTransact]),
+    )]);
+    let last_roles = |setup: &Setup| match setup
+        .module_impl
+        .list(events::ListArgs {
+            count: Some(1),
+            order: Some(many_types::SortOrder::Descending),
+            filter: None,
+        })
+        .unwrap()
+        .events
+        .remove(0)
+        .content
+    {
+        events::EventInfo::AccountAddRoles { roles, .. } => roles,
+        content => panic!("Unexpected event {:?}", content),
+    };
+
+    setup.block(|setup| setup.add_roles(account_id, roles.clone()));
+    assert_eq!(last_roles(&setup), roles);
+
+    // The identity already has the role.
+    setup.block(|setup| setup.add_roles(account_id, roles.clone()));
+    assert_eq!(last_roles(&setup), roles);
+    setup.block(|setup| setup.add_roles(account_id, roles.clone()));
+    assert_eq!(last_roles(&setup), BTreeMap::new());
+}
+
+#[test]
+/// Feature events list the roles of the arguments until the ChangedRoleEvents
+/// migration, and only the roles which changed after it.
+fn add_feature_events_after_migration() {
+    let mut setup = Setup::new(true);
+    setup.module_impl = setup.module_impl.with_migrations(
+        json5::from_str(r#"[{ type: "ChangedRoleEvents", block_height: 3 }]"#).unwrap(),
+    );
+    let (_, (before, after)) = setup.block(|setup| {
+        (
+            setup.create_account_(AccountType::Multisig),
+            setup.create_account_(AccountType::Multisig),
+        )
+    });
+
+    let roles = BTreeMap::from([(
+        identity(4),
+        BTreeSet::from([account::Role::CanLedgerTransact]),
+    )]);
+    setup.block(|setup| {
+        setup.add_roles(before, roles.clone());
+        setup.add_roles(after, roles.clone());
+    });
+
+    let add_features = |setup: &mut Setup, account_id: Address| {
+        let id = setup.id;
+        setup
+            .module_impl
+            .add_features(
+                &id,
+                account::AddFeaturesArgs {
+                    account: account_id,
+                    roles: Some(roles.clone()),
+                    features: account::features::FeatureSet::from_iter([
+                        account::features::ledger::AccountLedger.as_feature(),
+                    ]),
+                },
+            )
+            .unwrap();
+    };
+    let last_roles = |setup: &Setup| match setup
+        .module_impl
+        .list(events::ListArgs {
+            count: Some(1),
+            order: Some(many_types::SortOrder::Descending),
+            filter: None,
+        })
+        .unwrap()
+        .events
+        .remove(0)
+        .content
+    {
+        events::EventInfo::AccountAddFeatures { roles, .. } => roles,
+        content => panic!("Unexpected event {:?}", content),
+    };
+
+    // The identity already has the role.
+    setup.block(|setup| add_features(setup, before));
+    assert_eq!(last_roles(&setup), roles);
+    setup.block(|setup| add_features(setup, after));
+    assert_eq!(last_roles(&setup), BTreeMap::new());
+}
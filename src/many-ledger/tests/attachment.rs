@@ -0,0 +1,210 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::module::attachment::{
+    Attachment, AttachmentArgs, AttachmentModule, LedgerAttachmentModuleBackend, ATTACHMENT,
+};
+use many_modules::account::features::multisig::{self, AccountMultisigModuleBackend};
+use many_modules::events::{self, EventId};
+use many_modules::ledger::{LedgerCommandsModule, SendArgs};
+use many_modules::ManyModule;
+use many_protocol::RequestMessageBuilder;
+use many_types::cbor::CborAny;
+use std::sync::{Arc, Mutex};
+
+fn attachment() -> Attachment {
+    Attachment {
+        server: identity(9),
+        key: b"attachments/invoice-42".to_vec().into(),
+        hash: vec![7; 32].into(),
+    }
+}
+
+fn event_attachment(setup: &Setup, event: EventId) -> Option<Attachment> {
+    setup
+        .module_impl
+        .attachment(
+            &identity(1),
+            AttachmentArgs {
+                event: Some(event),
+                token: None,
+            },
+        )
+        .unwrap()
+        .attachment
+}
+
+#[test]
+fn send() {
+    let mut setup = setup();
+    setup.set_balance(setup.id, 1_000, *MFX_SYMBOL);
+    setup.send_(setup.id, identity(1), 100);
+
+    let since = setup.module_impl.latest_event_id();
+    setup.send_(setup.id, identity(1), 100);
+    setup.module_impl.attach(&since, &attachment()).unwrap();
+
+    let event = setup.module_impl.latest_event_id();
+    assert_eq!(event_attachment(&setup, event), Some(attachment()));
+    assert_eq!(event_attachment(&setup, since), None);
+}
+
+#[test]
+fn multisig_submit() {
+    let SetupWithAccount {
+        mut module_impl,
+        id,
+        account_id,
+    } = setup_with_account(AccountType::Multisig);
+
+    let since = module_impl.latest_event_id();
+    let token = module_impl
+        .multisig_submit_transaction(
+            &id,
+            multisig::SubmitTransactionArgs {
+                account: account_id,
+                memo: None,
+                transaction: Box::new(events::AccountMultisigTransaction::Send(SendArgs {
+                    from: Some(account_id),
+                    to: identity(1),
+                    symbol: *MFX_SYMBOL,
+                    amount: 100u64.into(),
+                })),
+                threshold: None,
+                timeout_in_secs: None,
+                execute_automatically: None,
+                data: None,
+            },
+        )
+        .unwrap()
+        .token;
+    module_impl.attach(&since, &attachment()).unwrap();
+
+    let returns = module_impl
+        .attachment(
+            &identity(1),
+            AttachmentArgs {
+                event: None,
+                token: Some(token),
+            },
+        )
+        .unwrap();
+    assert_eq!(returns.attachment, Some(attachment()));
+
+    // The submission event has the attachment too.
+    let returns = module_impl
+        .attachment(
+            &identity(1),
+            AttachmentArgs {
+                event: Some(module_impl.latest_event_id()),
+                token: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(returns.attachment, Some(attachment()));
+}
+
+#[test]
+fn query_by_event_or_token() {
+    let setup = setup();
+    for args in [
+        AttachmentArgs::default(),
+        AttachmentArgs {
+            event: Some(EventId::from(vec![1])),
+            token: Some(vec![1].into()),
+        },
+    ] {
+        assert_many_err(
+            setup.module_impl.attachment(&identity(1), args),
+            error::attachment_query_needs_one(),
+        );
+    }
+}
+
+#[test]
+fn format() {
+    assert!(attachment().validate().is_ok());
+
+    let mut anonymous = attachment();
+    anonymous.server = Address::anonymous();
+    assert!(anonymous.validate().is_err());
+
+    let mut no_key = attachment();
+    no_key.key = vec![].into();
+    assert!(no_key.validate().is_err());
+
+    let mut long_key = attachment();
+    long_key.key = vec![0; 257].into();
+    assert!(long_key.validate().is_err());
+
+    let mut short_hash = attachment();
+    short_hash.hash = vec![0; 31].into();
+    assert!(short_hash.validate().is_err());
+}
+
+#[tokio::test]
+async fn module() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let module = AttachmentModule::new(LedgerCommandsModule::new(backend.clone()), backend.clone());
+    let request = |amount: u64, attachment: &Attachment| {
+        let mut request = RequestMessageBuilder::default()
+            .from(id)
+            .method("ledger.send".to_string())
+            .data(
+                minicbor::to_vec(SendArgs {
+                    from: Some(id),
+                    to: identity(1),
+                    symbol: *MFX_SYMBOL,
+                    amount: amount.into(),
+                })
+                .unwrap(),
+            )
+            .build()
+            .unwrap();
+        request.attributes.insert(
+            ATTACHMENT.with_argument(CborAny::Bytes(minicbor::to_vec(attachment).unwrap())),
+        );
+        request
+    };
+    let latest = || backend.lock().unwrap().latest_event_id();
+
+    let response = module.execute(request(100, &attachment())).await.unwrap();
+    assert!(response.data.is_ok());
+    let event = latest();
+    let returns = backend
+        .lock()
+        .unwrap()
+        .attachment(
+            &id,
+            AttachmentArgs {
+                event: Some(event.clone()),
+                token: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(returns.attachment, Some(attachment()));
+
+    // Malformed attachments are refused before sending.
+    let mut invalid = attachment();
+    invalid.hash = vec![0; 4].into();
+    let response = module.execute(request(100, &invalid)).await.unwrap();
+    assert_eq!(
+        response.data,
+        Err(error::invalid_attachment("the hash must be 32 bytes long"))
+    );
+    assert_eq!(latest(), event);
+
+    // Failed sends log nothing.
+    let response = module
+        .execute(request(10_000, &attachment()))
+        .await
+        .unwrap();
+    assert!(response.data.is_err());
+    assert_eq!(latest(), event);
+}
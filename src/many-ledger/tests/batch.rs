@@ -0,0 +1,240 @@
+pub mod common;
+
+use common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::module::batch::{
+    BatchArgs, BatchItemStatus, BatchOperation, BatchReturns, LedgerBatchModuleBackend,
+    MAXIMUM_BATCH_COUNT,
+};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::ledger;
+
+fn send_op(from: Option<Address>, to: Address, amount: u32) -> BatchOperation {
+    BatchOperation::Send(ledger::SendArgs {
+        from,
+        to,
+        amount: amount.into(),
+        symbol: *MFX_SYMBOL,
+    })
+}
+
+fn batch(
+    setup: &mut Setup,
+    sender: Address,
+    operations: Vec<BatchOperation>,
+    atomic: bool,
+) -> Result<BatchReturns, ManyError> {
+    setup
+        .module_impl
+        .batch(&sender, BatchArgs { operations, atomic })
+}
+
+fn statuses(returns: &BatchReturns) -> Vec<BatchItemStatus> {
+    returns.results.iter().map(|r| r.status).collect()
+}
+
+fn error_code(returns: &BatchReturns, index: usize) -> Option<many_error::ManyErrorCode> {
+    returns.results[index].error.as_ref().map(|e| e.code())
+}
+
+fn mixed_operations() -> Vec<BatchOperation> {
+    vec![
+        send_op(None, identity(1), 100),
+        send_op(None, identity(2), 0),
+        send_op(None, identity(3), 2_000),
+        send_op(None, identity(4), 50),
+    ]
+}
+
+#[test]
+fn best_effort() {
+    let mut setup = Setup::default();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+
+    let returns = batch(&mut setup, id, mixed_operations(), false).unwrap();
+    assert_eq!(
+        statuses(&returns),
+        vec![
+            BatchItemStatus::Executed,
+            BatchItemStatus::Failed,
+            BatchItemStatus::Failed,
+            BatchItemStatus::Executed,
+        ]
+    );
+    assert_eq!(error_code(&returns, 0), None);
+    assert_eq!(
+        error_code(&returns, 1),
+        Some(error::amount_is_zero().code())
+    );
+    assert_eq!(
+        error_code(&returns, 2),
+        Some(error::insufficient_funds().code())
+    );
+
+    assert_eq!(setup.balance_(id), 850u32);
+    assert_eq!(setup.balance_(identity(1)), 100u32);
+    assert_eq!(setup.balance_(identity(3)), 0u32);
+    assert_eq!(setup.balance_(identity(4)), 50u32);
+}
+
+#[test]
+fn atomic_aborts_on_first_failure() {
+    let mut setup = Setup::default();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+
+    let returns = batch(&mut setup, id, mixed_operations(), true).unwrap();
+    assert_eq!(
+        statuses(&returns),
+        vec![
+            BatchItemStatus::Skipped,
+            BatchItemStatus::Failed,
+            BatchItemStatus::Skipped,
+            BatchItemStatus::Skipped,
+        ]
+    );
+    assert_eq!(
+        error_code(&returns, 1),
+        Some(error::amount_is_zero().code())
+    );
+
+    assert_eq!(setup.balance_(id), 1_000u32);
+    assert_eq!(setup.balance_(identity(1)), 0u32);
+    assert_eq!(setup.balance_(identity(4)), 0u32);
+}
+
+#[test]
+fn atomic_checks_cumulative_balance() {
+    let mut setup = Setup::default();
+    let id = setup.id;
+    setup.set_balance(id, 100, *MFX_SYMBOL);
+
+    let operations = vec![
+        send_op(None, identity(1), 60),
+        send_op(None, identity(2), 60),
+    ];
+    let returns = batch(&mut setup, id, operations, true).unwrap();
+    assert_eq!(
+        statuses(&returns),
+        vec![BatchItemStatus::Skipped, BatchItemStatus::Failed]
+    );
+    assert_eq!(
+        error_code(&returns, 1),
+        Some(error::insufficient_funds().code())
+    );
+    assert_eq!(setup.balance_(id), 100u32);
+    assert_eq!(setup.balance_(identity(1)), 0u32);
+}
+
+#[test]
+fn atomic_uses_earlier_operations() {
+    let mut setup = Setup::default();
+    let id = setup.id;
+    let account = setup.create_account_(AccountType::Ledger);
+    setup.set_balance(id, 100, *MFX_SYMBOL);
+
+    // The account is funded by the first send.
+    let operations = vec![
+        send_op(None, account, 100),
+        send_op(Some(account), identity(1), 70),
+    ];
+    let returns = batch(&mut setup, id, operations, true).unwrap();
+    assert_eq!(
+        statuses(&returns),
+        vec![BatchItemStatus::Executed, BatchItemStatus::Executed]
+    );
+    assert_eq!(setup.balance_(id), 0u32);
+    assert_eq!(setup.balance_(account), 30u32);
+    assert_eq!(setup.balance_(identity(1)), 70u32);
+}
+
+#[test]
+fn unauthorized() {
+    let mut setup = Setup::default();
+    let id = setup.id;
+    setup.set_balance(id, 100, *MFX_SYMBOL);
+    setup.set_balance(identity(5), 100, *MFX_SYMBOL);
+
+    let operations = || {
+        vec![
+            send_op(None, identity(1), 10),
+            send_op(Some(identity(5)), identity(1), 10),
+        ]
+    };
+
+    let returns = batch(&mut setup, id, operations(), true).unwrap();
+    assert_eq!(
+        statuses(&returns),
+        vec![BatchItemStatus::Skipped, BatchItemStatus::Failed]
+    );
+    assert_eq!(error_code(&returns, 1), Some(error::unauthorized().code()));
+    assert_eq!(setup.balance_(identity(1)), 0u32);
+
+    let returns = batch(&mut setup, id, operations(), false).unwrap();
+    assert_eq!(
+        statuses(&returns),
+        vec![BatchItemStatus::Executed, BatchItemStatus::Failed]
+    );
+    assert_eq!(setup.balance_(identity(1)), 10u32);
+    assert_eq!(setup.balance_(identity(5)), 100u32);
+}
+
+#[test]
+fn limits() {
+    let mut setup = Setup::default();
+    let id = setup.id;
+
+    assert_eq!(
+        batch(&mut setup, id, vec![], true).unwrap_err().code(),
+        error::batch_empty().code()
+    );
+
+    let operations = (0..=MAXIMUM_BATCH_COUNT)
+        .map(|_| send_op(None, identity(1), 1))
+        .collect();
+    assert_eq!(
+        batch(&mut setup, id, operations, false).unwrap_err().code(),
+        error::batch_too_large(0, 0).code()
+    );
+}
+
+fn block_hash(atomic: Option<bool>) -> Vec<u8> {
+    let mut setup = Setup::new(true);
+    let sender = identity(10);
+    setup.set_balance(sender, 1_000, *MFX_SYMBOL);
+
+    setup.block(|setup| match atomic {
+        Some(atomic) => {
+            let operations = (1..=5).map(|i| send_op(None, identity(i), i)).collect();
+            let returns = batch(setup, sender, operations, atomic).unwrap();
+            assert!(returns
+                .results
+                .iter()
+                .all(|r| r.status == BatchItemStatus::Executed));
+        }
+        None => {
+            for i in 1..=5 {
+                setup.send_(sender, identity(i), i);
+            }
+        }
+    });
+
+    ManyAbciModuleBackend::info(&setup.module_impl)
+        .unwrap()
+        .hash
+        .to_vec()
+}
+
+#[test]
+fn hash_determinism() {
+    let hash = block_hash(Some(true));
+    assert_eq!(hash, block_hash(Some(true)));
+    assert_eq!(hash, block_hash(Some(false)));
+
+    // A batch leaves the same state as the same sends one by one.
+    assert_eq!(hash, block_hash(None));
+}
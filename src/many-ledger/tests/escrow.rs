@@ -0,0 +1,375 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::module::escrow::{
+    EscrowArgs, EscrowCreateArgs, EscrowInfo, EscrowState, LedgerEscrowModuleBackend,
+};
+use many_ledger::module::governance::{LedgerGovernanceModuleBackend, ParamValue, SetParamArgs};
+use many_modules::events::{self, EventsModuleBackend};
+use many_types::ledger::TokenAmount;
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+const TIMEOUT_IN_SECS: u64 = 10;
+
+fn create(
+    setup: &mut Setup,
+    payer: Address,
+    payee: Address,
+    arbiter: Option<Address>,
+    amount: u64,
+) -> Result<Address, many_error::ManyError> {
+    setup
+        .module_impl
+        .escrow_create(
+            &payer,
+            EscrowCreateArgs {
+                from: None,
+                payee,
+                symbol: *MFX_SYMBOL,
+                amount: amount.into(),
+                timeout_in_secs: TIMEOUT_IN_SECS,
+                arbiter,
+            },
+        )
+        .map(|r| r.escrow)
+}
+
+fn create_(setup: &mut Setup, payer: Address, payee: Address, arbiter: Option<Address>) -> Address {
+    create(setup, payer, payee, arbiter, 100).expect("Could not create escrow")
+}
+
+fn release(
+    setup: &mut Setup,
+    sender: Address,
+    escrow: Address,
+) -> Result<(), many_error::ManyError> {
+    setup
+        .module_impl
+        .escrow_release(&sender, EscrowArgs { escrow })
+        .map(|_| ())
+}
+
+fn refund(
+    setup: &mut Setup,
+    sender: Address,
+    escrow: Address,
+) -> Result<(), many_error::ManyError> {
+    setup
+        .module_impl
+        .escrow_refund(&sender, EscrowArgs { escrow })
+        .map(|_| ())
+}
+
+fn info(setup: &Setup, escrow: Address) -> EscrowInfo {
+    setup
+        .module_impl
+        .escrow_info(&setup.id, EscrowArgs { escrow })
+        .expect("Could not get escrow info")
+}
+
+fn escrowed(setup: &Setup, account: Address) -> TokenAmount {
+    setup
+        .module_impl
+        .escrowed(&account, BTreeSet::new())
+        .unwrap()
+        .remove(&*MFX_SYMBOL)
+        .unwrap_or_default()
+}
+
+#[test]
+fn create_locks_funds() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+
+    let escrow = create_(&mut setup, payer, identity(2), None);
+    assert_eq!(setup.balance_(payer), 900u32);
+    assert_eq!(escrowed(&setup, payer), 100u32);
+
+    let i = info(&setup, escrow);
+    assert_eq!(i.state, EscrowState::Pending);
+    assert_eq!(i.payer, payer);
+    assert_eq!(i.payee, identity(2));
+
+    // Locked funds cannot be spent by anyone.
+    assert!(setup
+        .send_as(payer, escrow, identity(3), 1u32, *MFX_SYMBOL)
+        .is_err());
+    assert!(setup.send(payer, identity(3), 901u32, *MFX_SYMBOL).is_err());
+}
+
+#[test]
+fn create_invalid() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+
+    assert_many_err(
+        create(&mut setup, payer, identity(2), None, 1_001),
+        error::insufficient_funds(),
+    );
+    assert_many_err(
+        create(&mut setup, payer, identity(2), None, 0),
+        error::amount_is_zero(),
+    );
+    assert_many_err(
+        create(&mut setup, payer, payer, None, 1),
+        error::destination_is_source(),
+    );
+    assert_many_err(
+        setup
+            .module_impl
+            .escrow_create(
+                &payer,
+                EscrowCreateArgs {
+                    from: None,
+                    payee: identity(2),
+                    symbol: *MFX_SYMBOL,
+                    amount: 1u32.into(),
+                    timeout_in_secs: 0,
+                    arbiter: None,
+                },
+            )
+            .map(|r| r.escrow),
+        error::invalid_escrow_timeout(),
+    );
+    assert_eq!(setup.balance_(payer), 1_000u32);
+}
+
+#[test]
+fn release_by_payer() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+    let escrow = create_(&mut setup, payer, identity(2), None);
+
+    // The payee cannot release funds to themselves.
+    assert_many_err(
+        release(&mut setup, identity(2), escrow),
+        error::unauthorized(),
+    );
+
+    assert!(release(&mut setup, payer, escrow).is_ok());
+    assert_eq!(setup.balance_(identity(2)), 100u32);
+    assert_eq!(escrowed(&setup, payer), 0u32);
+    assert_eq!(info(&setup, escrow).state, EscrowState::Released);
+
+    assert_many_err(
+        release(&mut setup, payer, escrow),
+        error::escrow_already_settled(),
+    );
+    assert_many_err(
+        refund(&mut setup, payer, escrow),
+        error::escrow_already_settled(),
+    );
+}
+
+/// Creating an escrow pays the send fee, and a creation the payer cannot pay
+/// for leaves the state as it was, including the next escrow ID.
+#[test]
+fn create_with_fee() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+    for (name, value) in [
+        ("send.fee", ParamValue::Amount(5u32.into())),
+        ("send.feeCollector", ParamValue::Address(identity(9))),
+    ] {
+        setup
+            .module_impl
+            .set_param(
+                &LEDGER_IDENTITY,
+                SetParamArgs {
+                    name: name.to_string(),
+                    value,
+                },
+            )
+            .unwrap();
+    }
+
+    assert_many_err(
+        create(&mut setup, payer, identity(2), None, 996),
+        error::insufficient_funds(),
+    );
+    assert_eq!(setup.balance_(payer), 1_000u32);
+
+    let escrow = create(&mut setup, payer, identity(2), None, 995).unwrap();
+    assert_eq!(setup.balance_(payer), 0u32);
+    assert_eq!(setup.balance_(identity(9)), 5u32);
+    assert_eq!(escrowed(&setup, payer), 995u32);
+
+    let mut other = Setup::new(false);
+    let other_payer = other.id;
+    other.set_balance(other_payer, 1_000, *MFX_SYMBOL);
+    assert_eq!(escrow, create_(&mut other, other_payer, identity(2), None));
+}
+
+/// The escrow holds exactly the escrowed funds, so settling it does not
+/// pay the send fee.
+#[test]
+fn release_without_fee() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+    let escrow = create_(&mut setup, payer, identity(2), None);
+
+    for (name, value) in [
+        ("send.fee", ParamValue::Amount(5u32.into())),
+        ("send.feeCollector", ParamValue::Address(identity(9))),
+    ] {
+        setup
+            .module_impl
+            .set_param(
+                &LEDGER_IDENTITY,
+                SetParamArgs {
+                    name: name.to_string(),
+                    value,
+                },
+            )
+            .unwrap();
+    }
+
+    assert!(release(&mut setup, payer, escrow).is_ok());
+    assert_eq!(setup.balance_(identity(2)), 100u32);
+    assert_eq!(setup.balance_(identity(9)), 0u32);
+    assert_eq!(info(&setup, escrow).state, EscrowState::Released);
+}
+
+#[test]
+fn release_by_arbiter() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+    let escrow = create_(&mut setup, payer, identity(2), Some(identity(3)));
+
+    assert!(release(&mut setup, identity(3), escrow).is_ok());
+    assert_eq!(setup.balance_(identity(2)), 100u32);
+    assert_eq!(info(&setup, escrow).state, EscrowState::Released);
+}
+
+#[test]
+fn refund_by_arbiter() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+    let escrow = create_(&mut setup, payer, identity(2), Some(identity(3)));
+
+    // The payer needs to wait for the timeout.
+    assert_many_err(
+        refund(&mut setup, payer, escrow),
+        error::escrow_not_expired(),
+    );
+    assert_many_err(
+        refund(&mut setup, identity(2), escrow),
+        error::unauthorized(),
+    );
+
+    assert!(refund(&mut setup, identity(3), escrow).is_ok());
+    assert_eq!(setup.balance_(payer), 1_000u32);
+    assert_eq!(setup.balance_(identity(2)), 0u32);
+    assert_eq!(escrowed(&setup, payer), 0u32);
+    assert_eq!(info(&setup, escrow).state, EscrowState::Refunded);
+}
+
+#[test]
+fn refund_by_payer_after_timeout() {
+    let mut setup = Setup::new(true);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+
+    let (_, escrow) = setup.block(|setup| create_(setup, payer, identity(2), None));
+    setup.inc_time(TIMEOUT_IN_SECS - 1);
+
+    setup.block(|setup| {
+        assert!(refund(setup, payer, escrow).is_ok());
+    });
+    assert_eq!(setup.balance_(payer), 1_000u32);
+    assert_eq!(info(&setup, escrow).state, EscrowState::Refunded);
+}
+
+#[test]
+fn refund_at_timeout() {
+    let mut setup = Setup::new(true);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+
+    let (_, escrow) = setup.block(|setup| create_(setup, payer, identity(2), None));
+    setup.block(|_| {});
+    assert_eq!(info(&setup, escrow).state, EscrowState::Pending);
+
+    setup.inc_time(TIMEOUT_IN_SECS);
+    setup.block(|_| {});
+    assert_eq!(info(&setup, escrow).state, EscrowState::Refunded);
+    assert_eq!(setup.balance_(payer), 1_000u32);
+    assert_eq!(escrowed(&setup, payer), 0u32);
+}
+
+/// A release in the block right before the timeout goes through, and the
+/// timeout processing leaves the escrow alone.
+#[test]
+fn release_before_timeout() {
+    let mut setup = Setup::new(true);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+
+    let (_, escrow) = setup.block(|setup| create_(setup, payer, identity(2), None));
+    setup.inc_time(TIMEOUT_IN_SECS - 2);
+    setup.block(|setup| {
+        assert!(release(setup, payer, escrow).is_ok());
+    });
+
+    setup.inc_time(TIMEOUT_IN_SECS);
+    setup.block(|_| {});
+    assert_eq!(info(&setup, escrow).state, EscrowState::Released);
+    assert_eq!(setup.balance_(identity(2)), 100u32);
+    assert_eq!(setup.balance_(payer), 900u32);
+}
+
+/// A release in the same block as the timeout fails, and the escrow is
+/// refunded when the block is committed.
+#[test]
+fn release_and_timeout_same_block() {
+    let mut setup = Setup::new(true);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+
+    let (_, escrow) = setup.block(|setup| create_(setup, payer, identity(2), Some(identity(3))));
+    setup.inc_time(TIMEOUT_IN_SECS - 1);
+    setup.block(|setup| {
+        assert_many_err(release(setup, payer, escrow), error::escrow_expired());
+        assert_many_err(release(setup, identity(3), escrow), error::escrow_expired());
+    });
+
+    assert_eq!(info(&setup, escrow).state, EscrowState::Refunded);
+    assert_eq!(setup.balance_(identity(2)), 0u32);
+    assert_eq!(setup.balance_(payer), 1_000u32);
+}
+
+#[test]
+fn events_for_transitions() {
+    let mut setup = Setup::new(false);
+    let payer = setup.id;
+    setup.set_balance(payer, 1_000, *MFX_SYMBOL);
+
+    let escrow = create_(&mut setup, payer, identity(2), None);
+    release(&mut setup, payer, escrow).unwrap();
+
+    let list = setup
+        .module_impl
+        .list(events::ListArgs {
+            count: None,
+            order: None,
+            filter: None,
+        })
+        .unwrap();
+    assert_eq!(list.nb_events, 2);
+    assert!(list.events.iter().all(|e| e.is_about(&escrow)));
+}
@@ -0,0 +1,230 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::storage::LedgerStorage;
+use many_modules::events::{self, EventId, EventInfo, EventLog};
+use many_modules::ledger::LedgerCommandsModuleBackend;
+use many_modules::ManyModule;
+use many_protocol::RequestMessageBuilder;
+use many_storage::event_schema::{
+    decode_event, decoded_versions, encode_event, EventSchemaModule, EVENT_SCHEMA,
+    EVENT_SCHEMA_VERSION, OLDEST_EVENT_SCHEMA_VERSION,
+};
+use many_types::cbor::CborAny;
+use many_types::ledger::TokenAmount;
+use many_types::{CborRange, SortOrder, Timestamp};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+/// One event of each payload logged by the ledger.
+///
+/// The payloads are listed field by field, so this stops compiling when one
+/// of them changes. When it does, bump `EVENT_SCHEMA_VERSION`, add a decoder
+/// upgrading the previous version, and add the new version to `fixture`.
+fn samples() -> Vec<EventLog> {
+    let contents = vec![
+        EventInfo::Send {
+            from: identity(1),
+            to: identity(2),
+            symbol: *MFX_SYMBOL,
+            amount: 1_000u64.into(),
+        },
+        EventInfo::AccountDisable {
+            account: identity(3),
+        },
+        EventInfo::AccountSetDescription {
+            account: identity(3),
+            description: "Treasury".to_string(),
+        },
+        EventInfo::AccountMultisigApprove {
+            account: identity(3),
+            token: vec![1, 2, 3].into(),
+            approver: identity(1),
+        },
+    ];
+    contents
+        .into_iter()
+        .enumerate()
+        .map(|(i, content)| EventLog {
+            id: EventId::from(vec![1, i as u8]),
+            time: Timestamp::new(1_700_000_000 + i as u64).unwrap(),
+            content,
+        })
+        .collect()
+}
+
+/// An event as stored with a schema version.
+fn fixture(version: u64, event: &EventLog) -> Vec<u8> {
+    let payload = minicbor::to_vec(event).unwrap();
+    match version {
+        // Unversioned.
+        0 => payload,
+        // `[1, event]`.
+        1 => [vec![0x82, 0x01], payload].concat(),
+        _ => panic!("No fixture for the event schema version {}.", version),
+    }
+}
+
+#[test]
+fn decoders_cover_versions() {
+    assert_eq!(
+        decoded_versions() as u64,
+        EVENT_SCHEMA_VERSION - OLDEST_EVENT_SCHEMA_VERSION + 1
+    );
+}
+
+#[test]
+fn encode_versions() {
+    for event in samples() {
+        for version in [OLDEST_EVENT_SCHEMA_VERSION, EVENT_SCHEMA_VERSION] {
+            assert_eq!(
+                encode_event(&event, version).unwrap(),
+                fixture(version, &event)
+            );
+        }
+        assert!(encode_event(&event, EVENT_SCHEMA_VERSION + 1).is_err());
+    }
+}
+
+#[test]
+fn versioned_after_migration() {
+    let symbol = Address::anonymous();
+    let balances = BTreeMap::from([(
+        identity(0),
+        BTreeMap::from([(symbol, TokenAmount::from(1000u16))]),
+    )]);
+    let mut storage = LedgerStorage::new(
+        BTreeMap::from([(symbol, "MFX".to_string())]),
+        balances,
+        tempfile::tempdir().unwrap(),
+        identity(2),
+        true,
+        None,
+        None,
+    )
+    .unwrap()
+    .with_migrations(json5::from_str(r#"[{ type: "VersionedEvents", block_height: 2 }]"#).unwrap());
+
+    for _ in 0..3 {
+        storage
+            .send(
+                &identity(0),
+                &identity(1),
+                &symbol,
+                TokenAmount::from(100u16),
+            )
+            .unwrap();
+        storage.commit();
+    }
+
+    let stored: Vec<Vec<u8>> = storage
+        .iter(
+            CborRange {
+                start: Bound::Unbounded,
+                end: Bound::Unbounded,
+            },
+            SortOrder::Ascending,
+        )
+        .into_iter()
+        .map(|item| item.unwrap().1)
+        .collect();
+    let events: Vec<EventLog> = stored.iter().map(|v| decode_event(v).unwrap()).collect();
+
+    // Events are logged unversioned until the migration is active.
+    assert_eq!(stored[0], fixture(OLDEST_EVENT_SCHEMA_VERSION, &events[0]));
+    assert_eq!(stored[1], fixture(OLDEST_EVENT_SCHEMA_VERSION, &events[1]));
+    assert_eq!(stored[2], fixture(EVENT_SCHEMA_VERSION, &events[2]));
+}
+
+#[test]
+fn decode_every_version() {
+    for event in samples() {
+        let expected = minicbor::to_vec(&event).unwrap();
+        for version in OLDEST_EVENT_SCHEMA_VERSION..=EVENT_SCHEMA_VERSION {
+            let decoded = decode_event(&fixture(version, &event)).unwrap();
+            assert_eq!(minicbor::to_vec(&decoded).unwrap(), expected, "{}", version);
+        }
+    }
+}
+
+#[test]
+fn decode_unknown_version() {
+    let event = &samples()[0];
+    let bytes = [
+        minicbor::to_vec(EVENT_SCHEMA_VERSION + 1).unwrap(),
+        minicbor::to_vec(event).unwrap(),
+    ]
+    .concat();
+    assert!(decode_event(&[vec![0x82], bytes].concat()).is_err());
+}
+
+#[tokio::test]
+async fn info_versions() {
+    let Setup {
+        mut module_impl,
+        id,
+        ..
+    } = setup();
+    module_impl.set_balance_only_for_testing(id, 1_000, *MFX_SYMBOL);
+    module_impl
+        .send(
+            &id,
+            many_modules::ledger::SendArgs {
+                from: Some(id),
+                to: identity(1),
+                amount: 10u16.into(),
+                symbol: *MFX_SYMBOL,
+            },
+        )
+        .unwrap();
+    let backend = Arc::new(Mutex::new(module_impl));
+    let module = EventSchemaModule::new(events::EventsModule::new(backend));
+    let request = |method: &str, data: Vec<u8>| {
+        RequestMessageBuilder::default()
+            .from(id)
+            .method(method.to_string())
+            .data(data)
+            .build()
+            .unwrap()
+    };
+
+    let response = module
+        .execute(request(
+            "events.info",
+            minicbor::to_vec(events::InfoArgs {}).unwrap(),
+        ))
+        .await
+        .unwrap();
+    assert!(response.data.is_ok());
+    assert_eq!(
+        response
+            .attributes
+            .get_attribute(EVENT_SCHEMA.id)
+            .unwrap()
+            .arguments,
+        vec![
+            CborAny::Int(OLDEST_EVENT_SCHEMA_VERSION as i64),
+            CborAny::Int(EVENT_SCHEMA_VERSION as i64)
+        ]
+    );
+
+    // Events logged with the current version are listed as before.
+    let response = module
+        .execute(request(
+            "events.list",
+            minicbor::to_vec(events::ListArgs {
+                count: None,
+                order: None,
+                filter: None,
+            })
+            .unwrap(),
+        ))
+        .await
+        .unwrap();
+    let returns: events::ListReturns = minicbor::decode(&response.data.unwrap()).unwrap();
+    assert_eq!(returns.events.len(), 1);
+    assert!(response.attributes.get_attribute(EVENT_SCHEMA.id).is_none());
+}
@@ -0,0 +1,332 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::module::expiry::{
+    check, window, Clock, ExpiryModule, ReplayProtection, ReplayProtectionArgs,
+    ReplayProtectionModule, REPLAY_PROTECTION,
+};
+use many_ledger::module::governance::{
+    LedgerGovernanceModuleBackend, Param, ParamValue, SetParamArgs,
+};
+use many_ledger::module::LedgerModuleImpl;
+use many_modules::ledger::{BalanceArgs, LedgerCommandsModule, LedgerModule, SendArgs};
+use many_modules::ManyModule;
+use many_protocol::{RequestMessage, RequestMessageBuilder, ResponseMessage};
+use many_types::Timestamp;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+fn at(secs: u64) -> Timestamp {
+    Timestamp::new(secs).unwrap()
+}
+
+/// The error of a `ledger.send` at `timestamp`, outside of a 300 seconds
+/// window around `now`.
+fn stale(timestamp: u64, now: u64) -> many_error::ManyError {
+    error::message_stale(
+        "ledger.send".to_string(),
+        timestamp,
+        now,
+        now - 300,
+        now + 300,
+    )
+}
+
+#[test]
+fn boundaries() {
+    let now = at(1_000_000);
+    let send = |timestamp| check("ledger.send", timestamp, now, Some(300));
+
+    assert_eq!(send(Some(now)), Ok(()));
+    assert_eq!(send(Some(at(1_000_000 - 300))), Ok(()));
+    assert_eq!(send(Some(at(1_000_000 + 300))), Ok(()));
+    assert_eq!(
+        send(Some(at(1_000_000 - 301))),
+        Err(stale(999_699, 1_000_000))
+    );
+    assert_eq!(
+        send(Some(at(1_000_000 + 301))),
+        Err(stale(1_000_301, 1_000_000))
+    );
+    assert_eq!(
+        send(None),
+        Err(error::message_expired(
+            "ledger.send".to_string(),
+            "300".to_string(),
+        ))
+    );
+
+    // Without a window, any timestamp is accepted.
+    assert_eq!(check("ledger.send", Some(at(0)), now, None), Ok(()));
+    assert_eq!(check("ledger.send", None, now, None), Ok(()));
+}
+
+#[test]
+fn overrides() {
+    let overrides = BTreeMap::from([
+        ("ledger.send".to_string(), 300),
+        ("account.multisigApprove".to_string(), 86_400),
+        ("account.multisigExecute".to_string(), 0),
+    ]);
+
+    assert_eq!(window(600, &overrides, "ledger.send"), Some(300));
+    assert_eq!(
+        window(600, &overrides, "account.multisigApprove"),
+        Some(86_400)
+    );
+    assert_eq!(window(600, &overrides, "account.create"), Some(600));
+    assert_eq!(window(600, &overrides, "account.multisigExecute"), None);
+    assert_eq!(window(0, &overrides, "ledger.send"), Some(300));
+    assert_eq!(window(0, &overrides, "account.create"), None);
+
+    assert_eq!(
+        Param::TxExpiryOverrides.value_from_json(&json!({ "ledger.send": 300 })),
+        Ok(ParamValue::IntegerMap(BTreeMap::from([(
+            "ledger.send".to_string(),
+            300
+        )])))
+    );
+    assert!(Param::TxExpiryOverrides
+        .value_from_json(&json!({ "ledger.send": -1 }))
+        .is_err());
+    assert!(Param::TxExpiryOverrides
+        .validate(&ParamValue::IntegerMap(BTreeMap::from([(
+            String::new(),
+            1
+        )])))
+        .is_err());
+}
+
+fn wall_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn request_at(from: Address, method: &str, data: Vec<u8>, timestamp: u64) -> RequestMessage {
+    let mut message = RequestMessageBuilder::default()
+        .from(from)
+        .method(method.to_string())
+        .data(data)
+        .build()
+        .unwrap();
+    message.timestamp = Some(at(timestamp));
+    message
+}
+
+fn request(from: Address, method: &str, data: Vec<u8>, age: u64) -> RequestMessage {
+    request_at(from, method, data, wall_clock() - age)
+}
+
+fn send_at(id: Address, timestamp: u64) -> RequestMessage {
+    let args = SendArgs {
+        from: Some(id),
+        to: identity(1),
+        symbol: *MFX_SYMBOL,
+        amount: 10u64.into(),
+    };
+    request_at(
+        id,
+        "ledger.send",
+        minicbor::to_vec(args).unwrap(),
+        timestamp,
+    )
+}
+
+fn set_window(backend: &Mutex<LedgerModuleImpl>, window: u64) {
+    backend
+        .lock()
+        .unwrap()
+        .set_param(
+            &LEDGER_IDENTITY,
+            SetParamArgs {
+                name: "tx.expiryInSecs".to_string(),
+                value: ParamValue::Integer(window),
+            },
+        )
+        .unwrap();
+}
+
+/// The window of the server if it rejected a message as stale.
+fn window_of(response: ResponseMessage) -> Option<u64> {
+    let e = response.data.err()?;
+    if e.code() != error::message_stale("", "", "", "", "").code() {
+        return None;
+    }
+    let now: i128 = e.argument("now")?.parse().ok()?;
+    let not_after: i128 = e.argument("not_after")?.parse().ok()?;
+    Some((not_after - now) as u64)
+}
+
+#[tokio::test]
+async fn module() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let commands = ExpiryModule::new(LedgerCommandsModule::new(backend.clone()), backend.clone());
+    let queries = ExpiryModule::new(LedgerModule::new(backend.clone()), backend.clone());
+    let send = |age| send_at(id, wall_clock() - age);
+    let set = |name: &str, value| {
+        backend
+            .lock()
+            .unwrap()
+            .set_param(
+                &LEDGER_IDENTITY,
+                SetParamArgs {
+                    name: name.to_string(),
+                    value,
+                },
+            )
+            .unwrap();
+    };
+
+    // Any timestamp by default.
+    let response = commands.execute(send(100_000)).await.unwrap();
+    assert!(response.data.is_ok());
+
+    set("tx.expiryInSecs", ParamValue::Integer(3600));
+    let response = commands.execute(send(100_000)).await.unwrap();
+    assert_eq!(window_of(response), Some(3600));
+    let response = commands.execute(send(600)).await.unwrap();
+    assert!(response.data.is_ok());
+
+    // The override of the method takes precedence.
+    set(
+        "tx.expiryOverrides",
+        ParamValue::IntegerMap(BTreeMap::from([("ledger.send".to_string(), 300)])),
+    );
+    let response = commands.execute(send(600)).await.unwrap();
+    assert_eq!(window_of(response), Some(300));
+
+    // Queries are not checked.
+    let balance = request(
+        id,
+        "ledger.balance",
+        minicbor::to_vec(BalanceArgs {
+            account: None,
+            symbols: None,
+        })
+        .unwrap(),
+        100_000,
+    );
+    let response = queries.execute(balance).await.unwrap();
+    assert!(response.data.is_ok());
+}
+
+/// Behind the ABCI, the timestamps are checked against the block time, far
+/// from the wall clock here, with the same bounds as directly.
+#[tokio::test]
+async fn abci_path() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    setup.inc_time(1_000_000);
+    setup.block(|_| ());
+    let block_time = 1_000_001;
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let commands = ExpiryModule::new(LedgerCommandsModule::new(backend.clone()), backend.clone());
+    set_window(&backend, 300);
+
+    for timestamp in [block_time - 300, block_time, block_time + 300] {
+        let response = commands.execute(send_at(id, timestamp)).await.unwrap();
+        assert!(response.data.is_ok(), "At {}", timestamp);
+    }
+    for timestamp in [block_time - 301, block_time + 301, wall_clock()] {
+        let response = commands.execute(send_at(id, timestamp)).await.unwrap();
+        assert_eq!(
+            response.data,
+            Err(error::message_stale(
+                "ledger.send".to_string(),
+                timestamp,
+                block_time,
+                block_time - 300,
+                block_time + 300
+            ))
+        );
+    }
+}
+
+/// Directly, the timestamps are checked against the wall clock. It can tick
+/// between the message and its check, so the bounds are off by one second
+/// towards the inside.
+#[tokio::test]
+async fn direct_path() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let commands = ExpiryModule::new(LedgerCommandsModule::new(backend.clone()), backend.clone());
+    set_window(&backend, 300);
+
+    let now = wall_clock();
+    for timestamp in [now - 299, now, now + 300] {
+        let response = commands.execute(send_at(id, timestamp)).await.unwrap();
+        assert!(response.data.is_ok(), "At {}", timestamp);
+    }
+    let now = wall_clock();
+    for timestamp in [now - 301, now + 302, 1_000_000] {
+        let response = commands.execute(send_at(id, timestamp)).await.unwrap();
+        assert_eq!(window_of(response), Some(300), "At {}", timestamp);
+    }
+}
+
+async fn query(module: &ReplayProtectionModule<LedgerModuleImpl>) -> ReplayProtection {
+    let request = RequestMessageBuilder::default()
+        .method("ledger.replayProtection".to_string())
+        .data(minicbor::to_vec(ReplayProtectionArgs {}).unwrap())
+        .build()
+        .unwrap();
+    let response = module.execute(request).await.unwrap();
+    minicbor::decode(&response.data.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn replay_protection() {
+    let backend = Arc::new(Mutex::new(setup().module_impl));
+    let module = ReplayProtectionModule::new(backend.clone(), Clock::Block);
+    let expected = |window, overrides| ReplayProtection {
+        clock: Clock::Block,
+        window,
+        overrides,
+        nonce_required: false,
+        nonce_retention: 0,
+    };
+    assert_eq!(query(&module).await, expected(0, BTreeMap::new()));
+    assert_eq!(module.info().attribute, Some(REPLAY_PROTECTION));
+
+    // The response follows the governance parameters.
+    set_window(&backend, 300);
+    backend
+        .lock()
+        .unwrap()
+        .set_param(
+            &LEDGER_IDENTITY,
+            SetParamArgs {
+                name: "tx.expiryOverrides".to_string(),
+                value: ParamValue::IntegerMap(BTreeMap::from([(
+                    "account.multisigApprove".to_string(),
+                    86_400,
+                )])),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        query(&module).await,
+        expected(
+            300,
+            BTreeMap::from([("account.multisigApprove".to_string(), 86_400)])
+        )
+    );
+}
@@ -0,0 +1,379 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::json::InitialStateJson;
+use many_ledger::module::governance::{
+    ChangesArgs, LedgerGovernanceModuleBackend, Param, ParamType, ParamValue, ParamsArgs,
+    SetParamArgs,
+};
+use many_ledger::module::LedgerModuleImpl;
+use many_ledger::storage::MINIMUM_EVENT_RETENTION_BLOCKS;
+use many_modules::account::features::multisig;
+use many_modules::account::AccountModuleBackend;
+use many_modules::events::EventsModuleBackend;
+use many_modules::{account, events};
+use many_types::ledger::TokenAmount;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+fn set(
+    setup: &mut Setup,
+    sender: &Address,
+    name: &str,
+    value: ParamValue,
+) -> Result<(), many_error::ManyError> {
+    setup
+        .module_impl
+        .set_param(
+            sender,
+            SetParamArgs {
+                name: name.to_string(),
+                value,
+            },
+        )
+        .map(|_| ())
+}
+
+fn value(setup: &Setup, name: &str) -> ParamValue {
+    setup
+        .module_impl
+        .params(&identity(1), ParamsArgs {})
+        .unwrap()
+        .params[name]
+        .value
+        .clone()
+}
+
+#[test]
+fn defaults() {
+    let setup = Setup::new(false);
+    let params = setup
+        .module_impl
+        .params(&identity(1), ParamsArgs {})
+        .unwrap()
+        .params;
+
+    assert_eq!(params.len(), Param::ALL.len());
+    assert!(params.values().all(|p| p.is_default));
+    assert_eq!(params["send.fee"].param_type, ParamType::Amount);
+    assert_eq!(
+        params["governance"].value,
+        ParamValue::Address(*LEDGER_IDENTITY)
+    );
+    assert_eq!(
+        params["events.retentionBlocks"].value,
+        ParamValue::Integer(0)
+    );
+    assert_eq!(
+        params["validators.admins"].value,
+        ParamValue::AddressSet(BTreeSet::new())
+    );
+    assert_eq!(
+        params["validators.quorumMargin"].value,
+        ParamValue::Integer(67)
+    );
+}
+
+#[test]
+fn set_and_record_change() {
+    let mut setup = Setup::new(false);
+    set(
+        &mut setup,
+        &LEDGER_IDENTITY,
+        "multisig.defaultTimeoutInSecs",
+        ParamValue::Integer(3600),
+    )
+    .unwrap();
+    assert_eq!(
+        value(&setup, "multisig.defaultTimeoutInSecs"),
+        ParamValue::Integer(3600)
+    );
+
+    let changes = setup
+        .module_impl
+        .changes(&identity(1), ChangesArgs::default())
+        .unwrap()
+        .changes;
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].name, "multisig.defaultTimeoutInSecs");
+    assert_eq!(changes[0].old_value, ParamValue::Integer(60 * 60 * 24));
+    assert_eq!(changes[0].new_value, ParamValue::Integer(3600));
+    assert_eq!(changes[0].changed_by, *LEDGER_IDENTITY);
+
+    // New multisig accounts use the new default.
+    let account = setup.create_account_(AccountType::Multisig);
+    let info = AccountModuleBackend::info(
+        &setup.module_impl,
+        &identity(1),
+        account::InfoArgs { account },
+    )
+    .unwrap();
+    assert_eq!(
+        info.features
+            .get::<multisig::MultisigAccountFeature>()
+            .unwrap()
+            .arg
+            .timeout_in_secs,
+        Some(3600)
+    );
+}
+
+#[test]
+fn unauthorized() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+    assert_many_err(
+        set(
+            &mut setup,
+            &id,
+            "send.fee",
+            ParamValue::Amount(10u32.into()),
+        ),
+        error::unauthorized(),
+    );
+    assert_eq!(
+        value(&setup, "send.fee"),
+        ParamValue::Amount(TokenAmount::zero())
+    );
+}
+
+#[test]
+fn unknown_parameter() {
+    let mut setup = Setup::new(false);
+    assert_many_err(
+        set(
+            &mut setup,
+            &LEDGER_IDENTITY,
+            "send.feee",
+            ParamValue::Integer(1),
+        ),
+        error::unknown_parameter("send.feee"),
+    );
+    assert!(setup
+        .module_impl
+        .changes(
+            &identity(1),
+            ChangesArgs {
+                name: Some("send.feee".to_string()),
+                count: None,
+            },
+        )
+        .is_err());
+}
+
+#[test]
+fn invalid_values() {
+    let mut setup = Setup::new(false);
+    for (name, value) in [
+        ("send.fee", ParamValue::Integer(10)),
+        ("governance", ParamValue::Address(Address::anonymous())),
+        (
+            "events.retentionBlocks",
+            ParamValue::Integer(MINIMUM_EVENT_RETENTION_BLOCKS - 1),
+        ),
+        ("multisig.defaultTimeoutInSecs", ParamValue::Integer(0)),
+        (
+            "multisig.defaultTimeoutInSecs",
+            ParamValue::Integer(365 * 24 * 60 * 60),
+        ),
+        (
+            "governance",
+            ParamValue::Address(LEDGER_IDENTITY.with_subresource_id(1000).unwrap()),
+        ),
+        (
+            "validators.admins",
+            ParamValue::AddressSet(BTreeSet::from([identity(1), Address::anonymous()])),
+        ),
+        ("validators.admins", ParamValue::Address(identity(1))),
+        ("validators.quorumMargin", ParamValue::Integer(101)),
+    ] {
+        assert!(
+            set(&mut setup, &LEDGER_IDENTITY, name, value.clone()).is_err(),
+            "{name} = {value:?}"
+        );
+    }
+    assert!(setup
+        .module_impl
+        .params(&identity(1), ParamsArgs {})
+        .unwrap()
+        .params
+        .values()
+        .all(|p| p.is_default));
+}
+
+#[test]
+fn governance_account() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+    let account = setup.create_account_(AccountType::Multisig);
+    set(
+        &mut setup,
+        &LEDGER_IDENTITY,
+        "governance",
+        ParamValue::Address(account),
+    )
+    .unwrap();
+
+    // The owner of the account can now change parameters, and the ledger
+    // identity cannot.
+    set(
+        &mut setup,
+        &id,
+        "multisig.defaultExecuteAutomatically",
+        ParamValue::Bool(true),
+    )
+    .unwrap();
+    assert_many_err(
+        set(
+            &mut setup,
+            &LEDGER_IDENTITY,
+            "multisig.defaultExecuteAutomatically",
+            ParamValue::Bool(false),
+        ),
+        error::unauthorized(),
+    );
+    // Other roles of the account cannot.
+    assert_many_err(
+        set(
+            &mut setup,
+            &identity(2),
+            "multisig.defaultExecuteAutomatically",
+            ParamValue::Bool(false),
+        ),
+        error::unauthorized(),
+    );
+}
+
+#[test]
+fn send_fee() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+    setup.set_balance(id, 1000, *MFX_SYMBOL);
+    set(
+        &mut setup,
+        &LEDGER_IDENTITY,
+        "send.fee",
+        ParamValue::Amount(10u32.into()),
+    )
+    .unwrap();
+    set(
+        &mut setup,
+        &LEDGER_IDENTITY,
+        "send.feeCollector",
+        ParamValue::Address(identity(9)),
+    )
+    .unwrap();
+
+    setup.send_(id, identity(2), 100u32);
+    assert_eq!(setup.balance_(id), 890u32);
+    assert_eq!(setup.balance_(identity(2)), 100u32);
+    assert_eq!(setup.balance_(identity(9)), 10u32);
+
+    // The amount and the fee must both be covered.
+    assert_many_err(
+        setup.send(id, identity(2), 885u32, *MFX_SYMBOL),
+        error::insufficient_funds(),
+    );
+    setup.send_(id, identity(2), 880u32);
+    assert_eq!(setup.balance_(id), 0u32);
+
+    // The collector does not pay fees.
+    setup.send_(identity(9), identity(2), 20u32);
+    assert_eq!(setup.balance_(identity(9)), 0u32);
+}
+
+#[test]
+fn event_retention() {
+    let mut setup = Setup::new(true);
+    let id = setup.id;
+    setup.set_balance(id, 1000, *MFX_SYMBOL);
+    setup.block(|setup| {
+        set(
+            setup,
+            &LEDGER_IDENTITY,
+            "events.retentionBlocks",
+            ParamValue::Integer(MINIMUM_EVENT_RETENTION_BLOCKS),
+        )
+        .unwrap();
+        setup.send_(id, identity(2), 10u32);
+    });
+    let count = |setup: &Setup| {
+        setup
+            .module_impl
+            .list(events::ListArgs {
+                count: None,
+                order: None,
+                filter: None,
+            })
+            .unwrap()
+            .events
+            .len()
+    };
+    assert_eq!(count(&setup), 1);
+
+    for _ in 0..MINIMUM_EVENT_RETENTION_BLOCKS {
+        setup.block(|_| {});
+    }
+    assert_eq!(count(&setup), 1);
+    setup.block(|setup| setup.send_(id, identity(2), 10u32));
+    assert_eq!(count(&setup), 1);
+    setup.block(|_| {});
+    assert_eq!(count(&setup), 1);
+
+    // The events count still counts every event.
+    assert_eq!(
+        EventsModuleBackend::info(&setup.module_impl, events::InfoArgs {})
+            .unwrap()
+            .total,
+        2
+    );
+}
+
+#[test]
+fn initial_state() {
+    let mut state = InitialStateJson::read("../../staging/ledger_state.json5")
+        .or_else(|_| InitialStateJson::read("staging/ledger_state.json5"))
+        .expect("Could not read initial state.");
+    state.hash = None;
+    state.params = Some(BTreeMap::from([
+        ("send.fee".to_string(), serde_json::json!(5)),
+        (
+            "send.feeCollector".to_string(),
+            serde_json::json!(identity(9).to_string()),
+        ),
+        (
+            "validators.admins".to_string(),
+            serde_json::json!([identity(3).to_string()]),
+        ),
+    ]));
+
+    let module_impl =
+        LedgerModuleImpl::new(Some(state.clone()), tempfile::tempdir().unwrap(), false).unwrap();
+    let params = module_impl
+        .params(&identity(1), ParamsArgs {})
+        .unwrap()
+        .params;
+    assert_eq!(params["send.fee"].value, ParamValue::Amount(5u32.into()));
+    assert_eq!(
+        params["send.feeCollector"].value,
+        ParamValue::Address(identity(9))
+    );
+    assert_eq!(
+        params["validators.admins"].value,
+        ParamValue::AddressSet(BTreeSet::from([identity(3)]))
+    );
+    assert!(params["governance"].is_default);
+
+    state.params = Some(BTreeMap::from([(
+        "events.retentionBlocks".to_string(),
+        serde_json::json!(true),
+    )]));
+    assert!(LedgerModuleImpl::new(Some(state), tempfile::tempdir().unwrap(), false).is_err());
+}
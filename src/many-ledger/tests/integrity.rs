@@ -0,0 +1,242 @@
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::module::integrity::{
+    section, Finding, IntegrityArgs, IntegrityModule, IntegrityReturns,
+};
+use many_ledger::module::LedgerModuleImpl;
+use many_ledger::storage::LedgerStorage;
+use many_modules::account::features::multisig::{
+    AccountMultisigModuleBackend, MultisigAccountFeature, SubmitTransactionArgs,
+};
+use many_modules::account::features::FeatureInfo;
+use many_modules::account::AccountModuleBackend;
+use many_modules::ledger::LedgerCommandsModuleBackend;
+use many_modules::{account, events, ledger, ManyModule};
+use many_protocol::RequestMessageBuilder;
+use many_storage::integrity::{Corruption, CorruptionKind, Scrubber};
+use merk::rocksdb::{IteratorMode, Options, DB};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+fn symbol() -> Address {
+    identity(1000)
+}
+
+/// Create a ledger with a send and a multisig transaction, and return its
+/// path.
+fn fixture() -> PathBuf {
+    let path = tempfile::tempdir().unwrap().into_path();
+    LedgerStorage::new(
+        BTreeMap::from([(symbol(), "MF0".to_string())]),
+        BTreeMap::from([(
+            identity(5),
+            BTreeMap::from([(symbol(), 1_000_000u64.into())]),
+        )]),
+        path.clone(),
+        identity(666),
+        false,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let mut module_impl = LedgerModuleImpl::new(None, path.clone(), false).unwrap();
+    module_impl
+        .send(
+            &identity(5),
+            ledger::SendArgs {
+                from: None,
+                to: identity(6),
+                symbol: symbol(),
+                amount: 1000u64.into(),
+            },
+        )
+        .unwrap();
+
+    let account = module_impl
+        .create(
+            &identity(5),
+            account::CreateArgs {
+                description: None,
+                roles: None,
+                features: account::features::FeatureSet::from_iter([
+                    MultisigAccountFeature::default().as_feature(),
+                ]),
+            },
+        )
+        .unwrap()
+        .id;
+    module_impl
+        .multisig_submit_transaction(
+            &identity(5),
+            SubmitTransactionArgs {
+                account,
+                memo: None,
+                transaction: Box::new(events::AccountMultisigTransaction::Send(ledger::SendArgs {
+                    from: Some(account),
+                    to: identity(6),
+                    symbol: symbol(),
+                    amount: 1u64.into(),
+                })),
+                threshold: None,
+                timeout_in_secs: None,
+                execute_automatically: None,
+                data: None,
+            },
+        )
+        .unwrap();
+    path
+}
+
+/// Change the raw bytes of the first node of the merk tree under a prefix,
+/// behind its back, and return its key.
+fn corrupt(path: &Path, prefix: &[u8], f: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    let opts = Options::default();
+    let db = DB::open_cf(&opts, path, DB::list_cf(&opts, path).unwrap()).unwrap();
+    let (key, bytes) = db
+        .iterator(IteratorMode::Start)
+        .map(Result::unwrap)
+        .find(|(k, _)| k.starts_with(prefix))
+        .unwrap();
+    let mut bytes = bytes.to_vec();
+    f(&mut bytes);
+    db.put(&key, bytes).unwrap();
+    key.to_vec()
+}
+
+fn flip(bytes: &mut Vec<u8>) {
+    *bytes.last_mut().unwrap() ^= 0xff;
+}
+
+fn scrubber(path: PathBuf, slice_size: u64) -> Scrubber<LedgerModuleImpl> {
+    let module_impl = LedgerModuleImpl::new(None, path.clone(), false).unwrap();
+    Scrubber::new(
+        Arc::new(Mutex::new(module_impl)),
+        path.with_extension("cursor"),
+        slice_size,
+    )
+    .unwrap()
+}
+
+/// Check the whole tree once, and return the corruptions found.
+fn full_pass(scrubber: &mut Scrubber<LedgerModuleImpl>) -> Vec<Corruption> {
+    let metrics = scrubber.metrics();
+    let passes = metrics.passes.load(Ordering::Relaxed);
+    let mut corruptions = vec![];
+    while metrics.passes.load(Ordering::Relaxed) == passes {
+        corruptions.append(&mut scrubber.poll().unwrap().unwrap());
+    }
+    corruptions
+}
+
+#[test]
+fn clean() {
+    let mut scrubber = scrubber(fixture(), 4);
+    assert_eq!(full_pass(&mut scrubber), vec![]);
+
+    let last = scrubber.metrics().last_pass().unwrap();
+    assert!(last.nodes_checked > 10);
+    assert_eq!(
+        last.nodes_checked,
+        scrubber.metrics().nodes_checked.load(Ordering::Relaxed)
+    );
+    assert!(last.corruptions.is_empty());
+}
+
+#[test]
+fn sections() {
+    for (prefix, name) in [
+        (b"/balances/".as_slice(), "balances"),
+        (b"/accounts/".as_slice(), "accounts"),
+        (b"/multisig/".as_slice(), "multisig"),
+        (b"/events/".as_slice(), "events"),
+    ] {
+        let path = fixture();
+        let key = corrupt(&path, prefix, flip);
+
+        let mut scrubber = scrubber(path, 4);
+        let corruptions = full_pass(&mut scrubber);
+        assert_eq!(
+            corruptions,
+            vec![Corruption::new(CorruptionKind::ValueHash, &key, &key)]
+        );
+        assert_eq!(section(&corruptions[0].first), name);
+        assert_eq!(
+            scrubber.metrics().last_pass().unwrap().corruptions,
+            corruptions
+        );
+    }
+}
+
+#[test]
+fn truncated() {
+    let path = fixture();
+    let key = corrupt(&path, b"/balances/", |bytes| bytes.truncate(3));
+
+    let mut scrubber = scrubber(path, 4);
+    assert_eq!(
+        full_pass(&mut scrubber),
+        vec![Corruption::new(CorruptionKind::Undecodable, &key, &key)]
+    );
+}
+
+#[test]
+fn cursor() {
+    let path = fixture();
+    let mut scrubber1 = scrubber(path.clone(), 1);
+    scrubber1.poll().unwrap().unwrap();
+    let cursor = scrubber1.cursor().unwrap().to_vec();
+    drop(scrubber1);
+
+    // A restart resumes the pass where it was.
+    let mut scrubber2 = scrubber(path, 1);
+    assert_eq!(scrubber2.cursor(), Some(cursor.as_slice()));
+    full_pass(&mut scrubber2);
+    let resumed = scrubber2.metrics().last_pass().unwrap().nodes_checked;
+
+    full_pass(&mut scrubber2);
+    assert_eq!(
+        scrubber2.metrics().last_pass().unwrap().nodes_checked,
+        resumed + 1
+    );
+}
+
+#[tokio::test]
+async fn module() {
+    let path = fixture();
+    let key = corrupt(&path, b"/events/", flip);
+    let mut scrubber = scrubber(path, 100);
+    full_pass(&mut scrubber);
+
+    let server = identity(1);
+    let module = IntegrityModule::new(scrubber.metrics(), server);
+    let request = |from: Address| {
+        RequestMessageBuilder::default()
+            .from(from)
+            .method("server.integrity".to_string())
+            .data(minicbor::to_vec(IntegrityArgs {}).unwrap())
+            .build()
+            .unwrap()
+    };
+
+    let response = module.execute(request(server)).await.unwrap();
+    let returns: IntegrityReturns = minicbor::decode(&response.data.unwrap()).unwrap();
+    assert_eq!(returns.passes, 1);
+    assert_eq!(returns.corruptions, 1);
+    assert_eq!(
+        returns.last_pass.unwrap().findings,
+        vec![Finding {
+            kind: "value-hash".to_string(),
+            section: "events".to_string(),
+            first: key.clone().into(),
+            last: key.into(),
+        }]
+    );
+
+    // Only the server itself can read the state of the checks.
+    let response = module.execute(request(identity(2))).await.unwrap();
+    assert_eq!(response.data, Err(ManyError::invalid_from_identity()));
+}
@@ -2,6 +2,7 @@ use many_identity::testing::identity;
 use many_identity::Address;
 use many_ledger::storage::LedgerStorage;
 use many_modules::events::{EventId, EventLog};
+use many_storage::event_schema::decode_event;
 use many_types::ledger::TokenAmount;
 use many_types::{CborRange, SortOrder};
 use std::collections::BTreeMap;
@@ -50,7 +51,7 @@ fn iter_asc(
         .into_iter()
         .map(|item| {
             let (_, v) = item.expect("Error while reading DB");
-            minicbor::decode(&v).expect("Iterator item not an event.")
+            decode_event(&v).expect("Iterator item not an event.")
         })
 }
 
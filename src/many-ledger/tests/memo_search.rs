@@ -0,0 +1,190 @@
+pub mod common;
+
+use common::*;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::json::InitialStateJson;
+use many_ledger::module::memo_search::{
+    LedgerMemoSearchModuleBackend, SearchMemoArgs, MAX_MEMO_SEARCH_COUNT,
+};
+use many_ledger::module::LedgerModuleImpl;
+use many_modules::account::features::multisig::{
+    AccountMultisigModuleBackend, Memo, SubmitTransactionArgs,
+};
+use many_modules::events::{EventId, EventsModuleBackend};
+use many_modules::{events, ledger};
+use std::path::Path;
+
+/// A ledger in a directory that outlives it, with a multisig account.
+fn setup(path: &Path) -> (Setup, Address) {
+    let mut setup = Setup::new(false);
+    setup.module_impl = LedgerModuleImpl::new(
+        Some(
+            InitialStateJson::read("../../staging/ledger_state.json5")
+                .or_else(|_| InitialStateJson::read("staging/ledger_state.json5"))
+                .expect("Could not read initial state."),
+        ),
+        path,
+        false,
+    )
+    .unwrap();
+    let account = setup.create_account_(AccountType::Multisig);
+    (setup, account)
+}
+
+fn submit(module_impl: &mut LedgerModuleImpl, sender: Address, account: Address, memo: &str) {
+    module_impl
+        .multisig_submit_transaction(
+            &sender,
+            SubmitTransactionArgs {
+                account,
+                memo: Some(Memo::try_from(memo.to_string()).unwrap()),
+                transaction: Box::new(events::AccountMultisigTransaction::Send(ledger::SendArgs {
+                    from: Some(account),
+                    to: account,
+                    symbol: *MFX_SYMBOL,
+                    amount: 1u32.into(),
+                })),
+                threshold: None,
+                timeout_in_secs: None,
+                execute_automatically: None,
+                data: None,
+            },
+        )
+        .unwrap();
+}
+
+fn search(
+    module_impl: &LedgerModuleImpl,
+    query: &str,
+    count: Option<u64>,
+    before: Option<EventId>,
+) -> Vec<EventId> {
+    module_impl
+        .search_memo(
+            &Address::anonymous(),
+            SearchMemoArgs {
+                query: query.to_string(),
+                count,
+                before,
+            },
+        )
+        .unwrap()
+        .events
+}
+
+#[test]
+fn disabled() {
+    let setup = Setup::new(false);
+    assert_many_err(
+        setup.module_impl.search_memo(
+            &setup.id,
+            SearchMemoArgs {
+                query: "invoice".to_string(),
+                count: None,
+                before: None,
+            },
+        ),
+        error::memo_index_disabled(),
+    );
+}
+
+#[test]
+fn indexed_as_logged() {
+    let path = tempfile::tempdir().unwrap().into_path();
+    let (mut setup, account) = setup(&path);
+    setup.module_impl = setup.module_impl.with_memo_index(1_000).unwrap();
+    let id = setup.id;
+
+    submit(&mut setup.module_impl, id, account, "Invoice 4217");
+    submit(&mut setup.module_impl, id, account, "Rent for March");
+    submit(
+        &mut setup.module_impl,
+        id,
+        account,
+        "invoice 4217, second payment",
+    );
+
+    let found = search(&setup.module_impl, "invoice 4217", None, None);
+    assert_eq!(found.len(), 2);
+    assert!(found[0] > found[1]);
+    assert_eq!(search(&setup.module_impl, "MARCH", None, None).len(), 1);
+    assert_eq!(search(&setup.module_impl, "4218", None, None).len(), 0);
+
+    // The second page starts after the last result of the first one.
+    let first = search(&setup.module_impl, "invoice", Some(1), None);
+    let second = search(
+        &setup.module_impl,
+        "invoice",
+        Some(1),
+        first.last().cloned(),
+    );
+    assert_eq!([first, second].concat(), found);
+}
+
+#[test]
+fn rebuild_on_startup() {
+    let path = tempfile::tempdir().unwrap().into_path();
+    let found = {
+        let (mut setup, account) = setup(&path);
+        let id = setup.id;
+        submit(&mut setup.module_impl, id, account, "Invoice 4217");
+        submit(&mut setup.module_impl, id, account, "Unrelated");
+        setup
+            .module_impl
+            .list(events::ListArgs {
+                count: None,
+                order: None,
+                filter: None,
+            })
+            .unwrap()
+            .events
+            .into_iter()
+            .find(|e| {
+                matches!(
+                    &e.content,
+                    events::EventInfo::AccountMultisigSubmit { memo: Some(m), .. }
+                        if m == &Memo::try_from("Invoice 4217".to_string()).unwrap()
+                )
+            })
+            .unwrap()
+            .id
+    };
+
+    // The feature is enabled on a node with an existing event log.
+    let module_impl = LedgerModuleImpl::new(None, &path, false)
+        .unwrap()
+        .with_memo_index(1_000)
+        .unwrap();
+    assert_eq!(search(&module_impl, "invoice", None, None), vec![found]);
+}
+
+#[test]
+fn results_are_capped() {
+    let path = tempfile::tempdir().unwrap().into_path();
+    let (mut setup, account) = setup(&path);
+    setup.module_impl = setup.module_impl.with_memo_index(10_000).unwrap();
+    let id = setup.id;
+    for i in 0..=MAX_MEMO_SEARCH_COUNT {
+        submit(
+            &mut setup.module_impl,
+            id,
+            account,
+            &format!("payment {}", i),
+        );
+    }
+
+    let returns = setup
+        .module_impl
+        .search_memo(
+            &id,
+            SearchMemoArgs {
+                query: "payment".to_string(),
+                count: Some(1_000),
+                before: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(returns.events.len() as u64, MAX_MEMO_SEARCH_COUNT);
+    assert!(returns.more);
+}
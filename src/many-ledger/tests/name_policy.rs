@@ -0,0 +1,239 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::json::InitialStateJson;
+use many_ledger::module::name_policy::{
+    comparison_key, CharacterClass, CheckSymbolNameArgs, LedgerNamePolicyModuleBackend,
+    NamePolicyArgs, NamesDigestArgs, SetNamePolicyArgs, SymbolNamePolicy,
+};
+use many_ledger::module::LedgerModuleImpl;
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+fn policy() -> SymbolNamePolicy {
+    SymbolNamePolicy {
+        reserved_prefixes: vec!["MF".to_string()],
+        denied_names: vec!["USD".to_string()],
+        allowed_characters: BTreeSet::from([
+            CharacterClass::AsciiUppercase,
+            CharacterClass::AsciiDigit,
+        ]),
+        min_length: 2,
+        max_length: 8,
+    }
+}
+
+fn set_policy(setup: &mut Setup, policy: SymbolNamePolicy) {
+    setup
+        .module_impl
+        .set_name_policy(&LEDGER_IDENTITY, SetNamePolicyArgs { policy })
+        .expect("Could not set the name policy");
+}
+
+fn check(module_impl: &LedgerModuleImpl, symbol: Option<Address>, name: &str) -> bool {
+    module_impl
+        .check_symbol_name(
+            &identity(1),
+            CheckSymbolNameArgs {
+                name: name.to_string(),
+                symbol,
+            },
+        )
+        .is_ok()
+}
+
+#[test]
+fn normalization() {
+    // Fullwidth letters and ligatures are compatibility characters.
+    assert_eq!(comparison_key("ＭＦＸ"), "mfx");
+    assert_eq!(comparison_key("ﬁat"), "fiat");
+    // Composed and decomposed forms are the same.
+    assert_eq!(comparison_key("CAFÉ"), comparison_key("CAFE\u{301}"));
+}
+
+#[test]
+fn case_insensitive() {
+    let policy = SymbolNamePolicy::default();
+    assert!(policy.check("ABC", ["MFX"]).is_ok());
+    assert_eq!(
+        policy.check("mfx", ["MFX"]),
+        Err(error::symbol_name_taken("mfx", "MFX"))
+    );
+    assert_eq!(
+        policy.check("ＭｆＸ", ["MFX"]),
+        Err(error::symbol_name_taken("ＭｆＸ", "MFX"))
+    );
+}
+
+#[test]
+fn rules() {
+    let policy = policy();
+    assert!(policy.check("ABC1", []).is_ok());
+    assert_eq!(
+        policy.check("A", []),
+        Err(error::symbol_name_length("A", 2, 8))
+    );
+    assert_eq!(
+        policy.check("ABCDEFGHI", []),
+        Err(error::symbol_name_length("ABCDEFGHI", 2, 8))
+    );
+    assert_eq!(
+        policy.check("ABc", []),
+        Err(error::symbol_name_character("ABc", 'c'.escape_unicode()))
+    );
+    // Fullwidth letters are not ASCII, even if they normalize to ASCII.
+    assert_eq!(
+        policy.check("ＡＢ", []),
+        Err(error::symbol_name_character("ＡＢ", 'Ａ'.escape_unicode()))
+    );
+    assert_eq!(
+        policy.check("MFY", []),
+        Err(error::symbol_name_reserved("MFY", "MF"))
+    );
+    assert_eq!(
+        policy.check("USD", []),
+        Err(error::symbol_name_denied("USD"))
+    );
+}
+
+#[test]
+fn reserved_and_denied_any_case() {
+    let policy = SymbolNamePolicy {
+        allowed_characters: BTreeSet::new(),
+        ..policy()
+    };
+    assert_eq!(
+        policy.check("mfy", []),
+        Err(error::symbol_name_reserved("mfy", "MF"))
+    );
+    assert_eq!(
+        policy.check("uSd", []),
+        Err(error::symbol_name_denied("uSd"))
+    );
+}
+
+#[test]
+fn invalid_policy() {
+    let mut setup = Setup::new(false);
+    assert!(setup
+        .module_impl
+        .set_name_policy(
+            &LEDGER_IDENTITY,
+            SetNamePolicyArgs {
+                policy: SymbolNamePolicy {
+                    min_length: 5,
+                    max_length: 4,
+                    ..SymbolNamePolicy::default()
+                },
+            },
+        )
+        .is_err());
+}
+
+#[test]
+fn unauthorized() {
+    let mut setup = Setup::new(false);
+    assert_many_err(
+        setup
+            .module_impl
+            .set_name_policy(&identity(1), SetNamePolicyArgs { policy: policy() }),
+        error::unauthorized(),
+    );
+}
+
+#[test]
+fn set_and_get() {
+    let mut setup = Setup::new(false);
+    assert_eq!(
+        setup
+            .module_impl
+            .name_policy(&identity(1), NamePolicyArgs {})
+            .unwrap()
+            .policy,
+        SymbolNamePolicy::default()
+    );
+
+    set_policy(&mut setup, policy());
+    assert_eq!(
+        setup
+            .module_impl
+            .name_policy(&identity(1), NamePolicyArgs {})
+            .unwrap()
+            .policy,
+        policy()
+    );
+}
+
+#[test]
+fn grandfathered() {
+    let mut setup = Setup::new(false);
+    // MFX uses the reserved prefix, but predates the policy.
+    set_policy(&mut setup, policy());
+
+    // It keeps its name, but cannot be renamed into another violation.
+    assert!(check(&setup.module_impl, Some(*MFX_SYMBOL), "ABC"));
+    assert!(!check(&setup.module_impl, Some(*MFX_SYMBOL), "MFY"));
+
+    // Other symbols cannot take its name in any case.
+    assert!(!check(&setup.module_impl, None, "MFX"));
+    set_policy(&mut setup, SymbolNamePolicy::default());
+    assert!(!check(&setup.module_impl, None, "mfx"));
+    assert!(check(&setup.module_impl, Some(*MFX_SYMBOL), "mfx"));
+    assert!(!check(&setup.module_impl, Some(identity(1000)), "ABC"));
+}
+
+#[test]
+fn initial_state() {
+    let mut state = InitialStateJson::read("../../staging/ledger_state.json5")
+        .or_else(|_| InitialStateJson::read("staging/ledger_state.json5"))
+        .expect("Could not read initial state.");
+    state.hash = None;
+    state.symbol_name_policy = Some(policy());
+
+    let module_impl = LedgerModuleImpl::new(Some(state), tempfile::tempdir().unwrap(), false)
+        .expect("Could not create the ledger");
+    assert!(!check(&module_impl, None, "USD"));
+    assert!(check(&module_impl, None, "CAD"));
+}
+
+fn names_digest(symbols: Option<BTreeMap<Address, String>>) -> Vec<u8> {
+    let mut state = InitialStateJson::read("../../staging/ledger_state.json5")
+        .or_else(|_| InitialStateJson::read("staging/ledger_state.json5"))
+        .expect("Could not read initial state.");
+    state.hash = None;
+    if let Some(symbols) = symbols {
+        state.symbols = symbols;
+        state.initial.clear();
+    }
+
+    let module_impl = LedgerModuleImpl::new(Some(state), tempfile::tempdir().unwrap(), false)
+        .expect("Could not create the ledger");
+    module_impl
+        .names_digest(&identity(1), NamesDigestArgs {})
+        .unwrap()
+        .digest
+        .to_vec()
+}
+
+#[test]
+fn digest() {
+    let initial = names_digest(None);
+    assert_eq!(initial.len(), 32);
+    assert_eq!(names_digest(None), initial);
+
+    let renamed = names_digest(Some(BTreeMap::from([(*MFX_SYMBOL, "MFY".to_string())])));
+    assert_ne!(renamed, initial);
+    let added = names_digest(Some(BTreeMap::from([
+        (*MFX_SYMBOL, "MFX".to_string()),
+        (identity(1000), "ABC".to_string()),
+    ])));
+    assert_ne!(added, initial);
+    assert_ne!(added, renamed);
+}
@@ -0,0 +1,112 @@
+pub mod common;
+
+use common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::module::batch::LedgerBatchModule;
+use many_ledger::module::posture::{Posture, PostureArgs, PostureBuilder, PostureModule};
+use many_ledger::module::{AllowAddrsModule, LedgerModuleImpl};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::ledger::{LedgerCommandsModule, LedgerModule};
+use many_modules::ManyModule;
+use many_protocol::RequestMessageBuilder;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+fn builder(backend: &Arc<Mutex<LedgerModuleImpl>>) -> PostureBuilder {
+    let endpoints = backend.lock().unwrap().init().unwrap().endpoints;
+    PostureBuilder::new(endpoints)
+        .with_verifiers(&["anonymous", "cose-key"])
+        .with_listener("many", "127.0.0.1:8000")
+        .with_limit("memo-index", "off")
+}
+
+fn names(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+#[test]
+fn open() {
+    let backend = Arc::new(Mutex::new(setup().module_impl));
+    let mut posture = builder(&backend).with_allow_addrs(None);
+    posture.gated(LedgerBatchModule::new(backend.clone()));
+    posture.gated(LedgerCommandsModule::new(backend.clone()));
+    posture.open(LedgerModule::new(backend.clone()));
+    let posture = posture.build();
+
+    assert_eq!(posture.allow_addrs, None);
+    assert!(!posture.read_only);
+    assert_eq!(posture.commands, names(&["ledger.batch", "ledger.send"]));
+    assert_eq!(posture.anonymous_commands, posture.commands);
+}
+
+#[test]
+fn allow_list() {
+    let backend = Arc::new(Mutex::new(setup().module_impl));
+    let allowed = BTreeSet::from([identity(1)]);
+    let mut posture = builder(&backend).with_allow_addrs(Some(&allowed));
+    posture.gated(LedgerBatchModule::new(backend.clone()).with_allow_addrs(allowed.clone()));
+    posture.gated(AllowAddrsModule {
+        inner: LedgerCommandsModule::new(backend.clone()),
+        allow_addrs: allowed,
+    });
+    let posture = posture.build();
+
+    assert_eq!(posture.allow_addrs, Some(1));
+    assert_eq!(posture.commands, names(&["ledger.batch", "ledger.send"]));
+    assert_eq!(posture.anonymous_commands, BTreeSet::new());
+    assert_eq!(
+        posture.to_string(),
+        "Security posture:\n\
+         \x20 verifiers: anonymous, cose-key\n\
+         \x20 allow list: 1 addresses\n\
+         \x20 read-only: no (2 command endpoints)\n\
+         \x20 commands open to anonymous senders: none\n\
+         \x20 listeners: many=127.0.0.1:8000\n\
+         \x20 limits: memo-index=off"
+    );
+}
+
+#[test]
+fn read_only() {
+    let backend = Arc::new(Mutex::new(setup().module_impl));
+    let mut posture = builder(&backend);
+    posture.open(LedgerModule::new(backend.clone()));
+    let posture = posture.build();
+
+    assert!(posture.read_only);
+    assert!(posture.anonymous_commands.is_empty());
+}
+
+#[tokio::test]
+async fn module() {
+    let server = identity(1);
+    let posture = Posture {
+        verifiers: vec!["anonymous".to_string()],
+        commands: names(&["ledger.send"]),
+        anonymous_commands: names(&["ledger.send"]),
+        ..Default::default()
+    };
+    let module = PostureModule::new(posture.clone(), server);
+    let request = |from: Address| {
+        RequestMessageBuilder::default()
+            .from(from)
+            .method("server.posture".to_string())
+            .data(minicbor::to_vec(PostureArgs {}).unwrap())
+            .build()
+            .unwrap()
+    };
+
+    let response = module.execute(request(server)).await.unwrap();
+    assert_eq!(
+        minicbor::decode::<Posture>(&response.data.unwrap()).unwrap(),
+        posture
+    );
+
+    // Only the server itself can read its posture.
+    let response = module.execute(request(identity(2))).await.unwrap();
+    assert_eq!(response.data, Err(ManyError::invalid_from_identity()));
+    let response = module.execute(request(Address::anonymous())).await.unwrap();
+    assert_eq!(response.data, Err(ManyError::invalid_from_identity()));
+}
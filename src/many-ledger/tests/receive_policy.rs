@@ -0,0 +1,201 @@
+pub mod common;
+
+use common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::migration::Migration;
+use many_ledger::module::receive_policy::{
+    CheckReceiveArgs, LedgerReceivePolicyModuleBackend, ReceivePolicy, ReceivePolicyArgs,
+    SetReceivePolicyArgs,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+fn set_policy(
+    setup: &mut Setup,
+    sender: Address,
+    address: Option<Address>,
+    policy: ReceivePolicy,
+) -> Result<(), ManyError> {
+    setup
+        .module_impl
+        .set_receive_policy(&sender, SetReceivePolicyArgs { address, policy })
+        .map(|_| ())
+}
+
+fn policy(setup: &Setup, address: Address) -> ReceivePolicy {
+    setup
+        .module_impl
+        .receive_policy(&identity(9), ReceivePolicyArgs { address })
+        .unwrap()
+        .policy
+}
+
+fn check(setup: &Setup, from: Address, to: Address, amount: u64) -> Result<(), ManyError> {
+    setup
+        .module_impl
+        .check_receive(
+            &from,
+            CheckReceiveArgs {
+                from: None,
+                to,
+                symbol: *MFX_SYMBOL,
+                amount: amount.into(),
+            },
+        )
+        .map(|_| ())
+}
+
+#[test]
+fn open_by_default() {
+    let mut setup = setup();
+    assert_eq!(policy(&setup, identity(1)), ReceivePolicy::Open);
+
+    set_policy(
+        &mut setup,
+        identity(1),
+        None,
+        ReceivePolicy::Allowlist(BTreeSet::new()),
+    )
+    .unwrap();
+    assert_eq!(
+        policy(&setup, identity(1)),
+        ReceivePolicy::Allowlist(BTreeSet::new())
+    );
+
+    set_policy(&mut setup, identity(1), None, ReceivePolicy::Open).unwrap();
+    assert_eq!(policy(&setup, identity(1)), ReceivePolicy::Open);
+    set_policy(&mut setup, identity(1), None, ReceivePolicy::Open).unwrap();
+}
+
+#[test]
+fn allowlist() {
+    let mut setup = setup();
+    setup.set_balance(identity(2), 1000, *MFX_SYMBOL);
+    setup.set_balance(identity(3), 1000, *MFX_SYMBOL);
+    set_policy(
+        &mut setup,
+        identity(1),
+        None,
+        ReceivePolicy::Allowlist(BTreeSet::from([identity(2)])),
+    )
+    .unwrap();
+
+    let rejected = error::transfer_rejected_by_policy(identity(1), "allowlist");
+    assert_many_err(
+        check(&setup, identity(3), identity(1), 10),
+        rejected.clone(),
+    );
+    assert_many_err(
+        setup.send(identity(3), identity(1), 10u64, *MFX_SYMBOL),
+        rejected,
+    );
+    assert_eq!(setup.balance_(identity(3)), 1000u32);
+
+    assert!(check(&setup, identity(2), identity(1), 10).is_ok());
+    setup.send_(identity(2), identity(1), 10u32);
+    assert_eq!(setup.balance_(identity(1)), 10u32);
+
+    // The protected address can still send.
+    setup.send_(identity(1), identity(3), 5u64);
+}
+
+#[test]
+fn minimum_amount() {
+    let mut setup = setup();
+    setup.set_balance(identity(2), 1000, *MFX_SYMBOL);
+    set_policy(
+        &mut setup,
+        identity(1),
+        None,
+        ReceivePolicy::MinimumAmount(BTreeMap::from([(*MFX_SYMBOL, 100u64.into())])),
+    )
+    .unwrap();
+
+    let rejected = error::transfer_rejected_by_policy(identity(1), "minimum amount");
+    assert_many_err(
+        setup.send(identity(2), identity(1), 99u64, *MFX_SYMBOL),
+        rejected.clone(),
+    );
+    setup.send_(identity(2), identity(1), 100u32);
+    assert_eq!(setup.balance_(identity(1)), 100u32);
+
+    // Symbols without a minimum are rejected.
+    assert_many_err(
+        setup
+            .module_impl
+            .check_receive(
+                &identity(2),
+                CheckReceiveArgs {
+                    from: None,
+                    to: identity(1),
+                    symbol: identity(1000),
+                    amount: 1_000_000u64.into(),
+                },
+            )
+            .map(|_| ()),
+        rejected,
+    );
+}
+
+#[test]
+fn account_owner() {
+    let mut setup = setup();
+    let account_id = setup.create_account_(AccountType::Multisig);
+    let allowlist = ReceivePolicy::Allowlist(BTreeSet::from([identity(2)]));
+
+    assert_many_err(
+        set_policy(&mut setup, identity(2), Some(account_id), allowlist.clone()),
+        error::unauthorized(),
+    );
+    assert_many_err(
+        set_policy(
+            &mut setup,
+            identity(2),
+            Some(identity(1)),
+            allowlist.clone(),
+        ),
+        error::unauthorized(),
+    );
+    assert_eq!(policy(&setup, account_id), ReceivePolicy::Open);
+
+    let owner = setup.id;
+    set_policy(&mut setup, owner, Some(account_id), allowlist.clone()).unwrap();
+    assert_eq!(policy(&setup, account_id), allowlist);
+}
+
+#[test]
+fn multisig_submission() {
+    let mut setup = setup();
+    let account_id = setup.create_account_(AccountType::Multisig);
+    setup.set_balance(account_id, 1000, *MFX_SYMBOL);
+    set_policy(
+        &mut setup,
+        identity(1),
+        None,
+        ReceivePolicy::Allowlist(BTreeSet::from([identity(2)])),
+    )
+    .unwrap();
+
+    // Before the migration, the send is only rejected when executed.
+    let token = setup.multisig_send_(account_id, identity(1), 10u32);
+    setup.multisig_approve_(identity(2), &token);
+    setup.multisig_approve_(identity(3), &token);
+    assert_many_err(
+        setup.multisig_execute_(&token).data,
+        error::transfer_rejected_by_policy(identity(1), "allowlist"),
+    );
+
+    let migrations: BTreeSet<Box<dyn Migration>> =
+        json5::from_str(r#"[{ type: "CheckedMultisigSends", block_height: 1 }]"#).unwrap();
+    setup.module_impl = setup.module_impl.with_migrations(migrations);
+    setup.block(|_| {});
+
+    // Rejected when submitted, not when the approvals are in.
+    assert_many_err(
+        setup.multisig_send(account_id, identity(1), 10u64, *MFX_SYMBOL),
+        error::transfer_rejected_by_policy(identity(1), "allowlist"),
+    );
+    setup.multisig_send_(account_id, identity(3), 10u32);
+}
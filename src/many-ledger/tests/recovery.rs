@@ -0,0 +1,278 @@
+pub mod common;
+
+use common::*;
+use many_error::ManyError;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::migration::Migration;
+use many_ledger::module::recovery::{
+    AccountRecoveryModuleBackend, RecoverArgs, RecoverReturns, RecoveryInfoArgs,
+    RecoveryInfoReturns, SetRecoveryArgs,
+};
+use many_modules::account::{self, AccountModuleBackend};
+use many_modules::events::{self, EventsModuleBackend};
+use many_types::Timestamp;
+use std::collections::{BTreeMap, BTreeSet};
+
+const INACTIVITY_SECS: u64 = 100;
+const CHALLENGE_SECS: u64 = 50;
+
+/// A setup where the AccountRecovery migration is active.
+fn setup_with_recovery(blockchain: bool) -> Setup {
+    let mut setup = Setup::new(blockchain);
+    let migrations: BTreeSet<Box<dyn Migration>> =
+        json5::from_str(r#"[{ type: "AccountRecovery", block_height: 1 }]"#).unwrap();
+    setup.module_impl = setup.module_impl.with_migrations(migrations);
+    setup.block(|_| {});
+    setup
+}
+
+fn set_recovery(setup: &mut Setup, sender: Address, account: Address) -> Result<(), ManyError> {
+    setup
+        .module_impl
+        .set_recovery(
+            &sender,
+            SetRecoveryArgs {
+                account,
+                recovery: identity(5),
+                inactivity_secs: INACTIVITY_SECS,
+                challenge_secs: CHALLENGE_SECS,
+            },
+        )
+        .map(|_| ())
+}
+
+fn recover(
+    setup: &mut Setup,
+    sender: Address,
+    account: Address,
+) -> Result<RecoverReturns, ManyError> {
+    setup.module_impl.recover(&sender, RecoverArgs { account })
+}
+
+fn info(setup: &Setup, account: Address) -> RecoveryInfoReturns {
+    setup
+        .module_impl
+        .recovery_info(&identity(5), RecoveryInfoArgs { account })
+        .unwrap()
+}
+
+fn secs(t: Timestamp) -> u64 {
+    t.as_system_time()
+        .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn owners(setup: &Setup, id: Address) -> BTreeSet<Address> {
+    AccountModuleBackend::info(&setup.module_impl, &id, account::InfoArgs { account: id })
+        .unwrap()
+        .roles
+        .into_iter()
+        .filter(|(_, roles)| roles.contains(&account::Role::Owner))
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Create an account with recovery enabled, then let its owner become
+/// inactive and the recovery identity claim it.
+fn claimed() -> (Setup, Address) {
+    let mut setup = setup_with_recovery(true);
+    let owner = setup.id;
+    let (_, account) = setup.block(|setup| setup.create_account_(AccountType::Ledger));
+    setup.block(|setup| set_recovery(setup, owner, account).unwrap());
+
+    setup.inc_time(INACTIVITY_SECS);
+    let (_, returns) = setup.block(|setup| recover(setup, identity(5), account).unwrap());
+    assert!(!returns.recovered);
+    assert_eq!(returns.completable, info(&setup, account).completable);
+    (setup, account)
+}
+
+#[test]
+fn not_available_before_migration() {
+    let mut setup = Setup::default();
+    let owner = setup.id;
+    let account = setup.create_account_(AccountType::Ledger);
+    assert_many_err(
+        set_recovery(&mut setup, owner, account),
+        error::recovery_not_available(),
+    );
+}
+
+#[test]
+fn set_recovery_needs_owner() {
+    let mut setup = setup_with_recovery(false);
+    let account = setup.create_account_(AccountType::Ledger);
+    assert_many_err(
+        set_recovery(&mut setup, identity(2), account),
+        account::errors::user_needs_role("owner"),
+    );
+    assert_many_err(
+        setup
+            .module_impl
+            .recovery_info(&identity(5), RecoveryInfoArgs { account }),
+        error::recovery_not_enabled(account),
+    );
+}
+
+#[test]
+fn invalid_config() {
+    let mut setup = setup_with_recovery(false);
+    let owner = setup.id;
+    let account = setup.create_account_(AccountType::Ledger);
+    let mut args = SetRecoveryArgs {
+        account,
+        recovery: identity(5),
+        inactivity_secs: 0,
+        challenge_secs: CHALLENGE_SECS,
+    };
+    assert_many_err(
+        setup.module_impl.set_recovery(&owner, args.clone()),
+        error::invalid_recovery_inactivity(),
+    );
+
+    args.inactivity_secs = INACTIVITY_SECS;
+    args.recovery = account;
+    assert_many_err(
+        setup.module_impl.set_recovery(&owner, args),
+        error::invalid_recovery_identity(),
+    );
+}
+
+#[test]
+fn timeline() {
+    let mut setup = setup_with_recovery(true);
+    let owner = setup.id;
+    let (_, account) = setup.block(|setup| setup.create_account_(AccountType::Ledger));
+    setup.block(|setup| set_recovery(setup, owner, account).unwrap());
+
+    // The owner is active, so the account cannot be claimed yet.
+    let claimable = secs(info(&setup, account).claimable);
+    setup.inc_time(INACTIVITY_SECS / 2);
+    setup.block(|setup| {
+        assert_many_err(
+            recover(setup, identity(6), account),
+            error::not_recovery_identity(),
+        );
+        assert_many_err(
+            recover(setup, identity(5), account),
+            error::account_active(claimable),
+        );
+    });
+
+    // Once the owner is inactive for long enough, the recovery identity
+    // opens a claim and has to wait for the challenge window.
+    setup.inc_time(INACTIVITY_SECS / 2);
+    let (_, returns) = setup.block(|setup| recover(setup, identity(5), account).unwrap());
+    assert!(!returns.recovered);
+    let completable = secs(returns.completable.unwrap());
+    setup.block(|setup| {
+        assert_many_err(
+            recover(setup, identity(5), account),
+            error::recovery_challenge_pending(completable),
+        );
+    });
+    assert_eq!(owners(&setup, account), BTreeSet::from([account, owner]));
+
+    // After the challenge window, the claim transfers the ownership.
+    setup.inc_time(CHALLENGE_SECS);
+    let (_, returns) = setup.block(|setup| recover(setup, identity(5), account).unwrap());
+    assert!(returns.recovered);
+    assert_eq!(
+        owners(&setup, account),
+        BTreeSet::from([account, identity(5)])
+    );
+    assert_eq!(info(&setup, account).claimed, None);
+
+    let list = setup
+        .module_impl
+        .list(events::ListArgs {
+            count: Some(2),
+            order: Some(many_types::SortOrder::Descending),
+            filter: None,
+        })
+        .unwrap();
+    let contents: Vec<_> = list.events.into_iter().map(|e| e.content).collect();
+    assert_eq!(
+        contents,
+        vec![
+            events::EventInfo::AccountRemoveRoles {
+                account,
+                roles: BTreeMap::from([(owner, BTreeSet::from([account::Role::Owner]))]),
+            },
+            events::EventInfo::AccountAddRoles {
+                account,
+                roles: BTreeMap::from([(identity(5), BTreeSet::from([account::Role::Owner]))]),
+            },
+        ]
+    );
+}
+
+#[test]
+fn veto() {
+    let (mut setup, account) = claimed();
+    let owner = setup.id;
+
+    // Any command of the owner during the challenge window vetoes the claim.
+    setup.block(|setup| setup.module_impl.record_activity(&owner).unwrap());
+    let returns = info(&setup, account);
+    assert_eq!(returns.claimed, None);
+    let claimable = secs(returns.claimable);
+
+    setup.inc_time(CHALLENGE_SECS);
+    setup.block(|setup| {
+        assert_many_err(
+            recover(setup, identity(5), account),
+            error::account_active(claimable),
+        );
+    });
+    assert_eq!(owners(&setup, account), BTreeSet::from([account, owner]));
+
+    // The recovery identity can claim again after a new inactivity period.
+    setup.inc_time(INACTIVITY_SECS);
+    let (_, returns) = setup.block(|setup| recover(setup, identity(5), account).unwrap());
+    assert!(!returns.recovered);
+}
+
+#[test]
+fn other_activity_does_not_veto() {
+    let (mut setup, account) = claimed();
+
+    // Activity from identities which do not own the account is ignored.
+    setup.block(|setup| {
+        setup.module_impl.record_activity(&identity(2)).unwrap();
+        setup.module_impl.record_activity(&identity(5)).unwrap();
+    });
+    assert!(info(&setup, account).claimed.is_some());
+
+    setup.inc_time(CHALLENGE_SECS);
+    let (_, returns) = setup.block(|setup| recover(setup, identity(5), account).unwrap());
+    assert!(returns.recovered);
+}
+
+#[test]
+fn added_owner_vetoes() {
+    let (mut setup, account) = claimed();
+
+    // Owners added after the recovery was configured are watched as well.
+    setup.block(|setup| {
+        setup.add_roles(
+            account,
+            BTreeMap::from([(identity(3), BTreeSet::from([account::Role::Owner]))]),
+        )
+    });
+    setup.block(|setup| setup.module_impl.record_activity(&identity(3)).unwrap());
+    assert_eq!(info(&setup, account).claimed, None);
+}
+
+#[test]
+fn set_recovery_drops_claim() {
+    let (mut setup, account) = claimed();
+    let owner = setup.id;
+
+    setup.block(|setup| set_recovery(setup, owner, account).unwrap());
+    assert_eq!(info(&setup, account).claimed, None);
+}
@@ -0,0 +1,191 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::migration::Migration;
+use many_ledger::module::batch::{
+    BatchArgs, BatchItemStatus, BatchOperation, LedgerBatchModuleBackend,
+};
+use many_ledger::module::governance::{LedgerGovernanceModuleBackend, ParamValue, SetParamArgs};
+use many_modules::events::EventsModuleBackend;
+use many_modules::{events, ledger};
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+fn set(setup: &mut Setup, name: &str, value: ParamValue) {
+    setup
+        .module_impl
+        .set_param(
+            &LEDGER_IDENTITY,
+            SetParamArgs {
+                name: name.to_string(),
+                value,
+            },
+        )
+        .unwrap();
+}
+
+fn allow_self(setup: &mut Setup) {
+    set(setup, "send.allowSelf", ParamValue::Bool(true));
+}
+
+fn send_events(setup: &Setup) -> Vec<events::EventInfo> {
+    setup
+        .module_impl
+        .list(events::ListArgs {
+            count: None,
+            order: None,
+            filter: None,
+        })
+        .unwrap()
+        .events
+        .into_iter()
+        .map(|e| e.content)
+        .filter(|e| matches!(e, events::EventInfo::Send { .. }))
+        .collect()
+}
+
+fn batch_statuses(setup: &mut Setup, atomic: bool) -> Vec<BatchItemStatus> {
+    let id = setup.id;
+    let send = |to: Address| {
+        BatchOperation::Send(ledger::SendArgs {
+            from: None,
+            to,
+            amount: 10u32.into(),
+            symbol: *MFX_SYMBOL,
+        })
+    };
+    setup
+        .module_impl
+        .batch(
+            &id,
+            BatchArgs {
+                operations: vec![send(identity(1)), send(id)],
+                atomic,
+            },
+        )
+        .unwrap()
+        .results
+        .into_iter()
+        .map(|r| r.status)
+        .collect()
+}
+
+#[test]
+fn rejected_by_default() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+
+    assert_many_err(
+        setup.send(id, id, 10u32, *MFX_SYMBOL),
+        error::destination_is_source(),
+    );
+    assert!(send_events(&setup).is_empty());
+
+    assert_eq!(
+        batch_statuses(&mut setup, true),
+        vec![BatchItemStatus::Skipped, BatchItemStatus::Failed]
+    );
+    assert_eq!(
+        batch_statuses(&mut setup, false),
+        vec![BatchItemStatus::Executed, BatchItemStatus::Failed]
+    );
+    assert_eq!(setup.balance_(id), 990u32);
+}
+
+#[test]
+fn allowed_as_no_op() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    allow_self(&mut setup);
+    // Self sends do not pay fees, as no funds move.
+    set(&mut setup, "send.fee", ParamValue::Amount(5u32.into()));
+    set(
+        &mut setup,
+        "send.feeCollector",
+        ParamValue::Address(identity(9)),
+    );
+
+    setup.send_(id, id, 100u32);
+    assert_eq!(setup.balance_(id), 1_000u32);
+    assert_eq!(setup.balance_(identity(9)), 0u32);
+    match send_events(&setup).as_slice() {
+        [events::EventInfo::Send {
+            from, to, amount, ..
+        }] => {
+            assert_eq!((from, to), (&id, &id));
+            assert_eq!(amount, &100u32.into());
+        }
+        events => panic!("Unexpected events: {events:?}"),
+    }
+
+    // The usual checks still apply.
+    assert_many_err(
+        setup.send(id, id, 0u32, *MFX_SYMBOL),
+        error::amount_is_zero(),
+    );
+    assert_many_err(
+        setup.send(id, id, 1_001u32, *MFX_SYMBOL),
+        error::insufficient_funds(),
+    );
+
+    assert_eq!(
+        batch_statuses(&mut setup, true),
+        vec![BatchItemStatus::Executed, BatchItemStatus::Executed]
+    );
+    // 10 to identity(1) plus its fee.
+    assert_eq!(setup.balance_(id), 985u32);
+}
+
+#[test]
+fn multisig_submission() {
+    let mut setup = Setup::new(false);
+    let account = setup.create_account_(AccountType::Multisig);
+    setup.set_balance(account, 1_000, *MFX_SYMBOL);
+
+    // Before the migration, the send is only rejected when executed.
+    let token = setup.multisig_send_(account, account, 10u32);
+    setup.multisig_approve_(identity(2), &token);
+    setup.multisig_approve_(identity(3), &token);
+    assert_many_err(
+        setup.multisig_execute_(&token).data,
+        error::destination_is_source(),
+    );
+
+    let migrations: BTreeSet<Box<dyn Migration>> =
+        json5::from_str(r#"[{ type: "CheckedMultisigSends", block_height: 1 }]"#).unwrap();
+    setup.module_impl = setup.module_impl.with_migrations(migrations);
+    setup.block(|_| {});
+    assert_many_err(
+        setup.multisig_send(account, account, 10u32, *MFX_SYMBOL),
+        error::destination_is_source(),
+    );
+    // A missing `from` is the account.
+    assert_many_err(
+        setup.create_multisig(
+            account,
+            events::AccountMultisigTransaction::Send(ledger::SendArgs {
+                from: None,
+                to: account,
+                symbol: *MFX_SYMBOL,
+                amount: 10u32.into(),
+            }),
+        ),
+        error::destination_is_source(),
+    );
+
+    allow_self(&mut setup);
+    let token = setup.multisig_send_(account, account, 10u32);
+    setup.multisig_approve_(identity(2), &token);
+    setup.multisig_approve_(identity(3), &token);
+    assert!(setup.multisig_execute_(&token).data.is_ok());
+    assert_eq!(setup.balance_(account), 1_000u32);
+}
@@ -0,0 +1,187 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::module::snapshot::{
+    LedgerSnapshotModuleBackend, ListSnapshotsArgs, SnapshotArgs, SnapshotBalanceArgs,
+};
+use many_types::ledger::TokenAmount;
+use once_cell::sync::Lazy;
+use std::str::FromStr;
+
+/// The ledger identity of the staging state, which can take snapshots.
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+fn snapshot(setup: &mut Setup) -> u64 {
+    setup
+        .module_impl
+        .snapshot(
+            &LEDGER_IDENTITY,
+            SnapshotArgs {
+                symbol: *MFX_SYMBOL,
+            },
+        )
+        .expect("Could not create snapshot")
+        .snapshot
+}
+
+fn snapshot_balance(setup: &Setup, snapshot: u64, account: Address) -> TokenAmount {
+    setup
+        .module_impl
+        .snapshot_balance(
+            &identity(9),
+            SnapshotBalanceArgs {
+                snapshot,
+                account: Some(account),
+            },
+        )
+        .expect("Could not get snapshot balance")
+        .balance
+}
+
+#[test]
+fn balances_before_mutations() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+
+    let first = snapshot(&mut setup);
+    setup.send_(id, identity(2), 100u32);
+    let second = snapshot(&mut setup);
+    setup.send_(identity(2), id, 30u32);
+    setup.send_(id, identity(3), 200u32);
+
+    assert_eq!(setup.balance_(id), 730u32);
+    assert_eq!(setup.balance_(identity(2)), 70u32);
+    assert_eq!(setup.balance_(identity(3)), 200u32);
+
+    assert_eq!(snapshot_balance(&setup, first, id), 1_000u32);
+    assert_eq!(snapshot_balance(&setup, first, identity(2)), 0u32);
+    assert_eq!(snapshot_balance(&setup, first, identity(3)), 0u32);
+
+    assert_eq!(snapshot_balance(&setup, second, id), 900u32);
+    assert_eq!(snapshot_balance(&setup, second, identity(2)), 100u32);
+    assert_eq!(snapshot_balance(&setup, second, identity(3)), 0u32);
+}
+
+#[test]
+fn balance_of_sender() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    let s = snapshot(&mut setup);
+    setup.send_(id, identity(2), 100u32);
+
+    let returns = setup
+        .module_impl
+        .snapshot_balance(
+            &id,
+            SnapshotBalanceArgs {
+                snapshot: s,
+                account: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(returns.symbol, *MFX_SYMBOL);
+    assert_eq!(returns.balance, 1_000u32);
+}
+
+#[test]
+fn in_blocks() {
+    let mut setup = Setup::new(true);
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+
+    setup.block(|_| {});
+    setup.block(|setup| setup.send_(id, identity(2), 100u32));
+    let (height, s) = setup.block(|setup| {
+        let s = snapshot(setup);
+        setup.send_(id, identity(2), 100u32);
+        s
+    });
+    setup.block(|setup| setup.send_(id, identity(2), 100u32));
+
+    let info = setup
+        .module_impl
+        .list_snapshots(&id, Default::default())
+        .unwrap();
+    assert_eq!(info.snapshots.len(), 1);
+    assert_eq!(info.snapshots[0].height, height - 1);
+
+    assert_eq!(snapshot_balance(&setup, s, id), 900u32);
+    assert_eq!(snapshot_balance(&setup, s, identity(2)), 100u32);
+    assert_eq!(setup.balance_(id), 700u32);
+}
+
+#[test]
+fn list() {
+    let mut setup = Setup::new(false);
+    let first = snapshot(&mut setup);
+    let second = snapshot(&mut setup);
+
+    let list = setup
+        .module_impl
+        .list_snapshots(&setup.id, ListSnapshotsArgs::default())
+        .unwrap();
+    let ids: Vec<u64> = list.snapshots.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![first, second]);
+    assert!(list.snapshots.iter().all(|s| s.symbol == *MFX_SYMBOL));
+
+    let list = setup
+        .module_impl
+        .list_snapshots(
+            &setup.id,
+            ListSnapshotsArgs {
+                symbol: Some(identity(9)),
+            },
+        )
+        .unwrap();
+    assert!(list.snapshots.is_empty());
+}
+
+#[test]
+fn invalid() {
+    let mut setup = Setup::new(false);
+    let id = setup.id;
+
+    assert_many_err(
+        setup
+            .module_impl
+            .snapshot(
+                &id,
+                SnapshotArgs {
+                    symbol: *MFX_SYMBOL,
+                },
+            )
+            .map(|r| r.snapshot),
+        error::unauthorized(),
+    );
+    assert_many_err(
+        setup
+            .module_impl
+            .snapshot(
+                &LEDGER_IDENTITY,
+                SnapshotArgs {
+                    symbol: identity(9),
+                },
+            )
+            .map(|r| r.snapshot),
+        error::unknown_symbol(identity(9)),
+    );
+    assert_many_err(
+        setup
+            .module_impl
+            .snapshot_balance(
+                &id,
+                SnapshotBalanceArgs {
+                    snapshot: 0,
+                    account: None,
+                },
+            )
+            .map(|r| r.balance),
+        error::unknown_snapshot(0),
+    );
+}
@@ -0,0 +1,172 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_ledger::migration::Migration;
+use many_ledger::module::stats::{LedgerStatsModuleBackend, StatsArgs, StatsReturns};
+use many_modules::abci_backend::ManyAbciModuleBackend;
+use many_modules::account::features::multisig;
+use many_modules::{events, ledger};
+use std::collections::BTreeSet;
+
+/// A setup whose counters are backfilled and maintained from its first
+/// block.
+fn setup_with_stats(blockchain: bool) -> Setup {
+    let mut setup = Setup::new(blockchain);
+    let migrations: BTreeSet<Box<dyn Migration>> =
+        json5::from_str(r#"[{ type: "LedgerStats", block_height: 1 }]"#).unwrap();
+    setup.module_impl = setup.module_impl.with_migrations(migrations);
+    setup
+}
+
+fn stats(setup: &Setup) -> StatsReturns {
+    setup
+        .module_impl
+        .stats(&setup.id, StatsArgs {})
+        .expect("Could not get stats")
+}
+
+#[test]
+fn not_enabled() {
+    let mut setup = Setup::new(true);
+    setup.set_balance(identity(10), 1_000, *MFX_SYMBOL);
+    setup.block(|setup| setup.send_(identity(10), identity(11), 400u32));
+    assert!(setup.module_impl.stats(&setup.id, StatsArgs {}).is_err());
+}
+
+#[test]
+fn backfill() {
+    // The same chain, with an address and an account more.
+    let mut setup = setup_with_stats(true);
+    setup.set_balance(identity(10), 1_000, *MFX_SYMBOL);
+    setup.create_account_as_(identity(10), AccountType::Multisig);
+    assert!(setup.module_impl.stats(&setup.id, StatsArgs {}).is_err());
+    setup.block(|_| {});
+
+    let mut other = setup_with_stats(true);
+    other.block(|_| {});
+
+    let (stats, other) = (stats(&setup), stats(&other));
+    assert_eq!(stats.nonzero_addresses, other.nonzero_addresses + 1);
+    assert_eq!(stats.accounts, other.accounts + 1);
+    assert_eq!(stats.transactions, 0);
+    assert!(stats.last_event.is_some());
+}
+
+#[test]
+fn nonzero_addresses() {
+    let mut setup = setup_with_stats(false);
+    setup.set_balance(identity(10), 1_000, *MFX_SYMBOL);
+    setup.block(|_| {});
+    let base = stats(&setup);
+
+    // Sending everything moves the address with a nonzero balance.
+    setup.send_(identity(10), identity(11), 1_000u32);
+    assert_eq!(stats(&setup).nonzero_addresses, base.nonzero_addresses);
+
+    // A send to a new address.
+    setup.send_(identity(11), identity(12), 400u32);
+    assert_eq!(stats(&setup).nonzero_addresses, base.nonzero_addresses + 1);
+
+    setup.send_(identity(12), identity(11), 400u32);
+    assert_eq!(stats(&setup).nonzero_addresses, base.nonzero_addresses);
+}
+
+#[test]
+fn accounts_and_events() {
+    let mut setup = setup_with_stats(false);
+    setup.block(|_| {});
+    let base = stats(&setup);
+
+    setup.create_account_as_(identity(10), AccountType::Multisig);
+    setup.create_account_as_(identity(10), AccountType::Ledger);
+
+    let stats = stats(&setup);
+    assert_eq!(stats.accounts, base.accounts + 2);
+    assert_eq!(stats.events, base.events + 2);
+    assert!(stats.last_event.is_some());
+    assert_ne!(stats.last_event, base.last_event);
+    assert_eq!(stats.symbols, base.symbols);
+}
+
+#[test]
+fn pending_multisig() {
+    let mut setup = setup_with_stats(true);
+    let account_id = setup.create_account_(AccountType::Multisig);
+    setup.set_balance(account_id, 1_000, *MFX_SYMBOL);
+    setup.block(|_| {});
+    let base = stats(&setup);
+
+    let (_, (executed, expired)) = setup.block(|setup| {
+        (
+            setup.multisig_send_(account_id, identity(11), 10u32),
+            setup.multisig_send_(account_id, identity(11), 10u32),
+        )
+    });
+    assert_eq!(stats(&setup).pending_multisig, base.pending_multisig + 2);
+
+    setup.block(|setup| {
+        setup.multisig_approve_(setup.id, &executed);
+        setup.multisig_approve_(identity(2), &executed);
+        setup.multisig_approve_(identity(3), &executed);
+        assert!(setup.multisig_execute_(&executed).data.is_ok());
+    });
+    assert_eq!(stats(&setup).pending_multisig, base.pending_multisig + 1);
+
+    setup.inc_time(1_000_000);
+    setup.block(|_| {});
+    setup.assert_multisig_info(&expired, |i| {
+        assert_eq!(i.state, multisig::MultisigTransactionState::Expired);
+    });
+    assert_eq!(stats(&setup).pending_multisig, base.pending_multisig);
+}
+
+/// Run the same blocks on a replica, counting the transactions as the
+/// server does.
+fn replica() -> (StatsReturns, Vec<u8>) {
+    let mut setup = setup_with_stats(true);
+    let owner = identity(10);
+    setup.set_balance(owner, 1_000, *MFX_SYMBOL);
+    setup.block(|_| {});
+
+    let (_, account_id) = setup.block(|setup| {
+        setup.send_(owner, identity(11), 1_000u32);
+        setup.module_impl.count_transaction();
+        let account_id = setup.create_account_as_(owner, AccountType::Multisig);
+        setup.module_impl.count_transaction();
+        account_id
+    });
+    setup.block(|setup| {
+        setup.send_(identity(11), account_id, 500u32);
+        setup.module_impl.count_transaction();
+        setup
+            .create_multisig_as(
+                owner,
+                account_id,
+                events::AccountMultisigTransaction::Send(ledger::SendArgs {
+                    from: Some(account_id),
+                    to: identity(12),
+                    symbol: *MFX_SYMBOL,
+                    amount: 10u32.into(),
+                }),
+            )
+            .unwrap();
+        setup.module_impl.count_transaction();
+    });
+
+    let hash = ManyAbciModuleBackend::info(&setup.module_impl)
+        .unwrap()
+        .hash
+        .to_vec();
+    (stats(&setup), hash)
+}
+
+#[test]
+fn determinism() {
+    let (stats, hash) = replica();
+    assert_eq!(stats.transactions, 4);
+    assert_eq!(stats.pending_multisig, 1);
+
+    // The counters are part of the state, so the replicas agree on both.
+    assert_eq!(replica(), (stats, hash));
+}
@@ -0,0 +1,213 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::module::attachment::{Attachment, ATTACHMENT};
+use many_ledger::module::escrow::EscrowArgs;
+use many_ledger::module::governance::{LedgerGovernanceModuleBackend, ParamValue, SetParamArgs};
+use many_ledger::module::strict_args::{check, unknown_fields, FieldKey, StrictArgsModule};
+use many_modules::account::features::multisig;
+use many_modules::ledger::{LedgerCommandsModule, SendArgs};
+use many_modules::ManyModule;
+use many_protocol::RequestMessageBuilder;
+use many_types::cbor::CborAny;
+use once_cell::sync::Lazy;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+/// Append a field to an encoded map of four fields.
+fn with_field(mut data: Vec<u8>, key: impl minicbor::Encode<()>, value: Option<&str>) -> Vec<u8> {
+    assert_eq!(data[0], 0xa4);
+    data[0] = 0xa5;
+    let mut e = minicbor::Encoder::new(data);
+    e.encode(key).unwrap().encode(value).unwrap();
+    e.into_writer()
+}
+
+fn send_args(from: Address) -> Vec<u8> {
+    minicbor::to_vec(SendArgs {
+        from: Some(from),
+        to: identity(1),
+        symbol: *MFX_SYMBOL,
+        amount: 10u64.into(),
+    })
+    .unwrap()
+}
+
+#[test]
+fn fields() {
+    let data = send_args(identity(2));
+    assert_eq!(unknown_fields::<SendArgs>(&data), Ok(vec![]));
+    assert_eq!(
+        unknown_fields::<SendArgs>(&with_field(data.clone(), "memo_", Some("Rent"))),
+        Ok(vec![FieldKey::Name("memo_".to_string())])
+    );
+    assert_eq!(
+        unknown_fields::<SendArgs>(&with_field(data.clone(), 9u8, Some("Rent"))),
+        Ok(vec![FieldKey::Index(9)])
+    );
+    // Null fields are read as absent optional fields.
+    assert_eq!(
+        unknown_fields::<SendArgs>(&with_field(data, 9u8, None)),
+        Ok(vec![])
+    );
+
+    let approve = minicbor::to_vec(multisig::ApproveArgs {
+        token: vec![1, 2, 3].into(),
+    })
+    .unwrap();
+    let mut e = minicbor::Encoder::new(approve);
+    e.encode("memo").unwrap().encode("Approved").unwrap();
+    let mut approve = e.into_writer();
+    approve[0] += 1;
+    assert_eq!(
+        unknown_fields::<multisig::ApproveArgs>(&approve),
+        Ok(vec![FieldKey::Name("memo".to_string())])
+    );
+}
+
+#[test]
+fn queries_lenient() {
+    let data = with_field(send_args(identity(2)), "memo_", Some("Rent"));
+    assert!(check("ledger.send", &data).is_err());
+    assert_eq!(check("ledger.balance", &data), Ok(()));
+    assert_eq!(check("ledger.info", &data), Ok(()));
+}
+
+#[test]
+fn newer_commands() {
+    let mut e = minicbor::Encoder::new(
+        minicbor::to_vec(EscrowArgs {
+            escrow: identity(3),
+        })
+        .unwrap(),
+    );
+    e.encode("memo").unwrap().encode("Paid").unwrap();
+    let mut data = e.into_writer();
+    data[0] += 1;
+    for method in ["ledger.escrowRelease", "ledger.escrowRefund"] {
+        assert_eq!(
+            check(method, &data),
+            Err(error::unknown_fields(
+                method.to_string(),
+                "\"memo\"".to_string()
+            ))
+        );
+    }
+}
+
+#[tokio::test]
+async fn module() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let module = StrictArgsModule::new(LedgerCommandsModule::new(backend.clone()), backend.clone());
+    let request = |data: Vec<u8>| {
+        RequestMessageBuilder::default()
+            .from(id)
+            .method("ledger.send".to_string())
+            .data(data)
+            .build()
+            .unwrap()
+    };
+    let misspelled = with_field(send_args(id), "memo_", Some("Rent"));
+
+    // Lenient by default.
+    let response = module.execute(request(misspelled.clone())).await.unwrap();
+    assert!(response.data.is_ok());
+
+    backend
+        .lock()
+        .unwrap()
+        .set_param(
+            &LEDGER_IDENTITY,
+            SetParamArgs {
+                name: "args.strict".to_string(),
+                value: ParamValue::Bool(true),
+            },
+        )
+        .unwrap();
+
+    let response = module.execute(request(misspelled)).await.unwrap();
+    assert_eq!(
+        response.data,
+        Err(error::unknown_fields(
+            "ledger.send".to_string(),
+            "\"memo_\"".to_string()
+        ))
+    );
+    let response = module
+        .execute(request(with_field(send_args(id), 9u8, Some("Rent"))))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.data,
+        Err(error::unknown_fields(
+            "ledger.send".to_string(),
+            "9".to_string()
+        ))
+    );
+
+    let response = module.execute(request(send_args(id))).await.unwrap();
+    assert!(response.data.is_ok());
+}
+
+#[tokio::test]
+async fn attachments() {
+    let mut setup = setup();
+    let id = setup.id;
+    setup.set_balance(id, 1_000, *MFX_SYMBOL);
+    let backend = Arc::new(Mutex::new(setup.module_impl));
+    let module = StrictArgsModule::new(LedgerCommandsModule::new(backend.clone()), backend.clone());
+    let attachment = minicbor::to_vec(Attachment {
+        server: identity(3),
+        key: b"invoice".to_vec().into(),
+        hash: vec![0; 32].into(),
+    })
+    .unwrap();
+    let mut e = minicbor::Encoder::new(attachment.clone());
+    e.encode(3u8).unwrap().encode("Rent").unwrap();
+    let mut extended = e.into_writer();
+    extended[0] += 1;
+    let request = |attachment: &[u8]| {
+        let mut request = RequestMessageBuilder::default()
+            .from(id)
+            .method("ledger.send".to_string())
+            .data(send_args(id))
+            .build()
+            .unwrap();
+        request
+            .attributes
+            .insert(ATTACHMENT.with_argument(CborAny::Bytes(attachment.to_vec())));
+        request
+    };
+
+    backend
+        .lock()
+        .unwrap()
+        .set_param(
+            &LEDGER_IDENTITY,
+            SetParamArgs {
+                name: "args.strict".to_string(),
+                value: ParamValue::Bool(true),
+            },
+        )
+        .unwrap();
+
+    let response = module.execute(request(&extended)).await.unwrap();
+    assert_eq!(
+        response.data,
+        Err(error::unknown_attachment_fields(
+            "ledger.send".to_string(),
+            "3".to_string()
+        ))
+    );
+    let response = module.execute(request(&attachment)).await.unwrap();
+    assert!(response.data.is_ok());
+}
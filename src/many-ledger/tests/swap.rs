@@ -0,0 +1,370 @@
+pub mod common;
+
+use common::*;
+use many_identity::testing::identity;
+use many_identity::Address;
+use many_ledger::error;
+use many_ledger::json::InitialStateJson;
+use many_ledger::module::escrow::LedgerEscrowModuleBackend;
+use many_ledger::module::governance::{LedgerGovernanceModuleBackend, ParamValue, SetParamArgs};
+use many_ledger::module::swap::{
+    LedgerSwapModuleBackend, SwapAcceptArgs, SwapArgs, SwapCreateArgs, SwapInfo, SwapState,
+};
+use many_ledger::module::LedgerModuleImpl;
+use many_modules::events::{self, EventsModuleBackend};
+use many_types::ledger::{Symbol, TokenAmount};
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+const EXPIRY_IN_SECS: u64 = 10;
+
+static LEDGER_IDENTITY: Lazy<Address> =
+    Lazy::new(|| Address::from_str("mahukzwuwgt3porn6q4vq4xu3mwy5gyskhouryzbscq7wb2iow").unwrap());
+
+static ABC_SYMBOL: Lazy<Symbol> = Lazy::new(|| identity(100));
+
+/// A ledger with a second symbol, where the maker has 1000 MFX and the
+/// taker (identity 2) has 1000 ABC.
+fn setup(blockchain: bool) -> Setup {
+    let mut state = InitialStateJson::read("../../staging/ledger_state.json5")
+        .or_else(|_| InitialStateJson::read("staging/ledger_state.json5"))
+        .expect("Could not read initial state.");
+    state.hash = None;
+    state.symbols.insert(*ABC_SYMBOL, "ABC".to_string());
+
+    let mut setup = Setup::new(blockchain);
+    setup.module_impl =
+        LedgerModuleImpl::new(Some(state), tempfile::tempdir().unwrap(), blockchain)
+            .expect("Could not create the ledger");
+    setup.set_balance(setup.id, 1_000, *MFX_SYMBOL);
+    setup.set_balance(identity(2), 1_000, *ABC_SYMBOL);
+    setup
+}
+
+fn create(
+    setup: &mut Setup,
+    maker: Address,
+    counterparty: Option<Address>,
+    amount: u64,
+    want_amount: u64,
+) -> Result<Address, many_error::ManyError> {
+    setup
+        .module_impl
+        .swap_create(
+            &maker,
+            SwapCreateArgs {
+                from: None,
+                symbol: *MFX_SYMBOL,
+                amount: amount.into(),
+                want_symbol: *ABC_SYMBOL,
+                want_amount: want_amount.into(),
+                counterparty,
+                expires_in_secs: EXPIRY_IN_SECS,
+            },
+        )
+        .map(|r| r.swap)
+}
+
+fn create_(setup: &mut Setup, counterparty: Option<Address>) -> Address {
+    let maker = setup.id;
+    create(setup, maker, counterparty, 100, 50).expect("Could not create swap")
+}
+
+fn accept(setup: &mut Setup, taker: Address, swap: Address) -> Result<(), many_error::ManyError> {
+    setup
+        .module_impl
+        .swap_accept(&taker, SwapAcceptArgs { swap, from: None })
+        .map(|_| ())
+}
+
+fn cancel(setup: &mut Setup, sender: Address, swap: Address) -> Result<(), many_error::ManyError> {
+    setup
+        .module_impl
+        .swap_cancel(&sender, SwapArgs { swap })
+        .map(|_| ())
+}
+
+fn info(setup: &Setup, swap: Address) -> SwapInfo {
+    setup
+        .module_impl
+        .swap_info(&setup.id, SwapArgs { swap })
+        .expect("Could not get swap info")
+}
+
+fn balance(setup: &Setup, account: Address, symbol: Symbol) -> TokenAmount {
+    setup.balance(account, symbol).unwrap()
+}
+
+fn escrowed(setup: &Setup, account: Address) -> TokenAmount {
+    setup
+        .module_impl
+        .escrowed(&account, BTreeSet::new())
+        .unwrap()
+        .remove(&*MFX_SYMBOL)
+        .unwrap_or_default()
+}
+
+#[test]
+fn create_locks_funds() {
+    let mut setup = setup(false);
+    let maker = setup.id;
+    let swap = create_(&mut setup, None);
+
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 900u32);
+    assert_eq!(escrowed(&setup, maker), 100u32);
+
+    let i = info(&setup, swap);
+    assert_eq!(i.state, SwapState::Pending);
+    assert_eq!(i.maker, maker);
+    assert_eq!(i.taker, None);
+
+    // Locked funds cannot be spent by anyone.
+    assert!(setup
+        .send_as(maker, swap, identity(3), 1u32, *MFX_SYMBOL)
+        .is_err());
+    assert!(setup.send(maker, identity(3), 901u32, *MFX_SYMBOL).is_err());
+}
+
+#[test]
+fn create_invalid() {
+    let mut setup = setup(false);
+    let maker = setup.id;
+
+    assert_many_err(
+        create(&mut setup, maker, None, 1_001, 50),
+        error::insufficient_funds(),
+    );
+    assert_many_err(
+        create(&mut setup, maker, None, 100, 0),
+        error::amount_is_zero(),
+    );
+    assert_many_err(
+        create(&mut setup, maker, Some(maker), 100, 50),
+        error::destination_is_source(),
+    );
+
+    let args = SwapCreateArgs {
+        from: None,
+        symbol: *MFX_SYMBOL,
+        amount: 1u32.into(),
+        want_symbol: *ABC_SYMBOL,
+        want_amount: 1u32.into(),
+        counterparty: None,
+        expires_in_secs: EXPIRY_IN_SECS,
+    };
+    assert_many_err(
+        setup
+            .module_impl
+            .swap_create(
+                &maker,
+                SwapCreateArgs {
+                    expires_in_secs: 0,
+                    ..args.clone()
+                },
+            )
+            .map(|r| r.swap),
+        error::invalid_swap_expiry(),
+    );
+    assert_many_err(
+        setup
+            .module_impl
+            .swap_create(
+                &maker,
+                SwapCreateArgs {
+                    want_symbol: *MFX_SYMBOL,
+                    ..args
+                },
+            )
+            .map(|r| r.swap),
+        error::swap_same_symbol(),
+    );
+
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 1_000u32);
+    assert_eq!(escrowed(&setup, maker), 0u32);
+}
+
+#[test]
+fn accept_settles_both_legs() {
+    let mut setup = setup(false);
+    let maker = setup.id;
+    let swap = create_(&mut setup, None);
+
+    assert!(accept(&mut setup, identity(2), swap).is_ok());
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 900u32);
+    assert_eq!(balance(&setup, maker, *ABC_SYMBOL), 50u32);
+    assert_eq!(balance(&setup, identity(2), *MFX_SYMBOL), 100u32);
+    assert_eq!(balance(&setup, identity(2), *ABC_SYMBOL), 950u32);
+    assert_eq!(balance(&setup, swap, *MFX_SYMBOL), 0u32);
+    assert_eq!(escrowed(&setup, maker), 0u32);
+
+    let i = info(&setup, swap);
+    assert_eq!(i.state, SwapState::Accepted);
+    assert_eq!(i.taker, Some(identity(2)));
+
+    assert_many_err(
+        accept(&mut setup, identity(2), swap),
+        error::swap_already_settled(),
+    );
+    assert_many_err(
+        cancel(&mut setup, maker, swap),
+        error::swap_already_settled(),
+    );
+}
+
+#[test]
+fn accept_insufficient_funds() {
+    let mut setup = setup(false);
+    let maker = setup.id;
+    setup.set_balance(identity(3), 49, *ABC_SYMBOL);
+    let swap = create_(&mut setup, None);
+
+    assert_many_err(
+        accept(&mut setup, identity(3), swap),
+        error::insufficient_funds(),
+    );
+    assert_many_err(
+        accept(&mut setup, identity(4), swap),
+        error::insufficient_funds(),
+    );
+
+    // Nothing moved.
+    assert_eq!(info(&setup, swap).state, SwapState::Pending);
+    assert_eq!(balance(&setup, identity(3), *ABC_SYMBOL), 49u32);
+    assert_eq!(balance(&setup, identity(3), *MFX_SYMBOL), 0u32);
+    assert_eq!(balance(&setup, maker, *ABC_SYMBOL), 0u32);
+    assert_eq!(balance(&setup, swap, *MFX_SYMBOL), 100u32);
+    assert_eq!(escrowed(&setup, maker), 100u32);
+
+    // The swap can still be accepted by someone with the funds.
+    assert!(accept(&mut setup, identity(2), swap).is_ok());
+}
+
+#[test]
+fn counterparty_only() {
+    let mut setup = setup(false);
+    setup.set_balance(identity(3), 1_000, *ABC_SYMBOL);
+    let swap = create_(&mut setup, Some(identity(3)));
+
+    assert_many_err(accept(&mut setup, identity(2), swap), error::unauthorized());
+    assert!(accept(&mut setup, identity(3), swap).is_ok());
+    assert_eq!(balance(&setup, identity(3), *MFX_SYMBOL), 100u32);
+}
+
+#[test]
+fn cancel_by_maker() {
+    let mut setup = setup(false);
+    let maker = setup.id;
+    let swap = create_(&mut setup, None);
+
+    assert_many_err(cancel(&mut setup, identity(2), swap), error::unauthorized());
+    assert!(cancel(&mut setup, maker, swap).is_ok());
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 1_000u32);
+    assert_eq!(escrowed(&setup, maker), 0u32);
+    assert_eq!(info(&setup, swap).state, SwapState::Cancelled);
+
+    assert_many_err(
+        accept(&mut setup, identity(2), swap),
+        error::swap_already_settled(),
+    );
+    assert_eq!(balance(&setup, identity(2), *ABC_SYMBOL), 1_000u32);
+}
+
+/// The swap holds exactly the offered funds, so refunding them does not pay
+/// the send fee.
+#[test]
+fn cancel_without_fee() {
+    let mut setup = setup(false);
+    let maker = setup.id;
+    let swap = create_(&mut setup, None);
+
+    setup
+        .module_impl
+        .set_param(
+            &LEDGER_IDENTITY,
+            SetParamArgs {
+                name: "send.fee".to_string(),
+                value: ParamValue::Amount(5u32.into()),
+            },
+        )
+        .unwrap();
+
+    assert!(cancel(&mut setup, maker, swap).is_ok());
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 1_000u32);
+    assert_eq!(info(&setup, swap).state, SwapState::Cancelled);
+}
+
+/// The first of an accept and a cancel in the same block wins, and the other
+/// fails without changing anything.
+#[test]
+fn accept_racing_cancel() {
+    let mut setup = setup(true);
+    let maker = setup.id;
+
+    let (_, swap) = setup.block(|setup| create_(setup, None));
+    setup.block(|setup| {
+        assert!(accept(setup, identity(2), swap).is_ok());
+        assert_many_err(cancel(setup, maker, swap), error::swap_already_settled());
+    });
+    assert_eq!(info(&setup, swap).state, SwapState::Accepted);
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 900u32);
+    assert_eq!(balance(&setup, maker, *ABC_SYMBOL), 50u32);
+
+    let (_, swap) = setup.block(|setup| create_(setup, None));
+    setup.block(|setup| {
+        assert!(cancel(setup, maker, swap).is_ok());
+        assert_many_err(
+            accept(setup, identity(2), swap),
+            error::swap_already_settled(),
+        );
+    });
+    assert_eq!(info(&setup, swap).state, SwapState::Cancelled);
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 900u32);
+    assert_eq!(balance(&setup, identity(2), *ABC_SYMBOL), 950u32);
+    assert_eq!(escrowed(&setup, maker), 0u32);
+}
+
+#[test]
+fn refund_at_expiry() {
+    let mut setup = setup(true);
+    let maker = setup.id;
+
+    let (_, swap) = setup.block(|setup| create_(setup, None));
+    setup.block(|_| {});
+    assert_eq!(info(&setup, swap).state, SwapState::Pending);
+
+    setup.inc_time(EXPIRY_IN_SECS - 1);
+    setup.block(|setup| {
+        assert_many_err(accept(setup, identity(2), swap), error::swap_expired());
+    });
+    assert_eq!(info(&setup, swap).state, SwapState::Expired);
+    assert_eq!(balance(&setup, maker, *MFX_SYMBOL), 1_000u32);
+    assert_eq!(balance(&setup, identity(2), *ABC_SYMBOL), 1_000u32);
+    assert_eq!(escrowed(&setup, maker), 0u32);
+}
+
+#[test]
+fn events_for_transitions() {
+    let mut setup = setup(false);
+    let maker = setup.id;
+
+    let swap = create_(&mut setup, None);
+    accept(&mut setup, identity(2), swap).unwrap();
+    let other = create_(&mut setup, None);
+    cancel(&mut setup, maker, other).unwrap();
+
+    let list = setup
+        .module_impl
+        .list(events::ListArgs {
+            count: None,
+            order: None,
+            filter: None,
+        })
+        .unwrap();
+    // Lock, both legs of the accept, then lock and refund.
+    assert_eq!(list.nb_events, 5);
+    assert!(list
+        .events
+        .iter()
+        .all(|e| e.is_about(&swap) || e.is_about(&other) || e.is_about(&identity(2))));
+}
@@ -0,0 +1,401 @@
+use coset::CborSerializable;
+use many_identity::testing::identity;
+use many_identity::{Address, Identity};
+use many_identity_dsa::ed25519::generate_random_ed25519_identity;
+use many_ledger::error;
+use many_ledger::module::escrow::{EscrowArgs, EscrowCreateArgs, LedgerEscrowModuleBackend};
+use many_ledger::module::verify::{DiscrepancyKind, LedgerVerifyModuleBackend, VerifyArgs};
+use many_ledger::module::LedgerModuleImpl;
+use many_ledger::storage::LedgerStorage;
+use many_modules::account::features::multisig::{
+    AccountMultisigModuleBackend, MultisigAccountFeature, SubmitTransactionArgs,
+};
+use many_modules::account::features::FeatureInfo;
+use many_modules::account::AccountModuleBackend;
+use many_modules::idstore::{CredentialId, IdStoreModuleBackend, PublicKey, StoreArgs};
+use many_modules::ledger::LedgerCommandsModuleBackend;
+use many_modules::{account, events, ledger};
+use many_types::ledger::TokenAmount;
+use merk::Op;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+fn symbol() -> Address {
+    identity(1000)
+}
+
+fn ledger_identity() -> Address {
+    identity(666)
+}
+
+struct Fixture {
+    path: PathBuf,
+    escrow: Address,
+    account: Address,
+}
+
+/// Create a ledger with a send, a pending escrow, a multisig transaction and
+/// an identity store entry.
+fn fixture() -> Fixture {
+    let path = tempfile::tempdir().unwrap().into_path();
+    LedgerStorage::new(
+        BTreeMap::from([(symbol(), "MF0".to_string())]),
+        BTreeMap::from([(
+            identity(5),
+            BTreeMap::from([(symbol(), 1_000_000u64.into())]),
+        )]),
+        path.clone(),
+        ledger_identity(),
+        false,
+        None,
+        None,
+    )
+    .unwrap();
+
+    let mut module_impl = LedgerModuleImpl::new(None, path.clone(), false).unwrap();
+    module_impl
+        .send(
+            &identity(5),
+            ledger::SendArgs {
+                from: None,
+                to: identity(6),
+                symbol: symbol(),
+                amount: 1000u64.into(),
+            },
+        )
+        .unwrap();
+
+    let escrow = module_impl
+        .escrow_create(
+            &identity(5),
+            EscrowCreateArgs {
+                from: None,
+                payee: identity(6),
+                symbol: symbol(),
+                amount: 100u64.into(),
+                timeout_in_secs: 3600,
+                arbiter: None,
+            },
+        )
+        .unwrap()
+        .escrow;
+
+    let account = create_account(&mut module_impl);
+    module_impl
+        .multisig_submit_transaction(
+            &identity(5),
+            SubmitTransactionArgs {
+                account,
+                memo: None,
+                transaction: Box::new(events::AccountMultisigTransaction::Send(ledger::SendArgs {
+                    from: Some(account),
+                    to: identity(6),
+                    symbol: symbol(),
+                    amount: 1u64.into(),
+                })),
+                threshold: None,
+                timeout_in_secs: None,
+                execute_automatically: None,
+                data: None,
+            },
+        )
+        .unwrap();
+
+    let id = generate_random_ed25519_identity();
+    module_impl
+        .store(
+            &identity(5),
+            StoreArgs {
+                address: id.address(),
+                cred_id: CredentialId(vec![1; 16].into()),
+                public_key: PublicKey(id.public_key().to_vec().unwrap().into()),
+            },
+        )
+        .unwrap();
+
+    Fixture {
+        path,
+        escrow,
+        account,
+    }
+}
+
+fn create_account(module_impl: &mut LedgerModuleImpl) -> Address {
+    module_impl
+        .create(
+            &identity(5),
+            account::CreateArgs {
+                description: None,
+                roles: None,
+                features: account::features::FeatureSet::from_iter([
+                    MultisigAccountFeature::default().as_feature(),
+                ]),
+            },
+        )
+        .unwrap()
+        .id
+}
+
+/// Write raw entries in a ledger which is not opened.
+fn corrupt(path: &Path, mut batch: Vec<(Vec<u8>, Op)>) {
+    batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut merk = merk::Merk::open(path).unwrap();
+    merk.apply(&batch).unwrap();
+    merk.commit(&[]).unwrap();
+}
+
+fn verify_kinds(module_impl: &mut LedgerModuleImpl, repair: bool) -> Vec<DiscrepancyKind> {
+    module_impl
+        .verify_all(repair)
+        .unwrap()
+        .discrepancies
+        .into_iter()
+        .map(|d| d.kind)
+        .collect()
+}
+
+#[test]
+fn consistent() {
+    let mut module_impl = LedgerModuleImpl::new(None, fixture().path, false).unwrap();
+    let result = module_impl.verify_all(false).unwrap();
+    assert_eq!(result.discrepancies, vec![]);
+    assert_eq!(
+        result.supply,
+        Some(BTreeMap::from([(
+            symbol(),
+            TokenAmount::from(1_000_000u64)
+        )]))
+    );
+}
+
+#[test]
+fn balances() {
+    let path = fixture().path;
+    corrupt(
+        &path,
+        vec![
+            (
+                format!("/balances/{}/{}", identity(7), symbol()).into_bytes(),
+                Op::Put(TokenAmount::from(50u64).to_vec()),
+            ),
+            (
+                format!("/balances/{}/{}", identity(7), identity(2000)).into_bytes(),
+                Op::Put(TokenAmount::from(50u64).to_vec()),
+            ),
+        ],
+    );
+
+    let mut module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+    let result = module_impl.verify_all(true).unwrap();
+    assert_eq!(result.discrepancies.len(), 1);
+    assert_eq!(result.discrepancies[0].kind, DiscrepancyKind::UnknownSymbol);
+    assert!(!result.discrepancies[0].repairable);
+    assert_eq!(
+        result.supply,
+        Some(BTreeMap::from([(
+            symbol(),
+            TokenAmount::from(1_000_050u64)
+        )]))
+    );
+
+    // Not repairable, so still there.
+    assert_eq!(
+        verify_kinds(&mut module_impl, false),
+        vec![DiscrepancyKind::UnknownSymbol]
+    );
+}
+
+#[test]
+fn escrows() {
+    let Fixture { path, escrow, .. } = fixture();
+    let timeout = {
+        let module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+        let info = module_impl
+            .escrow_info(&identity(5), EscrowArgs { escrow })
+            .unwrap();
+        info.timeout
+            .as_system_time()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    };
+    let timeout_key = |secs: u64, id: Address| {
+        [
+            b"/escrow_timeouts/".to_vec(),
+            secs.to_be_bytes().to_vec(),
+            id.to_vec(),
+        ]
+        .concat()
+    };
+
+    corrupt(
+        &path,
+        vec![
+            (timeout_key(timeout, escrow), Op::Delete),
+            (timeout_key(0, identity(9)), Op::Put(vec![])),
+            (
+                format!("/escrowed/{}/{}", identity(5), symbol()).into_bytes(),
+                Op::Delete,
+            ),
+        ],
+    );
+
+    let mut module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+    assert_eq!(
+        verify_kinds(&mut module_impl, true),
+        vec![
+            DiscrepancyKind::MissingEscrowTimeout,
+            DiscrepancyKind::EscrowedAmount,
+            DiscrepancyKind::StaleEscrowTimeout,
+        ]
+    );
+    assert_eq!(verify_kinds(&mut module_impl, false), vec![]);
+}
+
+#[test]
+fn escrow_balance() {
+    let Fixture { path, escrow, .. } = fixture();
+    corrupt(
+        &path,
+        vec![(
+            format!("/balances/{}/{}", escrow, symbol()).into_bytes(),
+            Op::Put(TokenAmount::from(1u64).to_vec()),
+        )],
+    );
+
+    let mut module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+    assert_eq!(
+        verify_kinds(&mut module_impl, false),
+        vec![DiscrepancyKind::EscrowBalance]
+    );
+}
+
+#[test]
+fn indexes() {
+    let Fixture { path, account, .. } = fixture();
+    corrupt(
+        &path,
+        vec![(format!("/accounts/{}", account).into_bytes(), Op::Delete)],
+    );
+
+    let mut module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+    assert_eq!(
+        verify_kinds(&mut module_impl, false),
+        vec![DiscrepancyKind::MultisigAccount]
+    );
+    drop(module_impl);
+
+    // Remove an entry of the address index of the identity store.
+    let address_entry = {
+        let merk = merk::Merk::open(&path).unwrap();
+        let mut opts = merk::rocksdb::ReadOptions::default();
+        opts.set_iterate_lower_bound(b"/idstore/01".to_vec());
+        opts.set_iterate_upper_bound(b"/idstore/02".to_vec());
+        let (key, _) = merk
+            .iter_opt(merk::rocksdb::IteratorMode::Start, opts)
+            .next()
+            .unwrap()
+            .unwrap();
+        key.to_vec()
+    };
+    corrupt(&path, vec![(address_entry, Op::Delete)]);
+
+    let mut module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+    assert_eq!(
+        verify_kinds(&mut module_impl, true),
+        vec![
+            DiscrepancyKind::IdStoreCount,
+            DiscrepancyKind::MultisigAccount
+        ]
+    );
+}
+
+#[test]
+fn counters() {
+    let path = fixture().path;
+    corrupt(
+        &path,
+        vec![
+            (
+                b"/config/account_id".to_vec(),
+                Op::Put(0u32.to_be_bytes().to_vec()),
+            ),
+            (
+                b"/events_count".to_vec(),
+                Op::Put(1u64.to_be_bytes().to_vec()),
+            ),
+        ],
+    );
+
+    let mut module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+    assert_eq!(
+        verify_kinds(&mut module_impl, true),
+        vec![DiscrepancyKind::EventCount, DiscrepancyKind::AccountCounter]
+    );
+    assert_eq!(verify_kinds(&mut module_impl, false), vec![]);
+
+    // The escrow and the account used the first two IDs.
+    let account = create_account(&mut module_impl);
+    assert_eq!(account.subresource_id(), Some(2));
+}
+
+#[test]
+fn chunked() {
+    let path = fixture().path;
+    corrupt(
+        &path,
+        vec![(
+            format!("/escrowed/{}/{}", identity(5), symbol()).into_bytes(),
+            Op::Put(TokenAmount::from(1u64).to_vec()),
+        )],
+    );
+    let module_impl = LedgerModuleImpl::new(None, &path, false).unwrap();
+
+    let mut args = VerifyArgs {
+        cursor: None,
+        limit: Some(1),
+    };
+    let mut discrepancies = vec![];
+    let mut calls = 0;
+    let supply = loop {
+        let r = module_impl.verify(&ledger_identity(), args).unwrap();
+        discrepancies.extend(r.discrepancies);
+        calls += 1;
+        match r.next {
+            Some(cursor) => {
+                args = VerifyArgs {
+                    cursor: Some(cursor),
+                    limit: Some(1),
+                }
+            }
+            None => break r.supply,
+        }
+    };
+
+    assert_eq!(
+        discrepancies.iter().map(|d| d.kind).collect::<Vec<_>>(),
+        vec![DiscrepancyKind::EscrowedAmount]
+    );
+    assert_eq!(
+        discrepancies[0].expected,
+        Some(TokenAmount::from(100u64).to_vec().into())
+    );
+    assert_eq!(
+        supply,
+        Some(BTreeMap::from([(
+            symbol(),
+            TokenAmount::from(1_000_000u64)
+        )]))
+    );
+    assert!(calls > 10);
+}
+
+#[test]
+fn unauthorized() {
+    let module_impl = LedgerModuleImpl::new(None, fixture().path, false).unwrap();
+    assert_eq!(
+        module_impl.verify(&identity(5), VerifyArgs::default()),
+        Err(error::unauthorized())
+    );
+}
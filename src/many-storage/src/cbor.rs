@@ -0,0 +1,12 @@
+use many_error::ManyError;
+use minicbor::{Decode, Encode};
+
+/// Decode the arguments of a request.
+pub fn decode_args<'a, A: Decode<'a, ()>>(data: &'a [u8]) -> Result<A, ManyError> {
+    minicbor::decode(data).map_err(|e| ManyError::deserialization_error(e.to_string()))
+}
+
+/// Encode the returns of a response.
+pub fn encode_returns<R: Encode<()>>(returns: R) -> Result<Vec<u8>, ManyError> {
+    minicbor::to_vec(returns).map_err(|e| ManyError::serialization_error(e.to_string()))
+}
@@ -0,0 +1,168 @@
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_modules::events::EventLog;
+use many_modules::{ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use many_types::attributes::Attribute;
+use many_types::cbor::CborAny;
+use minicbor::data::Type;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+
+use crate::migration::Migration;
+
+/// The version of the schema of the events logged once the `VersionedEvents`
+/// migration is active. The ledger and key-value store events follow the
+/// same versions.
+///
+/// Bump it whenever the encoding of an event payload changes, add the
+/// decoder of the new version to `DECODERS`, and add fixtures of the new
+/// version to the `tests/event_schema.rs` of the servers. Decoders of older
+/// versions must keep upgrading their events to the current representation.
+pub const EVENT_SCHEMA_VERSION: u64 = 1;
+
+/// The oldest schema version still decoded. Events logged before schema
+/// versions were introduced are plain `EventLog`s, and have version 0.
+pub const OLDEST_EVENT_SCHEMA_VERSION: u64 = 0;
+
+/// The attribute added to the response of `events.info`, with the oldest
+/// and the current schema versions of the events as arguments. The servers
+/// use the same ID.
+pub const EVENT_SCHEMA: Attribute = Attribute::id(2001);
+
+type EventDecoder = fn(&mut minicbor::Decoder) -> Result<EventLog, minicbor::decode::Error>;
+
+/// The decoders of every schema version, from the oldest one.
+const DECODERS: &[EventDecoder] = &[decode_payload, decode_payload];
+
+/// Versions 0 and 1 have the same payloads, version 1 only records it.
+fn decode_payload(d: &mut minicbor::Decoder) -> Result<EventLog, minicbor::decode::Error> {
+    d.decode()
+}
+
+/// The number of schema versions decoded.
+pub fn decoded_versions() -> usize {
+    DECODERS.len()
+}
+
+/// The name of the migration from which events are logged with the current
+/// schema version. Before it, they are logged unversioned so replaying a
+/// chain gives the same state.
+pub const VERSIONED_EVENTS_MIGRATION: &str = "VersionedEvents";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionedEvents {
+    block_height: u64,
+    issue: Option<String>,
+}
+
+#[typetag::serde]
+impl Migration for VersionedEvents {
+    fn block_height(&self) -> u64 {
+        self.block_height
+    }
+
+    fn issue(&self) -> Option<&str> {
+        self.issue.as_deref()
+    }
+
+    fn name(&self) -> &str {
+        VERSIONED_EVENTS_MIGRATION
+    }
+
+    /// The events already logged are decoded as version 0.
+    fn migrate(&self, _persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, merk::Op)> {
+        vec![]
+    }
+}
+
+/// The schema version of the events logged with these migrations active.
+pub fn event_schema_version(active_migrations: &BTreeSet<String>) -> u64 {
+    if active_migrations.contains(VERSIONED_EVENTS_MIGRATION) {
+        EVENT_SCHEMA_VERSION
+    } else {
+        OLDEST_EVENT_SCHEMA_VERSION
+    }
+}
+
+/// Encode an event for storage with a schema version, as the array
+/// `[version, event]`, or as a plain `EventLog` for version 0. Only these
+/// two versions can be encoded.
+pub fn encode_event(event: &EventLog, version: u64) -> Result<Vec<u8>, ManyError> {
+    let bytes = match version {
+        OLDEST_EVENT_SCHEMA_VERSION => minicbor::to_vec(event),
+        EVENT_SCHEMA_VERSION => minicbor::to_vec((version, event)),
+        _ => {
+            return Err(ManyError::serialization_error(format!(
+                "Cannot encode events of schema version {}.",
+                version
+            )))
+        }
+    };
+    bytes.map_err(|e| ManyError::serialization_error(e.to_string()))
+}
+
+/// Decode a stored event of any known schema version, upgrading it to the
+/// current representation.
+pub fn decode_event(bytes: &[u8]) -> Result<EventLog, ManyError> {
+    let mut d = minicbor::Decoder::new(bytes);
+    let version = match d.datatype() {
+        Ok(Type::Array) => d
+            .array()
+            .and_then(|_| d.u64())
+            .map_err(|e| ManyError::deserialization_error(e.to_string()))?,
+        _ => OLDEST_EVENT_SCHEMA_VERSION,
+    };
+
+    let decoder = version
+        .checked_sub(OLDEST_EVENT_SCHEMA_VERSION)
+        .and_then(|i| DECODERS.get(i as usize))
+        .ok_or_else(|| {
+            ManyError::deserialization_error(format!("Unknown event schema version {}.", version))
+        })?;
+    decoder(&mut d).map_err(|e| ManyError::deserialization_error(e.to_string()))
+}
+
+/// Add the range of the event schema versions to the response of
+/// `events.info`, so clients can check they can decode the events.
+pub struct EventSchemaModule<M: ManyModule> {
+    inner: M,
+}
+
+impl<M: ManyModule> EventSchemaModule<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<M: ManyModule> Debug for EventSchemaModule<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventSchemaModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl<M: ManyModule> ManyModule for EventSchemaModule<M> {
+    fn info(&self) -> &ManyModuleInfo {
+        self.inner.info()
+    }
+
+    fn validate(&self, message: &RequestMessage, envelope: &CoseSign1) -> Result<(), ManyError> {
+        self.inner.validate(message, envelope)
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let is_info = message.method == "events.info";
+        let response = self.inner.execute(message).await?;
+        Ok(if is_info && response.data.is_ok() {
+            response.with_attribute(
+                EVENT_SCHEMA
+                    .with_argument(CborAny::Int(OLDEST_EVENT_SCHEMA_VERSION as i64))
+                    .with_argument(CborAny::Int(EVENT_SCHEMA_VERSION as i64)),
+            )
+        } else {
+            response
+        })
+    }
+}
@@ -0,0 +1,359 @@
+use many_error::ManyError;
+use merk::rocksdb::{IteratorMode, ReadOptions};
+use merk::tree::{kv_hash, Tree};
+use std::fs::File;
+use std::io::Write;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, warn};
+
+/// Default number of nodes of the merk tree checked at once.
+pub const DEFAULT_SLICE_SIZE: u64 = 1000;
+
+/// Default number of seconds between two slices.
+pub const DEFAULT_INTERVAL_SECS: u64 = 10;
+
+/// How long to wait before trying again when block processing holds the
+/// store.
+const YIELD_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CorruptionKind {
+    /// A node cannot be decoded.
+    Undecodable,
+
+    /// The value of a node does not match the hash of its key and value.
+    ValueHash,
+
+    /// A node links to a child which does not exist.
+    MissingChild,
+
+    /// The hash of a child does not match the hash its parent links it with.
+    ChildHash,
+}
+
+impl CorruptionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CorruptionKind::Undecodable => "undecodable",
+            CorruptionKind::ValueHash => "value-hash",
+            CorruptionKind::MissingChild => "missing-child",
+            CorruptionKind::ChildHash => "child-hash",
+        }
+    }
+}
+
+/// A node of the merk tree which does not match what the tree says of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    pub kind: CorruptionKind,
+
+    /// The first and last keys of the nodes involved.
+    pub first: Vec<u8>,
+    pub last: Vec<u8>,
+}
+
+impl Corruption {
+    pub fn new(kind: CorruptionKind, first: &[u8], last: &[u8]) -> Self {
+        Self {
+            kind,
+            first: first.to_vec(),
+            last: last.to_vec(),
+        }
+    }
+}
+
+/// The result of checking a slice of the merk tree.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeCheck {
+    /// The number of nodes checked.
+    pub checked: u64,
+
+    pub corruptions: Vec<Corruption>,
+
+    /// The key of the last node checked, to resume after, or `None` if the
+    /// end of the tree was reached.
+    pub next: Option<Vec<u8>>,
+}
+
+/// Decode a raw node of the merk tree. Decoding panics on malformed bytes,
+/// which are reported instead.
+fn decode(key: &[u8], bytes: &[u8]) -> Option<Tree> {
+    catch_unwind(AssertUnwindSafe(|| Tree::decode(key.to_vec(), bytes))).ok()
+}
+
+/// Read the committed raw node of the merk tree at a key.
+fn raw_node(merk: &merk::Merk, key: &[u8]) -> Result<Option<Vec<u8>>, ManyError> {
+    let mut opts = ReadOptions::default();
+    opts.set_iterate_lower_bound(key.to_vec());
+    opts.set_iterate_upper_bound(vec![key, &[0]].concat());
+
+    merk.iter_opt(IteratorMode::Start, opts)
+        .next()
+        .transpose()
+        .map(|item| item.map(|(_, v)| v.to_vec()))
+        .map_err(|e| ManyError::unknown(e.to_string()))
+}
+
+fn check_node(merk: &merk::Merk, key: &[u8], bytes: &[u8]) -> Result<Vec<Corruption>, ManyError> {
+    let tree = match decode(key, bytes) {
+        Some(tree) => tree,
+        None => return Ok(vec![Corruption::new(CorruptionKind::Undecodable, key, key)]),
+    };
+
+    let mut corruptions = vec![];
+    if kv_hash(tree.key(), tree.value()) != *tree.kv_hash() {
+        corruptions.push(Corruption::new(CorruptionKind::ValueHash, key, key));
+    }
+    for left in [true, false] {
+        let link = match tree.link(left) {
+            Some(link) => link,
+            None => continue,
+        };
+        let child = link.key();
+        let kind = match raw_node(merk, child)? {
+            None => Some(CorruptionKind::MissingChild),
+            // An undecodable child is reported when it is checked itself.
+            Some(bytes) => decode(child, &bytes)
+                .filter(|c| c.hash() != *link.hash())
+                .map(|_| CorruptionKind::ChildHash),
+        };
+        if let Some(kind) = kind {
+            corruptions.push(Corruption::new(kind, key.min(child), key.max(child)));
+        }
+    }
+    Ok(corruptions)
+}
+
+/// Check at most `limit` nodes of the committed merk tree, in key order
+/// after the `after` key: that each node decodes, that the hash of its key
+/// and value is the one it stores, and that each of its children exists
+/// with the hash the node links it with.
+pub fn check_tree(
+    merk: &merk::Merk,
+    after: Option<&[u8]>,
+    limit: usize,
+) -> Result<TreeCheck, ManyError> {
+    let mut opts = ReadOptions::default();
+    if let Some(after) = after {
+        opts.set_iterate_lower_bound(vec![after, &[0]].concat());
+    }
+
+    let mut check = TreeCheck::default();
+    let mut last = None;
+    for item in merk.iter_opt(IteratorMode::Start, opts).take(limit) {
+        let (key, bytes) = item.map_err(|e| ManyError::unknown(e.to_string()))?;
+        check
+            .corruptions
+            .append(&mut check_node(merk, &key, &bytes)?);
+        check.checked += 1;
+        last = Some(key.to_vec());
+    }
+
+    if check.checked as usize == limit {
+        check.next = last;
+    }
+    Ok(check)
+}
+
+pub trait IntegrityBackend: Send {
+    /// Check at most `limit` nodes of the committed merk tree after a key.
+    fn check_tree(&self, after: Option<&[u8]>, limit: usize) -> Result<TreeCheck, ManyError>;
+}
+
+/// A pass over the whole tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassReport {
+    pub completed_at: SystemTime,
+
+    /// How long the pass took, from the start of the scrubber for a pass
+    /// resumed after a restart.
+    pub duration: Duration,
+
+    /// The number of nodes checked, after the cursor for a pass resumed
+    /// after a restart.
+    pub nodes_checked: u64,
+
+    pub corruptions: Vec<Corruption>,
+}
+
+/// Counters of the checks, shared with whoever reports them.
+#[derive(Debug, Default)]
+pub struct IntegrityMetrics {
+    pub nodes_checked: AtomicU64,
+    pub corruptions: AtomicU64,
+
+    /// The number of passes over the whole tree completed.
+    pub passes: AtomicU64,
+
+    last_pass: Mutex<Option<PassReport>>,
+}
+
+impl IntegrityMetrics {
+    /// The last pass over the whole tree completed, if any.
+    pub fn last_pass(&self) -> Option<PassReport> {
+        self.last_pass.lock().ok().and_then(|last| last.clone())
+    }
+}
+
+/// Checks the merk tree against the bytes in rocksdb in the background, so
+/// disk corruption is found before a proof or a state sync trips on it.
+///
+/// Every interval, a bounded slice of the tree is checked, then the cursor
+/// is persisted, so a restart resumes the pass where it was. The store is
+/// only locked for as long as it takes to check a slice, and a slice is
+/// skipped while block processing holds it. Corruptions are logged loudly
+/// with the range of keys affected; they only stop the node if asked to.
+pub struct Scrubber<T: IntegrityBackend> {
+    backend: Arc<Mutex<T>>,
+    cursor_path: PathBuf,
+
+    /// The key of the last node checked.
+    cursor: Option<Vec<u8>>,
+
+    slice_size: usize,
+    halt_on_corruption: bool,
+    metrics: Arc<IntegrityMetrics>,
+
+    /// The current pass.
+    pass_started: Instant,
+    pass_nodes: u64,
+    pass_corruptions: Vec<Corruption>,
+}
+
+impl<T: IntegrityBackend + 'static> Scrubber<T> {
+    /// Create a scrubber resuming from the cursor saved at the given path,
+    /// if any.
+    pub fn new(
+        backend: Arc<Mutex<T>>,
+        cursor_path: impl Into<PathBuf>,
+        slice_size: u64,
+    ) -> Result<Self, String> {
+        let cursor_path = cursor_path.into();
+        let cursor = match std::fs::read_to_string(&cursor_path) {
+            Ok(hex) if hex.trim().is_empty() => None,
+            Ok(hex) => Some(hex::decode(hex.trim()).map_err(|e| e.to_string())?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        Ok(Self {
+            backend,
+            cursor_path,
+            cursor,
+            slice_size: slice_size.max(1) as usize,
+            halt_on_corruption: false,
+            metrics: Arc::default(),
+            pass_started: Instant::now(),
+            pass_nodes: 0,
+            pass_corruptions: vec![],
+        })
+    }
+
+    /// Exit the process when a corruption is found, instead of only logging
+    /// it.
+    pub fn with_halt_on_corruption(mut self, halt_on_corruption: bool) -> Self {
+        self.halt_on_corruption = halt_on_corruption;
+        self
+    }
+
+    pub fn metrics(&self) -> Arc<IntegrityMetrics> {
+        self.metrics.clone()
+    }
+
+    /// The key of the last node checked, or `None` at the start of a pass.
+    pub fn cursor(&self) -> Option<&[u8]> {
+        self.cursor.as_deref()
+    }
+
+    fn save_cursor(&self) -> std::io::Result<()> {
+        let tmp = self.cursor_path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(
+            self.cursor
+                .as_ref()
+                .map(hex::encode)
+                .unwrap_or_default()
+                .as_bytes(),
+        )?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.cursor_path)
+    }
+
+    /// Check the next slice of the tree. Returns the corruptions found, or
+    /// `None` if block processing holds the store.
+    pub fn poll(&mut self) -> Result<Option<Vec<Corruption>>, String> {
+        let check = match self.backend.try_lock() {
+            Ok(backend) => backend
+                .check_tree(self.cursor.as_deref(), self.slice_size)
+                .map_err(|e| e.to_string())?,
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Poisoned(e)) => return Err(e.to_string()),
+        };
+
+        self.metrics
+            .nodes_checked
+            .fetch_add(check.checked, Ordering::Relaxed);
+        self.metrics
+            .corruptions
+            .fetch_add(check.corruptions.len() as u64, Ordering::Relaxed);
+        for corruption in &check.corruptions {
+            error!(
+                "CORRUPTION in the merk tree: {:?} for keys {}..={}",
+                corruption.kind,
+                hex::encode(&corruption.first),
+                hex::encode(&corruption.last)
+            );
+        }
+        debug!("integrity: checked {} nodes", check.checked);
+
+        self.pass_nodes += check.checked;
+        self.pass_corruptions
+            .extend(check.corruptions.iter().cloned());
+        if check.next.is_none() {
+            let report = PassReport {
+                completed_at: SystemTime::now(),
+                duration: self.pass_started.elapsed(),
+                nodes_checked: std::mem::take(&mut self.pass_nodes),
+                corruptions: std::mem::take(&mut self.pass_corruptions),
+            };
+            self.pass_started = Instant::now();
+            let passes = self.metrics.passes.fetch_add(1, Ordering::Relaxed) + 1;
+            info!(
+                "integrity: pass {} over the merk tree completed in {:?}, {} nodes checked, {} corruptions found",
+                passes,
+                report.duration,
+                report.nodes_checked,
+                report.corruptions.len()
+            );
+            if let Ok(mut last) = self.metrics.last_pass.lock() {
+                *last = Some(report);
+            }
+        }
+        self.cursor = check.next;
+        self.save_cursor().map_err(|e| e.to_string())?;
+        Ok(Some(check.corruptions))
+    }
+
+    /// Run the checks on a background thread, a slice every interval.
+    pub fn spawn(mut self, interval: Duration) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            match self.poll() {
+                Ok(None) => {
+                    std::thread::sleep(YIELD_DELAY);
+                    continue;
+                }
+                Ok(Some(corruptions)) if !corruptions.is_empty() && self.halt_on_corruption => {
+                    error!("Halting on corruption of the merk tree.");
+                    std::process::exit(1);
+                }
+                Ok(Some(_)) => {}
+                Err(e) => warn!("integrity: {}", e),
+            }
+            std::thread::sleep(interval);
+        })
+    }
+}
@@ -0,0 +1,5 @@
+pub mod cbor;
+pub mod event_schema;
+pub mod integrity;
+pub mod migration;
+pub mod posture;
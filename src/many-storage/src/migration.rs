@@ -0,0 +1,79 @@
+use merk::Op;
+use std::{collections::BTreeSet, fmt::Debug};
+use tracing::info;
+
+/// The key of the names of the migrations applied to the store.
+pub const MIGRATIONS_KEY: &[u8] = b"/config/migrations";
+
+/// A change of the state, or of how it is written, which every node applies
+/// at the same block height.
+#[typetag::serde(tag = "type")]
+pub trait Migration: Debug + Send + Sync {
+    fn migrate(&self, persistent_store: &mut merk::Merk) -> Vec<(Vec<u8>, Op)>;
+    fn block_height(&self) -> u64;
+    fn issue(&self) -> Option<&str>;
+    fn name(&self) -> &str;
+}
+
+impl PartialEq<Box<dyn Migration>> for Box<dyn Migration> {
+    fn eq(&self, other: &Box<dyn Migration>) -> bool {
+        self.name().eq(other.name())
+    }
+}
+
+impl Eq for Box<dyn Migration> {}
+
+impl PartialOrd<Box<dyn Migration>> for Box<dyn Migration> {
+    fn partial_cmp(&self, other: &Box<dyn Migration>) -> Option<std::cmp::Ordering> {
+        self.name().partial_cmp(other.name())
+    }
+}
+
+impl Ord for Box<dyn Migration> {
+    fn cmp(&self, other: &Box<dyn Migration>) -> std::cmp::Ordering {
+        self.name().cmp(other.name())
+    }
+}
+
+/// The names of the migrations applied to the store.
+pub fn active_migrations(persistent_store: &merk::Merk) -> BTreeSet<String> {
+    persistent_store
+        .get(MIGRATIONS_KEY)
+        .expect("Could not open storage.")
+        .map(|x| minicbor::decode(&x).expect("Could not read migrations"))
+        .unwrap_or_default()
+}
+
+/// Apply the migrations whose height is reached, and record the names of the
+/// active migrations. Stores which recorded them before any was active keep
+/// recording them with `record_empty`, other stores only once one is, so
+/// their state does not change without migrations.
+pub fn run_migrations(
+    current_height: u64,
+    all_migrations: &BTreeSet<Box<dyn Migration>>,
+    active_migrations: &mut BTreeSet<String>,
+    persistent_store: &mut merk::Merk,
+    record_empty: bool,
+) {
+    let mut operations = vec![];
+    for migration in all_migrations {
+        if current_height >= migration.block_height()
+            && active_migrations.insert(migration.name().to_string())
+        {
+            info!("Migration {:?} being applied", migration);
+            operations.append(&mut migration.migrate(persistent_store))
+        }
+    }
+    if active_migrations.is_empty() && !record_empty {
+        return;
+    }
+    operations.push((
+        MIGRATIONS_KEY.to_vec(),
+        Op::Put(
+            minicbor::to_vec(active_migrations.clone())
+                .expect("Could not encode migrations to cbor"),
+        ),
+    ));
+    operations.sort_by(|(a, _), (b, _)| a.cmp(b));
+    persistent_store.apply(&operations).unwrap();
+}
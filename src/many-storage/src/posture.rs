@@ -0,0 +1,248 @@
+use coset::CoseSign1;
+use many_error::ManyError;
+use many_identity::Address;
+use many_modules::abci_backend::EndpointInfo;
+use many_modules::{kvstore, ledger, ManyModule, ManyModuleInfo};
+use many_protocol::{RequestMessage, ResponseMessage};
+use minicbor::{Decode, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Display, Formatter};
+
+/// The checks a module makes on the sender of a command before executing
+/// it. The posture evaluates them through this, so it reports what the
+/// module actually does.
+pub trait SenderGate {
+    /// Whether a command from `sender` passes the checks of the module on
+    /// its sender. The command can still fail later, e.g. on the ACL of a
+    /// key.
+    fn admits(&self, method: &str, sender: &Address) -> bool;
+}
+
+/// The commands module does not check senders, the backend checks they can
+/// send from the account.
+impl<T: ledger::LedgerCommandsModuleBackend> SenderGate for ledger::LedgerCommandsModule<T> {
+    fn admits(&self, _method: &str, _sender: &Address) -> bool {
+        true
+    }
+}
+
+/// The commands module does not check senders, the ACL of each key does.
+impl<T: kvstore::KvStoreCommandsModuleBackend> SenderGate for kvstore::KvStoreCommandsModule<T> {
+    fn admits(&self, _method: &str, _sender: &Address) -> bool {
+        true
+    }
+}
+
+/// The effective security settings of a server, as it was started.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct Posture {
+    /// The verifiers of the envelopes of requests.
+    #[n(0)]
+    pub verifiers: Vec<String>,
+
+    /// The number of addresses of the allow list, if there is one.
+    #[n(1)]
+    pub allow_addrs: Option<u64>,
+
+    /// Whether the server serves no command.
+    #[n(2)]
+    pub read_only: bool,
+
+    /// The command endpoints served.
+    #[n(3)]
+    pub commands: BTreeSet<String>,
+
+    /// The command endpoints whose sender checks admit anonymous senders.
+    #[n(4)]
+    pub anonymous_commands: BTreeSet<String>,
+
+    /// The addresses of the listeners, by name.
+    #[n(5)]
+    pub listeners: BTreeMap<String, String>,
+
+    /// The quotas and limits, by name.
+    #[n(6)]
+    pub limits: BTreeMap<String, String>,
+}
+
+fn list<'a>(items: impl IntoIterator<Item = &'a String>) -> String {
+    let items: Vec<&str> = items.into_iter().map(String::as_str).collect();
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+fn pairs(map: &BTreeMap<String, String>) -> String {
+    list(
+        &map.iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>(),
+    )
+}
+
+impl Display for Posture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Security posture:")?;
+        writeln!(f, "  verifiers: {}", list(&self.verifiers))?;
+        match self.allow_addrs {
+            Some(count) => writeln!(f, "  allow list: {count} addresses")?,
+            None => writeln!(f, "  allow list: none")?,
+        }
+        writeln!(
+            f,
+            "  read-only: {} ({} command endpoints)",
+            if self.read_only { "yes" } else { "no" },
+            self.commands.len()
+        )?;
+        writeln!(
+            f,
+            "  commands open to anonymous senders: {}",
+            list(&self.anonymous_commands)
+        )?;
+        writeln!(f, "  listeners: {}", pairs(&self.listeners))?;
+        write!(f, "  limits: {}", pairs(&self.limits))
+    }
+}
+
+/// Computes the posture of a server from its settings and the modules it
+/// serves.
+pub struct PostureBuilder {
+    posture: Posture,
+    endpoints: BTreeMap<String, EndpointInfo>,
+}
+
+impl PostureBuilder {
+    /// The endpoints are the ones of the ABCI backend, which tell commands
+    /// from queries.
+    pub fn new(endpoints: BTreeMap<String, EndpointInfo>) -> Self {
+        Self {
+            posture: Posture::default(),
+            endpoints,
+        }
+    }
+
+    pub fn with_verifiers(mut self, verifiers: &[&str]) -> Self {
+        self.posture.verifiers = verifiers.iter().map(|v| v.to_string()).collect();
+        self
+    }
+
+    pub fn with_allow_addrs(mut self, allow_addrs: Option<&BTreeSet<Address>>) -> Self {
+        self.posture.allow_addrs = allow_addrs.map(|a| a.len() as u64);
+        self
+    }
+
+    pub fn with_listener(mut self, name: &str, addr: impl Display) -> Self {
+        self.posture
+            .listeners
+            .insert(name.to_string(), addr.to_string());
+        self
+    }
+
+    pub fn with_limit(mut self, name: &str, value: impl Display) -> Self {
+        self.posture
+            .limits
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    fn commands<'a, M: ManyModule>(&'a self, module: &'a M) -> impl Iterator<Item = &'a String> {
+        module
+            .info()
+            .endpoints
+            .iter()
+            .filter(|e| self.endpoints.get(*e).map_or(false, |info| info.is_command))
+    }
+
+    /// Record the commands of a module, and which of them its sender checks
+    /// admit anonymous senders to, by asking them.
+    pub fn gated<M: ManyModule + SenderGate>(&mut self, module: M) -> M {
+        let anonymous = Address::anonymous();
+        let commands: Vec<String> = self.commands(&module).cloned().collect();
+        for command in commands {
+            if module.admits(&command, &anonymous) {
+                self.posture.anonymous_commands.insert(command.clone());
+            }
+            self.posture.commands.insert(command);
+        }
+        module
+    }
+
+    /// Record the commands of a module which does not check their sender.
+    pub fn open<M: ManyModule>(&mut self, module: M) -> M {
+        let commands: Vec<String> = self.commands(&module).cloned().collect();
+        self.posture.anonymous_commands.extend(commands.clone());
+        self.posture.commands.extend(commands);
+        module
+    }
+
+    pub fn build(self) -> Posture {
+        Posture {
+            read_only: self.posture.commands.is_empty(),
+            ..self.posture
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+#[cbor(map)]
+pub struct PostureArgs {}
+
+/// A module returning the security posture of the server to its own
+/// identity.
+pub struct PostureModule {
+    posture: Posture,
+    server: Address,
+    info: ManyModuleInfo,
+}
+
+impl PostureModule {
+    pub fn new(posture: Posture, server: Address) -> Self {
+        Self {
+            posture,
+            server,
+            info: ManyModuleInfo {
+                name: "PostureModule".to_string(),
+                attribute: None,
+                endpoints: vec!["server.posture".to_string()],
+            },
+        }
+    }
+}
+
+impl Debug for PostureModule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PostureModule")
+    }
+}
+
+#[async_trait::async_trait]
+impl ManyModule for PostureModule {
+    fn info(&self) -> &ManyModuleInfo {
+        &self.info
+    }
+
+    fn validate(&self, message: &RequestMessage, _envelope: &CoseSign1) -> Result<(), ManyError> {
+        match message.method.as_str() {
+            "server.posture" => minicbor::decode::<PostureArgs>(&message.data)
+                .map(|_| ())
+                .map_err(|e| ManyError::deserialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        }
+    }
+
+    async fn execute(&self, message: RequestMessage) -> Result<ResponseMessage, ManyError> {
+        let data = match message.method.as_str() {
+            "server.posture" if message.from() != self.server => {
+                Err(ManyError::invalid_from_identity())
+            }
+            "server.posture" => minicbor::to_vec(&self.posture)
+                .map_err(|e| ManyError::serialization_error(e.to_string())),
+            _ => Err(ManyError::invalid_method_name(message.method.clone())),
+        };
+
+        Ok(ResponseMessage::from_request(&message, &message.to, data))
+    }
+}
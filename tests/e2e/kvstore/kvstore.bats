@@ -77,3 +77,15 @@ function teardown() {
   call_kvstore --pem=1 --port=8000 query "112233"
   assert_output --partial "sad"
 }
+
+@test "$SUITE: can verify the hash of a value" {
+  call_kvstore --pem=1 --port=8000 put "0a0b0c" "foobar"
+  call_kvstore --pem=1 --port=8000 get "0a0b0c" --expect-sha256 "$(printf foobar | sha256sum | cut -d' ' -f1)"
+  assert_success
+  assert_output --partial "foobar"
+
+  call_kvstore --pem=1 --port=8000 get "0a0b0c" --expect-sha256 "$(printf foobaz | sha256sum | cut -d' ' -f1)"
+  assert_equal "$status" 3
+  assert_output --partial "The SHA-256 of the value is"
+  refute_output --partial "foobar"
+}
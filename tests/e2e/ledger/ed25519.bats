@@ -0,0 +1,68 @@
+GIT_ROOT="$BATS_TEST_DIRNAME/../../../"
+START_BALANCE=100000000000
+MFX_ADDRESS=mqbfbahksdwaqeenayy2gxke32hgb7aq4ao4wt745lsfs6wiaaaaqnz
+
+load '../../test_helper/load'
+load '../../test_helper/ledger'
+
+function setup() {
+    mkdir "$BATS_TEST_ROOTDIR"
+
+    skip_if_missing_background_utilities
+
+    if ! [ $CI ]; then
+        (
+          cd "$GIT_ROOT"
+          cargo build --all-features
+        )
+    fi
+
+    start_ledger --pem "$(pem 0)" \
+          "--balance-only-for-testing=$(identity ed25519-1):$START_BALANCE:$MFX_ADDRESS" \
+          "--balance-only-for-testing=$(identity 2):$START_BALANCE:$MFX_ADDRESS"
+}
+
+function teardown() {
+    stop_background_run
+}
+
+@test "$SUITE: Ledger shows a balance and can send tokens with an Ed25519 key" {
+    check_consistency --pem=ed25519-1 --balance=$START_BALANCE --id="$(identity ed25519-1)" 8000
+
+    call_ledger --pem=ed25519-1 --port=8000 send "$(identity ed25519-3)" 1000 MFX
+    check_consistency --pem=ed25519-3 --balance=1000 --id="$(identity ed25519-3)" 8000
+    check_consistency --pem=ed25519-1 --balance=$((START_BALANCE - 1000)) --id="$(identity ed25519-1)" 8000
+
+    # Back to an ECDSA identity.
+    call_ledger --pem=ed25519-3 --port=8000 send "$(identity 2)" 100 MFX
+    check_consistency --pem=2 --balance=$((START_BALANCE + 100)) --id="$(identity 2)" 8000
+    check_consistency --pem=ed25519-3 --balance=900 --id="$(identity ed25519-3)" 8000
+}
+
+@test "$SUITE: Ledger can do multisig transactions with an Ed25519 key" {
+    local account_id
+    local tx_id
+
+    account_id=$(account_create --pem=ed25519-1 '{ 1: { "'"$(identity 2)"'": ["canMultisigApprove"] }, 2: [[1, { 0: 2 }]] }')
+
+    call_ledger --pem=ed25519-1 --port=8000 send "$account_id" 1000000 MFX
+    check_consistency --pem=ed25519-1 --balance=1000000 --id="$account_id" 8000
+
+    call_ledger --pem=ed25519-1 --port=8000 multisig submit "$account_id" send "$(identity ed25519-3)" 100 MFX
+    tx_id=$(echo "$output" | grep -oE "[0-9a-f]+$")
+
+    call_ledger --pem=2 --port=8000 multisig approve "$tx_id"
+
+    call_ledger --pem=ed25519-1 --port=8000 multisig execute "$tx_id"
+    assert_output --partial "Sent 100 MFX from $account_id to $(identity ed25519-3)."
+
+    check_consistency --pem=ed25519-1 --balance=999900 --id="$account_id" 8000
+    check_consistency --pem=ed25519-3 --balance=100 --id="$(identity ed25519-3)" 8000
+}
+
+@test "$SUITE: Ledger rejects an unsupported key" {
+    openssl genpkey -algorithm rsa -pkeyopt rsa_keygen_bits:2048 -out "$PEM_ROOT/id-rsa.pem" 2>/dev/null
+
+    call_ledger --pem=rsa --port=8000 balance
+    assert_output --partial "The key must be an ECDSA P-256 or Ed25519 key."
+}
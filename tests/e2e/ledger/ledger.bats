@@ -42,3 +42,191 @@ function teardown() {
     call_ledger --pem=4 --port=8000 send --account="$account_id" "$(identity 4)" 2000 MFX
     assert_output --partial "Sender needs role 'canLedgerTransact' to perform this operation."
 }
+
+@test "$SUITE: ledger can split a transfer" {
+    call_ledger --pem=1 --port=8000 send --split=3 --yes "$(identity 3)" 1000 MFX
+    assert_output --partial "3/3: sent 334"
+    check_consistency --pem=3 --balance=1000 --id="$(identity 3)" 8000
+    check_consistency --pem=1 --balance=$((START_BALANCE - 1000)) --id="$(identity 1)" 8000
+}
+
+@test "$SUITE: ledger can send tokens to several recipients" {
+    call_ledger --pem=1 --port=8000 send-many --yes --symbol=MFX \
+        --to="$(identity 3):1000" --to="$(identity 4):2000" --to="$(identity 5):3000"
+    assert_success
+    assert_output --partial "Sent 6000 of 6000 $MFX_ADDRESS to 3 of 3 recipients."
+    check_consistency --pem=3 --balance=1000 --id="$(identity 3)" 8000
+    check_consistency --pem=4 --balance=2000 --id="$(identity 4)" 8000
+    check_consistency --pem=5 --balance=3000 --id="$(identity 5)" 8000
+    check_consistency --pem=1 --balance=$((START_BALANCE - 6000)) --id="$(identity 1)" 8000
+
+    # An invalid transfer is found before the first one is sent.
+    call_ledger --pem=1 --port=8000 send-many --yes --symbol=MFX \
+        --to="$(identity 3):1000" --to="$(identity 4):0"
+    assert_failure
+    assert_output --partial "Transfer 2 to $(identity 4): The amount cannot be zero."
+    check_consistency --pem=3 --balance=1000 --id="$(identity 3)" 8000
+
+    call_ledger --pem=1 --port=8000 send-many --yes --symbol=MFX \
+        --to="$(identity 3):1000" --to="$(identity 4):$START_BALANCE"
+    assert_failure
+    assert_output --partial "exceeds the balance"
+    check_consistency --pem=3 --balance=1000 --id="$(identity 3)" 8000
+}
+
+@test "$SUITE: ledger can send tokens to an alias" {
+    local aliases="--aliases=$BATS_TEST_ROOTDIR/aliases.json5"
+    call_ledger --port=8000 "$aliases" alias add bob "$(identity 3)"
+    assert_success
+
+    call_ledger --port=8000 "$aliases" alias list
+    assert_output --regexp "bob +$(identity 3)"
+
+    call_ledger --pem=1 --port=8000 "$aliases" send bob 1000 MFX
+    check_consistency --pem=3 --balance=1000 --id="$(identity 3)" 8000
+
+    call_ledger --port=8000 "$aliases" alias rm bob
+    call_ledger --pem=1 --port=8000 "$aliases" send bob 1000 MFX
+    assert_failure
+    assert_output --partial "Invalid address 'bob', which is not an alias either"
+}
+
+@test "$SUITE: ledger can use an encrypted PEM file" {
+    openssl pkcs8 -topk8 -v2 aes-256-cbc -in "$(pem 1)" -passout pass:hunter2 -out "$PEM_ROOT/id-1-encrypted.pem"
+
+    export PEM_PASSWORD=hunter2
+    call_ledger --pem=1-encrypted --port=8000 --pem-password-env=PEM_PASSWORD send "$(identity 4)" 1000 MFX
+    check_consistency --pem=4 --balance=1000 --id="$(identity 4)" 8000
+
+    call_ledger --port=8000 --pem-password-env=PEM_PASSWORD balance "$PEM_ROOT/id-1-encrypted.pem"
+    assert_output --partial "$((START_BALANCE - 1000)) MFX"
+
+    export PEM_PASSWORD=hunter3
+    call_ledger --pem=1-encrypted --port=8000 --pem-password-env=PEM_PASSWORD balance
+    assert_output --partial "Incorrect passphrase for the key in"
+}
+
+@test "$SUITE: ledger can use the keys of a keystore" {
+    local keystore="--keystore=$BATS_TEST_ROOTDIR/keystore.json5"
+
+    call_ledger --port=8000 "$keystore" key import default "$(pem 1)"
+    call_ledger --port=8000 "$keystore" key import other "$(pem 2)"
+    call_ledger --port=8000 "$keystore" key list
+    assert_output --regexp "default +$(identity 1)"
+    assert_output --regexp "other +$(identity 2)"
+
+    call_ledger --port=8000 "$keystore" send "$(identity 4)" 1000 MFX
+    check_consistency --pem=4 --balance=1000 --id="$(identity 4)" 8000
+    check_consistency --pem=1 --balance=$((START_BALANCE - 1000)) --id="$(identity 1)" 8000
+
+    call_ledger --port=8000 "$keystore" --key-name=other send "$(identity 4)" 1000 MFX
+    check_consistency --pem=4 --balance=2000 --id="$(identity 4)" 8000
+    check_consistency --pem=2 --balance=$((START_BALANCE - 1000)) --id="$(identity 2)" 8000
+
+    # --pem takes precedence.
+    call_ledger --pem=4 --port=8000 "$keystore" --key-name=other send "$(identity 5)" 1000 MFX
+    check_consistency --pem=5 --balance=1000 --id="$(identity 5)" 8000
+    check_consistency --pem=4 --balance=1000 --id="$(identity 4)" 8000
+
+    call_ledger --port=8000 "$keystore" --key-name=missing balance
+    assert_output --partial "Unknown key 'missing'"
+}
+
+@test "$SUITE: ledger can sign a transfer offline" {
+    local envelope="$BATS_TEST_ROOTDIR/envelope.cbor"
+
+    # Nothing listens on port 1, so this fails if the server is contacted.
+    call_ledger --pem=ed25519-1 --port=1 sign --out="$envelope" --timestamp=1700000000 --nonce=00112233 "$(identity 4)" 1000 "$MFX_ADDRESS"
+    assert_success
+    [ -s "$envelope" ]
+
+    # Ed25519 signatures are deterministic, so the same message gives the same envelope.
+    call_ledger --pem=ed25519-1 --port=1 sign --out="$envelope.2" --timestamp=1700000000 --nonce=00112233 "$(identity 4)" 1000 "$MFX_ADDRESS"
+    cmp "$envelope" "$envelope.2"
+
+    call_ledger --pem=ed25519-1 --port=1 sign --out="$envelope" "$(identity 4)" 1000 MFX
+    assert_failure
+    assert_output --partial "Symbols are not resolved when signing offline"
+}
+
+@test "$SUITE: ledger can submit a transfer signed offline" {
+    local envelope="$BATS_TEST_ROOTDIR/envelope.cbor"
+
+    call_ledger --pem=1 --port=1 sign --out="$envelope" "$(identity 4)" 1000 "$MFX_ADDRESS"
+    assert_success
+
+    # The submitting machine has no key.
+    call_ledger --port=8000 submit "$envelope"
+    assert_success
+    assert_output --partial "signed by $(identity 1)"
+    check_consistency --pem=4 --balance=1000 --id="$(identity 4)" 8000
+    check_consistency --pem=1 --balance=$((START_BALANCE - 1000)) --id="$(identity 1)" 8000
+
+    echo "not an envelope" > "$envelope"
+    call_ledger --port=8000 submit "$envelope"
+    assert_failure
+    assert_output --partial "Not a COSE_Sign1 envelope"
+}
+
+@test "$SUITE: ledger retries queries after network errors" {
+    # Nothing listens on port 1, so every attempt fails.
+    call_ledger --pem=1 --port=1 -v --retries=2 balance
+    assert_failure
+    assert_output --partial "Retrying in 500ms, attempt 1 of 2."
+    assert_output --partial "Retrying in 1s, attempt 2 of 2."
+
+    call_ledger --pem=1 --port=8000 --timeout=30 --retries=2 balance
+    assert_success
+    assert_output --partial "$START_BALANCE MFX"
+}
+
+@test "$SUITE: ledger can show the balances of several identities" {
+    call_ledger --pem=1 --port=8000 send "$(identity 3)" 1000 MFX
+
+    call_ledger --port=8000 balance "$(identity 1)" "$(identity 2)" "$(identity 3)" -- MFX
+    assert_success
+    assert_output --partial "$(identity 3)"
+    assert_line --regexp "^ +1000 MFX "
+    assert_line "Total"
+    assert_line --regexp "^ +$((START_BALANCE * 2)) MFX "
+}
+
+@test "$SUITE: ledger can watch balances until interrupted" {
+    local ledgercmd="$GIT_ROOT/target/debug/ledger"
+    [[ "$CI" == "true" ]] && ledgercmd="ledger"
+    local out="$BATS_TEST_ROOTDIR/watch.txt"
+
+    "$ledgercmd" http://localhost:8000/ balance --watch --interval=1 "$(identity 3)" > "$out" &
+    local pid=$!
+    sleep 2
+    call_ledger --pem=1 --port=8000 send "$(identity 3)" 1000 MFX
+    sleep 3
+
+    # Ctrl-C ends the watch successfully.
+    kill -INT "$pid"
+    wait "$pid"
+    grep -E "1000 MFX .*\((new|was 0)\)" "$out"
+}
+
+@test "$SUITE: ledger can show a single event" {
+    local event_id
+
+    call_ledger --pem=1 --port=8000 send "$(identity 3)" 1000 MFX
+    call_ledger --pem=3 --port=8000 history
+    event_id=$(echo "$output" | grep "receive" | grep -oE "^[0-9a-f]+")
+
+    call_ledger --port=8000 event get "$event_id"
+    assert_success
+    assert_output --partial "Event $event_id"
+    assert_output --partial "Type:   Send"
+    assert_output --partial "From:   $(identity 1)"
+    assert_output --partial "Amount: 1000 MFX"
+
+    call_ledger --port=8000 event get ffffffffffffffff
+    assert_failure
+    assert_output --partial "No event ffffffffffffffff."
+
+    call_ledger --port=8000 event count
+    assert_success
+    assert_line --regexp "^[1-9][0-9]*$"
+}
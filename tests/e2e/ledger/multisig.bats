@@ -51,14 +51,18 @@ function teardown() {
     # Cannot execute if not approved.
     call_ledger --pem=1 --port=8000 multisig execute "$tx_id"
     assert_output --partial "This transaction cannot be executed yet."
+    assert_output --partial "has 1 of the 2 approvals it needs"
 
     call_ledger --pem=2 --port=8000 multisig approve "$tx_id"
 
     # Cannot execute if not submitted.
     call_ledger --pem=2 --port=8000 multisig execute "$tx_id"
     assert_output --partial "This transaction cannot be executed yet."
+    assert_output --partial "only its submitter"
 
     call_ledger --pem=1 --port=8000 multisig execute "$tx_id"
+    assert_output --partial "Sent 100 MFX from $account_id to $(identity 3)."
+    assert_output --partial "Event: "
 
     check_consistency --pem=1 --balance=999900 --id="$account_id" 8000
     check_consistency --pem=3 --balance=100 --id="$(identity 3)" 8000
@@ -93,3 +97,129 @@ function teardown() {
 
     check_consistency --pem=3 --balance=100 --id="$(identity 3)" 8000
 }
+
+@test "$SUITE: can withdraw" {
+    local account_id
+    local tx_id
+
+    account_id=$(account_create --pem=1 '{ 1: { "'"$(identity 2)"'": ["canMultisigApprove"] }, 2: [[1, { 0: 2 }]] }')
+
+    call_ledger --pem=1 --port=8000 send "$account_id" 1000000 MFX
+    call_ledger --pem=1 --port=8000 multisig submit "$account_id" send "$(identity 3)" 100 MFX
+    tx_id=$(echo "$output" | grep -oE "[0-9a-f]+$")
+
+    # Only the submitter can withdraw.
+    call_ledger --pem=2 --port=8000 multisig withdraw "$tx_id"
+    assert_output --partial "Only the submitter of a transaction can withdraw it"
+
+    call_ledger --pem=1 --port=8000 multisig withdraw "$tx_id"
+    assert_output --partial "State:     Withdrawn"
+
+    call_ledger --pem=1 --port=8000 multisig execute "$tx_id"
+    check_consistency --pem=1 --balance=1000000 --id="$account_id" 8000
+}
+
+@test "$SUITE: can override the defaults of a transaction" {
+    local account_id
+    local tx_id
+
+    account_id=$(account_create --pem=1 '{ 1: { "'"$(identity 2)"'": ["canMultisigApprove"] }, 2: [[1, { 0: 2 }]] }')
+    call_ledger --pem=1 --port=8000 send "$account_id" 1000000 MFX
+
+    call_ledger --pem=1 --port=8000 multisig submit "$account_id" --threshold 0 send "$(identity 3)" 100 MFX
+    assert_output --partial "The threshold must be at least 1 approval."
+
+    call_ledger --pem=1 --port=8000 multisig submit "$account_id" --threshold 1 --expire-in 1h --execute-automatically false send "$(identity 3)" 100 MFX
+    assert_output --partial "Transaction token: "
+    tx_id=$(echo "$output" | grep -oE "[0-9a-f]+$")
+
+    call_ledger --pem=1 --port=8000 multisig info "$tx_id"
+    assert_output --partial "Approvals: 1/1"
+    assert_output --partial "Execute automatically: no"
+
+    call_ledger --pem=1 --port=8000 multisig execute "$tx_id"
+    check_consistency --pem=3 --balance=100 --id="$(identity 3)" 8000
+}
+
+@test "$SUITE: can create a multisig account with the CLI" {
+    local account_id
+    local tx_id
+
+    call_ledger --pem=1 --port=8000 account create --role "$(identity 2)":canFly --feature multisig
+    assert_output --partial "Unknown role 'canFly'"
+
+    call_ledger --pem=1 --port=8000 account create --description "Treasury" --role "$(identity 2)":canMultisigApprove --feature ledger --feature multisig
+    account_id=$(echo "$output" | grep -oE "^m[0-9a-z]+$")
+    assert [ ${#account_id} -eq 55 ]
+
+    call_ledger --pem=1 --port=8000 account info "$account_id"
+    assert_output --partial "Description: Treasury"
+    assert_output --partial "canMultisigApprove"
+    assert_output --regexp "Parent: +m[0-9a-z]+"
+    assert_output --regexp "Subresource: [0-9]+"
+    assert_output --partial "  multisig"
+    assert_output --partial "Threshold:             2"
+    assert_output --partial "Execute automatically: "
+
+    call_ledger --pem=1 --port=8000 send "$account_id" 1000 MFX
+    call_ledger --pem=1 --port=8000 multisig submit "$account_id" send "$(identity 3)" 100 MFX
+    tx_id=$(echo "$output" | grep -oE "[0-9a-f]+$")
+    call_ledger --pem=2 --port=8000 multisig approve "$tx_id"
+    call_ledger --pem=1 --port=8000 multisig execute "$tx_id"
+    check_consistency --pem=3 --balance=100 --id="$(identity 3)" 8000
+}
+
+@test "$SUITE: can manage the roles of an account with the CLI" {
+    local account_id
+
+    call_ledger --pem=1 --port=8000 account create --feature multisig
+    account_id=$(echo "$output" | grep -oE "^m[0-9a-z]+$")
+
+    call_ledger --pem=1 --port=8000 account add-roles "$account_id" "$(identity 2)":canMultisigApprove "$(identity 3)":owner
+    call_ledger --pem=1 --port=8000 account roles "$account_id"
+    assert_output --partial "$(identity 2)  canMultisigApprove"
+    assert_output --partial "$(identity 3)  owner"
+
+    call_ledger --pem=1 --port=8000 account remove-roles "$account_id" "$(identity 2)":canMultisigApprove "$(identity 1)":owner
+    call_ledger --pem=1 --port=8000 account roles "$account_id"
+    refute_output --partial "canMultisigApprove"
+    refute_output --partial "$(identity 1)  owner"
+
+    # Removing the last owner is cancelled unless confirmed.
+    call_ledger --pem=3 --port=8000 account remove-roles "$account_id" "$(identity 3)":owner < /dev/null
+    assert_output --partial "Cancelled, no role was removed."
+    call_ledger --pem=3 --port=8000 account roles "$account_id"
+    assert_output --partial "$(identity 3)  owner"
+}
+
+@test "$SUITE: can follow the approvals of a file of transactions" {
+    local account_id
+    local tokens_file="$BATS_TEST_ROOTDIR/tokens.txt"
+
+    account_id=$(account_create --pem=1 '{ 1: { "'"$(identity 2)"'": ["canMultisigApprove"] }, 2: [[1, { 0: 2 }]] }')
+    call_ledger --pem=1 --port=8000 send "$account_id" 1000000 MFX
+
+    for amount in 100 200; do
+        call_ledger --pem=1 --port=8000 multisig submit "$account_id" send "$(identity 3)" "$amount" MFX
+        echo "$output" | grep -oE "[0-9a-f]+$" >> "$tokens_file"
+    done
+    echo "# Not a transaction" >> "$tokens_file"
+    echo "ffff" >> "$tokens_file"
+
+    call_ledger --pem=1 --port=8000 multisig status --tokens-file "$tokens_file" --fail-if-pending
+    assert [ "$status" -ne 0 ]
+    assert_output --partial "1/2"
+    assert_output --partial "pending"
+    assert_output --partial "error: "
+    assert_output --partial "3 transactions: 0 executed, 2 pending, 0 other, 1 failed to query."
+
+    for token in $(grep -vE "^(#|ffff)" "$tokens_file"); do
+        call_ledger --pem=2 --port=8000 multisig approve "$token"
+        call_ledger --pem=1 --port=8000 multisig execute "$token"
+    done
+    sed -i '/^ffff$/d' "$tokens_file"
+
+    call_ledger --pem=1 --port=8000 multisig status --tokens-file "$tokens_file" --fail-if-pending
+    assert [ "$status" -eq 0 ]
+    assert_output --partial "2 transactions: 2 executed, 0 pending, 0 other, 0 failed to query."
+}
@@ -1,5 +1,12 @@
+# Generate (once) and print the path of the PEM file of an identity.
+# Identities named `ed25519-*` are Ed25519 keys, others are ECDSA P-256.
 function pem() {
-    [ -f "$PEM_ROOT/id-$1.pem" ] || ssh-keygen -a 100 -q -P "" -m pkcs8 -t ecdsa -f "$PEM_ROOT/id-$1.pem" >/dev/null
+    if [ ! -f "$PEM_ROOT/id-$1.pem" ]; then
+        case "$1" in
+          ed25519-*) openssl genpkey -algorithm ed25519 -out "$PEM_ROOT/id-$1.pem" >/dev/null ;;
+          *) ssh-keygen -a 100 -q -P "" -m pkcs8 -t ecdsa -f "$PEM_ROOT/id-$1.pem" >/dev/null ;;
+        esac
+    fi
     echo "$PEM_ROOT/id-$1.pem"
 }
 